@@ -0,0 +1,553 @@
+use crate::clock::warn_on_clock_skew;
+use crate::constants::{
+    BATCHES_ENDPOINT, BATCH_ENDPOINT, ENCRYPTION_BATCH_ID_FILE, FILENAMES_FILE, FILE_ENDPOINT,
+    HASH_ALGORITHM_FILE, ROOT_HASH_FILE,
+};
+use crate::diff::{fetch_batch_files, listed_leaves};
+use crate::upload::{compute_root_hash, save_upload_metadata};
+use anyhow::{Context, Result};
+use common::auth_message::{
+    access_message, create_message, delete_file_message, delete_message, list_batches_message,
+    rename_message, root_message,
+};
+use common::utils::get_current_timestamp_ms;
+use common::{
+    file_utils, BatchAccessRequest, BatchRootResponse, CreateBatchRequest, DeleteBatchRequest,
+    DeleteFileRequest, DeleteFileResponse, ListBatchesRequest, ListBatchesResponse,
+    RenameBatchRequest,
+};
+use crypto::{generate_nonce, sign_message};
+use ed25519_dalek::SigningKey;
+use log::info;
+use reqwest::blocking::Client;
+use std::fs;
+use std::path::Path;
+
+/// Print the IDs of this client's batches on the server, sorted
+pub fn list_batches(
+    server: &str,
+    signing_key: &SigningKey,
+    client_id: &str,
+) -> Result<Vec<String>> {
+    let timestamp = get_current_timestamp_ms();
+    let signature = sign_message(signing_key, &list_batches_message(timestamp));
+
+    let url = format!("{}{}", server, BATCHES_ENDPOINT);
+    let response = Client::new()
+        .get(&url)
+        .query(&ListBatchesRequest {
+            signature: hex::encode(signature.to_bytes()),
+            timestamp,
+            client_id: client_id.to_string(),
+        })
+        .send()
+        .context("Failed to connect to server")?;
+
+    let status = response.status();
+    if !status.is_success() {
+        warn_on_clock_skew(&response);
+        let error_text = response
+            .text()
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        anyhow::bail!("Listing batches failed: {} - {}", status, error_text);
+    }
+    let listing: ListBatchesResponse = response.json().context("Failed to parse batch list")?;
+
+    if listing.batches.is_empty() {
+        println!("No batches on server");
+    }
+    for batch_id in &listing.batches {
+        println!("{}", batch_id);
+    }
+    Ok(listing.batches)
+}
+
+/// Print a batch's listing as stored on the server, with its annotations
+/// Annotations are mutable metadata and are not covered by the root hash
+pub fn show_batch(
+    server: &str,
+    batch_id: &str,
+    signing_key: &SigningKey,
+    client_id: &str,
+) -> Result<()> {
+    let listing = fetch_batch_files(server, batch_id, signing_key, client_id)?;
+
+    println!("Batch: {}", listing.batch_id);
+    println!("Root hash: {}", listing.root_hash);
+    println!(
+        "Visibility: {}",
+        if listing.public { "public" } else { "private" }
+    );
+    println!("Files ({}):", listing.files.len());
+    for file in &listing.files {
+        println!("  {} {}", file.leaf_hash, file.filename);
+    }
+    if listing.annotations.is_empty() {
+        println!("Annotations: none");
+    } else {
+        println!("Annotations (not covered by the root hash):");
+        for (key, value) in &listing.annotations {
+            println!("  {} = {}", key, value);
+        }
+    }
+    Ok(())
+}
+
+/// Create an empty batch on the server, reserving its ID for later uploads
+pub fn create_batch(server: &str, batch_id: &str, signing_key: &SigningKey) -> Result<()> {
+    file_utils::validate_filename(batch_id)
+        .map_err(|e| anyhow::anyhow!("Invalid batch ID {}: {}", batch_id, e.message()))?;
+
+    let timestamp = get_current_timestamp_ms();
+    let message = create_message(batch_id, timestamp);
+    let signature = sign_message(signing_key, &message);
+
+    let url = format!("{}{}", server, BATCH_ENDPOINT);
+    let response = Client::new()
+        .post(&url)
+        .json(&CreateBatchRequest {
+            batch_id: batch_id.to_string(),
+            signature: hex::encode(signature.to_bytes()),
+            timestamp,
+            public_key: hex::encode(signing_key.verifying_key().to_bytes()),
+        })
+        .send()
+        .context("Failed to connect to server")?;
+
+    let status = response.status();
+    if !status.is_success() {
+        warn_on_clock_skew(&response);
+        let error_text = response
+            .text()
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        anyhow::bail!("Creating batch failed: {} - {}", status, error_text);
+    }
+
+    info!("Created empty batch {} on server", batch_id);
+    println!("✓ Empty batch {} created", batch_id);
+    Ok(())
+}
+
+/// Rename a batch on the server and move the local batch directory along with it
+/// The root hash is unchanged by a rename, so nothing else is rewritten
+pub fn rename_batch(
+    server: &str,
+    old_batch_id: &str,
+    new_batch_id: &str,
+    signing_key: &SigningKey,
+    client_id: &str,
+    data_dir: &Path,
+) -> Result<()> {
+    file_utils::validate_filename(new_batch_id)
+        .map_err(|e| anyhow::anyhow!("Invalid batch ID {}: {}", new_batch_id, e.message()))?;
+
+    let new_dir = data_dir.join(new_batch_id);
+    anyhow::ensure!(
+        !new_dir.exists(),
+        "Local batch directory already exists: {:?}",
+        new_dir
+    );
+
+    let timestamp = get_current_timestamp_ms();
+    let nonce = generate_nonce();
+    let message = rename_message(old_batch_id, new_batch_id, timestamp, &nonce);
+    let signature = sign_message(signing_key, &message);
+
+    let url = format!("{}{}/{}/rename", server, BATCH_ENDPOINT, old_batch_id);
+    let response = Client::new()
+        .post(&url)
+        .json(&RenameBatchRequest {
+            new_batch_id: new_batch_id.to_string(),
+            signature: hex::encode(signature.to_bytes()),
+            timestamp,
+            nonce: hex::encode(nonce),
+            client_id: client_id.to_string(),
+        })
+        .send()
+        .context("Failed to connect to server")?;
+
+    let status = response.status();
+    if !status.is_success() {
+        warn_on_clock_skew(&response);
+        let error_text = response
+            .text()
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        anyhow::bail!("Rename failed: {} - {}", status, error_text);
+    }
+
+    info!(
+        "Renamed batch {} to {} on server",
+        old_batch_id, new_batch_id
+    );
+
+    let old_dir = data_dir.join(old_batch_id);
+    if old_dir.exists() {
+        // Files were encrypted under the original batch ID; remember it so downloads
+        // from the renamed batch can still decrypt. The old name may then take new
+        // uploads under the same ID, which is safe only because nonces also cover the
+        // content: new content never reuses a nonce of the renamed batch's files.
+        let encryption_batch_id_file = old_dir.join(ENCRYPTION_BATCH_ID_FILE);
+        if !encryption_batch_id_file.exists() {
+            fs::write(&encryption_batch_id_file, old_batch_id)
+                .with_context(|| format!("Failed to write {}", ENCRYPTION_BATCH_ID_FILE))?;
+        }
+        fs::rename(&old_dir, &new_dir).context("Failed to rename local batch directory")?;
+    }
+
+    println!("✓ Batch {} renamed to {}", old_batch_id, new_batch_id);
+    Ok(())
+}
+
+/// Delete a batch and all its files from the server
+/// The local batch directory (root hash, downloads) is left for the user to remove
+pub fn delete_batch(
+    server: &str,
+    batch_id: &str,
+    signing_key: &SigningKey,
+    client_id: &str,
+) -> Result<()> {
+    let timestamp = get_current_timestamp_ms();
+    let nonce = generate_nonce();
+    let message = delete_message(batch_id, timestamp, &nonce);
+    let signature = sign_message(signing_key, &message);
+
+    let url = format!("{}{}/{}", server, BATCH_ENDPOINT, batch_id);
+    let response = Client::new()
+        .delete(&url)
+        .query(&DeleteBatchRequest {
+            signature: hex::encode(signature.to_bytes()),
+            timestamp,
+            nonce: hex::encode(nonce),
+            client_id: client_id.to_string(),
+        })
+        .send()
+        .context("Failed to connect to server")?;
+
+    let status = response.status();
+    if !status.is_success() {
+        warn_on_clock_skew(&response);
+        let error_text = response
+            .text()
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        anyhow::bail!("Deleting batch failed: {} - {}", status, error_text);
+    }
+
+    info!("Deleted batch {} on server", batch_id);
+    println!("✓ Batch {} deleted", batch_id);
+    Ok(())
+}
+
+/// Delete one file from a batch on the server and store the batch's new root locally
+/// The new root is computed from the server's listing of the remaining files before the
+/// request, and the root the server answers with must match it
+pub fn delete_file(
+    server: &str,
+    batch_id: &str,
+    filename: &str,
+    signing_key: &SigningKey,
+    client_id: &str,
+    hash_truncation_bytes: Option<usize>,
+    data_dir: &Path,
+) -> Result<String> {
+    let listing = fetch_batch_files(server, batch_id, signing_key, client_id)?;
+    if !listing.files.iter().any(|entry| entry.filename == filename) {
+        anyhow::bail!("File {} not found in batch {}", filename, batch_id);
+    }
+    let remaining: Vec<_> = listed_leaves(&listing.files)?
+        .into_iter()
+        .filter(|(name, _)| name != filename)
+        .collect();
+    let expected_root = if remaining.is_empty() {
+        String::new()
+    } else {
+        compute_root_hash(&remaining, hash_truncation_bytes)?
+    };
+
+    let timestamp = get_current_timestamp_ms();
+    let nonce = generate_nonce();
+    let message = delete_file_message(filename, batch_id, timestamp, &nonce);
+    let signature = sign_message(signing_key, &message);
+
+    let url = format!("{}{}", server, FILE_ENDPOINT);
+    let response = Client::new()
+        .delete(&url)
+        .query(&DeleteFileRequest {
+            filename: filename.to_string(),
+            batch_id: batch_id.to_string(),
+            signature: hex::encode(signature.to_bytes()),
+            timestamp,
+            nonce: hex::encode(nonce),
+            client_id: client_id.to_string(),
+        })
+        .send()
+        .context("Failed to connect to server")?;
+
+    let status = response.status();
+    if !status.is_success() {
+        warn_on_clock_skew(&response);
+        let error_text = response
+            .text()
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        anyhow::bail!("Deleting file failed: {} - {}", status, error_text);
+    }
+    let deleted: DeleteFileResponse = response.json().context("Failed to parse delete response")?;
+
+    // Another writer may have changed the batch between the listing and the deletion
+    if deleted.root_hash != expected_root {
+        anyhow::bail!(
+            "Server root {} does not match the root {} of the remaining files; \
+            the batch changed concurrently, keeping the local root",
+            deleted.root_hash,
+            expected_root
+        );
+    }
+
+    if remaining.is_empty() {
+        // An empty batch has no root to verify downloads against
+        let batch_dir = data_dir.join(batch_id);
+        for name in [ROOT_HASH_FILE, FILENAMES_FILE] {
+            match fs::remove_file(batch_dir.join(name)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| format!("Failed to remove {}", name));
+                }
+                _ => {}
+            }
+        }
+    } else {
+        save_upload_metadata(data_dir, batch_id, &deleted.root_hash, &remaining)?;
+    }
+
+    info!(
+        "Deleted {} from batch {}, {} files left",
+        filename, batch_id, deleted.num_files
+    );
+    println!("✓ File {} deleted from batch {}", filename, batch_id);
+    if deleted.root_hash.is_empty() {
+        println!("  Batch {} has no files left", batch_id);
+    } else {
+        println!("  New root hash: {}", deleted.root_hash);
+    }
+    Ok(deleted.root_hash)
+}
+
+/// Grant (or revoke) another client's read access to one of our batches
+pub fn update_access(
+    server: &str,
+    batch_id: &str,
+    grantee_id: &str,
+    granted: bool,
+    signing_key: &SigningKey,
+    client_id: &str,
+) -> Result<()> {
+    let action = if granted { "grant" } else { "revoke" };
+
+    let timestamp = get_current_timestamp_ms();
+    let nonce = generate_nonce();
+    let message = access_message(action, batch_id, grantee_id, timestamp, &nonce);
+    let signature = sign_message(signing_key, &message);
+
+    let url = format!("{}{}/{}/{}", server, BATCH_ENDPOINT, batch_id, action);
+    let response = Client::new()
+        .post(&url)
+        .json(&BatchAccessRequest {
+            grantee_id: grantee_id.to_string(),
+            signature: hex::encode(signature.to_bytes()),
+            timestamp,
+            nonce: hex::encode(nonce),
+            client_id: client_id.to_string(),
+        })
+        .send()
+        .context("Failed to connect to server")?;
+
+    let status = response.status();
+    if !status.is_success() {
+        warn_on_clock_skew(&response);
+        let error_text = response
+            .text()
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        anyhow::bail!("Access {} failed: {} - {}", action, status, error_text);
+    }
+
+    if granted {
+        println!("✓ Client {} can now read batch {}", grantee_id, batch_id);
+    } else {
+        println!(
+            "✓ Client {} can no longer read batch {}",
+            grantee_id, batch_id
+        );
+    }
+    Ok(())
+}
+
+/// Where to fetch a batch root from and how to authenticate
+pub struct FetchRootConfig<'a> {
+    /// Server URL
+    pub server: &'a str,
+    /// Batch ID
+    pub batch_id: &'a str,
+    /// Client ID of the batch owner
+    pub owner_id: &'a str,
+    /// This client's ID; differs from `owner_id` for batches shared with us
+    pub client_id: &'a str,
+    /// Signing key for authentication
+    pub signing_key: &'a SigningKey,
+    /// Read a public batch anonymously
+    pub public: bool,
+    /// Overwrite a local root hash that differs from the server's
+    pub force: bool,
+}
+
+/// Fetch a batch's Merkle root from the server and save it as the batch's local root hash
+/// so later downloads verify against it without --root-hash
+pub fn fetch_root(config: &FetchRootConfig, data_dir: &Path) -> Result<()> {
+    file_utils::validate_filename(config.batch_id)
+        .map_err(|e| anyhow::anyhow!("Invalid batch ID {}: {}", config.batch_id, e.message()))?;
+
+    let mut query = vec![("client_id", config.owner_id.to_string())];
+    // Anonymous reads of public batches are not signed
+    if !config.public {
+        let shared = config.client_id != config.owner_id;
+        let timestamp = get_current_timestamp_ms();
+        let message = root_message(
+            config.batch_id,
+            timestamp,
+            shared.then_some(config.owner_id),
+        );
+        let signature = sign_message(config.signing_key, &message);
+        query.push(("signature", hex::encode(signature.to_bytes())));
+        query.push(("timestamp", timestamp.to_string()));
+        if shared {
+            // Lets the server register us if we never uploaded anything
+            query.push(("requester_id", config.client_id.to_string()));
+            query.push((
+                "requester_public_key",
+                hex::encode(config.signing_key.verifying_key().as_bytes()),
+            ));
+        }
+    }
+
+    let url = format!(
+        "{}{}/{}/root",
+        config.server, BATCH_ENDPOINT, config.batch_id
+    );
+    let response = Client::new()
+        .get(&url)
+        .query(&query)
+        .send()
+        .context("Failed to connect to server")?;
+
+    let status = response.status();
+    if !status.is_success() {
+        warn_on_clock_skew(&response);
+        let error_text = response
+            .text()
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        anyhow::bail!("Fetching root failed: {} - {}", status, error_text);
+    }
+    let root: BatchRootResponse = response.json()?;
+
+    let batch_dir = data_dir.join(config.batch_id);
+    let root_hash_file = batch_dir.join(ROOT_HASH_FILE);
+    if root_hash_file.exists() {
+        let local_root = fs::read_to_string(&root_hash_file)
+            .with_context(|| format!("Failed to read {}", ROOT_HASH_FILE))?;
+        let local_root = local_root.trim();
+        if local_root == root.root_hash {
+            println!("✓ Server root matches the local root hash: {}", local_root);
+            return Ok(());
+        }
+        anyhow::ensure!(
+            config.force,
+            "Server root {} differs from local root hash {}; \
+            the batch changed or the server is misbehaving (use --force to overwrite)",
+            root.root_hash,
+            local_root
+        );
+    }
+
+    fs::create_dir_all(&batch_dir).context("Failed to create batch directory")?;
+    fs::write(&root_hash_file, &root.root_hash)
+        .with_context(|| format!("Failed to write {}", ROOT_HASH_FILE))?;
+    fs::write(batch_dir.join(HASH_ALGORITHM_FILE), &root.hash_algorithm)
+        .with_context(|| format!("Failed to write {}", HASH_ALGORITHM_FILE))?;
+
+    println!(
+        "✓ Saved root hash for batch {} ({} files): {}",
+        config.batch_id, root.num_files, root.root_hash
+    );
+    println!(
+        "⚠ This root comes from the server: downloads verified against it only prove \
+        consistency with what the server claims. Cross-check it with the batch owner \
+        or another out-of-band source before trusting it."
+    );
+    Ok(())
+}
+
+/// Restore one of this client's batches' local filenames.json and root_hash.txt from the
+/// server's file listing, for when they were lost
+/// The listed root must match the listed leaf hashes, but it is still only the server's claim
+pub fn fetch_metadata(
+    server: &str,
+    batch_id: &str,
+    signing_key: &SigningKey,
+    client_id: &str,
+    hash_truncation_bytes: Option<usize>,
+    force: bool,
+    data_dir: &Path,
+) -> Result<()> {
+    let listing = fetch_batch_files(server, batch_id, signing_key, client_id)?;
+    if listing.files.is_empty() {
+        anyhow::bail!("Batch {} has no files, so it has no root to save", batch_id);
+    }
+    let leaves = listed_leaves(&listing.files)?;
+    let root_hash = compute_root_hash(&leaves, hash_truncation_bytes)?;
+    anyhow::ensure!(
+        root_hash == listing.root_hash,
+        "Listed root {} does not match the listed files (root {}); \
+        check --hash-truncation-bytes",
+        listing.root_hash,
+        root_hash
+    );
+
+    let root_hash_file = data_dir.join(batch_id).join(ROOT_HASH_FILE);
+    if root_hash_file.exists() {
+        let local_root = fs::read_to_string(&root_hash_file)
+            .with_context(|| format!("Failed to read {}", ROOT_HASH_FILE))?;
+        anyhow::ensure!(
+            force || local_root.trim() == root_hash,
+            "Server root {} differs from local root hash {}; \
+            the batch changed or the server is misbehaving (use --force to overwrite)",
+            root_hash,
+            local_root.trim()
+        );
+    }
+
+    save_upload_metadata(data_dir, batch_id, &root_hash, &leaves)?;
+
+    println!(
+        "✓ Saved {} filenames and root hash for batch {}: {}",
+        leaves.len(),
+        batch_id,
+        root_hash
+    );
+    println!(
+        "⚠ This root comes from the server: downloads verified against it only prove \
+        consistency with what the server claims. Cross-check it with a root you recorded \
+        elsewhere before trusting it."
+    );
+    Ok(())
+}
+
+/// Load the batch ID the files of a batch were encrypted under
+/// This differs from the current batch ID only after a rename
+pub fn load_encryption_batch_id(batch_id: &str, data_dir: &Path) -> Result<String> {
+    let encryption_batch_id_file = data_dir.join(batch_id).join(ENCRYPTION_BATCH_ID_FILE);
+    if !encryption_batch_id_file.exists() {
+        return Ok(batch_id.to_string());
+    }
+    let encryption_batch_id = fs::read_to_string(&encryption_batch_id_file)
+        .with_context(|| format!("Failed to read {}", ENCRYPTION_BATCH_ID_FILE))?
+        .trim()
+        .to_string();
+    Ok(encryption_batch_id)
+}
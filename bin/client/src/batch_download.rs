@@ -0,0 +1,225 @@
+use crate::constants::{DOWNLOADED_DIR, DOWNLOAD_STATE_FILE};
+use crate::diff::fetch_batch_files;
+use crate::download::{load_root_hash, DownloadConfig, FileDownloader};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Progress of a batch download, saved as `client_data/{batch_id}/download_state.json`
+/// so an interrupted `download-batch` resumes instead of starting over
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct DownloadState {
+    /// Root every completed file was verified against; a different root starts over
+    pub root_hash: String,
+    /// Directory the files are written to; a different directory starts over
+    pub output_dir: PathBuf,
+    /// Files downloaded and verified so far
+    pub completed: BTreeSet<String>,
+}
+
+impl DownloadState {
+    /// Load the saved progress if it is for the same root and output directory
+    fn load(path: &Path, root_hash: &str, output_dir: &Path) -> Result<Self> {
+        let fresh = Self {
+            root_hash: root_hash.to_string(),
+            output_dir: output_dir.to_path_buf(),
+            completed: BTreeSet::new(),
+        };
+        if !path.exists() {
+            return Ok(fresh);
+        }
+        let json = fs::read_to_string(path).context("Failed to read download state")?;
+        let saved: Self = serde_json::from_str(&json).context("Failed to parse download state")?;
+        if saved.root_hash != root_hash {
+            println!(
+                "Root changed since the interrupted download ({}); starting over",
+                saved.root_hash
+            );
+            return Ok(fresh);
+        }
+        if saved.output_dir != output_dir {
+            return Ok(fresh);
+        }
+        Ok(saved)
+    }
+
+    /// Write the progress atomically, so an interruption never leaves a torn state file
+    fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).context("Failed to serialize state")?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, json).context("Failed to write download state")?;
+        fs::rename(&tmp, path).context("Failed to write download state")
+    }
+}
+
+/// Download and verify every file of one of our batches into `output_dir`
+/// (default: client_data/{batch_id}/downloaded/), resuming an interrupted run
+/// Whether the batch is public is taken from its listing, not from `config`
+pub fn download_batch(
+    config: &DownloadConfig,
+    root_hash: Option<&str>,
+    output_dir: Option<&PathBuf>,
+) -> Result<()> {
+    let DownloadConfig {
+        server,
+        batch_id,
+        signing_key,
+        client_id,
+        data_dir,
+        ..
+    } = config;
+    let root_hash = match root_hash {
+        Some(root_hash) => root_hash.to_string(),
+        None => load_root_hash(batch_id, data_dir)?,
+    };
+
+    let listing = fetch_batch_files(server, batch_id, signing_key, client_id)?;
+    anyhow::ensure!(
+        listing.root_hash == root_hash,
+        "Server root {} differs from trusted root {}",
+        listing.root_hash,
+        root_hash
+    );
+    let filenames: Vec<String> = listing.files.into_iter().map(|f| f.filename).collect();
+
+    let output_path = output_dir
+        .cloned()
+        .unwrap_or_else(|| data_dir.join(batch_id).join(DOWNLOADED_DIR));
+    let downloader = FileDownloader::new(
+        server.clone(),
+        batch_id.clone(),
+        signing_key.clone(),
+        client_id.clone(),
+        config.requester_id.clone(),
+        data_dir.clone(),
+        listing.public,
+    )
+    .with_file_mode(config.file_mode)
+    .with_overwrite(config.overwrite)
+    .with_hash_truncation_bytes(config.hash_truncation_bytes)
+    .with_max_download_size(config.max_download_size);
+    let state_path = data_dir.join(batch_id).join(DOWNLOAD_STATE_FILE);
+    let downloaded = download_remaining(
+        &state_path,
+        &root_hash,
+        &filenames,
+        &output_path,
+        |filename| downloader.download_and_verify(filename, Some(&root_hash), output_dir),
+    )?;
+
+    println!(
+        "\n✓ Downloaded and verified {} files of batch {} ({} already done)",
+        filenames.len(),
+        batch_id,
+        filenames.len() - downloaded
+    );
+    Ok(())
+}
+
+/// Fetch the files not yet completed, recording each one once it is verified
+/// A completed file whose local copy is gone is fetched again. The state file is
+/// removed once every file is done. Returns the number of files fetched.
+fn download_remaining(
+    state_path: &Path,
+    root_hash: &str,
+    filenames: &[String],
+    output_dir: &Path,
+    mut fetch: impl FnMut(&str) -> Result<()>,
+) -> Result<usize> {
+    if let Some(parent) = state_path.parent() {
+        fs::create_dir_all(parent).context("Failed to create batch directory")?;
+    }
+    let mut state = DownloadState::load(state_path, root_hash, output_dir)?;
+    let mut fetched = 0;
+    for filename in filenames {
+        if state.completed.contains(filename) && output_dir.join(filename).exists() {
+            println!("Skipping already verified file: {}", filename);
+            continue;
+        }
+        fetch(filename).with_context(|| {
+            format!(
+                "Download of {} failed; rerun download-batch to resume",
+                filename
+            )
+        })?;
+        state.completed.insert(filename.clone());
+        state.save(state_path)?;
+        fetched += 1;
+    }
+
+    if state_path.exists() {
+        fs::remove_file(state_path).context("Failed to remove download state")?;
+    }
+    Ok(fetched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stand-in for a verified download that fails (is interrupted) at `fail_on`
+    fn fetch(
+        output_dir: &Path,
+        fetched: &mut Vec<String>,
+        filename: &str,
+        fail_on: Option<&str>,
+    ) -> Result<()> {
+        anyhow::ensure!(Some(filename) != fail_on, "connection reset");
+        fs::write(output_dir.join(filename), filename)?;
+        fetched.push(filename.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_interrupted_download_resumes_without_refetching() {
+        let dir = std::env::temp_dir().join(format!("vs-resume-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        let output_dir = dir.join("downloaded");
+        fs::create_dir_all(&output_dir).unwrap();
+        let state_path = dir.join(DOWNLOAD_STATE_FILE);
+        let filenames: Vec<String> = ["a.txt", "b.txt", "c.txt", "d.txt"]
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        let root = "aa".repeat(32);
+        let mut fetched = Vec::new();
+
+        // Interrupted at c.txt: a.txt and b.txt are recorded as verified
+        let result = download_remaining(&state_path, &root, &filenames, &output_dir, |f| {
+            fetch(&output_dir, &mut fetched, f, Some("c.txt"))
+        });
+        assert!(result.is_err());
+        let state: DownloadState =
+            serde_json::from_str(&fs::read_to_string(&state_path).unwrap()).unwrap();
+        assert_eq!(state.root_hash, root);
+        assert_eq!(
+            state.completed,
+            BTreeSet::from(["a.txt".to_string(), "b.txt".to_string()])
+        );
+
+        // Resuming fetches only the rest and clears the state
+        let count = download_remaining(&state_path, &root, &filenames, &output_dir, |f| {
+            fetch(&output_dir, &mut fetched, f, None)
+        })
+        .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(fetched, ["a.txt", "b.txt", "c.txt", "d.txt"]);
+        assert!(!state_path.exists());
+
+        // A resume against a different root starts over
+        let result = download_remaining(&state_path, &root, &filenames, &output_dir, |f| {
+            fetch(&output_dir, &mut fetched, f, Some("b.txt"))
+        });
+        assert!(result.is_err());
+        let other_root = "bb".repeat(32);
+        let count = download_remaining(&state_path, &other_root, &filenames, &output_dir, |f| {
+            fetch(&output_dir, &mut fetched, f, None)
+        })
+        .unwrap();
+        assert_eq!(count, 4);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,317 @@
+use crate::constants::{BUNDLE_FILES_DIR, BUNDLE_INDEX_FILE};
+use crate::diff::fetch_batch_files;
+use crate::download::{load_hash_algorithm, load_root_hash, proof_nodes_from_json, FileDownloader};
+use anyhow::{Context, Result};
+use common::auth_message::bundle_root_message;
+use common::{file_utils, ProofNodeJson};
+use crypto::{compute_client_id, hash_leaf, public_key_from_bytes, sign_message, verify_signature};
+use ed25519_dalek::{Signature, SigningKey};
+use merkle_tree::{MerkleProof, MerkleTree};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Version of the bundle layout written by `export-bundle`
+pub const BUNDLE_FORMAT_VERSION: u32 = 1;
+
+/// Index of a verifiable bundle, stored as `bundle.json` next to the `files/` directory
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BundleIndex {
+    pub version: u32,
+    pub batch_id: String,
+    /// Owner of the batch; must be derived from `public_key`
+    pub client_id: String,
+    pub public_key: String, // hex-encoded Ed25519 public key
+    pub root_hash: String,  // hex-encoded Merkle root
+    /// Owner's signature over `"bundle" || batch_id || root`
+    pub root_signature: String,
+    pub hash_algorithm: String,
+    /// Public batches are stored unencrypted; private bundles hold ciphertext
+    pub public: bool,
+    /// Files in tree order
+    pub files: Vec<BundleFile>,
+}
+
+/// One file of a bundle and its proof against the bundle's root
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BundleFile {
+    pub filename: String,
+    pub leaf_hash: String, // hex-encoded
+    pub merkle_proof: Vec<ProofNodeJson>,
+}
+
+/// Decode a hex-encoded 32-byte root hash
+fn decode_root(root_hash: &str) -> Result<[u8; 32]> {
+    let mut root = [0u8; 32];
+    hex::decode_to_slice(root_hash.trim(), &mut root).context("Invalid root hash")?;
+    Ok(root)
+}
+
+/// Download every file of one of our batches, verify each against the trusted root
+/// and write the files, their proofs and a signed root to the bundle directory `out`
+pub fn export_bundle(
+    server: &str,
+    batch_id: &str,
+    signing_key: &SigningKey,
+    client_id: &str,
+    data_dir: &Path,
+    root_hash: Option<&str>,
+    out: &Path,
+) -> Result<()> {
+    let root_hash = match root_hash {
+        Some(root_hash) => root_hash.to_string(),
+        None => load_root_hash(batch_id, data_dir)?,
+    };
+
+    let listing = fetch_batch_files(server, batch_id, signing_key, client_id)?;
+    anyhow::ensure!(
+        listing.root_hash == root_hash,
+        "Server root {} differs from trusted root {}",
+        listing.root_hash,
+        root_hash
+    );
+
+    let downloader = FileDownloader::new(
+        server.to_string(),
+        batch_id.to_string(),
+        signing_key.clone(),
+        client_id.to_string(),
+        client_id.to_string(),
+        data_dir.to_path_buf(),
+        false,
+    );
+    let mut files = Vec::with_capacity(listing.files.len());
+    let mut contents = Vec::with_capacity(listing.files.len());
+    for entry in &listing.files {
+        let (result, content) = downloader.fetch_verified(&entry.filename, &root_hash)?;
+        files.push(BundleFile {
+            filename: entry.filename.clone(),
+            leaf_hash: hex::encode(hash_leaf(&content)),
+            merkle_proof: result.merkle_proof,
+        });
+        contents.push(content);
+    }
+
+    let root = decode_root(&root_hash)?;
+    let signature = sign_message(signing_key, &bundle_root_message(batch_id, &root));
+    let index = BundleIndex {
+        version: BUNDLE_FORMAT_VERSION,
+        batch_id: batch_id.to_string(),
+        client_id: client_id.to_string(),
+        public_key: hex::encode(signing_key.verifying_key().as_bytes()),
+        root_hash,
+        root_signature: hex::encode(signature.to_bytes()),
+        hash_algorithm: load_hash_algorithm(batch_id, data_dir)?,
+        public: listing.public,
+        files,
+    };
+    write_bundle(out, &index, &contents)?;
+
+    println!(
+        "✓ Exported {} verified files of batch {} to {:?}",
+        index.files.len(),
+        batch_id,
+        out
+    );
+    Ok(())
+}
+
+/// Write a bundle directory; `contents` are in the same order as `index.files`
+pub fn write_bundle(out: &Path, index: &BundleIndex, contents: &[Vec<u8>]) -> Result<()> {
+    if out.exists() {
+        anyhow::bail!("Bundle destination already exists: {:?}", out);
+    }
+    let files_dir = out.join(BUNDLE_FILES_DIR);
+    fs::create_dir_all(&files_dir).context("Failed to create bundle directory")?;
+
+    for (file, content) in index.files.iter().zip(contents) {
+        file_utils::validate_filename(&file.filename)
+            .map_err(|e| anyhow::anyhow!("{}: {}", e.message(), file.filename))?;
+        fs::write(files_dir.join(&file.filename), content)
+            .with_context(|| format!("Failed to write bundle file {}", file.filename))?;
+    }
+
+    let json = serde_json::to_string_pretty(index).context("Failed to serialize bundle index")?;
+    fs::write(out.join(BUNDLE_INDEX_FILE), json).context("Failed to write bundle index")?;
+    Ok(())
+}
+
+/// Verify a bundle offline: the root is signed by the batch owner, every file matches
+/// its leaf hash and proof, and the files are exactly the batch committed to by the root
+/// `expected_root` additionally pins the root to one obtained out of band
+pub fn verify_bundle(bundle: &Path, expected_root: Option<&str>) -> Result<BundleIndex> {
+    let json = fs::read_to_string(bundle.join(BUNDLE_INDEX_FILE))
+        .with_context(|| format!("Failed to read {}", BUNDLE_INDEX_FILE))?;
+    let index: BundleIndex = serde_json::from_str(&json).context("Failed to parse bundle index")?;
+
+    anyhow::ensure!(
+        index.version == BUNDLE_FORMAT_VERSION,
+        "Unsupported bundle version {}",
+        index.version
+    );
+    anyhow::ensure!(
+        index.hash_algorithm == merkle_tree::HASH_ALGORITHM,
+        "Bundle was built with hash algorithm {}, this client uses {}",
+        index.hash_algorithm,
+        merkle_tree::HASH_ALGORITHM
+    );
+
+    // The root must be signed by the key the owner's client ID is derived from
+    let public_key_bytes = hex::decode(&index.public_key).context("Invalid public key")?;
+    let public_key = public_key_from_bytes(&public_key_bytes)?;
+    anyhow::ensure!(
+        compute_client_id(&public_key) == index.client_id,
+        "Bundle public key does not belong to client {}",
+        index.client_id
+    );
+    let root = decode_root(&index.root_hash)?;
+    if let Some(expected_root) = expected_root {
+        anyhow::ensure!(
+            root == decode_root(expected_root)?,
+            "Bundle root {} differs from expected root {}",
+            index.root_hash,
+            expected_root
+        );
+    }
+    let signature_bytes: [u8; 64] = hex::decode(&index.root_signature)
+        .context("Invalid root signature")?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Invalid root signature length"))?;
+    verify_signature(
+        &public_key,
+        &bundle_root_message(&index.batch_id, &root),
+        &Signature::from_bytes(&signature_bytes),
+    )
+    .context("Bundle root signature is invalid")?;
+
+    let files_dir = bundle.join(BUNDLE_FILES_DIR);
+    let mut leaves = Vec::with_capacity(index.files.len());
+    for (leaf_index, file) in index.files.iter().enumerate() {
+        file_utils::validate_filename(&file.filename)
+            .map_err(|e| anyhow::anyhow!("{}: {}", e.message(), file.filename))?;
+        let content = fs::read(files_dir.join(&file.filename))
+            .with_context(|| format!("Bundle file {} is missing", file.filename))?;
+
+        let leaf_hash = hash_leaf(&content);
+        anyhow::ensure!(
+            hex::encode(leaf_hash) == file.leaf_hash,
+            "Bundle file {} does not match its recorded leaf hash",
+            file.filename
+        );
+        let proof = MerkleProof {
+            leaf_index,
+            leaf_hash,
+            path: proof_nodes_from_json(&file.merkle_proof, None)?,
+        };
+        anyhow::ensure!(
+            proof.compute_root()? == root,
+            "Proof for bundle file {} does not lead to the root",
+            file.filename
+        );
+        leaves.push(leaf_hash);
+    }
+
+    // Per-file proofs cannot tell whether files were dropped from the index
+    anyhow::ensure!(
+        MerkleTree::verify_batch_membership(&leaves, &root),
+        "Bundle files are not the complete batch committed to by the root"
+    );
+    let stored = fs::read_dir(&files_dir)
+        .context("Failed to read bundle files")?
+        .count();
+    anyhow::ensure!(
+        stored == index.files.len(),
+        "Bundle holds {} files but its index lists {}",
+        stored,
+        index.files.len()
+    );
+
+    println!(
+        "✓ Bundle verified: {} files of batch {} commit to root {} signed by {}",
+        index.files.len(),
+        index.batch_id,
+        index.root_hash,
+        index.client_id
+    );
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::generate_keypair;
+
+    /// Write a bundle for `contents` the way `export-bundle` would, without a server
+    fn bundle_for(out: &Path, contents: &[(&str, &[u8])]) {
+        let (signing_key, verifying_key) = generate_keypair();
+        let leaves: Vec<[u8; 32]> = contents
+            .iter()
+            .map(|(_, content)| hash_leaf(content))
+            .collect();
+        let tree = MerkleTree::from_leaf_hashes(&leaves).unwrap();
+        let root = tree.root_hash();
+
+        let files = contents
+            .iter()
+            .enumerate()
+            .map(|(i, (filename, _))| BundleFile {
+                filename: filename.to_string(),
+                leaf_hash: hex::encode(leaves[i]),
+                merkle_proof: tree
+                    .generate_proof(i)
+                    .unwrap()
+                    .path
+                    .iter()
+                    .map(|node| ProofNodeJson {
+                        hash: hex::encode(node.hash),
+                        is_left: node.is_left,
+                    })
+                    .collect(),
+            })
+            .collect();
+        let signature = sign_message(&signing_key, &bundle_root_message("batch", &root));
+        let index = BundleIndex {
+            version: BUNDLE_FORMAT_VERSION,
+            batch_id: "batch".to_string(),
+            client_id: compute_client_id(&verifying_key),
+            public_key: hex::encode(verifying_key.as_bytes()),
+            root_hash: hex::encode(root),
+            root_signature: hex::encode(signature.to_bytes()),
+            hash_algorithm: merkle_tree::HASH_ALGORITHM.to_string(),
+            public: true,
+            files,
+        };
+        let contents: Vec<Vec<u8>> = contents.iter().map(|(_, c)| c.to_vec()).collect();
+        write_bundle(out, &index, &contents).unwrap();
+    }
+
+    #[test]
+    fn test_tampered_bundle_fails_verification() {
+        let out = std::env::temp_dir().join(format!("vs-bundle-{}", std::process::id()));
+        fs::remove_dir_all(&out).ok();
+        bundle_for(&out, &[("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")]);
+
+        let index = verify_bundle(&out, None).unwrap();
+        assert_eq!(index.files.len(), 3);
+        assert!(verify_bundle(&out, Some(&"00".repeat(32))).is_err());
+
+        // A modified file no longer matches its leaf
+        fs::write(out.join(BUNDLE_FILES_DIR).join("b.txt"), b"tampered").unwrap();
+        let err = verify_bundle(&out, None).unwrap_err();
+        assert!(err.to_string().contains("b.txt"), "{}", err);
+
+        // Dropping a file from the index leaves the root unexplained
+        fs::write(out.join(BUNDLE_FILES_DIR).join("b.txt"), b"b").unwrap();
+        let mut index = index;
+        index.files.remove(2);
+        fs::remove_file(out.join(BUNDLE_FILES_DIR).join("c.txt")).unwrap();
+        fs::write(
+            out.join(BUNDLE_INDEX_FILE),
+            serde_json::to_string(&index).unwrap(),
+        )
+        .unwrap();
+        assert!(verify_bundle(&out, None).is_err());
+
+        fs::remove_dir_all(&out).ok();
+    }
+}
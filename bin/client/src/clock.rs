@@ -0,0 +1,59 @@
+use common::utils::{get_current_timestamp_ms, SERVER_TIME_HEADER};
+use log::warn;
+use reqwest::blocking::Response;
+
+/// Offsets smaller than this are network latency rather than a misconfigured clock
+const MIN_REPORTED_SKEW_MS: u64 = 1000;
+
+/// Warn if a failed response reports a server clock far from the local one
+/// The server sends its time when it rejects a request timestamp, so a skewed local
+/// clock shows up as a fixable warning instead of an opaque too-old/too-future error
+pub fn warn_on_clock_skew(response: &Response) {
+    let server_time_ms = response
+        .headers()
+        .get(SERVER_TIME_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if let Some(message) =
+        server_time_ms.and_then(|server| skew_warning(server, get_current_timestamp_ms()))
+    {
+        warn!("{}", message);
+    }
+}
+
+/// Describe how far the local clock is from the server's, if far enough to matter
+fn skew_warning(server_time_ms: u64, local_time_ms: u64) -> Option<String> {
+    let offset_ms = local_time_ms.abs_diff(server_time_ms);
+    if offset_ms < MIN_REPORTED_SKEW_MS {
+        return None;
+    }
+    let direction = if local_time_ms < server_time_ms {
+        "behind"
+    } else {
+        "ahead"
+    };
+    Some(format!(
+        "Your clock is {} seconds off from the server ({}); signed requests will be rejected until it is synchronized (e.g. with NTP)",
+        offset_ms / 1000,
+        direction
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skew_warning_reports_offset_and_direction() {
+        let server = 1_700_000_000_000;
+        let behind = skew_warning(server, server - 600_000).unwrap();
+        assert!(behind.contains("600 seconds off"));
+        assert!(behind.contains("behind"));
+        let ahead = skew_warning(server, server + 90_500).unwrap();
+        assert!(ahead.contains("90 seconds off"));
+        assert!(ahead.contains("ahead"));
+
+        // Latency-sized differences are not reported
+        assert!(skew_warning(server, server + 200).is_none());
+    }
+}
@@ -4,6 +4,16 @@ pub const CLIENT_DATA_DIR: &str = "client_data";
 /// Keypair filename
 pub const KEY_FILE: &str = "keypair.txt";
 
+/// Records where the secret key is kept when it is not in `KEY_FILE`
+pub const KEY_STORE_FILE: &str = "key_store.txt";
+
+/// Environment variable supplying the passphrase of a protected key file, so the
+/// client can run without a terminal to prompt on
+pub const KEY_PASSPHRASE_ENV: &str = "CLIENT_KEY_PASSPHRASE";
+
+/// Service name of keypairs kept in the OS keyring
+pub const KEYRING_SERVICE: &str = "verifiable-storage";
+
 /// Client ID filename
 pub const CLIENT_ID_FILE: &str = "client_id.txt";
 
@@ -13,14 +23,54 @@ pub const DEFAULT_SERVER_URL: &str = "http://127.0.0.1:8080";
 /// Root hash filename
 pub const ROOT_HASH_FILE: &str = "root_hash.txt";
 
+/// Hash algorithm used to build the batch's Merkle tree
+pub const HASH_ALGORITHM_FILE: &str = "hash_algorithm.txt";
+
+/// Original batch ID the files were encrypted under (written when a batch is renamed)
+pub const ENCRYPTION_BATCH_ID_FILE: &str = "encryption_batch_id.txt";
+
 /// Filenames metadata file
 pub const FILENAMES_FILE: &str = "filenames.json";
 
 /// Default downloaded files directory name
 pub const DOWNLOADED_DIR: &str = "downloaded";
 
+/// Suffix of the proof saved next to each downloaded file
+pub const PROOF_FILE_SUFFIX: &str = ".proof.json";
+
+/// Progress of an interrupted `download-batch`, kept in the batch's data directory
+pub const DOWNLOAD_STATE_FILE: &str = "download_state.json";
+
+/// Index file of an exported batch bundle
+pub const BUNDLE_INDEX_FILE: &str = "bundle.json";
+
+/// Directory holding the file contents of an exported batch bundle
+pub const BUNDLE_FILES_DIR: &str = "files";
+
 /// Upload endpoint path
 pub const UPLOAD_ENDPOINT: &str = "/upload";
 
+/// How often to poll the status of an upload the server queued (202 Accepted)
+pub const UPLOAD_STATUS_POLL_INTERVAL_MS: u64 = 100;
+
 /// Download endpoint path
 pub const DOWNLOAD_ENDPOINT: &str = "/download";
+
+/// Largest file a download accepts unless `--max-download-size` says otherwise (100 MiB)
+pub const DEFAULT_MAX_DOWNLOAD_SIZE_BYTES: u64 = 100 * 1024 * 1024;
+
+/// Room allowed in a download response beyond the encoded file content, for the proof
+/// and the other JSON fields
+pub const DOWNLOAD_RESPONSE_OVERHEAD_BYTES: u64 = 64 * 1024;
+
+/// Multiple-file proofs endpoint path
+pub const PROOFS_ENDPOINT: &str = "/proofs";
+
+/// Batch operations endpoint path prefix
+pub const BATCH_ENDPOINT: &str = "/batch";
+
+/// Listing endpoint path for the client's own batches
+pub const BATCHES_ENDPOINT: &str = "/batches";
+
+/// Single-file deletion endpoint path
+pub const FILE_ENDPOINT: &str = "/file";
@@ -0,0 +1,246 @@
+use crate::batch::load_encryption_batch_id;
+use crate::clock::warn_on_clock_skew;
+use crate::constants::BATCH_ENDPOINT;
+use crate::upload::{compute_upload_leaves, read_files_from_directory};
+use anyhow::{Context, Result};
+use common::auth_message::list_message;
+use common::utils::get_current_timestamp_ms;
+use common::{BatchFileEntry, BatchFilesResponse};
+use crypto::sign_message;
+use ed25519_dalek::SigningKey;
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Differences between a local directory and a batch on the server
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct BatchDiff {
+    /// Files only present locally
+    pub added: Vec<String>,
+    /// Files only present on the server
+    pub removed: Vec<String>,
+    /// Files present on both sides with different content
+    pub modified: Vec<String>,
+    /// Number of files identical on both sides
+    pub unchanged: usize,
+}
+
+impl BatchDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Compare local leaf hashes against the server's listing, both keyed by filename
+pub fn compute_diff(local: &[BatchFileEntry], remote: &[BatchFileEntry]) -> BatchDiff {
+    let remote: BTreeMap<_, _> = remote
+        .iter()
+        .map(|entry| (entry.filename.as_str(), entry.leaf_hash.as_str()))
+        .collect();
+    let local: BTreeMap<_, _> = local
+        .iter()
+        .map(|entry| (entry.filename.as_str(), entry.leaf_hash.as_str()))
+        .collect();
+
+    let mut diff = BatchDiff::default();
+    for (filename, leaf_hash) in &local {
+        match remote.get(filename) {
+            None => diff.added.push(filename.to_string()),
+            Some(remote_hash) if remote_hash != leaf_hash => {
+                diff.modified.push(filename.to_string())
+            }
+            Some(_) => diff.unchanged += 1,
+        }
+    }
+    diff.removed = remote
+        .keys()
+        .filter(|filename| !local.contains_key(*filename))
+        .map(|filename| filename.to_string())
+        .collect();
+    diff
+}
+
+/// Diff a local directory against a batch on the server and print the result
+pub fn diff_batch(
+    dir: &Path,
+    server: &str,
+    batch_id: &str,
+    signing_key: &SigningKey,
+    client_id: &str,
+    data_dir: &Path,
+    json: bool,
+) -> Result<BatchDiff> {
+    let listing = fetch_batch_files(server, batch_id, signing_key, client_id)?;
+
+    // Hash what an upload would send, so leaf hashes are comparable with the server's
+    let encryption_batch_id = load_encryption_batch_id(batch_id, data_dir)?;
+    let file_list = read_files_from_directory(dir)?;
+    let uploaded = compute_upload_leaves(
+        signing_key,
+        &encryption_batch_id,
+        listing.public,
+        &file_list,
+    )?;
+    let local: Vec<BatchFileEntry> = uploaded
+        .iter()
+        .map(|(filename, leaf)| BatchFileEntry {
+            filename: filename.clone(),
+            leaf_hash: hex::encode(leaf),
+        })
+        .collect();
+
+    let diff = compute_diff(&local, &listing.files);
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&diff).context("Failed to serialize diff")?
+        );
+    } else if diff.is_empty() {
+        println!("No changes ({} files unchanged)", diff.unchanged);
+    } else {
+        for filename in &diff.added {
+            println!("+ {}", filename);
+        }
+        for filename in &diff.removed {
+            println!("- {}", filename);
+        }
+        for filename in &diff.modified {
+            println!("M {}", filename);
+        }
+        println!(
+            "{} added, {} removed, {} modified, {} unchanged",
+            diff.added.len(),
+            diff.removed.len(),
+            diff.modified.len(),
+            diff.unchanged
+        );
+    }
+
+    Ok(diff)
+}
+
+/// Fetch the server's detailed file listing for a batch
+pub fn fetch_batch_files(
+    server: &str,
+    batch_id: &str,
+    signing_key: &SigningKey,
+    client_id: &str,
+) -> Result<BatchFilesResponse> {
+    fetch_existing_batch_files(server, batch_id, signing_key, client_id)?
+        .ok_or_else(|| anyhow::anyhow!("Listing batch failed: batch {} not found", batch_id))
+}
+
+/// Fetch the server's detailed file listing, or `None` if the batch does not exist yet
+pub fn fetch_existing_batch_files(
+    server: &str,
+    batch_id: &str,
+    signing_key: &SigningKey,
+    client_id: &str,
+) -> Result<Option<BatchFilesResponse>> {
+    let timestamp = get_current_timestamp_ms();
+    let signature = sign_message(signing_key, &list_message(batch_id, timestamp));
+
+    let url = format!("{}{}/{}/files", server, BATCH_ENDPOINT, batch_id);
+    let response = Client::new()
+        .get(&url)
+        .query(&[
+            ("signature", hex::encode(signature.to_bytes())),
+            ("timestamp", timestamp.to_string()),
+            ("client_id", client_id.to_string()),
+        ])
+        .send()
+        .context("Failed to connect to server")?;
+
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        warn_on_clock_skew(&response);
+        let error_text = response
+            .text()
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        anyhow::bail!("Listing batch failed: {} - {}", status, error_text);
+    }
+
+    response
+        .json()
+        .map(Some)
+        .context("Failed to parse batch listing response")
+}
+
+/// Decode listed files into (filename, leaf hash) pairs, keeping the listing's tree order
+pub fn listed_leaves(entries: &[BatchFileEntry]) -> Result<Vec<(String, [u8; 32])>> {
+    entries
+        .iter()
+        .map(|entry| {
+            let leaf_hash: [u8; 32] = hex::decode(&entry.leaf_hash)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .with_context(|| format!("Invalid leaf hash for {}", entry.filename))?;
+            Ok((entry.filename.clone(), leaf_hash))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upload::compute_root_hash;
+    use crypto::hash_leaf;
+
+    fn entry(filename: &str, content: &[u8]) -> BatchFileEntry {
+        BatchFileEntry {
+            filename: filename.to_string(),
+            leaf_hash: hex::encode(hash_leaf(content)),
+        }
+    }
+
+    #[test]
+    fn test_compute_diff_categories() {
+        let local = vec![
+            entry("added.txt", b"new"),
+            entry("modified.txt", b"changed"),
+            entry("same.txt", b"same"),
+        ];
+        let remote = vec![
+            entry("modified.txt", b"original"),
+            entry("removed.txt", b"gone"),
+            entry("same.txt", b"same"),
+        ];
+
+        let diff = compute_diff(&local, &remote);
+        assert_eq!(
+            diff,
+            BatchDiff {
+                added: vec!["added.txt".to_string()],
+                removed: vec!["removed.txt".to_string()],
+                modified: vec!["modified.txt".to_string()],
+                unchanged: 1,
+            }
+        );
+        assert!(!diff.is_empty());
+        assert!(compute_diff(&remote, &remote).is_empty());
+    }
+
+    #[test]
+    fn test_listed_leaves_rebuild_uploaded_root() {
+        let uploaded: Vec<(String, [u8; 32])> = [("a.txt", b"a"), ("b.txt", b"b")]
+            .iter()
+            .map(|(filename, content)| (filename.to_string(), hash_leaf(*content)))
+            .collect();
+        let uploaded_root = compute_root_hash(&uploaded, None).unwrap();
+
+        let listing = vec![entry("a.txt", b"a"), entry("b.txt", b"b")];
+        let leaves = listed_leaves(&listing).unwrap();
+        assert_eq!(leaves, uploaded);
+        assert_eq!(compute_root_hash(&leaves, None).unwrap(), uploaded_root);
+
+        let mut bad = listing;
+        bad[1].leaf_hash.truncate(10);
+        assert!(listed_leaves(&bad).is_err());
+    }
+}
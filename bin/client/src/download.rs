@@ -1,29 +1,144 @@
-use crate::constants::{DOWNLOADED_DIR, DOWNLOAD_ENDPOINT, ROOT_HASH_FILE};
+use crate::batch::load_encryption_batch_id;
+use crate::clock::warn_on_clock_skew;
+use crate::constants::{
+    DEFAULT_MAX_DOWNLOAD_SIZE_BYTES, DOWNLOADED_DIR, DOWNLOAD_ENDPOINT,
+    DOWNLOAD_RESPONSE_OVERHEAD_BYTES, HASH_ALGORITHM_FILE, ROOT_HASH_FILE,
+};
+use crate::recheck::{save_proof, SavedProof};
 use anyhow::{Context, Result};
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
+use common::auth_message::download_message;
 use common::utils::get_current_timestamp_ms;
 use common::{file_utils, DownloadResponse, ProofNodeJson};
-use crypto::{decrypt_file, hash_leaf, sign_message};
+use crypto::{decrypt_file, generate_nonce, hash_leaf, sign_message};
 use ed25519_dalek::SigningKey;
-use merkle_tree::MerkleProof;
-use reqwest::blocking::Client;
+use merkle_tree::{decode_hash, encode_hash, MerkleProof};
+use reqwest::blocking::{Client, Response};
 use std::fs;
+use std::io::{IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
 
-/// Helper to decode hex string to fixed-size array
-fn hex_decode_array<const N: usize>(s: &str) -> Result<[u8; N]> {
-    let bytes = hex::decode(s.trim())?;
-    if bytes.len() != N {
-        anyhow::bail!(
-            "Invalid hex length: expected {} bytes, got {}",
-            N,
-            bytes.len()
+/// Printed whenever a download skips proof verification
+pub const UNVERIFIED_WARNING: &str = "WARNING: MERKLE PROOF VERIFICATION WAS SKIPPED \
+(--insecure-skip-proof-verification). The integrity of this file was NOT verified: \
+the server could have returned any content. Never rely on this file.";
+
+/// Write the unverified-download warning, in bold red when `out` is a terminal
+fn warn_unverified(out: &mut impl Write, color: bool) {
+    let banner = "!".repeat(72);
+    let text = format!("{}\n{}\n{}", banner, UNVERIFIED_WARNING, banner);
+    let _ = if color {
+        writeln!(out, "\x1b[1;31m{}\x1b[0m", text)
+    } else {
+        writeln!(out, "{}", text)
+    };
+}
+
+/// Parse a `--file-mode` argument: octal permission bits such as 600, 0600 or 0o755
+pub fn parse_file_mode(arg: &str) -> Result<u32, String> {
+    let digits = arg.strip_prefix("0o").unwrap_or(arg);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!(
+            "Invalid file mode {:?}: expected octal permissions such as 0600",
+            arg
+        )),
+    }
+}
+
+/// Parse a `--hash-truncation-bytes` argument: a node hash width the server may truncate to
+pub fn parse_hash_truncation_bytes(arg: &str) -> Result<usize, String> {
+    arg.parse::<usize>()
+        .ok()
+        .filter(|bytes| merkle_tree::validate_truncation(*bytes).is_ok())
+        .ok_or_else(|| {
+            format!(
+                "Invalid hash truncation {:?}: expected {} to 31 bytes",
+                arg,
+                merkle_tree::MIN_TRUNCATION_BYTES
+            )
+        })
+}
+
+/// Set the permissions of a written file (Unix only)
+#[cfg(unix)]
+fn apply_file_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions of {:?}", path))
+}
+
+/// File modes are Unix permissions; elsewhere `with_file_mode` warns and nothing is applied
+#[cfg(not(unix))]
+fn apply_file_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+/// Convert a JSON Merkle proof to merkle-tree proof nodes
+/// Node hashes are `truncation` bytes long when the server truncates them, 32 otherwise
+pub fn proof_nodes_from_json(
+    proof_json: &[ProofNodeJson],
+    truncation: Option<usize>,
+) -> Result<Vec<merkle_tree::ProofNode>> {
+    proof_json
+        .iter()
+        .map(|p| {
+            let hash = decode_hash(&p.hash, truncation).context("Failed to decode proof hash")?;
+            Ok(merkle_tree::ProofNode {
+                hash,
+                is_left: p.is_left,
+            })
+        })
+        .collect()
+}
+
+/// Compute the root a proof leads to, at full width or truncated to `truncation` bytes
+pub fn compute_proof_root(proof: &MerkleProof, truncation: Option<usize>) -> Result<[u8; 32]> {
+    let root = match truncation {
+        Some(bytes) => proof.compute_root_truncated(bytes),
+        None => proof.compute_root(),
+    };
+    root.context("Failed to compute root from proof")
+}
+
+/// Read a download response whose file content must not exceed `max_size` bytes
+/// A hostile server could send an endless body: one declaring a larger length is refused
+/// outright, and at most the base64 size of `max_size` (plus room for the proof) is read
+/// before giving up, so an oversized file is never held in memory or decoded
+fn read_download_response(response: Response, max_size: u64) -> Result<DownloadResponse> {
+    let limit = max_size.div_ceil(3) * 4 + DOWNLOAD_RESPONSE_OVERHEAD_BYTES;
+    if let Some(length) = response.content_length() {
+        anyhow::ensure!(
+            length <= limit,
+            "Download of {} bytes exceeds the {} byte limit (--max-download-size)",
+            length,
+            max_size
         );
     }
-    bytes
-        .try_into()
-        .map_err(|_| anyhow::anyhow!("Failed to convert to array"))
+    let mut body = Vec::new();
+    response
+        .take(limit + 1)
+        .read_to_end(&mut body)
+        .context("Failed to read download response")?;
+    anyhow::ensure!(
+        body.len() as u64 <= limit,
+        "Download exceeds the {} byte limit (--max-download-size); aborted",
+        max_size
+    );
+    let result: DownloadResponse =
+        serde_json::from_slice(&body).context("Failed to parse download response")?;
+
+    // The overhead allowance could hide a slightly oversized file: check before decoding
+    let encoded = result.file_content.trim_end_matches('=');
+    let decoded_size = encoded.len() as u64 * 3 / 4;
+    anyhow::ensure!(
+        decoded_size <= max_size,
+        "File of {} bytes exceeds the {} byte limit (--max-download-size)",
+        decoded_size,
+        max_size
+    );
+    Ok(result)
 }
 
 /// Configuration for file downloads
@@ -35,10 +150,22 @@ pub struct DownloadConfig {
     pub batch_id: String,
     /// Signing key for authentication
     pub signing_key: SigningKey,
-    /// Client ID
+    /// Client ID of the batch owner
     pub client_id: String,
+    /// This client's ID; differs from `client_id` when reading a batch shared with us
+    pub requester_id: String,
     /// Data directory
     pub data_dir: PathBuf,
+    /// Read a public batch anonymously (no signature, content is not encrypted)
+    pub public: bool,
+    /// Permissions of written files (Unix only); `None` keeps the default
+    pub file_mode: Option<u32>,
+    /// Replace an existing output file instead of refusing to download
+    pub overwrite: bool,
+    /// Width the server truncates Merkle node hashes to (`--hash-truncation-bytes`)
+    pub hash_truncation_bytes: Option<usize>,
+    /// Largest file content accepted from the server, in bytes (`--max-download-size`)
+    pub max_download_size: u64,
 }
 
 /// Handles file downloads and verification
@@ -47,7 +174,13 @@ pub struct FileDownloader {
     batch_id: String,
     signing_key: SigningKey,
     client_id: String,
+    requester_id: String,
     data_dir: PathBuf,
+    public: bool,
+    file_mode: Option<u32>,
+    overwrite: bool,
+    hash_truncation_bytes: Option<usize>,
+    max_download_size: u64,
 }
 
 impl FileDownloader {
@@ -57,24 +190,204 @@ impl FileDownloader {
         batch_id: String,
         signing_key: SigningKey,
         client_id: String,
+        requester_id: String,
         data_dir: PathBuf,
+        public: bool,
     ) -> Self {
         Self {
             server,
             batch_id,
             signing_key,
             client_id,
+            requester_id,
             data_dir,
+            public,
+            file_mode: None,
+            overwrite: true,
+            hash_truncation_bytes: None,
+            max_download_size: DEFAULT_MAX_DOWNLOAD_SIZE_BYTES,
+        }
+    }
+
+    /// Refuse files larger than `bytes` without reading more of the response than such a
+    /// file would take (see `read_download_response`)
+    pub fn with_max_download_size(mut self, bytes: u64) -> Self {
+        self.max_download_size = bytes;
+        self
+    }
+
+    /// Verify proofs whose node hashes are truncated to `bytes` (full width when `None`)
+    /// Responses built with any other truncation are refused
+    pub fn with_hash_truncation_bytes(mut self, bytes: Option<usize>) -> Self {
+        self.hash_truncation_bytes = bytes;
+        self
+    }
+
+    /// Refuse to replace an existing output file unless `overwrite` is set
+    /// Downloads overwrite by default; the `download` command opts out unless
+    /// `--overwrite` is passed
+    pub fn with_overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// The file a download ends with: the plaintext, or the ciphertext of a shared batch
+    fn final_output_name(&self, filename: &str) -> String {
+        if self.is_shared() {
+            format!("{}.encrypted", filename)
+        } else {
+            filename.to_string()
+        }
+    }
+
+    /// Fail before downloading anything if the download would replace an existing file
+    fn ensure_not_overwriting(&self, output_dir: &Path, filename: &str) -> Result<()> {
+        let path = output_dir.join(self.final_output_name(filename));
+        if !self.overwrite && path.exists() {
+            anyhow::bail!(
+                "Refusing to overwrite existing file {:?}; pass --overwrite to replace it",
+                path
+            );
+        }
+        Ok(())
+    }
+
+    /// Set the permissions of written files to `mode` (Unix only)
+    pub fn with_file_mode(mut self, mode: Option<u32>) -> Self {
+        if mode.is_some() && !cfg!(unix) {
+            eprintln!("Warning: --file-mode is not supported on this platform and is ignored");
+        }
+        self.file_mode = mode;
+        self
+    }
+
+    /// Write a downloaded file, applying the configured permissions
+    /// Unless overwriting is allowed the file must not exist yet; creating it exclusively
+    /// also catches a file that appeared while the download was in flight
+    fn write_output(&self, path: &Path, content: &[u8], may_overwrite: bool) -> Result<()> {
+        if may_overwrite {
+            fs::write(path, content)?;
+        } else {
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .with_context(|| {
+                    format!(
+                        "Refusing to overwrite existing file {:?}; pass --overwrite to replace it",
+                        path
+                    )
+                })?
+                .write_all(content)?;
+        }
+        match self.file_mode {
+            Some(mode) => apply_file_mode(path, mode),
+            None => Ok(()),
         }
     }
 
-    /// Download and verify a file from the server
+    /// Signed read of a batch owned by another client that shared it with us
+    fn is_shared(&self) -> bool {
+        !self.public && self.requester_id != self.client_id
+    }
+
+    /// Download a file from the server, verifying it against `root_hash`
+    /// With no root the proof is not checked at all and a loud warning is printed
+    /// (`--insecure-skip-proof-verification`); decrypting a private file still
+    /// authenticates it with AES-GCM, since that does not depend on the proof
     pub fn download_and_verify(
         &self,
         filename: &str,
-        root_hash: &str,
+        root_hash: Option<&str>,
         output_dir: Option<&PathBuf>,
     ) -> Result<()> {
+        let output_path = if let Some(dir) = output_dir {
+            dir.clone()
+        } else {
+            self.data_dir.join(&self.batch_id).join(DOWNLOADED_DIR)
+        };
+        self.ensure_not_overwriting(&output_path, filename)?;
+
+        let (result, encrypted_content) = match root_hash {
+            Some(root_hash) => self.fetch_verified(filename, root_hash)?,
+            None => {
+                let result = self.request_file_proof(filename)?;
+                let stderr = &mut std::io::stderr();
+                let color = stderr.is_terminal();
+                let content = self.accept_unverified(&result, filename, stderr, color)?;
+                (result, content)
+            }
+        };
+        let file_hash_hex = hex::encode(hash_leaf(&encrypted_content));
+        // Kept next to the file so `recheck` can verify the local copy later;
+        // an unchecked proof is not worth keeping
+        let saved_proof = root_hash.map(|_| SavedProof {
+            filename: result.filename.clone(),
+            leaf_hash: file_hash_hex.clone(),
+            merkle_proof: result.merkle_proof.clone(),
+            encrypted: !self.public,
+            hash_truncation_bytes: self.hash_truncation_bytes,
+        });
+
+        // Public batches are stored unencrypted: nothing to decrypt
+        if self.public {
+            self.save_downloaded_file(&result.filename, &encrypted_content, output_dir)?;
+            if let Some(saved_proof) = &saved_proof {
+                save_proof(&output_path, saved_proof)?;
+            }
+
+            print_summary(filename, &file_hash_hex, root_hash);
+            return Ok(());
+        }
+
+        // Save encrypted file first
+        self.save_encrypted_file(&result.filename, &encrypted_content, &output_path)?;
+        if let Some(saved_proof) = &saved_proof {
+            save_proof(&output_path, saved_proof)?;
+        }
+
+        // Private batches are encrypted with a key derived from the owner's signing
+        // key; sharing grants access to the verified ciphertext, not the key
+        if self.is_shared() {
+            print_summary(filename, &file_hash_hex, root_hash);
+            println!(
+                "  Content is encrypted with the key of batch owner {} and was not decrypted",
+                self.client_id
+            );
+            return Ok(());
+        }
+
+        // Decrypt the encrypted content to get plaintext
+        let encryption_batch_id = load_encryption_batch_id(&self.batch_id, &self.data_dir)?;
+        let plaintext = decrypt_file(
+            &self.signing_key,
+            &result.filename,
+            &encryption_batch_id,
+            &encrypted_content,
+        )
+        .context("Failed to decrypt file content")?;
+
+        // Save decrypted plaintext file to output directory
+        self.save_downloaded_file(&result.filename, &plaintext, output_dir)?;
+
+        print_summary(filename, &file_hash_hex, root_hash);
+        println!(
+            "  Encrypted file saved temporarily: {}",
+            output_path
+                .join(format!("{}.encrypted", filename))
+                .display()
+        );
+
+        Ok(())
+    }
+
+    /// Download a file and verify its Merkle proof against `root_hash`
+    /// Returns the server's response and the stored (possibly encrypted) content
+    pub fn fetch_verified(
+        &self,
+        filename: &str,
+        root_hash: &str,
+    ) -> Result<(DownloadResponse, Vec<u8>)> {
         // Request file hash, content, and proof from server
         let result = self.request_file_proof(filename)?;
 
@@ -86,6 +399,14 @@ impl FileDownloader {
             result.filename
         );
 
+        // Refuse proofs whose semantics this client does not know, rather than misverify
+        result.check_proof_version()?;
+
+        // Fail early with a clear error if the server hashes differently than we did
+        let expected_algorithm = load_hash_algorithm(&self.batch_id, &self.data_dir)?;
+        result.check_hash_algorithm(&expected_algorithm)?;
+        result.check_hash_truncation(self.hash_truncation_bytes)?;
+
         // Decode encrypted file content from server
         let encrypted_content = STANDARD
             .decode(&result.file_content)
@@ -94,84 +415,91 @@ impl FileDownloader {
         // Compute file hash from downloaded content
         // Merkle tree is built from encrypted data, so encrypted data need to be hashed
         let file_hash = hash_leaf(&encrypted_content);
-        let file_hash_hex = hex::encode(file_hash);
 
         // Print received data
-        self.print_received_proof(&result, &file_hash_hex);
+        self.print_received_proof(&result, &hex::encode(file_hash));
 
         // Verify Merkle proof (proof is for encrypted data)
         // Use computed hash as leaf hash in proof verification
         self.verify_merkle_proof(&result, &file_hash, root_hash)?;
 
-        // Save encrypted file first
-        let output_path = if let Some(dir) = output_dir {
-            dir.clone()
-        } else {
-            self.data_dir.join(&self.batch_id).join(DOWNLOADED_DIR)
-        };
-        self.save_encrypted_file(&result.filename, &encrypted_content, &output_path)?;
-
-        // Decrypt the encrypted content to get plaintext
-        let plaintext = decrypt_file(
-            &self.signing_key,
-            &result.filename,
-            &self.batch_id,
-            &encrypted_content,
-        )
-        .context("Failed to decrypt file content")?;
-
-        // Save decrypted plaintext file to output directory
-        self.save_downloaded_file(&result.filename, &plaintext, output_dir)?;
+        Ok((result, encrypted_content))
+    }
 
-        println!("\n✓ File verification successful!");
-        println!("  File: {}", filename);
-        println!("  File hash: {}", file_hash_hex);
-        println!("  Verified against root: {}", root_hash);
-        println!(
-            "  Encrypted file saved temporarily: {}",
-            output_path
-                .join(format!("{}.encrypted", filename))
-                .display()
+    /// Accept a response without checking its proof, warning on `out` that it was not verified
+    /// Returns the stored (possibly encrypted) content
+    fn accept_unverified(
+        &self,
+        result: &DownloadResponse,
+        filename: &str,
+        out: &mut impl Write,
+        color: bool,
+    ) -> Result<Vec<u8>> {
+        anyhow::ensure!(
+            result.filename == filename,
+            "Filename mismatch: expected {}, got {}",
+            filename,
+            result.filename
         );
-
-        Ok(())
+        let content = STANDARD
+            .decode(&result.file_content)
+            .context("Failed to decode encrypted file content from server")?;
+        self.print_received_proof(result, &hex::encode(hash_leaf(&content)));
+        warn_unverified(out, color);
+        Ok(content)
     }
 
     /// Request file hash and Merkle proof from server
     fn request_file_proof(&self, filename: &str) -> Result<DownloadResponse> {
-        // Create message to sign
-        let timestamp = get_current_timestamp_ms();
-        let message = self.build_download_message(filename, timestamp);
-
-        // Sign message
-        let signature = sign_message(&self.signing_key, &message);
-        let signature_hex = hex::encode(signature.to_bytes());
+        let mut query = vec![
+            ("filename", filename.to_string()),
+            ("batch_id", self.batch_id.clone()),
+            ("client_id", self.client_id.clone()),
+        ];
+
+        // Anonymous reads of public batches are not signed
+        if !self.public {
+            // Create message to sign
+            let timestamp = get_current_timestamp_ms();
+            let nonce = generate_nonce();
+            // Shared reads also sign the owner's client ID
+            let owner = self.is_shared().then_some(self.client_id.as_str());
+            let message = download_message(filename, &self.batch_id, timestamp, &nonce, owner);
+
+            // Sign message
+            let signature = sign_message(&self.signing_key, &message);
+            query.push(("signature", hex::encode(signature.to_bytes())));
+            query.push(("timestamp", timestamp.to_string()));
+            query.push(("nonce", hex::encode(nonce)));
+            if self.is_shared() {
+                // Lets the server register us if we never uploaded anything
+                query.push(("requester_id", self.requester_id.clone()));
+                query.push((
+                    "requester_public_key",
+                    hex::encode(self.signing_key.verifying_key().as_bytes()),
+                ));
+            }
+        }
 
         // Send request
         let client = Client::new();
         let url = format!("{}{}", self.server, DOWNLOAD_ENDPOINT);
         let response = client
             .get(&url)
-            .query(&[
-                ("filename", filename),
-                ("batch_id", &self.batch_id),
-                ("signature", &signature_hex),
-                ("timestamp", &timestamp.to_string()),
-                ("client_id", &self.client_id),
-            ])
+            .query(&query)
             .send()
             .context("Failed to connect to server")?;
 
         let status = response.status();
         if !status.is_success() {
+            warn_on_clock_skew(&response);
             let error_text = response
                 .text()
                 .unwrap_or_else(|_| "Unknown error".to_string());
             anyhow::bail!("Download failed: {} - {}", status, error_text);
         }
 
-        let result: DownloadResponse = response.json()?;
-        Ok(result)
+        read_download_response(response, self.max_download_size)
     }
 
     /// Verify Merkle proof against stored root hash
@@ -193,24 +521,30 @@ impl FileDownloader {
             leaf_hash,
             path: proof_nodes,
         };
-        let computed_root = proof
-            .compute_root()
-            .context("Failed to compute root from proof")?;
-
-        // Decode expected root hash
-        let expected_root =
-            hex_decode_array::<32>(root_hash).context("Failed to decode root_hash")?;
+        let computed_root = compute_proof_root(&proof, self.hash_truncation_bytes)?;
+
+        // Check against the expected root; truncated roots are compared at the served width
+        let verified = match self.hash_truncation_bytes {
+            None => proof
+                .verify_hex(root_hash.trim())
+                .context("Failed to decode root_hash")?,
+            Some(_) => {
+                computed_root
+                    == decode_hash(root_hash.trim(), self.hash_truncation_bytes)
+                        .context("Failed to decode root_hash")?
+            }
+        };
 
         // Print verification result
         println!("\n=== Verification ===");
-        println!("Computed root: {}", hex::encode(computed_root));
+        println!(
+            "Computed root: {}",
+            encode_hash(&computed_root, self.hash_truncation_bytes)
+        );
         println!("Expected root: {}", root_hash);
 
         // Verify roots match
-        anyhow::ensure!(
-            computed_root == expected_root,
-            "✗ Verification failed: Root mismatch"
-        );
+        anyhow::ensure!(verified, "✗ Verification failed: Root mismatch");
 
         println!("✓ Verified: Root matches!");
         Ok(())
@@ -221,17 +555,7 @@ impl FileDownloader {
         &self,
         proof_json: &[ProofNodeJson],
     ) -> Result<Vec<merkle_tree::ProofNode>> {
-        proof_json
-            .iter()
-            .map(|p| {
-                let hash =
-                    hex_decode_array::<32>(&p.hash).context("Failed to decode proof hash")?;
-                Ok(merkle_tree::ProofNode {
-                    hash,
-                    is_left: p.is_left,
-                })
-            })
-            .collect()
+        proof_nodes_from_json(proof_json, self.hash_truncation_bytes)
     }
 
     /// Print received proof information
@@ -257,15 +581,6 @@ impl FileDownloader {
         }
     }
 
-    /// Build message for download signature
-    fn build_download_message(&self, filename: &str, timestamp: u64) -> Vec<u8> {
-        let mut message = Vec::new();
-        message.extend_from_slice(filename.as_bytes());
-        message.extend_from_slice(self.batch_id.as_bytes());
-        message.extend_from_slice(&timestamp.to_be_bytes());
-        message
-    }
-
     /// Save encrypted file to disk (for demo purposes)
     fn save_encrypted_file(
         &self,
@@ -279,7 +594,10 @@ impl FileDownloader {
         // Save encrypted file with .encrypted suffix
         let encrypted_filename = format!("{}.encrypted", filename);
         let encrypted_path = output_dir.join(&encrypted_filename);
-        fs::write(&encrypted_path, encrypted_content).context("Failed to write encrypted file")?;
+        // Only the ciphertext of a shared batch is a final output; otherwise it is scratch
+        let may_overwrite = self.overwrite || !self.is_shared();
+        self.write_output(&encrypted_path, encrypted_content, may_overwrite)
+            .context("Failed to write encrypted file")?;
 
         println!("  Encrypted file saved to: {:?}", encrypted_path);
         Ok(())
@@ -305,18 +623,40 @@ impl FileDownloader {
 
         // Save decrypted file
         let file_path = output_path.join(filename);
-        fs::write(&file_path, content).context("Failed to write downloaded file")?;
+        self.write_output(&file_path, content, self.overwrite)
+            .context("Failed to write downloaded file")?;
 
         println!("  Decrypted file saved to: {:?}", file_path);
         Ok(())
     }
 }
 
+/// Report the outcome of a download; unverified downloads repeat the warning
+fn print_summary(filename: &str, file_hash_hex: &str, root_hash: Option<&str>) {
+    match root_hash {
+        Some(root_hash) => {
+            println!("\n✓ File verification successful!");
+            println!("  File: {}", filename);
+            println!("  File hash: {}", file_hash_hex);
+            println!("  Verified against root: {}", root_hash);
+        }
+        None => {
+            println!("\n✗ File saved WITHOUT proof verification");
+            println!("  File: {}", filename);
+            println!("  File hash: {}", file_hash_hex);
+            let stderr = &mut std::io::stderr();
+            let color = stderr.is_terminal();
+            warn_unverified(stderr, color);
+        }
+    }
+}
+
 /// Download and verify a file from the server (convenience function)
+/// `root_hash` of `None` skips proof verification (see `download_and_verify`)
 pub fn download_file(
     config: &DownloadConfig,
     filename: &str,
-    root_hash: &str,
+    root_hash: Option<&str>,
     output_dir: Option<&PathBuf>,
 ) -> Result<()> {
     // Validate filename to prevent path traversal attacks
@@ -328,8 +668,14 @@ pub fn download_file(
         config.batch_id.clone(),
         config.signing_key.clone(),
         config.client_id.clone(),
+        config.requester_id.clone(),
         config.data_dir.clone(),
-    );
+        config.public,
+    )
+    .with_file_mode(config.file_mode)
+    .with_overwrite(config.overwrite)
+    .with_hash_truncation_bytes(config.hash_truncation_bytes)
+    .with_max_download_size(config.max_download_size);
     downloader.download_and_verify(filename, root_hash, output_dir)
 }
 
@@ -350,3 +696,317 @@ pub fn load_root_hash(batch_id: &str, data_dir: &Path) -> Result<String> {
         .to_string();
     Ok(root_hash)
 }
+
+/// Load the hash algorithm recorded for a batch at upload time
+/// Falls back to the current algorithm for batches uploaded elsewhere (e.g. public batches)
+pub fn load_hash_algorithm(batch_id: &str, data_dir: &Path) -> Result<String> {
+    let hash_algorithm_file = data_dir.join(batch_id).join(HASH_ALGORITHM_FILE);
+    if !hash_algorithm_file.exists() {
+        return Ok(merkle_tree::HASH_ALGORITHM.to_string());
+    }
+    let hash_algorithm = fs::read_to_string(&hash_algorithm_file)
+        .with_context(|| format!("Failed to read {}", HASH_ALGORITHM_FILE))?
+        .trim()
+        .to_string();
+    Ok(hash_algorithm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::generate_keypair;
+
+    #[test]
+    fn test_skipping_proof_verification_warns() {
+        let (signing_key, _) = generate_keypair();
+        let data_dir = std::env::temp_dir().join(format!("vs-unverified-{}", std::process::id()));
+        let downloader = FileDownloader::new(
+            "http://127.0.0.1:1".to_string(),
+            "batch".to_string(),
+            signing_key,
+            "owner".to_string(),
+            "owner".to_string(),
+            data_dir,
+            true,
+        );
+        // A proof from a broken tree that leads nowhere near the expected root
+        let response = DownloadResponse {
+            filename: "a.txt".to_string(),
+            file_content: STANDARD.encode(b"hello"),
+            merkle_proof: vec![ProofNodeJson {
+                hash: "00".repeat(32),
+                is_left: true,
+            }],
+            hash_algorithm: None,
+            proof_version: common::PROOF_FORMAT_VERSION,
+            stored_at: None,
+            hash_truncation_bytes: None,
+        };
+        let root_hash = hex::encode(hash_leaf(b"hello"));
+        assert!(downloader
+            .verify_merkle_proof(&response, &hash_leaf(b"hello"), &root_hash)
+            .is_err());
+
+        let mut warning = Vec::new();
+        let content = downloader
+            .accept_unverified(&response, "a.txt", &mut warning, true)
+            .unwrap();
+        assert_eq!(content, b"hello");
+        let warning = String::from_utf8(warning).unwrap();
+        assert!(warning.contains(UNVERIFIED_WARNING));
+        assert!(warning.starts_with("\x1b[1;31m"));
+
+        // Content must still match the requested file
+        let mut warning = Vec::new();
+        assert!(downloader
+            .accept_unverified(&response, "b.txt", &mut warning, false)
+            .is_err());
+    }
+
+    /// Serve one connection with `head` (status line and headers) followed by `body`
+    /// A client aborting mid-body closes the socket, so write errors are ignored
+    fn serve_once(head: &'static str, body: Vec<u8>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 4096];
+            let _ = stream.read(&mut request);
+            let _ = stream.write_all(head.as_bytes());
+            let _ = stream.write_all(&body);
+        });
+        url
+    }
+
+    #[test]
+    fn test_oversized_download_is_aborted() {
+        let (signing_key, _) = generate_keypair();
+        let data_dir = std::env::temp_dir().join(format!("vs-max-size-{}", std::process::id()));
+        let download = |server: String| {
+            FileDownloader::new(
+                server,
+                "batch".to_string(),
+                signing_key.clone(),
+                "owner".to_string(),
+                "owner".to_string(),
+                data_dir.clone(),
+                true,
+            )
+            .with_max_download_size(1024)
+            .request_file_proof("a.txt")
+        };
+        let response = |content: &[u8]| {
+            serde_json::to_vec(&DownloadResponse {
+                filename: "a.txt".to_string(),
+                file_content: STANDARD.encode(content),
+                merkle_proof: vec![],
+                hash_algorithm: None,
+                proof_version: common::PROOF_FORMAT_VERSION,
+                stored_at: None,
+                hash_truncation_bytes: None,
+            })
+            .unwrap()
+        };
+
+        // A declared length over the limit is refused before reading the body
+        let server = serve_once(
+            "HTTP/1.1 200 OK\r\nContent-Length: 1000000000000\r\n\r\n",
+            b"{".to_vec(),
+        );
+        let err = download(server).unwrap_err();
+        assert!(err.to_string().contains("--max-download-size"), "{:#}", err);
+
+        // An undeclared endless body is cut off once it passes the limit
+        let mut body = b"{\"filename\":\"a.txt\",\"file_content\":\"".to_vec();
+        body.resize(body.len() + 1024 * 1024, b'A');
+        let server = serve_once("HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n", body);
+        let err = download(server).unwrap_err();
+        assert!(err.to_string().contains("--max-download-size"), "{:#}", err);
+
+        // Content hidden in the proof allowance is refused before it is decoded
+        let server = serve_once(
+            "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n",
+            response(&[7u8; 1025]),
+        );
+        let err = download(server).unwrap_err();
+        assert!(err.to_string().contains("1025 bytes"), "{:#}", err);
+
+        // Files up to the limit are accepted
+        let server = serve_once(
+            "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n",
+            response(&[7u8; 1024]),
+        );
+        assert_eq!(
+            download(server).unwrap().file_content,
+            STANDARD.encode([7u8; 1024])
+        );
+    }
+
+    #[test]
+    fn test_parse_file_mode() {
+        assert_eq!(parse_file_mode("600"), Ok(0o600));
+        assert_eq!(parse_file_mode("0755"), Ok(0o755));
+        assert_eq!(parse_file_mode("0o4750"), Ok(0o4750));
+        for invalid in ["", "rw", "0o", "8", "17777", "-600"] {
+            assert!(parse_file_mode(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_truncated_proof_verifies_at_configured_width() {
+        let (signing_key, _) = generate_keypair();
+        let data_dir = std::env::temp_dir().join(format!("vs-truncated-{}", std::process::id()));
+        let downloader = |bytes| {
+            FileDownloader::new(
+                "http://127.0.0.1:1".to_string(),
+                "batch".to_string(),
+                signing_key.clone(),
+                "owner".to_string(),
+                "owner".to_string(),
+                data_dir.clone(),
+                true,
+            )
+            .with_hash_truncation_bytes(bytes)
+        };
+        let leaves = [hash_leaf(b"a"), hash_leaf(b"b"), hash_leaf(b"c")];
+        let tree = merkle_tree::MerkleTree::from_leaf_hashes(&leaves)
+            .unwrap()
+            .truncated(16)
+            .unwrap();
+        let response = DownloadResponse {
+            filename: "b.txt".to_string(),
+            file_content: STANDARD.encode(b"b"),
+            merkle_proof: proof_json(&tree, 1, Some(16)),
+            hash_algorithm: None,
+            proof_version: common::PROOF_FORMAT_VERSION,
+            stored_at: None,
+            hash_truncation_bytes: Some(16),
+        };
+        let root_hash = encode_hash(&tree.root_hash(), Some(16));
+        assert_eq!(root_hash.len(), 32);
+
+        assert!(downloader(Some(16))
+            .verify_merkle_proof(&response, &leaves[1], &root_hash)
+            .is_ok());
+        // A client expecting full-width or differently truncated hashes refuses the proof
+        assert!(downloader(None)
+            .verify_merkle_proof(&response, &leaves[1], &root_hash)
+            .is_err());
+        assert!(downloader(Some(20))
+            .verify_merkle_proof(&response, &leaves[1], &root_hash)
+            .is_err());
+
+        assert_eq!(parse_hash_truncation_bytes("20"), Ok(20));
+        for invalid in ["", "8", "32", "twenty"] {
+            assert!(parse_hash_truncation_bytes(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    fn proof_json(
+        tree: &merkle_tree::MerkleTree,
+        index: usize,
+        truncation: Option<usize>,
+    ) -> Vec<ProofNodeJson> {
+        tree.generate_proof(index)
+            .unwrap()
+            .path
+            .iter()
+            .map(|node| ProofNodeJson {
+                hash: encode_hash(&node.hash, truncation),
+                is_left: node.is_left,
+            })
+            .collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_mode_is_applied_to_downloaded_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (signing_key, _) = generate_keypair();
+        let output_dir = std::env::temp_dir().join(format!("vs-file-mode-{}", std::process::id()));
+        let downloader = |mode| {
+            FileDownloader::new(
+                "http://127.0.0.1:1".to_string(),
+                "batch".to_string(),
+                signing_key.clone(),
+                "owner".to_string(),
+                "owner".to_string(),
+                output_dir.clone(),
+                false,
+            )
+            .with_file_mode(mode)
+        };
+        let mode_of = |name: &str| {
+            fs::metadata(output_dir.join(name))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o7777
+        };
+
+        let restricted = downloader(Some(0o600));
+        restricted
+            .save_downloaded_file("secret.txt", b"secret", Some(&output_dir))
+            .unwrap();
+        restricted
+            .save_encrypted_file("secret.txt", b"ciphertext", &output_dir)
+            .unwrap();
+        assert_eq!(mode_of("secret.txt"), 0o600);
+        assert_eq!(mode_of("secret.txt.encrypted"), 0o600);
+
+        // Overwriting applies the new mode; the default leaves the umask's choice alone
+        downloader(Some(0o755))
+            .save_downloaded_file("secret.txt", b"#!/bin/sh", Some(&output_dir))
+            .unwrap();
+        assert_eq!(mode_of("secret.txt"), 0o755);
+        downloader(None)
+            .save_downloaded_file("plain.txt", b"plain", Some(&output_dir))
+            .unwrap();
+        assert_eq!(mode_of("plain.txt") & 0o111, 0);
+
+        fs::remove_dir_all(&output_dir).ok();
+    }
+
+    #[test]
+    fn test_existing_file_is_only_replaced_with_overwrite() {
+        let (signing_key, _) = generate_keypair();
+        let output_dir = std::env::temp_dir().join(format!("vs-overwrite-{}", std::process::id()));
+        fs::create_dir_all(&output_dir).unwrap();
+        fs::write(output_dir.join("a.txt"), b"user data").unwrap();
+        let downloader = |overwrite| {
+            FileDownloader::new(
+                "http://127.0.0.1:1".to_string(),
+                "batch".to_string(),
+                signing_key.clone(),
+                "owner".to_string(),
+                "owner".to_string(),
+                output_dir.clone(),
+                true,
+            )
+            .with_overwrite(overwrite)
+        };
+
+        // Refused before anything is requested from the (unreachable) server
+        let err = downloader(false)
+            .download_and_verify("a.txt", None, Some(&output_dir))
+            .unwrap_err();
+        assert!(err.to_string().contains("--overwrite"), "{:#}", err);
+        assert!(downloader(false)
+            .save_downloaded_file("a.txt", b"server data", Some(&output_dir))
+            .is_err());
+        assert_eq!(fs::read(output_dir.join("a.txt")).unwrap(), b"user data");
+
+        downloader(true)
+            .save_downloaded_file("a.txt", b"server data", Some(&output_dir))
+            .unwrap();
+        assert_eq!(fs::read(output_dir.join("a.txt")).unwrap(), b"server data");
+
+        // New files are written either way
+        downloader(false)
+            .save_downloaded_file("b.txt", b"b", Some(&output_dir))
+            .unwrap();
+
+        fs::remove_dir_all(&output_dir).ok();
+    }
+}
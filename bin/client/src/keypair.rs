@@ -1,49 +1,232 @@
 use crate::config::get_key_file_path;
-use crate::constants::CLIENT_ID_FILE;
+use crate::constants::{CLIENT_ID_FILE, KEYRING_SERVICE, KEY_PASSPHRASE_ENV, KEY_STORE_FILE};
 use anyhow::{Context, Result};
-use crypto::{compute_client_id, generate_keypair};
+use crypto::{
+    compute_client_id, decode_keypair, encode_keypair, generate_keypair, generate_mnemonic,
+    is_encrypted_keypair, keypair_from_mnemonic, load_encrypted_keypair, save_encrypted_keypair,
+    KeyFormat,
+};
 use log::info;
+use std::fmt;
 use std::fs;
 use std::path::Path;
+use std::str::FromStr;
+
+/// Where the client's secret key is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyStore {
+    /// `keypair.txt` in the data directory, the original store
+    #[default]
+    File,
+    /// The OS credential store (macOS Keychain, Windows Credential Manager, Secret Service
+    /// on Linux); `keypair.txt` is not written
+    Keyring,
+}
+
+impl FromStr for KeyStore {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "file" => Ok(Self::File),
+            "keyring" => Ok(Self::Keyring),
+            _ => anyhow::bail!("Invalid key store: {}. Must be 'file' or 'keyring'", s),
+        }
+    }
+}
+
+impl fmt::Display for KeyStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::File => "file",
+            Self::Keyring => "keyring",
+        })
+    }
+}
+
+/// How `generate-keypair` and `recover-keypair` save the key
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeySaveOptions {
+    /// Replace an existing keypair
+    pub force: bool,
+    /// Key file format (unused with `encrypt` or the keyring store)
+    pub format: KeyFormat,
+    pub key_store: KeyStore,
+    /// Protect the key file with a passphrase
+    pub encrypt: bool,
+}
+
+/// Key store recorded for a data directory; `File` unless `generate-keypair` chose another
+pub fn load_key_store(data_dir: &Path) -> Result<KeyStore> {
+    let key_store_file = data_dir.join(KEY_STORE_FILE);
+    if !key_store_file.exists() {
+        return Ok(KeyStore::File);
+    }
+    fs::read_to_string(&key_store_file)
+        .with_context(|| format!("Failed to read {}", KEY_STORE_FILE))?
+        .trim()
+        .parse()
+}
+
+/// Keyring entry holding the keypair of `data_dir`
+/// Entries are keyed by the canonical data directory so several data directories
+/// (e.g. one per identity) can keep separate keys
+fn keyring_entry(data_dir: &Path) -> Result<keyring::Entry> {
+    let data_dir = fs::canonicalize(data_dir)
+        .with_context(|| format!("Failed to resolve data directory {:?}", data_dir))?;
+    keyring::Entry::new(KEYRING_SERVICE, &data_dir.to_string_lossy()).map_err(keyring_unavailable)
+}
+
+/// Explain a keyring failure; there is deliberately no fallback to the key file
+fn keyring_unavailable(e: keyring::Error) -> anyhow::Error {
+    anyhow::anyhow!(
+        "OS keyring is not available ({}); use --key-store file on machines without one",
+        e
+    )
+}
+
+/// Passphrase of a protected key file: `CLIENT_KEY_PASSPHRASE` if set, else prompted
+/// for on the terminal (twice when `confirm`, for a new passphrase)
+fn key_passphrase(confirm: bool) -> Result<String> {
+    let passphrase = match std::env::var(KEY_PASSPHRASE_ENV) {
+        Ok(passphrase) => passphrase,
+        Err(_) => {
+            let passphrase = rpassword::prompt_password("Key passphrase: ").with_context(|| {
+                format!(
+                    "Failed to read key passphrase; set {} when no terminal is available",
+                    KEY_PASSPHRASE_ENV
+                )
+            })?;
+            if confirm {
+                let again = rpassword::prompt_password("Repeat key passphrase: ")
+                    .context("Failed to read key passphrase")?;
+                anyhow::ensure!(passphrase == again, "Passphrases do not match");
+            }
+            passphrase
+        }
+    };
+    anyhow::ensure!(!passphrase.is_empty(), "Key passphrase must not be empty");
+    Ok(passphrase)
+}
+
+/// Load the keypair in `key_file`, asking `passphrase` only if the file is protected
+fn load_key_file(
+    key_file: &Path,
+    passphrase: impl FnOnce() -> Result<String>,
+) -> Result<ed25519_dalek::SigningKey> {
+    let content = fs::read_to_string(key_file).context("Failed to read key file")?;
+    if is_encrypted_keypair(&content) {
+        load_encrypted_keypair(key_file, &passphrase()?)
+    } else {
+        decode_keypair(&content)
+    }
+}
 
 /// Manages keypair generation and loading
 pub struct KeypairManager;
 
 impl KeypairManager {
     /// Generate a new keypair
-    pub fn generate_keypair(data_dir: &Path, force: bool) -> Result<()> {
+    /// With `mnemonic`, the key is derived from a fresh recovery phrase, which is printed
+    /// so `recover-keypair` can rebuild the same key later
+    pub fn generate_keypair(
+        data_dir: &Path,
+        options: KeySaveOptions,
+        mnemonic: bool,
+    ) -> Result<()> {
+        let phrase = mnemonic.then(generate_mnemonic);
+        let signing_key = match &phrase {
+            Some(phrase) => keypair_from_mnemonic(phrase, "")?.0,
+            None => generate_keypair().0,
+        };
+        Self::install_keypair(data_dir, &signing_key, options, "generated")?;
+
+        if let Some(phrase) = phrase {
+            println!();
+            println!("Recovery phrase (write it down; anyone who has it can act as this client):");
+            println!("{}", phrase);
+        }
+        Ok(())
+    }
+
+    /// Rebuild the keypair derived from a recovery phrase printed by
+    /// `generate-keypair --mnemonic`
+    pub fn recover_keypair(data_dir: &Path, phrase: &str, options: KeySaveOptions) -> Result<()> {
+        let (signing_key, _) = keypair_from_mnemonic(phrase, "")?;
+        Self::install_keypair(data_dir, &signing_key, options, "recovered")
+    }
+
+    /// Save `signing_key` and its client ID as the keypair of `data_dir`
+    /// With `options.encrypt`, the key file is protected by a passphrase instead of
+    /// written in `options.format`
+    fn install_keypair(
+        data_dir: &Path,
+        signing_key: &ed25519_dalek::SigningKey,
+        options: KeySaveOptions,
+        action: &str,
+    ) -> Result<()> {
+        let KeySaveOptions {
+            force,
+            format,
+            key_store,
+            encrypt,
+        } = options;
+        if encrypt && key_store != KeyStore::File {
+            anyhow::bail!("--encrypt applies to the file key store only");
+        }
         fs::create_dir_all(data_dir).context("Failed to create client_data directory")?;
 
         let key_file = get_key_file_path(data_dir);
+        let current_store = load_key_store(data_dir)?;
+        let exists = key_file.exists() || current_store == KeyStore::Keyring;
 
-        if key_file.exists() && !force {
+        if exists && !force {
             anyhow::bail!(
-                "Keypair already exists at {:?}. Use --force to overwrite it.",
-                key_file
+                "Keypair already exists in {}. Use --force to overwrite it.",
+                Self::describe_location(data_dir, current_store)
             );
         }
 
+        // Ask before touching anything, so a mistyped passphrase leaves the old key in place
+        let passphrase = if encrypt {
+            Some(key_passphrase(true)?)
+        } else {
+            None
+        };
+
         // If force is true, remove existing keypair
-        if force && key_file.exists() {
-            Self::remove_existing_keypair(data_dir, &key_file)?;
+        if force && exists {
+            Self::remove_existing_keypair(data_dir, &key_file, current_store)?;
             info!("Removed existing keypair");
         }
 
-        // Generate new keypair
-        let (signing_key, verifying_key) = generate_keypair();
-        let client_id = compute_client_id(&verifying_key);
-
         // Save keypair and client ID
-        Self::save_keypair(&key_file, &signing_key, &verifying_key)?;
+        let client_id = compute_client_id(&signing_key.verifying_key());
+        match (key_store, passphrase) {
+            (KeyStore::File, Some(passphrase)) => {
+                save_encrypted_keypair(&key_file, signing_key, &passphrase)?
+            }
+            (KeyStore::File, None) => Self::save_keypair(&key_file, signing_key, format)?,
+            (KeyStore::Keyring, _) => Self::save_keypair_to_keyring(data_dir, signing_key)?,
+        }
         Self::save_client_id(data_dir, &client_id)?;
 
-        info!("Generated new keypair");
+        info!("Keypair {}", action);
         info!("Client ID: {}", client_id);
-        println!("✓ Keypair generated successfully");
+        println!("✓ Keypair {} successfully", action);
         println!("Client ID: {}", client_id);
-        println!("Keypair saved to: {:?}", key_file);
+        match key_store {
+            KeyStore::File if encrypt => {
+                println!("Keypair saved to: {:?} (passphrase-protected)", key_file)
+            }
+            KeyStore::File => println!("Keypair saved to: {:?} ({} format)", key_file, format),
+            KeyStore::Keyring => println!(
+                "Keypair saved to the OS keyring (service {:?})",
+                KEYRING_SERVICE
+            ),
+        }
 
-        if force {
+        if force && exists {
             println!("⚠️  Warning: Existing keypair was overwritten. You will need to re-register with the server.");
         }
 
@@ -51,16 +234,49 @@ impl KeypairManager {
     }
 
     /// Get or create keypair
+    /// A keypair kept in the OS keyring must already exist: it is never created here.
+    /// A passphrase-protected key file is unlocked with `CLIENT_KEY_PASSPHRASE` or a prompt
     pub fn get_or_create_keypair(data_dir: &Path) -> Result<(ed25519_dalek::SigningKey, String)> {
         fs::create_dir_all(data_dir).context("Failed to create client_data directory")?;
+        if load_key_store(data_dir)? == KeyStore::Keyring {
+            let signing_key = Self::load_keypair_from_keyring(data_dir)?;
+            let client_id = compute_client_id(&signing_key.verifying_key());
+            return Ok((signing_key, client_id));
+        }
         let key_file = get_key_file_path(data_dir);
+        if key_file.exists() {
+            let signing_key = load_key_file(&key_file, || key_passphrase(false))?;
+            let client_id = compute_client_id(&signing_key.verifying_key());
+            return Ok((signing_key, client_id));
+        }
         let (signing_key, _verifying_key, client_id) = crypto::load_or_generate_keypair(&key_file)?;
         Ok((signing_key, client_id))
     }
 
-    /// Remove existing keypair files
-    fn remove_existing_keypair(data_dir: &Path, key_file: &Path) -> Result<()> {
-        fs::remove_file(key_file)?;
+    /// Describe where the keypair of `data_dir` is kept, for messages
+    fn describe_location(data_dir: &Path, key_store: KeyStore) -> String {
+        match key_store {
+            KeyStore::File => format!("{:?}", get_key_file_path(data_dir)),
+            KeyStore::Keyring => format!("the OS keyring for {:?}", data_dir),
+        }
+    }
+
+    /// Remove existing keypair files (and the keyring entry, if the key is kept there)
+    fn remove_existing_keypair(
+        data_dir: &Path,
+        key_file: &Path,
+        key_store: KeyStore,
+    ) -> Result<()> {
+        if key_store == KeyStore::Keyring {
+            match keyring_entry(data_dir)?.delete_credential() {
+                Ok(()) | Err(keyring::Error::NoEntry) => {}
+                Err(e) => return Err(keyring_unavailable(e)),
+            }
+            fs::remove_file(data_dir.join(KEY_STORE_FILE))?;
+        }
+        if key_file.exists() {
+            fs::remove_file(key_file)?;
+        }
         let client_id_file = data_dir.join(CLIENT_ID_FILE);
         if client_id_file.exists() {
             fs::remove_file(&client_id_file)?;
@@ -68,24 +284,44 @@ impl KeypairManager {
         Ok(())
     }
 
-    /// Save keypair to file
+    /// Store the keypair in the OS keyring and record that the data directory uses it
+    fn save_keypair_to_keyring(
+        data_dir: &Path,
+        signing_key: &ed25519_dalek::SigningKey,
+    ) -> Result<()> {
+        keyring_entry(data_dir)?
+            .set_password(&encode_keypair(signing_key, KeyFormat::Hex))
+            .map_err(keyring_unavailable)?;
+        fs::write(data_dir.join(KEY_STORE_FILE), KeyStore::Keyring.to_string())
+            .with_context(|| format!("Failed to write {}", KEY_STORE_FILE))
+    }
+
+    /// Load the keypair of `data_dir` from the OS keyring
+    fn load_keypair_from_keyring(data_dir: &Path) -> Result<ed25519_dalek::SigningKey> {
+        let secret = match keyring_entry(data_dir)?.get_password() {
+            Ok(secret) => secret,
+            Err(keyring::Error::NoEntry) => anyhow::bail!(
+                "No keypair for {:?} in the OS keyring; run generate-keypair --key-store keyring --force",
+                data_dir
+            ),
+            Err(e) => return Err(keyring_unavailable(e)),
+        };
+        decode_keypair(&secret).context("Invalid keypair in the OS keyring")
+    }
+
+    /// Save keypair to file in the given format
     fn save_keypair(
         key_file: &Path,
         signing_key: &ed25519_dalek::SigningKey,
-        verifying_key: &ed25519_dalek::VerifyingKey,
+        format: KeyFormat,
     ) -> Result<()> {
-        // Save keypair (64 bytes: 32-byte secret key + 32-byte public key)
-        let mut key_data = Vec::with_capacity(64);
-        key_data.extend_from_slice(signing_key.as_bytes());
-        key_data.extend_from_slice(verifying_key.to_bytes().as_slice());
-
         // Ensure directory exists
         if let Some(parent) = key_file.parent() {
             fs::create_dir_all(parent)?;
         }
 
         // Write keypair
-        fs::write(key_file, hex::encode(key_data))?;
+        fs::write(key_file, encode_keypair(signing_key, format))?;
         Ok(())
     }
 
@@ -101,11 +337,133 @@ impl KeypairManager {
 }
 
 /// Generate keypair command (convenience function)
-pub fn generate_keypair_command(data_dir: &Path, force: bool) -> Result<()> {
-    KeypairManager::generate_keypair(data_dir, force)
+pub fn generate_keypair_command(
+    data_dir: &Path,
+    options: KeySaveOptions,
+    mnemonic: bool,
+) -> Result<()> {
+    KeypairManager::generate_keypair(data_dir, options, mnemonic)
+}
+
+/// Recover keypair command (convenience function)
+/// The phrase is prompted for (without echo) when not given
+pub fn recover_keypair_command(
+    data_dir: &Path,
+    phrase: Option<&str>,
+    options: KeySaveOptions,
+) -> Result<()> {
+    let phrase = match phrase {
+        Some(phrase) => phrase.to_string(),
+        None => rpassword::prompt_password("Recovery phrase: ")
+            .context("Failed to read recovery phrase; pass it with --phrase")?,
+    };
+    KeypairManager::recover_keypair(data_dir, &phrase, options)
 }
 
 /// Get or create keypair (convenience function)
 pub fn get_or_create_keypair(data_dir: &Path) -> Result<(ed25519_dalek::SigningKey, String)> {
     KeypairManager::get_or_create_keypair(data_dir)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether this machine has a usable OS keyring (CI containers usually do not)
+    fn keyring_available(data_dir: &Path) -> bool {
+        let Ok(entry) = keyring_entry(data_dir) else {
+            return false;
+        };
+        let available = entry.set_password("probe").is_ok();
+        let _ = entry.delete_credential();
+        available
+    }
+
+    #[test]
+    fn test_keypair_in_keyring_is_not_written_to_disk() {
+        let data_dir = std::env::temp_dir().join(format!("vs-keyring-{}", std::process::id()));
+        fs::create_dir_all(&data_dir).unwrap();
+        if !keyring_available(&data_dir) {
+            eprintln!("Skipping: no OS keyring available");
+            fs::remove_dir_all(&data_dir).ok();
+            return;
+        }
+
+        let keyring = KeySaveOptions {
+            key_store: KeyStore::Keyring,
+            ..KeySaveOptions::default()
+        };
+        KeypairManager::generate_keypair(&data_dir, keyring, false).unwrap();
+        assert!(!get_key_file_path(&data_dir).exists());
+        assert_eq!(load_key_store(&data_dir).unwrap(), KeyStore::Keyring);
+        let (_, client_id) = KeypairManager::get_or_create_keypair(&data_dir).unwrap();
+        assert_eq!(
+            fs::read_to_string(data_dir.join(CLIENT_ID_FILE)).unwrap(),
+            client_id
+        );
+        assert!(
+            KeypairManager::generate_keypair(&data_dir, KeySaveOptions::default(), false).is_err()
+        );
+
+        // Switching back to the file store removes the keyring entry
+        let force = KeySaveOptions {
+            force: true,
+            ..KeySaveOptions::default()
+        };
+        KeypairManager::generate_keypair(&data_dir, force, false).unwrap();
+        assert_eq!(load_key_store(&data_dir).unwrap(), KeyStore::File);
+        assert!(matches!(
+            keyring_entry(&data_dir).unwrap().get_password(),
+            Err(keyring::Error::NoEntry)
+        ));
+        assert!(get_key_file_path(&data_dir).exists());
+
+        fs::remove_dir_all(&data_dir).ok();
+    }
+
+    #[test]
+    fn test_protected_key_file_is_detected_and_unlocked() {
+        let dir = std::env::temp_dir().join(format!("vs-protected-key-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let key_file = get_key_file_path(&dir);
+        let (signing_key, _) = generate_keypair();
+
+        save_encrypted_keypair(&key_file, &signing_key, "secret").unwrap();
+        let loaded = load_key_file(&key_file, || Ok("secret".to_string())).unwrap();
+        assert_eq!(loaded.to_bytes(), signing_key.to_bytes());
+        assert!(load_key_file(&key_file, || Ok("wrong".to_string())).is_err());
+
+        // Plaintext key files load without asking for a passphrase
+        fs::write(&key_file, encode_keypair(&signing_key, KeyFormat::Hex)).unwrap();
+        let loaded = load_key_file(&key_file, || panic!("passphrase requested")).unwrap();
+        assert_eq!(loaded.to_bytes(), signing_key.to_bytes());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_recovered_keypair_matches_generated_one() {
+        let dir = std::env::temp_dir().join(format!("vs-recover-key-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        let phrase = generate_mnemonic();
+        let (_, verifying_key) = keypair_from_mnemonic(&phrase, "").unwrap();
+
+        KeypairManager::recover_keypair(&dir, &phrase, KeySaveOptions::default()).unwrap();
+        let (_, client_id) = KeypairManager::get_or_create_keypair(&dir).unwrap();
+        assert_eq!(client_id, compute_client_id(&verifying_key));
+
+        // Recovering over an existing key needs --force, and yields the same key again
+        assert!(KeypairManager::recover_keypair(&dir, &phrase, KeySaveOptions::default()).is_err());
+        let force = KeySaveOptions {
+            force: true,
+            ..KeySaveOptions::default()
+        };
+        KeypairManager::recover_keypair(&dir, &phrase, force).unwrap();
+        assert_eq!(
+            KeypairManager::get_or_create_keypair(&dir).unwrap().1,
+            client_id
+        );
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,13 +1,26 @@
+mod batch;
+mod batch_download;
+mod bundle;
+mod clock;
 mod config;
 mod constants;
+mod diff;
 mod download;
 mod keypair;
 mod logger;
+mod proofs;
+mod recheck;
+mod replace;
+mod root_source;
 mod upload;
+mod verify;
 
 use clap::{Parser, Subcommand};
 use config::ClientConfig;
-use keypair::{generate_keypair_command, get_or_create_keypair};
+use constants::DEFAULT_MAX_DOWNLOAD_SIZE_BYTES;
+use keypair::{
+    generate_keypair_command, get_or_create_keypair, recover_keypair_command, KeySaveOptions,
+};
 use logger::init as init_logger;
 use std::fs;
 use std::path::PathBuf;
@@ -27,6 +40,39 @@ enum Commands {
         /// Force generation even if keypair already exists
         #[arg(short, long)]
         force: bool,
+        /// Key file format: hex, base64 or json (any format is detected when loading)
+        #[arg(long, default_value = "hex")]
+        key_format: crypto::KeyFormat,
+        /// Where to keep the secret key: file (keypair.txt, default) or keyring (the OS
+        /// credential store; fails if none is available instead of falling back to a file)
+        #[arg(long, default_value = "file")]
+        key_store: keypair::KeyStore,
+        /// Protect keypair.txt with a passphrase (prompted for, or taken from
+        /// CLIENT_KEY_PASSPHRASE); --key-format is then ignored
+        #[arg(long)]
+        encrypt: bool,
+        /// Derive the key from a new recovery phrase and print it; recover-keypair
+        /// rebuilds the same key from the phrase
+        #[arg(long)]
+        mnemonic: bool,
+    },
+    /// Rebuild the keypair from a recovery phrase printed by generate-keypair --mnemonic
+    RecoverKeypair {
+        /// Recovery phrase; prompted for (without echo) when omitted
+        #[arg(long)]
+        phrase: Option<String>,
+        /// Replace an existing keypair
+        #[arg(short, long)]
+        force: bool,
+        /// Key file format: hex, base64 or json
+        #[arg(long, default_value = "hex")]
+        key_format: crypto::KeyFormat,
+        /// Where to keep the secret key: file (keypair.txt, default) or keyring
+        #[arg(long, default_value = "file")]
+        key_store: keypair::KeyStore,
+        /// Protect keypair.txt with a passphrase
+        #[arg(long)]
+        encrypt: bool,
     },
     /// Upload files to server
     Upload {
@@ -37,8 +83,45 @@ enum Commands {
         #[arg(short, long)]
         server: Option<String>,
         /// Batch ID for this upload (all files in this upload belong to the same batch)
+        /// A unique ID is generated and printed when omitted
         #[arg(short, long)]
-        batch_id: String,
+        batch_id: Option<String>,
+        /// Make the batch publicly readable (files are uploaded unencrypted)
+        #[arg(long)]
+        public: bool,
+        /// Annotate the batch with key=value (repeatable; replaces existing annotations)
+        #[arg(long = "annotate", value_name = "KEY=VALUE", value_parser = upload::parse_annotation)]
+        annotations: Vec<(String, String)>,
+        /// Only send files whose content differs from what the server holds for the batch
+        /// (the root still covers every file)
+        #[arg(long)]
+        skip_unchanged: bool,
+        /// Width in bytes the server truncates Merkle node hashes to; must match the
+        /// server's --hash-truncation-bytes (full 32-byte hashes by default)
+        #[arg(long, value_name = "BYTES", value_parser = download::parse_hash_truncation_bytes)]
+        hash_truncation_bytes: Option<usize>,
+    },
+    /// Upload bytes read from stdin as a single file
+    UploadStdin {
+        /// Name to store the file under
+        #[arg(short, long)]
+        filename: String,
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+        /// Batch ID for this upload (a unique ID is generated and printed when omitted)
+        #[arg(short, long)]
+        batch_id: Option<String>,
+        /// Make the batch publicly readable (the file is uploaded unencrypted)
+        #[arg(long)]
+        public: bool,
+        /// Annotate the batch with key=value (repeatable; replaces existing annotations)
+        #[arg(long = "annotate", value_name = "KEY=VALUE", value_parser = upload::parse_annotation)]
+        annotations: Vec<(String, String)>,
+        /// Width in bytes the server truncates Merkle node hashes to; must match the
+        /// server's --hash-truncation-bytes (full 32-byte hashes by default)
+        #[arg(long, value_name = "BYTES", value_parser = download::parse_hash_truncation_bytes)]
+        hash_truncation_bytes: Option<usize>,
     },
     /// Download and verify a file from server
     Download {
@@ -53,9 +136,281 @@ enum Commands {
         /// Root hash to verify against (if not provided, loads from client_data/{batch_id}/root_hash.txt)
         #[arg(short, long)]
         root_hash: Option<String>,
+        /// File path or http(s) URL holding the root to verify against, independent of the
+        /// download server: a bare hex root or a JSON object mapping batch IDs to roots
+        #[arg(long, value_name = "PATH_OR_URL", conflicts_with = "root_hash")]
+        root_source: Option<String>,
         /// Output directory for downloaded file (default: client_data/{batch_id}/downloaded/)
         #[arg(short, long)]
         output_dir: Option<PathBuf>,
+        /// Download from a public batch anonymously (no signature, no decryption)
+        #[arg(long)]
+        public: bool,
+        /// Client ID of the batch owner (defaults to this client)
+        /// Without --public, reads a batch the owner shared with this client via grant-access
+        #[arg(long)]
+        owner: Option<String>,
+        /// Save the file WITHOUT checking its Merkle proof (debugging a broken server tree only)
+        #[arg(long, conflicts_with_all = ["root_hash", "root_source"])]
+        insecure_skip_proof_verification: bool,
+        /// Permissions of the written file as octal, e.g. 0600 (Unix only; default: umask)
+        #[arg(long, value_name = "OCTAL", value_parser = download::parse_file_mode)]
+        file_mode: Option<u32>,
+        /// Replace the output file if it already exists (by default the download is refused)
+        #[arg(long)]
+        overwrite: bool,
+        /// Width in bytes the server truncates Merkle node hashes to; must match the
+        /// server's --hash-truncation-bytes (full 32-byte hashes by default)
+        #[arg(long, value_name = "BYTES", value_parser = download::parse_hash_truncation_bytes)]
+        hash_truncation_bytes: Option<usize>,
+        /// Largest file to accept from the server, in bytes; larger responses are aborted
+        #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_DOWNLOAD_SIZE_BYTES)]
+        max_download_size: u64,
+    },
+    /// Fetch and verify the Merkle proofs of several files of a batch without downloading them
+    GetProofs {
+        /// Batch ID the files belong to
+        #[arg(short, long)]
+        batch_id: String,
+        /// Comma-separated filenames to prove
+        #[arg(short, long, value_delimiter = ',', required = true)]
+        files: Vec<String>,
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+        /// Root hash to verify against (if not provided, loads from client_data/{batch_id}/root_hash.txt)
+        #[arg(short, long)]
+        root_hash: Option<String>,
+        /// Client ID of the batch owner (defaults to this client); the batch must be shared
+        /// with this client via grant-access
+        #[arg(long)]
+        owner: Option<String>,
+        /// Width in bytes the server truncates Merkle node hashes to; must match the
+        /// server's --hash-truncation-bytes (full 32-byte hashes by default)
+        #[arg(long, value_name = "BYTES", value_parser = download::parse_hash_truncation_bytes)]
+        hash_truncation_bytes: Option<usize>,
+    },
+    /// Download and verify every file of one of your batches, resuming an interrupted run
+    DownloadBatch {
+        /// Batch ID to download
+        #[arg(short, long)]
+        batch_id: String,
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+        /// Root hash to verify against (if not provided, loads from client_data/{batch_id}/root_hash.txt)
+        #[arg(short, long)]
+        root_hash: Option<String>,
+        /// Output directory for downloaded files (default: client_data/{batch_id}/downloaded/)
+        #[arg(short, long)]
+        output_dir: Option<PathBuf>,
+        /// Permissions of the written files as octal, e.g. 0600 (Unix only; default: umask)
+        #[arg(long, value_name = "OCTAL", value_parser = download::parse_file_mode)]
+        file_mode: Option<u32>,
+        /// Width in bytes the server truncates Merkle node hashes to; must match the
+        /// server's --hash-truncation-bytes (full 32-byte hashes by default)
+        #[arg(long, value_name = "BYTES", value_parser = download::parse_hash_truncation_bytes)]
+        hash_truncation_bytes: Option<usize>,
+        /// Largest file to accept from the server, in bytes; larger responses are aborted
+        #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_DOWNLOAD_SIZE_BYTES)]
+        max_download_size: u64,
+    },
+    /// Create an empty batch on the server to upload files into later
+    CreateBatch {
+        /// Batch ID to create
+        #[arg(short, long)]
+        batch_id: String,
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+    },
+    /// Rename a batch on the server and locally (root hash is unchanged)
+    RenameBatch {
+        /// Current batch ID
+        #[arg(short, long)]
+        batch_id: String,
+        /// New batch ID
+        #[arg(short, long)]
+        new_batch_id: String,
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+    },
+    /// Delete a batch and all its files from the server (local data is kept)
+    DeleteBatch {
+        /// Batch ID to delete
+        #[arg(short, long)]
+        batch_id: String,
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+    },
+    /// Delete one file from a batch on the server and store the batch's new root hash
+    DeleteFile {
+        /// Filename to delete
+        filename: String,
+        /// Batch ID
+        #[arg(short, long)]
+        batch_id: String,
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+        /// Width in bytes the server truncates Merkle node hashes to; must match the
+        /// server's --hash-truncation-bytes (full 32-byte hashes by default)
+        #[arg(long, value_name = "BYTES", value_parser = download::parse_hash_truncation_bytes)]
+        hash_truncation_bytes: Option<usize>,
+    },
+    /// Replace a batch's entire file set with the files of a directory, atomically
+    /// (changed and new files are sent, files missing from the directory are removed)
+    ReplaceBatch {
+        /// Directory holding the batch's new file set
+        #[arg(short, long)]
+        dir: PathBuf,
+        /// Batch ID
+        #[arg(short, long)]
+        batch_id: String,
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+        /// Width in bytes the server truncates Merkle node hashes to; must match the
+        /// server's --hash-truncation-bytes (full 32-byte hashes by default)
+        #[arg(long, value_name = "BYTES", value_parser = download::parse_hash_truncation_bytes)]
+        hash_truncation_bytes: Option<usize>,
+    },
+    /// Fetch a batch's Merkle root from the server and save it for later downloads
+    FetchRoot {
+        /// Batch ID
+        #[arg(short, long)]
+        batch_id: String,
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+        /// Client ID of the batch owner (defaults to this client)
+        #[arg(long)]
+        owner: Option<String>,
+        /// Read a public batch anonymously (no signature)
+        #[arg(long)]
+        public: bool,
+        /// Overwrite a local root hash that differs from the server's
+        #[arg(long)]
+        force: bool,
+    },
+    /// Restore a batch's local filenames.json and root_hash.txt from the server's listing
+    FetchMetadata {
+        /// Batch ID
+        #[arg(short, long)]
+        batch_id: String,
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+        /// Width in bytes the server truncates Merkle node hashes to; must match the
+        /// server's --hash-truncation-bytes (full 32-byte hashes by default)
+        #[arg(long, value_name = "BYTES", value_parser = download::parse_hash_truncation_bytes)]
+        hash_truncation_bytes: Option<usize>,
+        /// Overwrite a local root hash that differs from the server's
+        #[arg(long)]
+        force: bool,
+    },
+    /// Let another client read one of your batches
+    GrantAccess {
+        /// Batch ID to share
+        #[arg(short, long)]
+        batch_id: String,
+        /// Client ID to grant read access to
+        #[arg(short, long)]
+        grantee: String,
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+    },
+    /// Revoke a client's read access to one of your batches
+    RevokeAccess {
+        /// Batch ID to stop sharing
+        #[arg(short, long)]
+        batch_id: String,
+        /// Client ID to revoke read access from
+        #[arg(short, long)]
+        grantee: String,
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+    },
+    /// List the IDs of this client's batches on the server
+    ListBatches {
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+    },
+    /// Show a batch's files, root hash and annotations as stored on the server
+    ShowBatch {
+        /// Batch ID
+        #[arg(short, long)]
+        batch_id: String,
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+    },
+    /// Show what changed between a local directory and a batch on the server
+    Diff {
+        /// Local directory to compare
+        #[arg(short, long)]
+        dir: PathBuf,
+        /// Batch ID on the server
+        #[arg(short, long)]
+        batch_id: String,
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+        /// Print the diff as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Download and verify every file of a batch into a self-contained bundle directory
+    ExportBundle {
+        /// Batch ID to export
+        #[arg(short, long)]
+        batch_id: String,
+        /// Bundle directory to create
+        #[arg(short, long)]
+        out: PathBuf,
+        /// Root hash to verify against (if not provided, loads from client_data/{batch_id}/root_hash.txt)
+        #[arg(short, long)]
+        root_hash: Option<String>,
+        /// Server URL (defaults to CLIENT_SERVER_URL env var or http://127.0.0.1:8080)
+        #[arg(short, long)]
+        server: Option<String>,
+    },
+    /// Verify an exported bundle offline, without a server
+    VerifyBundle {
+        /// Bundle directory written by export-bundle
+        bundle: PathBuf,
+        /// Also require the bundle to commit to this root hash
+        #[arg(short, long)]
+        root_hash: Option<String>,
+    },
+    /// Recheck downloaded files against the proofs saved at download time
+    Recheck {
+        /// Batch ID whose downloads (client_data/{batch_id}/downloaded/) to recheck
+        #[arg(short, long)]
+        batch_id: String,
+        /// Root hash to verify against (if not provided, loads from client_data/{batch_id}/root_hash.txt)
+        #[arg(short, long)]
+        root_hash: Option<String>,
+    },
+    /// Verify offline that a local directory is exactly the batch committed to by a root hash
+    VerifyBatchLocal {
+        /// Directory containing the batch's original files
+        #[arg(short, long)]
+        dir: PathBuf,
+        /// Root hash to verify against
+        #[arg(short, long)]
+        root_hash: String,
+        /// Batch ID the files were uploaded under (needed to reproduce encryption)
+        #[arg(short, long, required_unless_present = "public")]
+        batch_id: Option<String>,
+        /// Files were uploaded to a public batch (unencrypted)
+        #[arg(long, conflicts_with = "batch_id")]
+        public: bool,
     },
 }
 
@@ -65,8 +420,44 @@ fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
     let config = ClientConfig::load();
 
-    if let Commands::GenerateKeypair { force } = &cli.command {
-        return generate_keypair_command(&config.data_dir, *force);
+    if let Commands::GenerateKeypair {
+        force,
+        key_format,
+        key_store,
+        encrypt,
+        mnemonic,
+    } = &cli.command
+    {
+        let options = KeySaveOptions {
+            force: *force,
+            format: *key_format,
+            key_store: *key_store,
+            encrypt: *encrypt,
+        };
+        return generate_keypair_command(&config.data_dir, options, *mnemonic);
+    }
+
+    if let Commands::RecoverKeypair {
+        phrase,
+        force,
+        key_format,
+        key_store,
+        encrypt,
+    } = &cli.command
+    {
+        let options = KeySaveOptions {
+            force: *force,
+            format: *key_format,
+            key_store: *key_store,
+            encrypt: *encrypt,
+        };
+        return recover_keypair_command(&config.data_dir, phrase.as_deref(), options);
+    }
+
+    // Bundles are verified with the key recorded in them; no local keypair needed
+    if let Commands::VerifyBundle { bundle, root_hash } = &cli.command {
+        bundle::verify_bundle(bundle, root_hash.as_deref())?;
+        return Ok(());
     }
 
     use crate::constants::CLIENT_ID_FILE;
@@ -82,36 +473,378 @@ fn main() -> anyhow::Result<()> {
         Commands::GenerateKeypair { .. } => {
             unreachable!("GenerateKeypair should have been handled earlier")
         }
+        Commands::RecoverKeypair { .. } => {
+            unreachable!("RecoverKeypair should have been handled earlier")
+        }
+        Commands::VerifyBundle { .. } => {
+            unreachable!("VerifyBundle should have been handled earlier")
+        }
         Commands::Upload {
             dir,
             server,
             batch_id,
+            public,
+            annotations,
+            skip_unchanged,
+            hash_truncation_bytes,
         } => {
             let server_url = config.get_server_url(server.as_deref());
-            upload::upload_files(&dir, &server_url, &batch_id, &signing_key, &config.data_dir)?;
+            let batch_id = resolve_batch_id(batch_id);
+            upload::upload_files(
+                &dir,
+                &server_url,
+                &batch_id,
+                &signing_key,
+                &config.data_dir,
+                upload::UploadOptions {
+                    skip_unchanged,
+                    ..upload_options(public, annotations, hash_truncation_bytes)
+                },
+            )?;
+        }
+        Commands::UploadStdin {
+            filename,
+            server,
+            batch_id,
+            public,
+            annotations,
+            hash_truncation_bytes,
+        } => {
+            let server_url = config.get_server_url(server.as_deref());
+            let batch_id = resolve_batch_id(batch_id);
+            upload::upload_from_reader(
+                std::io::stdin().lock(),
+                &filename,
+                &server_url,
+                &batch_id,
+                &signing_key,
+                &config.data_dir,
+                upload_options(public, annotations, hash_truncation_bytes),
+            )?;
         }
         Commands::Download {
             filename,
             batch_id,
             server,
             root_hash,
+            root_source,
             output_dir,
+            public,
+            owner,
+            insecure_skip_proof_verification,
+            file_mode,
+            overwrite,
+            hash_truncation_bytes,
+            max_download_size,
         } => {
             let server_url = config.get_server_url(server.as_deref());
-            let root_hash = root_hash.unwrap_or_else(|| {
-                download::load_root_hash(&batch_id, &config.data_dir)
-                    .expect("Failed to load root hash")
-            });
+            let root_hash = match (root_hash, root_source) {
+                _ if insecure_skip_proof_verification => None,
+                (Some(root_hash), _) => Some(root_hash),
+                (None, Some(source)) => {
+                    Some(root_source::load_root_from_source(&source, &batch_id)?)
+                }
+                (None, None) => Some(
+                    download::load_root_hash(&batch_id, &config.data_dir)
+                        .expect("Failed to load root hash"),
+                ),
+            };
             let download_config = download::DownloadConfig {
                 server: server_url,
                 batch_id,
                 signing_key: signing_key.clone(),
+                client_id: owner.unwrap_or_else(|| client_id.clone()),
+                requester_id: client_id.clone(),
+                data_dir: config.data_dir.clone(),
+                public,
+                file_mode,
+                overwrite,
+                hash_truncation_bytes,
+                max_download_size,
+            };
+            download::download_file(
+                &download_config,
+                &filename,
+                root_hash.as_deref(),
+                output_dir.as_ref(),
+            )?;
+        }
+        Commands::GetProofs {
+            batch_id,
+            files,
+            server,
+            root_hash,
+            owner,
+            hash_truncation_bytes,
+        } => {
+            let server_url = config.get_server_url(server.as_deref());
+            let root_hash = match root_hash {
+                Some(root_hash) => root_hash,
+                None => download::load_root_hash(&batch_id, &config.data_dir)?,
+            };
+            let owner_id = owner.unwrap_or_else(|| client_id.clone());
+            proofs::get_proofs(
+                &proofs::GetProofsConfig {
+                    server: &server_url,
+                    batch_id: &batch_id,
+                    owner_id: &owner_id,
+                    client_id: &client_id,
+                    signing_key: &signing_key,
+                    hash_truncation_bytes,
+                },
+                &files,
+                &root_hash,
+                &config.data_dir,
+            )?;
+        }
+        Commands::DownloadBatch {
+            batch_id,
+            server,
+            root_hash,
+            output_dir,
+            file_mode,
+            hash_truncation_bytes,
+            max_download_size,
+        } => {
+            let download_config = download::DownloadConfig {
+                server: config.get_server_url(server.as_deref()),
+                batch_id,
+                signing_key: signing_key.clone(),
                 client_id: client_id.clone(),
+                requester_id: client_id.clone(),
                 data_dir: config.data_dir.clone(),
+                public: false,
+                file_mode,
+                overwrite: true,
+                hash_truncation_bytes,
+                max_download_size,
             };
-            download::download_file(&download_config, &filename, &root_hash, output_dir.as_ref())?;
+            batch_download::download_batch(
+                &download_config,
+                root_hash.as_deref(),
+                output_dir.as_ref(),
+            )?;
+        }
+        Commands::CreateBatch { batch_id, server } => {
+            let server_url = config.get_server_url(server.as_deref());
+            batch::create_batch(&server_url, &batch_id, &signing_key)?;
+        }
+        Commands::RenameBatch {
+            batch_id,
+            new_batch_id,
+            server,
+        } => {
+            let server_url = config.get_server_url(server.as_deref());
+            batch::rename_batch(
+                &server_url,
+                &batch_id,
+                &new_batch_id,
+                &signing_key,
+                &client_id,
+                &config.data_dir,
+            )?;
+        }
+        Commands::DeleteBatch { batch_id, server } => {
+            let server_url = config.get_server_url(server.as_deref());
+            batch::delete_batch(&server_url, &batch_id, &signing_key, &client_id)?;
+        }
+        Commands::DeleteFile {
+            filename,
+            batch_id,
+            server,
+            hash_truncation_bytes,
+        } => {
+            let server_url = config.get_server_url(server.as_deref());
+            batch::delete_file(
+                &server_url,
+                &batch_id,
+                &filename,
+                &signing_key,
+                &client_id,
+                hash_truncation_bytes,
+                &config.data_dir,
+            )?;
+        }
+        Commands::ReplaceBatch {
+            dir,
+            batch_id,
+            server,
+            hash_truncation_bytes,
+        } => {
+            let server_url = config.get_server_url(server.as_deref());
+            replace::replace_batch(
+                &replace::ReplaceBatchConfig {
+                    server: &server_url,
+                    batch_id: &batch_id,
+                    client_id: &client_id,
+                    signing_key: &signing_key,
+                    hash_truncation_bytes,
+                },
+                &dir,
+                &config.data_dir,
+            )?;
+        }
+        Commands::FetchRoot {
+            batch_id,
+            server,
+            owner,
+            public,
+            force,
+        } => {
+            let server_url = config.get_server_url(server.as_deref());
+            let owner_id = owner.unwrap_or_else(|| client_id.clone());
+            batch::fetch_root(
+                &batch::FetchRootConfig {
+                    server: &server_url,
+                    batch_id: &batch_id,
+                    owner_id: &owner_id,
+                    client_id: &client_id,
+                    signing_key: &signing_key,
+                    public,
+                    force,
+                },
+                &config.data_dir,
+            )?;
+        }
+        Commands::FetchMetadata {
+            batch_id,
+            server,
+            hash_truncation_bytes,
+            force,
+        } => {
+            let server_url = config.get_server_url(server.as_deref());
+            batch::fetch_metadata(
+                &server_url,
+                &batch_id,
+                &signing_key,
+                &client_id,
+                hash_truncation_bytes,
+                force,
+                &config.data_dir,
+            )?;
+        }
+        Commands::GrantAccess {
+            batch_id,
+            grantee,
+            server,
+        } => {
+            let server_url = config.get_server_url(server.as_deref());
+            batch::update_access(
+                &server_url,
+                &batch_id,
+                &grantee,
+                true,
+                &signing_key,
+                &client_id,
+            )?;
+        }
+        Commands::RevokeAccess {
+            batch_id,
+            grantee,
+            server,
+        } => {
+            let server_url = config.get_server_url(server.as_deref());
+            batch::update_access(
+                &server_url,
+                &batch_id,
+                &grantee,
+                false,
+                &signing_key,
+                &client_id,
+            )?;
+        }
+        Commands::ListBatches { server } => {
+            let server_url = config.get_server_url(server.as_deref());
+            batch::list_batches(&server_url, &signing_key, &client_id)?;
+        }
+        Commands::ShowBatch { batch_id, server } => {
+            let server_url = config.get_server_url(server.as_deref());
+            batch::show_batch(&server_url, &batch_id, &signing_key, &client_id)?;
+        }
+        Commands::Diff {
+            dir,
+            batch_id,
+            server,
+            json,
+        } => {
+            let server_url = config.get_server_url(server.as_deref());
+            diff::diff_batch(
+                &dir,
+                &server_url,
+                &batch_id,
+                &signing_key,
+                &client_id,
+                &config.data_dir,
+                json,
+            )?;
+        }
+        Commands::ExportBundle {
+            batch_id,
+            out,
+            root_hash,
+            server,
+        } => {
+            let server_url = config.get_server_url(server.as_deref());
+            bundle::export_bundle(
+                &server_url,
+                &batch_id,
+                &signing_key,
+                &client_id,
+                &config.data_dir,
+                root_hash.as_deref(),
+                &out,
+            )?;
+        }
+        Commands::Recheck {
+            batch_id,
+            root_hash,
+        } => {
+            let results = recheck::recheck_downloads(
+                &batch_id,
+                &config.data_dir,
+                &signing_key,
+                root_hash.as_deref(),
+            )?;
+            recheck::report(&batch_id, &results)?;
+        }
+        Commands::VerifyBatchLocal {
+            dir,
+            root_hash,
+            batch_id,
+            public: _,
+        } => {
+            verify::verify_batch_local(
+                &dir,
+                &root_hash,
+                &signing_key,
+                batch_id.as_deref(),
+                &config.data_dir,
+            )?;
         }
     }
 
     Ok(())
 }
+
+/// Batch options of the upload commands; no --annotate flags leaves annotations untouched
+fn upload_options(
+    public: bool,
+    annotations: Vec<(String, String)>,
+    hash_truncation_bytes: Option<usize>,
+) -> upload::UploadOptions {
+    upload::UploadOptions {
+        public,
+        annotations: (!annotations.is_empty()).then(|| annotations.into_iter().collect()),
+        skip_unchanged: false,
+        hash_truncation_bytes,
+    }
+}
+
+/// Use the given batch ID, or generate one and report it so the batch can be found later
+fn resolve_batch_id(batch_id: Option<String>) -> String {
+    batch_id.unwrap_or_else(|| {
+        let batch_id = upload::generate_batch_id();
+        println!("Generated batch ID: {}", batch_id);
+        batch_id
+    })
+}
@@ -0,0 +1,190 @@
+use crate::clock::warn_on_clock_skew;
+use crate::constants::PROOFS_ENDPOINT;
+use crate::download::{compute_proof_root, load_hash_algorithm, proof_nodes_from_json};
+use anyhow::{Context, Result};
+use common::auth_message::proofs_message;
+use common::utils::get_current_timestamp_ms;
+use common::{file_utils, FileProofJson, ProofsRequest, ProofsResponse, PROOF_FORMAT_VERSION};
+use crypto::sign_message;
+use ed25519_dalek::SigningKey;
+use merkle_tree::{decode_hash, encode_hash, MerkleProof};
+use reqwest::blocking::Client;
+use std::path::Path;
+
+/// Where to fetch proofs from and how to authenticate
+pub struct GetProofsConfig<'a> {
+    /// Server URL
+    pub server: &'a str,
+    /// Batch ID
+    pub batch_id: &'a str,
+    /// Client ID of the batch owner
+    pub owner_id: &'a str,
+    /// This client's ID; differs from `owner_id` for batches shared with us
+    pub client_id: &'a str,
+    /// Signing key for authentication
+    pub signing_key: &'a SigningKey,
+    /// Width in bytes the server truncates node hashes to, if any
+    pub hash_truncation_bytes: Option<usize>,
+}
+
+/// Fetch the proofs of several files of a batch in one request and verify each
+/// against `root_hash`, without downloading the files
+/// Files the batch does not hold are reported; the command fails if any file is
+/// missing or any proof does not lead to the root.
+pub fn get_proofs(
+    config: &GetProofsConfig,
+    filenames: &[String],
+    root_hash: &str,
+    data_dir: &Path,
+) -> Result<()> {
+    for filename in filenames {
+        file_utils::validate_filename(filename)
+            .map_err(|e| anyhow::anyhow!("Invalid filename {}: {}", filename, e.message()))?;
+    }
+    let expected_root = decode_hash(root_hash.trim(), config.hash_truncation_bytes)
+        .context("Failed to decode root_hash")?;
+
+    let shared = config.client_id != config.owner_id;
+    let timestamp = get_current_timestamp_ms();
+    let message = proofs_message(
+        config.batch_id,
+        filenames,
+        timestamp,
+        shared.then_some(config.owner_id),
+    );
+    let signature = sign_message(config.signing_key, &message);
+    let request = ProofsRequest {
+        batch_id: config.batch_id.to_string(),
+        filenames: filenames.to_vec(),
+        signature: Some(hex::encode(signature.to_bytes())),
+        timestamp: Some(timestamp),
+        client_id: config.owner_id.to_string(),
+        // Lets the server register us if we never uploaded anything
+        requester_id: shared.then(|| config.client_id.to_string()),
+        requester_public_key: shared
+            .then(|| hex::encode(config.signing_key.verifying_key().as_bytes())),
+    };
+
+    let url = format!("{}{}", config.server, PROOFS_ENDPOINT);
+    let response = Client::new()
+        .post(&url)
+        .json(&request)
+        .send()
+        .context("Failed to connect to server")?;
+
+    let status = response.status();
+    if !status.is_success() {
+        warn_on_clock_skew(&response);
+        let error_text = response
+            .text()
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        anyhow::bail!("Fetching proofs failed: {} - {}", status, error_text);
+    }
+    let result: ProofsResponse = response.json()?;
+
+    anyhow::ensure!(
+        result.proof_version == PROOF_FORMAT_VERSION,
+        "Unsupported proof version {} (this client supports {})",
+        result.proof_version,
+        PROOF_FORMAT_VERSION
+    );
+    let expected_algorithm = load_hash_algorithm(config.batch_id, data_dir)?;
+    anyhow::ensure!(
+        result.hash_algorithm == expected_algorithm,
+        "Hash algorithm mismatch: expected {}, server used {}",
+        expected_algorithm,
+        result.hash_algorithm
+    );
+    anyhow::ensure!(
+        result.hash_truncation_bytes == config.hash_truncation_bytes,
+        "Hash truncation mismatch: expected {:?}, server used {:?}",
+        config.hash_truncation_bytes,
+        result.hash_truncation_bytes
+    );
+
+    println!("Batch: {}", result.batch_id);
+    println!(
+        "Expected root: {}",
+        encode_hash(&expected_root, config.hash_truncation_bytes)
+    );
+    let mut failed = 0;
+    for (filename, proof) in &result.proofs {
+        match verify_file_proof(proof, &expected_root, config.hash_truncation_bytes) {
+            Ok(()) => println!(
+                "  ✓ {} (leaf {}, {})",
+                filename, proof.leaf_index, proof.file_hash
+            ),
+            Err(e) => {
+                failed += 1;
+                println!("  ✗ {}: {:#}", filename, e);
+            }
+        }
+    }
+    for filename in &result.missing {
+        println!("  ✗ {}: not in batch", filename);
+    }
+
+    anyhow::ensure!(
+        failed == 0 && result.missing.is_empty(),
+        "{} proofs failed verification, {} files not in batch",
+        failed,
+        result.missing.len()
+    );
+    println!("✓ Verified {} proofs against the root", result.proofs.len());
+    Ok(())
+}
+
+/// Check that a file's proof leads from its hash to `expected_root`
+fn verify_file_proof(
+    proof: &FileProofJson,
+    expected_root: &[u8; 32],
+    truncation: Option<usize>,
+) -> Result<()> {
+    let leaf_hash =
+        decode_hash(&proof.file_hash, truncation).context("Failed to decode file hash")?;
+    let proof = MerkleProof {
+        leaf_index: proof.leaf_index,
+        leaf_hash,
+        path: proof_nodes_from_json(&proof.merkle_proof, truncation)?,
+    };
+    let computed_root = compute_proof_root(&proof, truncation)?;
+    anyhow::ensure!(
+        &computed_root == expected_root,
+        "Root mismatch: proof leads to {}",
+        encode_hash(&computed_root, truncation)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::ProofNodeJson;
+    use crypto::hash_leaf;
+    use merkle_tree::MerkleTree;
+
+    #[test]
+    fn test_verify_file_proof() {
+        let leaves: Vec<[u8; 32]> = [b"a", b"b", b"c"].iter().map(|c| hash_leaf(*c)).collect();
+        let tree = MerkleTree::from_leaf_hashes(&leaves).unwrap();
+        let root = tree.root_hash();
+        let proof = tree.generate_proof(2).unwrap();
+        let mut json = FileProofJson {
+            leaf_index: 2,
+            file_hash: hex::encode(leaves[2]),
+            merkle_proof: proof
+                .path
+                .iter()
+                .map(|node| ProofNodeJson {
+                    hash: hex::encode(node.hash),
+                    is_left: node.is_left,
+                })
+                .collect(),
+        };
+        assert!(verify_file_proof(&json, &root, None).is_ok());
+
+        // Another file's hash does not lead to the root through this path
+        json.file_hash = hex::encode(leaves[0]);
+        assert!(verify_file_proof(&json, &root, None).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+use crate::batch::load_encryption_batch_id;
+use crate::constants::{DOWNLOADED_DIR, PROOF_FILE_SUFFIX};
+use crate::download::{compute_proof_root, load_root_hash, proof_nodes_from_json};
+use anyhow::{Context, Result};
+use common::ProofNodeJson;
+use crypto::{encrypt_file, hash_leaf};
+use ed25519_dalek::SigningKey;
+use merkle_tree::{decode_hash, MerkleProof};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Proof saved as `{filename}.proof.json` next to a downloaded file, so the local copy
+/// can be rechecked later without contacting the server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedProof {
+    pub filename: String,
+    /// hex-encoded leaf hash of the content as stored on the server
+    pub leaf_hash: String,
+    pub merkle_proof: Vec<ProofNodeJson>,
+    /// The stored content is ciphertext, saved as `{filename}.encrypted`; `{filename}`,
+    /// if present, is the decrypted plaintext
+    pub encrypted: bool,
+    /// Width the proof's node hashes are truncated to; absent for full-width proofs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_truncation_bytes: Option<usize>,
+}
+
+/// Write the proof of a verified download next to the downloaded file
+pub fn save_proof(output_dir: &Path, proof: &SavedProof) -> Result<()> {
+    let path = output_dir.join(format!("{}{}", proof.filename, PROOF_FILE_SUFFIX));
+    let json = serde_json::to_string_pretty(proof).context("Failed to serialize proof")?;
+    fs::write(&path, json).context("Failed to write proof file")
+}
+
+/// Outcome of rechecking one downloaded file
+#[derive(Debug)]
+pub struct FileRecheck {
+    pub filename: String,
+    /// Why the local copy failed, or `None` if it still matches its proof
+    pub failure: Option<String>,
+}
+
+/// Recheck every downloaded file of a batch that has a saved proof
+/// Each proof must still lead to the batch root, and each local copy (ciphertext,
+/// plaintext or both) must still hash to the proof's leaf. Plaintext copies of private
+/// files are re-encrypted deterministically to compare against the stored leaf.
+/// The client-side counterpart of the server's scrubber.
+pub fn recheck_downloads(
+    batch_id: &str,
+    data_dir: &Path,
+    signing_key: &SigningKey,
+    root_hash: Option<&str>,
+) -> Result<Vec<FileRecheck>> {
+    let root_hash = match root_hash {
+        Some(root_hash) => root_hash.to_string(),
+        None => load_root_hash(batch_id, data_dir)?,
+    };
+
+    let dir = data_dir.join(batch_id).join(DOWNLOADED_DIR);
+    let mut proof_files: Vec<_> = fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read downloaded files in {:?}", dir))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.ends_with(PROOF_FILE_SUFFIX))
+        })
+        .collect();
+    proof_files.sort();
+
+    let mut results = Vec::with_capacity(proof_files.len());
+    for path in proof_files {
+        let json = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read proof file {:?}", path))?;
+        let proof: SavedProof = serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse proof file {:?}", path))?;
+        let failure = recheck_file(&dir, &proof, &root_hash, batch_id, data_dir, signing_key).err();
+        results.push(FileRecheck {
+            filename: proof.filename,
+            failure,
+        });
+    }
+    Ok(results)
+}
+
+/// Check one saved proof and the local copies of its file
+fn recheck_file(
+    dir: &Path,
+    proof: &SavedProof,
+    root_hash: &str,
+    batch_id: &str,
+    data_dir: &Path,
+    signing_key: &SigningKey,
+) -> Result<(), String> {
+    let mut leaf_hash = [0u8; 32];
+    hex::decode_to_slice(&proof.leaf_hash, &mut leaf_hash)
+        .map_err(|e| format!("Invalid leaf hash in proof: {}", e))?;
+    // The root is at the width the proof was served with
+    let truncation = proof.hash_truncation_bytes;
+    let root = decode_hash(root_hash.trim(), truncation)
+        .map_err(|e| format!("Invalid root hash: {}", e))?;
+    let path =
+        proof_nodes_from_json(&proof.merkle_proof, truncation).map_err(|e| format!("{:#}", e))?;
+    let proof_root = MerkleProof {
+        leaf_index: 0, // Not used in compute_root()
+        leaf_hash,
+        path,
+    };
+    let computed = compute_proof_root(&proof_root, truncation).map_err(|e| format!("{:#}", e))?;
+    if computed != root {
+        return Err("Saved proof does not lead to the batch root".to_string());
+    }
+
+    let mut checked = 0;
+    let stored_path = dir.join(format!("{}.encrypted", proof.filename));
+    if proof.encrypted && stored_path.exists() {
+        let content = fs::read(&stored_path).map_err(|e| format!("Failed to read file: {}", e))?;
+        if hash_leaf(&content) != leaf_hash {
+            return Err(format!("{}.encrypted is corrupted", proof.filename));
+        }
+        checked += 1;
+    }
+
+    let plain_path = dir.join(&proof.filename);
+    if plain_path.exists() {
+        let content = fs::read(&plain_path).map_err(|e| format!("Failed to read file: {}", e))?;
+        let stored = if proof.encrypted {
+            let encryption_batch_id =
+                load_encryption_batch_id(batch_id, data_dir).map_err(|e| format!("{:#}", e))?;
+            encrypt_file(signing_key, &proof.filename, &encryption_batch_id, &content)
+                .map_err(|e| format!("Failed to re-encrypt file: {:#}", e))?
+        } else {
+            content
+        };
+        if hash_leaf(&stored) != leaf_hash {
+            return Err(format!("{} is corrupted", proof.filename));
+        }
+        checked += 1;
+    }
+
+    if checked == 0 {
+        return Err("Downloaded file is missing".to_string());
+    }
+    Ok(())
+}
+
+/// Print a per-file report and fail if any file did not pass
+pub fn report(batch_id: &str, results: &[FileRecheck]) -> Result<()> {
+    println!("Recheck of downloaded files in batch {}", batch_id);
+    for result in results {
+        match &result.failure {
+            None => println!("  PASS {}", result.filename),
+            Some(reason) => println!("  FAIL {}: {}", result.filename, reason),
+        }
+    }
+    let failed = results.iter().filter(|r| r.failure.is_some()).count();
+    if failed > 0 {
+        anyhow::bail!(
+            "✗ Recheck failed: {} of {} downloaded files no longer match their proofs",
+            failed,
+            results.len()
+        );
+    }
+    println!("✓ Recheck passed: {} files", results.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::ROOT_HASH_FILE;
+    use crypto::generate_keypair;
+    use merkle_tree::MerkleTree;
+
+    #[test]
+    fn test_recheck_flags_corrupted_download() {
+        let data_dir = std::env::temp_dir().join(format!("vs-recheck-{}", std::process::id()));
+        fs::remove_dir_all(&data_dir).ok();
+        let dir = data_dir.join("batch").join(DOWNLOADED_DIR);
+        fs::create_dir_all(&dir).unwrap();
+        let (signing_key, _) = generate_keypair();
+
+        // a.txt as a private download (ciphertext and plaintext), b.txt as a public one
+        let ciphertext = encrypt_file(&signing_key, "a.txt", "batch", b"secret").unwrap();
+        let leaves = [hash_leaf(&ciphertext), hash_leaf(b"open")];
+        let tree = MerkleTree::from_leaf_hashes(&leaves).unwrap();
+        fs::write(
+            data_dir.join("batch").join(ROOT_HASH_FILE),
+            hex::encode(tree.root_hash()),
+        )
+        .unwrap();
+        fs::write(dir.join("a.txt.encrypted"), &ciphertext).unwrap();
+        fs::write(dir.join("a.txt"), b"secret").unwrap();
+        fs::write(dir.join("b.txt"), b"open").unwrap();
+        for (i, (filename, encrypted)) in [("a.txt", true), ("b.txt", false)].iter().enumerate() {
+            let proof = SavedProof {
+                filename: filename.to_string(),
+                leaf_hash: hex::encode(leaves[i]),
+                merkle_proof: tree
+                    .generate_proof(i)
+                    .unwrap()
+                    .path
+                    .iter()
+                    .map(|node| ProofNodeJson {
+                        hash: hex::encode(node.hash),
+                        is_left: node.is_left,
+                    })
+                    .collect(),
+                encrypted: *encrypted,
+                hash_truncation_bytes: None,
+            };
+            save_proof(&dir, &proof).unwrap();
+        }
+
+        let failures = |results: Vec<FileRecheck>| -> Vec<String> {
+            results
+                .into_iter()
+                .filter(|r| r.failure.is_some())
+                .map(|r| r.filename)
+                .collect()
+        };
+        let results = recheck_downloads("batch", &data_dir, &signing_key, None).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(report("batch", &results).is_ok());
+
+        // Bit rot in the decrypted copy is caught by re-encrypting it
+        fs::write(dir.join("a.txt"), b"secreT").unwrap();
+        fs::write(dir.join("b.txt"), b"open").unwrap();
+        let results = recheck_downloads("batch", &data_dir, &signing_key, None).unwrap();
+        assert_eq!(failures(results), vec!["a.txt"]);
+
+        fs::write(dir.join("a.txt"), b"secret").unwrap();
+        fs::write(dir.join("b.txt"), b"opeN").unwrap();
+        let results = recheck_downloads("batch", &data_dir, &signing_key, None).unwrap();
+        assert!(report("batch", &results).is_err());
+        assert_eq!(failures(results), vec!["b.txt"]);
+
+        fs::remove_dir_all(&data_dir).ok();
+    }
+}
@@ -0,0 +1,195 @@
+use crate::batch::load_encryption_batch_id;
+use crate::clock::warn_on_clock_skew;
+use crate::constants::BATCH_ENDPOINT;
+use crate::diff::fetch_batch_files;
+use crate::upload::{
+    compute_root_hash, compute_upload_leaves, prepare_upload_content, read_files_from_directory,
+    save_upload_metadata, select_unchanged,
+};
+use anyhow::{Context, Result};
+use common::auth_message::replace_message;
+use common::utils::get_current_timestamp_ms;
+use common::{BatchFileEntry, ReplaceBatchManifest};
+use crypto::{generate_nonce, sign_message};
+use ed25519_dalek::SigningKey;
+use log::info;
+use reqwest::blocking::{multipart, Client};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Which batch to replace and how to authenticate
+pub struct ReplaceBatchConfig<'a> {
+    /// Server URL
+    pub server: &'a str,
+    /// Batch ID
+    pub batch_id: &'a str,
+    /// This client's ID (the batch owner)
+    pub client_id: &'a str,
+    /// Signing key for authentication
+    pub signing_key: &'a SigningKey,
+    /// Width in bytes the server truncates node hashes to, if any
+    pub hash_truncation_bytes: Option<usize>,
+}
+
+/// Replace a batch's entire file set with the files of a directory in one request
+/// Only new or changed files are sent; files the directory no longer holds are removed
+/// and the new root becomes the batch's committed root. The server applies the new set
+/// atomically, so a failure leaves the batch as it was.
+pub fn replace_batch(config: &ReplaceBatchConfig, dir: &Path, data_dir: &Path) -> Result<String> {
+    let file_list = read_files_from_directory(dir)?;
+    if file_list.is_empty() {
+        anyhow::bail!("No files found in directory: {:?}", dir);
+    }
+
+    // Files are stored the way the batch already stores them
+    let listing = fetch_batch_files(
+        config.server,
+        config.batch_id,
+        config.signing_key,
+        config.client_id,
+    )?;
+    let encryption_batch_id = load_encryption_batch_id(config.batch_id, data_dir)?;
+    let leaves = compute_upload_leaves(
+        config.signing_key,
+        &encryption_batch_id,
+        listing.public,
+        &file_list,
+    )?;
+    let root_hash_hex = compute_root_hash(&leaves, config.hash_truncation_bytes)?;
+
+    let unchanged = select_unchanged(&leaves, &listing.files);
+    let removed = removed_files(&leaves, &listing.files);
+    info!(
+        "Replacing batch {}: {} files unchanged, {} to send, {} to remove",
+        config.batch_id,
+        unchanged.len(),
+        leaves.len() - unchanged.len(),
+        removed.len()
+    );
+
+    let nonce = generate_nonce();
+    let mut manifest = ReplaceBatchManifest {
+        files: leaves
+            .iter()
+            .map(|(filename, leaf)| BatchFileEntry {
+                filename: filename.clone(),
+                leaf_hash: hex::encode(leaf),
+            })
+            .collect(),
+        root_hash: root_hash_hex.clone(),
+        signature: String::new(),
+        timestamp: get_current_timestamp_ms(),
+        nonce: hex::encode(nonce),
+        client_id: config.client_id.to_string(),
+    };
+    let signature = sign_message(
+        config.signing_key,
+        &replace_message(config.batch_id, &manifest, &nonce),
+    );
+    manifest.signature = hex::encode(signature.to_bytes());
+
+    // Public content is streamed from disk; private content is encrypted again (the same
+    // ciphertext, as the nonce is derived from the content; a changed file gets a new one)
+    let mut form = multipart::Form::new().text("manifest", serde_json::to_string(&manifest)?);
+    for file in file_list
+        .iter()
+        .filter(|file| !unchanged.contains(&file.filename))
+    {
+        let body = if listing.public {
+            file.streaming_part()?
+        } else {
+            multipart::Part::bytes(prepare_upload_content(
+                config.signing_key,
+                &encryption_batch_id,
+                false,
+                file,
+            )?)
+        };
+        form = form.part(
+            "files",
+            body.file_name(file.filename.clone())
+                .mime_str("application/octet-stream")
+                .context("Failed to set MIME type")?,
+        );
+    }
+
+    let url = format!(
+        "{}{}/{}/replace",
+        config.server, BATCH_ENDPOINT, config.batch_id
+    );
+    let response = Client::new()
+        .post(&url)
+        .multipart(form)
+        .send()
+        .context("Failed to connect to server")?;
+
+    let status = response.status();
+    if !status.is_success() {
+        warn_on_clock_skew(&response);
+        let error_text = response
+            .text()
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        anyhow::bail!("Replacing batch failed: {} - {}", status, error_text);
+    }
+
+    save_upload_metadata(data_dir, config.batch_id, &root_hash_hex, &leaves)?;
+
+    info!(
+        "Replaced batch {}. Root hash: {}",
+        config.batch_id, root_hash_hex
+    );
+    println!(
+        "✓ Batch {} replaced: {} files ({} sent, {} unchanged, {} removed)",
+        config.batch_id,
+        leaves.len(),
+        leaves.len() - unchanged.len(),
+        unchanged.len(),
+        removed.len()
+    );
+    println!("Root hash: {}", root_hash_hex);
+    Ok(root_hash_hex)
+}
+
+/// Filenames the server holds that the new file set no longer contains
+fn removed_files(uploaded: &[(String, [u8; 32])], remote: &[BatchFileEntry]) -> BTreeSet<String> {
+    let kept: BTreeSet<&str> = uploaded
+        .iter()
+        .map(|(filename, _)| filename.as_str())
+        .collect();
+    remote
+        .iter()
+        .filter(|entry| !kept.contains(entry.filename.as_str()))
+        .map(|entry| entry.filename.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::hash_leaf;
+
+    #[test]
+    fn test_removed_files_are_those_missing_locally() {
+        let uploaded = vec![
+            ("a.txt".to_string(), hash_leaf(b"a")),
+            ("b.txt".to_string(), hash_leaf(b"b2")),
+        ];
+        let remote: Vec<BatchFileEntry> = [("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")]
+            .iter()
+            .map(|(filename, content)| BatchFileEntry {
+                filename: filename.to_string(),
+                leaf_hash: hex::encode(hash_leaf(*content)),
+            })
+            .collect();
+
+        assert_eq!(
+            removed_files(&uploaded, &remote),
+            BTreeSet::from(["c.txt".to_string()])
+        );
+        // Only a.txt can be left out of the request; b.txt changed
+        assert_eq!(
+            select_unchanged(&uploaded, &remote),
+            BTreeSet::from(["a.txt".to_string()])
+        );
+    }
+}
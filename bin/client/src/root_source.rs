@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use log::warn;
+use reqwest::blocking::Client;
+use std::collections::BTreeMap;
+use std::fs;
+
+/// Load the expected root of a batch from a source independent of the download server
+/// `source` is a local file path or an http(s) URL. Its content is either a bare hex
+/// root or a JSON object mapping batch IDs to hex roots (e.g. a roots file committed to git).
+pub fn load_root_from_source(source: &str, batch_id: &str) -> Result<String> {
+    let contents = if source.starts_with("https://") || source.starts_with("http://") {
+        fetch_url(source)?
+    } else {
+        fs::read_to_string(source)
+            .with_context(|| format!("Failed to read root source {}", source))?
+    };
+    parse_root(&contents, batch_id).with_context(|| format!("Invalid root source {}", source))
+}
+
+/// Fetch a root source over HTTP(S)
+fn fetch_url(url: &str) -> Result<String> {
+    if url.starts_with("http://") {
+        // Anyone on the path could substitute the root, defeating the point of pinning it
+        warn!(
+            "Root source {} is not HTTPS; the root is only as trustworthy as the network",
+            url
+        );
+    }
+    let response = Client::new()
+        .get(url)
+        .send()
+        .with_context(|| format!("Failed to fetch root source {}", url))?;
+    let status = response.status();
+    anyhow::ensure!(
+        status.is_success(),
+        "Failed to fetch root source {}: {}",
+        url,
+        status
+    );
+    response
+        .text()
+        .with_context(|| format!("Failed to read root source {}", url))
+}
+
+/// Extract the batch's root from a bare hex root or a JSON batch ID to root map
+fn parse_root(contents: &str, batch_id: &str) -> Result<String> {
+    let contents = contents.trim();
+    let root = if contents.starts_with('{') {
+        let roots: BTreeMap<String, String> =
+            serde_json::from_str(contents).context("Roots map must be a JSON object of strings")?;
+        roots
+            .get(batch_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No root listed for batch {}", batch_id))?
+    } else {
+        contents.to_string()
+    };
+
+    let root = root.trim().to_ascii_lowercase();
+    anyhow::ensure!(
+        root.len() == 64 && root.chars().all(|c| c.is_ascii_hexdigit()),
+        "Root must be exactly 64 hex characters"
+    );
+    Ok(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT: &str = "36d6c32637709cddc2646a05cf9a16b756454578e405a1772ff9f8504dffb7b1";
+
+    #[test]
+    fn test_load_root_from_file() {
+        let dir = std::env::temp_dir().join(format!("vs-root-source-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let bare = dir.join("root.txt");
+        fs::write(&bare, format!("{}\n", ROOT.to_uppercase())).unwrap();
+        assert_eq!(
+            load_root_from_source(bare.to_str().unwrap(), "any").unwrap(),
+            ROOT
+        );
+
+        let map = dir.join("roots.json");
+        fs::write(
+            &map,
+            format!(
+                r#"{{"release-1": "{}", "release-2": "{}"}}"#,
+                ROOT,
+                "ab".repeat(32)
+            ),
+        )
+        .unwrap();
+        let map = map.to_str().unwrap();
+        assert_eq!(load_root_from_source(map, "release-1").unwrap(), ROOT);
+        assert!(load_root_from_source(map, "missing").is_err());
+        assert!(load_root_from_source(dir.join("absent").to_str().unwrap(), "any").is_err());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_malformed_roots_rejected() {
+        assert!(parse_root("not-a-root", "batch").is_err());
+        assert!(parse_root(&ROOT[..62], "batch").is_err());
+        assert!(parse_root(r#"{"batch": 1}"#, "batch").is_err());
+        assert!(parse_root(r#"{"batch": "xyz"}"#, "batch").is_err());
+    }
+}
@@ -1,21 +1,52 @@
-use crate::constants::{FILENAMES_FILE, ROOT_HASH_FILE, UPLOAD_ENDPOINT};
+use crate::clock::warn_on_clock_skew;
+use crate::constants::{
+    BATCH_ENDPOINT, FILENAMES_FILE, HASH_ALGORITHM_FILE, ROOT_HASH_FILE, UPLOAD_ENDPOINT,
+    UPLOAD_STATUS_POLL_INTERVAL_MS,
+};
+use crate::diff::fetch_existing_batch_files;
 use anyhow::{Context, Result};
-use common::file_utils;
+use common::annotations::{validate_annotations, Annotations};
+use common::auth_message::{finalize_message, upload_message, UploadMessage};
 use common::utils::get_current_timestamp_ms;
-use crypto::{encrypt_file, hash_leaf, sign_message};
+use common::{
+    file_utils, BatchFileEntry, FinalizeBatchRequest, UploadAcceptedResponse, UploadStatusResponse,
+};
+use crypto::{
+    compute_client_id, encrypt_file, generate_nonce, hash_leaf, hash_leaf_reader, sign_message,
+};
 use ed25519_dalek::SigningKey;
 use log::info;
-use merkle_tree::MerkleTree;
+use merkle_tree::{encode_hash, MerkleTree};
 use reqwest::blocking::{multipart, Client};
+use reqwest::StatusCode;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
+/// Batch-level options of an upload
+#[derive(Debug, Clone, Default)]
+pub struct UploadOptions {
+    /// Make the batch publicly readable
+    pub public: bool,
+    /// Replace the batch's annotations (left untouched when `None`)
+    pub annotations: Option<Annotations>,
+    /// Skip files whose content the server already holds for this batch
+    pub skip_unchanged: bool,
+    /// Width the server truncates Merkle node hashes to; the root is computed to match
+    pub hash_truncation_bytes: Option<usize>,
+}
+
 /// Handles file uploads to the server
 pub struct FileUploader {
     server: String,
     batch_id: String,
     signing_key: SigningKey,
     data_dir: PathBuf,
+    public: bool,
+    annotations: Option<Annotations>,
+    skip_unchanged: bool,
+    hash_truncation_bytes: Option<usize>,
 }
 
 impl FileUploader {
@@ -25,79 +56,334 @@ impl FileUploader {
         batch_id: String,
         signing_key: SigningKey,
         data_dir: PathBuf,
-    ) -> Self {
-        Self {
+        options: UploadOptions,
+    ) -> Result<Self> {
+        if let Some(annotations) = &options.annotations {
+            validate_annotations(annotations).map_err(|e| anyhow::anyhow!(e))?;
+        }
+        Ok(Self {
             server,
             batch_id,
             signing_key,
             data_dir,
-        }
+            public: options.public,
+            annotations: options.annotations,
+            skip_unchanged: options.skip_unchanged,
+            hash_truncation_bytes: options.hash_truncation_bytes,
+        })
     }
 }
 
+/// Parse a `key=value` annotation argument
+pub fn parse_annotation(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("Invalid annotation {:?}: expected key=value", arg)),
+    }
+}
+
+/// Generate a batch ID for an upload that does not name one: `batch-{timestamp_ms}-{random}`
+/// The millisecond timestamp plus 64 random bits makes collisions practically impossible,
+/// and only ASCII letters, digits and '-' are used, so the ID passes batch ID validation
+pub fn generate_batch_id() -> String {
+    format!(
+        "batch-{}-{:016x}",
+        get_current_timestamp_ms(),
+        rand::random::<u64>()
+    )
+}
+
 /// Upload files from a directory to the server
+/// Public batches are uploaded unencrypted so anyone can download and read them
 pub fn upload_files(
     dir: &Path,
     server: &str,
     batch_id: &str,
     signing_key: &SigningKey,
     data_dir: &Path,
+    options: UploadOptions,
 ) -> Result<String> {
     let uploader = FileUploader::new(
         server.to_string(),
         batch_id.to_string(),
         signing_key.clone(),
         data_dir.to_path_buf(),
-    );
+        options,
+    )?;
     uploader.upload_from_directory(dir)
 }
 
+/// Upload everything read from `reader` (e.g. stdin) as a single file named `filename`
+pub fn upload_from_reader(
+    reader: impl Read,
+    filename: &str,
+    server: &str,
+    batch_id: &str,
+    signing_key: &SigningKey,
+    data_dir: &Path,
+    options: UploadOptions,
+) -> Result<String> {
+    let file_list = read_single_file(reader, filename)?;
+    let uploader = FileUploader::new(
+        server.to_string(),
+        batch_id.to_string(),
+        signing_key.clone(),
+        data_dir.to_path_buf(),
+        options,
+    )?;
+    uploader.upload_file_list(&file_list)
+}
+
+/// A file to upload, whose content is only read when it is hashed or sent
+/// Files found in a directory stay on disk, so listing a directory of large files costs
+/// no memory; only content that cannot be read twice (e.g. stdin) is kept in memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalFile {
+    pub filename: String,
+    source: FileSource,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FileSource {
+    Disk { path: PathBuf, size: u64 },
+    Memory(Vec<u8>),
+}
+
+impl LocalFile {
+    pub fn in_memory(filename: String, content: Vec<u8>) -> Self {
+        Self {
+            filename,
+            source: FileSource::Memory(content),
+        }
+    }
+
+    /// Size of the content in bytes
+    pub fn size(&self) -> u64 {
+        match &self.source {
+            FileSource::Disk { size, .. } => *size,
+            FileSource::Memory(content) => content.len() as u64,
+        }
+    }
+
+    /// Read the whole content, e.g. to encrypt it
+    pub fn read(&self) -> Result<Vec<u8>> {
+        match &self.source {
+            FileSource::Disk { path, .. } => {
+                fs::read(path).with_context(|| format!("Failed to read file: {:?}", path))
+            }
+            FileSource::Memory(content) => Ok(content.clone()),
+        }
+    }
+
+    /// Leaf hash of the content, read from disk in chunks
+    pub fn hash_leaf(&self) -> Result<[u8; 32]> {
+        match &self.source {
+            FileSource::Disk { path, .. } => {
+                let file = fs::File::open(path)
+                    .with_context(|| format!("Failed to open file: {:?}", path))?;
+                hash_leaf_reader(file).with_context(|| format!("Failed to read file: {:?}", path))
+            }
+            FileSource::Memory(content) => Ok(hash_leaf(content)),
+        }
+    }
+
+    /// Multipart body sending the content as-is, streamed from disk
+    pub fn streaming_part(&self) -> Result<multipart::Part> {
+        match &self.source {
+            FileSource::Disk { path, size } => {
+                let file = fs::File::open(path)
+                    .with_context(|| format!("Failed to open file: {:?}", path))?;
+                Ok(multipart::Part::reader_with_length(file, *size))
+            }
+            FileSource::Memory(content) => Ok(multipart::Part::bytes(content.clone())),
+        }
+    }
+}
+
+/// Read a single named file from `reader`, validating the name like directory uploads do
+pub fn read_single_file(mut reader: impl Read, filename: &str) -> Result<Vec<LocalFile>> {
+    file_utils::validate_filename(filename)
+        .map_err(|e| anyhow::anyhow!("{}: {}", e.message(), filename))?;
+
+    let mut content = Vec::new();
+    reader
+        .read_to_end(&mut content)
+        .context("Failed to read file content")?;
+    Ok(vec![LocalFile::in_memory(filename.to_string(), content)])
+}
+
+/// Compute the hex Merkle root the server will hold for exactly these uploaded leaves,
+/// at the width the server truncates node hashes to
+pub fn compute_root_hash(
+    uploaded: &[(String, [u8; 32])],
+    truncation: Option<usize>,
+) -> Result<String> {
+    let leaf_hashes: Vec<[u8; 32]> = uploaded.iter().map(|(_, leaf)| *leaf).collect();
+    let tree = MerkleTree::from_leaf_hashes(&leaf_hashes)
+        .context("Failed to build Merkle tree from encrypted files")?;
+    let tree = match truncation {
+        Some(bytes) => tree
+            .truncated(bytes)
+            .context("Failed to truncate Merkle tree")?,
+        None => tree,
+    };
+    Ok(encode_hash(&tree.root_hash(), truncation))
+}
+
+/// Filenames whose upload bytes hash to the leaf the server already holds under that name
+pub fn select_unchanged(
+    uploaded: &[(String, [u8; 32])],
+    remote: &[BatchFileEntry],
+) -> BTreeSet<String> {
+    let remote: BTreeMap<_, _> = remote
+        .iter()
+        .map(|entry| (entry.filename.as_str(), entry.leaf_hash.as_str()))
+        .collect();
+    uploaded
+        .iter()
+        .filter(|(filename, leaf)| {
+            remote.get(filename.as_str()) == Some(&hex::encode(leaf).as_str())
+        })
+        .map(|(filename, _)| filename.clone())
+        .collect()
+}
+
+/// List all files of a directory, sorted by filename, without reading their content
+pub fn read_files_from_directory(dir: &Path) -> Result<Vec<LocalFile>> {
+    let entries = fs::read_dir(dir).context("Failed to read directory")?;
+
+    let mut file_list: Vec<LocalFile> = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_file() {
+            let filename = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| path.to_string_lossy().to_string());
+
+            // Validate filename to prevent path traversal attacks
+            file_utils::validate_filename(&filename)
+                .map_err(|e| anyhow::anyhow!("{}: {}", e.message(), filename))?;
+
+            let size = entry
+                .metadata()
+                .with_context(|| format!("Failed to read metadata of file: {:?}", path))?
+                .len();
+            file_list.push(LocalFile {
+                filename,
+                source: FileSource::Disk { path, size },
+            });
+        }
+    }
+
+    // Sort files by filename for deterministic order
+    file_list.sort_by(|a, b| a.filename.cmp(&b.filename));
+
+    Ok(file_list)
+}
+
+/// Produce the bytes that are uploaded for a file: its ciphertext, or its plaintext for
+/// public batches. Encryption is deterministic, so this also reproduces what a
+/// previous upload sent.
+pub fn prepare_upload_content(
+    signing_key: &SigningKey,
+    encryption_batch_id: &str,
+    public: bool,
+    file: &LocalFile,
+) -> Result<Vec<u8>> {
+    let content = file.read()?;
+    if public {
+        return Ok(content);
+    }
+    encrypt_file(signing_key, &file.filename, encryption_batch_id, &content)
+        .with_context(|| format!("Failed to encrypt file: {}", file.filename))
+}
+
+/// Leaf hash of the bytes uploaded for each file, in file order
+/// Files are processed one at a time: public content is hashed in chunks straight from
+/// disk, and private content is only held while it is encrypted and hashed
+pub fn compute_upload_leaves(
+    signing_key: &SigningKey,
+    encryption_batch_id: &str,
+    public: bool,
+    file_list: &[LocalFile],
+) -> Result<Vec<(String, [u8; 32])>> {
+    file_list
+        .iter()
+        .map(|file| {
+            let leaf = if public {
+                file.hash_leaf()?
+            } else {
+                hash_leaf(&prepare_upload_content(
+                    signing_key,
+                    encryption_batch_id,
+                    false,
+                    file,
+                )?)
+            };
+            Ok((file.filename.clone(), leaf))
+        })
+        .collect()
+}
+
 impl FileUploader {
     /// Upload files from a directory
     pub fn upload_from_directory(&self, dir: &Path) -> Result<String> {
         // Read all files from directory
-        let file_list = self.read_files_from_directory(dir)?;
+        let file_list = read_files_from_directory(dir)?;
 
         if file_list.is_empty() {
             anyhow::bail!("No files found in directory: {:?}", dir);
         }
 
-        info!("Found {} files to upload", file_list.len());
-
-        // Encrypt all files first
-        let encrypted_file_list: Vec<(String, Vec<u8>)> = file_list
-            .iter()
-            .map(|(filename, plaintext)| {
-                let encrypted =
-                    encrypt_file(&self.signing_key, filename, &self.batch_id, plaintext)
-                        .with_context(|| format!("Failed to encrypt file: {}", filename))?;
-                Ok((filename.clone(), encrypted))
-            })
-            .collect::<Result<Vec<_>>>()?;
+        let total_bytes: u64 = file_list.iter().map(LocalFile::size).sum();
+        info!(
+            "Found {} files to upload ({} bytes)",
+            file_list.len(),
+            total_bytes
+        );
+        self.upload_file_list(&file_list)
+    }
 
-        info!("Encrypted {} files", encrypted_file_list.len());
+    /// Encrypt (unless public), upload and record the root of exactly these files
+    /// Content is read one file at a time, once to hash it and once to send it, so
+    /// memory does not grow with the size of the batch
+    fn upload_file_list(&self, file_list: &[LocalFile]) -> Result<String> {
+        // Hash what will be sent (public batches are meant to be readable by anyone)
+        let leaves =
+            compute_upload_leaves(&self.signing_key, &self.batch_id, self.public, file_list)?;
+        if self.public {
+            info!("Public batch: uploading files unencrypted");
+        } else {
+            info!("Encrypted and hashed {} files", leaves.len());
+        }
 
         // Build Merkle tree from encrypted files and compute root hash
-        let encrypted_file_data: Vec<Vec<u8>> = encrypted_file_list
-            .iter()
-            .map(|(_, encrypted_content)| encrypted_content.clone())
-            .collect();
-        let root_hash_hex = hex::encode(
-            MerkleTree::from_data(&encrypted_file_data)
-                .context("Failed to build Merkle tree from encrypted files")?
-                .root_hash(),
-        );
+        // (a single file is its own root)
+        let root_hash_hex = compute_root_hash(&leaves, self.hash_truncation_bytes)?;
 
         info!(
             "Uploading files (computed root hash from encrypted data: {})",
             root_hash_hex
         );
 
+        // The root above covers every file; only the upload of unchanged ones is skipped
+        let unchanged = if self.skip_unchanged {
+            self.unchanged_files(&leaves)?
+        } else {
+            BTreeSet::new()
+        };
+
         // Upload each encrypted file
-        self.upload_files_to_server(&encrypted_file_list)?;
+        self.upload_files_to_server(file_list, &leaves, &unchanged)?;
+
+        // Have the server confirm it received exactly these files
+        self.finalize_batch(&leaves, &root_hash_hex)?;
 
         // Save metadata (root hash and filenames) - use original filenames
-        self.save_upload_metadata(&root_hash_hex, &file_list)?;
+        save_upload_metadata(&self.data_dir, &self.batch_id, &root_hash_hex, &leaves)?;
 
         info!(
             "Upload complete. Batch ID: {}, Root hash: {}",
@@ -113,45 +399,58 @@ impl FileUploader {
         Ok(root_hash_hex)
     }
 
-    /// Read all files from a directory, sorted by filename
-    fn read_files_from_directory(&self, dir: &Path) -> Result<Vec<(String, Vec<u8>)>> {
-        let entries = fs::read_dir(dir).context("Failed to read directory")?;
-
-        let mut file_list: Vec<(String, Vec<u8>)> = Vec::new();
-        for entry in entries {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() {
-                let filename = path
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .map(|s| s.to_string())
-                    .unwrap_or_else(|| path.to_string_lossy().to_string());
-
-                // Validate filename to prevent path traversal attacks
-                file_utils::validate_filename(&filename)
-                    .map_err(|e| anyhow::anyhow!("{}: {}", e.message(), filename))?;
-
-                let content =
-                    fs::read(&path).with_context(|| format!("Failed to read file: {:?}", path))?;
-                file_list.push((filename, content));
+    /// Files the server already holds with identical content, from its listing of the batch
+    /// Finalizing afterwards confirms the server's tree matches the full root
+    fn unchanged_files(&self, uploaded: &[(String, [u8; 32])]) -> Result<BTreeSet<String>> {
+        let client_id = compute_client_id(&self.signing_key.verifying_key());
+        let Some(listing) = fetch_existing_batch_files(
+            &self.server,
+            &self.batch_id,
+            &self.signing_key,
+            &client_id,
+        )?
+        else {
+            return Ok(BTreeSet::new());
+        };
+
+        let mut unchanged = select_unchanged(uploaded, &listing.files);
+        // Annotations are applied by uploads, so send one file if none would carry them
+        let annotations_changed = self
+            .annotations
+            .as_ref()
+            .is_some_and(|annotations| *annotations != listing.annotations);
+        if annotations_changed && unchanged.len() == uploaded.len() {
+            if let Some((filename, _)) = uploaded.first() {
+                unchanged.remove(filename);
             }
         }
 
-        // Sort files by filename for deterministic order
-        file_list.sort_by(|a, b| a.0.cmp(&b.0));
-
-        Ok(file_list)
+        info!(
+            "{} of {} files unchanged on the server",
+            unchanged.len(),
+            uploaded.len()
+        );
+        Ok(unchanged)
     }
 
-    /// Upload files to the server
-    fn upload_files_to_server(&self, file_list: &[(String, Vec<u8>)]) -> Result<()> {
+    /// Upload files to the server, except those named in `skip`
+    /// `leaves` holds the leaf hash of each file's upload bytes, in the same order
+    fn upload_files_to_server(
+        &self,
+        file_list: &[LocalFile],
+        leaves: &[(String, [u8; 32])],
+        skip: &BTreeSet<String>,
+    ) -> Result<()> {
         let client = Client::new();
         let public_key = self.signing_key.verifying_key();
         let public_key_hex = hex::encode(public_key.to_bytes());
 
-        for (filename, content) in file_list {
-            let form = self.build_multipart_form(filename, content, &public_key_hex)?;
+        for (file, (filename, leaf_hash)) in file_list.iter().zip(leaves) {
+            if skip.contains(filename) {
+                println!("Skipped unchanged file: {}", filename);
+                continue;
+            }
+            let form = self.build_multipart_form(file, leaf_hash, &public_key_hex)?;
 
             // Send request
             let url = format!("{}{}", self.server, UPLOAD_ENDPOINT);
@@ -163,6 +462,7 @@ impl FileUploader {
 
             let status = response.status();
             if !status.is_success() {
+                warn_on_clock_skew(&response);
                 let error_text = response
                     .text()
                     .unwrap_or_else(|_| "Unknown error".to_string());
@@ -174,6 +474,14 @@ impl FileUploader {
                 );
             }
 
+            // The server queued the upload; the batch can only be finalized once it is stored
+            if status == StatusCode::ACCEPTED {
+                let accepted: UploadAcceptedResponse = response
+                    .json()
+                    .context("Failed to parse upload acceptance")?;
+                self.wait_until_stored(&client, filename, &accepted)?;
+            }
+
             // Upload successful (HTTP status code indicates success)
             info!("Uploaded file: {}", filename);
             println!("Uploaded file: {}", filename);
@@ -182,89 +490,374 @@ impl FileUploader {
         Ok(())
     }
 
+    /// Poll the status of a queued upload until the server has stored it or given up
+    fn wait_until_stored(
+        &self,
+        client: &Client,
+        filename: &str,
+        accepted: &UploadAcceptedResponse,
+    ) -> Result<()> {
+        let url = format!("{}{}", self.server, accepted.status_url);
+        loop {
+            let response = client
+                .get(&url)
+                .send()
+                .context("Failed to connect to server")?;
+            let status = response.status();
+            if !status.is_success() {
+                let error_text = response
+                    .text()
+                    .unwrap_or_else(|_| "Unknown error".to_string());
+                anyhow::bail!(
+                    "Failed to check upload status for file {}: {} - {}",
+                    filename,
+                    status,
+                    error_text
+                );
+            }
+            let report: UploadStatusResponse =
+                response.json().context("Failed to parse upload status")?;
+            match report.status.as_str() {
+                "stored" => return Ok(()),
+                "failed" => anyhow::bail!(
+                    "Upload failed for file {}: {}",
+                    filename,
+                    report.error.unwrap_or_else(|| "Unknown error".to_string())
+                ),
+                _ => std::thread::sleep(std::time::Duration::from_millis(
+                    UPLOAD_STATUS_POLL_INTERVAL_MS,
+                )),
+            }
+        }
+    }
+
+    /// Send the signed commitment to the uploaded file set
+    /// Fails if the server holds a different set of files, e.g. because an upload
+    /// request was dropped on the way
+    fn finalize_batch(&self, uploaded: &[(String, [u8; 32])], root_hash_hex: &str) -> Result<()> {
+        let leaf_hashes: Vec<String> = uploaded.iter().map(|(_, leaf)| hex::encode(leaf)).collect();
+        let timestamp = get_current_timestamp_ms();
+        let nonce = generate_nonce();
+        let message = finalize_message(
+            &self.batch_id,
+            &leaf_hashes,
+            root_hash_hex,
+            timestamp,
+            &nonce,
+        );
+        let signature = sign_message(&self.signing_key, &message);
+
+        let url = format!(
+            "{}{}/{}/finalize",
+            self.server, BATCH_ENDPOINT, self.batch_id
+        );
+        let response = Client::new()
+            .post(&url)
+            .json(&FinalizeBatchRequest {
+                leaf_hashes,
+                root_hash: root_hash_hex.to_string(),
+                signature: hex::encode(signature.to_bytes()),
+                timestamp,
+                nonce: hex::encode(nonce),
+                client_id: compute_client_id(&self.signing_key.verifying_key()),
+            })
+            .send()
+            .context("Failed to connect to server")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            warn_on_clock_skew(&response);
+            let error_text = response
+                .text()
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            anyhow::bail!("Finalizing batch failed: {} - {}", status, error_text);
+        }
+
+        info!("Server confirmed the file set of batch {}", self.batch_id);
+        Ok(())
+    }
+
     /// Build multipart form for file upload
-    /// content is encrypted data
+    /// Public content is streamed from disk; private content is encrypted again (the same
+    /// ciphertext, as the nonce is derived from the content) and only held while it is sent
     fn build_multipart_form(
         &self,
-        filename: &str,
-        content: &[u8], // Encrypted content
+        file: &LocalFile,
+        leaf_hash: &[u8; 32], // Leaf hash of the upload bytes (Merkle tree is built from encrypted data)
         public_key_hex: &str,
     ) -> Result<multipart::Form> {
-        // Compute leaf hash from encrypted content (Merkle tree is built from encrypted data)
-        let leaf_hash = hash_leaf(content);
+        let filename = file.filename.as_str();
         let leaf_hash_hex = hex::encode(leaf_hash);
-
-        // Create message to sign using encrypted file bytes
+        let body = if self.public {
+            file.streaming_part()?
+        } else {
+            multipart::Part::bytes(prepare_upload_content(
+                &self.signing_key,
+                &self.batch_id,
+                false,
+                file,
+            )?)
+        };
+
+        // Sign the hash of the bytes sent (encrypted unless public)
         let timestamp = get_current_timestamp_ms();
-        let message = self.build_upload_message(filename, &leaf_hash_hex, content, timestamp);
+        let nonce = generate_nonce();
+        let message = upload_message(&UploadMessage {
+            filename,
+            batch_id: &self.batch_id,
+            file_hash: &leaf_hash_hex,
+            timestamp,
+            nonce: &nonce,
+            public_key: self.signing_key.verifying_key().as_bytes(),
+            public: self.public,
+            annotations: self.annotations.as_ref(),
+        });
 
         // Sign message
         let signature = sign_message(&self.signing_key, &message);
         let signature_hex = hex::encode(signature.to_bytes());
 
         // Create multipart form
-        let form = multipart::Form::new()
+        let mut form = multipart::Form::new()
             .text("filename", filename.to_string())
             .text("batch_id", self.batch_id.clone())
             .text("file_hash", leaf_hash_hex)
             .text("signature", signature_hex)
             .text("timestamp", timestamp.to_string())
+            .text("nonce", hex::encode(nonce))
             .text("public_key", public_key_hex.to_string())
             .part(
                 "file",
-                multipart::Part::bytes(content.to_vec())
-                    .file_name(filename.to_string())
+                body.file_name(filename.to_string())
                     .mime_str("application/octet-stream")
                     .context("Failed to set MIME type")?,
             );
+        if self.public {
+            form = form.text("public", "true");
+        }
+        if let Some(annotations) = &self.annotations {
+            form = form.text("annotations", serde_json::to_string(annotations)?);
+        }
 
         Ok(form)
     }
+}
 
-    /// Build message for upload signature
-    /// Signs encrypted file bytes (not base64)
-    fn build_upload_message(
-        &self,
-        filename: &str,
-        file_hash: &str,
-        file_content: &[u8], // Encrypted bytes
-        timestamp: u64,
-    ) -> Vec<u8> {
-        let mut message = Vec::new();
-        message.extend_from_slice(filename.as_bytes());
-        message.extend_from_slice(self.batch_id.as_bytes());
-        message.extend_from_slice(file_hash.as_bytes());
-        message.extend_from_slice(file_content);
-        message.extend_from_slice(&timestamp.to_be_bytes());
-        message
+/// Save upload metadata (root hash and filenames) for later downloads
+pub fn save_upload_metadata(
+    data_dir: &Path,
+    batch_id: &str,
+    root_hash_hex: &str,
+    file_list: &[(String, [u8; 32])],
+) -> Result<()> {
+    let batch_dir = data_dir.join(batch_id);
+    fs::create_dir_all(&batch_dir).context("Failed to create batch directory")?;
+
+    // Save root hash
+    let root_hash_file = batch_dir.join(ROOT_HASH_FILE);
+    fs::write(&root_hash_file, root_hash_hex)
+        .with_context(|| format!("Failed to write {}", ROOT_HASH_FILE))?;
+
+    // Save hash algorithm the root was computed with
+    let hash_algorithm_file = batch_dir.join(HASH_ALGORITHM_FILE);
+    fs::write(&hash_algorithm_file, merkle_tree::HASH_ALGORITHM)
+        .with_context(|| format!("Failed to write {}", HASH_ALGORITHM_FILE))?;
+
+    // Save filenames
+    let filenames: Vec<String> = file_list
+        .iter()
+        .map(|(filename, _)| filename.clone())
+        .collect();
+    let filenames_file = batch_dir.join(FILENAMES_FILE);
+    fs::write(
+        &filenames_file,
+        serde_json::to_string_pretty(&filenames).context("Failed to serialize filenames")?,
+    )
+    .context("Failed to write filenames.json")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::generate_keypair;
+
+    #[test]
+    fn test_single_file_from_reader_is_its_own_root() {
+        let bytes = b"generated by a pipeline".to_vec();
+        let file_list = read_single_file(&bytes[..], "foo.bin").unwrap();
+        assert_eq!(
+            file_list,
+            vec![LocalFile::in_memory("foo.bin".to_string(), bytes.clone())]
+        );
+
+        // Public uploads send the bytes as-is
+        let (signing_key, _) = generate_keypair();
+        let leaves = compute_upload_leaves(&signing_key, "batch", true, &file_list).unwrap();
+        assert_eq!(
+            compute_root_hash(&leaves, None).unwrap(),
+            hex::encode(hash_leaf(&bytes))
+        );
+
+        // Private uploads commit to the ciphertext
+        let encrypted =
+            prepare_upload_content(&signing_key, "batch", false, &file_list[0]).unwrap();
+        let leaves = compute_upload_leaves(&signing_key, "batch", false, &file_list).unwrap();
+        assert_eq!(
+            compute_root_hash(&leaves, None).unwrap(),
+            hex::encode(hash_leaf(&encrypted))
+        );
+
+        assert!(read_single_file(&bytes[..], "../escape").is_err());
     }
 
-    /// Save upload metadata (root hash and filenames)
-    fn save_upload_metadata(
-        &self,
-        root_hash_hex: &str,
-        file_list: &[(String, Vec<u8>)],
-    ) -> Result<()> {
-        let batch_dir = self.data_dir.join(&self.batch_id);
-        fs::create_dir_all(&batch_dir).context("Failed to create batch directory")?;
-
-        // Save root hash
-        let root_hash_file = batch_dir.join(ROOT_HASH_FILE);
-        fs::write(&root_hash_file, root_hash_hex)
-            .with_context(|| format!("Failed to write {}", ROOT_HASH_FILE))?;
-
-        // Save filenames
-        let filenames: Vec<String> = file_list
-            .iter()
-            .map(|(filename, _)| filename.clone())
-            .collect();
-        let filenames_file = batch_dir.join(FILENAMES_FILE);
-        fs::write(
-            &filenames_file,
-            serde_json::to_string_pretty(&filenames).context("Failed to serialize filenames")?,
-        )
-        .context("Failed to write filenames.json")?;
+    #[test]
+    fn test_overlong_filename_fails_before_reading() {
+        use common::file_utils::MAX_FILENAME_BYTES;
 
-        Ok(())
+        let at_limit = "a".repeat(MAX_FILENAME_BYTES);
+        assert!(read_single_file(&b"x"[..], &at_limit).is_ok());
+
+        // The reader is never touched, so nothing is read or hashed
+        struct Unreadable;
+        impl Read for Unreadable {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                panic!("read before the filename was validated")
+            }
+        }
+        let too_long = "a".repeat(MAX_FILENAME_BYTES + 1);
+        let err = read_single_file(Unreadable, &too_long).unwrap_err();
+        assert!(err.to_string().contains("longer than 255 bytes"));
+        // Counted in bytes, not characters
+        assert!(read_single_file(Unreadable, &"é".repeat(128)).is_err());
+    }
+
+    #[test]
+    fn test_generated_batch_id_is_usable() {
+        let batch_id = generate_batch_id();
+        assert_ne!(batch_id, generate_batch_id());
+        file_utils::validate_filename(&batch_id).unwrap();
+        assert!(batch_id.len() <= common::file_utils::MAX_FILENAME_BYTES);
+
+        // Round-trips through encryption like an explicit batch ID
+        let (signing_key, _) = generate_keypair();
+        let file = LocalFile::in_memory("a.txt".to_string(), b"secret".to_vec());
+        let encrypted = prepare_upload_content(&signing_key, &batch_id, false, &file).unwrap();
+        let decrypted = crypto::decrypt_file(&signing_key, "a.txt", &batch_id, &encrypted).unwrap();
+        assert_eq!(decrypted, b"secret");
+    }
+
+    #[test]
+    fn test_only_changed_files_are_sent() {
+        let (signing_key, _) = generate_keypair();
+        let files = |contents: &[(&str, &[u8])]| -> Vec<LocalFile> {
+            contents
+                .iter()
+                .map(|(filename, content)| {
+                    LocalFile::in_memory(filename.to_string(), content.to_vec())
+                })
+                .collect()
+        };
+        let previous = files(&[("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")]);
+        let current = files(&[
+            ("a.txt", b"a"),
+            ("b.txt", b"B"),
+            ("c.txt", b"c"),
+            ("d.txt", b"d"),
+        ]);
+        // What the server lists after the previous upload
+        let remote: Vec<BatchFileEntry> =
+            compute_upload_leaves(&signing_key, "batch", false, &previous)
+                .unwrap()
+                .iter()
+                .map(|(filename, leaf)| BatchFileEntry {
+                    filename: filename.clone(),
+                    leaf_hash: hex::encode(leaf),
+                })
+                .collect();
+
+        // Encryption is deterministic, so unchanged plaintext gives the same leaf
+        let uploaded = compute_upload_leaves(&signing_key, "batch", false, &current).unwrap();
+        let unchanged = select_unchanged(&uploaded, &remote);
+        assert_eq!(
+            unchanged,
+            BTreeSet::from(["a.txt".to_string(), "c.txt".to_string()])
+        );
+        // ... while the changed file is sent under a nonce of its own, not its old one
+        let sent = |file: &LocalFile| prepare_upload_content(&signing_key, "batch", false, file);
+        let (old_b, new_b) = (sent(&previous[1]).unwrap(), sent(&current[1]).unwrap());
+        assert_ne!(
+            old_b[..crypto::FILE_NONCE_BYTES],
+            new_b[..crypto::FILE_NONCE_BYTES]
+        );
+
+        // The server rebuilds its tree over the skipped files it holds plus the sent ones,
+        // which is the root the client records over the full set
+        let mut server_holds = compute_upload_leaves(&signing_key, "batch", false, &previous)
+            .unwrap()
+            .into_iter()
+            .filter(|(filename, _)| unchanged.contains(filename))
+            .collect::<Vec<_>>();
+        server_holds.extend(
+            uploaded
+                .iter()
+                .filter(|(filename, _)| !unchanged.contains(filename))
+                .cloned(),
+        );
+        server_holds.sort();
+        assert_eq!(
+            compute_root_hash(&server_holds, None).unwrap(),
+            compute_root_hash(&uploaded, None).unwrap()
+        );
+
+        // Nothing is skipped for a batch the server does not have
+        assert!(select_unchanged(&uploaded, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_directory_files_are_hashed_without_loading() {
+        let dir = std::env::temp_dir().join(format!("vs-lazy-upload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // Sparse, so the test neither writes nor allocates 16 MiB
+        let size = 16 << 20;
+        fs::File::create(dir.join("large.bin"))
+            .unwrap()
+            .set_len(size)
+            .unwrap();
+        fs::write(dir.join("small.txt"), b"small").unwrap();
+
+        // Listing records paths and sizes only
+        let file_list = read_files_from_directory(&dir).unwrap();
+        assert_eq!(
+            file_list
+                .iter()
+                .map(|file| (file.filename.as_str(), file.size()))
+                .collect::<Vec<_>>(),
+            vec![("large.bin", size), ("small.txt", 5)]
+        );
+
+        // Public leaves are hashed in fixed-size chunks, never holding the whole file
+        let (signing_key, _) = generate_keypair();
+        let leaves = compute_upload_leaves(&signing_key, "batch", true, &file_list).unwrap();
+        let expected = hash_leaf_reader(std::io::repeat(0).take(size)).unwrap();
+        assert_eq!(leaves[0], ("large.bin".to_string(), expected));
+        assert_eq!(leaves[1], ("small.txt".to_string(), hash_leaf(b"small")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_annotation() {
+        assert_eq!(
+            parse_annotation("commit=1a2b=3c").unwrap(),
+            ("commit".to_string(), "1a2b=3c".to_string())
+        );
+        assert_eq!(
+            parse_annotation("empty=").unwrap(),
+            ("empty".to_string(), String::new())
+        );
+        assert!(parse_annotation("no-separator").is_err());
+        assert!(parse_annotation("=value").is_err());
     }
 }
@@ -0,0 +1,54 @@
+use crate::batch::load_encryption_batch_id;
+use crate::upload::{compute_upload_leaves, read_files_from_directory};
+use anyhow::{Context, Result};
+use ed25519_dalek::SigningKey;
+use log::info;
+use merkle_tree::MerkleTree;
+use std::path::Path;
+
+/// Verify offline that the files in a local directory are exactly the batch committed
+/// to by `root_hash_hex`: no file missing, extra or changed
+/// Private batches are re-encrypted (deterministically) under `batch_id` so leaves
+/// match what was uploaded; `batch_id` is None for public (plaintext) batches.
+pub fn verify_batch_local(
+    dir: &Path,
+    root_hash_hex: &str,
+    signing_key: &SigningKey,
+    batch_id: Option<&str>,
+    data_dir: &Path,
+) -> Result<()> {
+    let mut root = [0u8; 32];
+    hex::decode_to_slice(root_hash_hex, &mut root).context("Invalid root hash")?;
+
+    let file_list = read_files_from_directory(dir)?;
+    if file_list.is_empty() {
+        anyhow::bail!("No files found in directory: {:?}", dir);
+    }
+
+    let uploaded = match batch_id {
+        Some(batch_id) => {
+            let encryption_batch_id = load_encryption_batch_id(batch_id, data_dir)?;
+            compute_upload_leaves(signing_key, &encryption_batch_id, false, &file_list)?
+        }
+        // Plaintext files, hashed as they are
+        None => compute_upload_leaves(signing_key, "", true, &file_list)?,
+    };
+    let leaves: Vec<[u8; 32]> = uploaded.iter().map(|(_, leaf)| *leaf).collect();
+    info!("Computed {} leaf hashes from {:?}", leaves.len(), dir);
+
+    if !MerkleTree::verify_batch_membership(&leaves, &root) {
+        anyhow::bail!(
+            "Batch verification failed: the {} files in {:?} do not commit to root {}",
+            leaves.len(),
+            dir,
+            root_hash_hex
+        );
+    }
+
+    println!(
+        "✓ Batch verified: {} files commit to root {}",
+        leaves.len(),
+        root_hash_hex
+    );
+    Ok(())
+}
@@ -0,0 +1,229 @@
+use anyhow::Context;
+use crypto::hash_leaf;
+use merkle_tree::MerkleTree;
+use storage::Storage;
+
+/// Outcome of auditing a single file
+#[derive(Debug)]
+pub struct FileAudit {
+    pub filename: String,
+    /// Why the file failed, or `None` if its proof verified
+    pub failure: Option<String>,
+}
+
+/// Outcome of auditing a whole batch
+#[derive(Debug)]
+pub struct BatchAudit {
+    pub client_id: String,
+    pub batch_id: String,
+    /// Root of the Merkle tree stored for the batch
+    pub stored_root: [u8; 32],
+    /// Root rebuilt from the current file contents
+    pub computed_root: [u8; 32],
+    /// Whether the stored tree pairs lone nodes with themselves, as trees stored before
+    /// lone-node promotion do; the root is then rebuilt the same way
+    pub legacy_tree: bool,
+    pub files: Vec<FileAudit>,
+}
+
+impl BatchAudit {
+    pub fn passed(&self) -> bool {
+        self.stored_root == self.computed_root && self.files.iter().all(|f| f.failure.is_none())
+    }
+
+    /// Print a per-file report followed by the overall result
+    pub fn print(&self) {
+        println!(
+            "Audit of batch {} (client {})",
+            self.batch_id, self.client_id
+        );
+        for file in &self.files {
+            match &file.failure {
+                None => println!("  PASS {}", file.filename),
+                Some(reason) => println!("  FAIL {}: {}", file.filename, reason),
+            }
+        }
+        println!("Stored root:   {}", hex::encode(self.stored_root));
+        println!("Computed root: {}", hex::encode(self.computed_root));
+        if self.legacy_tree {
+            println!("Stored tree predates lone-node promotion; rebuilt by its old rule");
+        }
+        let failed = self.files.iter().filter(|f| f.failure.is_some()).count();
+        if self.passed() {
+            println!("✓ Audit passed: {} files", self.files.len());
+        } else {
+            println!(
+                "✗ Audit failed: {} of {} files failed{}",
+                failed,
+                self.files.len(),
+                if self.stored_root != self.computed_root {
+                    ", stored root does not match the file contents"
+                } else {
+                    ""
+                }
+            );
+        }
+    }
+}
+
+/// Exercise the download proof path for every file of a batch
+/// Rebuilds the tree from the stored contents, then generates each file's proof from
+/// the stored tree (as downloads do) and verifies it from the file's current content
+/// against the stored root. Unlike the scrubber, this covers proof generation and
+/// verification end to end, not just leaf hashes.
+pub async fn audit_batch(
+    storage: &dyn Storage,
+    client_id: &str,
+    batch_id: &str,
+) -> anyhow::Result<BatchAudit> {
+    anyhow::ensure!(
+        storage.batch_exists(client_id, batch_id).await?,
+        "Batch {} not found for client {}",
+        batch_id,
+        client_id
+    );
+    let mut filenames = storage
+        .load_batch_filenames(client_id, batch_id)
+        .await
+        .context("Failed to load batch filenames")?;
+    filenames.sort();
+    let tree = storage
+        .load_merkle_tree(client_id, batch_id)
+        .await
+        .context("Failed to load Merkle tree")?
+        .ok_or_else(|| anyhow::anyhow!("Batch {} has no Merkle tree", batch_id))?;
+    anyhow::ensure!(
+        tree.num_leaves() == filenames.len(),
+        "Stored tree has {} leaves but batch has {} files",
+        tree.num_leaves(),
+        filenames.len()
+    );
+    let stored_root = tree.root_hash();
+
+    let mut leaves = Vec::with_capacity(filenames.len());
+    let mut files = Vec::with_capacity(filenames.len());
+    for (leaf_index, filename) in filenames.into_iter().enumerate() {
+        let failure = match storage.read_file(client_id, batch_id, &filename).await {
+            Ok(content) => {
+                let leaf_hash = hash_leaf(&content);
+                leaves.push(leaf_hash);
+                verify_leaf(&tree, leaf_index, leaf_hash, &stored_root).err()
+            }
+            Err(e) => {
+                // Unreadable files still take their stored leaf, so the rebuilt root
+                // reflects only the files that actually changed
+                leaves.push(tree.leaf_hash(leaf_index).unwrap_or_default());
+                Some(format!("Failed to read file: {:#}", e))
+            }
+        };
+        files.push(FileAudit { filename, failure });
+    }
+
+    // A legacy tree is checked against the rule it was built with, not today's
+    let legacy_tree = tree.duplicates_lone_nodes();
+    let computed_root = if legacy_tree {
+        MerkleTree::from_leaf_hashes_legacy(&leaves)
+    } else {
+        MerkleTree::from_leaf_hashes(&leaves)
+    }
+    .context("Failed to rebuild Merkle tree")?
+    .root_hash();
+
+    Ok(BatchAudit {
+        client_id: client_id.to_string(),
+        batch_id: batch_id.to_string(),
+        stored_root,
+        computed_root,
+        legacy_tree,
+        files,
+    })
+}
+
+/// Generate the proof for `leaf_index` and check that `leaf_hash` verifies against `root`
+fn verify_leaf(
+    tree: &MerkleTree,
+    leaf_index: usize,
+    leaf_hash: [u8; 32],
+    root: &[u8; 32],
+) -> Result<(), String> {
+    let mut proof = tree
+        .generate_proof(leaf_index)
+        .map_err(|e| format!("Failed to generate proof: {}", e))?;
+    // Verify from the content as read now, as a client would
+    proof.leaf_hash = leaf_hash;
+    let computed = proof
+        .compute_root()
+        .map_err(|e| format!("Failed to compute root from proof: {}", e))?;
+    if &computed != root {
+        return Err(format!(
+            "Proof leads to {} instead of the stored root",
+            hex::encode(computed)
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{store_legacy_batch, TempDataDir};
+    use storage::filesystem::FilesystemStorage;
+
+    #[tokio::test]
+    async fn test_audit_reports_corrupted_file() {
+        let dir = TempDataDir::new();
+        let storage = FilesystemStorage::new(dir.0.clone());
+        for (name, content) in [("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")] {
+            storage
+                .store_file_and_update_tree("client", "batch", name, content)
+                .await
+                .unwrap();
+        }
+
+        let audit = audit_batch(&storage, "client", "batch").await.unwrap();
+        assert!(audit.passed());
+        assert_eq!(audit.files.len(), 3);
+
+        // Flip the content on disk behind storage's back
+        std::fs::write(dir.0.join("client").join("batch").join("b.txt"), b"x").unwrap();
+
+        let audit = audit_batch(&storage, "client", "batch").await.unwrap();
+        assert!(!audit.passed());
+        assert_ne!(audit.stored_root, audit.computed_root);
+        let failed: Vec<_> = audit
+            .files
+            .iter()
+            .filter(|f| f.failure.is_some())
+            .map(|f| f.filename.as_str())
+            .collect();
+        assert_eq!(failed, vec!["b.txt"]);
+
+        assert!(audit_batch(&storage, "client", "missing").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_audit_checks_legacy_tree_by_its_own_rule() {
+        let dir = TempDataDir::new();
+        let storage = FilesystemStorage::new(dir.0.clone());
+        let legacy_root = store_legacy_batch(
+            &dir,
+            &storage,
+            "client",
+            "batch",
+            &[("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")],
+        )
+        .await;
+
+        let audit = audit_batch(&storage, "client", "batch").await.unwrap();
+        assert!(audit.legacy_tree);
+        assert!(audit.passed());
+        assert_eq!(audit.stored_root, legacy_root);
+        assert_eq!(audit.computed_root, legacy_root);
+
+        // Corruption is still caught
+        std::fs::write(dir.0.join("client").join("batch").join("c.txt"), b"x").unwrap();
+        let audit = audit_batch(&storage, "client", "batch").await.unwrap();
+        assert!(!audit.passed());
+        assert_ne!(audit.computed_root, legacy_root);
+    }
+}
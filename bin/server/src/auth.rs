@@ -2,54 +2,156 @@ use crate::constants::{DEFAULT_MAX_AGE_SECONDS, DEFAULT_MAX_CLOCK_SKEW_SECONDS};
 use crate::state::AppState;
 use actix_web::web;
 use anyhow::{Context, Result};
-use crypto::{compute_client_id, public_key_from_bytes, verify_signature};
-use ed25519_dalek::Signature;
+use crypto::{public_key_from_bytes, verify_batch, PublicKey, SignatureScheme, NONCE_BYTES};
+use ed25519_dalek::{Signature, VerifyingKey};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Timestamps this many times smaller or larger than now (in milliseconds) are
+/// assumed to be in the wrong unit rather than merely stale or skewed
+const TIMESTAMP_UNIT_TOLERANCE: u64 = 100;
+
+/// Error returned when a request timestamp is implausible as milliseconds since the
+/// Unix epoch, e.g. a client sending seconds or nanoseconds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimestampUnitError {
+    pub timestamp: u64,
+    pub now_ms: u64,
+}
+
+impl std::fmt::Display for TimestampUnitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hint = if self.timestamp < self.now_ms {
+            "seconds"
+        } else {
+            "microseconds or nanoseconds"
+        };
+        write!(
+            f,
+            "Request timestamp unit looks wrong: got {}, but now is {} ms since the Unix epoch \
+             (the value looks like {}; timestamps must be milliseconds)",
+            self.timestamp, self.now_ms, hint
+        )
+    }
+}
+
+impl std::error::Error for TimestampUnitError {}
+
 /// Handles authentication and signature verification
 pub struct AuthVerifier;
 
 impl AuthVerifier {
+    /// Verify a request signed with the given key of `scheme`, registering the key
+    /// on the client's first request
+    /// Returns the client ID and whether the client is new
     pub async fn verify_request_signature(
         state: &web::Data<AppState>,
+        scheme: SignatureScheme,
         message: &[u8],
-        signature: &Signature,
+        signature: &[u8],
+        public_key_hex: &str,
+    ) -> Result<(String, bool)> {
+        let public_key_bytes =
+            hex::decode(public_key_hex.trim()).context("Failed to decode public key")?;
+        let public_key = scheme
+            .public_key_from_bytes(&public_key_bytes)
+            .context("Failed to parse public key")?;
+        // Never register a degenerate key, even if a caller skipped validate_public_key
+        reject_weak_key(&public_key)?;
+
+        let client_id = public_key.client_id();
+
+        public_key
+            .verify(message, signature)
+            .inspect_err(|_| state.metrics.record_signature_failure())
+            .context("Signature verification failed")?;
+
+        let is_new = Self::register_public_key(state, &client_id, &public_key_bytes).await?;
+        Ok((client_id, is_new))
+    }
+
+    /// Verify many requests signed with the same Ed25519 key in one batch verification,
+    /// registering the key on the client's first request
+    /// `requests` pairs each message with its signature. Fails if any signature is invalid.
+    /// Returns the client ID and whether the client is new
+    pub async fn verify_request_signatures(
+        state: &web::Data<AppState>,
+        requests: &[(Vec<u8>, Vec<u8>)],
         public_key_hex: &str,
     ) -> Result<(String, bool)> {
         let public_key_bytes =
             hex::decode(public_key_hex.trim()).context("Failed to decode public key")?;
-        let public_key =
+        let verifying_key =
             public_key_from_bytes(&public_key_bytes).context("Failed to parse public key")?;
+        let public_key = PublicKey::Ed25519(verifying_key);
+        // Never register a degenerate key, even if a caller skipped validate_public_key
+        reject_weak_key(&public_key)?;
 
-        let client_id = compute_client_id(&public_key);
+        let client_id = public_key.client_id();
 
-        verify_signature(&public_key, message, signature)
+        let signatures = requests
+            .iter()
+            .map(|(_, signature)| {
+                let signature: [u8; SignatureScheme::SIGNATURE_LEN] = signature
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("Invalid signature length"))?;
+                Ok(Signature::from_bytes(&signature))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let items: Vec<(&VerifyingKey, &[u8], &Signature)> = requests
+            .iter()
+            .zip(&signatures)
+            .map(|((message, _), signature)| (&verifying_key, message.as_slice(), signature))
+            .collect();
+        verify_batch(&items)
+            .inspect_err(|_| state.metrics.record_signature_failure())
             .context("Signature verification failed")?;
 
-        let is_new = state
+        let is_new = Self::register_public_key(state, &client_id, &public_key_bytes).await?;
+        Ok((client_id, is_new))
+    }
+
+    /// Store a verified client's public key on its first request
+    /// Returns whether the client is new
+    async fn register_public_key(
+        state: &web::Data<AppState>,
+        client_id: &str,
+        public_key_bytes: &[u8],
+    ) -> Result<bool> {
+        let registered_key = state
             .storage
-            .load_public_key(&client_id)
+            .load_public_key(client_id)
             .await
-            .context("Failed to check if client exists")?
-            .is_none();
+            .context("Failed to check if client exists")?;
+        // The id is derived from the key, so this only fails if the stored key was
+        // tampered with or the derivation ever changes
+        if let Some(registered_key) = &registered_key {
+            anyhow::ensure!(
+                registered_key == public_key_bytes,
+                "Public key does not match the key registered for client {}",
+                client_id
+            );
+        }
+        let is_new = registered_key.is_none();
 
         if is_new {
             state
                 .storage
-                .store_public_key(&client_id, &public_key_bytes)
+                .store_public_key(client_id, public_key_bytes)
                 .await
                 .context("Failed to store public key")?;
         }
 
-        Ok((client_id, is_new))
+        Ok(is_new)
     }
 
     /// Verify request signature using client_id for key lookup
+    /// The scheme is that of the registered key, told apart by the key's length
     pub async fn verify_request_signature_with_client_id(
         state: &web::Data<AppState>,
         client_id: &str,
         message: &[u8],
-        signature: &Signature,
+        signature: &[u8],
     ) -> Result<()> {
         let public_key_bytes = state
             .storage
@@ -63,23 +165,51 @@ impl AuthVerifier {
                 )
             })?;
 
-        let public_key =
-            public_key_from_bytes(&public_key_bytes).context("Failed to parse public key")?;
+        let public_key = SignatureScheme::of_public_key(&public_key_bytes)
+            .and_then(|scheme| scheme.public_key_from_bytes(&public_key_bytes))
+            .context("Failed to parse public key")?;
 
-        verify_signature(&public_key, message, signature)
+        public_key
+            .verify(message, signature)
+            .inspect_err(|_| state.metrics.record_signature_failure())
             .context("Signature verification failed")?;
 
         Ok(())
     }
 
+    /// Parse the signature scheme a request names
+    pub fn parse_scheme(scheme: &str) -> Result<SignatureScheme> {
+        scheme.trim().parse()
+    }
+
     /// Parse signature from hex string
-    pub fn parse_signature(signature_hex: &str) -> Result<Signature> {
+    pub fn parse_signature(signature_hex: &str) -> Result<Vec<u8>> {
         let signature_bytes =
             hex::decode(signature_hex.trim()).context("Failed to decode signature")?;
-        let signature_array: [u8; 64] = signature_bytes
+        anyhow::ensure!(
+            signature_bytes.len() == SignatureScheme::SIGNATURE_LEN,
+            "Invalid signature length"
+        );
+        Ok(signature_bytes)
+    }
+
+    /// Parse a request nonce from hex
+    pub fn parse_nonce(nonce_hex: &str) -> Result<[u8; NONCE_BYTES]> {
+        let nonce_bytes = hex::decode(nonce_hex.trim()).context("Failed to decode nonce")?;
+        nonce_bytes
             .try_into()
-            .map_err(|_| anyhow::anyhow!("Invalid signature length"))?;
-        Ok(Signature::from_bytes(&signature_array))
+            .map_err(|_| anyhow::anyhow!("Invalid nonce length: expected {} bytes", NONCE_BYTES))
+    }
+
+    /// Accept a signed request's nonce once; a second request with it is a replay
+    /// Call only after the signature is verified, so unsigned requests cannot use up nonces
+    pub fn check_nonce(state: &AppState, nonce: [u8; NONCE_BYTES]) -> Result<()> {
+        anyhow::ensure!(
+            state.nonces.insert(nonce),
+            "Nonce {} was already used; request replayed",
+            hex::encode(nonce)
+        );
+        Ok(())
     }
 
     /// Validate timestamp to prevent replay attacks
@@ -103,6 +233,17 @@ impl AuthVerifier {
             .context("Failed to get current time")?
             .as_millis() as u64;
 
+        // Catch unit bugs before they surface as a confusing too-old/too-future rejection
+        if request_timestamp_ms.saturating_mul(TIMESTAMP_UNIT_TOLERANCE) < now
+            || request_timestamp_ms / TIMESTAMP_UNIT_TOLERANCE > now
+        {
+            return Err(TimestampUnitError {
+                timestamp: request_timestamp_ms,
+                now_ms: now,
+            }
+            .into());
+        }
+
         let request_timestamp_seconds = request_timestamp_ms / 1000;
         let now_seconds = now / 1000;
 
@@ -142,20 +283,177 @@ impl AuthVerifier {
     /// - Key is valid hex encoding
     /// - Key length is exactly 32 bytes (Ed25519 public key size)
     /// - Key can be parsed as a valid Ed25519 VerifyingKey
+    /// - Key is not a small-order point (including the identity and the all-zero encoding)
     pub fn validate_public_key(public_key_hex: &str) -> Result<()> {
+        Self::validate_scheme_public_key(SignatureScheme::Ed25519, public_key_hex)
+    }
+
+    /// Validate public key format for `scheme`: valid hex of a key of the scheme's
+    /// length that parses as a key of the scheme and is not weak
+    pub fn validate_scheme_public_key(scheme: SignatureScheme, public_key_hex: &str) -> Result<()> {
         let public_key_bytes =
             hex::decode(public_key_hex.trim()).context("Failed to decode public key hex")?;
 
-        if public_key_bytes.len() != 32 {
+        if public_key_bytes.len() != scheme.public_key_len() {
             anyhow::bail!(
-                "Invalid public key length: expected 32 bytes (Ed25519), got {} bytes",
+                "Invalid public key length: expected {} bytes ({}), got {} bytes",
+                scheme.public_key_len(),
+                scheme,
                 public_key_bytes.len()
             );
         }
 
-        // Try to parse as Ed25519 key - this validates the key format
-        public_key_from_bytes(&public_key_bytes).context("Invalid Ed25519 public key format")?;
+        // Parse as a key of the scheme - this validates the key format
+        let public_key = scheme
+            .public_key_from_bytes(&public_key_bytes)
+            .with_context(|| format!("Invalid {} public key format", scheme))?;
+        reject_weak_key(&public_key)
+    }
+}
+
+/// Reject small-order public keys
+/// Signatures under such a key can be produced without any secret key for some messages,
+/// so a client registered with one would have no real key binding
+fn reject_weak_key(public_key: &PublicKey) -> Result<()> {
+    if public_key.is_weak() {
+        anyhow::bail!("Weak public key rejected: small-order Ed25519 point");
+    }
+    Ok(())
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{test_state, SMALL_ORDER_KEYS};
+    use common::utils::get_current_timestamp_ms;
+
+    #[test]
+    fn test_small_order_public_keys_rejected() {
+        for key in SMALL_ORDER_KEYS {
+            let err = AuthVerifier::validate_public_key(key).unwrap_err();
+            assert!(
+                format!("{:#}", err).contains("Weak public key rejected"),
+                "{}: {:#}",
+                key,
+                err
+            );
+        }
+
+        let (_, verifying_key) = crypto::generate_keypair();
+        assert!(AuthVerifier::validate_public_key(&hex::encode(verifying_key.as_bytes())).is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_small_order_public_key_not_registered() {
+        let (state, _dir) = test_state();
+        let signature = [0u8; 64];
+        for key in SMALL_ORDER_KEYS {
+            let err = AuthVerifier::verify_request_signature(
+                &state,
+                SignatureScheme::Ed25519,
+                b"message",
+                &signature,
+                key,
+            )
+            .await
+            .unwrap_err();
+            assert!(format!("{:#}", err).contains("Weak public key rejected"));
+
+            let client_id = crypto::compute_client_id_from_bytes(&hex::decode(key).unwrap());
+            assert!(state
+                .storage
+                .load_public_key(&client_id)
+                .await
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    /// Register a key of `scheme` by signing, then verify by client ID alone
+    async fn verify_with_scheme(state: &web::Data<AppState>, scheme: SignatureScheme) {
+        let secret_key = [5u8; 32];
+        let public_key = scheme.public_key_from_secret(&secret_key).unwrap();
+        let public_key_hex = hex::encode(public_key.to_bytes());
+        let signature = scheme.sign(&secret_key, b"message").unwrap();
+
+        AuthVerifier::validate_scheme_public_key(scheme, &public_key_hex).unwrap();
+        let (client_id, is_new) = AuthVerifier::verify_request_signature(
+            state,
+            scheme,
+            b"message",
+            &signature,
+            &public_key_hex,
+        )
+        .await
+        .unwrap();
+        assert_eq!(client_id, public_key.client_id());
+        assert!(is_new);
+
+        // The registered key's scheme is used for requests that only name the client
+        AuthVerifier::verify_request_signature_with_client_id(
+            state, &client_id, b"message", &signature,
+        )
+        .await
+        .unwrap();
+        assert!(AuthVerifier::verify_request_signature_with_client_id(
+            state,
+            &client_id,
+            b"other message",
+            &signature,
+        )
+        .await
+        .is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_verification_dispatches_on_scheme() {
+        let (state, _dir) = test_state();
+        verify_with_scheme(&state, SignatureScheme::Ed25519).await;
+        assert_eq!(
+            AuthVerifier::parse_scheme(common::DEFAULT_SIGNATURE_SCHEME).unwrap(),
+            SignatureScheme::Ed25519
+        );
+        assert!(AuthVerifier::parse_scheme("rsa").is_err());
+
+        #[cfg(feature = "secp256k1")]
+        {
+            verify_with_scheme(&state, SignatureScheme::Secp256k1).await;
+
+            // A key is only accepted under its own scheme
+            let ed25519_key = SignatureScheme::Ed25519
+                .public_key_from_secret(&[5u8; 32])
+                .unwrap();
+            let err = AuthVerifier::validate_scheme_public_key(
+                SignatureScheme::Secp256k1,
+                &hex::encode(ed25519_key.to_bytes()),
+            )
+            .unwrap_err();
+            assert!(err.to_string().contains("expected 33 bytes (secp256k1)"));
+        }
+    }
+
+    fn unit_error(timestamp: u64) -> Option<TimestampUnitError> {
+        AuthVerifier::validate_timestamp_default(timestamp)
+            .err()
+            .and_then(|e| e.downcast_ref::<TimestampUnitError>().cloned())
+    }
+
+    #[test]
+    fn test_timestamp_in_wrong_unit_is_reported_as_such() {
+        let now_ms = get_current_timestamp_ms();
+        assert!(AuthVerifier::validate_timestamp_default(now_ms).is_ok());
+
+        let seconds = unit_error(now_ms / 1000).expect("seconds should be a unit error");
+        assert!(seconds.to_string().contains("seconds"));
+        let nanos = unit_error(now_ms * 1_000_000).expect("nanoseconds should be a unit error");
+        assert!(nanos.to_string().contains("nanoseconds"));
+        assert!(unit_error(now_ms * 1000).is_some());
+
+        // Stale or skewed millisecond timestamps keep their ordinary errors
+        let stale = AuthVerifier::validate_timestamp_default(now_ms - 3_600_000).unwrap_err();
+        assert!(stale.downcast_ref::<TimestampUnitError>().is_none());
+        assert!(stale.to_string().contains("too old"));
+        assert!(unit_error(now_ms + 3_600_000).is_none());
+        assert!(unit_error(0).is_some());
     }
 }
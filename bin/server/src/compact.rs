@@ -0,0 +1,117 @@
+use crate::audit::{audit_batch, BatchAudit};
+use anyhow::Context;
+use storage::{CompactionReport, Storage};
+
+/// Outcome of compacting a batch and auditing it afterwards
+#[derive(Debug)]
+pub struct BatchCompaction {
+    pub report: CompactionReport,
+    /// Root stored for the batch before compaction
+    pub root_before: [u8; 32],
+    /// Audit of the compacted batch
+    pub audit: BatchAudit,
+}
+
+impl BatchCompaction {
+    /// The batch still proves every file against the root it had before compaction
+    pub fn passed(&self) -> bool {
+        self.audit.passed() && self.audit.stored_root == self.root_before
+    }
+
+    /// Print what was removed followed by the audit of the result
+    pub fn print(&self) {
+        println!(
+            "Compaction of batch {} (client {})",
+            self.audit.batch_id, self.audit.client_id
+        );
+        println!(
+            "  Duplicate metadata entries removed: {}",
+            self.report.duplicate_entries_removed
+        );
+        println!(
+            "  Temp files removed:                 {}",
+            self.report.temp_files_removed
+        );
+        println!(
+            "  Orphaned rows removed:              {}",
+            self.report.orphaned_rows_removed
+        );
+        println!(
+            "  Unreferenced content removed:       {}",
+            self.report.orphaned_blobs_removed
+        );
+        self.audit.print();
+        if self.audit.stored_root != self.root_before {
+            println!(
+                "✗ Root changed from {} during compaction",
+                hex::encode(self.root_before)
+            );
+        }
+    }
+}
+
+/// Compact a batch, then audit it to confirm every file still proves against the same root
+pub async fn compact_batch(
+    storage: &dyn Storage,
+    client_id: &str,
+    batch_id: &str,
+) -> anyhow::Result<BatchCompaction> {
+    let root_before = storage
+        .load_merkle_tree(client_id, batch_id)
+        .await
+        .context("Failed to load Merkle tree")?
+        .ok_or_else(|| anyhow::anyhow!("Batch {} has no Merkle tree", batch_id))?
+        .root_hash();
+
+    let report = storage
+        .compact_batch(client_id, batch_id)
+        .await
+        .context("Failed to compact batch")?;
+    let audit = audit_batch(storage, client_id, batch_id).await?;
+
+    Ok(BatchCompaction {
+        report,
+        root_before,
+        audit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDataDir;
+    use storage::filesystem::FilesystemStorage;
+
+    #[tokio::test]
+    async fn test_compacted_batch_still_verifies() {
+        let dir = TempDataDir::new();
+        // Every filename lands in metadata.json, so it can be hand-edited below
+        let storage = FilesystemStorage::new(dir.0.clone()).with_metadata_log_limit(1);
+        for (name, content) in [("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")] {
+            storage
+                .store_file_and_update_tree("client", "batch", name, content)
+                .await
+                .unwrap();
+        }
+        // Overwrites, a duplicated entry and a temp file from an interrupted write
+        storage
+            .store_file_and_update_tree("client", "batch", "b.txt", b"B")
+            .await
+            .unwrap();
+        let batch_dir = dir.0.join("client").join("batch");
+        let metadata_file = batch_dir.join("metadata.json");
+        let metadata = std::fs::read_to_string(&metadata_file)
+            .unwrap()
+            .replace(r#""c.txt""#, r#""c.txt","c.txt""#);
+        std::fs::write(&metadata_file, metadata).unwrap();
+        std::fs::write(batch_dir.join(".d.txt.99.1.tmp"), b"partial").unwrap();
+
+        let compaction = compact_batch(&storage, "client", "batch").await.unwrap();
+        assert!(compaction.passed());
+        assert_eq!(compaction.report.duplicate_entries_removed, 1);
+        assert_eq!(compaction.report.temp_files_removed, 1);
+        assert_eq!(compaction.audit.files.len(), 3);
+
+        assert!(compact_batch(&storage, "client", "missing").await.is_err());
+    }
+}
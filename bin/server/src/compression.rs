@@ -0,0 +1,203 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderValue, ACCEPT_ENCODING};
+use actix_web::middleware::Next;
+
+/// Largest download whose content is still compressed; bigger bodies are sent as-is
+/// so a single request cannot tie up a worker compressing megabytes of data
+pub const MAX_COMPRESSIBLE_CONTENT_BYTES: usize = 4 * 1024 * 1024;
+
+/// Magic numbers of common formats that are already compressed
+const COMPRESSED_MAGIC: &[&[u8]] = &[
+    b"\x1f\x8b",           // gzip
+    b"PK\x03\x04",         // zip and zip-based formats (docx, jar, ...)
+    b"\x89PNG",            // png
+    b"\xff\xd8\xff",       // jpeg
+    b"GIF8",               // gif
+    b"\x28\xb5\x2f\xfd",   // zstd
+    b"BZh",                // bzip2
+    b"\xfd7zXZ\x00",       // xz
+    b"7z\xbc\xaf\x27\x1c", // 7z
+    b"%PDF",               // pdf (streams are usually deflated)
+];
+
+/// Response compression offered to clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseCompression {
+    None,
+    Gzip,
+    Brotli,
+}
+
+impl ResponseCompression {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(Self::None),
+            "gzip" => Some(Self::Gzip),
+            "br" => Some(Self::Brotli),
+            _ => None,
+        }
+    }
+
+    pub fn is_enabled(self) -> bool {
+        self != Self::None
+    }
+
+    /// Content coding token as used in `Accept-Encoding`
+    fn token(self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some("gzip"),
+            Self::Brotli => Some("br"),
+        }
+    }
+}
+
+/// Whether `accept_encoding` lists `token` (or `*`) without a zero quality value
+fn accepts(accept_encoding: &str, token: &str) -> bool {
+    accept_encoding.split(',').any(|entry| {
+        let mut parts = entry.split(';').map(str::trim);
+        let coding = parts.next().unwrap_or_default();
+        if !coding.eq_ignore_ascii_case(token) && coding != "*" {
+            return false;
+        }
+        !parts.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        })
+    })
+}
+
+/// Narrow the request's `Accept-Encoding` to the configured compression so the
+/// compression middleware never picks an encoding the server was not asked to use
+pub async fn restrict_accept_encoding(
+    compression: ResponseCompression,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if let Some(token) = compression.token() {
+        let accepted = req
+            .headers()
+            .get(ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| accepts(value, token));
+
+        let headers = req.headers_mut();
+        if accepted {
+            headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(token));
+        } else {
+            headers.remove(ACCEPT_ENCODING);
+        }
+    }
+    next.call(req).await
+}
+
+/// Whether compressing `content` is not worth the CPU: too large, or already
+/// in a compressed format
+pub fn is_incompressible(content: &[u8]) -> bool {
+    content.len() > MAX_COMPRESSIBLE_CONTENT_BYTES
+        || COMPRESSED_MAGIC
+            .iter()
+            .any(|magic| content.starts_with(magic))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::download::download;
+    use crate::test_utils::test_state;
+    use actix_web::http::header::CONTENT_ENCODING;
+    use actix_web::middleware::{from_fn, Compress, Condition};
+    use actix_web::{test, App};
+
+    /// Download a file from a public batch with the given compression and
+    /// `Accept-Encoding`, returning the response's content encoding and body size
+    async fn download_size(
+        compression: ResponseCompression,
+        accept_encoding: &str,
+        content: &[u8],
+    ) -> (Option<String>, usize) {
+        let (state, _dir) = test_state();
+        state
+            .storage
+            .store_file_and_update_tree("client", "batch", "a.txt", content)
+            .await
+            .unwrap();
+        state
+            .storage
+            .set_batch_public("client", "batch", true)
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .wrap(Condition::new(
+                    compression.is_enabled(),
+                    Compress::default(),
+                ))
+                .wrap(from_fn(move |req, next| {
+                    restrict_accept_encoding(compression, req, next)
+                }))
+                .service(download),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/download?filename=a.txt&batch_id=batch&client_id=client")
+            .insert_header((ACCEPT_ENCODING, accept_encoding))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let encoding = resp
+            .headers()
+            .get(CONTENT_ENCODING)
+            .map(|v| v.to_str().unwrap().to_string());
+        let body = test::read_body(resp).await;
+        (encoding, body.len())
+    }
+
+    #[actix_web::test]
+    async fn test_gzip_shrinks_compressible_download() {
+        let content = b"verifiable storage ".repeat(1000);
+
+        let (encoding, plain) =
+            download_size(ResponseCompression::Gzip, "identity", &content).await;
+        assert_eq!(encoding, None);
+
+        let (encoding, gzipped) =
+            download_size(ResponseCompression::Gzip, "gzip, deflate", &content).await;
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+        assert!(gzipped < plain / 4, "gzip {} vs plain {}", gzipped, plain);
+    }
+
+    #[actix_web::test]
+    async fn test_compression_follows_configuration() {
+        let content = b"verifiable storage ".repeat(1000);
+
+        // Brotli is accepted by the client but only gzip is configured
+        let (encoding, _) = download_size(ResponseCompression::Gzip, "br", &content).await;
+        assert_eq!(encoding, None);
+
+        let (encoding, _) = download_size(ResponseCompression::Brotli, "gzip, br", &content).await;
+        assert_eq!(encoding.as_deref(), Some("br"));
+
+        let (encoding, _) = download_size(ResponseCompression::None, "gzip, br", &content).await;
+        assert_eq!(encoding, None);
+
+        let (encoding, _) =
+            download_size(ResponseCompression::Gzip, "gzip;q=0, br", &content).await;
+        assert_eq!(encoding, None);
+    }
+
+    #[actix_web::test]
+    async fn test_already_compressed_content_is_not_recompressed() {
+        let mut content = b"\x1f\x8b\x08\x00".to_vec();
+        content.extend(b"pretend gzip payload ".repeat(1000));
+
+        let (encoding, _) = download_size(ResponseCompression::Gzip, "gzip", &content).await;
+        assert_ne!(encoding.as_deref(), Some("gzip"));
+    }
+}
@@ -1,9 +1,19 @@
+use crate::compression::ResponseCompression;
 use crate::constants::{
-    DEFAULT_DATA_DIR, DEFAULT_HOST, DEFAULT_PORT, STORAGE_TYPE_DATABASE, STORAGE_TYPE_FILESYSTEM,
+    DEFAULT_BACKLOG, DEFAULT_CLIENT_REQUEST_TIMEOUT_MS, DEFAULT_DATA_DIR, DEFAULT_DURABILITY,
+    DEFAULT_HOST, DEFAULT_INGEST_QUEUE_SIZE, DEFAULT_KEEP_ALIVE_SECONDS,
+    DEFAULT_MAX_FILES_PER_BATCH, DEFAULT_MAX_UPLOAD_SIZE_BYTES, DEFAULT_PORT,
+    DEFAULT_RESPONSE_COMPRESSION, DEFAULT_SCRUB_FILES_PER_TICK, DEFAULT_SLOW_OP_THRESHOLD_MS,
+    DEFAULT_SQLITE_PATH, STORAGE_TYPE_DATABASE, STORAGE_TYPE_FILESYSTEM, STORAGE_TYPE_MEMORY,
+    STORAGE_TYPE_S3, STORAGE_TYPE_SQLITE,
 };
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command};
 use std::path::PathBuf;
-use storage::DatabaseRetryConfig;
+use std::time::Duration;
+use storage::{
+    Compression, CompressionConfig, DatabasePoolConfig, DatabaseRetryConfig, Durability,
+    EncryptionConfig,
+};
 use tracing::error;
 
 /// Server configuration
@@ -21,6 +31,75 @@ pub struct ServerConfig {
     pub database_url: Option<String>,
     /// Database retry configuration
     pub database_retry_config: DatabaseRetryConfig,
+    /// Database connection pool configuration
+    pub database_pool_config: DatabasePoolConfig,
+    /// Directory for file content when database storage keeps only references
+    pub db_external_content_dir: Option<PathBuf>,
+    /// Database file for SQLite storage
+    pub sqlite_path: Option<PathBuf>,
+    /// Bucket for S3 storage
+    pub s3_bucket: Option<String>,
+    /// Endpoint URL of an S3-compatible store (e.g. MinIO); AWS when unset
+    pub s3_endpoint: Option<String>,
+    /// Region for S3 storage; taken from the AWS environment when unset
+    pub s3_region: Option<String>,
+    /// Encryption of stored file content at rest (`SERVER_ENCRYPTION_KEY`); off when unset
+    pub encryption: Option<EncryptionConfig>,
+    /// Compression of stored file content at rest; off when unset
+    pub storage_compression: Option<CompressionConfig>,
+    /// How filesystem storage flushes uploads to disk
+    pub durability: Durability,
+    /// Whether the filesystem data directory may be a symlink
+    pub follow_data_symlink: bool,
+    /// Interval between scrubber ticks; the scrubber is disabled when unset
+    pub scrub_interval: Option<Duration>,
+    /// Maximum number of files the scrubber checks per tick
+    pub scrub_files_per_tick: usize,
+    /// Compression applied to responses for clients that accept it
+    pub response_compression: ResponseCompression,
+    /// Storage calls taking at least this long are logged as slow
+    pub slow_op_threshold: Duration,
+    /// Most files a single batch may hold
+    pub max_files_per_batch: usize,
+    /// Largest file in bytes an upload or batch replacement may carry
+    pub max_upload_size_bytes: usize,
+    /// Most files a batch may hold to have proofs generated from it; unlimited when unset
+    pub max_proof_batch_files: Option<usize>,
+    /// Most downloads a client may have in flight at once (unlimited when `None`)
+    pub max_concurrent_downloads_per_client: Option<usize>,
+    /// Uploads and downloads per second each client may sustain (unlimited when `None`)
+    pub rate_limit_rps: Option<f64>,
+    /// Requests a client may make at once before `rate_limit_rps` applies
+    pub rate_limit_burst: usize,
+    /// Width in bytes Merkle node hashes are cut to in proofs and roots; full width when unset
+    pub hash_truncation_bytes: Option<usize>,
+    /// Number of upload ingestion workers; uploads are stored inline when unset
+    pub ingest_workers: Option<usize>,
+    /// Most uploads the ingestion pipeline queues before rejecting new ones
+    pub ingest_queue_size: usize,
+    /// Answer queued uploads with 202 Accepted instead of waiting for them to be stored
+    pub async_uploads: bool,
+    /// Reject every storage write (uploads, renames, sharing changes, key registration)
+    pub read_only: bool,
+    /// Serve developer aids such as `POST /debug/sign-preview`
+    pub enable_debug_endpoints: bool,
+    /// Audit this batch and exit instead of serving requests
+    pub audit_batch: Option<BatchTarget>,
+    /// Compact this batch and exit instead of serving requests
+    pub compact_batch: Option<BatchTarget>,
+    /// Maximum number of pending connections on the listening socket
+    pub backlog: u32,
+    /// How long idle connections are kept open; zero disables keep-alive
+    pub keep_alive: Duration,
+    /// How long a client has to send the request headers; zero disables the timeout
+    pub client_request_timeout: Duration,
+}
+
+/// Batch targeted by the `audit-batch` or `compact-batch` subcommand
+#[derive(Debug, Clone)]
+pub struct BatchTarget {
+    pub client_id: String,
+    pub batch_id: String,
 }
 
 /// Storage backend type
@@ -28,6 +107,9 @@ pub struct ServerConfig {
 pub enum StorageType {
     Filesystem,
     Database,
+    Sqlite,
+    Memory,
+    S3,
 }
 
 impl ServerConfig {
@@ -37,7 +119,7 @@ impl ServerConfig {
                 Arg::new("storage")
                     .long("storage")
                     .value_name("TYPE")
-                    .help("Storage backend type: 'fs' for filesystem or 'db' for database")
+                    .help("Storage backend type: 'fs' for filesystem, 'db' for database, 'sqlite' for a SQLite database file, 'mem' for in-memory (lost on restart) or 's3' for S3-compatible object storage")
                     .default_value(STORAGE_TYPE_FILESYSTEM),
             )
             .arg(
@@ -53,6 +135,57 @@ impl ServerConfig {
                     .value_name("URL")
                     .help("Database URL for database storage (can also use DATABASE_URL env var)"),
             )
+            .arg(
+                Arg::new("db-external-content-dir")
+                    .long("db-external-content-dir")
+                    .value_name("DIR")
+                    .help(
+                        "Store file content in DIR instead of the database (database storage only)",
+                    ),
+            )
+            .arg(
+                Arg::new("sqlite-path")
+                    .long("sqlite-path")
+                    .value_name("FILE")
+                    .help("Database file for SQLite storage, created if missing (default: server_data.db)"),
+            )
+            .arg(
+                Arg::new("s3-bucket")
+                    .long("s3-bucket")
+                    .value_name("BUCKET")
+                    .help("Bucket for S3 storage (required with --storage s3)"),
+            )
+            .arg(
+                Arg::new("s3-endpoint")
+                    .long("s3-endpoint")
+                    .value_name("URL")
+                    .help("Endpoint of an S3-compatible store such as MinIO (S3 storage only; AWS when unset)"),
+            )
+            .arg(
+                Arg::new("s3-region")
+                    .long("s3-region")
+                    .value_name("REGION")
+                    .help("Region for S3 storage (defaults to the AWS_REGION env var or profile)"),
+            )
+            .arg(
+                Arg::new("storage-compression")
+                    .long("storage-compression")
+                    .value_name("ALGORITHM")
+                    .help("Compress stored file content: 'gzip', 'zstd' or 'none' (filesystem and database storage; files stored under other settings stay readable)"),
+            )
+            .arg(
+                Arg::new("storage-compression-level")
+                    .long("storage-compression-level")
+                    .value_name("LEVEL")
+                    .help("Level for --storage-compression: 0-9 for gzip (default 6), 1-22 for zstd (default 3)"),
+            )
+            .arg(
+                Arg::new("durability")
+                    .long("durability")
+                    .value_name("MODE")
+                    .help("Filesystem flushing: 'strict' fsyncs every write, 'relaxed' fsyncs the batch directory once per upload (faster, may lose recent uploads on power loss)")
+                    .default_value(DEFAULT_DURABILITY),
+            )
             .arg(
                 Arg::new("port")
                     .long("port")
@@ -65,6 +198,175 @@ impl ServerConfig {
                     .value_name("HOST")
                     .help("Server host (default: 0.0.0.0, or SERVER_HOST env var)"),
             )
+            .arg(
+                Arg::new("scrub-interval")
+                    .long("scrub-interval")
+                    .value_name("SECONDS")
+                    .help(
+                        "Run the background integrity scrubber every SECONDS (disabled by default)",
+                    ),
+            )
+            .arg(
+                Arg::new("scrub-files-per-tick")
+                    .long("scrub-files-per-tick")
+                    .value_name("N")
+                    .help("Maximum number of files the scrubber checks per tick")
+                    .default_value(DEFAULT_SCRUB_FILES_PER_TICK),
+            )
+            .arg(
+                Arg::new("response-compression")
+                    .long("response-compression")
+                    .value_name("ENCODING")
+                    .help("Response compression: 'none', 'gzip' or 'br'")
+                    .default_value(DEFAULT_RESPONSE_COMPRESSION),
+            )
+            .arg(
+                Arg::new("slow-op-threshold-ms")
+                    .long("slow-op-threshold-ms")
+                    .value_name("MS")
+                    .help("Log a warning for storage calls taking at least MS milliseconds")
+                    .default_value(DEFAULT_SLOW_OP_THRESHOLD_MS),
+            )
+            .arg(
+                Arg::new("max-files-per-batch")
+                    .long("max-files-per-batch")
+                    .value_name("COUNT")
+                    .help("Reject uploads that would add a file to a batch already holding COUNT files")
+                    .default_value(DEFAULT_MAX_FILES_PER_BATCH),
+            )
+            .arg(
+                Arg::new("max-upload-size")
+                    .long("max-upload-size")
+                    .value_name("BYTES")
+                    .help("Reject uploaded files larger than BYTES with 413; multipart uploads are streamed to disk, so large limits do not need memory (default: 10485760)"),
+            )
+            .arg(
+                Arg::new("max-proof-batch-files")
+                    .long("max-proof-batch-files")
+                    .value_name("COUNT")
+                    .help("Answer downloads from batches holding more than COUNT files with 507 instead of loading their tree (unlimited by default)"),
+            )
+            .arg(
+                Arg::new("max-concurrent-downloads-per-client")
+                    .long("max-concurrent-downloads-per-client")
+                    .value_name("COUNT")
+                    .help("Answer a client's downloads with 429 while COUNT of its downloads are in flight (unlimited by default)"),
+            )
+            .arg(
+                Arg::new("rate-limit-rps")
+                    .long("rate-limit-rps")
+                    .value_name("RATE")
+                    .help("Answer a client's uploads and downloads with 429 beyond RATE requests per second, counted once its signature is verified (unlimited by default)"),
+            )
+            .arg(
+                Arg::new("rate-limit-burst")
+                    .long("rate-limit-burst")
+                    .value_name("COUNT")
+                    .help("Requests a client may make in a burst before --rate-limit-rps applies (defaults to RATE rounded up)"),
+            )
+            .arg(
+                Arg::new("hash-truncation-bytes")
+                    .long("hash-truncation-bytes")
+                    .value_name("BYTES")
+                    .help("Cut Merkle node hashes to BYTES (16-31) for shorter proofs, at the cost of collision resistance; clients must pass the same value (full 32-byte hashes by default)"),
+            )
+            .arg(
+                Arg::new("ingest-workers")
+                    .long("ingest-workers")
+                    .value_name("COUNT")
+                    .help("Store uploads through a pool of COUNT workers fed by a bounded queue instead of inside each request (disabled by default)"),
+            )
+            .arg(
+                Arg::new("ingest-queue-size")
+                    .long("ingest-queue-size")
+                    .value_name("COUNT")
+                    .help("Uploads waiting for an ingestion worker before new ones are answered with 503 (default: 64)"),
+            )
+            .arg(
+                Arg::new("async-uploads")
+                    .long("async-uploads")
+                    .action(ArgAction::SetTrue)
+                    .requires("ingest-workers")
+                    .help("Answer uploads with 202 Accepted and a status URL once queued, instead of 200 once stored"),
+            )
+            .arg(
+                Arg::new("no-follow-data-symlink")
+                    .long("no-follow-data-symlink")
+                    .help("Refuse to start if the data directory is a symlink")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("read-only")
+                    .long("read-only")
+                    .help("Serve existing batches but reject every storage write")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("enable-debug-endpoints")
+                    .long("enable-debug-endpoints")
+                    .help("Serve debugging aids for client integrators (POST /debug/sign-preview); never enable in production")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("backlog")
+                    .long("backlog")
+                    .value_name("COUNT")
+                    .help("Maximum number of pending connections on the listening socket")
+                    .default_value(DEFAULT_BACKLOG),
+            )
+            .arg(
+                Arg::new("keep-alive-seconds")
+                    .long("keep-alive-seconds")
+                    .value_name("SECONDS")
+                    .help("Close idle connections after SECONDS; 0 disables keep-alive")
+                    .default_value(DEFAULT_KEEP_ALIVE_SECONDS),
+            )
+            .arg(
+                Arg::new("client-request-timeout")
+                    .long("client-request-timeout")
+                    .value_name("MS")
+                    .help("Drop connections that don't send request headers within MS milliseconds; 0 disables the timeout")
+                    .default_value(DEFAULT_CLIENT_REQUEST_TIMEOUT_MS),
+            )
+            .subcommand(
+                Command::new("audit-batch")
+                    .about("Generate and verify a proof for every file of a batch, then exit")
+                    .arg(
+                        Arg::new("client-id")
+                            .long("client-id")
+                            .value_name("ID")
+                            .help("Client ID owning the batch")
+                            .required(true),
+                    )
+                    .arg(
+                        Arg::new("batch-id")
+                            .long("batch-id")
+                            .value_name("ID")
+                            .help("Batch ID to audit")
+                            .required(true),
+                    ),
+            )
+            .subcommand(
+                Command::new("compact-batch")
+                    .about(
+                        "Rewrite a batch's metadata canonically, remove leftovers and check \
+                         its root is unchanged, then exit",
+                    )
+                    .arg(
+                        Arg::new("client-id")
+                            .long("client-id")
+                            .value_name("ID")
+                            .help("Client ID owning the batch")
+                            .required(true),
+                    )
+                    .arg(
+                        Arg::new("batch-id")
+                            .long("batch-id")
+                            .value_name("ID")
+                            .help("Batch ID to compact")
+                            .required(true),
+                    ),
+            )
             .get_matches();
 
         // Determine storage type
@@ -75,12 +377,20 @@ impl ServerConfig {
         let storage_type = match storage_type_str {
             STORAGE_TYPE_DATABASE => StorageType::Database,
             STORAGE_TYPE_FILESYSTEM => StorageType::Filesystem,
+            STORAGE_TYPE_SQLITE => StorageType::Sqlite,
+            STORAGE_TYPE_MEMORY => StorageType::Memory,
+            STORAGE_TYPE_S3 => StorageType::S3,
             _ => {
                 return Err(std::io::Error::new(
                     std::io::ErrorKind::InvalidInput,
                     format!(
-                        "Invalid storage type: {}. Must be '{}' or '{}'",
-                        storage_type_str, STORAGE_TYPE_FILESYSTEM, STORAGE_TYPE_DATABASE
+                        "Invalid storage type: {}. Must be '{}', '{}', '{}', '{}' or '{}'",
+                        storage_type_str,
+                        STORAGE_TYPE_FILESYSTEM,
+                        STORAGE_TYPE_DATABASE,
+                        STORAGE_TYPE_SQLITE,
+                        STORAGE_TYPE_MEMORY,
+                        STORAGE_TYPE_S3
                     ),
                 ));
             }
@@ -111,6 +421,112 @@ impl ServerConfig {
             None
         };
 
+        let db_external_content_dir = matches
+            .get_one::<String>("db-external-content-dir")
+            .map(PathBuf::from);
+        if db_external_content_dir.is_some() && storage_type != StorageType::Database {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--db-external-content-dir requires database storage",
+            ));
+        }
+
+        let sqlite_path = matches.get_one::<String>("sqlite-path").map(PathBuf::from);
+        let sqlite_path = if storage_type == StorageType::Sqlite {
+            Some(sqlite_path.unwrap_or_else(|| PathBuf::from(DEFAULT_SQLITE_PATH)))
+        } else if sqlite_path.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--sqlite-path requires SQLite storage",
+            ));
+        } else {
+            None
+        };
+
+        let s3_bucket = matches.get_one::<String>("s3-bucket").cloned();
+        let s3_endpoint = matches.get_one::<String>("s3-endpoint").cloned();
+        let s3_region = matches.get_one::<String>("s3-region").cloned();
+        if storage_type == StorageType::S3 {
+            if s3_bucket.is_none() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "--s3-bucket is required with S3 storage",
+                ));
+            }
+        } else if s3_bucket.is_some() || s3_endpoint.is_some() || s3_region.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--s3-bucket, --s3-endpoint and --s3-region require S3 storage",
+            ));
+        }
+
+        let encryption = EncryptionConfig::from_env().map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{:#}", e))
+        })?;
+        if encryption.is_some()
+            && !matches!(
+                storage_type,
+                StorageType::Filesystem | StorageType::Database
+            )
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "SERVER_ENCRYPTION_KEY requires filesystem or database storage",
+            ));
+        }
+
+        let storage_compression = match matches.get_one::<String>("storage-compression") {
+            Some(algorithm) => {
+                let algorithm = algorithm.parse::<Compression>().map_err(|e| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string())
+                })?;
+                let level = match matches.get_one::<String>("storage-compression-level") {
+                    Some(level) => level.parse::<i32>().map_err(|_| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!("Invalid storage compression level: {}", level),
+                        )
+                    })?,
+                    None => algorithm.default_level(),
+                };
+                Some(CompressionConfig::new(algorithm, level).map_err(|e| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string())
+                })?)
+            }
+            None if matches.contains_id("storage-compression-level") => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "--storage-compression-level requires --storage-compression",
+                ));
+            }
+            None => None,
+        };
+        if storage_compression.is_some()
+            && !matches!(
+                storage_type,
+                StorageType::Filesystem | StorageType::Database
+            )
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--storage-compression requires filesystem or database storage",
+            ));
+        }
+
+        let durability_str = matches
+            .get_one::<String>("durability")
+            .map(|s| s.as_str())
+            .unwrap_or(DEFAULT_DURABILITY);
+        let durability = durability_str
+            .parse::<Durability>()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+        if durability == Durability::Relaxed && storage_type != StorageType::Filesystem {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--durability relaxed requires filesystem storage",
+            ));
+        }
+
         let env_host = std::env::var("SERVER_HOST").ok();
         let env_port = std::env::var("SERVER_PORT").ok();
 
@@ -134,6 +550,240 @@ impl ServerConfig {
             )
         })?;
 
+        let scrub_interval = matches
+            .get_one::<String>("scrub-interval")
+            .map(|s| match s.parse::<u64>() {
+                Ok(seconds) if seconds > 0 => Ok(Duration::from_secs(seconds)),
+                _ => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid scrub interval: {}", s),
+                )),
+            })
+            .transpose()?;
+
+        let scrub_files_str = matches
+            .get_one::<String>("scrub-files-per-tick")
+            .map(|s| s.as_str())
+            .unwrap_or(DEFAULT_SCRUB_FILES_PER_TICK);
+        let scrub_files_per_tick = match scrub_files_str.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid scrub files per tick: {}", scrub_files_str),
+                ));
+            }
+        };
+
+        let compression_str = matches
+            .get_one::<String>("response-compression")
+            .map(|s| s.as_str())
+            .unwrap_or(DEFAULT_RESPONSE_COMPRESSION);
+        let response_compression =
+            ResponseCompression::parse(compression_str).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid response compression: {}. Must be 'none', 'gzip' or 'br'",
+                        compression_str
+                    ),
+                )
+            })?;
+
+        let slow_op_str = matches
+            .get_one::<String>("slow-op-threshold-ms")
+            .map(|s| s.as_str())
+            .unwrap_or(DEFAULT_SLOW_OP_THRESHOLD_MS);
+        let slow_op_threshold = slow_op_str
+            .parse::<u64>()
+            .map(Duration::from_millis)
+            .map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid slow operation threshold: {}", slow_op_str),
+                )
+            })?;
+
+        let max_files_str = matches
+            .get_one::<String>("max-files-per-batch")
+            .map(|s| s.as_str())
+            .unwrap_or(DEFAULT_MAX_FILES_PER_BATCH);
+        let max_files_per_batch = max_files_str
+            .parse::<usize>()
+            .ok()
+            .filter(|max| *max > 0)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid max files per batch: {}", max_files_str),
+                )
+            })?;
+
+        let max_upload_size_bytes = matches
+            .get_one::<String>("max-upload-size")
+            .map(|s| match s.parse::<usize>() {
+                Ok(max) if max > 0 => Ok(max),
+                _ => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid max upload size: {}", s),
+                )),
+            })
+            .transpose()?
+            .unwrap_or(DEFAULT_MAX_UPLOAD_SIZE_BYTES);
+
+        let max_proof_batch_files = matches
+            .get_one::<String>("max-proof-batch-files")
+            .map(|s| match s.parse::<usize>() {
+                Ok(max) if max > 0 => Ok(max),
+                _ => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid max proof batch files: {}", s),
+                )),
+            })
+            .transpose()?;
+
+        let max_concurrent_downloads_per_client = matches
+            .get_one::<String>("max-concurrent-downloads-per-client")
+            .map(|s| match s.parse::<usize>() {
+                Ok(max) if max > 0 => Ok(max),
+                _ => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid max concurrent downloads per client: {}", s),
+                )),
+            })
+            .transpose()?;
+
+        let rate_limit_rps = matches
+            .get_one::<String>("rate-limit-rps")
+            .map(|s| match s.parse::<f64>() {
+                Ok(rps) if rps.is_finite() && rps > 0.0 => Ok(rps),
+                _ => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid rate limit rps: {}", s),
+                )),
+            })
+            .transpose()?;
+        let rate_limit_burst = matches
+            .get_one::<String>("rate-limit-burst")
+            .map(|s| match s.parse::<usize>() {
+                Ok(burst) if burst > 0 => Ok(burst),
+                _ => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid rate limit burst: {}", s),
+                )),
+            })
+            .transpose()?;
+        let rate_limit_burst = match (rate_limit_rps, rate_limit_burst) {
+            (None, Some(_)) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "--rate-limit-burst requires --rate-limit-rps",
+                ));
+            }
+            (_, Some(burst)) => burst,
+            (Some(rps), None) => rps.ceil() as usize,
+            (None, None) => 0,
+        };
+
+        let hash_truncation_bytes = matches
+            .get_one::<String>("hash-truncation-bytes")
+            .map(|s| {
+                s.parse::<usize>()
+                    .ok()
+                    .filter(|bytes| merkle_tree::validate_truncation(*bytes).is_ok())
+                    .ok_or_else(|| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!(
+                                "Invalid hash truncation bytes: {} (expected {} to 31)",
+                                s,
+                                merkle_tree::MIN_TRUNCATION_BYTES
+                            ),
+                        )
+                    })
+            })
+            .transpose()?;
+
+        let ingest_workers = matches
+            .get_one::<String>("ingest-workers")
+            .map(|s| match s.parse::<usize>() {
+                Ok(workers) if workers > 0 => Ok(workers),
+                _ => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid ingest workers: {}", s),
+                )),
+            })
+            .transpose()?;
+
+        let ingest_queue_str = matches
+            .get_one::<String>("ingest-queue-size")
+            .map(|s| s.as_str())
+            .unwrap_or(DEFAULT_INGEST_QUEUE_SIZE);
+        let ingest_queue_size = ingest_queue_str
+            .parse::<usize>()
+            .ok()
+            .filter(|size| *size > 0)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid ingest queue size: {}", ingest_queue_str),
+                )
+            })?;
+
+        let backlog_str = matches
+            .get_one::<String>("backlog")
+            .map(|s| s.as_str())
+            .unwrap_or(DEFAULT_BACKLOG);
+        let backlog = backlog_str
+            .parse::<u32>()
+            .ok()
+            .filter(|backlog| *backlog > 0)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid backlog: {}", backlog_str),
+                )
+            })?;
+
+        let keep_alive_str = matches
+            .get_one::<String>("keep-alive-seconds")
+            .map(|s| s.as_str())
+            .unwrap_or(DEFAULT_KEEP_ALIVE_SECONDS);
+        let keep_alive = keep_alive_str
+            .parse::<u64>()
+            .map(Duration::from_secs)
+            .map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid keep-alive seconds: {}", keep_alive_str),
+                )
+            })?;
+
+        let request_timeout_str = matches
+            .get_one::<String>("client-request-timeout")
+            .map(|s| s.as_str())
+            .unwrap_or(DEFAULT_CLIENT_REQUEST_TIMEOUT_MS);
+        let client_request_timeout = request_timeout_str
+            .parse::<u64>()
+            .map(Duration::from_millis)
+            .map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid client request timeout: {}", request_timeout_str),
+                )
+            })?;
+
+        let batch_target = |subcommand: &str| {
+            matches
+                .subcommand_matches(subcommand)
+                .map(|target| BatchTarget {
+                    client_id: target.get_one::<String>("client-id").unwrap().clone(),
+                    batch_id: target.get_one::<String>("batch-id").unwrap().clone(),
+                })
+        };
+        let audit_batch = batch_target("audit-batch");
+        let compact_batch = batch_target("compact-batch");
+
         Ok(ServerConfig {
             storage_type,
             host,
@@ -141,6 +791,37 @@ impl ServerConfig {
             data_dir,
             database_url,
             database_retry_config: DatabaseRetryConfig::from_env(),
+            database_pool_config: DatabasePoolConfig::from_env(),
+            db_external_content_dir,
+            sqlite_path,
+            s3_bucket,
+            s3_endpoint,
+            s3_region,
+            encryption,
+            storage_compression,
+            durability,
+            follow_data_symlink: !matches.get_flag("no-follow-data-symlink"),
+            scrub_interval,
+            scrub_files_per_tick,
+            response_compression,
+            slow_op_threshold,
+            max_files_per_batch,
+            max_upload_size_bytes,
+            max_proof_batch_files,
+            max_concurrent_downloads_per_client,
+            rate_limit_rps,
+            rate_limit_burst,
+            hash_truncation_bytes,
+            ingest_workers,
+            ingest_queue_size,
+            async_uploads: matches.get_flag("async-uploads"),
+            read_only: matches.get_flag("read-only"),
+            enable_debug_endpoints: matches.get_flag("enable-debug-endpoints"),
+            audit_batch,
+            compact_batch,
+            backlog,
+            keep_alive,
+            client_request_timeout,
         })
     }
 
@@ -7,6 +7,9 @@ pub const DEFAULT_MAX_CLOCK_SKEW_SECONDS: u64 = 60;
 /// Default data directory for filesystem storage
 pub const DEFAULT_DATA_DIR: &str = "server_data";
 
+/// Default database file for SQLite storage
+pub const DEFAULT_SQLITE_PATH: &str = "server_data.db";
+
 /// Default server host
 pub const DEFAULT_HOST: &str = "0.0.0.0";
 
@@ -19,5 +22,54 @@ pub const STORAGE_TYPE_DATABASE: &str = "db";
 /// Storage type identifier for filesystem (also used as the default storage type)
 pub const STORAGE_TYPE_FILESYSTEM: &str = "fs";
 
-/// Maximum upload payload size in bytes (10 MB)
-pub const MAX_UPLOAD_SIZE_BYTES: usize = 10 * 1024 * 1024;
+/// Storage type identifier for in-memory storage (lost on restart)
+pub const STORAGE_TYPE_MEMORY: &str = "mem";
+
+/// Storage type identifier for SQLite database storage
+pub const STORAGE_TYPE_SQLITE: &str = "sqlite";
+
+/// Storage type identifier for S3-compatible object storage (needs the `s3` feature)
+pub const STORAGE_TYPE_S3: &str = "s3";
+
+/// Default maximum size in bytes of one uploaded file (10 MB), set with `--max-upload-size`
+pub const DEFAULT_MAX_UPLOAD_SIZE_BYTES: usize = 10 * 1024 * 1024;
+
+/// Maximum JSON body size in bytes: a default-size upload base64-encoded, plus room for the other fields
+/// JSON uploads are buffered in memory, so they stay capped here whatever `--max-upload-size` is
+pub const MAX_JSON_PAYLOAD_SIZE_BYTES: usize = DEFAULT_MAX_UPLOAD_SIZE_BYTES / 3 * 4 + 64 * 1024;
+
+/// Number of maximum-size files a multipart body may carry, reached by batch replacements
+pub const MAX_MULTIPART_FILES_PER_REQUEST: usize = 10;
+
+/// Maximum number of files one bulk upload (POST /upload-batch) may carry
+pub const MAX_FILES_PER_UPLOAD_BATCH: usize = 1000;
+
+/// Default number of files the scrubber checks per tick
+pub const DEFAULT_SCRUB_FILES_PER_TICK: &str = "100";
+
+/// Default number of uploads the ingestion pipeline queues before answering 503
+pub const DEFAULT_INGEST_QUEUE_SIZE: &str = "64";
+
+/// Default response compression offered to clients that accept it
+pub const DEFAULT_RESPONSE_COMPRESSION: &str = "gzip";
+
+/// Default duration in milliseconds above which a storage call is logged as slow
+pub const DEFAULT_SLOW_OP_THRESHOLD_MS: &str = "1000";
+
+/// Default maximum number of files a single batch may hold
+pub const DEFAULT_MAX_FILES_PER_BATCH: &str = "100000";
+
+/// Default filesystem durability: fsync every write
+pub const DEFAULT_DURABILITY: &str = "strict";
+
+/// Default maximum number of pending connections, matching actix-web's default
+pub const DEFAULT_BACKLOG: &str = "1024";
+
+/// Default keep-alive timeout in seconds for idle connections, matching actix-web's default
+pub const DEFAULT_KEEP_ALIVE_SECONDS: &str = "5";
+
+/// Default time in milliseconds a client has to send request headers, matching actix-web's default
+pub const DEFAULT_CLIENT_REQUEST_TIMEOUT_MS: &str = "5000";
+
+/// Most files one POST /proofs request may ask proofs for
+pub const MAX_PROOFS_PER_REQUEST: usize = 1000;
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Per-client cap on in-flight downloads
+/// Each client gets a semaphore with `max_per_client` permits, created on its first
+/// download and dropped once its last download finishes, so idle clients cost nothing.
+/// Bounds concurrency rather than request rate: one client cannot tie up workers and
+/// bandwidth with many simultaneous large downloads, while other clients are unaffected.
+pub struct DownloadLimiter {
+    max_per_client: usize,
+    in_flight: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+/// A download slot, released when dropped, including on error paths
+pub struct DownloadPermit {
+    client_id: String,
+    permit: Option<OwnedSemaphorePermit>,
+    in_flight: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+impl DownloadLimiter {
+    pub fn new(max_per_client: usize) -> Self {
+        Self {
+            max_per_client: max_per_client.max(1),
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn max_per_client(&self) -> usize {
+        self.max_per_client
+    }
+
+    /// Take a download slot for `client_id`, or `None` if all of its slots are in use
+    pub fn try_acquire(&self, client_id: &str) -> Option<DownloadPermit> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let semaphore = in_flight
+            .entry(client_id.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max_per_client)))
+            .clone();
+        let permit = semaphore.try_acquire_owned().ok()?;
+        Some(DownloadPermit {
+            client_id: client_id.to_string(),
+            permit: Some(permit),
+            in_flight: self.in_flight.clone(),
+        })
+    }
+}
+
+impl Drop for DownloadPermit {
+    fn drop(&mut self) {
+        // Permits are taken under the same lock, so nobody can grab this semaphore
+        // between the check and the removal
+        let mut in_flight = self.in_flight.lock().unwrap();
+        drop(self.permit.take());
+        let idle = in_flight
+            .get(&self.client_id)
+            .is_some_and(|semaphore| Arc::strong_count(semaphore) == 1);
+        if idle {
+            in_flight.remove(&self.client_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots_are_per_client_and_released_on_drop() {
+        let limiter = DownloadLimiter::new(2);
+        let first = limiter.try_acquire("a").unwrap();
+        let _second = limiter.try_acquire("a").unwrap();
+        assert!(limiter.try_acquire("a").is_none());
+        // Another client has its own slots
+        let other = limiter.try_acquire("b");
+        assert!(other.is_some());
+
+        drop(first);
+        assert!(limiter.try_acquire("a").is_some());
+
+        // Idle clients are forgotten
+        drop(other);
+        assert!(!limiter.in_flight.lock().unwrap().contains_key("b"));
+    }
+}
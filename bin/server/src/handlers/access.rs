@@ -0,0 +1,151 @@
+use crate::auth::AuthVerifier;
+use crate::handlers::error::{
+    handle_auth_error, handle_error, handle_storage_error, handle_timestamp_error,
+};
+use crate::state::AppState;
+use actix_web::{web, Result as ActixResult};
+use crypto::{constant_time_eq, SignatureScheme};
+use tracing::info;
+
+/// Credentials a read request may carry
+pub struct ReadCredentials<'a> {
+    /// Client ID of the batch owner
+    pub client_id: &'a str,
+    /// Batch being read
+    pub batch_id: &'a str,
+    /// Signer's client ID when reading a batch shared by its owner
+    pub requester_id: Option<&'a str>,
+    /// hex-encoded public key of the signer, for requesters that never uploaded
+    pub requester_public_key: Option<&'a str>,
+    /// Signature scheme of `requester_public_key`; registered keys carry their own
+    pub scheme: SignatureScheme,
+    /// hex-encoded signature (absent for anonymous reads)
+    pub signature: Option<&'a str>,
+    /// Timestamp for replay attack prevention
+    pub timestamp: Option<u64>,
+}
+
+/// Authorize a read of a batch
+/// Allowed when signed by the owner, signed by a client the owner granted access to,
+/// or unsigned for public batches. `build_message(timestamp, owner)` builds the signed
+/// message; `owner` is set for shared reads so the owner's client ID is signed too.
+pub async fn authorize_read(
+    state: &web::Data<AppState>,
+    route: &str,
+    creds: ReadCredentials<'_>,
+    build_message: impl FnOnce(u64, Option<&str>) -> Vec<u8>,
+) -> ActixResult<()> {
+    let ReadCredentials {
+        client_id,
+        batch_id,
+        requester_id,
+        requester_public_key,
+        scheme,
+        signature,
+        timestamp,
+    } = creds;
+
+    match (signature, timestamp) {
+        (Some(signature), Some(timestamp)) => {
+            // Validate timestamp to prevent replay attacks
+            AuthVerifier::validate_timestamp_default(timestamp).map_err(handle_timestamp_error)?;
+
+            // The owner signs its own reads; a client the owner shared the batch with
+            // signs as itself and names the owner in the signed message
+            let requester_id = requester_id.unwrap_or(client_id);
+            let shared = requester_id != client_id;
+            let owner = shared.then_some(client_id);
+
+            // Verify signature using the requester's client_id for O(1) key lookup
+            let message = build_message(timestamp, owner);
+            let signature_obj = AuthVerifier::parse_signature(signature)
+                .map_err(|e| handle_error("Failed to parse signature", e))?;
+
+            match requester_public_key {
+                // A grantee that only downloads has never registered a key by uploading;
+                // it sends its key instead, which is registered like on a first upload
+                Some(public_key_hex) => {
+                    AuthVerifier::validate_scheme_public_key(scheme, public_key_hex)
+                        .map_err(|e| handle_auth_error("Invalid public key", e))?;
+                    let (signer_id, is_new_client) = AuthVerifier::verify_request_signature(
+                        state,
+                        scheme,
+                        &message,
+                        &signature_obj,
+                        public_key_hex,
+                    )
+                    .await
+                    .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+                    if !constant_time_eq(signer_id.as_bytes(), requester_id.as_bytes()) {
+                        return Err(actix_web::error::ErrorUnauthorized(
+                            "Public key does not match the requester's client ID",
+                        ));
+                    }
+                    if is_new_client {
+                        info!("{} - Registered new client: {}", route, signer_id);
+                    }
+                }
+                None => {
+                    AuthVerifier::verify_request_signature_with_client_id(
+                        state,
+                        requester_id,
+                        &message,
+                        &signature_obj,
+                    )
+                    .await
+                    .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+                }
+            }
+
+            if shared {
+                let granted = state
+                    .storage
+                    .has_batch_access(client_id, batch_id, requester_id)
+                    .await
+                    .map_err(|e| handle_storage_error("Failed to check batch access", e))?;
+                if !granted {
+                    return Err(actix_web::error::ErrorForbidden(format!(
+                        "Client {} has no access to batch {}",
+                        requester_id, batch_id
+                    )));
+                }
+            }
+
+            info!(
+                client_id = ?client_id,
+                requester_id = ?requester_id,
+                "{} - Signature verified",
+                route
+            );
+        }
+        (None, None) => {
+            // Anonymous read: only allowed for batches marked public
+            let is_public = state
+                .storage
+                .is_batch_public(client_id, batch_id)
+                .await
+                .map_err(|e| handle_storage_error("Failed to check batch visibility", e))?;
+
+            if !is_public {
+                return Err(actix_web::error::ErrorUnauthorized(format!(
+                    "Batch {} is not public: signature required",
+                    batch_id
+                )));
+            }
+
+            info!(
+                client_id = ?client_id,
+                batch_id = ?batch_id,
+                "{} - Anonymous read of public batch",
+                route
+            );
+        }
+        _ => {
+            return Err(actix_web::error::ErrorBadRequest(
+                "Signature and timestamp must be provided together",
+            ));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,1843 @@
+use crate::auth::AuthVerifier;
+use crate::handlers::access::{authorize_read, ReadCredentials};
+use crate::handlers::error::{
+    ensure_batch_exists, handle_auth_error, handle_error, handle_server_error,
+    handle_storage_error, handle_timestamp_error,
+};
+use crate::handlers::upload_form::ReplaceBatchForm;
+use crate::proof::served_tree;
+use crate::state::AppState;
+use actix_multipart::form::MultipartForm;
+use actix_web::{delete, get, post, web, HttpResponse, Result as ActixResult};
+use common::auth_message::{
+    access_message, create_message, delete_file_message, delete_message, finalize_message,
+    list_batches_message, list_message, rename_message, replace_message, root_message,
+    tree_message,
+};
+use common::file_utils::MAX_FILENAME_BYTES;
+use common::{
+    file_utils, BatchAccessRequest, BatchFileEntry, BatchFilesRequest, BatchFilesResponse,
+    BatchRootRequest, BatchRootResponse, BatchTreeResponse, CreateBatchRequest, DeleteBatchRequest,
+    DeleteFileRequest, DeleteFileResponse, FinalizeBatchRequest, ListBatchesRequest,
+    ListBatchesResponse, RenameBatchRequest, ReplaceBatchManifest,
+};
+use crypto::{hash_leaf_reader, SignatureScheme};
+use merkle_tree::{decode_hash, encode_hash, MerkleTree};
+use storage::ReplacementFile;
+use tracing::{info, warn};
+
+/// Create an empty batch that files can be uploaded into later
+/// Registers the client like a first upload would. Fails with 409 Conflict if the
+/// batch already exists, so a batch ID can be reserved
+#[post("/batch")]
+pub async fn create_batch(
+    body: web::Json<CreateBatchRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let req = body.into_inner();
+
+    info!(batch_id = ?req.batch_id, "POST /batch - Request received");
+
+    // Batch IDs become directory names on the filesystem backend
+    if req.batch_id.len() > MAX_FILENAME_BYTES {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "Batch ID must be between 1 and {} bytes",
+            MAX_FILENAME_BYTES
+        )));
+    }
+    file_utils::validate_filename(&req.batch_id)
+        .map_err(|e| actix_web::error::ErrorBadRequest(format!("Batch ID: {}", e.message())))?;
+
+    // Validate timestamp to prevent replay attacks
+    AuthVerifier::validate_timestamp_default(req.timestamp).map_err(handle_timestamp_error)?;
+
+    AuthVerifier::validate_public_key(&req.public_key)
+        .map_err(|e| handle_auth_error("Invalid public key", e))?;
+    let message = create_message(&req.batch_id, req.timestamp);
+    let signature = AuthVerifier::parse_signature(&req.signature)
+        .map_err(|e| handle_error("Failed to parse signature", e))?;
+    let (client_id, is_new_client) = AuthVerifier::verify_request_signature(
+        &state,
+        SignatureScheme::Ed25519,
+        &message,
+        &signature,
+        &req.public_key,
+    )
+    .await
+    .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+    if is_new_client {
+        info!("POST /batch - Registered new client: {}", client_id);
+    }
+
+    let created = state
+        .storage
+        .create_batch(&client_id, &req.batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to create batch", e))?;
+    if !created {
+        return Err(actix_web::error::ErrorConflict(format!(
+            "Batch {} already exists",
+            req.batch_id
+        )));
+    }
+
+    info!(
+        client_id = ?client_id,
+        batch_id = ?req.batch_id,
+        "POST /batch - Empty batch created"
+    );
+
+    Ok(HttpResponse::Created().finish())
+}
+
+/// Handle batch rename (files, visibility and Merkle tree move with the batch)
+#[post("/batch/{batch_id}/rename")]
+pub async fn rename_batch(
+    path: web::Path<String>,
+    body: web::Json<RenameBatchRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let old_batch_id = path.into_inner();
+    let req = body.into_inner();
+
+    info!(
+        batch_id = ?old_batch_id,
+        new_batch_id = ?req.new_batch_id,
+        "POST /batch/rename - Request received"
+    );
+
+    // Batch IDs become directory names on the filesystem backend
+    for batch_id in [&old_batch_id, &req.new_batch_id] {
+        if batch_id.len() > MAX_FILENAME_BYTES {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "Batch ID must be between 1 and {} bytes",
+                MAX_FILENAME_BYTES
+            )));
+        }
+        file_utils::validate_filename(batch_id)
+            .map_err(|e| actix_web::error::ErrorBadRequest(format!("Batch ID: {}", e.message())))?;
+    }
+
+    // Validate timestamp to prevent replay attacks
+    AuthVerifier::validate_timestamp_default(req.timestamp).map_err(handle_timestamp_error)?;
+
+    let nonce =
+        AuthVerifier::parse_nonce(&req.nonce).map_err(|e| handle_error("Invalid nonce", e))?;
+    let message = rename_message(&old_batch_id, &req.new_batch_id, req.timestamp, &nonce);
+    let signature = AuthVerifier::parse_signature(&req.signature)
+        .map_err(|e| handle_error("Failed to parse signature", e))?;
+
+    AuthVerifier::verify_request_signature_with_client_id(
+        &state,
+        &req.client_id,
+        &message,
+        &signature,
+    )
+    .await
+    .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+    AuthVerifier::check_nonce(&state, nonce)
+        .map_err(|e| handle_auth_error("Replay rejected", e))?;
+
+    let client_id = req.client_id;
+
+    ensure_batch_exists(state.storage.as_ref(), &client_id, &old_batch_id).await?;
+
+    if state
+        .storage
+        .batch_exists(&client_id, &req.new_batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to check batch existence", e))?
+    {
+        return Err(actix_web::error::ErrorConflict(format!(
+            "Batch {} already exists",
+            req.new_batch_id
+        )));
+    }
+
+    state
+        .storage
+        .rename_batch(&client_id, &old_batch_id, &req.new_batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to rename batch", e))?;
+
+    info!(
+        client_id = ?client_id,
+        batch_id = ?old_batch_id,
+        new_batch_id = ?req.new_batch_id,
+        "POST /batch/rename - Batch renamed"
+    );
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Delete a batch with its files, Merkle tree, access list and annotations
+/// Only the owner may delete a batch; authenticated with the owner's client ID,
+/// signature and timestamp as query parameters, like an owner's download
+#[delete("/batch/{batch_id}")]
+pub async fn delete_batch(
+    path: web::Path<String>,
+    query: web::Query<DeleteBatchRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let batch_id = path.into_inner();
+    let req = query.into_inner();
+
+    info!(batch_id = ?batch_id, "DELETE /batch - Request received");
+
+    // Batch IDs become directory names on the filesystem backend
+    file_utils::validate_filename(&batch_id)
+        .map_err(|e| actix_web::error::ErrorBadRequest(format!("Batch ID: {}", e.message())))?;
+
+    // Validate timestamp to prevent replay attacks
+    AuthVerifier::validate_timestamp_default(req.timestamp).map_err(handle_timestamp_error)?;
+
+    let nonce =
+        AuthVerifier::parse_nonce(&req.nonce).map_err(|e| handle_error("Invalid nonce", e))?;
+    let message = delete_message(&batch_id, req.timestamp, &nonce);
+    let signature = AuthVerifier::parse_signature(&req.signature)
+        .map_err(|e| handle_error("Failed to parse signature", e))?;
+
+    AuthVerifier::verify_request_signature_with_client_id(
+        &state,
+        &req.client_id,
+        &message,
+        &signature,
+    )
+    .await
+    .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+    AuthVerifier::check_nonce(&state, nonce)
+        .map_err(|e| handle_auth_error("Replay rejected", e))?;
+
+    let client_id = req.client_id;
+
+    ensure_batch_exists(state.storage.as_ref(), &client_id, &batch_id).await?;
+
+    state
+        .storage
+        .delete_batch(&client_id, &batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to delete batch", e))?;
+
+    info!(
+        client_id = ?client_id,
+        batch_id = ?batch_id,
+        "DELETE /batch - Batch deleted"
+    );
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Delete one file from a batch and answer with the batch's new Merkle root
+/// Removing a leaf changes the root, so the owner replaces its stored root with the
+/// returned one. Authenticated like a batch deletion, with query parameters
+#[delete("/file")]
+pub async fn delete_file(
+    query: web::Query<DeleteFileRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let req = query.into_inner();
+
+    info!(
+        filename = ?req.filename,
+        batch_id = ?req.batch_id,
+        "DELETE /file - Request received"
+    );
+
+    file_utils::validate_filename(&req.filename)
+        .map_err(|e| actix_web::error::ErrorBadRequest(e.message()))?;
+    file_utils::validate_filename(&req.batch_id)
+        .map_err(|e| actix_web::error::ErrorBadRequest(format!("Batch ID: {}", e.message())))?;
+
+    // Validate timestamp to prevent replay attacks
+    AuthVerifier::validate_timestamp_default(req.timestamp).map_err(handle_timestamp_error)?;
+
+    let nonce =
+        AuthVerifier::parse_nonce(&req.nonce).map_err(|e| handle_error("Invalid nonce", e))?;
+    let message = delete_file_message(&req.filename, &req.batch_id, req.timestamp, &nonce);
+    let signature = AuthVerifier::parse_signature(&req.signature)
+        .map_err(|e| handle_error("Failed to parse signature", e))?;
+
+    AuthVerifier::verify_request_signature_with_client_id(
+        &state,
+        &req.client_id,
+        &message,
+        &signature,
+    )
+    .await
+    .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+    AuthVerifier::check_nonce(&state, nonce)
+        .map_err(|e| handle_auth_error("Replay rejected", e))?;
+
+    let client_id = req.client_id;
+
+    ensure_batch_exists(state.storage.as_ref(), &client_id, &req.batch_id).await?;
+    let exists = state
+        .storage
+        .file_exists(&client_id, &req.batch_id, &req.filename)
+        .await
+        .map_err(|e| handle_storage_error("Failed to check file existence", e))?;
+    if !exists {
+        return Err(actix_web::error::ErrorNotFound(format!(
+            "File {} not found in batch {}",
+            req.filename, req.batch_id
+        )));
+    }
+
+    let tree = state
+        .storage
+        .delete_file(&client_id, &req.batch_id, &req.filename)
+        .await
+        .map_err(|e| handle_storage_error("Failed to delete file", e))?;
+
+    let (root_hash, num_files) = match tree {
+        Some(tree) => {
+            let num_files = tree.num_leaves();
+            let root_hash = encode_hash(
+                &served_tree(&state, tree)?.root_hash(),
+                state.hash_truncation_bytes,
+            );
+            (root_hash, num_files)
+        }
+        None => (String::new(), 0),
+    };
+
+    info!(
+        client_id = ?client_id,
+        batch_id = ?req.batch_id,
+        filename = ?req.filename,
+        "DELETE /file - File deleted, {} files left",
+        num_files
+    );
+
+    Ok(HttpResponse::Ok().json(DeleteFileResponse {
+        batch_id: req.batch_id,
+        root_hash,
+        num_files,
+    }))
+}
+
+/// Check the owner's signed commitment to a batch's file set against the files received
+/// Each upload is signed on its own, so a dropped upload request would otherwise only
+/// surface as a proof failure at download time. Fails with 409 Conflict if the server
+/// holds a different set of files; otherwise the committed root is recorded.
+/// Uploads after a finalize are still accepted; `GET /batch/{batch_id}/root` then reports
+/// a root that differs from the committed one. Leaf hashes are always full width, while the
+/// root is at the served width (see `--hash-truncation-bytes`).
+#[post("/batch/{batch_id}/finalize")]
+pub async fn finalize_batch(
+    path: web::Path<String>,
+    body: web::Json<FinalizeBatchRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let batch_id = path.into_inner();
+    let req = body.into_inner();
+
+    info!(
+        batch_id = ?batch_id,
+        num_files = req.leaf_hashes.len(),
+        "POST /batch/finalize - Request received"
+    );
+
+    file_utils::validate_filename(&batch_id)
+        .map_err(|e| actix_web::error::ErrorBadRequest(format!("Batch ID: {}", e.message())))?;
+
+    // Validate timestamp to prevent replay attacks
+    AuthVerifier::validate_timestamp_default(req.timestamp).map_err(handle_timestamp_error)?;
+
+    let nonce =
+        AuthVerifier::parse_nonce(&req.nonce).map_err(|e| handle_error("Invalid nonce", e))?;
+    let message = finalize_message(
+        &batch_id,
+        &req.leaf_hashes,
+        &req.root_hash,
+        req.timestamp,
+        &nonce,
+    );
+    let signature = AuthVerifier::parse_signature(&req.signature)
+        .map_err(|e| handle_error("Failed to parse signature", e))?;
+
+    AuthVerifier::verify_request_signature_with_client_id(
+        &state,
+        &req.client_id,
+        &message,
+        &signature,
+    )
+    .await
+    .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+    AuthVerifier::check_nonce(&state, nonce)
+        .map_err(|e| handle_auth_error("Replay rejected", e))?;
+
+    let committed_leaves = req
+        .leaf_hashes
+        .iter()
+        .map(|hash| parse_hash(hash))
+        .collect::<Option<Vec<[u8; 32]>>>()
+        .ok_or_else(|| {
+            actix_web::error::ErrorBadRequest("Leaf hashes must be exactly 64 hex characters")
+        })?;
+    let truncation = state.hash_truncation_bytes;
+    let committed_root = decode_hash(&req.root_hash, truncation).map_err(|_| {
+        actix_web::error::ErrorBadRequest(format!(
+            "Root hash must be exactly {} hex characters",
+            truncation.unwrap_or(32) * 2
+        ))
+    })?;
+    let committed_tree = MerkleTree::from_leaf_hashes(&committed_leaves)
+        .map_err(|e| handle_error("Invalid leaf hashes", e))?;
+    let computed_root = served_tree(&state, committed_tree)?.root_hash();
+    if computed_root != committed_root {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Root hash does not match the committed leaf hashes",
+        ));
+    }
+
+    let client_id = req.client_id;
+    let tree = state
+        .storage
+        .load_merkle_tree(&client_id, &batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to load Merkle tree", e))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("Batch {} not found", batch_id)))?;
+
+    if served_tree(&state, tree.clone())?.root_hash() != committed_root {
+        let stored: Vec<[u8; 32]> = (0..tree.num_leaves())
+            .filter_map(|index| tree.leaf_hash(index))
+            .collect();
+        let missing = committed_leaves
+            .iter()
+            .filter(|leaf| !stored.contains(leaf))
+            .count();
+        let unexpected = stored
+            .iter()
+            .filter(|leaf| !committed_leaves.contains(leaf))
+            .count();
+        warn!(
+            client_id = ?client_id,
+            batch_id = ?batch_id,
+            missing,
+            unexpected,
+            "POST /batch/finalize - File set diverges from the commitment"
+        );
+        return Err(actix_web::error::ErrorConflict(format!(
+            "Batch {} holds {} files but the commitment lists {}: {} committed files were not \
+             received, {} received files were not committed",
+            batch_id,
+            stored.len(),
+            committed_leaves.len(),
+            missing,
+            unexpected
+        )));
+    }
+
+    state
+        .storage
+        .set_committed_root(&client_id, &batch_id, &committed_root)
+        .await
+        .map_err(|e| handle_storage_error("Failed to record committed root", e))?;
+
+    info!(
+        client_id = ?client_id,
+        batch_id = ?batch_id,
+        "POST /batch/finalize - Batch finalized"
+    );
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Parse a hex-encoded 32-byte hash
+fn parse_hash(hash_hex: &str) -> Option<[u8; 32]> {
+    hex::decode(hash_hex).ok()?.try_into().ok()
+}
+
+/// Replace a batch's file set in one step: add, change and remove files at once
+/// The owner signs a manifest of the complete new set with its root; only new or changed
+/// files are sent. The swap is atomic: readers see either the old set or the new one, and
+/// any failure leaves the batch as it was. The signed root is recorded as the committed
+/// root, like a finalize. Fails with 409 Conflict if a file the manifest keeps no longer
+/// has the listed content.
+#[post("/batch/{batch_id}/replace")]
+pub async fn replace_batch(
+    path: web::Path<String>,
+    MultipartForm(form): MultipartForm<ReplaceBatchForm>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let batch_id = path.into_inner();
+    let manifest: ReplaceBatchManifest = serde_json::from_str(&form.manifest)
+        .map_err(|e| handle_error("Invalid replacement manifest", e))?;
+
+    info!(
+        batch_id = ?batch_id,
+        num_files = manifest.files.len(),
+        num_uploads = form.files.len(),
+        "POST /batch/replace - Request received"
+    );
+
+    // Batch IDs become directory names on the filesystem backend
+    if batch_id.len() > MAX_FILENAME_BYTES {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "Batch ID must be between 1 and {} bytes",
+            MAX_FILENAME_BYTES
+        )));
+    }
+    file_utils::validate_filename(&batch_id)
+        .map_err(|e| actix_web::error::ErrorBadRequest(format!("Batch ID: {}", e.message())))?;
+
+    // A batch without files has no root to commit to
+    if manifest.files.is_empty() {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Manifest must list at least one file",
+        ));
+    }
+    // Strictly ascending filenames are the tree's leaf order and rule out duplicates
+    for entry in &manifest.files {
+        file_utils::validate_filename(&entry.filename)
+            .map_err(|e| actix_web::error::ErrorBadRequest(format!("Filename: {}", e.message())))?;
+    }
+    if manifest
+        .files
+        .windows(2)
+        .any(|pair| pair[0].filename >= pair[1].filename)
+    {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Manifest files must be listed once each, in filename order",
+        ));
+    }
+    let leaf_hashes = manifest
+        .files
+        .iter()
+        .map(|entry| parse_hash(&entry.leaf_hash))
+        .collect::<Option<Vec<[u8; 32]>>>()
+        .ok_or_else(|| {
+            actix_web::error::ErrorBadRequest("Leaf hashes must be exactly 64 hex characters")
+        })?;
+
+    // Validate timestamp to prevent replay attacks
+    AuthVerifier::validate_timestamp_default(manifest.timestamp).map_err(handle_timestamp_error)?;
+
+    let nonce =
+        AuthVerifier::parse_nonce(&manifest.nonce).map_err(|e| handle_error("Invalid nonce", e))?;
+    let message = replace_message(&batch_id, &manifest, &nonce);
+    let signature = AuthVerifier::parse_signature(&manifest.signature)
+        .map_err(|e| handle_error("Failed to parse signature", e))?;
+
+    AuthVerifier::verify_request_signature_with_client_id(
+        &state,
+        &manifest.client_id,
+        &message,
+        &signature,
+    )
+    .await
+    .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+    AuthVerifier::check_nonce(&state, nonce)
+        .map_err(|e| handle_auth_error("Replay rejected", e))?;
+
+    let client_id = manifest.client_id.clone();
+    ensure_batch_exists(state.storage.as_ref(), &client_id, &batch_id).await?;
+
+    let truncation = state.hash_truncation_bytes;
+    let committed_root = decode_hash(&manifest.root_hash, truncation).map_err(|_| {
+        actix_web::error::ErrorBadRequest(format!(
+            "Root hash must be exactly {} hex characters",
+            truncation.unwrap_or(32) * 2
+        ))
+    })?;
+    let tree = MerkleTree::from_leaf_hashes(&leaf_hashes)
+        .map_err(|e| handle_error("Invalid leaf hashes", e))?;
+    let computed_root = served_tree(&state, tree)?.root_hash();
+    if computed_root != committed_root {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Root hash does not match the manifest's leaf hashes",
+        ));
+    }
+
+    let mut files: Vec<ReplacementFile> = manifest
+        .files
+        .iter()
+        .zip(&leaf_hashes)
+        .map(|(entry, leaf_hash)| ReplacementFile {
+            filename: entry.filename.clone(),
+            leaf_hash: *leaf_hash,
+            source: None,
+        })
+        .collect();
+
+    // Every uploaded part must be a listed file with exactly the listed content
+    for upload in &form.files {
+        let filename = upload.file_name.as_deref().unwrap_or_default();
+        let index = files
+            .binary_search_by(|file| file.filename.as_str().cmp(filename))
+            .map_err(|_| {
+                actix_web::error::ErrorBadRequest(format!(
+                    "Uploaded file {:?} is not in the manifest",
+                    filename
+                ))
+            })?;
+        if files[index].source.is_some() {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "File {} is uploaded twice",
+                filename
+            )));
+        }
+        if upload.size > state.max_upload_size_bytes {
+            return Err(actix_web::error::ErrorPayloadTooLarge(format!(
+                "File {} exceeds maximum upload size of {} bytes",
+                filename, state.max_upload_size_bytes
+            )));
+        }
+
+        // Hash the temp file in chunks instead of reading it into memory
+        let hash_path = upload.file.path().to_path_buf();
+        let computed_hash = web::block(move || {
+            let file = std::fs::File::open(&hash_path)?;
+            hash_leaf_reader(file)
+        })
+        .await
+        .map_err(|e| handle_error("Failed to hash uploaded file", e))?
+        .map_err(|e| handle_error("Failed to read uploaded file", e))?;
+        if computed_hash != files[index].leaf_hash {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "File {} does not match its leaf hash in the manifest",
+                filename
+            )));
+        }
+        files[index].source = Some(upload.file.path().to_path_buf());
+    }
+
+    let uploaded = form.files.len();
+    state
+        .storage
+        .replace_batch(&client_id, &batch_id, &files, &committed_root)
+        .await
+        .map_err(|e| handle_storage_error("POST /batch/replace - Failed to replace batch", e))?;
+
+    info!(
+        client_id = ?client_id,
+        batch_id = ?batch_id,
+        num_files = files.len(),
+        uploaded,
+        "POST /batch/replace - Batch file set replaced"
+    );
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Grant another client read access to a batch
+#[post("/batch/{batch_id}/grant")]
+pub async fn grant_access(
+    path: web::Path<String>,
+    body: web::Json<BatchAccessRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    update_access(path.into_inner(), body.into_inner(), &state, true).await
+}
+
+/// Revoke a client's read access to a batch
+#[post("/batch/{batch_id}/revoke")]
+pub async fn revoke_access(
+    path: web::Path<String>,
+    body: web::Json<BatchAccessRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    update_access(path.into_inner(), body.into_inner(), &state, false).await
+}
+
+/// Shared implementation of grant and revoke: only the batch owner may change its access list
+async fn update_access(
+    batch_id: String,
+    req: BatchAccessRequest,
+    state: &web::Data<AppState>,
+    granted: bool,
+) -> ActixResult<HttpResponse> {
+    let action = if granted { "grant" } else { "revoke" };
+
+    info!(
+        batch_id = ?batch_id,
+        grantee_id = ?req.grantee_id,
+        "POST /batch/{} - Request received",
+        action
+    );
+
+    // Client IDs are hex-encoded SHA256 hashes of public keys
+    if req.grantee_id.len() != 64 || !req.grantee_id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Grantee ID must be a hex-encoded client ID",
+        ));
+    }
+    if req.grantee_id == req.client_id {
+        return Err(actix_web::error::ErrorBadRequest(
+            "The batch owner always has access to its batches",
+        ));
+    }
+
+    // Validate timestamp to prevent replay attacks
+    AuthVerifier::validate_timestamp_default(req.timestamp).map_err(handle_timestamp_error)?;
+
+    let nonce =
+        AuthVerifier::parse_nonce(&req.nonce).map_err(|e| handle_error("Invalid nonce", e))?;
+    let message = access_message(action, &batch_id, &req.grantee_id, req.timestamp, &nonce);
+    let signature = AuthVerifier::parse_signature(&req.signature)
+        .map_err(|e| handle_error("Failed to parse signature", e))?;
+
+    AuthVerifier::verify_request_signature_with_client_id(
+        state,
+        &req.client_id,
+        &message,
+        &signature,
+    )
+    .await
+    .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+    AuthVerifier::check_nonce(state, nonce).map_err(|e| handle_auth_error("Replay rejected", e))?;
+
+    let client_id = req.client_id;
+
+    ensure_batch_exists(state.storage.as_ref(), &client_id, &batch_id).await?;
+
+    let result = if granted {
+        state
+            .storage
+            .grant_batch_access(&client_id, &batch_id, &req.grantee_id)
+            .await
+    } else {
+        state
+            .storage
+            .revoke_batch_access(&client_id, &batch_id, &req.grantee_id)
+            .await
+    };
+    result.map_err(|e| handle_server_error("Failed to update batch access", e))?;
+
+    info!(
+        client_id = ?client_id,
+        batch_id = ?batch_id,
+        grantee_id = ?req.grantee_id,
+        "POST /batch/{} - Access updated",
+        action
+    );
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Return a batch's current Merkle root, for clients that never uploaded the batch
+/// The server's word only: clients should cross-check it against a trusted source
+#[get("/batch/{batch_id}/root")]
+pub async fn batch_root(
+    path: web::Path<String>,
+    query: web::Query<BatchRootRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let batch_id = path.into_inner();
+    let req = query.into_inner();
+
+    info!(batch_id = ?batch_id, "GET /batch/root - Request received");
+
+    authorize_read(
+        &state,
+        "GET /batch/root",
+        ReadCredentials {
+            client_id: &req.client_id,
+            batch_id: &batch_id,
+            requester_id: req.requester_id.as_deref(),
+            requester_public_key: req.requester_public_key.as_deref(),
+            scheme: SignatureScheme::Ed25519,
+            signature: req.signature.as_deref(),
+            timestamp: req.timestamp,
+        },
+        |timestamp, owner| root_message(&batch_id, timestamp, owner),
+    )
+    .await?;
+
+    let tree = state
+        .storage
+        .load_merkle_tree(&req.client_id, &batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to load Merkle tree", e))?
+        .ok_or_else(|| {
+            actix_web::error::ErrorNotFound(format!("Merkle tree not found for batch {}", batch_id))
+        })?;
+    let committed_root = state
+        .storage
+        .load_committed_root(&req.client_id, &batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to load committed root", e))?;
+
+    let truncation = state.hash_truncation_bytes;
+    let tree = served_tree(&state, tree)?;
+
+    Ok(HttpResponse::Ok().json(BatchRootResponse {
+        batch_id,
+        root_hash: encode_hash(&tree.root_hash(), truncation),
+        num_files: tree.num_leaves(),
+        hash_algorithm: merkle_tree::HASH_ALGORITHM.to_string(),
+        committed_root: committed_root.map(|root| encode_hash(&root, truncation)),
+    }))
+}
+
+/// Return every level of a batch's stored Merkle tree, authorized like a root read
+/// The levels are derivable from the leaf hashes, so this reveals nothing beyond the
+/// file listing; it lets auditors check each internal node with their own tooling
+#[get("/batch/{batch_id}/tree")]
+pub async fn batch_tree(
+    path: web::Path<String>,
+    query: web::Query<BatchRootRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let batch_id = path.into_inner();
+    let req = query.into_inner();
+
+    info!(batch_id = ?batch_id, "GET /batch/tree - Request received");
+
+    authorize_read(
+        &state,
+        "GET /batch/tree",
+        ReadCredentials {
+            client_id: &req.client_id,
+            batch_id: &batch_id,
+            requester_id: req.requester_id.as_deref(),
+            requester_public_key: req.requester_public_key.as_deref(),
+            scheme: SignatureScheme::Ed25519,
+            signature: req.signature.as_deref(),
+            timestamp: req.timestamp,
+        },
+        |timestamp, owner| tree_message(&batch_id, timestamp, owner),
+    )
+    .await?;
+
+    let tree = state
+        .storage
+        .load_merkle_tree(&req.client_id, &batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to load Merkle tree", e))?
+        .ok_or_else(|| {
+            actix_web::error::ErrorNotFound(format!("Merkle tree not found for batch {}", batch_id))
+        })?;
+    let (root_hash, levels) = served_tree(&state, tree)?.root_and_levels_hex();
+
+    Ok(HttpResponse::Ok().json(BatchTreeResponse {
+        batch_id,
+        root_hash,
+        hash_algorithm: merkle_tree::HASH_ALGORITHM.to_string(),
+        levels,
+        hash_truncation_bytes: state.hash_truncation_bytes,
+    }))
+}
+
+/// List a batch's files with the leaf hashes committed to by its Merkle tree
+#[get("/batch/{batch_id}/files")]
+pub async fn list_batch_files(
+    path: web::Path<String>,
+    query: web::Query<BatchFilesRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let batch_id = path.into_inner();
+    let req = query.into_inner();
+
+    info!(batch_id = ?batch_id, "GET /batch/files - Request received");
+
+    // Validate timestamp to prevent replay attacks
+    AuthVerifier::validate_timestamp_default(req.timestamp).map_err(handle_timestamp_error)?;
+
+    let message = list_message(&batch_id, req.timestamp);
+    let signature = AuthVerifier::parse_signature(&req.signature)
+        .map_err(|e| handle_error("Failed to parse signature", e))?;
+
+    AuthVerifier::verify_request_signature_with_client_id(
+        &state,
+        &req.client_id,
+        &message,
+        &signature,
+    )
+    .await
+    .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+
+    let client_id = req.client_id;
+
+    ensure_batch_exists(state.storage.as_ref(), &client_id, &batch_id).await?;
+    let filenames = state
+        .storage
+        .load_batch_filenames(&client_id, &batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to load batch", e))?;
+
+    let public = state
+        .storage
+        .is_batch_public(&client_id, &batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to check batch visibility", e))?;
+
+    let annotations = state
+        .storage
+        .load_batch_annotations(&client_id, &batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to load batch annotations", e))?;
+
+    // A batch whose files were all removed has no tree; list it as empty
+    if filenames.is_empty() {
+        return Ok(HttpResponse::Ok().json(BatchFilesResponse {
+            batch_id,
+            root_hash: String::new(),
+            public,
+            files: Vec::new(),
+            annotations,
+        }));
+    }
+
+    let tree = state
+        .storage
+        .load_merkle_tree(&client_id, &batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to load Merkle tree", e))?
+        .ok_or_else(|| {
+            actix_web::error::ErrorNotFound(format!("Merkle tree not found for batch {}", batch_id))
+        })?;
+
+    // Filenames are sorted, matching leaf order in the tree
+    let files = filenames
+        .into_iter()
+        .enumerate()
+        .map(|(index, filename)| {
+            tree.leaf_hash(index)
+                .map(|leaf_hash| BatchFileEntry {
+                    filename,
+                    leaf_hash: hex::encode(leaf_hash),
+                })
+                .ok_or_else(|| {
+                    actix_web::error::ErrorInternalServerError(
+                        "Merkle tree does not match batch files",
+                    )
+                })
+        })
+        .collect::<ActixResult<Vec<_>>>()?;
+
+    info!(
+        client_id = ?client_id,
+        batch_id = ?batch_id,
+        "GET /batch/files - Listed {} files",
+        files.len()
+    );
+
+    // Leaves are listed at full width so they can be checked against local content
+    let root_hash = encode_hash(
+        &served_tree(&state, tree)?.root_hash(),
+        state.hash_truncation_bytes,
+    );
+
+    Ok(HttpResponse::Ok().json(BatchFilesResponse {
+        batch_id,
+        root_hash,
+        public,
+        files,
+        annotations,
+    }))
+}
+
+/// List the IDs of the signer's own batches, so a client can discover them without
+/// remembering them locally. Empty batches are listed too
+#[get("/batches")]
+pub async fn list_batches(
+    query: web::Query<ListBatchesRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let req = query.into_inner();
+
+    info!("GET /batches - Request received");
+
+    // Validate timestamp to prevent replay attacks
+    AuthVerifier::validate_timestamp_default(req.timestamp).map_err(handle_timestamp_error)?;
+
+    let message = list_batches_message(req.timestamp);
+    let signature = AuthVerifier::parse_signature(&req.signature)
+        .map_err(|e| handle_error("Failed to parse signature", e))?;
+
+    AuthVerifier::verify_request_signature_with_client_id(
+        &state,
+        &req.client_id,
+        &message,
+        &signature,
+    )
+    .await
+    .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+
+    let client_id = req.client_id;
+    let batches = state
+        .storage
+        .list_client_batches(&client_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to list batches", e))?;
+
+    info!(
+        client_id = ?client_id,
+        "GET /batches - Listed {} batches",
+        batches.len()
+    );
+
+    Ok(HttpResponse::Ok().json(ListBatchesResponse { batches }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{register_client, test_state};
+    use actix_web::{test, App};
+    use common::utils::get_current_timestamp_ms;
+    use crypto::{generate_nonce, sign_message};
+    use ed25519_dalek::SigningKey;
+
+    fn rename_request(
+        signing_key: &SigningKey,
+        client_id: &str,
+        old_batch_id: &str,
+        new_batch_id: &str,
+    ) -> test::TestRequest {
+        let timestamp = get_current_timestamp_ms();
+        let nonce = generate_nonce();
+        let signature = sign_message(
+            signing_key,
+            &rename_message(old_batch_id, new_batch_id, timestamp, &nonce),
+        );
+        test::TestRequest::post()
+            .uri(&format!("/batch/{}/rename", old_batch_id))
+            .set_json(RenameBatchRequest {
+                new_batch_id: new_batch_id.to_string(),
+                signature: hex::encode(signature.to_bytes()),
+                timestamp,
+                nonce: hex::encode(nonce),
+                client_id: client_id.to_string(),
+            })
+    }
+
+    #[actix_web::test]
+    async fn test_rename_batch() {
+        let (state, _dir) = test_state();
+        let (signing_key, client_id) = register_client(&state).await;
+        state
+            .storage
+            .store_file_and_update_tree(&client_id, "tmp", "a.txt", b"a")
+            .await
+            .unwrap();
+        let app =
+            test::init_service(App::new().app_data(state.clone()).service(rename_batch)).await;
+
+        let req = rename_request(&signing_key, &client_id, "tmp", "final").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let filenames = state
+            .storage
+            .load_batch_filenames(&client_id, "final")
+            .await
+            .unwrap();
+        assert_eq!(filenames, vec!["a.txt"]);
+    }
+
+    #[actix_web::test]
+    async fn test_rename_batch_conflict() {
+        let (state, _dir) = test_state();
+        let (signing_key, client_id) = register_client(&state).await;
+        for batch_id in ["tmp", "final"] {
+            state
+                .storage
+                .store_file_and_update_tree(&client_id, batch_id, "a.txt", b"a")
+                .await
+                .unwrap();
+        }
+        let app =
+            test::init_service(App::new().app_data(state.clone()).service(rename_batch)).await;
+
+        let req = rename_request(&signing_key, &client_id, "tmp", "final").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn test_rename_unknown_batch_is_not_found() {
+        let (state, _dir) = test_state();
+        let (signing_key, client_id) = register_client(&state).await;
+        let app =
+            test::init_service(App::new().app_data(state.clone()).service(rename_batch)).await;
+
+        let req = rename_request(&signing_key, &client_id, "missing", "final").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+        let body = test::read_body(resp).await;
+        assert_eq!(String::from_utf8_lossy(&body), "Batch missing not found");
+    }
+
+    fn finalize_request(
+        signing_key: &SigningKey,
+        client_id: &str,
+        batch_id: &str,
+        contents: &[&[u8]],
+    ) -> test::TestRequest {
+        let leaves: Vec<[u8; 32]> = contents.iter().map(|c| crypto::hash_leaf(c)).collect();
+        let leaf_hashes: Vec<String> = leaves.iter().map(hex::encode).collect();
+        let root_hash = hex::encode(MerkleTree::from_leaf_hashes(&leaves).unwrap().root_hash());
+        let timestamp = get_current_timestamp_ms();
+        let nonce = generate_nonce();
+        let signature = sign_message(
+            signing_key,
+            &finalize_message(batch_id, &leaf_hashes, &root_hash, timestamp, &nonce),
+        );
+        test::TestRequest::post()
+            .uri(&format!("/batch/{}/finalize", batch_id))
+            .set_json(FinalizeBatchRequest {
+                leaf_hashes,
+                root_hash,
+                signature: hex::encode(signature.to_bytes()),
+                timestamp,
+                nonce: hex::encode(nonce),
+                client_id: client_id.to_string(),
+            })
+    }
+
+    #[actix_web::test]
+    async fn test_finalize_detects_missing_file() {
+        let (state, _dir) = test_state();
+        let (signing_key, client_id) = register_client(&state).await;
+        // The client meant to upload three files but b.txt never arrived
+        for (name, content) in [("a.txt", b"a"), ("c.txt", b"c")] {
+            state
+                .storage
+                .store_file_and_update_tree(&client_id, "batch", name, content)
+                .await
+                .unwrap();
+        }
+        let app =
+            test::init_service(App::new().app_data(state.clone()).service(finalize_batch)).await;
+
+        let req = finalize_request(&signing_key, &client_id, "batch", &[b"a", b"b", b"c"]);
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::CONFLICT);
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("1 committed files were not received"));
+        assert!(state
+            .storage
+            .load_committed_root(&client_id, "batch")
+            .await
+            .unwrap()
+            .is_none());
+
+        // Once the missing file arrives the same file set is accepted and recorded
+        state
+            .storage
+            .store_file_and_update_tree(&client_id, "batch", "b.txt", b"b")
+            .await
+            .unwrap();
+        let req = finalize_request(&signing_key, &client_id, "batch", &[b"a", b"b", b"c"]);
+        assert!(test::call_service(&app, req.to_request())
+            .await
+            .status()
+            .is_success());
+        let tree = state
+            .storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            state
+                .storage
+                .load_committed_root(&client_id, "batch")
+                .await
+                .unwrap(),
+            Some(tree.root_hash())
+        );
+
+        // A commitment signed by someone else is rejected
+        let (other_key, _) = crypto::generate_keypair();
+        let req = finalize_request(&other_key, &client_id, "batch", &[b"a", b"b", b"c"]);
+        assert_eq!(
+            test::call_service(&app, req.to_request()).await.status(),
+            actix_web::http::StatusCode::UNAUTHORIZED
+        );
+    }
+
+    const BOUNDARY: &str = "vs-replace-boundary";
+
+    /// Multipart replacement of a batch's files with `files`, sending only `uploads`
+    /// Each upload is sent with the content given for it, which may differ from the
+    /// content the manifest commits to
+    fn replace_request(
+        signing_key: &SigningKey,
+        client_id: &str,
+        batch_id: &str,
+        files: &[(&str, &[u8])],
+        uploads: &[(&str, &[u8])],
+    ) -> test::TestRequest {
+        let entries: Vec<BatchFileEntry> = files
+            .iter()
+            .map(|(filename, content)| BatchFileEntry {
+                filename: filename.to_string(),
+                leaf_hash: hex::encode(crypto::hash_leaf(content)),
+            })
+            .collect();
+        let leaves: Vec<[u8; 32]> = files.iter().map(|(_, c)| crypto::hash_leaf(c)).collect();
+        let nonce = generate_nonce();
+        let mut manifest = ReplaceBatchManifest {
+            files: entries,
+            root_hash: hex::encode(MerkleTree::from_leaf_hashes(&leaves).unwrap().root_hash()),
+            signature: String::new(),
+            timestamp: get_current_timestamp_ms(),
+            nonce: hex::encode(nonce),
+            client_id: client_id.to_string(),
+        };
+        let signature = sign_message(signing_key, &replace_message(batch_id, &manifest, &nonce));
+        manifest.signature = hex::encode(signature.to_bytes());
+
+        let mut body = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"manifest\"\r\n\r\n{}\r\n",
+            BOUNDARY,
+            serde_json::to_string(&manifest).unwrap()
+        )
+        .into_bytes();
+        for (filename, content) in uploads {
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"files\"; filename=\"{}\"\r\n\
+                     Content-Type: application/octet-stream\r\n\r\n",
+                    BOUNDARY, filename
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+
+        test::TestRequest::post()
+            .uri(&format!("/batch/{}/replace", batch_id))
+            .insert_header((
+                "content-type",
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            ))
+            .set_payload(body)
+    }
+
+    async fn stored_files(state: &web::Data<AppState>, client_id: &str) -> Vec<(String, Vec<u8>)> {
+        let mut files = Vec::new();
+        for filename in state
+            .storage
+            .load_batch_filenames(client_id, "batch")
+            .await
+            .unwrap()
+        {
+            let content = state
+                .storage
+                .read_file(client_id, "batch", &filename)
+                .await
+                .unwrap();
+            files.push((filename, content));
+        }
+        files
+    }
+
+    #[actix_web::test]
+    async fn test_replace_batch() {
+        let (state, _dir) = test_state();
+        let (signing_key, client_id) = register_client(&state).await;
+        for (name, content) in [("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")] {
+            state
+                .storage
+                .store_file_and_update_tree(&client_id, "batch", name, content)
+                .await
+                .unwrap();
+        }
+        let app =
+            test::init_service(App::new().app_data(state.clone()).service(replace_batch)).await;
+
+        // a.txt is kept, b.txt changes, c.txt goes away and d.txt is new
+        let files: [(&str, &[u8]); 3] = [("a.txt", b"a"), ("b.txt", b"b2"), ("d.txt", b"d")];
+        let req = replace_request(&signing_key, &client_id, "batch", &files, &files[1..]);
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert!(resp.status().is_success(), "{:?}", resp.status());
+
+        assert_eq!(
+            stored_files(&state, &client_id).await,
+            files
+                .iter()
+                .map(|(name, content)| (name.to_string(), content.to_vec()))
+                .collect::<Vec<_>>()
+        );
+        let leaves: Vec<[u8; 32]> = files.iter().map(|(_, c)| crypto::hash_leaf(c)).collect();
+        let root = MerkleTree::from_leaf_hashes(&leaves).unwrap().root_hash();
+        let tree = state
+            .storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tree.root_hash(), root);
+        assert_eq!(
+            state
+                .storage
+                .load_committed_root(&client_id, "batch")
+                .await
+                .unwrap(),
+            Some(root)
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_rejected_replace_leaves_batch_unchanged() {
+        let (state, _dir) = test_state();
+        let (signing_key, client_id) = register_client(&state).await;
+        for (name, content) in [("a.txt", b"a"), ("b.txt", b"b")] {
+            state
+                .storage
+                .store_file_and_update_tree(&client_id, "batch", name, content)
+                .await
+                .unwrap();
+        }
+        let before = stored_files(&state, &client_id).await;
+        let app =
+            test::init_service(App::new().app_data(state.clone()).service(replace_batch)).await;
+
+        // A kept file whose stored content differs from the manifest
+        let files: [(&str, &[u8]); 2] = [("a.txt", b"stale"), ("c.txt", b"c")];
+        let req = replace_request(&signing_key, &client_id, "batch", &files, &files[1..]);
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::CONFLICT);
+
+        // An upload whose content does not match its manifest entry
+        let files: [(&str, &[u8]); 2] = [("a.txt", b"a"), ("c.txt", b"c")];
+        let req = replace_request(
+            &signing_key,
+            &client_id,
+            "batch",
+            &files,
+            &[("c.txt", b"tampered")],
+        );
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+        // An upload the manifest does not list
+        let req = replace_request(
+            &signing_key,
+            &client_id,
+            "batch",
+            &files,
+            &[("c.txt", b"c"), ("x.txt", b"x")],
+        );
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+        // A manifest signed by someone else
+        let (other_key, _) = crypto::generate_keypair();
+        let req = replace_request(&other_key, &client_id, "batch", &files, &files[1..]);
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+
+        assert_eq!(stored_files(&state, &client_id).await, before);
+        assert!(state
+            .storage
+            .load_committed_root(&client_id, "batch")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_list_batch_files() {
+        let (state, _dir) = test_state();
+        let (signing_key, client_id) = register_client(&state).await;
+        for (name, content) in [("b.txt", b"b"), ("a.txt", b"a")] {
+            state
+                .storage
+                .store_file_and_update_tree(&client_id, "batch", name, content)
+                .await
+                .unwrap();
+        }
+        let annotations: common::annotations::Annotations =
+            [("description".to_string(), "nightly".to_string())].into();
+        state
+            .storage
+            .set_batch_annotations(&client_id, "batch", &annotations)
+            .await
+            .unwrap();
+        let app =
+            test::init_service(App::new().app_data(state.clone()).service(list_batch_files)).await;
+
+        let timestamp = get_current_timestamp_ms();
+        let signature = sign_message(&signing_key, &list_message("batch", timestamp));
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/batch/batch/files?signature={}&timestamp={}&client_id={}",
+                hex::encode(signature.to_bytes()),
+                timestamp,
+                client_id
+            ))
+            .to_request();
+        let resp: BatchFilesResponse = test::call_and_read_body_json(&app, req).await;
+
+        // The root a client lost can be recovered: it is the one the uploader computed
+        let uploaded_root =
+            MerkleTree::from_leaf_hashes(&[crypto::hash_leaf(b"a"), crypto::hash_leaf(b"b")])
+                .unwrap()
+                .root_hash();
+        assert_eq!(resp.root_hash, hex::encode(uploaded_root));
+        assert!(!resp.public);
+        assert_eq!(resp.annotations, annotations);
+        assert_eq!(
+            resp.files,
+            vec![
+                BatchFileEntry {
+                    filename: "a.txt".to_string(),
+                    leaf_hash: hex::encode(crypto::hash_leaf(b"a")),
+                },
+                BatchFileEntry {
+                    filename: "b.txt".to_string(),
+                    leaf_hash: hex::encode(crypto::hash_leaf(b"b")),
+                },
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_list_batches() {
+        let (state, _dir) = test_state();
+        let (signing_key, client_id) = register_client(&state).await;
+        let (other_key, other_id) = register_client(&state).await;
+        let app =
+            test::init_service(App::new().app_data(state.clone()).service(list_batches)).await;
+        let list = |signing_key: &SigningKey, client_id: &str| {
+            let timestamp = get_current_timestamp_ms();
+            let signature = sign_message(signing_key, &list_batches_message(timestamp));
+            test::TestRequest::get()
+                .uri(&format!(
+                    "/batches?signature={}&timestamp={}&client_id={}",
+                    hex::encode(signature.to_bytes()),
+                    timestamp,
+                    client_id
+                ))
+                .to_request()
+        };
+
+        let resp: ListBatchesResponse =
+            test::call_and_read_body_json(&app, list(&signing_key, &client_id)).await;
+        assert!(resp.batches.is_empty());
+
+        state
+            .storage
+            .store_file_and_update_tree(&client_id, "second", "a.txt", b"a")
+            .await
+            .unwrap();
+        let resp: ListBatchesResponse =
+            test::call_and_read_body_json(&app, list(&signing_key, &client_id)).await;
+        assert_eq!(resp.batches, ["second"]);
+
+        state
+            .storage
+            .store_file_and_update_tree(&client_id, "first", "a.txt", b"a")
+            .await
+            .unwrap();
+        state
+            .storage
+            .create_batch(&client_id, "third")
+            .await
+            .unwrap();
+        state
+            .storage
+            .store_file_and_update_tree(&other_id, "theirs", "a.txt", b"a")
+            .await
+            .unwrap();
+        let resp: ListBatchesResponse =
+            test::call_and_read_body_json(&app, list(&signing_key, &client_id)).await;
+        assert_eq!(resp.batches, ["first", "second", "third"]);
+
+        // Listing another client's batches needs that client's signature
+        let resp = test::call_service(&app, list(&other_key, &client_id)).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_create_empty_batch_then_append() {
+        use actix_web::http::StatusCode;
+
+        let (state, _dir) = test_state();
+        let (signing_key, verifying_key) = crypto::generate_keypair();
+        let client_id = crypto::compute_client_id(&verifying_key);
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(create_batch)
+                .service(list_batch_files),
+        )
+        .await;
+        let create_request = || {
+            let timestamp = get_current_timestamp_ms();
+            let signature = sign_message(&signing_key, &create_message("batch", timestamp));
+            test::TestRequest::post()
+                .uri("/batch")
+                .set_json(CreateBatchRequest {
+                    batch_id: "batch".to_string(),
+                    signature: hex::encode(signature.to_bytes()),
+                    timestamp,
+                    public_key: hex::encode(verifying_key.as_bytes()),
+                })
+                .to_request()
+        };
+        let list_request = || {
+            let timestamp = get_current_timestamp_ms();
+            let signature = sign_message(&signing_key, &list_message("batch", timestamp));
+            test::TestRequest::get()
+                .uri(&format!(
+                    "/batch/batch/files?signature={}&timestamp={}&client_id={}",
+                    hex::encode(signature.to_bytes()),
+                    timestamp,
+                    client_id
+                ))
+                .to_request()
+        };
+
+        // Creating the batch registers the client, like a first upload
+        let resp = test::call_service(&app, create_request()).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert!(state.storage.client_exists(&client_id).await.unwrap());
+        assert!(state
+            .storage
+            .batch_exists(&client_id, "batch")
+            .await
+            .unwrap());
+
+        let resp = test::call_service(&app, create_request()).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let listing: BatchFilesResponse = test::call_and_read_body_json(&app, list_request()).await;
+        assert!(listing.files.is_empty());
+        assert!(listing.root_hash.is_empty());
+
+        state
+            .storage
+            .store_file_and_update_tree(&client_id, "batch", "a.txt", b"a")
+            .await
+            .unwrap();
+        let listing: BatchFilesResponse = test::call_and_read_body_json(&app, list_request()).await;
+        assert_eq!(
+            listing.files,
+            vec![BatchFileEntry {
+                filename: "a.txt".to_string(),
+                leaf_hash: hex::encode(crypto::hash_leaf(b"a")),
+            }]
+        );
+        assert_eq!(listing.root_hash, hex::encode(crypto::hash_leaf(b"a")));
+    }
+
+    #[actix_web::test]
+    async fn test_skewed_timestamp_reports_server_time() {
+        use common::utils::SERVER_TIME_HEADER;
+
+        let (state, _dir) = test_state();
+        let (signing_key, client_id) = register_client(&state).await;
+        state
+            .storage
+            .store_file_and_update_tree(&client_id, "batch", "a.txt", b"a")
+            .await
+            .unwrap();
+        let app =
+            test::init_service(App::new().app_data(state.clone()).service(list_batch_files)).await;
+
+        // A client whose clock runs ten minutes behind
+        let timestamp = get_current_timestamp_ms() - 600_000;
+        let signature = sign_message(&signing_key, &list_message("batch", timestamp));
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/batch/batch/files?signature={}&timestamp={}&client_id={}",
+                hex::encode(signature.to_bytes()),
+                timestamp,
+                client_id
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+
+        let server_time: u64 = resp
+            .headers()
+            .get(SERVER_TIME_HEADER)
+            .expect("timestamp rejections carry the server time")
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(server_time.abs_diff(timestamp + 600_000) < 60_000);
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("too old"));
+    }
+
+    fn access_request(
+        signing_key: &SigningKey,
+        client_id: &str,
+        action: &str,
+        grantee_id: &str,
+    ) -> test::TestRequest {
+        access_request_with_nonce(
+            signing_key,
+            client_id,
+            action,
+            grantee_id,
+            get_current_timestamp_ms(),
+            generate_nonce(),
+        )
+    }
+
+    fn access_request_with_nonce(
+        signing_key: &SigningKey,
+        client_id: &str,
+        action: &str,
+        grantee_id: &str,
+        timestamp: u64,
+        nonce: [u8; 16],
+    ) -> test::TestRequest {
+        let signature = sign_message(
+            signing_key,
+            &access_message(action, "batch", grantee_id, timestamp, &nonce),
+        );
+        test::TestRequest::post()
+            .uri(&format!("/batch/batch/{}", action))
+            .set_json(BatchAccessRequest {
+                grantee_id: grantee_id.to_string(),
+                signature: hex::encode(signature.to_bytes()),
+                timestamp,
+                nonce: hex::encode(nonce),
+                client_id: client_id.to_string(),
+            })
+    }
+
+    #[actix_web::test]
+    async fn test_grant_and_revoke_access() {
+        let (state, _dir) = test_state();
+        let (owner_key, owner_id) = register_client(&state).await;
+        let (reader_key, reader_id) = register_client(&state).await;
+        state
+            .storage
+            .store_file_and_update_tree(&owner_id, "batch", "a.txt", b"a")
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(grant_access)
+                .service(revoke_access),
+        )
+        .await;
+
+        let req = access_request(&owner_key, &owner_id, "grant", &reader_id).to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        assert!(state
+            .storage
+            .has_batch_access(&owner_id, "batch", &reader_id)
+            .await
+            .unwrap());
+
+        // Only the owner can change the access list
+        let req = access_request(&reader_key, &owner_id, "revoke", &reader_id).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+
+        // A grant signature cannot be replayed as a revoke
+        let timestamp = get_current_timestamp_ms();
+        let nonce = generate_nonce();
+        let signature = sign_message(
+            &owner_key,
+            &access_message("grant", "batch", &reader_id, timestamp, &nonce),
+        );
+        let req = test::TestRequest::post()
+            .uri("/batch/batch/revoke")
+            .set_json(BatchAccessRequest {
+                grantee_id: reader_id.clone(),
+                signature: hex::encode(signature.to_bytes()),
+                timestamp,
+                nonce: hex::encode(nonce),
+                client_id: owner_id.clone(),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+
+        let req = access_request(&owner_key, &owner_id, "revoke", &reader_id).to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        assert!(!state
+            .storage
+            .has_batch_access(&owner_id, "batch", &reader_id)
+            .await
+            .unwrap());
+    }
+
+    #[actix_web::test]
+    async fn test_replayed_access_change_is_rejected() {
+        let (state, _dir) = test_state();
+        let (owner_key, owner_id) = register_client(&state).await;
+        let (_, reader_id) = register_client(&state).await;
+        state
+            .storage
+            .store_file_and_update_tree(&owner_id, "batch", "a.txt", b"a")
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(grant_access)
+                .service(revoke_access),
+        )
+        .await;
+
+        let timestamp = get_current_timestamp_ms();
+        let nonce = generate_nonce();
+        let revoke = || {
+            access_request_with_nonce(
+                &owner_key, &owner_id, "revoke", &reader_id, timestamp, nonce,
+            )
+            .to_request()
+        };
+        let req = access_request(&owner_key, &owner_id, "grant", &reader_id).to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        assert!(test::call_service(&app, revoke())
+            .await
+            .status()
+            .is_success());
+
+        // The owner grants access again; replaying the captured revoke must not undo it
+        let req = access_request(&owner_key, &owner_id, "grant", &reader_id).to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let resp = test::call_service(&app, revoke()).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+        assert!(state
+            .storage
+            .has_batch_access(&owner_id, "batch", &reader_id)
+            .await
+            .unwrap());
+    }
+
+    #[actix_web::test]
+    async fn test_batch_root() {
+        let (state, _dir) = test_state();
+        let (owner_key, owner_id) = register_client(&state).await;
+        // The reader never uploaded, so the server does not know its key yet
+        let (reader_key, reader_verifying_key) = crypto::generate_keypair();
+        let reader_id = crypto::compute_client_id(&reader_verifying_key);
+        let reader_public_key = hex::encode(reader_verifying_key.as_bytes());
+        for (name, content) in [("a.txt", b"a"), ("b.txt", b"b")] {
+            state
+                .storage
+                .store_file_and_update_tree(&owner_id, "batch", name, content)
+                .await
+                .unwrap();
+        }
+        let app = test::init_service(App::new().app_data(state.clone()).service(batch_root)).await;
+        let expected = merkle_tree::MerkleTree::from_leaf_hashes(&[
+            crypto::hash_leaf(b"a"),
+            crypto::hash_leaf(b"b"),
+        ])
+        .unwrap();
+
+        let root_request = |key: &SigningKey, query: &str| {
+            let timestamp = get_current_timestamp_ms();
+            let owner = (!query.is_empty()).then_some(owner_id.as_str());
+            let signature = sign_message(key, &root_message("batch", timestamp, owner));
+            test::TestRequest::get()
+                .uri(&format!(
+                    "/batch/batch/root?signature={}&timestamp={}&client_id={}{}",
+                    hex::encode(signature.to_bytes()),
+                    timestamp,
+                    owner_id,
+                    query
+                ))
+                .to_request()
+        };
+        let reader_query = format!(
+            "&requester_id={}&requester_public_key={}",
+            reader_id, reader_public_key
+        );
+
+        // Owner
+        let resp: BatchRootResponse =
+            test::call_and_read_body_json(&app, root_request(&owner_key, "")).await;
+        assert_eq!(resp.root_hash, hex::encode(expected.root_hash()));
+        assert_eq!(resp.num_files, 2);
+
+        // Private batch: neither an anonymous nor a non-granted reader gets the root
+        let anonymous = test::TestRequest::get()
+            .uri(&format!("/batch/batch/root?client_id={}", owner_id))
+            .to_request();
+        let resp = test::call_service(&app, anonymous).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+        let resp = test::call_service(&app, root_request(&reader_key, &reader_query)).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::FORBIDDEN);
+
+        // A key that does not belong to the claimed requester is rejected
+        let (other_key, other_verifying_key) = crypto::generate_keypair();
+        let mismatched = format!(
+            "&requester_id={}&requester_public_key={}",
+            reader_id,
+            hex::encode(other_verifying_key.as_bytes())
+        );
+        let resp = test::call_service(&app, root_request(&other_key, &mismatched)).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+
+        // Granted reader, registered by its first signed read
+        state
+            .storage
+            .grant_batch_access(&owner_id, "batch", &reader_id)
+            .await
+            .unwrap();
+        let resp: BatchRootResponse =
+            test::call_and_read_body_json(&app, root_request(&reader_key, &reader_query)).await;
+        assert_eq!(resp.root_hash, hex::encode(expected.root_hash()));
+        assert!(state
+            .storage
+            .load_public_key(&reader_id)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_batch_tree() {
+        let (state, _dir) = test_state();
+        let (signing_key, client_id) = register_client(&state).await;
+        let leaves = [
+            crypto::hash_leaf(b"a"),
+            crypto::hash_leaf(b"b"),
+            crypto::hash_leaf(b"c"),
+        ];
+        for (name, content) in [("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")] {
+            state
+                .storage
+                .store_file_and_update_tree(&client_id, "batch", name, content)
+                .await
+                .unwrap();
+        }
+        let app = test::init_service(App::new().app_data(state.clone()).service(batch_tree)).await;
+        let tree_request = |message: Vec<u8>, timestamp: u64| {
+            let signature = sign_message(&signing_key, &message);
+            test::TestRequest::get()
+                .uri(&format!(
+                    "/batch/batch/tree?signature={}&timestamp={}&client_id={}",
+                    hex::encode(signature.to_bytes()),
+                    timestamp,
+                    client_id
+                ))
+                .to_request()
+        };
+
+        let timestamp = get_current_timestamp_ms();
+        let resp: BatchTreeResponse = test::call_and_read_body_json(
+            &app,
+            tree_request(tree_message("batch", timestamp, None), timestamp),
+        )
+        .await;
+        let expected = merkle_tree::MerkleTree::from_leaf_hashes(&leaves).unwrap();
+        assert_eq!(resp.root_hash, hex::encode(expected.root_hash()));
+        assert_eq!(resp.levels, expected.root_and_levels_hex().1);
+        // Three leaves, two nodes above them, then the root
+        assert_eq!(
+            resp.levels.iter().map(Vec::len).collect::<Vec<_>>(),
+            [3, 2, 1]
+        );
+
+        // A signature made for the root endpoint does not authorize a tree read
+        let resp = test::call_service(
+            &app,
+            tree_request(root_message("batch", timestamp, None), timestamp),
+        )
+        .await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,14 @@
+use crate::state::AppState;
+use actix_web::{get, web, HttpResponse, Result as ActixResult};
+use common::{CapabilitiesResponse, PROOF_FORMAT_VERSION};
+
+/// Advertise how proofs and roots are built, so clients can match the hash truncation
+/// before verifying anything. Unauthenticated: it only describes the server.
+#[get("/capabilities")]
+pub async fn capabilities(state: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(CapabilitiesResponse {
+        hash_algorithm: merkle_tree::HASH_ALGORITHM.to_string(),
+        proof_version: PROOF_FORMAT_VERSION,
+        hash_truncation_bytes: state.hash_truncation_bytes,
+    }))
+}
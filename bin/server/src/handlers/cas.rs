@@ -0,0 +1,290 @@
+use crate::auth::AuthVerifier;
+use crate::handlers::error::{
+    handle_auth_error, handle_error, handle_server_error, handle_storage_error,
+    handle_timestamp_error,
+};
+use crate::proof::{proof_to_json, served_tree};
+use crate::state::AppState;
+use actix_web::{get, web, HttpResponse, Result as ActixResult};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use common::auth_message::cas_message;
+use common::{CasRequest, CasResponse, PROOF_FORMAT_VERSION};
+use crypto::hash_leaf;
+use tracing::{info, warn};
+
+/// Serve content by its leaf hash, independent of filename and batch ID
+/// Searches every batch the requester may read (its own, batches shared with it and
+/// public batches; only public batches for anonymous requests) and serves the first
+/// match with its proof in that batch. Content held only in batches the requester may
+/// not read is reported as not found, so the endpoint does not reveal that it exists.
+/// Lookup scans the stored Merkle trees, which keep every leaf hash, so its cost grows
+/// with the number of batches.
+#[get("/cas/{leaf_hash}")]
+pub async fn get_by_hash(
+    path: web::Path<String>,
+    query: web::Query<CasRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let leaf_hash_hex = path.into_inner();
+    let req = query.into_inner();
+
+    info!(leaf_hash = ?leaf_hash_hex, "GET /cas - Request received");
+
+    let leaf_hash: [u8; 32] = hex::decode(&leaf_hash_hex)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            actix_web::error::ErrorBadRequest("Leaf hash must be exactly 64 hex characters")
+        })?;
+
+    let requester_id = match (req.signature, req.timestamp, req.client_id) {
+        (Some(signature), Some(timestamp), Some(client_id)) => {
+            // Validate timestamp to prevent replay attacks
+            AuthVerifier::validate_timestamp_default(timestamp).map_err(handle_timestamp_error)?;
+
+            let message = cas_message(&leaf_hash_hex, timestamp);
+            let signature = AuthVerifier::parse_signature(&signature)
+                .map_err(|e| handle_error("Failed to parse signature", e))?;
+            AuthVerifier::verify_request_signature_with_client_id(
+                &state, &client_id, &message, &signature,
+            )
+            .await
+            .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+            Some(client_id)
+        }
+        (None, None, _) => None,
+        _ => {
+            return Err(actix_web::error::ErrorBadRequest(
+                "Signature, timestamp and client_id must be provided together",
+            ));
+        }
+    };
+
+    let batches = state
+        .storage
+        .list_batches()
+        .await
+        .map_err(|e| handle_storage_error("Failed to list batches", e))?;
+
+    for (client_id, batch_id) in batches {
+        if !can_read(&state, &client_id, &batch_id, requester_id.as_deref()).await? {
+            continue;
+        }
+        if let Some(response) = find_in_batch(&state, &client_id, &batch_id, &leaf_hash).await? {
+            info!(
+                client_id = ?client_id,
+                batch_id = ?batch_id,
+                requester_id = ?requester_id,
+                "GET /cas - Served {}",
+                response.filename
+            );
+            return Ok(HttpResponse::Ok().json(response));
+        }
+    }
+
+    Err(actix_web::error::ErrorNotFound(format!(
+        "No readable content with leaf hash {}",
+        leaf_hash_hex
+    )))
+}
+
+/// Whether the requester (or an anonymous reader) may read a batch
+async fn can_read(
+    state: &web::Data<AppState>,
+    client_id: &str,
+    batch_id: &str,
+    requester_id: Option<&str>,
+) -> ActixResult<bool> {
+    if requester_id == Some(client_id) {
+        return Ok(true);
+    }
+    let public = state
+        .storage
+        .is_batch_public(client_id, batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to check batch visibility", e))?;
+    match requester_id {
+        Some(requester_id) if !public => state
+            .storage
+            .has_batch_access(client_id, batch_id, requester_id)
+            .await
+            .map_err(|e| handle_storage_error("Failed to check batch access", e)),
+        _ => Ok(public),
+    }
+}
+
+/// Look for the leaf hash in one batch and build the response if it is there
+async fn find_in_batch(
+    state: &web::Data<AppState>,
+    client_id: &str,
+    batch_id: &str,
+    leaf_hash: &[u8; 32],
+) -> ActixResult<Option<CasResponse>> {
+    let Some(tree) = state
+        .storage
+        .load_merkle_tree(client_id, batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to load Merkle tree", e))?
+    else {
+        return Ok(None);
+    };
+    let Some(leaf_index) =
+        (0..tree.num_leaves()).find(|&index| tree.leaf_hash(index).as_ref() == Some(leaf_hash))
+    else {
+        return Ok(None);
+    };
+
+    // Filenames are sorted, matching leaf order in the tree
+    let mut filenames = state
+        .storage
+        .load_batch_filenames(client_id, batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to load batch filenames", e))?;
+    filenames.sort();
+    if filenames.len() != tree.num_leaves() {
+        warn!(
+            client_id = ?client_id,
+            batch_id = ?batch_id,
+            "Stored tree has {} leaves but batch has {} files; skipping batch",
+            tree.num_leaves(),
+            filenames.len()
+        );
+        return Ok(None);
+    }
+    let filename = filenames.swap_remove(leaf_index);
+
+    let content = state
+        .storage
+        .read_file(client_id, batch_id, &filename)
+        .await
+        .map_err(|e| handle_storage_error("Failed to read file", e))?;
+    // Never serve content that no longer matches the hash it was requested by
+    if &hash_leaf(&content) != leaf_hash {
+        warn!(
+            client_id = ?client_id,
+            batch_id = ?batch_id,
+            "Content of {} no longer matches its leaf hash; skipping batch",
+            filename
+        );
+        return Ok(None);
+    }
+
+    let proof = served_tree(state, tree)?
+        .generate_proof(leaf_index)
+        .map_err(|e| handle_server_error("Failed to generate proof", e))?;
+
+    Ok(Some(CasResponse {
+        client_id: client_id.to_string(),
+        batch_id: batch_id.to_string(),
+        filename,
+        file_content: STANDARD.encode(&content),
+        merkle_proof: proof_to_json(&proof, state.hash_truncation_bytes),
+        hash_algorithm: merkle_tree::HASH_ALGORITHM.to_string(),
+        proof_version: PROOF_FORMAT_VERSION,
+        hash_truncation_bytes: state.hash_truncation_bytes,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{register_client, test_state};
+    use actix_web::{test, App};
+    use common::utils::get_current_timestamp_ms;
+    use crypto::sign_message;
+    use ed25519_dalek::SigningKey;
+    use merkle_tree::{MerkleProof, ProofNode};
+
+    fn signed_uri(signing_key: &SigningKey, client_id: &str, leaf_hash_hex: &str) -> String {
+        let timestamp = get_current_timestamp_ms();
+        let signature = sign_message(signing_key, &cas_message(leaf_hash_hex, timestamp));
+        format!(
+            "/cas/{}?signature={}&timestamp={}&client_id={}",
+            leaf_hash_hex,
+            hex::encode(signature.to_bytes()),
+            timestamp,
+            client_id
+        )
+    }
+
+    #[actix_web::test]
+    async fn test_fetch_content_by_leaf_hash() {
+        let (state, _dir) = test_state();
+        let (owner_key, owner_id) = register_client(&state).await;
+        let (reader_key, reader_id) = register_client(&state).await;
+        for (name, content) in [("a.txt", b"a"), ("b.txt", b"b")] {
+            state
+                .storage
+                .store_file_and_update_tree(&owner_id, "batch", name, content)
+                .await
+                .unwrap();
+        }
+        let app = test::init_service(App::new().app_data(state.clone()).service(get_by_hash)).await;
+        let leaf_hash_hex = hex::encode(hash_leaf(b"b"));
+
+        let req = test::TestRequest::get()
+            .uri(&signed_uri(&owner_key, &owner_id, &leaf_hash_hex))
+            .to_request();
+        let resp: CasResponse = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp.client_id, owner_id);
+        assert_eq!(resp.batch_id, "batch");
+        assert_eq!(resp.filename, "b.txt");
+        assert_eq!(STANDARD.decode(&resp.file_content).unwrap(), b"b");
+
+        // The proof leads to the batch's stored root
+        let proof = MerkleProof {
+            leaf_index: 1,
+            leaf_hash: hash_leaf(b"b"),
+            path: resp
+                .merkle_proof
+                .iter()
+                .map(|node| ProofNode {
+                    hash: hex::decode(&node.hash).unwrap().try_into().unwrap(),
+                    is_left: node.is_left,
+                })
+                .collect(),
+        };
+        let tree = state
+            .storage
+            .load_merkle_tree(&owner_id, "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(proof.compute_root().unwrap(), tree.root_hash());
+
+        // Other clients and anonymous readers cannot see private content
+        let not_found = actix_web::http::StatusCode::NOT_FOUND;
+        let req = test::TestRequest::get()
+            .uri(&signed_uri(&reader_key, &reader_id, &leaf_hash_hex))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), not_found);
+        let req = test::TestRequest::get()
+            .uri(&format!("/cas/{}", leaf_hash_hex))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), not_found);
+
+        // Until the owner shares the batch
+        state
+            .storage
+            .grant_batch_access(&owner_id, "batch", &reader_id)
+            .await
+            .unwrap();
+        let req = test::TestRequest::get()
+            .uri(&signed_uri(&reader_key, &reader_id, &leaf_hash_hex))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        // Unknown hashes are not found; malformed ones are rejected
+        let unknown = hex::encode(hash_leaf(b"unknown"));
+        let req = test::TestRequest::get()
+            .uri(&signed_uri(&owner_key, &owner_id, &unknown))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), not_found);
+        let req = test::TestRequest::get().uri("/cas/xyz").to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            actix_web::http::StatusCode::BAD_REQUEST
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use crate::auth::AuthVerifier;
+use crate::handlers::error::handle_error;
+use crate::handlers::fallback::not_found;
+use crate::state::AppState;
+use actix_web::{post, web, HttpRequest, HttpResponse, Result as ActixResult};
+use common::auth_message::{upload_message, UploadMessage};
+use common::{SignPreviewRequest, SignPreviewResponse};
+use crypto::SIGNATURE_DOMAIN;
+use tracing::info;
+
+/// Show the exact message the server verifies an upload signature against
+/// For integrators whose signatures are rejected: they can compare these bytes with
+/// what their client signs. Only served with `--enable-debug-endpoints`; otherwise the
+/// route answers like an unknown path.
+#[post("/debug/sign-preview")]
+pub async fn sign_preview(
+    req: HttpRequest,
+    body: web::Json<SignPreviewRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    if !state.debug_endpoints {
+        return Ok(not_found(&req));
+    }
+    let preview = body.into_inner();
+
+    info!(
+        filename = ?preview.filename,
+        batch_id = ?preview.batch_id,
+        "POST /debug/sign-preview - Request received"
+    );
+
+    AuthVerifier::validate_public_key(&preview.public_key)
+        .map_err(|e| handle_error("Invalid public key", e))?;
+    let public_key = hex::decode(preview.public_key.trim())
+        .map_err(|e| handle_error("Failed to decode public key", e))?;
+    let nonce =
+        AuthVerifier::parse_nonce(&preview.nonce).map_err(|e| handle_error("Invalid nonce", e))?;
+    let message = upload_message(&UploadMessage {
+        filename: &preview.filename,
+        batch_id: &preview.batch_id,
+        file_hash: &preview.file_hash,
+        timestamp: preview.timestamp,
+        nonce: &nonce,
+        public_key: &public_key,
+        public: preview.public,
+        annotations: preview.annotations.as_ref(),
+    });
+
+    let mut signed_bytes = SIGNATURE_DOMAIN.to_vec();
+    signed_bytes.extend_from_slice(&message);
+    Ok(HttpResponse::Ok().json(SignPreviewResponse {
+        message_hex: hex::encode(&message),
+        signed_bytes_hex: hex::encode(signed_bytes),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{test_state, TempDataDir};
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use common::annotations::Annotations;
+    use crypto::{generate_keypair, sign_message, verify_signature};
+    use ed25519_dalek::Verifier;
+    use std::sync::Arc;
+    use storage::filesystem::FilesystemStorage;
+
+    fn preview_request(public_key: &[u8]) -> SignPreviewRequest {
+        SignPreviewRequest {
+            filename: "a.txt".to_string(),
+            batch_id: "batch".to_string(),
+            file_hash: hex::encode(crypto::hash_leaf(b"a")),
+            timestamp: 1_700_000_000_000,
+            nonce: "00112233445566778899aabbccddeeff".to_string(),
+            public_key: hex::encode(public_key),
+            public: true,
+            annotations: Some(Annotations::from([(
+                "commit".to_string(),
+                "1a2b3c".to_string(),
+            )])),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_preview_matches_the_verified_message() {
+        let dir = TempDataDir::new();
+        let storage = Arc::new(FilesystemStorage::new(dir.0.clone()));
+        let state = web::Data::new(AppState::new(storage).with_debug_endpoints(true));
+        let app = test::init_service(App::new().app_data(state).service(sign_preview)).await;
+        let (signing_key, verifying_key) = generate_keypair();
+
+        let request = preview_request(verifying_key.as_bytes());
+        let req = test::TestRequest::post()
+            .uri("/debug/sign-preview")
+            .set_json(&request)
+            .to_request();
+        let preview: SignPreviewResponse = test::call_and_read_body_json(&app, req).await;
+
+        let message = upload_message(&UploadMessage {
+            filename: &request.filename,
+            batch_id: &request.batch_id,
+            file_hash: &request.file_hash,
+            timestamp: request.timestamp,
+            nonce: &AuthVerifier::parse_nonce(&request.nonce).unwrap(),
+            public_key: verifying_key.as_bytes(),
+            public: true,
+            annotations: request.annotations.as_ref(),
+        });
+        assert_eq!(preview.message_hex, hex::encode(&message));
+
+        // A client signing the previewed message produces a signature the server accepts,
+        // and the signature is over exactly the previewed signed bytes
+        let signature = sign_message(&signing_key, &hex::decode(&preview.message_hex).unwrap());
+        assert!(verify_signature(&verifying_key, &message, &signature).is_ok());
+        let signed_bytes = hex::decode(&preview.signed_bytes_hex).unwrap();
+        assert!(signed_bytes.starts_with(SIGNATURE_DOMAIN));
+        assert!(verifying_key.verify(&signed_bytes, &signature).is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_preview_is_not_served_by_default() {
+        let (state, _dir) = test_state();
+        let app = test::init_service(App::new().app_data(state).service(sign_preview)).await;
+        let (_, verifying_key) = generate_keypair();
+
+        let req = test::TestRequest::post()
+            .uri("/debug/sign-preview")
+            .set_json(preview_request(verifying_key.as_bytes()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
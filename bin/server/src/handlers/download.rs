@@ -1,20 +1,34 @@
 use crate::auth::AuthVerifier;
+use crate::compression::is_incompressible;
+use crate::handlers::access::{authorize_read, ReadCredentials};
 use crate::handlers::error::{
-    handle_auth_error, handle_error, handle_not_found, handle_server_error,
+    ensure_batch_exists, ensure_within_rate_limit, handle_auth_error, handle_error,
+    handle_storage_error,
 };
 use crate::proof::{generate_proof, proof_to_json};
 use crate::state::AppState;
+use actix_web::http::header::ContentEncoding;
 use actix_web::{get, web, HttpResponse, Result as ActixResult};
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
-use common::{file_utils, DownloadRequest, DownloadResponse};
-use tracing::info;
+use common::auth_message::download_message;
+use common::{file_utils, DownloadRequest, DownloadResponse, PROOF_FORMAT_VERSION};
+use tracing::{info, warn};
 
 /// Handle file download and proof generation
 #[get("/download")]
 pub async fn download(
     query: web::Query<DownloadRequest>,
     state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let result = serve_download(query, state.clone()).await;
+    state.metrics.record_download(&result);
+    result
+}
+
+async fn serve_download(
+    query: web::Query<DownloadRequest>,
+    state: web::Data<AppState>,
 ) -> ActixResult<HttpResponse> {
     let req = query.into_inner();
 
@@ -29,36 +43,92 @@ pub async fn download(
     file_utils::validate_filename(&req.filename)
         .map_err(|e| actix_web::error::ErrorBadRequest(e.message()))?;
 
-    // Validate timestamp to prevent replay attacks
-    AuthVerifier::validate_timestamp_default(req.timestamp)
-        .map_err(|e| handle_auth_error("Timestamp validation failed", e))?;
+    let client_id = req.client_id.clone();
+
+    // Signed downloads carry a nonce the server accepts only once; anonymous reads
+    // of public batches need no replay protection
+    let nonce = match (&req.signature, &req.nonce) {
+        (Some(_), Some(nonce)) => {
+            Some(AuthVerifier::parse_nonce(nonce).map_err(|e| handle_error("Invalid nonce", e))?)
+        }
+        (Some(_), None) => {
+            return Err(actix_web::error::ErrorBadRequest(
+                "Signed downloads must carry a nonce",
+            ))
+        }
+        (None, _) => None,
+    };
 
-    // Verify signature using client_id for O(1) key lookup
-    let message = build_message(&req.filename, &req.batch_id, req.timestamp);
-    let signature_obj = AuthVerifier::parse_signature(&req.signature)
-        .map_err(|e| handle_error("Failed to parse signature", e))?;
+    let scheme = AuthVerifier::parse_scheme(&req.scheme)
+        .map_err(|e| handle_error("Unsupported signature scheme", e))?;
 
-    AuthVerifier::verify_request_signature_with_client_id(
+    authorize_read(
         &state,
-        &req.client_id,
-        &message,
-        &signature_obj,
+        "GET /download",
+        ReadCredentials {
+            client_id: &client_id,
+            batch_id: &req.batch_id,
+            requester_id: req.requester_id.as_deref(),
+            requester_public_key: req.requester_public_key.as_deref(),
+            scheme,
+            signature: req.signature.as_deref(),
+            timestamp: req.timestamp,
+        },
+        |timestamp, owner| {
+            download_message(
+                &req.filename,
+                &req.batch_id,
+                timestamp,
+                &nonce.unwrap_or_default(),
+                owner,
+            )
+        },
     )
-    .await
-    .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+    .await?;
 
-    let client_id = req.client_id.clone();
+    // Only a signed requester ID is trusted, so anonymous reads count against the owner
+    let requester_id = match req.signature {
+        Some(_) => req.requester_id.as_deref().unwrap_or(&client_id),
+        None => &client_id,
+    };
+    // Anonymous reads are not authenticated, so they are not rate limited
+    if req.signature.is_some() {
+        ensure_within_rate_limit(&state, "GET /download", requester_id)?;
+    }
+    if let Some(nonce) = nonce {
+        AuthVerifier::check_nonce(&state, nonce)
+            .map_err(|e| handle_auth_error("Replay rejected", e))?;
+    }
 
-    info!(
-        client_id = ?client_id,
-        "GET /download - Signature verified"
-    );
+    // Held until the response is built; dropping it on any early return frees the slot
+    let _download_slot = match &state.download_limiter {
+        Some(limiter) => Some(limiter.try_acquire(requester_id).ok_or_else(|| {
+            warn!(
+                client_id = ?requester_id,
+                "GET /download - Too many concurrent downloads"
+            );
+            actix_web::error::ErrorTooManyRequests(format!(
+                "At most {} concurrent downloads per client; retry later",
+                limiter.max_per_client()
+            ))
+        })?),
+        None => None,
+    };
 
+    ensure_batch_exists(state.storage.as_ref(), &client_id, &req.batch_id).await?;
     let filenames = state
         .storage
         .load_batch_filenames(&client_id, &req.batch_id)
         .await
-        .map_err(|e| handle_not_found("Failed to load batch", &req.batch_id, e))?;
+        .map_err(|e| handle_storage_error("Failed to load batch", e))?;
+
+    // A batch whose files were all removed has no tree to prove anything against
+    if filenames.is_empty() {
+        return Err(actix_web::error::ErrorNotFound(format!(
+            "Batch {} has no files",
+            req.batch_id
+        )));
+    }
 
     if !filenames.contains(&req.filename.to_string()) {
         return Err(actix_web::error::ErrorNotFound(format!(
@@ -72,7 +142,7 @@ pub async fn download(
         .storage
         .file_exists(&client_id, &req.batch_id, &req.filename)
         .await
-        .map_err(|e| handle_server_error("Failed to check file existence", e))?;
+        .map_err(|e| handle_storage_error("Failed to check file existence", e))?;
 
     if !exists {
         return Err(actix_web::error::ErrorNotFound(format!(
@@ -85,14 +155,28 @@ pub async fn download(
         .storage
         .read_file(&client_id, &req.batch_id, &req.filename)
         .await
-        .map_err(|e| handle_server_error("Failed to read file", e))?;
+        .map_err(|e| handle_storage_error("Failed to read file", e))?;
 
+    // Already-compressed or very large content gains little from response
+    // compression, so opt out of it for this response
+    let skip_compression = is_incompressible(&file_content);
     let file_content_b64 = STANDARD.encode(&file_content);
 
+    // Only looked up on request, so default responses stay unchanged
+    let stored_at = if req.include_timestamp {
+        state
+            .storage
+            .file_stored_at(&client_id, &req.batch_id, &req.filename)
+            .await
+            .map_err(|e| handle_storage_error("Failed to load file timestamp", e))?
+    } else {
+        None
+    };
+
     // Generate Merkle proof
     let proof =
         generate_proof(&state, &client_id, &req.batch_id, &filenames, &req.filename).await?;
-    let proof_json = proof_to_json(&proof);
+    let proof_json = proof_to_json(&proof, state.hash_truncation_bytes);
 
     info!(
         "GET /download - File and proof for {} (proof length: {})",
@@ -100,18 +184,480 @@ pub async fn download(
         proof_json.len()
     );
 
-    Ok(HttpResponse::Ok().json(DownloadResponse {
+    let mut response = HttpResponse::Ok();
+    if skip_compression {
+        response.insert_header(ContentEncoding::Identity);
+    }
+    Ok(response.json(DownloadResponse {
         filename: req.filename,
         file_content: file_content_b64,
         merkle_proof: proof_json,
+        hash_algorithm: Some(merkle_tree::HASH_ALGORITHM.to_string()),
+        proof_version: PROOF_FORMAT_VERSION,
+        stored_at,
+        hash_truncation_bytes: state.hash_truncation_bytes,
     }))
 }
 
-/// Build message for download signature verification
-fn build_message(filename: &str, batch_id: &str, timestamp: u64) -> Vec<u8> {
-    let mut message = Vec::new();
-    message.extend_from_slice(filename.as_bytes());
-    message.extend_from_slice(batch_id.as_bytes());
-    message.extend_from_slice(&timestamp.to_be_bytes());
-    message
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{register_client, test_state};
+    use actix_web::{test, App};
+    use merkle_tree::{MerkleProof, MerkleTree, ProofNode};
+
+    /// Public key of the batch owner, and its client ID (SHA256 of the key)
+    const OWNER_KEY: [u8; 32] = [7u8; 32];
+    const CLIENT_ID: &str = "4bb06f8e4e3a7715d201d573d0aa423762e55dabd61a2c02278fa56cc6d294e0";
+    const BATCH_ID: &str = "batch";
+
+    async fn seed_batch(state: &web::Data<AppState>, public: bool) {
+        state
+            .storage
+            .store_public_key(CLIENT_ID, &OWNER_KEY)
+            .await
+            .unwrap();
+        state
+            .storage
+            .store_file_and_update_tree(CLIENT_ID, BATCH_ID, "a.txt", b"hello")
+            .await
+            .unwrap();
+        state
+            .storage
+            .set_batch_public(CLIENT_ID, BATCH_ID, public)
+            .await
+            .unwrap();
+    }
+
+    fn anonymous_request() -> test::TestRequest {
+        test::TestRequest::get().uri(&format!(
+            "/download?filename=a.txt&batch_id={}&client_id={}",
+            BATCH_ID, CLIENT_ID
+        ))
+    }
+
+    #[actix_web::test]
+    async fn test_anonymous_download_of_public_batch() {
+        let (state, _dir) = test_state();
+        seed_batch(&state, true).await;
+        let app = test::init_service(App::new().app_data(state.clone()).service(download)).await;
+
+        let resp = test::call_service(&app, anonymous_request().to_request()).await;
+        assert!(resp.status().is_success());
+
+        let body: DownloadResponse = test::read_body_json(resp).await;
+        assert_eq!(body.filename, "a.txt");
+        assert_eq!(STANDARD.decode(body.file_content).unwrap(), b"hello");
+    }
+
+    #[actix_web::test]
+    async fn test_stored_timestamp_only_when_requested() {
+        let (state, _dir) = test_state();
+        let before = common::utils::get_current_timestamp_ms();
+        seed_batch(&state, true).await;
+        let app = test::init_service(App::new().app_data(state.clone()).service(download)).await;
+
+        let resp = test::call_service(&app, anonymous_request().to_request()).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body.get("stored_at").is_none());
+
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/download?filename=a.txt&batch_id={}&client_id={}&include_timestamp=true",
+                BATCH_ID, CLIENT_ID
+            ))
+            .to_request();
+        let body: DownloadResponse = test::call_and_read_body_json(&app, req).await;
+        let stored_at = body.stored_at.expect("timestamp requested");
+        // Allow for coarse filesystem timestamp granularity
+        assert!(stored_at + 1000 >= before);
+        assert!(stored_at <= common::utils::get_current_timestamp_ms());
+    }
+
+    #[actix_web::test]
+    async fn test_batch_over_proof_limit_is_refused() {
+        use crate::test_utils::TempDataDir;
+        use actix_web::http::StatusCode;
+        use std::sync::Arc;
+        use storage::filesystem::FilesystemStorage;
+
+        let dir = TempDataDir::new();
+        let storage = Arc::new(FilesystemStorage::new(dir.0.clone()));
+        let state = web::Data::new(AppState::new(storage).with_max_proof_batch_files(Some(1)));
+        seed_batch(&state, true).await;
+        let app = test::init_service(App::new().app_data(state.clone()).service(download)).await;
+
+        // At the limit proofs are served as usual
+        let resp = test::call_service(&app, anonymous_request().to_request()).await;
+        assert!(resp.status().is_success());
+
+        state
+            .storage
+            .store_file_and_update_tree(CLIENT_ID, BATCH_ID, "b.txt", b"world")
+            .await
+            .unwrap();
+        let resp = test::call_service(&app, anonymous_request().to_request()).await;
+        assert_eq!(resp.status(), StatusCode::INSUFFICIENT_STORAGE);
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("holds 2 files, the limit is 1"));
+    }
+
+    #[actix_web::test]
+    async fn test_concurrent_downloads_are_capped_per_client() {
+        use crate::test_utils::TempDataDir;
+        use actix_web::http::StatusCode;
+        use std::sync::Arc;
+        use storage::filesystem::FilesystemStorage;
+
+        let dir = TempDataDir::new();
+        let storage = Arc::new(FilesystemStorage::new(dir.0.clone()));
+        let state = web::Data::new(
+            AppState::new(storage).with_max_concurrent_downloads_per_client(Some(2)),
+        );
+        seed_batch(&state, true).await;
+        state
+            .storage
+            .store_file_and_update_tree("other", BATCH_ID, "a.txt", b"hello")
+            .await
+            .unwrap();
+        state
+            .storage
+            .set_batch_public("other", BATCH_ID, true)
+            .await
+            .unwrap();
+        let app = test::init_service(App::new().app_data(state.clone()).service(download)).await;
+
+        // A failed download gives its slot back
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/download?filename=missing.txt&batch_id={}&client_id={}",
+                BATCH_ID, CLIENT_ID
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // Two downloads in flight: the third is throttled
+        let limiter = state.download_limiter.as_ref().unwrap();
+        let in_flight = [
+            limiter.try_acquire(CLIENT_ID).unwrap(),
+            limiter.try_acquire(CLIENT_ID).unwrap(),
+        ];
+        let resp = test::call_service(&app, anonymous_request().to_request()).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // Other clients are unaffected
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/download?filename=a.txt&batch_id={}&client_id=other",
+                BATCH_ID
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        drop(in_flight);
+        let resp = test::call_service(&app, anonymous_request().to_request()).await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_anonymous_download_of_private_batch_rejected() {
+        let (state, _dir) = test_state();
+        seed_batch(&state, false).await;
+        let app = test::init_service(App::new().app_data(state.clone()).service(download)).await;
+
+        let resp = test::call_service(&app, anonymous_request().to_request()).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_empty_batch_is_not_a_server_error() {
+        use crate::handlers::batch::list_batch_files;
+        use common::utils::get_current_timestamp_ms;
+        use common::BatchFilesResponse;
+        use crypto::sign_message;
+
+        let (state, dir) = test_state();
+        let (signing_key, client_id) = register_client(&state).await;
+        state
+            .storage
+            .store_file_and_update_tree(&client_id, BATCH_ID, "a.txt", b"hello")
+            .await
+            .unwrap();
+        state
+            .storage
+            .set_batch_public(&client_id, BATCH_ID, true)
+            .await
+            .unwrap();
+
+        // Empty the batch behind storage's back, leaving the batch itself in place
+        let batch_dir = dir.0.join(&client_id).join(BATCH_ID);
+        let metadata = serde_json::json!({ "filenames": [], "public": true });
+        std::fs::write(batch_dir.join("metadata.json"), metadata.to_string()).unwrap();
+        std::fs::remove_file(batch_dir.join("a.txt")).unwrap();
+        std::fs::remove_file(batch_dir.join("merkle_tree.json")).unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(download)
+                .service(list_batch_files),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/download?filename=a.txt&batch_id={}&client_id={}",
+                BATCH_ID, client_id
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("has no files"));
+
+        let timestamp = get_current_timestamp_ms();
+        let message = [
+            b"list\0".as_slice(),
+            BATCH_ID.as_bytes(),
+            b"\0",
+            &timestamp.to_be_bytes(),
+        ]
+        .concat();
+        let signature = sign_message(&signing_key, &message);
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/batch/{}/files?signature={}&timestamp={}&client_id={}",
+                BATCH_ID,
+                hex::encode(signature.to_bytes()),
+                timestamp,
+                client_id
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let listing: BatchFilesResponse = test::read_body_json(resp).await;
+        assert!(listing.files.is_empty());
+        assert!(listing.root_hash.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_download_from_large_batch_reads_only_requested_file() {
+        const NUM_FILES: usize = 10_000;
+        let (state, dir) = test_state();
+        seed_batch(&state, true).await;
+
+        // Replace the batch with 10,000 entries of which only a.txt exists on disk:
+        // serving a proof must work from the stored leaf hashes alone
+        let mut filenames = vec!["a.txt".to_string()];
+        filenames.extend((1..NUM_FILES).map(|i| format!("missing{:05}.txt", i)));
+        let mut leaves = vec![crypto::hash_leaf(b"hello")];
+        leaves.extend((1..NUM_FILES).map(|i| crypto::hash_leaf(&i.to_be_bytes())));
+        let tree = MerkleTree::from_leaf_hashes(&leaves).unwrap();
+
+        let batch_dir = dir.0.join(CLIENT_ID).join(BATCH_ID);
+        let metadata = serde_json::json!({ "filenames": filenames, "public": true });
+        std::fs::write(batch_dir.join("metadata.json"), metadata.to_string()).unwrap();
+        std::fs::write(
+            batch_dir.join("merkle_tree.json"),
+            serde_json::to_string(&tree).unwrap(),
+        )
+        .unwrap();
+
+        let app = test::init_service(App::new().app_data(state.clone()).service(download)).await;
+        let resp = test::call_service(&app, anonymous_request().to_request()).await;
+        assert!(resp.status().is_success());
+
+        let body: DownloadResponse = test::read_body_json(resp).await;
+        // ceil(log2(10,000)) siblings
+        assert_eq!(body.merkle_proof.len(), 14);
+
+        let proof = MerkleProof {
+            leaf_index: 0,
+            leaf_hash: crypto::hash_leaf(b"hello"),
+            path: body
+                .merkle_proof
+                .iter()
+                .map(|node| {
+                    let mut hash = [0u8; 32];
+                    hex::decode_to_slice(&node.hash, &mut hash).unwrap();
+                    ProofNode {
+                        hash,
+                        is_left: node.is_left,
+                    }
+                })
+                .collect(),
+        };
+        assert_eq!(proof.compute_root().unwrap(), tree.root_hash());
+    }
+
+    /// Signed download of a.txt from `owner_id`'s batch by `requester_id`
+    fn shared_request(
+        signing_key: &ed25519_dalek::SigningKey,
+        owner_id: &str,
+        requester_id: &str,
+    ) -> test::TestRequest {
+        let nonce = crypto::generate_nonce();
+        signed_request(signing_key, owner_id, requester_id, &nonce)
+    }
+
+    /// Signed download of a.txt from `owner_id`'s batch by `requester_id`, using `nonce`
+    fn signed_request(
+        signing_key: &ed25519_dalek::SigningKey,
+        owner_id: &str,
+        requester_id: &str,
+        nonce: &[u8],
+    ) -> test::TestRequest {
+        let timestamp = common::utils::get_current_timestamp_ms();
+        let owner = (owner_id != requester_id).then_some(owner_id);
+        let message = download_message("a.txt", BATCH_ID, timestamp, nonce, owner);
+        let signature = crypto::sign_message(signing_key, &message);
+        test::TestRequest::get().uri(&format!(
+            "/download?filename=a.txt&batch_id={}&client_id={}&requester_id={}&signature={}&timestamp={}&nonce={}",
+            BATCH_ID,
+            owner_id,
+            requester_id,
+            hex::encode(signature.to_bytes()),
+            timestamp,
+            hex::encode(nonce)
+        ))
+    }
+
+    #[actix_web::test]
+    async fn test_replayed_download_nonce_is_rejected() {
+        let (state, _dir) = test_state();
+        let (signing_key, client_id) = register_client(&state).await;
+        state
+            .storage
+            .store_file_and_update_tree(&client_id, BATCH_ID, "a.txt", b"hello")
+            .await
+            .unwrap();
+        let app = test::init_service(App::new().app_data(state.clone()).service(download)).await;
+
+        // Distinct nonces are both accepted
+        let first = crypto::generate_nonce();
+        let req = signed_request(&signing_key, &client_id, &client_id, &first).to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let second = crypto::generate_nonce();
+        let req = signed_request(&signing_key, &client_id, &client_id, &second).to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        // Reusing a nonce is rejected even with a fresh timestamp and signature
+        let req = signed_request(&signing_key, &client_id, &client_id, &first).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+
+        // A signed download without a nonce is malformed
+        let timestamp = common::utils::get_current_timestamp_ms();
+        let message = download_message("a.txt", BATCH_ID, timestamp, &[], None);
+        let signature = crypto::sign_message(&signing_key, &message);
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/download?filename=a.txt&batch_id={}&client_id={}&signature={}&timestamp={}",
+                BATCH_ID,
+                client_id,
+                hex::encode(signature.to_bytes()),
+                timestamp
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_signed_downloads_are_rate_limited_per_client() {
+        use crate::test_utils::TempDataDir;
+        use actix_web::http::header::RETRY_AFTER;
+        use actix_web::http::StatusCode;
+        use std::sync::Arc;
+        use storage::filesystem::FilesystemStorage;
+
+        const BURST: usize = 3;
+        let dir = TempDataDir::new();
+        let storage = Arc::new(FilesystemStorage::new(dir.0.clone()));
+        // Slow enough that no token is earned back during the test
+        let state = web::Data::new(AppState::new(storage).with_rate_limit(Some(0.01), BURST));
+        let (signing_key, client_id) = register_client(&state).await;
+        state
+            .storage
+            .store_file_and_update_tree(&client_id, BATCH_ID, "a.txt", b"hello")
+            .await
+            .unwrap();
+        seed_batch(&state, true).await;
+        let app = test::init_service(App::new().app_data(state.clone()).service(download)).await;
+
+        let mut limited = Vec::new();
+        for _ in 0..=BURST {
+            let req = shared_request(&signing_key, &client_id, &client_id).to_request();
+            let resp = test::call_service(&app, req).await;
+            if resp.status() == StatusCode::TOO_MANY_REQUESTS {
+                limited.push(resp.headers().get(RETRY_AFTER).cloned());
+            } else {
+                assert!(resp.status().is_success());
+            }
+        }
+        assert_eq!(limited.len(), 1);
+        assert_eq!(limited[0].as_ref().unwrap(), "100");
+
+        // Other clients and anonymous reads of public batches are not affected
+        let (other_key, other_id) = register_client(&state).await;
+        state
+            .storage
+            .store_file_and_update_tree(&other_id, BATCH_ID, "a.txt", b"hello")
+            .await
+            .unwrap();
+        let req = shared_request(&other_key, &other_id, &other_id).to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        for _ in 0..=BURST {
+            let resp = test::call_service(&app, anonymous_request().to_request()).await;
+            assert!(resp.status().is_success());
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_download_of_shared_batch_follows_acl() {
+        let (state, _dir) = test_state();
+        let (_, owner_id) = register_client(&state).await;
+        let (reader_key, reader_id) = register_client(&state).await;
+        let (stranger_key, stranger_id) = register_client(&state).await;
+        state
+            .storage
+            .store_file_and_update_tree(&owner_id, BATCH_ID, "a.txt", b"hello")
+            .await
+            .unwrap();
+        state
+            .storage
+            .grant_batch_access(&owner_id, BATCH_ID, &reader_id)
+            .await
+            .unwrap();
+        let app = test::init_service(App::new().app_data(state.clone()).service(download)).await;
+
+        // Granted
+        let req = shared_request(&reader_key, &owner_id, &reader_id).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let body: DownloadResponse = test::read_body_json(resp).await;
+        assert_eq!(STANDARD.decode(body.file_content).unwrap(), b"hello");
+
+        // Not granted
+        let req = shared_request(&stranger_key, &owner_id, &stranger_id).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::FORBIDDEN);
+
+        // Claiming to be the reader without its key
+        let req = shared_request(&stranger_key, &owner_id, &reader_id).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+
+        // Revoked
+        state
+            .storage
+            .revoke_batch_access(&owner_id, BATCH_ID, &reader_id)
+            .await
+            .unwrap();
+        let req = shared_request(&reader_key, &owner_id, &reader_id).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::FORBIDDEN);
+    }
 }
@@ -1,4 +1,10 @@
-use tracing::error;
+use crate::state::AppState;
+use actix_web::error::InternalError;
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::HttpResponse;
+use common::utils::{get_current_timestamp_ms, SERVER_TIME_HEADER};
+use storage::{Storage, StorageError};
+use tracing::{error, warn};
 
 pub fn handle_error<E: std::fmt::Display>(msg: &str, e: E) -> actix_web::Error {
     error!("{}: {}", msg, e);
@@ -10,12 +16,89 @@ pub fn handle_auth_error<E: std::fmt::Display>(msg: &str, e: E) -> actix_web::Er
     actix_web::error::ErrorUnauthorized(format!("{}: {}", msg, e))
 }
 
+/// Reject a request whose timestamp failed validation, reporting the server's clock
+/// so the client can tell a skewed clock from a replayed request
+pub fn handle_timestamp_error<E: std::fmt::Display>(e: E) -> actix_web::Error {
+    let msg = format!("Timestamp validation failed: {}", e);
+    error!("{}", msg);
+    let response = HttpResponse::Unauthorized()
+        .insert_header((SERVER_TIME_HEADER, get_current_timestamp_ms().to_string()))
+        .body(msg.clone());
+    InternalError::from_response(msg, response).into()
+}
+
 pub fn handle_server_error<E: std::fmt::Display>(msg: &str, e: E) -> actix_web::Error {
     error!("{}: {}", msg, e);
     actix_web::error::ErrorInternalServerError(format!("{}: {}", msg, e))
 }
 
-pub fn handle_not_found<E: std::fmt::Display>(msg: &str, id: &str, e: E) -> actix_web::Error {
+/// Map a storage failure to its response by kind: a missing batch or file is 404, an
+/// existing or conflicting one 409, a full batch 403 and a key under another client's
+/// ID 400; only backend failures are server errors
+pub fn handle_storage_error(msg: &str, e: StorageError) -> actix_web::Error {
+    match e {
+        StorageError::NotFound { .. } => actix_web::error::ErrorNotFound(e.to_string()),
+        StorageError::AlreadyExists { .. } | StorageError::Conflict(_) => {
+            warn!("{}: {}", msg, e);
+            actix_web::error::ErrorConflict(e.to_string())
+        }
+        StorageError::BatchFull(_) => {
+            warn!("{}: {}", msg, e);
+            actix_web::error::ErrorForbidden(e.to_string())
+        }
+        StorageError::ClientIdMismatch(_) => handle_error(msg, e),
+        StorageError::Backend(e) => handle_server_error(msg, format!("{:#}", e)),
+    }
+}
+
+/// Refuse work that would need more memory than the server is configured to spend on it
+pub fn handle_insufficient_storage<E: std::fmt::Display>(msg: &str, e: E) -> actix_web::Error {
     error!("{}: {}", msg, e);
-    actix_web::error::ErrorNotFound(format!("Batch {} not found: {}", id, e))
+    actix_web::error::ErrorInsufficientStorage(format!("{}: {}", msg, e))
+}
+
+/// Answer 429 with `Retry-After` if `client_id` has used up its request rate
+/// Call only once the client is authenticated, so nobody can spend another client's tokens
+pub fn ensure_within_rate_limit(
+    state: &AppState,
+    route: &str,
+    client_id: &str,
+) -> Result<(), actix_web::Error> {
+    let Some(limiter) = &state.rate_limiter else {
+        return Ok(());
+    };
+    let Err(wait) = limiter.check(client_id) else {
+        return Ok(());
+    };
+    warn!(client_id = ?client_id, "{} - Rate limit exceeded", route);
+    // Retry-After is in whole seconds; rounding up means the retry finds a token
+    let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+    let msg = format!(
+        "Rate limit of {} requests per second exceeded; retry in {} s",
+        limiter.rps(),
+        retry_after
+    );
+    let response = HttpResponse::TooManyRequests()
+        .insert_header((RETRY_AFTER, retry_after.to_string()))
+        .body(msg.clone());
+    Err(InternalError::from_response(msg, response).into())
+}
+
+/// Answer 404 if the batch does not exist; failing to find out is a server error
+pub async fn ensure_batch_exists(
+    storage: &dyn Storage,
+    client_id: &str,
+    batch_id: &str,
+) -> Result<(), actix_web::Error> {
+    let exists = storage
+        .batch_exists(client_id, batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to check batch existence", e))?;
+    if !exists {
+        return Err(actix_web::error::ErrorNotFound(format!(
+            "Batch {} not found",
+            batch_id
+        )));
+    }
+    Ok(())
 }
@@ -0,0 +1,27 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+use common::ApiError;
+use tracing::info;
+
+/// Answer requests no route matched with a JSON error instead of an empty body
+/// A path some route serves with another method gets 405, anything else 404
+pub async fn unmatched(req: HttpRequest) -> HttpResponse {
+    if !req.resource_map().has_resource(req.path()) {
+        return not_found(&req);
+    }
+    let status = StatusCode::METHOD_NOT_ALLOWED;
+    info!(method = %req.method(), path = ?req.path(), "Unmatched request: {}", status);
+    HttpResponse::build(status).json(ApiError {
+        status: status.as_u16(),
+        error: format!("Method {} not allowed for {}", req.method(), req.path()),
+    })
+}
+
+/// The 404 answered for paths no route serves, also used by routes that are switched off
+pub fn not_found(req: &HttpRequest) -> HttpResponse {
+    info!(method = %req.method(), path = ?req.path(), "Unmatched request: 404 Not Found");
+    HttpResponse::NotFound().json(ApiError {
+        status: StatusCode::NOT_FOUND.as_u16(),
+        error: format!("No route for {}", req.path()),
+    })
+}
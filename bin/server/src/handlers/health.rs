@@ -1,9 +1,37 @@
-use actix_web::{get, HttpResponse, Result as ActixResult};
+use actix_web::http::Method;
+use actix_web::{route, HttpRequest, HttpResponse, Result as ActixResult};
 
 /// Health check endpoint
-#[get("/health")]
-pub async fn health() -> ActixResult<HttpResponse> {
+/// HEAD gets the same 200 without a body, for liveness probes that avoid the transfer
+#[route("/health", method = "GET", method = "HEAD")]
+pub async fn health(req: HttpRequest) -> ActixResult<HttpResponse> {
+    if req.method() == Method::HEAD {
+        return Ok(HttpResponse::Ok().finish());
+    }
     Ok(HttpResponse::Ok().json(common::HealthResponse {
         status: "ok".to_string(),
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn test_head_health_has_no_body() {
+        let app = test::init_service(App::new().service(health)).await;
+
+        let req = test::TestRequest::default()
+            .method(Method::HEAD)
+            .uri("/health")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+        assert!(test::read_body(resp).await.is_empty());
+
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let body: common::HealthResponse = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body.status, "ok");
+    }
+}
@@ -0,0 +1,11 @@
+use crate::state::AppState;
+use actix_web::{get, web, HttpResponse, Result as ActixResult};
+
+/// Metrics endpoint in Prometheus text exposition format
+#[get("/metrics")]
+pub async fn metrics(state: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    let body = state.metrics.render(&state).await;
+    Ok(HttpResponse::Ok()
+        .content_type(state.metrics.content_type())
+        .body(body))
+}
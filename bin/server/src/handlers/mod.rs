@@ -1,5 +1,13 @@
+pub mod access;
+pub mod batch;
+pub mod capabilities;
+pub mod cas;
+pub mod debug;
 pub mod download;
 pub mod error;
+pub mod fallback;
 pub mod health;
+pub mod metrics;
+pub mod proofs;
 pub mod upload;
 pub mod upload_form;
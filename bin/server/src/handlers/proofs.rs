@@ -0,0 +1,209 @@
+use crate::constants::MAX_PROOFS_PER_REQUEST;
+use crate::handlers::access::{authorize_read, ReadCredentials};
+use crate::handlers::error::{ensure_batch_exists, handle_server_error, handle_storage_error};
+use crate::proof::{load_proof_tree, proof_to_json};
+use crate::state::AppState;
+use actix_web::{post, web, HttpResponse, Result as ActixResult};
+use common::auth_message::proofs_message;
+use common::{file_utils, FileProofJson, ProofsRequest, ProofsResponse, PROOF_FORMAT_VERSION};
+use crypto::SignatureScheme;
+use merkle_tree::encode_hash;
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::info;
+
+/// Return the proofs of several files of a batch without their content
+/// Every proof comes from one load of the batch's tree, instead of one download (and
+/// tree load) per file. Requested files the batch does not hold are listed in
+/// `missing`; the others are still proven.
+#[post("/proofs")]
+pub async fn get_proofs(
+    body: web::Json<ProofsRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let req = body.into_inner();
+
+    info!(
+        batch_id = ?req.batch_id,
+        files = req.filenames.len(),
+        "POST /proofs - Request received"
+    );
+
+    if req.filenames.is_empty() || req.filenames.len() > MAX_PROOFS_PER_REQUEST {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "Between 1 and {} filenames must be requested",
+            MAX_PROOFS_PER_REQUEST
+        )));
+    }
+    for filename in &req.filenames {
+        file_utils::validate_filename(filename).map_err(|e| {
+            actix_web::error::ErrorBadRequest(format!("{}: {}", e.message(), filename))
+        })?;
+    }
+
+    authorize_read(
+        &state,
+        "POST /proofs",
+        ReadCredentials {
+            client_id: &req.client_id,
+            batch_id: &req.batch_id,
+            requester_id: req.requester_id.as_deref(),
+            requester_public_key: req.requester_public_key.as_deref(),
+            scheme: SignatureScheme::Ed25519,
+            signature: req.signature.as_deref(),
+            timestamp: req.timestamp,
+        },
+        |timestamp, owner| proofs_message(&req.batch_id, &req.filenames, timestamp, owner),
+    )
+    .await?;
+
+    ensure_batch_exists(state.storage.as_ref(), &req.client_id, &req.batch_id).await?;
+    let mut filenames = state
+        .storage
+        .load_batch_filenames(&req.client_id, &req.batch_id)
+        .await
+        .map_err(|e| handle_storage_error("Failed to load batch", e))?;
+    filenames.sort();
+
+    let requested: BTreeSet<&String> = req.filenames.iter().collect();
+    let (found, missing): (Vec<&String>, Vec<&String>) = requested
+        .into_iter()
+        .partition(|filename| filenames.binary_search(filename).is_ok());
+
+    let mut proofs = BTreeMap::new();
+    // An empty batch has no tree, and every requested file is missing from it
+    if !found.is_empty() {
+        let tree = load_proof_tree(&state, &req.client_id, &req.batch_id, &filenames).await?;
+        for filename in found {
+            let leaf_index = filenames.binary_search(filename).unwrap_or_default();
+            let proof = tree
+                .generate_proof(leaf_index)
+                .map_err(|e| handle_server_error("Failed to generate proof", e))?;
+            proofs.insert(
+                filename.clone(),
+                FileProofJson {
+                    leaf_index,
+                    file_hash: encode_hash(&proof.leaf_hash, state.hash_truncation_bytes),
+                    merkle_proof: proof_to_json(&proof, state.hash_truncation_bytes),
+                },
+            );
+        }
+    }
+
+    info!(
+        batch_id = ?req.batch_id,
+        "POST /proofs - {} proofs, {} missing",
+        proofs.len(),
+        missing.len()
+    );
+
+    Ok(HttpResponse::Ok().json(ProofsResponse {
+        batch_id: req.batch_id,
+        proofs,
+        missing: missing.into_iter().cloned().collect(),
+        hash_algorithm: merkle_tree::HASH_ALGORITHM.to_string(),
+        proof_version: PROOF_FORMAT_VERSION,
+        hash_truncation_bytes: state.hash_truncation_bytes,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{register_client, test_state};
+    use actix_web::{test, App};
+    use common::utils::get_current_timestamp_ms;
+    use crypto::{hash_leaf, sign_message};
+    use merkle_tree::{MerkleProof, MerkleTree, ProofNode};
+
+    #[actix_web::test]
+    async fn test_multiple_proofs_verify_against_root() {
+        let (state, _dir) = test_state();
+        let (signing_key, client_id) = register_client(&state).await;
+        let contents: [(&str, &[u8]); 5] = [
+            ("a.txt", b"a"),
+            ("b.txt", b"b"),
+            ("c.txt", b"c"),
+            ("d.txt", b"d"),
+            ("e.txt", b"e"),
+        ];
+        for (name, content) in contents {
+            state
+                .storage
+                .store_file_and_update_tree(&client_id, "batch", name, content)
+                .await
+                .unwrap();
+        }
+        let root = MerkleTree::from_leaf_hashes(
+            &contents
+                .iter()
+                .map(|(_, content)| hash_leaf(content))
+                .collect::<Vec<_>>(),
+        )
+        .unwrap()
+        .root_hash();
+        let app = test::init_service(App::new().app_data(state.clone()).service(get_proofs)).await;
+
+        let filenames: Vec<String> = ["e.txt", "a.txt", "nope.txt", "c.txt", "a.txt"]
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        let timestamp = get_current_timestamp_ms();
+        let signature = sign_message(
+            &signing_key,
+            &proofs_message("batch", &filenames, timestamp, None),
+        );
+        let request = ProofsRequest {
+            batch_id: "batch".to_string(),
+            filenames,
+            signature: Some(hex::encode(signature.to_bytes())),
+            timestamp: Some(timestamp),
+            client_id: client_id.clone(),
+            requester_id: None,
+            requester_public_key: None,
+        };
+        let req = test::TestRequest::post()
+            .uri("/proofs")
+            .set_json(&request)
+            .to_request();
+        let resp: ProofsResponse = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(resp.missing, vec!["nope.txt"]);
+        assert_eq!(
+            resp.proofs.keys().collect::<Vec<_>>(),
+            vec!["a.txt", "c.txt", "e.txt"]
+        );
+        for (filename, proof) in &resp.proofs {
+            let (index, (_, content)) = contents
+                .iter()
+                .enumerate()
+                .find(|(_, (name, _))| name == filename)
+                .unwrap();
+            assert_eq!(proof.leaf_index, index);
+            assert_eq!(proof.file_hash, hex::encode(hash_leaf(content)));
+            let path = proof
+                .merkle_proof
+                .iter()
+                .map(|node| ProofNode {
+                    hash: merkle_tree::decode_hash(&node.hash, None).unwrap(),
+                    is_left: node.is_left,
+                })
+                .collect();
+            let proof = MerkleProof {
+                leaf_index: proof.leaf_index,
+                leaf_hash: hash_leaf(content),
+                path,
+            };
+            assert_eq!(proof.compute_root().unwrap(), root);
+        }
+
+        // A signature over a different file list is rejected
+        let mut tampered = request;
+        tampered.filenames.push("b.txt".to_string());
+        let req = test::TestRequest::post()
+            .uri("/proofs")
+            .set_json(&tampered)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+    }
+}
@@ -1,12 +1,60 @@
 use crate::auth::AuthVerifier;
-use crate::handlers::error::{handle_auth_error, handle_error, handle_server_error};
-use crate::handlers::upload_form::UploadForm;
+use crate::constants::MAX_FILES_PER_UPLOAD_BATCH;
+use crate::handlers::error::{
+    ensure_within_rate_limit, handle_auth_error, handle_error, handle_storage_error,
+    handle_timestamp_error,
+};
+use crate::handlers::upload_form::{validate_upload_fields, UploadBatchForm, UploadForm};
+use crate::ingest::{IngestStatus, QueueFull};
 use crate::state::AppState;
+use actix_multipart::form::tempfile::TempFile;
 use actix_multipart::form::MultipartForm;
-use actix_web::{post, web, HttpResponse, Result as ActixResult};
-use common::file_utils;
-use crypto::hash_leaf;
-use tracing::info;
+use actix_web::{get, post, web, HttpResponse, Result as ActixResult};
+use anyhow::Context;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use common::annotations::{validate_annotations, Annotations};
+use common::auth_message::{upload_message, UploadMessage};
+use common::{
+    file_utils, UploadAcceptedResponse, UploadBatchManifest, UploadRequest, UploadStatusResponse,
+    DEFAULT_SIGNATURE_SCHEME,
+};
+use crypto::{hash_leaf, hash_leaf_reader, SignatureScheme};
+use std::collections::HashSet;
+use storage::Storage;
+use tracing::{info, warn};
+
+/// Upload fields common to the multipart and JSON upload paths
+struct UploadFields {
+    filename: String,
+    batch_id: String,
+    file_hash: String,
+    signature: String,
+    timestamp: u64,
+    nonce: String,
+    public_key: String,
+    scheme: String,
+    public: bool,
+    annotations: Option<Annotations>,
+}
+
+/// Where the uploaded content is
+pub enum UploadContent {
+    /// Multipart temp file, hashed and stored by streaming; removed once this is dropped
+    TempFile(TempFile),
+    /// Decoded JSON content
+    Bytes(Vec<u8>),
+}
+
+/// An authenticated upload whose content matches its signed hash, ready to be stored
+pub struct PendingUpload {
+    pub client_id: String,
+    pub batch_id: String,
+    pub filename: String,
+    pub content: UploadContent,
+    pub public: bool,
+    pub annotations: Option<Annotations>,
+}
 
 /// Handle file upload (multipart/form-data)
 #[post("/upload")]
@@ -14,36 +62,327 @@ pub async fn upload(
     form: MultipartForm<UploadForm>,
     state: web::Data<AppState>,
 ) -> ActixResult<HttpResponse> {
-    // Extract file path before moving form
-    let file_path = form.file.file.path().to_path_buf();
-
-    // Validate form fields (length, format checks)
-    form.validate_fields()
-        .map_err(actix_web::error::ErrorBadRequest)?;
+    let result = receive_upload(form, state.clone()).await;
+    state.metrics.record_upload(&result);
+    result
+}
 
-    // Extract all fields from multipart form
+async fn receive_upload(
+    form: MultipartForm<UploadForm>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    // Extract all fields from multipart form; the temp file is removed when `file` drops
     let UploadForm {
-        file: _file,
+        file,
         filename,
         batch_id,
         file_hash,
         signature,
         timestamp,
+        nonce,
         public_key,
+        scheme,
+        public,
+        annotations,
     } = form.into_inner();
 
-    let filename = filename.into_inner();
-    let batch_id = batch_id.into_inner();
-    let file_hash = file_hash.into_inner();
-    let signature_hex = signature.into_inner();
-    let timestamp = timestamp.into_inner();
-    let public_key_hex = public_key.into_inner();
+    let annotations = annotations
+        .map(|a| serde_json::from_str(&a))
+        .transpose()
+        .map_err(|_| {
+            actix_web::error::ErrorBadRequest("Annotations must be a JSON object of strings")
+        })?;
+
+    let fields = UploadFields {
+        filename: filename.into_inner(),
+        batch_id: batch_id.into_inner(),
+        file_hash: file_hash.into_inner(),
+        signature: signature.into_inner(),
+        timestamp: timestamp.into_inner(),
+        nonce: nonce.into_inner(),
+        public_key: public_key.into_inner(),
+        scheme: scheme
+            .map(|s| s.into_inner())
+            .unwrap_or_else(|| DEFAULT_SIGNATURE_SCHEME.to_string()),
+        public: public.map(|p| p.into_inner()).unwrap_or(false),
+        annotations,
+    };
+
+    check_upload_size(file.size, state.max_upload_size_bytes)?;
+    let content = UploadContent::TempFile(file);
+    store_upload(&state, "POST /upload", fields, content).await
+}
+
+/// Handle file upload (JSON body with base64-encoded content)
+/// For clients that cannot easily send multipart/form-data
+#[post("/upload/json")]
+pub async fn upload_json(
+    body: web::Json<UploadRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let result = receive_upload_json(body, state.clone()).await;
+    state.metrics.record_upload(&result);
+    result
+}
+
+async fn receive_upload_json(
+    body: web::Json<UploadRequest>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let req = body.into_inner();
+
+    let content = STANDARD
+        .decode(&req.file_content)
+        .map_err(|_| actix_web::error::ErrorBadRequest("File content must be valid base64"))?;
+    check_upload_size(content.len(), state.max_upload_size_bytes)?;
+
+    let fields = UploadFields {
+        filename: req.filename,
+        batch_id: req.batch_id,
+        file_hash: req.file_hash,
+        signature: req.signature,
+        timestamp: req.timestamp,
+        nonce: req.nonce,
+        public_key: req.public_key,
+        scheme: req.scheme,
+        public: req.public,
+        annotations: req.annotations,
+    };
+    store_upload(
+        &state,
+        "POST /upload/json",
+        fields,
+        UploadContent::Bytes(content),
+    )
+    .await
+}
+
+/// Handle a bulk upload: several files of one batch in a single multipart request
+/// Each file is signed exactly like a single upload of it, and all signatures are checked
+/// in one Ed25519 batch verification before anything is stored. The files are then stored
+/// one at a time, so a storage failure leaves the files before it stored.
+/// Counted as one upload in the metrics, whatever the number of files.
+#[post("/upload-batch")]
+pub async fn upload_batch(
+    form: MultipartForm<UploadBatchForm>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let result = receive_upload_batch(form, state.clone()).await;
+    state.metrics.record_upload(&result);
+    result
+}
+
+async fn receive_upload_batch(
+    MultipartForm(form): MultipartForm<UploadBatchForm>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let route = "POST /upload-batch";
+    let manifest: UploadBatchManifest = serde_json::from_str(&form.manifest)
+        .map_err(|e| handle_error("Invalid upload manifest", e))?;
+    let UploadBatchManifest {
+        batch_id,
+        public_key: public_key_hex,
+        files: entries,
+    } = manifest;
+
+    info!(
+        batch_id = ?batch_id,
+        num_files = entries.len(),
+        "{} - Request received",
+        route
+    );
+
+    if entries.is_empty() || entries.len() > MAX_FILES_PER_UPLOAD_BATCH {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "Between 1 and {} files must be uploaded",
+            MAX_FILES_PER_UPLOAD_BATCH
+        )));
+    }
+    if form.files.len() != entries.len() {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "Manifest lists {} files but {} were uploaded",
+            entries.len(),
+            form.files.len()
+        )));
+    }
+
+    // Reject malformed or weak keys before hashing any content or verifying anything
+    AuthVerifier::validate_public_key(&public_key_hex)
+        .map_err(|e| handle_auth_error("Invalid public key", e))?;
+    let public_key = hex::decode(public_key_hex.trim())
+        .map_err(|e| handle_error("Failed to decode public key", e))?;
+
+    let mut filenames = HashSet::with_capacity(entries.len());
+    let mut requests = Vec::with_capacity(entries.len());
+    let mut nonces = Vec::with_capacity(entries.len());
+    for (entry, part) in entries.iter().zip(&form.files) {
+        validate_upload_fields(
+            &entry.filename,
+            &batch_id,
+            &entry.file_hash,
+            &entry.signature,
+            &public_key_hex,
+            SignatureScheme::Ed25519,
+        )
+        .map_err(actix_web::error::ErrorBadRequest)?;
+        file_utils::validate_filename(&entry.filename)
+            .map_err(|e| actix_web::error::ErrorBadRequest(e.message()))?;
+        if !filenames.insert(entry.filename.as_str()) {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "File {} is uploaded twice",
+                entry.filename
+            )));
+        }
+        if part.file_name.as_deref() != Some(entry.filename.as_str()) {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "Uploaded file {:?} does not match manifest entry {}",
+                part.file_name.as_deref().unwrap_or_default(),
+                entry.filename
+            )));
+        }
+        check_upload_size(part.size, state.max_upload_size_bytes)?;
+
+        AuthVerifier::validate_timestamp_default(entry.timestamp)
+            .map_err(handle_timestamp_error)?;
+        let nonce = AuthVerifier::parse_nonce(&entry.nonce)
+            .map_err(|e| handle_error("Invalid nonce", e))?;
+
+        // Hash the temp file in chunks instead of reading it into memory
+        let hash_path = part.file.path().to_path_buf();
+        let computed_hash = web::block(move || {
+            let file = std::fs::File::open(&hash_path)?;
+            hash_leaf_reader(file)
+        })
+        .await
+        .map_err(|e| handle_error("Failed to hash uploaded file", e))?
+        .map_err(|e| handle_error("Failed to read uploaded file", e))?;
+        let computed_hash_hex = hex::encode(computed_hash);
+        if computed_hash_hex != entry.file_hash {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "File hash mismatch for {}: expected {}, got {}",
+                entry.filename, entry.file_hash, computed_hash_hex
+            )));
+        }
+
+        let message = upload_message(&UploadMessage {
+            filename: &entry.filename,
+            batch_id: &batch_id,
+            file_hash: &entry.file_hash,
+            timestamp: entry.timestamp,
+            nonce: &nonce,
+            public_key: &public_key,
+            public: false,
+            annotations: None,
+        });
+        let signature = AuthVerifier::parse_signature(&entry.signature)
+            .map_err(|e| handle_error("Failed to parse signature", e))?;
+        requests.push((message, signature));
+        nonces.push(nonce);
+    }
+
+    let (client_id, is_new_client) =
+        AuthVerifier::verify_request_signatures(&state, &requests, &public_key_hex)
+            .await
+            .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+    ensure_within_rate_limit(&state, route, &client_id)?;
+    for nonce in nonces {
+        AuthVerifier::check_nonce(&state, nonce)
+            .map_err(|e| handle_auth_error("Replay rejected", e))?;
+    }
+
+    if is_new_client {
+        info!("{} - Registered new client: {}", route, client_id);
+    }
+
+    info!(
+        "{} - {} signatures verified for client: {}",
+        route,
+        requests.len(),
+        client_id
+    );
+
+    for (entry, file) in entries.into_iter().zip(form.files) {
+        let pending = PendingUpload {
+            client_id: client_id.clone(),
+            batch_id: batch_id.clone(),
+            filename: entry.filename.clone(),
+            content: UploadContent::TempFile(file),
+            public: false,
+            annotations: None,
+        };
+        // Waited for even when uploads are asynchronous, so the answer covers every file
+        let stored = match &state.ingest {
+            None => persist_upload(state.storage.as_ref(), &pending).await,
+            Some(ingest) => ingest.submit_and_wait(pending).await,
+        };
+        stored.map_err(|e| store_error(e, &entry.filename, &batch_id))?;
+    }
+
+    info!(
+        client_id = ?client_id,
+        batch_id = ?batch_id,
+        "{} - Files uploaded and Merkle tree rebuilt",
+        route
+    );
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Reject an uploaded file larger than the server's `--max-upload-size`
+fn check_upload_size(size: usize, max_upload_size_bytes: usize) -> ActixResult<()> {
+    if size > max_upload_size_bytes {
+        return Err(actix_web::error::ErrorPayloadTooLarge(format!(
+            "File exceeds maximum upload size of {} bytes",
+            max_upload_size_bytes
+        )));
+    }
+    Ok(())
+}
+
+/// Validate, authenticate and store an upload
+/// Shared by both upload handlers so they enforce exactly the same checks
+async fn store_upload(
+    state: &web::Data<AppState>,
+    route: &str,
+    fields: UploadFields,
+    content: UploadContent,
+) -> ActixResult<HttpResponse> {
+    let UploadFields {
+        filename,
+        batch_id,
+        file_hash,
+        signature: signature_hex,
+        timestamp,
+        nonce: nonce_hex,
+        public_key: public_key_hex,
+        scheme,
+        public,
+        annotations,
+    } = fields;
+
+    let scheme = AuthVerifier::parse_scheme(&scheme)
+        .map_err(|e| handle_error("Unsupported signature scheme", e))?;
+
+    // Validate fields (length, format checks)
+    validate_upload_fields(
+        &filename,
+        &batch_id,
+        &file_hash,
+        &signature_hex,
+        &public_key_hex,
+        scheme,
+    )
+    .map_err(actix_web::error::ErrorBadRequest)?;
+    if let Some(annotations) = &annotations {
+        validate_annotations(annotations).map_err(actix_web::error::ErrorBadRequest)?;
+    }
 
     // Use structured logging with Debug formatter (?), which automatically escapes control characters
     info!(
         filename = ?filename,
         batch_id = ?batch_id,
-        "POST /upload - Request received"
+        "{} - Request received",
+        route
     );
 
     // Validate filename to prevent path traversal attacks
@@ -51,16 +390,30 @@ pub async fn upload(
         .map_err(|e| actix_web::error::ErrorBadRequest(e.message()))?;
 
     // Validate timestamp to prevent replay attacks
-    AuthVerifier::validate_timestamp_default(timestamp)
-        .map_err(|e| handle_auth_error("Timestamp validation failed", e))?;
+    AuthVerifier::validate_timestamp_default(timestamp).map_err(handle_timestamp_error)?;
+    let nonce =
+        AuthVerifier::parse_nonce(&nonce_hex).map_err(|e| handle_error("Invalid nonce", e))?;
+
+    // Reject malformed or weak keys before hashing the content or verifying anything
+    AuthVerifier::validate_scheme_public_key(scheme, &public_key_hex)
+        .map_err(|e| handle_auth_error("Invalid public key", e))?;
 
-    // Read file content from temp file
-    // Note: File size is already limited by #[multipart(limit = "10MB")] in UploadForm
-    let file_content =
-        std::fs::read(&file_path).map_err(|e| handle_error("Failed to read uploaded file", e))?;
+    let computed_hash = match &content {
+        // Hash the temp file in chunks instead of reading it into memory
+        UploadContent::TempFile(file) => {
+            let hash_path = file.file.path().to_path_buf();
+            web::block(move || {
+                let file = std::fs::File::open(&hash_path)?;
+                hash_leaf_reader(file)
+            })
+            .await
+            .map_err(|e| handle_error("Failed to hash uploaded file", e))?
+            .map_err(|e| handle_error("Failed to read uploaded file", e))?
+        }
+        UploadContent::Bytes(bytes) => hash_leaf(bytes),
+    };
 
-    // Verify file hash matches content
-    let computed_hash = hash_leaf(&file_content);
+    // Verify file hash matches content; hashes are public, so a plain comparison is fine
     let computed_hash_hex = hex::encode(computed_hash);
     if computed_hash_hex != file_hash {
         return Err(actix_web::error::ErrorBadRequest(format!(
@@ -69,62 +422,733 @@ pub async fn upload(
         )));
     }
 
-    // Build message using raw file bytes (same format as before)
-    let message = build_message(&filename, &batch_id, &file_hash, &file_content, timestamp);
+    // The signature covers the verified hash, which binds it to the content, and the
+    // public key, which binds it to the client ID the upload is stored under
+    let public_key = hex::decode(public_key_hex.trim())
+        .map_err(|e| handle_error("Failed to decode public key", e))?;
+    let message = upload_message(&UploadMessage {
+        filename: &filename,
+        batch_id: &batch_id,
+        file_hash: &file_hash,
+        timestamp,
+        nonce: &nonce,
+        public_key: &public_key,
+        public,
+        annotations: annotations.as_ref(),
+    });
     let signature = AuthVerifier::parse_signature(&signature_hex)
         .map_err(|e| handle_error("Failed to parse signature", e))?;
 
-    // Validate public key format before verification
-    AuthVerifier::validate_public_key(&public_key_hex)
-        .map_err(|e| handle_auth_error("Invalid public key", e))?;
-
-    let (client_id, is_new_client) =
-        AuthVerifier::verify_request_signature(&state, &message, &signature, &public_key_hex)
-            .await
-            .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+    let (client_id, is_new_client) = AuthVerifier::verify_request_signature(
+        state,
+        scheme,
+        &message,
+        &signature,
+        &public_key_hex,
+    )
+    .await
+    .map_err(|e| handle_auth_error("Signature verification failed", e))?;
+    ensure_within_rate_limit(state, route, &client_id)?;
+    AuthVerifier::check_nonce(state, nonce).map_err(|e| handle_auth_error("Replay rejected", e))?;
 
     if is_new_client {
-        info!("POST /upload - Registered new client: {}", client_id);
+        info!("{} - Registered new client: {}", route, client_id);
     }
 
-    info!(
-        "POST /upload - Signature verified for client: {}",
-        client_id
-    );
+    info!("{} - Signature verified for client: {}", route, client_id);
 
-    // Atomically store file and update Merkle tree
-    // This ensures that concurrent uploads to the same batch_id are handled correctly
-    // by using transactions and locking to prevent race conditions
-    state
-        .storage
-        .store_file_and_update_tree(&client_id, &batch_id, &filename, &file_content)
-        .await
-        .map_err(|e| handle_server_error("Failed to store file and update Merkle tree", e))?;
+    let pending = PendingUpload {
+        client_id: client_id.clone(),
+        batch_id: batch_id.clone(),
+        filename: filename.clone(),
+        content,
+        public,
+        annotations,
+    };
+    let stored = match &state.ingest {
+        None => persist_upload(state.storage.as_ref(), &pending).await,
+        // Stored later by the pipeline; the client polls the status URL for the outcome
+        Some(ingest) if ingest.is_async() => {
+            let job_id = ingest.submit(pending).map_err(|e| handle_queue_full(&e))?;
+            info!(
+                filename = ?filename,
+                client_id = ?client_id,
+                batch_id = ?batch_id,
+                job_id = ?job_id,
+                "{} - Upload queued",
+                route
+            );
+            let status_url = format!("/upload/status/{}", job_id);
+            return Ok(HttpResponse::Accepted()
+                .insert_header((actix_web::http::header::LOCATION, status_url.clone()))
+                .json(UploadAcceptedResponse { job_id, status_url }));
+        }
+        Some(ingest) => ingest.submit_and_wait(pending).await,
+    };
+    stored.map_err(|e| store_error(e, &filename, &batch_id))?;
 
     info!(
         filename = ?filename,
         client_id = ?client_id,
         batch_id = ?batch_id,
-        "POST /upload - File uploaded and Merkle tree rebuilt"
+        "{} - File uploaded and Merkle tree rebuilt",
+        route
     );
 
     Ok(HttpResponse::Ok().finish())
 }
 
-/// Build message for upload signature verification
-/// Signs raw file bytes
-fn build_message(
-    filename: &str,
-    batch_id: &str,
-    file_hash: &str,
-    file_content: &[u8],
-    timestamp: u64,
-) -> Vec<u8> {
-    let mut message = Vec::new();
-    message.extend_from_slice(filename.as_bytes());
-    message.extend_from_slice(batch_id.as_bytes());
-    message.extend_from_slice(file_hash.as_bytes());
-    message.extend_from_slice(file_content);
-    message.extend_from_slice(&timestamp.to_be_bytes());
-    message
+/// Map a failure to store an upload to its response
+fn store_error(e: anyhow::Error, filename: &str, batch_id: &str) -> actix_web::Error {
+    if let Some(full) = e.downcast_ref::<QueueFull>() {
+        return handle_queue_full(full);
+    }
+    handle_storage_error(
+        &format!("Failed to store {} in batch {}", filename, batch_id),
+        e.into(),
+    )
+}
+
+/// Store an authenticated upload: the file and rebuilt Merkle tree, then the batch flags
+/// Run by the upload handlers, or by the ingestion pipeline's workers when it is enabled
+pub async fn persist_upload(storage: &dyn Storage, pending: &PendingUpload) -> anyhow::Result<()> {
+    let PendingUpload {
+        client_id,
+        batch_id,
+        filename,
+        content,
+        public,
+        annotations,
+    } = pending;
+
+    // Atomically store file and update Merkle tree
+    // This ensures that concurrent uploads to the same batch_id are handled correctly
+    // by using transactions and locking to prevent race conditions
+    match content {
+        // Stream from the temp file rather than buffering it
+        UploadContent::TempFile(file) => {
+            storage
+                .store_file_from_path_and_update_tree(
+                    client_id,
+                    batch_id,
+                    filename,
+                    file.file.path(),
+                )
+                .await
+        }
+        UploadContent::Bytes(bytes) => {
+            storage
+                .store_file_and_update_tree(client_id, batch_id, filename, bytes)
+                .await
+        }
+    }
+    .context("Failed to store file and update Merkle tree")?;
+
+    // Uploads never make a public batch private again; the flag only opts in
+    if *public {
+        storage
+            .set_batch_public(client_id, batch_id, true)
+            .await
+            .context("Failed to mark batch as public")?;
+    }
+
+    // Annotations are stored beside the batch, outside the Merkle tree
+    if let Some(annotations) = annotations {
+        storage
+            .set_batch_annotations(client_id, batch_id, annotations)
+            .await
+            .context("Failed to store batch annotations")?;
+    }
+    Ok(())
+}
+
+/// Report whether an upload answered with 202 Accepted has been stored
+/// Unsigned: job IDs are random and only ever returned to the uploader
+#[get("/upload/status/{job_id}")]
+pub async fn upload_status(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let job_id = path.into_inner();
+    let status = state
+        .ingest
+        .as_ref()
+        .and_then(|ingest| ingest.status(&job_id))
+        .ok_or_else(|| {
+            actix_web::error::ErrorNotFound(format!("Upload job {} not found", job_id))
+        })?;
+    Ok(HttpResponse::Ok().json(UploadStatusResponse {
+        job_id,
+        status: status.as_str().to_string(),
+        error: match status {
+            IngestStatus::Failed(error) => Some(error),
+            _ => None,
+        },
+    }))
+}
+
+/// Reject an upload the ingestion pipeline has no room for; the client should retry
+fn handle_queue_full(e: &QueueFull) -> actix_web::Error {
+    warn!("Rejected upload: {}", e);
+    actix_web::error::ErrorServiceUnavailable(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_state;
+    use actix_web::{test, App};
+    use common::utils::get_current_timestamp_ms;
+    use common::UploadBatchEntry;
+    use crypto::{compute_client_id, generate_keypair, sign_message};
+    use ed25519_dalek::SigningKey;
+
+    /// The message `req` is signed over, as signed with `public_key` and `annotations`
+    fn signed_message(
+        req: &UploadRequest,
+        public_key: &[u8],
+        annotations: Option<&Annotations>,
+    ) -> Vec<u8> {
+        upload_message(&UploadMessage {
+            filename: &req.filename,
+            batch_id: &req.batch_id,
+            file_hash: &req.file_hash,
+            timestamp: req.timestamp,
+            nonce: &AuthVerifier::parse_nonce(&req.nonce).unwrap(),
+            public_key,
+            public: req.public,
+            annotations,
+        })
+    }
+
+    fn json_upload(signing_key: &SigningKey, filename: &str, content: &[u8]) -> UploadRequest {
+        let mut req = UploadRequest {
+            filename: filename.to_string(),
+            batch_id: "batch".to_string(),
+            file_content: STANDARD.encode(content),
+            file_hash: hex::encode(hash_leaf(content)),
+            signature: String::new(),
+            timestamp: get_current_timestamp_ms(),
+            nonce: hex::encode(crypto::generate_nonce()),
+            public_key: hex::encode(signing_key.verifying_key().as_bytes()),
+            scheme: DEFAULT_SIGNATURE_SCHEME.to_string(),
+            public: false,
+            annotations: None,
+        };
+        let message = signed_message(&req, signing_key.verifying_key().as_bytes(), None);
+        req.signature = hex::encode(sign_message(signing_key, &message).to_bytes());
+        req
+    }
+
+    #[actix_web::test]
+    async fn test_json_upload_stores_file_and_tree() {
+        let (state, _dir) = test_state();
+        let (signing_key, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+        let app = test::init_service(App::new().app_data(state.clone()).service(upload_json)).await;
+
+        for (name, content) in [("a.txt", b"a"), ("b.txt", b"b")] {
+            let req = test::TestRequest::post()
+                .uri("/upload/json")
+                .set_json(json_upload(&signing_key, name, content))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success());
+        }
+
+        // Auto-registered like a multipart upload
+        assert!(state
+            .storage
+            .load_public_key(&client_id)
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            state
+                .storage
+                .read_file(&client_id, "batch", "b.txt")
+                .await
+                .unwrap(),
+            b"b"
+        );
+        let tree = state
+            .storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        let expected =
+            merkle_tree::MerkleTree::from_leaf_hashes(&[hash_leaf(b"a"), hash_leaf(b"b")]).unwrap();
+        assert_eq!(tree.root_hash(), expected.root_hash());
+    }
+
+    const BOUNDARY: &str = "vs-upload-batch-boundary";
+
+    /// Multipart bulk upload of `files`, each signed like a single upload
+    fn upload_batch_request(signing_key: &SigningKey, files: &[(&str, &[u8])]) -> Vec<u8> {
+        let public_key = signing_key.verifying_key().to_bytes();
+        let entries: Vec<UploadBatchEntry> = files
+            .iter()
+            .map(|(filename, content)| {
+                let file_hash = hex::encode(hash_leaf(content));
+                let timestamp = get_current_timestamp_ms();
+                let nonce = crypto::generate_nonce();
+                let message = upload_message(&UploadMessage {
+                    filename,
+                    batch_id: "batch",
+                    file_hash: &file_hash,
+                    timestamp,
+                    nonce: &nonce,
+                    public_key: &public_key,
+                    public: false,
+                    annotations: None,
+                });
+                UploadBatchEntry {
+                    filename: filename.to_string(),
+                    signature: hex::encode(sign_message(signing_key, &message).to_bytes()),
+                    file_hash,
+                    timestamp,
+                    nonce: hex::encode(nonce),
+                }
+            })
+            .collect();
+        let manifest = UploadBatchManifest {
+            batch_id: "batch".to_string(),
+            public_key: hex::encode(public_key),
+            files: entries,
+        };
+
+        let mut body = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"manifest\"\r\n\r\n{}\r\n",
+            BOUNDARY,
+            serde_json::to_string(&manifest).unwrap()
+        )
+        .into_bytes();
+        for (filename, content) in files {
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"files\"; filename=\"{}\"\r\n\
+                     Content-Type: application/octet-stream\r\n\r\n",
+                    BOUNDARY, filename
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    fn post_upload_batch(body: Vec<u8>) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/upload-batch")
+            .insert_header((
+                "content-type",
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            ))
+            .set_payload(body)
+    }
+
+    #[actix_web::test]
+    async fn test_upload_batch_stores_every_file_once() {
+        let (state, _dir) = test_state();
+        let (signing_key, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+        let app =
+            test::init_service(App::new().app_data(state.clone()).service(upload_batch)).await;
+
+        let files: [(&str, &[u8]); 3] = [("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")];
+        let body = upload_batch_request(&signing_key, &files);
+        let resp = test::call_service(&app, post_upload_batch(body.clone()).to_request()).await;
+        assert!(resp.status().is_success());
+
+        assert_eq!(
+            state
+                .storage
+                .load_batch_filenames(&client_id, "batch")
+                .await
+                .unwrap(),
+            vec!["a.txt", "b.txt", "c.txt"]
+        );
+        let tree = state
+            .storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        let leaves: Vec<[u8; 32]> = files.iter().map(|(_, c)| hash_leaf(c)).collect();
+        let expected = merkle_tree::MerkleTree::from_leaf_hashes(&leaves).unwrap();
+        assert_eq!(tree.root_hash(), expected.root_hash());
+
+        // Every nonce is spent, so the same request cannot be replayed
+        let resp = test::call_service(&app, post_upload_batch(body).to_request()).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_upload_batch_with_one_bad_signature_stores_nothing() {
+        let (state, _dir) = test_state();
+        let (signing_key, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+        let app =
+            test::init_service(App::new().app_data(state.clone()).service(upload_batch)).await;
+
+        // The last file's content is swapped after signing, and its hash with it
+        let body = upload_batch_request(&signing_key, &[("a.txt", b"a"), ("b.txt", b"b")]);
+        let body = String::from_utf8(body).unwrap();
+        let b_hash = hex::encode(hash_leaf(b"b"));
+        let x_hash = hex::encode(hash_leaf(b"x"));
+        let body = body.replace(&b_hash, &x_hash).replace(
+            "Content-Type: application/octet-stream\r\n\r\nb\r\n",
+            "Content-Type: application/octet-stream\r\n\r\nx\r\n",
+        );
+
+        let resp =
+            test::call_service(&app, post_upload_batch(body.into_bytes()).to_request()).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+        assert!(!state.storage.client_exists(&client_id).await.unwrap());
+        assert!(!state
+            .storage
+            .batch_exists(&client_id, "batch")
+            .await
+            .unwrap());
+    }
+
+    #[actix_web::test]
+    async fn test_json_upload_with_unknown_scheme_rejected() {
+        let (state, _dir) = test_state();
+        let (signing_key, verifying_key) = generate_keypair();
+        let app = test::init_service(App::new().app_data(state.clone()).service(upload_json)).await;
+
+        let mut json = json_upload(&signing_key, "a.txt", b"a");
+        json.scheme = "rsa".to_string();
+        let req = test::TestRequest::post()
+            .uri("/upload/json")
+            .set_json(json)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        assert!(state
+            .storage
+            .load_public_key(&compute_client_id(&verifying_key))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[cfg(feature = "secp256k1")]
+    #[actix_web::test]
+    async fn test_json_upload_signed_with_secp256k1() {
+        use crypto::SignatureScheme;
+
+        let (state, _dir) = test_state();
+        let app = test::init_service(App::new().app_data(state.clone()).service(upload_json)).await;
+
+        let secret_key = [9u8; 32];
+        let scheme = SignatureScheme::Secp256k1;
+        let public_key = scheme.public_key_from_secret(&secret_key).unwrap();
+        let mut json = json_upload(&SigningKey::from_bytes(&secret_key), "a.txt", b"a");
+        json.scheme = scheme.to_string();
+        json.public_key = hex::encode(public_key.to_bytes());
+        let message = signed_message(&json, &public_key.to_bytes(), None);
+        json.signature = hex::encode(scheme.sign(&secret_key, &message).unwrap());
+
+        // The same request claiming to be Ed25519 is rejected before verification
+        let mut mislabeled = json.clone();
+        mislabeled.scheme = SignatureScheme::Ed25519.to_string();
+        let req = test::TestRequest::post()
+            .uri("/upload/json")
+            .set_json(mislabeled)
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            actix_web::http::StatusCode::BAD_REQUEST
+        );
+
+        let req = test::TestRequest::post()
+            .uri("/upload/json")
+            .set_json(json)
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        assert_eq!(
+            state
+                .storage
+                .read_file(&public_key.client_id(), "batch", "a.txt")
+                .await
+                .unwrap(),
+            b"a"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_async_upload_is_accepted_then_reported_stored() {
+        let dir = crate::test_utils::TempDataDir::new();
+        let storage: std::sync::Arc<dyn Storage> =
+            std::sync::Arc::new(storage::filesystem::FilesystemStorage::new(dir.0.clone()));
+        let ingest = crate::ingest::IngestPipeline::start(storage.clone(), 1, 4, true);
+        let state = web::Data::new(AppState::new(storage).with_ingest(Some(ingest)));
+        let (signing_key, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(upload_json)
+                .service(upload_status),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/upload/json")
+            .set_json(json_upload(&signing_key, "a.txt", b"a"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::ACCEPTED);
+        let accepted: UploadAcceptedResponse = test::read_body_json(resp).await;
+        assert_eq!(
+            accepted.status_url,
+            format!("/upload/status/{}", accepted.job_id)
+        );
+
+        state.ingest.as_ref().unwrap().wait_idle().await;
+        let req = test::TestRequest::get()
+            .uri(&accepted.status_url)
+            .to_request();
+        let report: UploadStatusResponse = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(report.status, "stored");
+        assert_eq!(report.error, None);
+        assert_eq!(
+            state
+                .storage
+                .read_file(&client_id, "batch", "a.txt")
+                .await
+                .unwrap(),
+            b"a"
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/upload/status/unknown")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_upload_client_id_is_sha256_of_public_key() {
+        use sha2::{Digest, Sha256};
+
+        let (state, dir) = test_state();
+        let (signing_key, verifying_key) = generate_keypair();
+        let app = test::init_service(App::new().app_data(state.clone()).service(upload_json)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/upload/json")
+            .set_json(json_upload(&signing_key, "a.txt", b"a"))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        // Derived independently of compute_client_id
+        let client_id = hex::encode(Sha256::digest(verifying_key.as_bytes()));
+        assert_eq!(
+            state.storage.load_public_key(&client_id).await.unwrap(),
+            Some(verifying_key.as_bytes().to_vec())
+        );
+        assert!(state
+            .storage
+            .read_file(&client_id, "batch", "a.txt")
+            .await
+            .is_ok());
+
+        // A key stored under the id that is not the uploading key is refused; storage
+        // refuses to register it, so this stands for a tampered key file
+        let (_, other_key) = generate_keypair();
+        assert!(state
+            .storage
+            .store_public_key(&client_id, other_key.as_bytes())
+            .await
+            .is_err());
+        std::fs::write(
+            dir.0.join(&client_id).join("public_key.hex"),
+            hex::encode(other_key.as_bytes()),
+        )
+        .unwrap();
+        let req = test::TestRequest::post()
+            .uri("/upload/json")
+            .set_json(json_upload(&signing_key, "b.txt", b"b"))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            actix_web::http::StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_swapped_public_key_invalidates_signature() {
+        let (state, _dir) = test_state();
+        let (signing_key, _) = generate_keypair();
+        let (other_signing_key, other_key) = generate_keypair();
+        let app = test::init_service(App::new().app_data(state.clone()).service(upload_json)).await;
+
+        // The signed message names the signer's key, so a different key in the request
+        // no longer matches it
+        let mut swapped = json_upload(&signing_key, "a.txt", b"a");
+        swapped.public_key = hex::encode(other_key.as_bytes());
+        // Nor does a signature by the other key over a message naming the original key
+        let mut resigned = json_upload(&signing_key, "a.txt", b"a");
+        let message = signed_message(&resigned, signing_key.verifying_key().as_bytes(), None);
+        resigned.signature = hex::encode(sign_message(&other_signing_key, &message).to_bytes());
+        resigned.public_key = hex::encode(other_key.as_bytes());
+
+        for body in [swapped, resigned] {
+            let req = test::TestRequest::post()
+                .uri("/upload/json")
+                .set_json(body)
+                .to_request();
+            assert_eq!(
+                test::call_service(&app, req).await.status(),
+                actix_web::http::StatusCode::UNAUTHORIZED
+            );
+        }
+        assert!(state.storage.list_batches().await.unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_replayed_upload_nonce_is_rejected() {
+        let (state, _dir) = test_state();
+        let (signing_key, _) = generate_keypair();
+        let app = test::init_service(App::new().app_data(state.clone()).service(upload_json)).await;
+
+        // Distinct nonces are both accepted
+        let first = json_upload(&signing_key, "a.txt", b"a");
+        let second = json_upload(&signing_key, "b.txt", b"b");
+        for body in [&first, &second] {
+            let req = test::TestRequest::post()
+                .uri("/upload/json")
+                .set_json(body)
+                .to_request();
+            assert!(test::call_service(&app, req).await.status().is_success());
+        }
+
+        // Resending a request verbatim within its timestamp window is a replay
+        let req = test::TestRequest::post()
+            .uri("/upload/json")
+            .set_json(&first)
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            actix_web::http::StatusCode::UNAUTHORIZED
+        );
+
+        // A malformed nonce is a bad request
+        let mut short = json_upload(&signing_key, "c.txt", b"c");
+        short.nonce = "abcd".to_string();
+        let req = test::TestRequest::post()
+            .uri("/upload/json")
+            .set_json(&short)
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            actix_web::http::StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_json_upload_rejects_invalid_requests() {
+        let (state, _dir) = test_state();
+        let (signing_key, _) = generate_keypair();
+        let app = test::init_service(App::new().app_data(state.clone()).service(upload_json)).await;
+
+        let mut tampered = json_upload(&signing_key, "a.txt", b"a");
+        tampered.file_content = STANDARD.encode(b"x");
+
+        let mut bad_base64 = json_upload(&signing_key, "a.txt", b"a");
+        bad_base64.file_content = "not base64!".to_string();
+
+        let traversal = json_upload(&signing_key, "..", b"a");
+
+        let mut stale = json_upload(&signing_key, "a.txt", b"a");
+        stale.timestamp -= 3_600_000;
+
+        let mut forged_public = json_upload(&signing_key, "a.txt", b"a");
+        forged_public.public = true;
+
+        let mut weak_key = json_upload(&signing_key, "a.txt", b"a");
+        weak_key.public_key = crate::test_utils::SMALL_ORDER_KEYS[0].to_string();
+
+        for (body, status) in [
+            (tampered, actix_web::http::StatusCode::BAD_REQUEST),
+            (bad_base64, actix_web::http::StatusCode::BAD_REQUEST),
+            (traversal, actix_web::http::StatusCode::BAD_REQUEST),
+            (stale, actix_web::http::StatusCode::UNAUTHORIZED),
+            (forged_public, actix_web::http::StatusCode::UNAUTHORIZED),
+            (weak_key, actix_web::http::StatusCode::UNAUTHORIZED),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/upload/json")
+                .set_json(body)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), status);
+        }
+
+        assert!(state.storage.list_batches().await.unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_json_upload_stores_signed_annotations() {
+        let (state, _dir) = test_state();
+        let (signing_key, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+        let app = test::init_service(App::new().app_data(state.clone()).service(upload_json)).await;
+
+        let annotations: Annotations = [("commit".to_string(), "1a2b3c".to_string())].into();
+        let annotated = |annotations: Annotations| {
+            let mut req = json_upload(&signing_key, "a.txt", b"a");
+            let message = signed_message(
+                &req,
+                signing_key.verifying_key().as_bytes(),
+                Some(&annotations),
+            );
+            req.signature = hex::encode(sign_message(&signing_key, &message).to_bytes());
+            req.annotations = Some(annotations);
+            req
+        };
+
+        // Annotations added after signing are rejected
+        let mut forged = json_upload(&signing_key, "a.txt", b"a");
+        forged.annotations = Some(annotations.clone());
+        let oversized: Annotations = [(
+            "description".to_string(),
+            "x".repeat(common::annotations::MAX_ANNOTATION_VALUE_LEN + 1),
+        )]
+        .into();
+
+        for (body, status) in [
+            (forged, actix_web::http::StatusCode::UNAUTHORIZED),
+            (
+                annotated(oversized),
+                actix_web::http::StatusCode::BAD_REQUEST,
+            ),
+            (
+                annotated(annotations.clone()),
+                actix_web::http::StatusCode::OK,
+            ),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/upload/json")
+                .set_json(body)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), status);
+        }
+
+        assert_eq!(
+            state
+                .storage
+                .load_batch_annotations(&client_id, "batch")
+                .await
+                .unwrap(),
+            annotations
+        );
+    }
 }
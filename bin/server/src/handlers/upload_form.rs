@@ -1,10 +1,12 @@
 use actix_multipart::form::{tempfile::TempFile, text::Text, MultipartForm};
+use common::file_utils::MAX_FILENAME_BYTES;
+use crypto::SignatureScheme;
 
 /// Multipart form for file upload
 #[derive(MultipartForm)]
 pub struct UploadForm {
-    /// The file being uploaded
-    #[multipart(limit = "10MB")]
+    /// The file being uploaded, spilled to disk as it arrives
+    /// Not limited per field: the limit is configurable, so the handler checks the size
     pub file: TempFile,
 
     /// Original filename
@@ -16,45 +18,127 @@ pub struct UploadForm {
     /// Hex-encoded leaf hash of the file
     pub file_hash: Text<String>,
 
-    /// Hex-encoded Ed25519 signature
+    /// Hex-encoded signature
     pub signature: Text<String>,
 
     /// Timestamp in milliseconds since Unix epoch
     pub timestamp: Text<u64>,
 
-    /// Hex-encoded Ed25519 public key
+    /// Hex-encoded random 16-byte nonce; the server accepts each once
+    pub nonce: Text<String>,
+
+    /// Hex-encoded public key
     pub public_key: Text<String>,
+
+    /// Signature scheme of the key and signature ("ed25519" unless given)
+    pub scheme: Option<Text<String>>,
+
+    /// Mark the batch as publicly readable (anonymous downloads allowed)
+    pub public: Option<Text<bool>>,
+
+    /// JSON object of key-value annotations replacing the batch's annotations
+    pub annotations: Option<Text<String>>,
 }
 
-impl UploadForm {
-    /// Validate form fields
-    pub fn validate_fields(&self) -> Result<(), String> {
-        let filename = &self.filename.0;
-        let batch_id = &self.batch_id.0;
-        let file_hash = &self.file_hash.0;
-        let signature = &self.signature.0;
-        let public_key = &self.public_key.0;
-
-        if filename.is_empty() || filename.len() > 255 {
-            return Err("Filename must be between 1 and 255 characters".to_string());
-        }
+/// Multipart form for uploading several files of a batch at once
+#[derive(MultipartForm)]
+pub struct UploadBatchForm {
+    /// JSON `UploadBatchManifest` with each file's signed upload fields
+    pub manifest: Text<String>,
 
-        if batch_id.is_empty() || batch_id.len() > 255 {
-            return Err("Batch ID must be between 1 and 255 characters".to_string());
-        }
+    /// The files, in manifest order, each part's filename naming the file
+    /// Not limited per field, like `ReplaceBatchForm`; the handler checks each size
+    pub files: Vec<TempFile>,
+}
 
-        if file_hash.len() != 64 {
-            return Err("File hash must be exactly 64 hex characters".to_string());
-        }
+/// Multipart form for replacing a batch's file set
+#[derive(MultipartForm)]
+pub struct ReplaceBatchForm {
+    /// JSON `ReplaceBatchManifest` describing the new file set
+    pub manifest: Text<String>,
 
-        if signature.len() != 128 {
-            return Err("Signature must be exactly 128 hex characters".to_string());
-        }
+    /// New or changed files, each part's filename naming the batch file it becomes
+    /// Not limited per field: the limit would apply to all parts together, so each
+    /// file's size is checked by the handler instead
+    pub files: Vec<TempFile>,
+}
 
-        if public_key.len() != 64 {
-            return Err("Public key must be exactly 64 hex characters".to_string());
-        }
+/// Validate upload fields (length, format checks), shared by multipart and JSON uploads
+pub fn validate_upload_fields(
+    filename: &str,
+    batch_id: &str,
+    file_hash: &str,
+    signature: &str,
+    public_key: &str,
+    scheme: SignatureScheme,
+) -> Result<(), String> {
+    if filename.is_empty() || filename.len() > MAX_FILENAME_BYTES {
+        return Err(format!(
+            "Filename must be between 1 and {} bytes",
+            MAX_FILENAME_BYTES
+        ));
+    }
 
-        Ok(())
+    if batch_id.is_empty() || batch_id.len() > MAX_FILENAME_BYTES {
+        return Err(format!(
+            "Batch ID must be between 1 and {} bytes",
+            MAX_FILENAME_BYTES
+        ));
+    }
+
+    if file_hash.len() != 64 {
+        return Err("File hash must be exactly 64 hex characters".to_string());
+    }
+
+    if signature.len() != SignatureScheme::SIGNATURE_LEN * 2 {
+        return Err(format!(
+            "Signature must be exactly {} hex characters",
+            SignatureScheme::SIGNATURE_LEN * 2
+        ));
+    }
+
+    if public_key.len() != scheme.public_key_len() * 2 {
+        return Err(format!(
+            "Public key must be exactly {} hex characters for {}",
+            scheme.public_key_len() * 2,
+            scheme
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(filename: &str) -> Result<(), String> {
+        validate_upload_fields(
+            filename,
+            "batch",
+            &"0".repeat(64),
+            &"0".repeat(128),
+            &"0".repeat(64),
+            SignatureScheme::Ed25519,
+        )
+    }
+
+    #[test]
+    fn test_filename_limit_matches_common_validation() {
+        use common::file_utils::validate_filename;
+
+        // Same boundary, in bytes, as the client's validate_filename
+        for (filename, allowed) in [
+            ("a".repeat(MAX_FILENAME_BYTES), true),
+            ("a".repeat(MAX_FILENAME_BYTES + 1), false),
+            ("é".repeat(127), true),
+            ("é".repeat(128), false),
+        ] {
+            assert_eq!(validate(&filename).is_ok(), allowed);
+            assert_eq!(validate_filename(&filename).is_ok(), allowed);
+        }
+        assert!(validate("")
+            .unwrap_err()
+            .contains("between 1 and 255 bytes"));
     }
 }
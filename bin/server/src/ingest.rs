@@ -0,0 +1,308 @@
+use crate::handlers::upload::{persist_upload, PendingUpload};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use storage::Storage;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info};
+
+/// Most finished async uploads whose outcome is kept for polling; older ones are forgotten
+const STATUS_RETENTION: usize = 10_000;
+
+/// Where a queued upload stands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IngestStatus {
+    Queued,
+    Stored,
+    Failed(String),
+}
+
+impl IngestStatus {
+    /// Name reported by `GET /upload/status/{job_id}`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Stored => "stored",
+            Self::Failed(_) => "failed",
+        }
+    }
+}
+
+/// Error returned when the upload queue is full; the upload should be retried later
+#[derive(Debug)]
+pub struct QueueFull;
+
+impl std::fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Upload queue is full; retry later")
+    }
+}
+
+impl std::error::Error for QueueFull {}
+
+/// An upload waiting for a worker, with where to report the outcome
+struct IngestJob {
+    pending: PendingUpload,
+    outcome: JobOutcome,
+}
+
+enum JobOutcome {
+    /// The handler is waiting for the store to confirm
+    Reply(oneshot::Sender<anyhow::Result<()>>),
+    /// The client polls the status of this job ID
+    Track(String),
+}
+
+/// Statuses of async uploads, queued ones first and then the most recent finished ones
+#[derive(Default)]
+struct StatusTable {
+    statuses: HashMap<String, IngestStatus>,
+    finished: VecDeque<String>,
+}
+
+impl StatusTable {
+    fn finish(&mut self, job_id: String, status: IngestStatus) {
+        self.statuses.insert(job_id.clone(), status);
+        self.finished.push_back(job_id);
+        while self.finished.len() > STATUS_RETENTION {
+            if let Some(oldest) = self.finished.pop_front() {
+                self.statuses.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Bounded upload ingestion pipeline
+/// Handlers validate, hash and authenticate uploads, then hand the storage writes to a
+/// fixed pool of workers through a bounded channel. A full channel rejects the upload
+/// instead of queueing without limit, so storage latency turns into backpressure.
+pub struct IngestPipeline {
+    sender: mpsc::Sender<IngestJob>,
+    statuses: Arc<Mutex<StatusTable>>,
+    /// Uploads submitted but not yet stored or failed
+    pending: Arc<AtomicUsize>,
+    /// Answer uploads with 202 Accepted once queued instead of waiting for the store
+    async_uploads: bool,
+}
+
+impl IngestPipeline {
+    /// Start `workers` workers storing uploads from a queue holding at most `queue_size`
+    pub fn start(
+        storage: Arc<dyn Storage>,
+        workers: usize,
+        queue_size: usize,
+        async_uploads: bool,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(queue_size.max(1));
+        let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+        let statuses = Arc::new(Mutex::new(StatusTable::default()));
+        let pending = Arc::new(AtomicUsize::new(0));
+        for _ in 0..workers.max(1) {
+            tokio::spawn(Self::work(
+                storage.clone(),
+                receiver.clone(),
+                statuses.clone(),
+                pending.clone(),
+            ));
+        }
+        info!(
+            "Upload ingestion pipeline started: workers={}, queue_size={}, async={}",
+            workers, queue_size, async_uploads
+        );
+        Self {
+            sender,
+            statuses,
+            pending,
+            async_uploads,
+        }
+    }
+
+    pub fn is_async(&self) -> bool {
+        self.async_uploads
+    }
+
+    /// Queue an upload and wait until a worker has stored it
+    pub async fn submit_and_wait(&self, upload: PendingUpload) -> anyhow::Result<()> {
+        let (reply, outcome) = oneshot::channel();
+        self.enqueue(IngestJob {
+            pending: upload,
+            outcome: JobOutcome::Reply(reply),
+        })?;
+        outcome
+            .await
+            .map_err(|_| anyhow::anyhow!("Upload worker stopped before storing the upload"))?
+    }
+
+    /// Queue an upload and return the job ID its status can be polled under
+    pub fn submit(&self, upload: PendingUpload) -> Result<String, QueueFull> {
+        let job_id = uuid::Uuid::new_v4().to_string();
+        self.statuses
+            .lock()
+            .unwrap()
+            .statuses
+            .insert(job_id.clone(), IngestStatus::Queued);
+        let queued = self.enqueue(IngestJob {
+            pending: upload,
+            outcome: JobOutcome::Track(job_id.clone()),
+        });
+        if queued.is_err() {
+            self.statuses.lock().unwrap().statuses.remove(&job_id);
+        }
+        queued.map(|_| job_id)
+    }
+
+    fn enqueue(&self, job: IngestJob) -> Result<(), QueueFull> {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        self.sender.try_send(job).map_err(|_| {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            QueueFull
+        })
+    }
+
+    /// Status of an async upload, or `None` if the job ID is unknown or long finished
+    pub fn status(&self, job_id: &str) -> Option<IngestStatus> {
+        self.statuses.lock().unwrap().statuses.get(job_id).cloned()
+    }
+
+    /// Wait until every submitted upload has been stored or has failed
+    pub async fn wait_idle(&self) {
+        while self.pending.load(Ordering::SeqCst) > 0 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    /// Worker loop: store uploads until every sender is gone
+    async fn work(
+        storage: Arc<dyn Storage>,
+        receiver: Arc<tokio::sync::Mutex<mpsc::Receiver<IngestJob>>>,
+        statuses: Arc<Mutex<StatusTable>>,
+        pending: Arc<AtomicUsize>,
+    ) {
+        loop {
+            // Only one idle worker waits on the channel at a time; the lock is released
+            // before storing so the others keep taking jobs
+            let Some(job) = receiver.lock().await.recv().await else {
+                break;
+            };
+            let result = persist_upload(storage.as_ref(), &job.pending).await;
+            match job.outcome {
+                JobOutcome::Reply(reply) => {
+                    let _ = reply.send(result);
+                }
+                JobOutcome::Track(job_id) => {
+                    let status = match result {
+                        Ok(()) => IngestStatus::Stored,
+                        Err(e) => {
+                            error!("Queued upload {} failed: {:#}", job_id, e);
+                            IngestStatus::Failed(format!("{:#}", e))
+                        }
+                    };
+                    statuses.lock().unwrap().finish(job_id, status);
+                }
+            }
+            pending.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::upload::UploadContent;
+    use crate::test_utils::TempDataDir;
+    use storage::filesystem::FilesystemStorage;
+    use storage::ReadOnlyStorage;
+
+    fn upload(filename: &str, content: &[u8]) -> PendingUpload {
+        PendingUpload {
+            client_id: "client".to_string(),
+            batch_id: "batch".to_string(),
+            filename: filename.to_string(),
+            content: UploadContent::Bytes(content.to_vec()),
+            public: false,
+            annotations: None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_pipeline_stores_queued_uploads_and_reports_completion() {
+        let dir = TempDataDir::new();
+        let storage: Arc<dyn Storage> = Arc::new(FilesystemStorage::new(dir.0.clone()));
+        let pipeline = IngestPipeline::start(storage.clone(), 2, 8, true);
+
+        let job_ids: Vec<String> = (0..5)
+            .map(|i| {
+                let filename = format!("file{}.txt", i);
+                pipeline
+                    .submit(upload(&filename, filename.as_bytes()))
+                    .unwrap()
+            })
+            .collect();
+        pipeline.wait_idle().await;
+
+        for (i, job_id) in job_ids.iter().enumerate() {
+            assert_eq!(pipeline.status(job_id), Some(IngestStatus::Stored));
+            let filename = format!("file{}.txt", i);
+            assert_eq!(
+                storage
+                    .read_file("client", "batch", &filename)
+                    .await
+                    .unwrap(),
+                filename.as_bytes()
+            );
+        }
+        let tree = storage
+            .load_merkle_tree("client", "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tree.num_leaves(), 5);
+        assert_eq!(pipeline.status("unknown"), None);
+
+        // Synchronous submissions report the store's outcome directly
+        assert!(pipeline
+            .submit_and_wait(upload("sync.txt", b"sync"))
+            .await
+            .is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_failed_store_is_reported() {
+        let dir = TempDataDir::new();
+        let storage: Arc<dyn Storage> = Arc::new(ReadOnlyStorage::new(Arc::new(
+            FilesystemStorage::new(dir.0.clone()),
+        )));
+        let pipeline = IngestPipeline::start(storage, 1, 8, true);
+
+        assert!(pipeline
+            .submit_and_wait(upload("a.txt", b"a"))
+            .await
+            .is_err());
+        let job_id = pipeline.submit(upload("b.txt", b"b")).unwrap();
+        pipeline.wait_idle().await;
+        assert!(matches!(
+            pipeline.status(&job_id),
+            Some(IngestStatus::Failed(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn test_full_queue_rejects_uploads() {
+        let dir = TempDataDir::new();
+        let storage: Arc<dyn Storage> = Arc::new(FilesystemStorage::new(dir.0.clone()));
+        // No runtime turn happens between submissions, so the single worker cannot drain
+        let pipeline = IngestPipeline::start(storage, 1, 2, true);
+        let results: Vec<_> = (0..4)
+            .map(|i| pipeline.submit(upload(&format!("f{}.txt", i), b"x")))
+            .collect();
+        assert!(results.iter().filter(|r| r.is_err()).count() >= 1);
+        let rejected = results.iter().position(|r| r.is_err()).unwrap();
+        assert!(results[..rejected].iter().all(|r| r.is_ok()));
+
+        pipeline.wait_idle().await;
+        for job_id in results.into_iter().flatten() {
+            assert_eq!(pipeline.status(&job_id), Some(IngestStatus::Stored));
+        }
+    }
+}
@@ -1,17 +1,32 @@
+mod audit;
 mod auth;
+mod compact;
+mod compression;
 mod config;
 mod constants;
+mod download_limit;
 mod handlers;
+mod ingest;
 mod logger;
+mod metrics;
+mod nonce_cache;
 mod proof;
+mod rate_limit;
+mod routes;
+mod scrubber;
 mod state;
+#[cfg(test)]
+mod test_utils;
 
+use actix_web::middleware::{from_fn, Compress, Condition};
 use actix_web::{web, App, HttpServer};
 use config::ServerConfig;
+use ingest::IngestPipeline;
 use logger::init as init_logger;
+use scrubber::Scrubber;
 use state::AppState;
-use storage::StorageBackend;
-use tracing::{error, info};
+use storage::{Durability, StorageBackend, StorageLayers};
+use tracing::{error, info, warn};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -24,6 +39,28 @@ async fn main() -> std::io::Result<()> {
 
     let config = ServerConfig::load()?;
 
+    info!(
+        "Logging storage calls slower than {:?}",
+        config.slow_op_threshold
+    );
+    if config.read_only {
+        info!("Read-only mode: storage writes are rejected");
+    }
+    let layers = StorageLayers {
+        read_only: config.read_only,
+        slow_op_threshold: Some(config.slow_op_threshold),
+    };
+
+    if config.encryption.is_some() {
+        info!("Encrypting stored file content at rest");
+    }
+    if let Some(compression) = &config.storage_compression {
+        info!(
+            "Compressing stored file content with {} (level {})",
+            compression.algorithm, compression.level
+        );
+    }
+
     let storage = match config.storage_type {
         config::StorageType::Database => {
             let database_url = config.database_url.as_ref().unwrap();
@@ -33,11 +70,36 @@ async fn main() -> std::io::Result<()> {
                 config.database_retry_config.max_attempts,
                 config.database_retry_config.initial_delay_seconds
             );
+            info!(
+                "Database pool configuration: {:?}",
+                config.database_pool_config
+            );
+            let external_content_dir = match &config.db_external_content_dir {
+                Some(dir) => {
+                    info!("Storing file content outside the database: {:?}", dir);
+                    Some(
+                        dir.to_str()
+                            .ok_or_else(|| {
+                                std::io::Error::new(
+                                    std::io::ErrorKind::InvalidInput,
+                                    "Invalid external content directory path",
+                                )
+                            })?
+                            .to_string(),
+                    )
+                }
+                None => None,
+            };
             StorageBackend::Database {
                 database_url: database_url.clone(),
                 retry_config: Some(config.database_retry_config.clone()),
+                pool_config: Some(config.database_pool_config.clone()),
+                external_content_dir,
+                max_files_per_batch: Some(config.max_files_per_batch),
+                encryption: config.encryption.clone(),
+                compression: config.storage_compression,
             }
-            .initialize()
+            .initialize(&layers)
             .await
             .map_err(|e| {
                 error!("Failed to initialize database storage: {}", e);
@@ -45,12 +107,12 @@ async fn main() -> std::io::Result<()> {
             })?
         }
         config::StorageType::Filesystem => {
-            if !config.data_dir.exists() {
-                std::fs::create_dir_all(&config.data_dir)?;
-            }
             info!("Using filesystem storage: {:?}", config.data_dir);
-            StorageBackend::Filesystem(
-                config
+            if config.durability == Durability::Relaxed {
+                warn!("Relaxed durability: uploads are not fsynced individually and may be lost on power loss");
+            }
+            StorageBackend::Filesystem {
+                data_dir: config
                     .data_dir
                     .to_str()
                     .ok_or_else(|| {
@@ -60,31 +122,186 @@ async fn main() -> std::io::Result<()> {
                         )
                     })?
                     .to_string(),
-            )
-            .initialize()
+                max_files_per_batch: Some(config.max_files_per_batch),
+                durability: config.durability,
+                follow_data_symlink: config.follow_data_symlink,
+                encryption: config.encryption.clone(),
+                compression: config.storage_compression,
+            }
+            .initialize(&layers)
             .await
             .map_err(|e| {
                 error!("Failed to initialize filesystem storage: {}", e);
                 std::io::Error::other(format!("Failed to initialize filesystem storage: {}", e))
             })?
         }
+        config::StorageType::Sqlite => {
+            let sqlite_path = config.sqlite_path.as_ref().unwrap();
+            info!("Using SQLite storage: {:?}", sqlite_path);
+            StorageBackend::Sqlite {
+                path: sqlite_path
+                    .to_str()
+                    .ok_or_else(|| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            "Invalid SQLite database path",
+                        )
+                    })?
+                    .to_string(),
+                max_files_per_batch: Some(config.max_files_per_batch),
+            }
+            .initialize(&layers)
+            .await
+            .map_err(|e| {
+                error!("Failed to initialize SQLite storage: {:#}", e);
+                std::io::Error::other(format!("Failed to initialize SQLite storage: {:#}", e))
+            })?
+        }
+        config::StorageType::Memory => {
+            warn!("Using in-memory storage: everything stored is lost when the server stops");
+            StorageBackend::Memory {
+                max_files_per_batch: Some(config.max_files_per_batch),
+            }
+            .initialize(&layers)
+            .await
+            .map_err(|e| {
+                error!("Failed to initialize in-memory storage: {}", e);
+                std::io::Error::other(format!("Failed to initialize in-memory storage: {}", e))
+            })?
+        }
+        #[cfg(feature = "s3")]
+        config::StorageType::S3 => {
+            info!(
+                "Using S3 storage: bucket {:?}, endpoint {:?}",
+                config.s3_bucket, config.s3_endpoint
+            );
+            StorageBackend::S3 {
+                bucket: config.s3_bucket.clone().unwrap_or_default(),
+                endpoint: config.s3_endpoint.clone(),
+                region: config.s3_region.clone(),
+                max_files_per_batch: Some(config.max_files_per_batch),
+            }
+            .initialize(&layers)
+            .await
+            .map_err(|e| {
+                error!("Failed to initialize S3 storage: {:#}", e);
+                std::io::Error::other(format!("Failed to initialize S3 storage: {:#}", e))
+            })?
+        }
+        #[cfg(not(feature = "s3"))]
+        config::StorageType::S3 => {
+            error!(
+                "S3 storage requested (bucket {:?}, endpoint {:?}, region {:?}) but this server was built without the s3 feature",
+                config.s3_bucket, config.s3_endpoint, config.s3_region
+            );
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "This server was built without S3 support; rebuild with `--features s3`",
+            ));
+        }
     };
     info!("Storage backend initialized successfully");
 
-    let state = web::Data::new(AppState::new(storage));
+    if let Some(target) = &config.audit_batch {
+        let audit = audit::audit_batch(storage.as_ref(), &target.client_id, &target.batch_id)
+            .await
+            .map_err(|e| std::io::Error::other(format!("Batch audit failed: {:#}", e)))?;
+        audit.print();
+        if !audit.passed() {
+            return Err(std::io::Error::other("Batch audit found failures"));
+        }
+        return Ok(());
+    }
+
+    if let Some(target) = &config.compact_batch {
+        let compaction =
+            compact::compact_batch(storage.as_ref(), &target.client_id, &target.batch_id)
+                .await
+                .map_err(|e| std::io::Error::other(format!("Batch compaction failed: {:#}", e)))?;
+        compaction.print();
+        if !compaction.passed() {
+            return Err(std::io::Error::other(
+                "Compacted batch no longer verifies against its root",
+            ));
+        }
+        return Ok(());
+    }
+
+    let ingest = config.ingest_workers.map(|workers| {
+        IngestPipeline::start(
+            storage.clone(),
+            workers,
+            config.ingest_queue_size,
+            config.async_uploads,
+        )
+    });
+    let state = web::Data::new(
+        AppState::new(storage)
+            .with_max_upload_size_bytes(config.max_upload_size_bytes)
+            .with_max_proof_batch_files(config.max_proof_batch_files)
+            .with_hash_truncation_bytes(config.hash_truncation_bytes)
+            .with_max_concurrent_downloads_per_client(config.max_concurrent_downloads_per_client)
+            .with_rate_limit(config.rate_limit_rps, config.rate_limit_burst)
+            .with_ingest(ingest)
+            .with_debug_endpoints(config.enable_debug_endpoints),
+    );
+    if config.enable_debug_endpoints {
+        warn!("Debug endpoints are enabled (POST /debug/sign-preview); do not use in production");
+    }
+    if let Some(rps) = config.rate_limit_rps {
+        info!(
+            rps,
+            burst = config.rate_limit_burst,
+            "Rate limiting authenticated uploads and downloads per client"
+        );
+    }
+    if let Some(bytes) = config.hash_truncation_bytes {
+        warn!(
+            "Merkle node hashes are truncated to {} bytes; proofs are shorter but weaker",
+            bytes
+        );
+    }
+
+    // Optional background scrubber, stopped once the HTTP server shuts down
+    let (scrub_shutdown, scrub_shutdown_rx) = tokio::sync::watch::channel(false);
+    let scrub_task = config.scrub_interval.map(|interval| {
+        let scrubber = Scrubber::new(
+            state.storage.clone(),
+            state.scrub_report.clone(),
+            config.scrub_files_per_tick,
+        );
+        tokio::spawn(scrubber.run(interval, scrub_shutdown_rx))
+    });
     let bind_address = config.bind_address();
+    let compression = config.response_compression;
+    info!("Response compression: {:?}", compression);
+    info!(
+        backlog = config.backlog,
+        keep_alive_seconds = config.keep_alive.as_secs(),
+        client_request_timeout_ms = config.client_request_timeout.as_millis() as u64,
+        "Connection tuning"
+    );
 
     info!("Starting server on http://{}", bind_address);
 
     let bind_addr = bind_address.clone();
+    let max_upload_size_bytes = config.max_upload_size_bytes;
+    let ingest_state = state.clone();
     let server = HttpServer::new(move || {
         App::new()
             .app_data(state.clone())
-            .app_data(web::PayloadConfig::default().limit(crate::constants::MAX_UPLOAD_SIZE_BYTES))
-            .service(handlers::upload::upload)
-            .service(handlers::download::download)
-            .service(handlers::health::health)
+            .wrap(from_fn(metrics::track_request_duration))
+            // Registered last so it runs first, narrowing Accept-Encoding before Compress sees it
+            .wrap(Condition::new(compression.is_enabled(), Compress::default()))
+            .wrap(from_fn(move |req, next| {
+                compression::restrict_accept_encoding(compression, req, next)
+            }))
+            .configure(|cfg| routes::configure_with_max_upload_size(cfg, max_upload_size_bytes))
     })
+    // The backlog only applies to sockets bound after it is set
+    .backlog(config.backlog)
+    .keep_alive(config.keep_alive)
+    .client_request_timeout(config.client_request_timeout)
     .bind(&bind_addr)
     .map_err(|e| {
         error!("Failed to bind to {}: {}", bind_addr, e);
@@ -92,5 +309,18 @@ async fn main() -> std::io::Result<()> {
     })?;
 
     info!("Server bound successfully to http://{}", bind_address);
-    server.workers(1).run().await
+    let result = server.workers(1).run().await;
+
+    // Queued uploads were already acknowledged (202 Accepted); store them before exiting
+    if let Some(ingest) = &ingest_state.ingest {
+        info!("Waiting for queued uploads to be stored");
+        ingest.wait_idle().await;
+    }
+
+    let _ = scrub_shutdown.send(true);
+    if let Some(task) = scrub_task {
+        let _ = task.await;
+    }
+
+    result
 }
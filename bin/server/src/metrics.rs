@@ -0,0 +1,196 @@
+use crate::state::AppState;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse, Result as ActixResult};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::time::Instant;
+
+/// Prometheus metrics of one server instance
+/// Counters and the duration histogram count this instance's requests since it started;
+/// the gauges are read from shared state on each scrape, so every instance reports the same.
+pub struct Metrics {
+    registry: Registry,
+    uploads: IntCounterVec,
+    downloads: IntCounterVec,
+    request_duration: HistogramVec,
+    signature_verification_failures: IntCounter,
+    registered_clients: IntGauge,
+    corrupt_files: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let uploads = IntCounterVec::new(
+            Opts::new("uploads_total", "Upload requests, by result"),
+            &["result"],
+        )
+        .unwrap();
+        let downloads = IntCounterVec::new(
+            Opts::new("downloads_total", "Download requests, by result"),
+            &["result"],
+        )
+        .unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to answer requests, by method and route",
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        let signature_verification_failures = IntCounter::new(
+            "signature_verification_failures_total",
+            "Request signatures that failed to verify",
+        )
+        .unwrap();
+        let registered_clients =
+            IntGauge::new("registered_clients", "Clients with a registered public key").unwrap();
+        let corrupt_files = IntGauge::new(
+            "corrupt_files",
+            "Files whose content does not match their stored leaf hash",
+        )
+        .unwrap();
+
+        // Names are fixed and distinct, so registering cannot fail
+        let registry = Registry::new();
+        registry.register(Box::new(uploads.clone())).unwrap();
+        registry.register(Box::new(downloads.clone())).unwrap();
+        registry
+            .register(Box::new(request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(signature_verification_failures.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(registered_clients.clone()))
+            .unwrap();
+        registry.register(Box::new(corrupt_files.clone())).unwrap();
+
+        Self {
+            registry,
+            uploads,
+            downloads,
+            request_duration,
+            signature_verification_failures,
+            registered_clients,
+            corrupt_files,
+        }
+    }
+
+    /// Count an upload request by its outcome
+    pub fn record_upload(&self, result: &ActixResult<HttpResponse>) {
+        self.uploads
+            .with_label_values(&[result_label(result)])
+            .inc();
+    }
+
+    /// Count a download request by its outcome
+    pub fn record_download(&self, result: &ActixResult<HttpResponse>) {
+        self.downloads
+            .with_label_values(&[result_label(result)])
+            .inc();
+    }
+
+    /// Count a request signature that failed to verify
+    pub fn record_signature_failure(&self) {
+        self.signature_verification_failures.inc();
+    }
+
+    /// Refresh the gauges and encode every metric in Prometheus text format
+    pub async fn render(&self, state: &AppState) -> Vec<u8> {
+        match state.storage.count_clients().await {
+            Ok(count) => self.registered_clients.set(count as i64),
+            // Serve the other metrics rather than fail the scrape
+            Err(e) => tracing::warn!("Failed to count registered clients: {}", e),
+        }
+        self.corrupt_files
+            .set(state.scrub_report.corrupt_files() as i64);
+
+        let mut body = Vec::new();
+        // Writing to a Vec cannot fail, and every registered metric is valid
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut body)
+            .unwrap();
+        body
+    }
+
+    /// Content type of the text `render` produces
+    pub fn content_type(&self) -> String {
+        TextEncoder::new().format_type().to_string()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `result` label of a request's outcome: `success`, `client_error` or `server_error`
+fn result_label(result: &ActixResult<HttpResponse>) -> &'static str {
+    let status = match result {
+        Ok(response) => response.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    if status.is_server_error() {
+        "server_error"
+    } else if status.is_client_error() {
+        "client_error"
+    } else {
+        "success"
+    }
+}
+
+/// Record how long each request took in the duration histogram
+/// Labelled by route pattern rather than path, so query strings and path parameters
+/// do not create a series per request
+pub async fn track_request_duration(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    let state = req.app_data::<web::Data<AppState>>().cloned();
+
+    let response = next.call(req).await;
+
+    if let Some(state) = state {
+        state
+            .metrics
+            .request_duration
+            .with_label_values(&[&method, &route])
+            .observe(started.elapsed().as_secs_f64());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_results_are_labelled_by_status_class() {
+        let metrics = Metrics::new();
+        metrics.record_upload(&Ok(HttpResponse::Ok().finish()));
+        metrics.record_upload(&Ok(HttpResponse::Accepted().finish()));
+        metrics.record_upload(&Err(actix_web::error::ErrorBadRequest("bad")));
+        metrics.record_download(&Err(actix_web::error::ErrorInternalServerError("oops")));
+
+        assert_eq!(metrics.uploads.with_label_values(&["success"]).get(), 2);
+        assert_eq!(
+            metrics.uploads.with_label_values(&["client_error"]).get(),
+            1
+        );
+        assert_eq!(
+            metrics.downloads.with_label_values(&["server_error"]).get(),
+            1
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use crypto::NONCE_BYTES;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Nonces of recently accepted signed requests, so each signature is accepted once
+/// A request is only accepted while its timestamp is fresh, so a nonce has to be
+/// remembered for that long and no longer: entries older than the TTL are evicted, which
+/// bounds memory by the request rate. The cache is per server process; instances behind
+/// a load balancer each keep their own.
+pub struct NonceCache {
+    ttl: Duration,
+    seen: Mutex<SeenNonces>,
+}
+
+#[derive(Default)]
+struct SeenNonces {
+    nonces: HashSet<[u8; NONCE_BYTES]>,
+    /// The same nonces in the order they were accepted, oldest first, for eviction
+    accepted: VecDeque<(Instant, [u8; NONCE_BYTES])>,
+}
+
+impl NonceCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            seen: Mutex::new(SeenNonces::default()),
+        }
+    }
+
+    /// Record `nonce` as used; returns false if it was already used within the TTL
+    pub fn insert(&self, nonce: [u8; NONCE_BYTES]) -> bool {
+        let now = Instant::now();
+        let mut seen = self.seen.lock().unwrap();
+        while let Some(&(accepted_at, old)) = seen.accepted.front() {
+            if now.duration_since(accepted_at) < self.ttl {
+                break;
+            }
+            seen.accepted.pop_front();
+            seen.nonces.remove(&old);
+        }
+
+        if !seen.nonces.insert(nonce) {
+            return false;
+        }
+        seen.accepted.push_back((now, nonce));
+        true
+    }
+
+    /// Number of nonces currently remembered
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.seen.lock().unwrap().nonces.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nonce_accepted_once_until_evicted() {
+        let cache = NonceCache::new(Duration::from_millis(50));
+        assert!(cache.insert([1; NONCE_BYTES]));
+        assert!(!cache.insert([1; NONCE_BYTES]));
+        assert!(cache.insert([2; NONCE_BYTES]));
+        assert_eq!(cache.len(), 2);
+
+        // Expired entries are dropped on the next insert
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(cache.insert([3; NONCE_BYTES]));
+        assert_eq!(cache.len(), 1);
+        assert!(cache.insert([1; NONCE_BYTES]));
+    }
+}
@@ -1,9 +1,12 @@
 use crate::state::AppState;
 use actix_web::web;
 use common::ProofNodeJson;
+use merkle_tree::{encode_hash, MerkleTree};
 use tracing::error;
 
-use crate::handlers::error::handle_server_error;
+use crate::handlers::error::{
+    handle_insufficient_storage, handle_server_error, handle_storage_error,
+};
 
 /// Generate Merkle proof for a file in a batch
 pub async fn generate_proof(
@@ -17,12 +20,50 @@ pub async fn generate_proof(
     let mut sorted_filenames = filenames.to_vec();
     sorted_filenames.sort();
 
+    let tree = load_proof_tree(state, client_id, batch_id, &sorted_filenames).await?;
+
+    // Find file index and generate proof
+    let file_index = sorted_filenames
+        .iter()
+        .position(|name| name == filename)
+        .ok_or_else(|| {
+            error!("File {} not found in sorted filenames", filename);
+            actix_web::error::ErrorNotFound(format!("File {} not found", filename))
+        })?;
+
+    tree.generate_proof(file_index)
+        .map_err(|e| handle_server_error("Failed to generate proof", e))
+}
+
+/// Load the tree a batch's proofs are served from, checked against its sorted `filenames`
+/// Batches over `--max-proof-batch-files` are refused with 507 before their tree is loaded,
+/// since the whole tree is held in memory to build a proof
+pub async fn load_proof_tree(
+    state: &web::Data<AppState>,
+    client_id: &str,
+    batch_id: &str,
+    filenames: &[String],
+) -> Result<MerkleTree, actix_web::Error> {
+    if let Some(max) = state.max_proof_batch_files {
+        if filenames.len() > max {
+            return Err(handle_insufficient_storage(
+                "Batch too large for proof generation",
+                anyhow::anyhow!(
+                    "batch {} holds {} files, the limit is {}",
+                    batch_id,
+                    filenames.len(),
+                    max
+                ),
+            ));
+        }
+    }
+
     // Load stored Merkle tree from database/filesystem
     let tree = state
         .storage
         .load_merkle_tree(client_id, batch_id)
         .await
-        .map_err(|e| handle_server_error("Failed to load Merkle tree", e))?
+        .map_err(|e| handle_storage_error("Failed to load Merkle tree", e))?
         .ok_or_else(|| {
             error!("Merkle tree not found in storage for batch {}", batch_id);
             handle_server_error(
@@ -35,42 +76,46 @@ pub async fn generate_proof(
         })?;
 
     // Verify stored tree has correct number of leaves (data integrity check)
-    if tree.num_leaves() != sorted_filenames.len() {
+    if tree.num_leaves() != filenames.len() {
         error!(
             "Stored tree has {} leaves but batch has {} files - tree is out of sync",
             tree.num_leaves(),
-            sorted_filenames.len()
+            filenames.len()
         );
         return Err(handle_server_error(
             "Stored Merkle tree is invalid (leaf count mismatch)",
             anyhow::anyhow!(
                 "Tree has {} leaves but batch has {} files",
                 tree.num_leaves(),
-                sorted_filenames.len()
+                filenames.len()
             ),
         ));
     }
 
-    // Find file index and generate proof
-    let file_index = sorted_filenames
-        .iter()
-        .position(|name| name == filename)
-        .ok_or_else(|| {
-            error!("File {} not found in sorted filenames", filename);
-            actix_web::error::ErrorNotFound(format!("File {} not found", filename))
-        })?;
+    served_tree(state, tree)
+}
 
-    tree.generate_proof(file_index)
-        .map_err(|e| handle_server_error("Failed to generate proof", e))
+/// The tree proofs and roots are served from: `tree` itself, or its truncated view
+/// when the server runs with `--hash-truncation-bytes`
+pub fn served_tree(state: &AppState, tree: MerkleTree) -> Result<MerkleTree, actix_web::Error> {
+    match state.hash_truncation_bytes {
+        Some(bytes) => tree
+            .truncated(bytes)
+            .map_err(|e| handle_server_error("Failed to truncate Merkle tree", e)),
+        None => Ok(tree),
+    }
 }
 
-/// Convert Merkle proof to JSON format
-pub fn proof_to_json(proof: &merkle_tree::MerkleProof) -> Vec<ProofNodeJson> {
+/// Convert Merkle proof to JSON format, encoding each node at the served width
+pub fn proof_to_json(
+    proof: &merkle_tree::MerkleProof,
+    truncation: Option<usize>,
+) -> Vec<ProofNodeJson> {
     proof
         .path
         .iter()
         .map(|p| ProofNodeJson {
-            hash: hex::encode(p.hash),
+            hash: encode_hash(&p.hash, truncation),
             is_left: p.is_left,
         })
         .collect()
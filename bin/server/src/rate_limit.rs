@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often idle buckets are dropped, at most
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Per-client token-bucket limit on request rate
+/// Each client's bucket holds up to `burst` tokens and refills at `rps` tokens per
+/// second; every authenticated upload or download takes one. Buckets are created on a
+/// client's first request, and full ones are dropped by a sweep at most once per
+/// `SWEEP_INTERVAL`: a full bucket is the same as a fresh one, so idle clients cost nothing.
+pub struct RateLimiter {
+    rps: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    last_sweep: Mutex<Instant>,
+}
+
+/// Tokens a client has left, as of `updated`
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Add the tokens earned since the last update, up to `burst`
+    fn refill(&mut self, rps: f64, burst: f64, now: Instant) {
+        let earned = now.saturating_duration_since(self.updated).as_secs_f64() * rps;
+        self.tokens = (self.tokens + earned).min(burst);
+        self.updated = now;
+    }
+}
+
+impl RateLimiter {
+    pub fn new(rps: f64, burst: usize) -> Self {
+        Self {
+            rps,
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(HashMap::new()),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn rps(&self) -> f64 {
+        self.rps
+    }
+
+    /// Take a token for `client_id`, or return how long until one is available
+    pub fn check(&self, client_id: &str) -> Result<(), Duration> {
+        self.check_at(client_id, Instant::now())
+    }
+
+    fn check_at(&self, client_id: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        self.sweep(&mut buckets, now);
+
+        let bucket = buckets.entry(client_id.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.refill(self.rps, self.burst, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rps))
+        }
+    }
+
+    /// Drop the buckets that have refilled completely, once per `SWEEP_INTERVAL`
+    fn sweep(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        let mut last_sweep = self.last_sweep.lock().unwrap();
+        if now.saturating_duration_since(*last_sweep) < SWEEP_INTERVAL {
+            return;
+        }
+        *last_sweep = now;
+        buckets.retain(|_, bucket| {
+            bucket.refill(self.rps, self.burst, now);
+            bucket.tokens < self.burst
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_is_allowed_then_one_request_is_limited() {
+        let limiter = RateLimiter::new(1.0, 3);
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check_at("a", now).is_ok());
+        }
+        let wait = limiter.check_at("a", now).unwrap_err();
+        assert_eq!(wait, Duration::from_secs(1));
+
+        // Other clients have their own bucket
+        assert!(limiter.check_at("b", now).is_ok());
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let limiter = RateLimiter::new(2.0, 2);
+        let start = Instant::now();
+        assert!(limiter.check_at("a", start).is_ok());
+        assert!(limiter.check_at("a", start).is_ok());
+        assert!(limiter.check_at("a", start).is_err());
+
+        // One token is earned every half second, and never more than the burst
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check_at("a", later).is_ok());
+        assert!(limiter.check_at("a", later).is_err());
+
+        let much_later = later + Duration::from_secs(30);
+        assert!(limiter.check_at("a", much_later).is_ok());
+        assert!(limiter.check_at("a", much_later).is_ok());
+        assert!(limiter.check_at("a", much_later).is_err());
+    }
+
+    #[test]
+    fn test_idle_buckets_are_swept() {
+        let limiter = RateLimiter::new(1.0, 2);
+        let start = Instant::now();
+        assert!(limiter.check_at("idle", start).is_ok());
+        assert!(limiter.check_at("busy", start).is_ok());
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 2);
+
+        // By the next sweep "idle" has refilled and is dropped; "busy" just spent a token
+        let next_sweep = start + SWEEP_INTERVAL + Duration::from_secs(1);
+        assert!(limiter.check_at("busy", next_sweep).is_ok());
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), 1);
+        assert!(buckets.contains_key("busy"));
+    }
+}
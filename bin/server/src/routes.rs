@@ -0,0 +1,664 @@
+use crate::constants::{
+    DEFAULT_MAX_UPLOAD_SIZE_BYTES, MAX_JSON_PAYLOAD_SIZE_BYTES, MAX_MULTIPART_FILES_PER_REQUEST,
+};
+use crate::handlers;
+use actix_multipart::form::MultipartFormConfig;
+use actix_web::web;
+
+/// [`configure_with_max_upload_size`] with the default upload size, for in-process tests
+#[cfg(test)]
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    configure_with_max_upload_size(cfg, DEFAULT_MAX_UPLOAD_SIZE_BYTES);
+}
+
+/// Register the request size limits and every endpoint
+/// Shared by the server and in-process tests, so tests drive the real route table
+/// without a running server; middleware and app state are added by the caller
+/// Multipart bodies are sized for files of up to `max_upload_size_bytes`; each file's
+/// own size is checked by the handlers against the same limit in `AppState`
+pub fn configure_with_max_upload_size(cfg: &mut web::ServiceConfig, max_upload_size_bytes: usize) {
+    cfg.app_data(web::PayloadConfig::default().limit(DEFAULT_MAX_UPLOAD_SIZE_BYTES))
+        .app_data(web::JsonConfig::default().limit(MAX_JSON_PAYLOAD_SIZE_BYTES))
+        // Text fields such as a replacement manifest are buffered, files spill to disk
+        .app_data(
+            MultipartFormConfig::default()
+                .total_limit(max_upload_size_bytes.saturating_mul(MAX_MULTIPART_FILES_PER_REQUEST))
+                .memory_limit(MAX_JSON_PAYLOAD_SIZE_BYTES),
+        )
+        .service(handlers::upload::upload)
+        .service(handlers::upload::upload_json)
+        .service(handlers::upload::upload_batch)
+        .service(handlers::upload::upload_status)
+        .service(handlers::download::download)
+        .service(handlers::proofs::get_proofs)
+        .service(handlers::batch::create_batch)
+        .service(handlers::batch::rename_batch)
+        .service(handlers::batch::delete_batch)
+        .service(handlers::batch::delete_file)
+        .service(handlers::batch::finalize_batch)
+        .service(handlers::batch::replace_batch)
+        .service(handlers::batch::list_batch_files)
+        .service(handlers::batch::list_batches)
+        .service(handlers::batch::batch_root)
+        .service(handlers::batch::batch_tree)
+        .service(handlers::batch::grant_access)
+        .service(handlers::batch::revoke_access)
+        .service(handlers::cas::get_by_hash)
+        .service(handlers::capabilities::capabilities)
+        .service(handlers::health::health)
+        .service(handlers::metrics::metrics)
+        .service(handlers::debug::sign_preview)
+        .default_service(web::to(handlers::fallback::unmatched));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::AppState;
+    use crate::test_utils::{test_state, TempDataDir};
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use common::utils::get_current_timestamp_ms;
+    use common::{
+        ApiError, CapabilitiesResponse, DeleteFileResponse, DownloadResponse, FinalizeBatchRequest,
+        HealthResponse, UploadRequest, DEFAULT_SIGNATURE_SCHEME,
+    };
+    use crypto::{compute_client_id, generate_keypair, generate_nonce, hash_leaf, sign_message};
+    use ed25519_dalek::SigningKey;
+    use merkle_tree::{decode_hash, encode_hash, MerkleProof, MerkleTree, ProofNode};
+    use std::sync::Arc;
+    use storage::filesystem::FilesystemStorage;
+    use storage::SqliteStorage;
+
+    const BATCH_ID: &str = "round-trip";
+    const BOUNDARY: &str = "vs-test-boundary";
+
+    // Signed messages are assembled here from the documented wire format rather than
+    // the handlers' own builders, so a change to either side fails these tests
+
+    fn upload_signature(
+        signing_key: &SigningKey,
+        filename: &str,
+        file_hash: &str,
+        ts: u64,
+        nonce: &[u8],
+    ) -> String {
+        let mut message = b"upload\0".to_vec();
+        for field in [filename, BATCH_ID, file_hash] {
+            message.extend_from_slice(field.as_bytes());
+            message.push(0);
+        }
+        message.extend_from_slice(&ts.to_be_bytes());
+        message.extend_from_slice(&(nonce.len() as u32).to_be_bytes());
+        message.extend_from_slice(nonce);
+        message.extend_from_slice(&32u32.to_be_bytes());
+        message.extend_from_slice(signing_key.verifying_key().as_bytes());
+        // Not public, no annotations
+        message.extend_from_slice(&[0, 0]);
+        hex::encode(sign_message(signing_key, &message).to_bytes())
+    }
+
+    fn download_uri(signing_key: &SigningKey, client_id: &str, filename: &str) -> String {
+        let timestamp = get_current_timestamp_ms();
+        let nonce = generate_nonce();
+        let mut message = b"download\0".to_vec();
+        for field in [filename, BATCH_ID] {
+            message.extend_from_slice(field.as_bytes());
+            message.push(0);
+        }
+        message.extend_from_slice(&timestamp.to_be_bytes());
+        message.extend_from_slice(&(nonce.len() as u32).to_be_bytes());
+        message.extend_from_slice(&nonce);
+        // Not a shared read
+        message.push(0);
+        format!(
+            "/download?filename={}&batch_id={}&signature={}&timestamp={}&nonce={}&client_id={}",
+            filename,
+            BATCH_ID,
+            hex::encode(sign_message(signing_key, &message).to_bytes()),
+            timestamp,
+            hex::encode(nonce),
+            client_id
+        )
+    }
+
+    /// Multipart body as the client sends it
+    fn multipart_upload(signing_key: &SigningKey, filename: &str, content: &[u8]) -> Vec<u8> {
+        let file_hash = hex::encode(hash_leaf(content));
+        let timestamp = get_current_timestamp_ms();
+        let nonce = generate_nonce();
+        let fields = [
+            ("filename", filename.to_string()),
+            ("batch_id", BATCH_ID.to_string()),
+            ("file_hash", file_hash.clone()),
+            (
+                "signature",
+                upload_signature(signing_key, filename, &file_hash, timestamp, &nonce),
+            ),
+            ("timestamp", timestamp.to_string()),
+            ("nonce", hex::encode(nonce)),
+            (
+                "public_key",
+                hex::encode(signing_key.verifying_key().as_bytes()),
+            ),
+        ];
+
+        let mut body = Vec::new();
+        for (name, value) in fields {
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                    BOUNDARY, name, value
+                )
+                .as_bytes(),
+            );
+        }
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+                 Content-Type: application/octet-stream\r\n\r\n",
+                BOUNDARY, filename
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(content);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    #[actix_web::test]
+    async fn test_upload_download_verify_round_trip() {
+        let (state, _dir) = test_state();
+        check_round_trip(state).await;
+    }
+
+    #[actix_web::test]
+    async fn test_upload_download_verify_round_trip_on_sqlite() {
+        let dir = TempDataDir::new();
+        let storage = SqliteStorage::new(dir.0.join("data.db")).await.unwrap();
+        check_round_trip(web::Data::new(AppState::new(Arc::new(storage)))).await;
+    }
+
+    /// Upload through both routes, then download each file and check its proof against
+    /// the root the client computes itself
+    async fn check_round_trip(state: web::Data<AppState>) {
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+        let (signing_key, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let health: HealthResponse = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(health.status, "ok");
+
+        // One file through each upload route
+        let req = test::TestRequest::post()
+            .uri("/upload")
+            .insert_header((
+                "content-type",
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            ))
+            .set_payload(multipart_upload(&signing_key, "a.txt", b"alpha"))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let file_hash = hex::encode(hash_leaf(b"beta"));
+        let timestamp = get_current_timestamp_ms();
+        let nonce = generate_nonce();
+        let mut json = UploadRequest {
+            filename: "b.txt".to_string(),
+            batch_id: BATCH_ID.to_string(),
+            file_content: STANDARD.encode(b"beta"),
+            signature: upload_signature(&signing_key, "b.txt", &file_hash, timestamp, &nonce),
+            file_hash,
+            timestamp,
+            nonce: hex::encode(nonce),
+            public_key: hex::encode(verifying_key.as_bytes()),
+            scheme: DEFAULT_SIGNATURE_SCHEME.to_string(),
+            public: false,
+            annotations: None,
+        };
+        let req = test::TestRequest::post()
+            .uri("/upload/json")
+            .set_json(&json)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        // Tampered content no longer matches its signed hash
+        json.file_content = STANDARD.encode(b"gamma");
+        let req = test::TestRequest::post()
+            .uri("/upload/json")
+            .set_json(&json)
+            .to_request();
+        assert!(test::call_service(&app, req)
+            .await
+            .status()
+            .is_client_error());
+
+        // The root the client expects, computed from its own copies of the files
+        let expected_root =
+            MerkleTree::from_leaf_hashes(&[hash_leaf(b"alpha"), hash_leaf(b"beta")])
+                .unwrap()
+                .root_hash();
+
+        for (leaf_index, (filename, content)) in [
+            ("a.txt", b"alpha".as_slice()),
+            ("b.txt", b"beta".as_slice()),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let req = test::TestRequest::get()
+                .uri(&download_uri(&signing_key, &client_id, filename))
+                .to_request();
+            let resp: DownloadResponse = test::call_and_read_body_json(&app, req).await;
+            assert!(resp.check_proof_version().is_ok());
+            let downloaded = STANDARD.decode(&resp.file_content).unwrap();
+            assert_eq!(downloaded, content);
+
+            let proof = MerkleProof {
+                leaf_index,
+                leaf_hash: hash_leaf(&downloaded),
+                path: resp
+                    .merkle_proof
+                    .iter()
+                    .map(|node| ProofNode {
+                        hash: hex::decode(&node.hash).unwrap().try_into().unwrap(),
+                        is_left: node.is_left,
+                    })
+                    .collect(),
+            };
+            assert_eq!(proof.compute_root().unwrap(), expected_root);
+        }
+
+        // Missing files and bad signatures
+        let req = test::TestRequest::get()
+            .uri(&download_uri(&signing_key, &client_id, "missing.txt"))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let (other_key, _) = generate_keypair();
+        let req = test::TestRequest::get()
+            .uri(&download_uri(&other_key, &client_id, "a.txt"))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_deleted_batch_is_not_found() {
+        let (state, _dir) = test_state();
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+        let (signing_key, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+
+        let req = test::TestRequest::post()
+            .uri("/upload")
+            .insert_header((
+                "content-type",
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            ))
+            .set_payload(multipart_upload(&signing_key, "a.txt", b"alpha"))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let delete = |signing_key: &SigningKey| {
+            let timestamp = get_current_timestamp_ms();
+            let nonce = generate_nonce();
+            let mut message = b"delete".to_vec();
+            message.extend_from_slice(BATCH_ID.as_bytes());
+            message.extend_from_slice(&timestamp.to_be_bytes());
+            message.extend_from_slice(&nonce);
+            test::TestRequest::delete()
+                .uri(&format!(
+                    "/batch/{}?signature={}&timestamp={}&nonce={}&client_id={}",
+                    BATCH_ID,
+                    hex::encode(sign_message(signing_key, &message).to_bytes()),
+                    timestamp,
+                    hex::encode(nonce),
+                    client_id
+                ))
+                .to_request()
+        };
+
+        // Only the owner may delete
+        let (other_key, _) = generate_keypair();
+        let resp = test::call_service(&app, delete(&other_key)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = test::call_service(&app, delete(&signing_key)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get()
+            .uri(&download_uri(&signing_key, &client_id, "a.txt"))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let resp = test::call_service(&app, delete(&signing_key)).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_remaining_files_verify_after_file_deletion() {
+        let (state, _dir) = test_state();
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+        let (signing_key, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+
+        for (filename, content) in [
+            ("a.txt", b"alpha"),
+            ("b.txt", b"bravo"),
+            ("c.txt", b"charl"),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/upload")
+                .insert_header((
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                ))
+                .set_payload(multipart_upload(&signing_key, filename, content))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        }
+
+        let delete = |filename: &str| {
+            let timestamp = get_current_timestamp_ms();
+            let nonce = generate_nonce();
+            let mut message = b"deletefile".to_vec();
+            message.extend_from_slice(filename.as_bytes());
+            message.push(0);
+            message.extend_from_slice(BATCH_ID.as_bytes());
+            message.extend_from_slice(&timestamp.to_be_bytes());
+            message.extend_from_slice(&nonce);
+            test::TestRequest::delete()
+                .uri(&format!(
+                    "/file?filename={}&batch_id={}&signature={}&timestamp={}&nonce={}&client_id={}",
+                    filename,
+                    BATCH_ID,
+                    hex::encode(sign_message(&signing_key, &message).to_bytes()),
+                    timestamp,
+                    hex::encode(nonce),
+                    client_id
+                ))
+                .to_request()
+        };
+
+        let resp: DeleteFileResponse = test::call_and_read_body_json(&app, delete("b.txt")).await;
+        let expected_root =
+            MerkleTree::from_leaf_hashes(&[hash_leaf(b"alpha"), hash_leaf(b"charl")])
+                .unwrap()
+                .root_hash();
+        assert_eq!(resp.root_hash, hex::encode(expected_root));
+        assert_eq!(resp.num_files, 2);
+
+        // The remaining files prove against the new root, at their shifted leaf indices
+        for (leaf_index, (filename, content)) in [("a.txt", b"alpha"), ("c.txt", b"charl")]
+            .into_iter()
+            .enumerate()
+        {
+            let req = test::TestRequest::get()
+                .uri(&download_uri(&signing_key, &client_id, filename))
+                .to_request();
+            let resp: DownloadResponse = test::call_and_read_body_json(&app, req).await;
+            let downloaded = STANDARD.decode(&resp.file_content).unwrap();
+            assert_eq!(downloaded, content);
+
+            let proof = MerkleProof {
+                leaf_index,
+                leaf_hash: hash_leaf(&downloaded),
+                path: resp
+                    .merkle_proof
+                    .iter()
+                    .map(|node| ProofNode {
+                        hash: hex::decode(&node.hash).unwrap().try_into().unwrap(),
+                        is_left: node.is_left,
+                    })
+                    .collect(),
+            };
+            assert_eq!(proof.compute_root().unwrap(), expected_root);
+        }
+
+        let req = test::TestRequest::get()
+            .uri(&download_uri(&signing_key, &client_id, "b.txt"))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let resp = test::call_service(&app, delete("b.txt")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_upload_larger_than_default_limit() {
+        let content: Vec<u8> = (0..20 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let (signing_key, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+        let upload = || {
+            test::TestRequest::post()
+                .uri("/upload")
+                .insert_header((
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                ))
+                .set_payload(multipart_upload(&signing_key, "big.bin", &content))
+                .to_request()
+        };
+
+        // The default limit refuses a 20 MB file
+        let (state, _dir) = test_state();
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+        assert_eq!(
+            test::call_service(&app, upload()).await.status(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+
+        // Raised with --max-upload-size, it is streamed to disk and stored
+        let dir = TempDataDir::new();
+        let storage = Arc::new(FilesystemStorage::new(dir.0.clone()));
+        let max_upload_size_bytes = 32 * 1024 * 1024;
+        let state = web::Data::new(
+            AppState::new(storage).with_max_upload_size_bytes(max_upload_size_bytes),
+        );
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .configure(|cfg| configure_with_max_upload_size(cfg, max_upload_size_bytes)),
+        )
+        .await;
+        assert_eq!(
+            test::call_service(&app, upload()).await.status(),
+            StatusCode::OK
+        );
+
+        let stored = state
+            .storage
+            .read_file(&client_id, BATCH_ID, "big.bin")
+            .await
+            .unwrap();
+        assert_eq!(hash_leaf(&stored), hash_leaf(&content));
+    }
+
+    #[actix_web::test]
+    async fn test_truncated_hashes_are_consistent_end_to_end() {
+        let dir = TempDataDir::new();
+        let storage = Arc::new(FilesystemStorage::new(dir.0.clone()));
+        let state = web::Data::new(AppState::new(storage).with_hash_truncation_bytes(Some(20)));
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+        let (signing_key, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+
+        let req = test::TestRequest::get().uri("/capabilities").to_request();
+        let capabilities: CapabilitiesResponse = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(capabilities.hash_truncation_bytes, Some(20));
+
+        let files = [
+            ("a.txt", b"alpha".as_slice()),
+            ("b.txt", b"beta".as_slice()),
+            ("c.txt", b"gamma".as_slice()),
+        ];
+        for (filename, content) in files {
+            let req = test::TestRequest::post()
+                .uri("/upload")
+                .insert_header((
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                ))
+                .set_payload(multipart_upload(&signing_key, filename, content))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        }
+
+        // The client builds its truncated root from full-width leaves of its own copies
+        let leaves: Vec<[u8; 32]> = files
+            .iter()
+            .map(|(_, content)| hash_leaf(content))
+            .collect();
+        let expected_root = MerkleTree::from_leaf_hashes(&leaves)
+            .unwrap()
+            .truncated(20)
+            .unwrap()
+            .root_hash();
+
+        for (leaf_index, (filename, content)) in files.into_iter().enumerate() {
+            let req = test::TestRequest::get()
+                .uri(&download_uri(&signing_key, &client_id, filename))
+                .to_request();
+            let resp: DownloadResponse = test::call_and_read_body_json(&app, req).await;
+            assert!(resp.check_hash_truncation(Some(20)).is_ok());
+            assert!(resp.check_hash_truncation(None).is_err());
+            assert!(resp.merkle_proof.iter().all(|node| node.hash.len() == 40));
+
+            let proof = MerkleProof {
+                leaf_index,
+                leaf_hash: hash_leaf(content),
+                path: resp
+                    .merkle_proof
+                    .iter()
+                    .map(|node| ProofNode {
+                        hash: decode_hash(&node.hash, Some(20)).unwrap(),
+                        is_left: node.is_left,
+                    })
+                    .collect(),
+            };
+            assert_eq!(proof.compute_root_truncated(20).unwrap(), expected_root);
+        }
+
+        // Finalize takes the root at the same width, and rejects a full-width one
+        let leaf_hashes: Vec<String> = leaves.iter().map(hex::encode).collect();
+        let finalize = |root_hash: String| {
+            let timestamp = get_current_timestamp_ms();
+            let nonce = generate_nonce();
+            let mut message = b"finalize".to_vec();
+            message.extend_from_slice(BATCH_ID.as_bytes());
+            for leaf_hash in &leaf_hashes {
+                message.extend_from_slice(leaf_hash.as_bytes());
+            }
+            message.extend_from_slice(root_hash.as_bytes());
+            message.extend_from_slice(&timestamp.to_be_bytes());
+            message.extend_from_slice(&nonce);
+            test::TestRequest::post()
+                .uri(&format!("/batch/{}/finalize", BATCH_ID))
+                .set_json(FinalizeBatchRequest {
+                    leaf_hashes: leaf_hashes.clone(),
+                    root_hash,
+                    signature: hex::encode(sign_message(&signing_key, &message).to_bytes()),
+                    timestamp,
+                    nonce: hex::encode(nonce),
+                    client_id: client_id.clone(),
+                })
+                .to_request()
+        };
+        let full_root = MerkleTree::from_leaf_hashes(&leaves).unwrap().root_hash();
+        let resp = test::call_service(&app, finalize(hex::encode(full_root))).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp = test::call_service(&app, finalize(encode_hash(&expected_root, Some(20)))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn test_metrics_count_uploads_and_signature_failures() {
+        let (state, _dir) = test_state();
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .wrap(actix_web::middleware::from_fn(
+                    crate::metrics::track_request_duration,
+                ))
+                .configure(configure),
+        )
+        .await;
+        let scrape = || async {
+            let resp =
+                test::call_service(&app, test::TestRequest::get().uri("/metrics").to_request())
+                    .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            String::from_utf8(test::read_body(resp).await.to_vec()).unwrap()
+        };
+
+        let before = scrape().await;
+        assert!(!before.contains("uploads_total{result=\"success\"}"));
+        assert!(before.contains("registered_clients 0"));
+
+        let (signing_key, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+        let req = test::TestRequest::post()
+            .uri("/upload")
+            .insert_header((
+                "content-type",
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            ))
+            .set_payload(multipart_upload(&signing_key, "a.txt", b"alpha"))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        // A download signed with the wrong key fails verification
+        let (other_key, _) = generate_keypair();
+        let req = test::TestRequest::get()
+            .uri(&download_uri(&other_key, &client_id, "a.txt"))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let after = scrape().await;
+        assert!(after.contains("uploads_total{result=\"success\"} 1"));
+        assert!(after.contains("downloads_total{result=\"client_error\"} 1"));
+        assert!(after.contains("signature_verification_failures_total 1"));
+        assert!(after.contains("registered_clients 1"));
+        assert!(after.contains("corrupt_files 0"));
+        assert!(after
+            .contains("http_request_duration_seconds_count{method=\"POST\",route=\"/upload\"} 1"));
+    }
+
+    #[actix_web::test]
+    async fn test_unmatched_requests_get_json_errors() {
+        let (state, _dir) = test_state();
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+
+        let req = test::TestRequest::get().uri("/nope").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let body: ApiError = test::read_body_json(resp).await;
+        assert_eq!(body.status, 404);
+        assert_eq!(body.error, "No route for /nope");
+
+        // /health only answers GET and HEAD; parametrized paths are known too
+        for uri in ["/health", "/batch/some-batch/root"] {
+            let req = test::TestRequest::delete().uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+            let body: ApiError = test::read_body_json(resp).await;
+            assert_eq!(body.status, 405);
+            assert_eq!(body.error, format!("Method DELETE not allowed for {}", uri));
+        }
+    }
+}
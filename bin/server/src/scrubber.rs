@@ -0,0 +1,293 @@
+use crypto::hash_leaf;
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use storage::Storage;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+/// Findings of the background scrubber, shared with the metrics endpoint
+#[derive(Debug, Default)]
+pub struct ScrubReport {
+    /// Number of files whose content no longer matches their stored leaf hash
+    corrupt_files: AtomicU64,
+}
+
+impl ScrubReport {
+    pub fn corrupt_files(&self) -> u64 {
+        self.corrupt_files.load(Ordering::Relaxed)
+    }
+}
+
+/// A single file to check, with the leaf hash recorded for it in the batch's Merkle tree
+struct ScrubTarget {
+    client_id: String,
+    batch_id: String,
+    filename: String,
+    leaf_index: usize,
+    expected: [u8; 32],
+}
+
+/// Background integrity scrubber
+/// Walks every batch, recomputes each file's leaf hash and compares it with the
+/// leaf stored in the batch's Merkle tree. At most `files_per_tick` files are
+/// checked per tick so a full pass is spread out instead of causing an I/O storm.
+pub struct Scrubber {
+    storage: Arc<dyn Storage>,
+    report: Arc<ScrubReport>,
+    files_per_tick: usize,
+    queue: VecDeque<ScrubTarget>,
+    corrupt: HashSet<(String, String, String)>,
+}
+
+impl Scrubber {
+    pub fn new(storage: Arc<dyn Storage>, report: Arc<ScrubReport>, files_per_tick: usize) -> Self {
+        Self {
+            storage,
+            report,
+            files_per_tick: files_per_tick.max(1),
+            queue: VecDeque::new(),
+            corrupt: HashSet::new(),
+        }
+    }
+
+    /// Run until `shutdown` flips to true (or its sender is dropped)
+    pub async fn run(mut self, interval: Duration, mut shutdown: watch::Receiver<bool>) {
+        info!(
+            "Scrubber started: interval={:?}, files_per_tick={}",
+            interval, self.files_per_tick
+        );
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    if let Err(e) = self.scrub_tick().await {
+                        error!("Scrub tick failed: {:#}", e);
+                    }
+                }
+                changed = shutdown.changed() => {
+                    if changed.is_err() || *shutdown.borrow() {
+                        break;
+                    }
+                }
+            }
+        }
+        info!("Scrubber stopped");
+    }
+
+    /// Check up to `files_per_tick` files, starting a new pass when the previous one is done
+    pub async fn scrub_tick(&mut self) -> anyhow::Result<()> {
+        if self.queue.is_empty() {
+            self.start_pass().await?;
+        }
+
+        for _ in 0..self.files_per_tick {
+            let Some(target) = self.queue.pop_front() else {
+                break;
+            };
+            self.check(target).await;
+        }
+
+        self.report
+            .corrupt_files
+            .store(self.corrupt.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Queue every file of every batch for checking
+    async fn start_pass(&mut self) -> anyhow::Result<()> {
+        let batches = self.storage.list_batches().await?;
+
+        // Findings for batches that no longer exist are dropped with the new pass
+        let live: HashSet<_> = batches.iter().cloned().collect();
+        self.corrupt.retain(|(client_id, batch_id, _)| {
+            live.contains(&(client_id.clone(), batch_id.clone()))
+        });
+
+        for (client_id, batch_id) in batches {
+            let filenames = self
+                .storage
+                .load_batch_filenames(&client_id, &batch_id)
+                .await?;
+            let Some(tree) = self.storage.load_merkle_tree(&client_id, &batch_id).await? else {
+                warn!(
+                    client_id = ?client_id,
+                    batch_id = ?batch_id,
+                    "Scrub: batch has no Merkle tree, skipping"
+                );
+                continue;
+            };
+            if tree.num_leaves() != filenames.len() {
+                warn!(
+                    client_id = ?client_id,
+                    batch_id = ?batch_id,
+                    "Scrub: Merkle tree has {} leaves but batch has {} files, skipping",
+                    tree.num_leaves(),
+                    filenames.len()
+                );
+                continue;
+            }
+
+            // Filenames are sorted, matching leaf order in the tree
+            for (leaf_index, filename) in filenames.into_iter().enumerate() {
+                if let Some(expected) = tree.leaf_hash(leaf_index) {
+                    self.queue.push_back(ScrubTarget {
+                        client_id: client_id.clone(),
+                        batch_id: batch_id.clone(),
+                        filename,
+                        leaf_index,
+                        expected,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check one file and record the outcome
+    async fn check(&mut self, target: ScrubTarget) {
+        let key = (
+            target.client_id.clone(),
+            target.batch_id.clone(),
+            target.filename.clone(),
+        );
+
+        let matches = match self.matches_stored_leaf(&target, target.expected).await {
+            Ok(true) => true,
+            // The file may have been re-uploaded since the pass started; recheck
+            // against the current tree before reporting corruption
+            Ok(false) => match self.current_leaf(&target).await {
+                Some(current) if current != target.expected => self
+                    .matches_stored_leaf(&target, current)
+                    .await
+                    .unwrap_or(false),
+                _ => false,
+            },
+            Err(e) => {
+                // Deleted or renamed since the pass started: nothing to report
+                if !self
+                    .storage
+                    .file_exists(&target.client_id, &target.batch_id, &target.filename)
+                    .await
+                    .unwrap_or(false)
+                {
+                    self.corrupt.remove(&key);
+                    return;
+                }
+                warn!(
+                    client_id = ?target.client_id,
+                    batch_id = ?target.batch_id,
+                    filename = ?target.filename,
+                    "Scrub: failed to read file: {:#}",
+                    e
+                );
+                false
+            }
+        };
+
+        if matches {
+            self.corrupt.remove(&key);
+        } else {
+            error!(
+                client_id = ?target.client_id,
+                batch_id = ?target.batch_id,
+                filename = ?target.filename,
+                "Scrub: leaf hash mismatch, file may be corrupt"
+            );
+            self.corrupt.insert(key);
+        }
+    }
+
+    async fn matches_stored_leaf(
+        &self,
+        target: &ScrubTarget,
+        expected: [u8; 32],
+    ) -> anyhow::Result<bool> {
+        let content = self
+            .storage
+            .read_file(&target.client_id, &target.batch_id, &target.filename)
+            .await?;
+        Ok(hash_leaf(&content) == expected)
+    }
+
+    /// Leaf hash currently stored for the target, if the tree still has it
+    async fn current_leaf(&self, target: &ScrubTarget) -> Option<[u8; 32]> {
+        let tree = self
+            .storage
+            .load_merkle_tree(&target.client_id, &target.batch_id)
+            .await
+            .ok()??;
+        tree.leaf_hash(target.leaf_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{store_legacy_batch, TempDataDir};
+    use storage::filesystem::FilesystemStorage;
+
+    #[tokio::test]
+    async fn test_scrubber_flags_corrupted_file() {
+        let dir = TempDataDir::new();
+        let storage: Arc<dyn Storage> = Arc::new(FilesystemStorage::new(dir.0.clone()));
+        for (name, content) in [("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")] {
+            storage
+                .store_file_and_update_tree("client", "batch", name, content)
+                .await
+                .unwrap();
+        }
+
+        let report = Arc::new(ScrubReport::default());
+        // Two files per tick, so a pass over three files takes two ticks
+        let mut scrubber = Scrubber::new(storage.clone(), report.clone(), 2);
+
+        scrubber.scrub_tick().await.unwrap();
+        scrubber.scrub_tick().await.unwrap();
+        assert_eq!(report.corrupt_files(), 0);
+
+        // Flip the content on disk behind storage's back
+        std::fs::write(dir.0.join("client").join("batch").join("b.txt"), b"x").unwrap();
+
+        scrubber.scrub_tick().await.unwrap();
+        scrubber.scrub_tick().await.unwrap();
+        assert_eq!(report.corrupt_files(), 1);
+        assert!(scrubber
+            .corrupt
+            .contains(&("client".into(), "batch".into(), "b.txt".into())));
+
+        // Restoring the content clears the finding on the next pass
+        std::fs::write(dir.0.join("client").join("batch").join("b.txt"), b"b").unwrap();
+        scrubber.scrub_tick().await.unwrap();
+        scrubber.scrub_tick().await.unwrap();
+        assert_eq!(report.corrupt_files(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_scrubber_accepts_legacy_tree() {
+        let dir = TempDataDir::new();
+        let filesystem = FilesystemStorage::new(dir.0.clone());
+        store_legacy_batch(
+            &dir,
+            &filesystem,
+            "client",
+            "batch",
+            &[("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")],
+        )
+        .await;
+        let storage: Arc<dyn Storage> = Arc::new(filesystem);
+
+        // Only leaf hashes are compared, and a legacy tree's leaves are the files' own
+        let report = Arc::new(ScrubReport::default());
+        let mut scrubber = Scrubber::new(storage.clone(), report.clone(), 3);
+        scrubber.scrub_tick().await.unwrap();
+        assert_eq!(report.corrupt_files(), 0);
+        assert!(storage
+            .load_merkle_tree("client", "batch")
+            .await
+            .unwrap()
+            .unwrap()
+            .duplicates_lone_nodes());
+    }
+}
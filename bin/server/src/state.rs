@@ -1,12 +1,100 @@
+use crate::constants::{
+    DEFAULT_MAX_AGE_SECONDS, DEFAULT_MAX_CLOCK_SKEW_SECONDS, DEFAULT_MAX_UPLOAD_SIZE_BYTES,
+};
+use crate::download_limit::DownloadLimiter;
+use crate::ingest::IngestPipeline;
+use crate::metrics::Metrics;
+use crate::nonce_cache::NonceCache;
+use crate::rate_limit::RateLimiter;
+use crate::scrubber::ScrubReport;
 use std::sync::Arc;
+use std::time::Duration;
 
 /// Server application state
 pub struct AppState {
     pub storage: Arc<dyn storage::Storage>,
+    pub scrub_report: Arc<ScrubReport>,
+    /// Most files a batch may hold for the server to generate proofs from it
+    pub max_proof_batch_files: Option<usize>,
+    /// Width Merkle node hashes are cut to in proofs and roots (full width when `None`)
+    pub hash_truncation_bytes: Option<usize>,
+    /// Worker pool uploads are stored through; handlers store inline when `None`
+    pub ingest: Option<IngestPipeline>,
+    /// Cap on each client's in-flight downloads (unlimited when `None`)
+    pub download_limiter: Option<DownloadLimiter>,
+    /// Per-client limit on authenticated upload and download rate (unlimited when `None`)
+    pub rate_limiter: Option<RateLimiter>,
+    /// Serve the `/debug` endpoints; they answer 404 otherwise
+    pub debug_endpoints: bool,
+    /// Largest file an upload or batch replacement may carry
+    pub max_upload_size_bytes: usize,
+    /// Nonces of signed uploads and downloads already accepted
+    pub nonces: NonceCache,
+    /// Request counters and timings served at `/metrics`
+    pub metrics: Metrics,
 }
 
 impl AppState {
     pub fn new(storage: Arc<dyn storage::Storage>) -> Self {
-        Self { storage }
+        Self {
+            storage,
+            scrub_report: Arc::new(ScrubReport::default()),
+            max_proof_batch_files: None,
+            hash_truncation_bytes: None,
+            ingest: None,
+            download_limiter: None,
+            rate_limiter: None,
+            debug_endpoints: false,
+            max_upload_size_bytes: DEFAULT_MAX_UPLOAD_SIZE_BYTES,
+            // A timestamp up to the clock skew ahead stays valid for the max age after that
+            nonces: NonceCache::new(Duration::from_secs(
+                DEFAULT_MAX_AGE_SECONDS + DEFAULT_MAX_CLOCK_SKEW_SECONDS,
+            )),
+            metrics: Metrics::new(),
+        }
+    }
+
+    /// Refuse proofs for batches holding more than `max` files (unlimited when `None`)
+    pub fn with_max_proof_batch_files(mut self, max: Option<usize>) -> Self {
+        self.max_proof_batch_files = max;
+        self
+    }
+
+    /// Serve proofs and roots from trees with every node cut to `bytes`
+    /// The stored trees stay full width; the truncated view is rebuilt per request
+    pub fn with_hash_truncation_bytes(mut self, bytes: Option<usize>) -> Self {
+        self.hash_truncation_bytes = bytes;
+        self
+    }
+
+    /// Store uploads through `ingest` instead of inside the upload handlers
+    pub fn with_ingest(mut self, ingest: Option<IngestPipeline>) -> Self {
+        self.ingest = ingest;
+        self
+    }
+
+    /// Answer a client's downloads with 429 while `max` of its downloads are in flight
+    pub fn with_max_concurrent_downloads_per_client(mut self, max: Option<usize>) -> Self {
+        self.download_limiter = max.map(DownloadLimiter::new);
+        self
+    }
+
+    /// Answer a client's uploads and downloads with 429 beyond `rps` requests per second,
+    /// allowing bursts of up to `burst` (unlimited when `rps` is `None`)
+    pub fn with_rate_limit(mut self, rps: Option<f64>, burst: usize) -> Self {
+        self.rate_limiter = rps.map(|rps| RateLimiter::new(rps, burst));
+        self
+    }
+
+    /// Reject uploaded files larger than `bytes` with 413 Payload Too Large
+    pub fn with_max_upload_size_bytes(mut self, bytes: usize) -> Self {
+        self.max_upload_size_bytes = bytes;
+        self
+    }
+
+    /// Serve the `/debug` endpoints, which reveal how requests are verified
+    pub fn with_debug_endpoints(mut self, enabled: bool) -> Self {
+        self.debug_endpoints = enabled;
+        self
     }
 }
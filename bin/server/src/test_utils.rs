@@ -0,0 +1,88 @@
+use crate::state::AppState;
+use actix_web::web;
+use crypto::{compute_client_id, generate_keypair, hash_leaf};
+use ed25519_dalek::SigningKey;
+use merkle_tree::MerkleTree;
+use std::path::PathBuf;
+use std::sync::Arc;
+use storage::filesystem::FilesystemStorage;
+use storage::Storage;
+
+/// Temporary data directory removed when dropped
+pub struct TempDataDir(pub PathBuf);
+
+impl TempDataDir {
+    pub fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("vs-server-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("Failed to create temp data dir");
+        Self(dir)
+    }
+}
+
+impl Drop for TempDataDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Canonical encodings of the eight small-order points of Curve25519
+pub const SMALL_ORDER_KEYS: [&str; 8] = [
+    "0100000000000000000000000000000000000000000000000000000000000000",
+    "ecffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff7f",
+    "0000000000000000000000000000000000000000000000000000000000000000",
+    "0000000000000000000000000000000000000000000000000000000000000080",
+    "c7176a703d4dd84fba3c0b760d10670f2a2053fa2c39ccc64ec7fd7792ac037a",
+    "c7176a703d4dd84fba3c0b760d10670f2a2053fa2c39ccc64ec7fd7792ac03fa",
+    "26e8958fc2b227b045c3f489f2ef98f0d5dfac05d3c63339b13802886d53fc05",
+    "26e8958fc2b227b045c3f489f2ef98f0d5dfac05d3c63339b13802886d53fc85",
+];
+
+/// Build app state backed by filesystem storage in a fresh temp directory
+pub fn test_state() -> (web::Data<AppState>, TempDataDir) {
+    let dir = TempDataDir::new();
+    let storage = Arc::new(FilesystemStorage::new(dir.0.clone()));
+    (web::Data::new(AppState::new(storage)), dir)
+}
+
+/// Generate a keypair and register its public key, as a first upload would
+pub async fn register_client(state: &web::Data<AppState>) -> (SigningKey, String) {
+    let (signing_key, verifying_key) = generate_keypair();
+    let client_id = compute_client_id(&verifying_key);
+    state
+        .storage
+        .store_public_key(&client_id, verifying_key.as_bytes())
+        .await
+        .expect("Failed to register test client");
+    (signing_key, client_id)
+}
+
+/// Store `files` (sorted by name) as one batch, then swap its tree for one built before
+/// lone-node promotion, as a server of that version left it. Returns the legacy root
+pub async fn store_legacy_batch(
+    dir: &TempDataDir,
+    storage: &FilesystemStorage,
+    client_id: &str,
+    batch_id: &str,
+    files: &[(&str, &[u8])],
+) -> [u8; 32] {
+    for (filename, content) in files {
+        storage
+            .store_file_and_update_tree(client_id, batch_id, filename, content)
+            .await
+            .expect("Failed to store test file");
+    }
+    let leaves: Vec<[u8; 32]> = files
+        .iter()
+        .map(|(_, content)| hash_leaf(content))
+        .collect();
+    let tree = MerkleTree::from_leaf_hashes_legacy(&leaves).expect("Failed to build legacy tree");
+    std::fs::write(
+        dir.0
+            .join(client_id)
+            .join(batch_id)
+            .join("merkle_tree.json"),
+        serde_json::to_vec(&tree).expect("Failed to serialize legacy tree"),
+    )
+    .expect("Failed to write legacy tree");
+    tree.root_hash()
+}
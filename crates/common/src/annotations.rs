@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+
+/// Free-form key-value metadata attached to a batch (description, source commit, tags)
+/// Annotations are mutable and are NOT covered by the batch's Merkle root: a verified
+/// download proves nothing about them
+pub type Annotations = BTreeMap<String, String>;
+
+/// Maximum number of annotations on a batch
+pub const MAX_ANNOTATIONS: usize = 32;
+
+/// Maximum length of an annotation key in bytes
+pub const MAX_ANNOTATION_KEY_LEN: usize = 64;
+
+/// Maximum length of an annotation value in bytes
+pub const MAX_ANNOTATION_VALUE_LEN: usize = 1024;
+
+/// Maximum combined length of all keys and values in bytes
+pub const MAX_ANNOTATIONS_TOTAL_LEN: usize = 8 * 1024;
+
+/// Check annotations against the size limits
+/// Keys must be non-empty and free of control characters
+pub fn validate_annotations(annotations: &Annotations) -> Result<(), String> {
+    if annotations.len() > MAX_ANNOTATIONS {
+        return Err(format!(
+            "At most {} annotations are allowed, got {}",
+            MAX_ANNOTATIONS,
+            annotations.len()
+        ));
+    }
+
+    let mut total = 0;
+    for (key, value) in annotations {
+        if key.is_empty() || key.len() > MAX_ANNOTATION_KEY_LEN {
+            return Err(format!(
+                "Annotation keys must be between 1 and {} bytes",
+                MAX_ANNOTATION_KEY_LEN
+            ));
+        }
+        if key.chars().any(char::is_control) {
+            return Err("Annotation keys must not contain control characters".to_string());
+        }
+        if value.len() > MAX_ANNOTATION_VALUE_LEN {
+            return Err(format!(
+                "Annotation {} exceeds {} bytes",
+                key, MAX_ANNOTATION_VALUE_LEN
+            ));
+        }
+        total += key.len() + value.len();
+    }
+
+    if total > MAX_ANNOTATIONS_TOTAL_LEN {
+        return Err(format!(
+            "Annotations exceed {} bytes in total",
+            MAX_ANNOTATIONS_TOTAL_LEN
+        ));
+    }
+    Ok(())
+}
+
+/// Canonical encoding of annotations for upload signatures
+/// Keys are sorted, so signer and verifier always produce the same bytes
+pub fn signing_bytes(annotations: &Annotations) -> Vec<u8> {
+    // Serializing a map of strings cannot fail
+    serde_json::to_vec(annotations).expect("Failed to serialize annotations")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn annotations(pairs: &[(&str, &str)]) -> Annotations {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_annotation_limits() {
+        assert!(validate_annotations(&Annotations::new()).is_ok());
+        assert!(validate_annotations(&annotations(&[
+            ("description", "nightly build"),
+            ("commit", "1a2b3c")
+        ]))
+        .is_ok());
+
+        let long_key = "k".repeat(MAX_ANNOTATION_KEY_LEN + 1);
+        let long_value = "v".repeat(MAX_ANNOTATION_VALUE_LEN + 1);
+        for bad in [
+            annotations(&[("", "empty key")]),
+            annotations(&[("line\nbreak", "x")]),
+            annotations(&[(long_key.as_str(), "x")]),
+            annotations(&[("key", long_value.as_str())]),
+            (0..=MAX_ANNOTATIONS)
+                .map(|i| (format!("k{}", i), String::new()))
+                .collect(),
+            (0..16)
+                .map(|i| (format!("k{}", i), "v".repeat(MAX_ANNOTATION_VALUE_LEN)))
+                .collect(),
+        ] {
+            assert!(validate_annotations(&bad).is_err(), "{:?}", bad.keys());
+        }
+    }
+
+    #[test]
+    fn test_signing_bytes_ignore_insertion_order() {
+        let mut a = Annotations::new();
+        a.insert("b".to_string(), "2".to_string());
+        a.insert("a".to_string(), "1".to_string());
+        assert_eq!(signing_bytes(&a), br#"{"a":"1","b":"2"}"#.to_vec());
+    }
+}
@@ -0,0 +1,540 @@
+use crate::annotations::{signing_bytes, Annotations};
+use crate::ReplaceBatchManifest;
+
+/// The upload fields an upload signature covers
+pub struct UploadMessage<'a> {
+    pub filename: &'a str,
+    pub batch_id: &'a str,
+    /// hex-encoded leaf hash of the bytes sent
+    pub file_hash: &'a str,
+    pub timestamp: u64,
+    pub nonce: &'a [u8],
+    /// Raw public key bytes of the signer
+    pub public_key: &'a [u8],
+    pub public: bool,
+    pub annotations: Option<&'a Annotations>,
+}
+
+/// Append a NUL-terminated text field
+/// Filenames, batch IDs, client IDs and hex hashes cannot contain null bytes, so the
+/// terminator always marks where the field ends
+fn push_terminated(message: &mut Vec<u8>, field: &str) {
+    message.extend_from_slice(field.as_bytes());
+    message.push(0);
+}
+
+/// Append a binary field prefixed with its length (4 bytes, big-endian)
+fn push_prefixed(message: &mut Vec<u8>, field: &[u8]) {
+    message.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    message.extend_from_slice(field);
+}
+
+/// Message signed for an upload
+/// Signs the file hash rather than the raw bytes; the server checks the hash against the
+/// content, so the content is still covered. The signer's public key bytes are included
+/// so the signature explicitly names the client ID (and storage location) it is for.
+/// Every variable-length field is NUL-terminated or length-prefixed and the public and
+/// annotations flags are one byte each, so no two uploads sign the same bytes
+pub fn upload_message(fields: &UploadMessage) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(b"upload\0");
+    push_terminated(&mut message, fields.filename);
+    push_terminated(&mut message, fields.batch_id);
+    push_terminated(&mut message, fields.file_hash);
+    message.extend_from_slice(&fields.timestamp.to_be_bytes());
+    push_prefixed(&mut message, fields.nonce);
+    push_prefixed(&mut message, fields.public_key);
+    message.push(fields.public as u8);
+    match fields.annotations {
+        Some(annotations) => {
+            message.push(1);
+            push_prefixed(&mut message, &signing_bytes(annotations));
+        }
+        None => message.push(0),
+    }
+    message
+}
+
+/// Message signed for a download
+/// Reads of a shared batch also sign the owner's client ID, after a flag byte, so a
+/// signature for one owner's batch cannot be replayed against another owner's batch of
+/// the same name
+pub fn download_message(
+    filename: &str,
+    batch_id: &str,
+    timestamp: u64,
+    nonce: &[u8],
+    owner: Option<&str>,
+) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(b"download\0");
+    push_terminated(&mut message, filename);
+    push_terminated(&mut message, batch_id);
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    push_prefixed(&mut message, nonce);
+    match owner {
+        Some(owner) => {
+            message.push(1);
+            push_terminated(&mut message, owner);
+        }
+        None => message.push(0),
+    }
+    message
+}
+
+/// Message signed for creating an empty batch
+pub fn create_message(batch_id: &str, timestamp: u64) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(b"create");
+    message.extend_from_slice(batch_id.as_bytes());
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    message
+}
+
+/// Message signed for a batch rename
+/// Prefixed so a download signature can never be replayed as a rename. Batch IDs cannot
+/// contain null bytes, so null-terminating each keeps the pair unambiguous
+pub fn rename_message(
+    old_batch_id: &str,
+    new_batch_id: &str,
+    timestamp: u64,
+    nonce: &[u8],
+) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(b"rename");
+    message.extend_from_slice(old_batch_id.as_bytes());
+    message.push(0);
+    message.extend_from_slice(new_batch_id.as_bytes());
+    message.push(0);
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    message.extend_from_slice(nonce);
+    message
+}
+
+/// Message signed for a batch deletion
+/// Prefixed so a download signature can never be replayed as a delete
+pub fn delete_message(batch_id: &str, timestamp: u64, nonce: &[u8]) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(b"delete");
+    message.extend_from_slice(batch_id.as_bytes());
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    message.extend_from_slice(nonce);
+    message
+}
+
+/// Message signed for deleting one file of a batch
+/// The filename is NUL-terminated so no filename and batch ID pair can sign for another
+pub fn delete_file_message(
+    filename: &str,
+    batch_id: &str,
+    timestamp: u64,
+    nonce: &[u8],
+) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(b"deletefile");
+    message.extend_from_slice(filename.as_bytes());
+    message.push(0);
+    message.extend_from_slice(batch_id.as_bytes());
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    message.extend_from_slice(nonce);
+    message
+}
+
+/// Message signed for a finalize: the batch, every leaf hash in filename order and the
+/// root over them
+pub fn finalize_message(
+    batch_id: &str,
+    leaf_hashes: &[String],
+    root_hash: &str,
+    timestamp: u64,
+    nonce: &[u8],
+) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(b"finalize");
+    message.extend_from_slice(batch_id.as_bytes());
+    for leaf_hash in leaf_hashes {
+        message.extend_from_slice(leaf_hash.as_bytes());
+    }
+    message.extend_from_slice(root_hash.as_bytes());
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    message.extend_from_slice(nonce);
+    message
+}
+
+/// Message signed for replacing a batch's file set: the batch, every file of the new set
+/// with its leaf hash, and the root over them
+/// Filenames cannot contain null bytes, so null-terminating each keeps the list unambiguous.
+/// `nonce` is the manifest's nonce, decoded
+pub fn replace_message(batch_id: &str, manifest: &ReplaceBatchManifest, nonce: &[u8]) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(b"replace");
+    message.extend_from_slice(batch_id.as_bytes());
+    for entry in &manifest.files {
+        message.extend_from_slice(entry.filename.as_bytes());
+        message.push(0);
+        message.extend_from_slice(entry.leaf_hash.as_bytes());
+    }
+    message.extend_from_slice(manifest.root_hash.as_bytes());
+    message.extend_from_slice(&manifest.timestamp.to_be_bytes());
+    message.extend_from_slice(nonce);
+    message
+}
+
+/// Message signed for granting or revoking read access; `action` is "grant" or "revoke"
+/// Prefixed with the action so a grant signature can never be replayed as a revoke
+pub fn access_message(
+    action: &str,
+    batch_id: &str,
+    grantee_id: &str,
+    timestamp: u64,
+    nonce: &[u8],
+) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(action.as_bytes());
+    message.extend_from_slice(batch_id.as_bytes());
+    message.extend_from_slice(grantee_id.as_bytes());
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    message.extend_from_slice(nonce);
+    message
+}
+
+/// Message signed for reading a batch's root
+/// Shared reads also sign the owner's client ID, as for downloads
+pub fn root_message(batch_id: &str, timestamp: u64, owner: Option<&str>) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(b"root");
+    message.extend_from_slice(batch_id.as_bytes());
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    if let Some(owner) = owner {
+        message.extend_from_slice(owner.as_bytes());
+    }
+    message
+}
+
+/// Message signed for reading a batch's Merkle tree
+/// Shared reads also sign the owner's client ID, as for downloads
+pub fn tree_message(batch_id: &str, timestamp: u64, owner: Option<&str>) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(b"tree");
+    message.extend_from_slice(batch_id.as_bytes());
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    if let Some(owner) = owner {
+        message.extend_from_slice(owner.as_bytes());
+    }
+    message
+}
+
+/// Message signed for listing a batch's files
+/// The tag and the batch ID are NUL-terminated, so no batch ID can make this equal the
+/// batch listing message
+pub fn list_message(batch_id: &str, timestamp: u64) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(b"list\0");
+    message.extend_from_slice(batch_id.as_bytes());
+    message.push(0);
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    message
+}
+
+/// Message signed for listing the signer's batches
+pub fn list_batches_message(timestamp: u64) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(b"list-batches\0");
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    message
+}
+
+/// Message signed for fetching several files' proofs at once
+/// Filenames cannot contain null bytes, so null-terminating each keeps the list unambiguous.
+/// Shared reads also sign the owner's client ID, as for downloads
+pub fn proofs_message(
+    batch_id: &str,
+    filenames: &[String],
+    timestamp: u64,
+    owner: Option<&str>,
+) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(b"proofs");
+    message.extend_from_slice(batch_id.as_bytes());
+    for filename in filenames {
+        message.extend_from_slice(filename.as_bytes());
+        message.push(0);
+    }
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    if let Some(owner) = owner {
+        message.extend_from_slice(owner.as_bytes());
+    }
+    message
+}
+
+/// Message signed for a content-addressed read by leaf hash
+pub fn cas_message(leaf_hash_hex: &str, timestamp: u64) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(b"cas");
+    message.extend_from_slice(leaf_hash_hex.as_bytes());
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    message
+}
+
+/// Message the owner signs to commit to a batch's root in an exported bundle
+pub fn bundle_root_message(batch_id: &str, root: &[u8; 32]) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(b"bundle");
+    message.extend_from_slice(batch_id.as_bytes());
+    message.extend_from_slice(root);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BatchFileEntry;
+
+    const TIMESTAMP: u64 = 0x0102_0304_0506_0708;
+    const TIMESTAMP_BYTES: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+    const NONCE: [u8; 16] = [0xaa; 16];
+
+    fn concat(parts: &[&[u8]]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    #[test]
+    fn test_upload_message_layout() {
+        let nonce = [0xaa; 16];
+        let public_key = [0xbb; 32];
+        let mut fields = UploadMessage {
+            filename: "a.txt",
+            batch_id: "batch",
+            file_hash: "00ff",
+            timestamp: TIMESTAMP,
+            nonce: &nonce,
+            public_key: &public_key,
+            public: false,
+            annotations: None,
+        };
+        let base = concat(&[
+            b"upload\0",
+            b"a.txt\0",
+            b"batch\0",
+            b"00ff\0",
+            &TIMESTAMP_BYTES,
+            &[0, 0, 0, 16],
+            &nonce,
+            &[0, 0, 0, 32],
+            &public_key,
+        ]);
+        assert_eq!(upload_message(&fields), concat(&[&base, &[0, 0]]));
+
+        fields.public = true;
+        assert_eq!(upload_message(&fields), concat(&[&base, &[1, 0]]));
+
+        let annotations = Annotations::from([
+            ("z".to_string(), "last".to_string()),
+            ("a".to_string(), "first".to_string()),
+        ]);
+        fields.annotations = Some(&annotations);
+        let json = br#"{"a":"first","z":"last"}"#;
+        assert_eq!(
+            upload_message(&fields),
+            concat(&[&base, &[1, 1], &[0, 0, 0, json.len() as u8], json])
+        );
+    }
+
+    #[test]
+    fn test_upload_message_separates_fields() {
+        let nonce = [0xaa; 16];
+        let public_key = [0xbb; 32];
+        let fields = |filename, batch_id| UploadMessage {
+            filename,
+            batch_id,
+            file_hash: "00ff",
+            timestamp: TIMESTAMP,
+            nonce: &nonce,
+            public_key: &public_key,
+            public: false,
+            annotations: None,
+        };
+        // A signature for "a" in batch "bc" must not also upload "ab" to batch "c"
+        assert_ne!(
+            upload_message(&fields("a", "bc")),
+            upload_message(&fields("ab", "c"))
+        );
+        // Nor can a key ending in the old trailer stand in for the public flag
+        let longer_key = [&public_key[..], b"public"].concat();
+        let mut forged = fields("a", "bc");
+        forged.public_key = &longer_key;
+        let mut public = fields("a", "bc");
+        public.public = true;
+        assert_ne!(upload_message(&forged), upload_message(&public));
+    }
+
+    #[test]
+    fn test_download_message_layout() {
+        let nonce = [0xaa; 16];
+        let own = concat(&[
+            b"download\0",
+            b"a.txt\0",
+            b"batch\0",
+            &TIMESTAMP_BYTES,
+            &[0, 0, 0, 16],
+            &nonce,
+        ]);
+        assert_eq!(
+            download_message("a.txt", "batch", TIMESTAMP, &nonce, None),
+            concat(&[&own, &[0]])
+        );
+        assert_eq!(
+            download_message("a.txt", "batch", TIMESTAMP, &nonce, Some("owner")),
+            concat(&[&own, &[1], b"owner\0"])
+        );
+        assert_ne!(
+            download_message("a", "bc", TIMESTAMP, &nonce, None),
+            download_message("ab", "c", TIMESTAMP, &nonce, None)
+        );
+    }
+
+    #[test]
+    fn test_delete_message_layout() {
+        assert_eq!(
+            delete_message("batch", TIMESTAMP, &NONCE),
+            concat(&[b"delete", b"batch", &TIMESTAMP_BYTES, &NONCE])
+        );
+        assert_eq!(
+            delete_file_message("a.txt", "batch", TIMESTAMP, &NONCE),
+            concat(&[
+                b"deletefile",
+                b"a.txt\0",
+                b"batch",
+                &TIMESTAMP_BYTES,
+                &NONCE
+            ])
+        );
+    }
+
+    #[test]
+    fn test_batch_management_message_layouts() {
+        assert_eq!(
+            create_message("batch", TIMESTAMP),
+            concat(&[b"create", b"batch", &TIMESTAMP_BYTES])
+        );
+        assert_eq!(
+            rename_message("old", "new", TIMESTAMP, &NONCE),
+            concat(&[b"rename", b"old\0", b"new\0", &TIMESTAMP_BYTES, &NONCE])
+        );
+        assert_eq!(
+            access_message("grant", "batch", "grantee", TIMESTAMP, &NONCE),
+            concat(&[b"grant", b"batch", b"grantee", &TIMESTAMP_BYTES, &NONCE])
+        );
+    }
+
+    #[test]
+    fn test_rename_message_separates_batch_ids() {
+        // A signature renaming "a" to "bc" must not also rename "ab" to "c"
+        assert_ne!(
+            rename_message("a", "bc", TIMESTAMP, &NONCE),
+            rename_message("ab", "c", TIMESTAMP, &NONCE)
+        );
+    }
+
+    #[test]
+    fn test_file_set_message_layouts() {
+        let leaf_hashes = vec!["aa".to_string(), "bb".to_string()];
+        assert_eq!(
+            finalize_message("batch", &leaf_hashes, "cc", TIMESTAMP, &NONCE),
+            concat(&[
+                b"finalize",
+                b"batch",
+                b"aa",
+                b"bb",
+                b"cc",
+                &TIMESTAMP_BYTES,
+                &NONCE
+            ])
+        );
+
+        let entry = |filename: &str, leaf_hash: &str| BatchFileEntry {
+            filename: filename.to_string(),
+            leaf_hash: leaf_hash.to_string(),
+        };
+        let manifest = ReplaceBatchManifest {
+            files: vec![entry("a.txt", "aa"), entry("b.txt", "bb")],
+            root_hash: "cc".to_string(),
+            signature: String::new(),
+            timestamp: TIMESTAMP,
+            nonce: hex::encode(NONCE),
+            client_id: String::new(),
+        };
+        assert_eq!(
+            replace_message("batch", &manifest, &NONCE),
+            concat(&[
+                b"replace",
+                b"batch",
+                b"a.txt\0aa",
+                b"b.txt\0bb",
+                b"cc",
+                &TIMESTAMP_BYTES,
+                &NONCE
+            ])
+        );
+    }
+
+    #[test]
+    fn test_read_message_layouts() {
+        let root = concat(&[b"root", b"batch", &TIMESTAMP_BYTES]);
+        assert_eq!(root_message("batch", TIMESTAMP, None), root);
+        assert_eq!(
+            root_message("batch", TIMESTAMP, Some("owner")),
+            concat(&[&root, b"owner"])
+        );
+
+        let tree = concat(&[b"tree", b"batch", &TIMESTAMP_BYTES]);
+        assert_eq!(tree_message("batch", TIMESTAMP, None), tree);
+        assert_eq!(
+            tree_message("batch", TIMESTAMP, Some("owner")),
+            concat(&[&tree, b"owner"])
+        );
+
+        let filenames = vec!["a.txt".to_string(), "b.txt".to_string()];
+        let proofs = concat(&[b"proofs", b"batch", b"a.txt\0b.txt\0", &TIMESTAMP_BYTES]);
+        assert_eq!(proofs_message("batch", &filenames, TIMESTAMP, None), proofs);
+        assert_eq!(
+            proofs_message("batch", &filenames, TIMESTAMP, Some("owner")),
+            concat(&[&proofs, b"owner"])
+        );
+
+        assert_eq!(
+            cas_message("00ff", TIMESTAMP),
+            concat(&[b"cas", b"00ff", &TIMESTAMP_BYTES])
+        );
+    }
+
+    #[test]
+    fn test_listing_message_layouts() {
+        assert_eq!(
+            list_message("batch", TIMESTAMP),
+            concat(&[b"list\0", b"batch\0", &TIMESTAMP_BYTES])
+        );
+        assert_eq!(
+            list_batches_message(TIMESTAMP),
+            concat(&[b"list-batches\0", &TIMESTAMP_BYTES])
+        );
+    }
+
+    #[test]
+    fn test_listing_messages_differ() {
+        // A file listing signature for batch "-batches" must not list the signer's batches
+        assert_ne!(
+            list_message("-batches", TIMESTAMP),
+            list_batches_message(TIMESTAMP)
+        );
+    }
+
+    #[test]
+    fn test_bundle_root_message_layout() {
+        let root = [0xcc; 32];
+        assert_eq!(
+            bundle_root_message("batch", &root),
+            concat(&[b"bundle", b"batch", &root])
+        );
+    }
+}
@@ -1,9 +1,15 @@
 use std::path::Path;
 
+/// Longest filename (and batch ID) accepted, in bytes of UTF-8
+/// Bytes rather than characters: filesystems cap names at 255 bytes, and a name within
+/// 255 bytes also fits the database's `VARCHAR(255)` columns, which count characters
+pub const MAX_FILENAME_BYTES: usize = 255;
+
 /// Error type for filename validation
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FilenameValidationError {
     Empty,
+    TooLong,
     ContainsNullByte,
     ContainsPathSeparator,
     IsSpecialDirectory,
@@ -15,6 +21,7 @@ impl FilenameValidationError {
     pub fn message(&self) -> &'static str {
         match self {
             FilenameValidationError::Empty => "Filename cannot be empty",
+            FilenameValidationError::TooLong => "Filename cannot be longer than 255 bytes",
             FilenameValidationError::ContainsNullByte => "Filename cannot contain null bytes",
             FilenameValidationError::ContainsPathSeparator => {
                 "Filename cannot contain path separators (/ or \\)"
@@ -43,12 +50,17 @@ impl std::error::Error for FilenameValidationError {}
 /// - Filename contains no path separators (/, \)
 /// - Path::new(filename).file_name() returns Some(_)
 /// - Filename is not empty
+/// - Filename is at most `MAX_FILENAME_BYTES` bytes long
 /// - Filename is not "." or ".."
 pub fn validate_filename(filename: &str) -> Result<(), FilenameValidationError> {
     if filename.is_empty() {
         return Err(FilenameValidationError::Empty);
     }
 
+    if filename.len() > MAX_FILENAME_BYTES {
+        return Err(FilenameValidationError::TooLong);
+    }
+
     // Check for null bytes (not allowed in filenames)
     if filename.contains('\0') {
         return Err(FilenameValidationError::ContainsNullByte);
@@ -96,6 +108,21 @@ mod tests {
         assert_eq!(validate_filename(""), Err(FilenameValidationError::Empty));
     }
 
+    #[test]
+    fn test_filename_length_is_counted_in_bytes() {
+        assert!(validate_filename(&"a".repeat(MAX_FILENAME_BYTES)).is_ok());
+        assert_eq!(
+            validate_filename(&"a".repeat(MAX_FILENAME_BYTES + 1)),
+            Err(FilenameValidationError::TooLong)
+        );
+        // 128 two-byte characters: within 255 characters, but 256 bytes
+        assert_eq!(
+            validate_filename(&"é".repeat(128)),
+            Err(FilenameValidationError::TooLong)
+        );
+        assert!(validate_filename(&"é".repeat(127)).is_ok());
+    }
+
     #[test]
     fn test_path_separators() {
         assert_eq!(
@@ -1,18 +1,300 @@
+pub mod annotations;
+pub mod auth_message;
 pub mod file_utils;
 pub mod utils;
 
+use annotations::Annotations;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Request to upload a file as JSON (body of POST /upload/json)
+/// Same fields and signature as the multipart upload, with the content base64-encoded
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadRequest {
+    pub filename: String,     // Original filename
+    pub batch_id: String,     // Batch ID this file belongs to
+    pub file_content: String, // base64-encoded file content
+    pub file_hash: String,    // hex-encoded leaf hash of the file
+    pub signature: String,    // hex-encoded signature
+    pub timestamp: u64,       // Timestamp for replay attack prevention
+    pub nonce: String,        // hex-encoded random 16-byte nonce; the server accepts each once
+    pub public_key: String,   // hex-encoded public key of the signature scheme
+    #[serde(default = "default_signature_scheme")]
+    pub scheme: String, // Signature scheme of the key and signature: "ed25519" (default) or "secp256k1"
+    #[serde(default)]
+    pub public: bool, // Mark the batch as publicly readable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Annotations>, // Replace the batch's annotations (not covered by the root)
+}
+
+/// Manifest of a bulk upload (`manifest` part of POST /upload-batch)
+/// The files follow as `files` parts in manifest order, each named by its filename
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadBatchManifest {
+    pub batch_id: String,             // Batch ID the files belong to
+    pub public_key: String,           // hex-encoded Ed25519 public key
+    pub files: Vec<UploadBatchEntry>, // One entry per uploaded file
+}
+
+/// One file of a bulk upload, signed exactly like a single upload of it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadBatchEntry {
+    pub filename: String,  // Original filename
+    pub file_hash: String, // hex-encoded leaf hash of the file
+    pub signature: String, // hex-encoded signature
+    pub timestamp: u64,    // Timestamp for replay attack prevention
+    pub nonce: String,     // hex-encoded random 16-byte nonce; the server accepts each once
+}
+
+/// Upload fields to preview the signed message for (JSON body of POST /debug/sign-preview)
+/// Same fields as `UploadRequest` without the content and the signature
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignPreviewRequest {
+    pub filename: String,   // Original filename
+    pub batch_id: String,   // Batch ID this file belongs to
+    pub file_hash: String,  // hex-encoded leaf hash of the file
+    pub timestamp: u64,     // Timestamp the upload is signed with
+    pub nonce: String,      // hex-encoded nonce the upload is signed with
+    pub public_key: String, // hex-encoded Ed25519 public key
+    #[serde(default)]
+    pub public: bool, // Mark the batch as publicly readable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Annotations>, // Replace the batch's annotations (not covered by the root)
+}
+
+/// The exact bytes the server verifies an upload signature against
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SignPreviewResponse {
+    pub message_hex: String, // hex-encoded message the client passes to its signing function
+    pub signed_bytes_hex: String, // hex-encoded bytes the Ed25519 signature covers: the domain prefix followed by the message
+}
+
+/// An upload queued for storage by a server running with `--async-uploads`
+/// (body of a 202 Accepted answer to POST /upload and POST /upload/json)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UploadAcceptedResponse {
+    pub job_id: String,     // Random ID of the queued upload
+    pub status_url: String, // Path to poll for completion: /upload/status/{job_id}
+}
+
+/// Where a queued upload stands (response of GET /upload/status/{job_id})
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UploadStatusResponse {
+    pub job_id: String,
+    pub status: String, // "queued", "stored" or "failed"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // Why storing failed, for "failed" uploads
+}
 
 /// Request to download a file from the server (query parameters)
+/// Signature and timestamp may be omitted together to read a public batch anonymously
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct DownloadRequest {
-    pub filename: String,  // Original filename
-    pub batch_id: String,  // Batch ID this file belongs to
+    pub filename: String,                     // Original filename
+    pub batch_id: String,                     // Batch ID this file belongs to
+    pub signature: Option<String>,            // hex-encoded signature (absent for anonymous reads)
+    pub timestamp: Option<u64>,               // Timestamp for replay attack prevention
+    pub nonce: Option<String>, // hex-encoded random 16-byte nonce of a signed read; the server accepts each once
+    pub client_id: String,     // Client ID (SHA256 hash of public key) of the batch owner
+    pub requester_id: Option<String>, // Signer's client ID when reading a batch shared by its owner
+    pub requester_public_key: Option<String>, // hex-encoded signer public key; registers a requester that never uploaded
+    #[serde(default = "default_signature_scheme")]
+    pub scheme: String, // Signature scheme of requester_public_key: "ed25519" (default) or "secp256k1"
+    #[serde(default)]
+    pub include_timestamp: bool, // Include when the file was stored in the response
+}
+
+/// Signature scheme requests are signed with unless they name another
+pub const DEFAULT_SIGNATURE_SCHEME: &str = "ed25519";
+
+fn default_signature_scheme() -> String {
+    DEFAULT_SIGNATURE_SCHEME.to_string()
+}
+
+/// Request to create an empty batch (JSON body of POST /batch)
+/// Carries the public key like an upload, so creating a batch can be a client's first request
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CreateBatchRequest {
+    pub batch_id: String,   // Batch ID to create
+    pub signature: String,  // hex-encoded signature
+    pub timestamp: u64,     // Timestamp for replay attack prevention
+    pub public_key: String, // hex-encoded public key
+}
+
+/// Request to rename a batch (JSON body of POST /batch/{batch_id}/rename)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RenameBatchRequest {
+    pub new_batch_id: String, // Batch ID to rename to
+    pub signature: String,    // hex-encoded signature
+    pub timestamp: u64,       // Timestamp for replay attack prevention
+    pub nonce: String,        // hex-encoded random 16-byte nonce; the server accepts each once
+    pub client_id: String,    // Client ID (SHA256 hash of public key) for O(1) key lookup
+}
+
+/// Request to delete a batch (query parameters of DELETE /batch/{batch_id})
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeleteBatchRequest {
+    pub signature: String, // hex-encoded signature
+    pub timestamp: u64,    // Timestamp for replay attack prevention
+    pub nonce: String,     // hex-encoded random 16-byte nonce; the server accepts each once
+    pub client_id: String, // Client ID (SHA256 hash of public key) for O(1) key lookup
+}
+
+/// Request to delete one file from a batch (query parameters of DELETE /file)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeleteFileRequest {
+    pub filename: String,  // Filename to delete
+    pub batch_id: String,  // Batch ID the file belongs to
+    pub signature: String, // hex-encoded signature
+    pub timestamp: u64,    // Timestamp for replay attack prevention
+    pub nonce: String,     // hex-encoded random 16-byte nonce; the server accepts each once
+    pub client_id: String, // Client ID (SHA256 hash of public key) for O(1) key lookup
+}
+
+/// A batch's Merkle root after one of its files was deleted (response of DELETE /file)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DeleteFileResponse {
+    pub batch_id: String,
+    pub root_hash: String, // hex-encoded new Merkle root; empty if no files are left
+    pub num_files: usize,  // Number of files left in the batch
+}
+
+/// Request to grant or revoke another client's read access to a batch
+/// (JSON body of POST /batch/{batch_id}/grant and POST /batch/{batch_id}/revoke)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BatchAccessRequest {
+    pub grantee_id: String, // Client ID being granted or losing read access
+    pub signature: String,  // hex-encoded signature
+    pub timestamp: u64,     // Timestamp for replay attack prevention
+    pub nonce: String,      // hex-encoded random 16-byte nonce; the server accepts each once
+    pub client_id: String,  // Client ID of the batch owner, for O(1) key lookup
+}
+
+/// Request to list a batch's files (query parameters of GET /batch/{batch_id}/files)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BatchFilesRequest {
+    pub signature: String, // hex-encoded signature
+    pub timestamp: u64,    // Timestamp for replay attack prevention
+    pub client_id: String, // Client ID (SHA256 hash of public key) for O(1) key lookup
+}
+
+/// Owner's signed commitment to the complete file set of a batch, sent after its last upload
+/// (JSON body of POST /batch/{batch_id}/finalize)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FinalizeBatchRequest {
+    pub leaf_hashes: Vec<String>, // hex-encoded leaf hashes of every file, in filename order
+    pub root_hash: String,        // hex-encoded Merkle root over leaf_hashes
+    pub signature: String,        // hex-encoded signature
+    pub timestamp: u64,           // Timestamp for replay attack prevention
+    pub nonce: String,            // hex-encoded random 16-byte nonce; the server accepts each once
+    pub client_id: String,        // Client ID (SHA256 hash of public key) for O(1) key lookup
+}
+
+/// Owner's signed description of a batch's new file set
+/// (`manifest` field of the multipart POST /batch/{batch_id}/replace)
+/// Files whose content changed or is new are sent as `files` parts named by filename;
+/// listed files that are not sent are kept as stored, and unlisted files are removed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReplaceBatchManifest {
+    pub files: Vec<BatchFileEntry>, // Every file of the new set, in filename order
+    pub root_hash: String,          // hex-encoded Merkle root over the files' leaf hashes
+    pub signature: String,          // hex-encoded signature
+    pub timestamp: u64,             // Timestamp for replay attack prevention
+    pub nonce: String, // hex-encoded random 16-byte nonce; the server accepts each once
+    pub client_id: String, // Client ID (SHA256 hash of public key) for O(1) key lookup
+}
+
+/// Request for a batch's Merkle root or tree (query parameters of GET /batch/{batch_id}/root
+/// and GET /batch/{batch_id}/tree)
+/// Authorized like a download: signed by the owner or a grantee, or anonymous for public batches
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BatchRootRequest {
+    pub signature: Option<String>, // hex-encoded signature (absent for anonymous reads)
+    pub timestamp: Option<u64>,    // Timestamp for replay attack prevention
+    pub client_id: String,         // Client ID (SHA256 hash of public key) of the batch owner
+    pub requester_id: Option<String>, // Signer's client ID when reading a batch shared by its owner
+    pub requester_public_key: Option<String>, // hex-encoded signer public key; registers a requester that never uploaded
+}
+
+/// A batch's Merkle root as currently stored by the server
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BatchRootResponse {
+    pub batch_id: String,
+    pub root_hash: String,      // hex-encoded Merkle root
+    pub num_files: usize,       // Number of leaves in the tree
+    pub hash_algorithm: String, // Hash algorithm the server built the tree with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub committed_root: Option<String>, // hex-encoded root of the owner's last finalize, if any
+}
+
+/// Every level of a batch's stored Merkle tree, for auditors cross-checking it
+/// against their own implementation (response of GET /batch/{batch_id}/tree)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BatchTreeResponse {
+    pub batch_id: String,
+    pub root_hash: String,        // hex-encoded Merkle root
+    pub hash_algorithm: String,   // Hash algorithm the server built the tree with
+    pub levels: Vec<Vec<String>>, // hex-encoded node hashes, from the leaves up to the root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_truncation_bytes: Option<usize>, // Width every node is cut to, if truncated
+}
+
+/// A file in a batch listing with the leaf hash the server committed to
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BatchFileEntry {
+    pub filename: String,  // Filename within the batch
+    pub leaf_hash: String, // hex-encoded leaf hash of the stored (possibly encrypted) content
+}
+
+/// Request to list the signer's own batches (query parameters of GET /batches)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ListBatchesRequest {
     pub signature: String, // hex-encoded signature
     pub timestamp: u64,    // Timestamp for replay attack prevention
     pub client_id: String, // Client ID (SHA256 hash of public key) for O(1) key lookup
 }
 
+/// A client's batch IDs, sorted (response of GET /batches)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ListBatchesResponse {
+    pub batches: Vec<String>,
+}
+
+/// Detailed batch listing: files in tree order with their leaf hashes
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BatchFilesResponse {
+    pub batch_id: String,
+    pub root_hash: String, // hex-encoded Merkle root; empty for a batch with no files
+    pub public: bool,      // Public batches are stored unencrypted
+    pub files: Vec<BatchFileEntry>,
+    /// Key-value annotations; mutable metadata outside the Merkle commitment
+    #[serde(default)]
+    pub annotations: Annotations,
+}
+
+/// Request to read content by its leaf hash (query parameters of GET /cas/{leaf_hash})
+/// Signature and timestamp may be omitted together to search public batches only
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CasRequest {
+    pub signature: Option<String>, // hex-encoded signature (absent for anonymous reads)
+    pub timestamp: Option<u64>,    // Timestamp for replay attack prevention
+    pub client_id: Option<String>, // Signer's client ID (required with a signature)
+}
+
+/// Content found by leaf hash, with where it is stored and its proof in that batch
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CasResponse {
+    pub client_id: String,    // Owner of the batch the content was served from
+    pub batch_id: String,     // Batch the content was served from
+    pub filename: String,     // Filename within that batch
+    pub file_content: String, // base64-encoded file content
+    pub merkle_proof: Vec<ProofNodeJson>,
+    pub hash_algorithm: String, // Hash algorithm the server built the tree with
+    pub proof_version: u32,     // Proof semantics of `merkle_proof`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_truncation_bytes: Option<usize>, // Width every node is cut to, if truncated
+}
+
 /// Download response containing file data and Merkle proof
 /// Note: Server returns file content and proof - not the root hash
 /// Client computes file hash from content and verifies proof against stored root hash
@@ -21,8 +303,165 @@ pub struct DownloadResponse {
     pub filename: String,     // Original filename
     pub file_content: String, // base64-encoded file content
     pub merkle_proof: Vec<ProofNodeJson>,
+    #[serde(default)]
+    pub hash_algorithm: Option<String>, // Hash algorithm the server built the tree with
+    /// Proof semantics of `merkle_proof`; responses from older servers omit it and use version 1
+    #[serde(default = "default_proof_version")]
+    pub proof_version: u32,
+    /// When the file's current content was stored (milliseconds since the Unix epoch);
+    /// only present when the download asked for it with `include_timestamp`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stored_at: Option<u64>,
+    /// Width in bytes every node hash of the proof is cut to; absent for full-width hashes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_truncation_bytes: Option<usize>,
+}
+
+/// Request for several files' proofs without their content (JSON body of POST /proofs)
+/// Authorized like a download: signed by the owner or a grantee, or unsigned for public batches
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProofsRequest {
+    pub batch_id: String,                     // Batch ID the files belong to
+    pub filenames: Vec<String>,               // Files to prove
+    pub signature: Option<String>,            // hex-encoded signature (absent for anonymous reads)
+    pub timestamp: Option<u64>,               // Timestamp for replay attack prevention
+    pub client_id: String, // Client ID (SHA256 hash of public key) of the batch owner
+    pub requester_id: Option<String>, // Signer's client ID when reading a batch shared by its owner
+    pub requester_public_key: Option<String>, // hex-encoded signer public key; registers a requester that never uploaded
+}
+
+/// One file's proof in a `ProofsResponse`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FileProofJson {
+    pub leaf_index: usize, // Position of the file in the batch's sorted filenames
+    pub file_hash: String, // hex-encoded leaf hash, at the served width
+    pub merkle_proof: Vec<ProofNodeJson>,
+}
+
+/// Proofs of the requested files, all from the same tree
+/// Files not in the batch are listed in `missing` instead of failing the whole request
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProofsResponse {
+    pub batch_id: String,
+    pub proofs: BTreeMap<String, FileProofJson>,
+    pub missing: Vec<String>,
+    pub hash_algorithm: String, // Hash algorithm the server built the tree with
+    pub proof_version: u32,     // Proof format version, see PROOF_FORMAT_VERSION
+    /// Width in bytes every node hash is cut to; absent for full-width hashes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_truncation_bytes: Option<usize>,
+}
+
+/// Version of the proof format described by `DownloadResponse::merkle_proof`:
+/// a leaf-to-root path of sibling hashes, with an odd last node paired with itself
+pub const PROOF_FORMAT_VERSION: u32 = 1;
+
+fn default_proof_version() -> u32 {
+    1
+}
+
+impl DownloadResponse {
+    /// Ensure the proof uses a format this build knows how to verify
+    pub fn check_proof_version(&self) -> Result<(), UnsupportedProofVersion> {
+        if self.proof_version != PROOF_FORMAT_VERSION {
+            return Err(UnsupportedProofVersion {
+                version: self.proof_version,
+                supported: PROOF_FORMAT_VERSION,
+            });
+        }
+        Ok(())
+    }
+
+    /// Ensure the server built the proof with the hash algorithm the client expects
+    /// Responses without an algorithm (older servers) are accepted as-is
+    pub fn check_hash_algorithm(&self, expected: &str) -> Result<(), HashAlgorithmMismatch> {
+        match &self.hash_algorithm {
+            Some(actual) if actual != expected => Err(HashAlgorithmMismatch {
+                expected: expected.to_string(),
+                actual: actual.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ensure the server cut node hashes to the width the client verifies with
+    /// Unlike the algorithm, a missing value is meaningful: it means full-width hashes
+    pub fn check_hash_truncation(
+        &self,
+        expected: Option<usize>,
+    ) -> Result<(), HashTruncationMismatch> {
+        if self.hash_truncation_bytes != expected {
+            return Err(HashTruncationMismatch {
+                expected,
+                actual: self.hash_truncation_bytes,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Error returned when a response uses a proof format this build cannot verify
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedProofVersion {
+    pub version: u32,
+    pub supported: u32,
+}
+
+impl std::fmt::Display for UnsupportedProofVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Unsupported proof version {} (this client supports version {}); upgrade the client",
+            self.version, self.supported
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedProofVersion {}
+
+/// Error returned when client and server disagree on the Merkle hash algorithm
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashAlgorithmMismatch {
+    pub expected: String,
+    pub actual: String,
+}
+
+impl std::fmt::Display for HashAlgorithmMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Hash algorithm mismatch: expected {}, server used {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for HashAlgorithmMismatch {}
+
+/// Error returned when client and server disagree on the node hash truncation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashTruncationMismatch {
+    pub expected: Option<usize>,
+    pub actual: Option<usize>,
+}
+
+impl std::fmt::Display for HashTruncationMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let describe = |bytes: Option<usize>| match bytes {
+            Some(bytes) => format!("{}-byte", bytes),
+            None => "full-width".to_string(),
+        };
+        write!(
+            f,
+            "Hash truncation mismatch: expected {} hashes, server used {} hashes; set --hash-truncation-bytes to match the server",
+            describe(self.expected),
+            describe(self.actual)
+        )
+    }
 }
 
+impl std::error::Error for HashTruncationMismatch {}
+
 /// JSON representation of a Merkle proof node
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct ProofNodeJson {
@@ -30,8 +469,97 @@ pub struct ProofNodeJson {
     pub is_left: bool,
 }
 
+/// Error body for requests that match no route (404) or use a method the path does
+/// not accept (405)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ApiError {
+    pub status: u16,   // HTTP status code, repeated for clients that only see the body
+    pub error: String, // Human-readable description
+}
+
 /// Response from health check endpoint
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct HealthResponse {
     pub status: String, // "ok" when healthy
 }
+
+/// What the server's proofs are built with (response of GET /capabilities), so a
+/// client can configure itself before trusting a root
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CapabilitiesResponse {
+    pub hash_algorithm: String, // Hash algorithm of leaf and node hashes
+    pub proof_version: u32,     // Proof format version, see PROOF_FORMAT_VERSION
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_truncation_bytes: Option<usize>, // Width every node is cut to, if truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(hash_algorithm: Option<&str>) -> DownloadResponse {
+        DownloadResponse {
+            filename: "file.txt".to_string(),
+            file_content: String::new(),
+            merkle_proof: vec![],
+            hash_algorithm: hash_algorithm.map(|s| s.to_string()),
+            proof_version: PROOF_FORMAT_VERSION,
+            stored_at: None,
+            hash_truncation_bytes: None,
+        }
+    }
+
+    #[test]
+    fn test_hash_algorithm_match() {
+        assert!(response(Some("sha256"))
+            .check_hash_algorithm("sha256")
+            .is_ok());
+        // Older servers don't report an algorithm
+        assert!(response(None).check_hash_algorithm("sha256").is_ok());
+    }
+
+    #[test]
+    fn test_hash_algorithm_mismatch() {
+        let err = response(Some("blake3"))
+            .check_hash_algorithm("sha256")
+            .unwrap_err();
+        assert_eq!(err.actual, "blake3");
+        assert_eq!(
+            err.to_string(),
+            "Hash algorithm mismatch: expected sha256, server used blake3"
+        );
+    }
+
+    #[test]
+    fn test_proof_version() {
+        assert!(response(None).check_proof_version().is_ok());
+
+        // Responses from servers predating the field parse as version 1
+        let json = r#"{"filename":"file.txt","file_content":"","merkle_proof":[]}"#;
+        let old: DownloadResponse = serde_json::from_str(json).unwrap();
+        assert_eq!(old.proof_version, 1);
+        assert!(old.check_proof_version().is_ok());
+
+        let mut future = response(None);
+        future.proof_version = PROOF_FORMAT_VERSION + 1;
+        let err = future.check_proof_version().unwrap_err();
+        assert_eq!(err.version, PROOF_FORMAT_VERSION + 1);
+        assert!(err.to_string().contains("Unsupported proof version"));
+    }
+
+    #[test]
+    fn test_hash_truncation() {
+        assert!(response(None).check_hash_truncation(None).is_ok());
+
+        let mut truncated = response(None);
+        truncated.hash_truncation_bytes = Some(20);
+        assert!(truncated.check_hash_truncation(Some(20)).is_ok());
+        // Either side assuming a different width can't verify the other's proofs
+        let err = truncated.check_hash_truncation(None).unwrap_err();
+        assert_eq!(err.actual, Some(20));
+        assert!(err.to_string().starts_with(
+            "Hash truncation mismatch: expected full-width hashes, server used 20-byte hashes"
+        ));
+        assert!(response(None).check_hash_truncation(Some(20)).is_err());
+    }
+}
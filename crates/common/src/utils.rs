@@ -6,3 +6,7 @@ pub fn get_current_timestamp_ms() -> u64 {
         .unwrap()
         .as_millis() as u64
 }
+
+/// Response header carrying the server's current time in milliseconds since Unix epoch,
+/// sent when a request timestamp is rejected so clients can detect clock skew
+pub const SERVER_TIME_HEADER: &str = "X-Server-Time";
@@ -0,0 +1,379 @@
+use anyhow::{Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::XChaCha20Poly1305;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+#[allow(deprecated)]
+// generic-array 0.14 API is deprecated but required by chacha20poly1305 0.10
+use generic_array::GenericArray;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Length of a keypair in bytes: 32-byte secret key followed by 32-byte public key
+const KEYPAIR_LEN: usize = 64;
+
+/// First word of a passphrase-protected key file, followed by the format version
+const ENCRYPTED_KEY_MAGIC: &str = "VS-ENCRYPTED-KEY";
+
+/// Header line of the current passphrase-protected format; bound to the ciphertext
+const ENCRYPTED_KEY_HEADER: &str = "VS-ENCRYPTED-KEY v1";
+
+/// Argon2id cost of format v1 (the OWASP minimum: 19 MiB, 2 passes, 1 lane)
+/// Fixed per version, so a crate upgrade changing Argon2 defaults cannot lock a file out
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+const ARGON2_ITERATIONS: u32 = 2;
+const ARGON2_PARALLELISM: u32 = 1;
+
+/// Lengths of the random salt and XChaCha20-Poly1305 nonce stored in the key file
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// On-disk encoding of a keypair file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyFormat {
+    /// Hex of secret || public (128 characters), the original format
+    #[default]
+    Hex,
+    /// Standard base64 of secret || public (88 characters with padding)
+    Base64,
+    /// `{ "secret": "<hex>", "public": "<hex>" }`
+    Json,
+}
+
+impl KeyFormat {
+    /// Detect the format of key file content
+    /// JSON is recognized by its opening brace. Exactly 128 hex digits is hex: base64 of
+    /// a 64-byte keypair is at most 88 characters, so a 128-character string that also
+    /// happens to be valid base64 could never decode to a keypair. Anything else is base64.
+    pub fn detect(content: &str) -> Self {
+        let content = content.trim();
+        if content.starts_with('{') {
+            Self::Json
+        } else if content.len() == KEYPAIR_LEN * 2 && content.bytes().all(|b| b.is_ascii_hexdigit())
+        {
+            Self::Hex
+        } else {
+            Self::Base64
+        }
+    }
+}
+
+impl FromStr for KeyFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "hex" => Ok(Self::Hex),
+            "base64" => Ok(Self::Base64),
+            "json" => Ok(Self::Json),
+            _ => anyhow::bail!(
+                "Invalid key format: {}. Must be 'hex', 'base64' or 'json'",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for KeyFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Hex => "hex",
+            Self::Base64 => "base64",
+            Self::Json => "json",
+        })
+    }
+}
+
+/// JSON key file layout
+#[derive(Serialize, Deserialize)]
+struct JsonKeyFile {
+    secret: String,
+    public: String,
+}
+
+/// Encode a keypair as key file content
+pub fn encode_keypair(signing_key: &SigningKey, format: KeyFormat) -> String {
+    let verifying_key = signing_key.verifying_key();
+    let mut key_bytes = Vec::with_capacity(KEYPAIR_LEN);
+    key_bytes.extend_from_slice(signing_key.as_bytes());
+    key_bytes.extend_from_slice(verifying_key.as_bytes());
+
+    match format {
+        KeyFormat::Hex => hex::encode(key_bytes),
+        KeyFormat::Base64 => STANDARD.encode(key_bytes),
+        KeyFormat::Json => {
+            let json = JsonKeyFile {
+                secret: hex::encode(signing_key.as_bytes()),
+                public: hex::encode(verifying_key.as_bytes()),
+            };
+            // Serializing two strings cannot fail
+            serde_json::to_string_pretty(&json).expect("Failed to serialize key file")
+        }
+    }
+}
+
+/// Decode key file content in any supported format (auto-detected)
+/// The stored public key must match the one derived from the secret key
+pub fn decode_keypair(content: &str) -> Result<SigningKey> {
+    let content = content.trim();
+    anyhow::ensure!(
+        !is_encrypted_keypair(content),
+        "Key file is passphrase-protected and needs its passphrase to load"
+    );
+    let (secret, public) = match KeyFormat::detect(content) {
+        KeyFormat::Json => {
+            let json: JsonKeyFile =
+                serde_json::from_str(content).context("Failed to parse JSON key file")?;
+            (
+                decode_hex_key(&json.secret, "secret")?,
+                decode_hex_key(&json.public, "public")?,
+            )
+        }
+        format @ (KeyFormat::Hex | KeyFormat::Base64) => {
+            let key_bytes = if format == KeyFormat::Hex {
+                hex::decode(content).context("Failed to decode key file")?
+            } else {
+                STANDARD
+                    .decode(content)
+                    .or_else(|_| STANDARD_NO_PAD.decode(content))
+                    .context("Failed to decode key file: not hex, base64 or JSON")?
+            };
+            if key_bytes.len() != KEYPAIR_LEN {
+                anyhow::bail!(
+                    "Invalid key file format. Expected {} bytes, got {}",
+                    KEYPAIR_LEN,
+                    key_bytes.len()
+                );
+            }
+            let mut secret = [0u8; 32];
+            let mut public = [0u8; 32];
+            secret.copy_from_slice(&key_bytes[..32]);
+            public.copy_from_slice(&key_bytes[32..]);
+            (secret, public)
+        }
+    };
+
+    keypair_from_parts(&secret, &public)
+}
+
+/// Rebuild a signing key from its secret, checking it against the stored public key
+fn keypair_from_parts(secret: &[u8; 32], public: &[u8; 32]) -> Result<SigningKey> {
+    let signing_key = SigningKey::from_bytes(secret);
+    let stored_public = VerifyingKey::from_bytes(public)
+        .map_err(|e| anyhow::anyhow!("Invalid public key in key file: {}", e))?;
+    anyhow::ensure!(
+        signing_key.verifying_key() == stored_public,
+        "Key file public key does not match its secret key"
+    );
+    Ok(signing_key)
+}
+
+/// Whether key file content is passphrase-protected rather than a plaintext format
+pub fn is_encrypted_keypair(content: &str) -> bool {
+    content.trim_start().starts_with(ENCRYPTED_KEY_MAGIC)
+}
+
+/// Encrypt a keypair under a passphrase as key file content
+/// The file is a version header line followed by base64 of salt || nonce || ciphertext;
+/// the key is derived with Argon2id and the keypair sealed with XChaCha20-Poly1305
+#[allow(deprecated)] // generic-array 0.14 API is deprecated but required by chacha20poly1305 0.10
+pub fn encrypt_keypair(signing_key: &SigningKey, passphrase: &str) -> Result<String> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let mut key_bytes = Vec::with_capacity(KEYPAIR_LEN);
+    key_bytes.extend_from_slice(signing_key.as_bytes());
+    key_bytes.extend_from_slice(signing_key.verifying_key().as_bytes());
+
+    let cipher = passphrase_cipher(passphrase, &salt)?;
+    let ciphertext = cipher
+        .encrypt(
+            GenericArray::from_slice(&nonce),
+            Payload {
+                msg: &key_bytes,
+                aad: ENCRYPTED_KEY_HEADER.as_bytes(),
+            },
+        )
+        .map_err(|e| anyhow::anyhow!("Failed to encrypt keypair: {}", e))?;
+
+    let mut body = Vec::with_capacity(SALT_LEN + NONCE_LEN + ciphertext.len());
+    body.extend_from_slice(&salt);
+    body.extend_from_slice(&nonce);
+    body.extend_from_slice(&ciphertext);
+    Ok(format!(
+        "{}\n{}\n",
+        ENCRYPTED_KEY_HEADER,
+        STANDARD.encode(body)
+    ))
+}
+
+/// Decrypt passphrase-protected key file content
+/// A wrong passphrase and a tampered file fail alike, as the AEAD tag does not verify
+#[allow(deprecated)] // generic-array 0.14 API is deprecated but required by chacha20poly1305 0.10
+pub fn decrypt_keypair(content: &str, passphrase: &str) -> Result<SigningKey> {
+    let mut lines = content.trim().lines();
+    let header = lines.next().unwrap_or_default().trim();
+    if header != ENCRYPTED_KEY_HEADER {
+        anyhow::ensure!(
+            is_encrypted_keypair(header),
+            "Key file is not passphrase-protected"
+        );
+        anyhow::bail!("Unsupported encrypted key file version: {:?}", header);
+    }
+    let body = STANDARD
+        .decode(lines.collect::<String>().trim())
+        .context("Failed to decode encrypted key file")?;
+    anyhow::ensure!(
+        body.len() > SALT_LEN + NONCE_LEN,
+        "Encrypted key file is truncated"
+    );
+    let (salt, rest) = body.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    let cipher = passphrase_cipher(passphrase, salt)?;
+    let key_bytes = cipher
+        .decrypt(
+            GenericArray::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: ENCRYPTED_KEY_HEADER.as_bytes(),
+            },
+        )
+        .map_err(|_| {
+            anyhow::anyhow!("Failed to decrypt key file: wrong passphrase or corrupted file")
+        })?;
+    anyhow::ensure!(
+        key_bytes.len() == KEYPAIR_LEN,
+        "Invalid encrypted key file. Expected {} bytes, got {}",
+        KEYPAIR_LEN,
+        key_bytes.len()
+    );
+    let secret: [u8; 32] = key_bytes[..32].try_into().expect("Slice is 32 bytes");
+    let public: [u8; 32] = key_bytes[32..].try_into().expect("Slice is 32 bytes");
+    keypair_from_parts(&secret, &public)
+}
+
+/// Cipher keyed by Argon2id over the passphrase and salt
+fn passphrase_cipher(passphrase: &str, salt: &[u8]) -> Result<XChaCha20Poly1305> {
+    let params = Params::new(
+        ARGON2_MEMORY_KIB,
+        ARGON2_ITERATIONS,
+        ARGON2_PARALLELISM,
+        Some(32),
+    )
+    .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Failed to derive key from passphrase: {}", e))?;
+    XChaCha20Poly1305::new_from_slice(&key)
+        .map_err(|e| anyhow::anyhow!("Failed to create cipher: {}", e))
+}
+
+/// Decode a 32-byte hex field of a JSON key file
+fn decode_hex_key(value: &str, field: &str) -> Result<[u8; 32]> {
+    let bytes = hex::decode(value.trim())
+        .with_context(|| format!("Failed to decode {} key in key file", field))?;
+    bytes.try_into().map_err(|bytes: Vec<u8>| {
+        anyhow::anyhow!(
+            "Invalid {} key in key file. Expected 32 bytes, got {}",
+            field,
+            bytes.len()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_keypair;
+
+    #[test]
+    fn test_key_file_round_trip_in_every_format() {
+        let (signing_key, _) = generate_keypair();
+        for format in [KeyFormat::Hex, KeyFormat::Base64, KeyFormat::Json] {
+            let content = encode_keypair(&signing_key, format);
+            assert_eq!(KeyFormat::detect(&content), format);
+            let decoded = decode_keypair(&content).unwrap();
+            assert_eq!(decoded.to_bytes(), signing_key.to_bytes(), "{}", format);
+            assert_eq!(format.to_string().parse::<KeyFormat>().unwrap(), format);
+        }
+
+        // Unpadded base64 and surrounding whitespace are accepted too
+        let unpadded = format!(
+            "  {}\n",
+            encode_keypair(&signing_key, KeyFormat::Base64).trim_end_matches('=')
+        );
+        assert_eq!(
+            decode_keypair(&unpadded).unwrap().to_bytes(),
+            signing_key.to_bytes()
+        );
+    }
+
+    #[test]
+    fn test_key_file_detection_prefers_hex_for_128_hex_digits() {
+        // All-digit content is valid hex and valid base64 alike
+        let ambiguous = "0".repeat(128);
+        assert_eq!(KeyFormat::detect(&ambiguous), KeyFormat::Hex);
+        // Hex of a 32-byte key is reported as a bad length, not misread
+        let (signing_key, _) = generate_keypair();
+        let short = hex::encode(signing_key.as_bytes());
+        assert!(decode_keypair(&short).is_err());
+    }
+
+    #[test]
+    fn test_key_file_with_mismatched_public_key_rejected() {
+        let (signing_key, _) = generate_keypair();
+        let (_, other_public) = generate_keypair();
+        let mut content = hex::encode(signing_key.as_bytes());
+        content.push_str(&hex::encode(other_public.as_bytes()));
+        let err = decode_keypair(&content).unwrap_err();
+        assert!(err.to_string().contains("does not match"));
+    }
+
+    #[test]
+    fn test_encrypted_key_file_round_trip() {
+        let (signing_key, _) = generate_keypair();
+        let content = encrypt_keypair(&signing_key, "correct horse").unwrap();
+
+        assert!(is_encrypted_keypair(&content));
+        assert!(!is_encrypted_keypair(&encode_keypair(
+            &signing_key,
+            KeyFormat::Hex
+        )));
+        // The secret never appears in the file
+        assert!(!content.contains(&hex::encode(signing_key.as_bytes())));
+
+        let decoded = decrypt_keypair(&content, "correct horse").unwrap();
+        assert_eq!(decoded.to_bytes(), signing_key.to_bytes());
+
+        // Fresh salt and nonce for every encryption
+        assert_ne!(
+            encrypt_keypair(&signing_key, "correct horse").unwrap(),
+            content
+        );
+    }
+
+    #[test]
+    fn test_encrypted_key_file_rejects_wrong_passphrase_and_unknown_version() {
+        let (signing_key, _) = generate_keypair();
+        let content = encrypt_keypair(&signing_key, "correct horse").unwrap();
+
+        let err = decrypt_keypair(&content, "wrong horse").unwrap_err();
+        assert!(err.to_string().contains("wrong passphrase"));
+
+        let future = content.replacen("v1", "v2", 1);
+        let err = decrypt_keypair(&future, "correct horse").unwrap_err();
+        assert!(err.to_string().contains("Unsupported"));
+
+        let legacy = encode_keypair(&signing_key, KeyFormat::Hex);
+        assert!(decrypt_keypair(&legacy, "correct horse").is_err());
+    }
+}
@@ -3,14 +3,47 @@ use aes_gcm::{
     Aes256Gcm,
 };
 use anyhow::{Context, Result};
+use chacha20poly1305::XChaCha20Poly1305;
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 #[allow(deprecated)] // generic-array 0.14 API is deprecated but required by aes-gcm 0.10
 use generic_array::{typenum::U12, GenericArray};
 use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
 use rand::rngs::OsRng;
+use rand::RngCore;
 use sha2::{Digest, Sha256};
 use std::fs;
+use std::io::Read;
 use std::path::Path;
+use subtle::ConstantTimeEq;
+
+pub mod key_file;
+pub mod scheme;
+
+pub use key_file::{
+    decode_keypair, decrypt_keypair, encode_keypair, encrypt_keypair, is_encrypted_keypair,
+    KeyFormat,
+};
+pub use scheme::{PublicKey, SignatureScheme};
+
+/// Domain string prepended to every signed message, scoping signatures to this
+/// application and version of the signed-message formats
+pub const SIGNATURE_DOMAIN: &[u8] = b"verifiable-storage/v1:";
+
+/// Chunk size used when streaming data into a hasher
+const HASH_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Length in bytes of the random nonce signed uploads and downloads carry
+pub const NONCE_BYTES: usize = 16;
+
+/// Length in bytes of the AES-GCM nonce prefixed to encrypted file content
+pub const FILE_NONCE_BYTES: usize = 12;
+
+/// Length in bytes of the XChaCha20-Poly1305 nonce prefixed to content encrypted at rest
+pub const AT_REST_NONCE_BYTES: usize = 24;
+
+/// Words in a generated recovery phrase (24 words encode 256 bits of entropy)
+pub const MNEMONIC_WORDS: usize = 24;
 
 /// Generate a new key pair
 pub fn generate_keypair() -> (SigningKey, VerifyingKey) {
@@ -20,30 +53,55 @@ pub fn generate_keypair() -> (SigningKey, VerifyingKey) {
     (signing_key, verifying_key)
 }
 
+/// Generate a random BIP39 recovery phrase (English wordlist)
+pub fn generate_mnemonic() -> String {
+    bip39::Mnemonic::generate(MNEMONIC_WORDS)
+        .expect("24 is a valid BIP39 word count")
+        .to_string()
+}
+
+/// Derive a key pair deterministically from a BIP39 recovery phrase
+/// The phrase's checksum is verified, then its BIP39 seed (PBKDF2 over phrase and
+/// passphrase) is expanded with HKDF into the 32-byte Ed25519 secret key, so the same
+/// phrase and passphrase always yield the same key and client ID
+pub fn keypair_from_mnemonic(phrase: &str, passphrase: &str) -> Result<(SigningKey, VerifyingKey)> {
+    let mnemonic = bip39::Mnemonic::parse(phrase.trim())
+        .map_err(|e| anyhow::anyhow!("Invalid recovery phrase: {}", e))?;
+    let seed = mnemonic.to_seed(passphrase);
+
+    let hk = Hkdf::<Sha256>::new(None, &seed);
+    let mut secret = [0u8; 32];
+    hk.expand(b"verifiable-storage-signing-key", &mut secret)
+        .expect("HKDF expansion failed");
+    let signing_key = SigningKey::from_bytes(&secret);
+    let verifying_key = signing_key.verifying_key();
+    Ok((signing_key, verifying_key))
+}
+
+/// Generate a random nonce for a signed request
+/// The server accepts each nonce once, so a captured request cannot be replayed
+pub fn generate_nonce() -> [u8; NONCE_BYTES] {
+    let mut nonce = [0u8; NONCE_BYTES];
+    OsRng.fill_bytes(&mut nonce);
+    nonce
+}
+
 /// Compute Client ID from public key: SHA256(public_key)
 pub fn compute_client_id(public_key: &VerifyingKey) -> String {
-    hex::encode(Sha256::digest(public_key.as_bytes()))
+    compute_client_id_from_bytes(public_key.as_bytes())
+}
+
+/// Compute Client ID from raw public key bytes, as stored by the server
+pub fn compute_client_id_from_bytes(public_key: &[u8]) -> String {
+    hex::encode(Sha256::digest(public_key))
 }
 
 /// Load or generate keypair from file
+/// Existing key files may be in any supported `KeyFormat`; new ones are written as hex
 pub fn load_or_generate_keypair(key_file: &Path) -> Result<(SigningKey, VerifyingKey, String)> {
     if key_file.exists() {
         let key_data = fs::read_to_string(key_file).context("Failed to read key file")?;
-
-        // Parse as hex-encoded key (64 bytes: 32-byte secret + 32-byte public)
-        let key_bytes = hex::decode(key_data.trim()).context("Failed to decode key file")?;
-
-        if key_bytes.len() != 64 {
-            anyhow::bail!(
-                "Invalid key file format. Expected 64 bytes, got {}",
-                key_bytes.len()
-            );
-        }
-
-        // Extract secret key (first 32 bytes) and reconstruct keypair
-        let mut secret_key_bytes = [0u8; 32];
-        secret_key_bytes.copy_from_slice(&key_bytes[0..32]);
-        let signing_key = SigningKey::from_bytes(&secret_key_bytes);
+        let signing_key = decode_keypair(&key_data)?;
         let verifying_key = signing_key.verifying_key();
 
         let client_id = compute_client_id(&verifying_key);
@@ -53,37 +111,87 @@ pub fn load_or_generate_keypair(key_file: &Path) -> Result<(SigningKey, Verifyin
         let (signing_key, verifying_key) = generate_keypair();
         let client_id = compute_client_id(&verifying_key);
 
-        // Store secret key + public key = 64 bytes
-        let mut key_data = Vec::with_capacity(64);
-        key_data.extend_from_slice(signing_key.as_bytes());
-        key_data.extend_from_slice(verifying_key.as_bytes());
-
         if let Some(parent) = key_file.parent() {
             fs::create_dir_all(parent).context("Failed to create key directory")?;
         }
 
-        fs::write(key_file, hex::encode(key_data)).context("Failed to write key file")?;
+        fs::write(key_file, encode_keypair(&signing_key, KeyFormat::Hex))
+            .context("Failed to write key file")?;
 
         Ok((signing_key, verifying_key, client_id))
     }
 }
 
+/// Write a keypair to `key_file` protected by a passphrase (see `encrypt_keypair`)
+pub fn save_encrypted_keypair(
+    key_file: &Path,
+    signing_key: &SigningKey,
+    passphrase: &str,
+) -> Result<()> {
+    let content = encrypt_keypair(signing_key, passphrase)?;
+    if let Some(parent) = key_file.parent() {
+        fs::create_dir_all(parent).context("Failed to create key directory")?;
+    }
+    fs::write(key_file, content).context("Failed to write key file")
+}
+
+/// Read a passphrase-protected keypair written by `save_encrypted_keypair`
+pub fn load_encrypted_keypair(key_file: &Path, passphrase: &str) -> Result<SigningKey> {
+    let content = fs::read_to_string(key_file).context("Failed to read key file")?;
+    decrypt_keypair(&content, passphrase)
+}
+
+/// Prefix a message with the signature domain
+fn domain_separated(message: &[u8]) -> Vec<u8> {
+    let mut scoped = Vec::with_capacity(SIGNATURE_DOMAIN.len() + message.len());
+    scoped.extend_from_slice(SIGNATURE_DOMAIN);
+    scoped.extend_from_slice(message);
+    scoped
+}
+
 /// Sign a message with the signing key
+/// The signature covers `SIGNATURE_DOMAIN || message`, so it is not valid for the
+/// same bytes signed by another application
 pub fn sign_message(signing_key: &SigningKey, message: &[u8]) -> Signature {
-    signing_key.sign(message)
+    signing_key.sign(&domain_separated(message))
 }
 
-/// Verify a signature
+/// Verify a signature made by `sign_message`
 pub fn verify_signature(
     verifying_key: &VerifyingKey,
     message: &[u8],
     signature: &Signature,
 ) -> Result<()> {
     verifying_key
-        .verify(message, signature)
+        .verify(&domain_separated(message), signature)
         .map_err(|e| anyhow::anyhow!("Signature verification failed: {}", e))
 }
 
+/// Verify many signatures made by `sign_message` at once
+/// Much faster than verifying each one with `verify_signature`, but all-or-nothing: an
+/// error does not say which signature failed
+pub fn verify_batch(items: &[(&VerifyingKey, &[u8], &Signature)]) -> Result<()> {
+    let messages: Vec<Vec<u8>> = items
+        .iter()
+        .map(|(_, message, _)| domain_separated(message))
+        .collect();
+    let messages: Vec<&[u8]> = messages.iter().map(Vec::as_slice).collect();
+    let signatures: Vec<Signature> = items.iter().map(|(_, _, signature)| **signature).collect();
+    let verifying_keys: Vec<VerifyingKey> = items.iter().map(|(key, _, _)| **key).collect();
+    ed25519_dalek::verify_batch(&messages, &signatures, &verifying_keys)
+        .map_err(|e| anyhow::anyhow!("Batch signature verification failed: {}", e))
+}
+
+/// Compare two byte strings in constant time
+/// Use this for auth tokens and other shared secrets (admin, bearer and share tokens,
+/// idempotency keys) and for identity bindings checked during authentication, so the
+/// comparison does not leak how long a matching prefix was. Only the lengths can leak,
+/// which is fine for fixed-length values. Hashes, roots and proofs are public and may be
+/// compared with `==`
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
 /// Deserialize public key from bytes
 pub fn public_key_from_bytes(bytes: &[u8]) -> Result<VerifyingKey> {
     let array: [u8; 32] = bytes
@@ -101,57 +209,107 @@ pub fn hash_leaf(data: &[u8]) -> [u8; 32] {
         .into()
 }
 
-/// Derive encryption key from Ed25519 signing key using HKDF
+/// Compute the leaf hash of data read from a stream, chunk by chunk
+/// Produces the same hash as `hash_leaf` without holding the whole content in memory
+pub fn hash_leaf_reader<R: Read>(mut reader: R) -> std::io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    hasher.update([0x00]); // Domain separation prefix for leaves
+
+    let mut buffer = vec![0u8; HASH_CHUNK_SIZE];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher.finalize().into())
+}
+
+/// Derive a key from the Ed25519 signing key using HKDF, scoped by `info`
 /// Uses a fixed salt to ensure deterministic key derivation
-fn derive_encryption_key(signing_key: &SigningKey) -> [u8; 32] {
+fn derive_key(signing_key: &SigningKey, info: &[u8]) -> [u8; 32] {
     let hk = Hkdf::<Sha256>::new(None, signing_key.as_bytes());
     // output key material
     let mut okm = [0u8; 32];
     // expand the key material with domain separation prefix
-    hk.expand(b"verifiable-storage-encryption-key", &mut okm)
-        .expect("HKDF expansion failed");
+    hk.expand(info, &mut okm).expect("HKDF expansion failed");
     okm
 }
 
-/// Derive a deterministic nonce for file encryption
-/// Uses filename and batch_id to ensure unique nonce per file
+/// Derive the nonce for encrypting one version of a file
+/// A keyed hash of filename, batch ID and content: the same content always encrypts to
+/// the same ciphertext, which leaf hashes of unchanged files rely on, while different
+/// content under the same name never shares a nonce. The nonce is stored in the clear,
+/// so it is keyed to reveal nothing about the content.
+#[allow(deprecated)] // generic-array 0.14 API is deprecated but required by aes-gcm 0.10
+fn derive_nonce(
+    signing_key: &SigningKey,
+    filename: &str,
+    batch_id: &str,
+    plaintext: &[u8],
+) -> GenericArray<u8, U12> {
+    let key = derive_key(signing_key, b"verifiable-storage-nonce-key");
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key).expect("HMAC accepts any key length");
+    mac.update(filename.as_bytes());
+    mac.update(&[0]);
+    mac.update(batch_id.as_bytes());
+    mac.update(&[0]);
+    mac.update(plaintext);
+    let tag = mac.finalize().into_bytes();
+
+    let nonce_bytes: [u8; FILE_NONCE_BYTES] = tag[..FILE_NONCE_BYTES]
+        .try_into()
+        .expect("Tag slice is 12 bytes");
+    nonce_bytes.into()
+}
+
+/// Nonce of content encrypted before nonces were prefixed: derived from filename and
+/// batch ID alone, so only ever used to decrypt
 #[allow(deprecated)] // generic-array 0.14 API is deprecated but required by aes-gcm 0.10
-fn derive_nonce(filename: &str, batch_id: &str) -> GenericArray<u8, U12> {
+fn derive_legacy_nonce(filename: &str, batch_id: &str) -> GenericArray<u8, U12> {
     let mut hasher = Sha256::new();
     hasher.update(b"verifiable-storage-nonce");
     hasher.update(filename.as_bytes());
     hasher.update(batch_id.as_bytes());
     let hash = hasher.finalize();
 
-    // Use first 12 bytes of hash as nonce (AES-GCM requires 12-byte nonce)
-    // Convert [u8; 12] to GenericArray using TryInto (non-deprecated path)
-    let nonce_bytes: [u8; 12] = hash[..12].try_into().expect("Hash slice is 12 bytes");
+    let nonce_bytes: [u8; FILE_NONCE_BYTES] = hash[..FILE_NONCE_BYTES]
+        .try_into()
+        .expect("Hash slice is 12 bytes");
     nonce_bytes.into()
 }
 
+fn file_cipher(signing_key: &SigningKey) -> Result<Aes256Gcm> {
+    let key = derive_key(signing_key, b"verifiable-storage-encryption-key");
+    Aes256Gcm::new_from_slice(&key).map_err(|e| anyhow::anyhow!("Failed to create cipher: {}", e))
+}
+
 /// Encrypt file content using AES-256-GCM
-/// Derives encryption key from Ed25519 signing key
-/// Uses deterministic nonce based on filename and batch_id
-#[allow(deprecated)] // generic-array 0.14 API is deprecated but required by aes-gcm 0.10
+/// Derives encryption key from Ed25519 signing key. The nonce (see `derive_nonce`)
+/// is prefixed to the ciphertext.
 pub fn encrypt_file(
     signing_key: &SigningKey,
     filename: &str,
     batch_id: &str,
     plaintext: &[u8],
 ) -> Result<Vec<u8>> {
-    let key = derive_encryption_key(signing_key);
-    let cipher = Aes256Gcm::new_from_slice(&key)
-        .map_err(|e| anyhow::anyhow!("Failed to create cipher: {}", e))?;
-    let nonce = derive_nonce(filename, batch_id);
+    let cipher = file_cipher(signing_key)?;
+    let nonce = derive_nonce(signing_key, filename, batch_id, plaintext);
 
-    cipher
+    let ciphertext = cipher
         .encrypt(&nonce, plaintext)
-        .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))
+        .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+    let mut sealed = Vec::with_capacity(FILE_NONCE_BYTES + ciphertext.len());
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
 }
 
-/// Decrypt file content using AES-256-GCM
-/// Derives encryption key from Ed25519 signing key
-/// Uses deterministic nonce based on filename and batch_id
+/// Decrypt file content produced by [`encrypt_file`]
+/// Content encrypted before nonces were prefixed is still read, with the nonce
+/// derived from filename and batch ID; authentication tells the two apart.
 #[allow(deprecated)] // generic-array 0.14 API is deprecated but required by aes-gcm 0.10
 pub fn decrypt_file(
     signing_key: &SigningKey,
@@ -159,12 +317,241 @@ pub fn decrypt_file(
     batch_id: &str,
     ciphertext: &[u8],
 ) -> Result<Vec<u8>> {
-    let key = derive_encryption_key(signing_key);
-    let cipher = Aes256Gcm::new_from_slice(&key)
+    let cipher = file_cipher(signing_key)?;
+    if ciphertext.len() >= FILE_NONCE_BYTES {
+        let (nonce, sealed) = ciphertext.split_at(FILE_NONCE_BYTES);
+        if let Ok(plaintext) = cipher.decrypt(GenericArray::from_slice(nonce), sealed) {
+            return Ok(plaintext);
+        }
+    }
+
+    cipher
+        .decrypt(&derive_legacy_nonce(filename, batch_id), ciphertext)
+        .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))
+}
+
+/// Encrypt content for storage at rest using XChaCha20-Poly1305
+/// A random nonce is generated per call and prefixed to the ciphertext
+#[allow(deprecated)] // generic-array 0.14 API is deprecated but required by chacha20poly1305 0.10
+pub fn encrypt_at_rest(key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = XChaCha20Poly1305::new_from_slice(key)
+        .map_err(|e| anyhow::anyhow!("Failed to create cipher: {}", e))?;
+    let mut nonce = [0u8; AT_REST_NONCE_BYTES];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = cipher
+        .encrypt(GenericArray::from_slice(&nonce), plaintext)
+        .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+    let mut sealed = Vec::with_capacity(AT_REST_NONCE_BYTES + ciphertext.len());
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Decrypt content produced by [`encrypt_at_rest`]
+/// Fails if the content is truncated, was tampered with or was sealed under another key
+#[allow(deprecated)] // generic-array 0.14 API is deprecated but required by chacha20poly1305 0.10
+pub fn decrypt_at_rest(key: &[u8; 32], sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < AT_REST_NONCE_BYTES {
+        anyhow::bail!("Encrypted content is shorter than its nonce");
+    }
+    let (nonce, ciphertext) = sealed.split_at(AT_REST_NONCE_BYTES);
+    let cipher = XChaCha20Poly1305::new_from_slice(key)
         .map_err(|e| anyhow::anyhow!("Failed to create cipher: {}", e))?;
-    let nonce = derive_nonce(filename, batch_id);
 
     cipher
-        .decrypt(&nonce, ciphertext)
+        .decrypt(GenericArray::from_slice(nonce), ciphertext)
         .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_leaf_reader_matches_hash_leaf() {
+        // Larger than one chunk, and not a multiple of the chunk size
+        let data: Vec<u8> = (0..(HASH_CHUNK_SIZE * 3 + 17))
+            .map(|i| (i % 251) as u8)
+            .collect();
+        assert_eq!(hash_leaf_reader(&data[..]).unwrap(), hash_leaf(&data));
+        assert_eq!(hash_leaf_reader(&[][..]).unwrap(), hash_leaf(&[]));
+    }
+
+    #[test]
+    fn test_client_id_is_sha256_of_public_key() {
+        // The Ed25519 base point, pinned so client and server can never drift apart
+        let key = public_key_from_bytes(
+            &hex::decode("5866666666666666666666666666666666666666666666666666666666666666")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            compute_client_id(&key),
+            "cb05c9fac26332f9abc5f4f50b47e39edab18bd5afa645edefa27f5b527c57d2"
+        );
+
+        let (_, verifying_key) = generate_keypair();
+        assert_eq!(
+            compute_client_id(&verifying_key),
+            hex::encode(Sha256::digest(verifying_key.as_bytes()))
+        );
+    }
+
+    #[test]
+    fn test_verify_batch_rejects_any_invalid_signature() {
+        let keys: Vec<_> = (0..100).map(|_| generate_keypair()).collect();
+        let messages: Vec<Vec<u8>> = (0..100)
+            .map(|i| format!("file{}", i).into_bytes())
+            .collect();
+        let signatures: Vec<Signature> = keys
+            .iter()
+            .zip(&messages)
+            .map(|((signing_key, _), message)| sign_message(signing_key, message))
+            .collect();
+        let mut items: Vec<(&VerifyingKey, &[u8], &Signature)> = keys
+            .iter()
+            .zip(&messages)
+            .zip(&signatures)
+            .map(|(((_, verifying_key), message), signature)| {
+                (verifying_key, message.as_slice(), signature)
+            })
+            .collect();
+        assert!(verify_batch(&items).is_ok());
+
+        // One signature over the wrong message fails the whole batch
+        let (signing_key, verifying_key) = generate_keypair();
+        let wrong = sign_message(&signing_key, b"other");
+        items.push((&verifying_key, b"file100", &wrong));
+        assert!(verify_batch(&items).is_err());
+
+        // As does a plain signature without the domain prefix
+        items.pop();
+        let plain = signing_key.sign(b"file100");
+        items.push((&verifying_key, b"file100", &plain));
+        assert!(verify_batch(&items).is_err());
+    }
+
+    #[test]
+    fn test_signatures_are_domain_separated() {
+        let (signing_key, verifying_key) = generate_keypair();
+        let message = b"a.txtbatch";
+
+        let signature = sign_message(&signing_key, message);
+        assert!(verify_signature(&verifying_key, message, &signature).is_ok());
+        assert!(verify_signature(&verifying_key, b"a.txtbatch2", &signature).is_err());
+
+        // A plain signature over the same bytes is rejected, and ours is not
+        // valid as a plain signature either
+        let plain = signing_key.sign(message);
+        assert!(verify_signature(&verifying_key, message, &plain).is_err());
+        assert!(verifying_key.verify(message, &signature).is_err());
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret-token", b"secret-token"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"secret-token", b"secret-tokeN"));
+        assert!(!constant_time_eq(b"secret-token", b"secret"));
+        assert!(!constant_time_eq(b"", b"x"));
+    }
+
+    #[test]
+    fn test_at_rest_round_trip_and_tamper_detection() {
+        let key = [7u8; 32];
+        let plaintext = b"stored content";
+
+        let sealed = encrypt_at_rest(&key, plaintext).unwrap();
+        assert_eq!(sealed.len(), AT_REST_NONCE_BYTES + plaintext.len() + 16);
+        assert!(!sealed.windows(plaintext.len()).any(|w| w == plaintext));
+        assert_eq!(decrypt_at_rest(&key, &sealed).unwrap(), plaintext);
+
+        // Fresh nonce per call
+        assert_ne!(encrypt_at_rest(&key, plaintext).unwrap(), sealed);
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decrypt_at_rest(&key, &tampered).is_err());
+        assert!(decrypt_at_rest(&[8u8; 32], &sealed).is_err());
+        assert!(decrypt_at_rest(&key, &sealed[..10]).is_err());
+    }
+
+    #[test]
+    #[allow(deprecated)] // generic-array 0.14 API is deprecated but required by aes-gcm 0.10
+    fn test_file_nonce_follows_content() {
+        let (signing_key, _) = generate_keypair();
+        let first = encrypt_file(&signing_key, "a.txt", "batch", b"first version").unwrap();
+        let second = encrypt_file(&signing_key, "a.txt", "batch", b"second version").unwrap();
+
+        // Different content under the same name never reuses a nonce
+        assert_ne!(first[..FILE_NONCE_BYTES], second[..FILE_NONCE_BYTES]);
+        for i in 0..100 {
+            let other =
+                encrypt_file(&signing_key, "a.txt", "batch", format!("v{}", i).as_bytes()).unwrap();
+            assert_ne!(other[..FILE_NONCE_BYTES], first[..FILE_NONCE_BYTES]);
+        }
+        // The same content gives the same ciphertext, so unchanged files keep their leaf
+        assert_eq!(
+            encrypt_file(&signing_key, "a.txt", "batch", b"first version").unwrap(),
+            first
+        );
+        assert_eq!(
+            decrypt_file(&signing_key, "a.txt", "batch", &first).unwrap(),
+            b"first version"
+        );
+        assert_eq!(
+            decrypt_file(&signing_key, "a.txt", "batch", &second).unwrap(),
+            b"second version"
+        );
+
+        let mut tampered = first.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decrypt_file(&signing_key, "a.txt", "batch", &tampered).is_err());
+        let (other_key, _) = generate_keypair();
+        assert!(decrypt_file(&other_key, "a.txt", "batch", &first).is_err());
+
+        // Content encrypted before nonces were prefixed still decrypts
+        let legacy = file_cipher(&signing_key)
+            .unwrap()
+            .encrypt(&derive_legacy_nonce("a.txt", "batch"), &b"old upload"[..])
+            .unwrap();
+        assert_eq!(
+            decrypt_file(&signing_key, "a.txt", "batch", &legacy).unwrap(),
+            b"old upload"
+        );
+    }
+
+    #[test]
+    fn test_mnemonic_derives_the_same_client_id_every_time() {
+        let phrase = generate_mnemonic();
+        assert_eq!(phrase.split_whitespace().count(), MNEMONIC_WORDS);
+
+        let (_, first) = keypair_from_mnemonic(&phrase, "").unwrap();
+        let (_, again) = keypair_from_mnemonic(&format!("  {}\n", phrase), "").unwrap();
+        assert_eq!(compute_client_id(&first), compute_client_id(&again));
+
+        // Pinned, so a dependency upgrade can never silently change recovered identities
+        let (_, pinned) = keypair_from_mnemonic(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            "",
+        )
+        .unwrap();
+        assert_eq!(
+            compute_client_id(&pinned),
+            "1820ca98ee0143862dfc6b672b466d13e4855a5c05572fb99144ebdcb202a61b"
+        );
+
+        // The passphrase and the phrase both select the key
+        let (_, other) = keypair_from_mnemonic(&phrase, "extra").unwrap();
+        assert_ne!(compute_client_id(&first), compute_client_id(&other));
+        assert_ne!(
+            compute_client_id(&first),
+            compute_client_id(&keypair_from_mnemonic(&generate_mnemonic(), "").unwrap().1)
+        );
+
+        // A phrase with a bad checksum is rejected rather than yielding some key
+        assert!(keypair_from_mnemonic(&["abandon"; 12].join(" "), "").is_err());
+        assert!(keypair_from_mnemonic("not a recovery phrase", "").is_err());
+    }
+}
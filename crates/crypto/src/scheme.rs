@@ -0,0 +1,316 @@
+use crate::domain_separated;
+use anyhow::Result;
+use ed25519_dalek::{Signer, Verifier};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+/// Signature scheme a client signs its requests with
+/// Ed25519 is the default and always available; secp256k1 (ECDSA over SHA-256, as used
+/// by Bitcoin and Ethereum tooling) needs the `secp256k1` feature. Both sign
+/// `SIGNATURE_DOMAIN || message` and make 64-byte signatures; the client ID stays the
+/// SHA-256 of the encoded public key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SignatureScheme {
+    /// Ed25519 with 32-byte public keys
+    #[default]
+    Ed25519,
+    /// ECDSA over secp256k1 with 33-byte compressed SEC1 public keys and
+    /// 64-byte `r || s` signatures (low-S only)
+    #[cfg(feature = "secp256k1")]
+    Secp256k1,
+}
+
+/// A public key of any supported scheme
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PublicKey {
+    Ed25519(ed25519_dalek::VerifyingKey),
+    #[cfg(feature = "secp256k1")]
+    Secp256k1(k256::ecdsa::VerifyingKey),
+}
+
+impl SignatureScheme {
+    /// Name of the default scheme, as sent in requests
+    pub const DEFAULT_NAME: &'static str = "ed25519";
+
+    /// Length in bytes of a signature of any supported scheme
+    pub const SIGNATURE_LEN: usize = 64;
+
+    /// Name of the scheme, as sent in requests
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ed25519 => Self::DEFAULT_NAME,
+            #[cfg(feature = "secp256k1")]
+            Self::Secp256k1 => "secp256k1",
+        }
+    }
+
+    /// Length in bytes of an encoded public key
+    pub fn public_key_len(self) -> usize {
+        match self {
+            Self::Ed25519 => 32,
+            #[cfg(feature = "secp256k1")]
+            Self::Secp256k1 => 33,
+        }
+    }
+
+    /// Scheme of an encoded public key, told apart by its length
+    /// Lets the server verify with a registered key without storing its scheme
+    pub fn of_public_key(public_key: &[u8]) -> Result<Self> {
+        match public_key.len() {
+            32 => Ok(Self::Ed25519),
+            #[cfg(feature = "secp256k1")]
+            33 => Ok(Self::Secp256k1),
+            len => anyhow::bail!("No supported signature scheme has {}-byte public keys", len),
+        }
+    }
+
+    /// Parse and validate an encoded public key of this scheme
+    pub fn public_key_from_bytes(self, bytes: &[u8]) -> Result<PublicKey> {
+        anyhow::ensure!(
+            bytes.len() == self.public_key_len(),
+            "Invalid {} public key length: expected {} bytes, got {}",
+            self,
+            self.public_key_len(),
+            bytes.len()
+        );
+        match self {
+            Self::Ed25519 => crate::public_key_from_bytes(bytes).map(PublicKey::Ed25519),
+            #[cfg(feature = "secp256k1")]
+            Self::Secp256k1 => k256::ecdsa::VerifyingKey::from_sec1_bytes(bytes)
+                .map(PublicKey::Secp256k1)
+                .map_err(|e| anyhow::anyhow!("Invalid secp256k1 public key: {}", e)),
+        }
+    }
+
+    /// Public key of a 32-byte secret key of this scheme
+    pub fn public_key_from_secret(self, secret_key: &[u8]) -> Result<PublicKey> {
+        match self {
+            Self::Ed25519 => Ok(PublicKey::Ed25519(
+                ed25519_signing_key(secret_key)?.verifying_key(),
+            )),
+            #[cfg(feature = "secp256k1")]
+            Self::Secp256k1 => Ok(PublicKey::Secp256k1(
+                *secp256k1_signing_key(secret_key)?.verifying_key(),
+            )),
+        }
+    }
+
+    /// Sign a message with a 32-byte secret key of this scheme
+    /// Like `sign_message`, the signature covers `SIGNATURE_DOMAIN || message`
+    pub fn sign(self, secret_key: &[u8], message: &[u8]) -> Result<Vec<u8>> {
+        let message = domain_separated(message);
+        match self {
+            Self::Ed25519 => Ok(ed25519_signing_key(secret_key)?
+                .sign(&message)
+                .to_bytes()
+                .to_vec()),
+            #[cfg(feature = "secp256k1")]
+            Self::Secp256k1 => {
+                let signature: k256::ecdsa::Signature =
+                    secp256k1_signing_key(secret_key)?.sign(&message);
+                Ok(signature.to_bytes().to_vec())
+            }
+        }
+    }
+
+    /// Verify a signature made by `sign` against an encoded public key of this scheme
+    pub fn verify(self, public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<()> {
+        self.public_key_from_bytes(public_key)?
+            .verify(message, signature)
+    }
+
+    /// Client ID of an encoded public key of this scheme: SHA256(public_key)
+    pub fn compute_client_id(self, public_key: &[u8]) -> Result<String> {
+        Ok(self.public_key_from_bytes(public_key)?.client_id())
+    }
+}
+
+impl PublicKey {
+    /// Scheme this key belongs to
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            Self::Ed25519(_) => SignatureScheme::Ed25519,
+            #[cfg(feature = "secp256k1")]
+            Self::Secp256k1(_) => SignatureScheme::Secp256k1,
+        }
+    }
+
+    /// Encoded key, as sent in requests and registered by the server
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Ed25519(key) => key.as_bytes().to_vec(),
+            #[cfg(feature = "secp256k1")]
+            Self::Secp256k1(key) => key.to_encoded_point(true).as_bytes().to_vec(),
+        }
+    }
+
+    /// Client ID of this key: SHA256(encoded key)
+    pub fn client_id(&self) -> String {
+        hex::encode(Sha256::digest(self.to_bytes()))
+    }
+
+    /// Whether this is an Ed25519 small-order point
+    /// Signatures under such a key can be produced without any secret key for some
+    /// messages. secp256k1 has prime order, so parsing already rejects every weak key.
+    pub fn is_weak(&self) -> bool {
+        match self {
+            Self::Ed25519(key) => key.is_weak(),
+            #[cfg(feature = "secp256k1")]
+            Self::Secp256k1(_) => false,
+        }
+    }
+
+    /// Verify a signature over `SIGNATURE_DOMAIN || message`
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<()> {
+        let signature: [u8; SignatureScheme::SIGNATURE_LEN] =
+            signature.try_into().map_err(|_| {
+                anyhow::anyhow!(
+                    "Invalid signature length: expected {} bytes, got {}",
+                    SignatureScheme::SIGNATURE_LEN,
+                    signature.len()
+                )
+            })?;
+        let message = domain_separated(message);
+        match self {
+            Self::Ed25519(key) => key
+                .verify(&message, &ed25519_dalek::Signature::from_bytes(&signature))
+                .map_err(|e| anyhow::anyhow!("Signature verification failed: {}", e)),
+            #[cfg(feature = "secp256k1")]
+            Self::Secp256k1(key) => {
+                let signature = k256::ecdsa::Signature::from_slice(&signature)
+                    .map_err(|e| anyhow::anyhow!("Invalid secp256k1 signature: {}", e))?;
+                key.verify(&message, &signature)
+                    .map_err(|e| anyhow::anyhow!("Signature verification failed: {}", e))
+            }
+        }
+    }
+}
+
+fn ed25519_signing_key(secret_key: &[u8]) -> Result<ed25519_dalek::SigningKey> {
+    let secret: [u8; 32] = secret_key
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Invalid Ed25519 secret key length"))?;
+    Ok(ed25519_dalek::SigningKey::from_bytes(&secret))
+}
+
+#[cfg(feature = "secp256k1")]
+fn secp256k1_signing_key(secret_key: &[u8]) -> Result<k256::ecdsa::SigningKey> {
+    k256::ecdsa::SigningKey::from_slice(secret_key)
+        .map_err(|e| anyhow::anyhow!("Invalid secp256k1 secret key: {}", e))
+}
+
+impl fmt::Display for SignatureScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SignatureScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ed25519" => Ok(Self::Ed25519),
+            #[cfg(feature = "secp256k1")]
+            "secp256k1" => Ok(Self::Secp256k1),
+            #[cfg(not(feature = "secp256k1"))]
+            "secp256k1" => anyhow::bail!(
+                "Signature scheme secp256k1 is not supported by this build (needs the secp256k1 feature)"
+            ),
+            _ => anyhow::bail!(
+                "Invalid signature scheme: {}. Must be 'ed25519' or 'secp256k1'",
+                s
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(scheme: SignatureScheme) {
+        let secret_key = [7u8; 32];
+        let public_key = scheme
+            .public_key_from_secret(&secret_key)
+            .unwrap()
+            .to_bytes();
+        assert_eq!(public_key.len(), scheme.public_key_len());
+        assert_eq!(SignatureScheme::of_public_key(&public_key).unwrap(), scheme);
+
+        let message = b"a.txtbatch";
+        let signature = scheme.sign(&secret_key, message).unwrap();
+        assert_eq!(signature.len(), SignatureScheme::SIGNATURE_LEN);
+        assert!(scheme.verify(&public_key, message, &signature).is_ok());
+        assert!(scheme
+            .verify(&public_key, b"a.txtbatch2", &signature)
+            .is_err());
+
+        let mut tampered = signature.clone();
+        tampered[10] ^= 1;
+        assert!(scheme.verify(&public_key, message, &tampered).is_err());
+
+        let other_key = scheme
+            .public_key_from_secret(&[8u8; 32])
+            .unwrap()
+            .to_bytes();
+        assert!(scheme.verify(&other_key, message, &signature).is_err());
+
+        assert_eq!(
+            scheme.compute_client_id(&public_key).unwrap(),
+            hex::encode(Sha256::digest(&public_key))
+        );
+        assert_eq!(scheme.name().parse::<SignatureScheme>().unwrap(), scheme);
+    }
+
+    #[test]
+    fn test_ed25519_round_trip_matches_sign_message() {
+        round_trip(SignatureScheme::Ed25519);
+
+        // The scheme API and the Ed25519 helpers make the same signatures
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+        let signature = SignatureScheme::Ed25519
+            .sign(&[7u8; 32], b"message")
+            .unwrap();
+        assert_eq!(
+            signature,
+            crate::sign_message(&signing_key, b"message").to_bytes()
+        );
+        assert_eq!(
+            SignatureScheme::default().name(),
+            SignatureScheme::DEFAULT_NAME
+        );
+        assert!("rsa".parse::<SignatureScheme>().is_err());
+    }
+
+    #[cfg(feature = "secp256k1")]
+    #[test]
+    fn test_secp256k1_round_trip() {
+        round_trip(SignatureScheme::Secp256k1);
+
+        // Keys are only accepted in compressed form, so each key has one client ID
+        let public_key = SignatureScheme::Secp256k1
+            .public_key_from_secret(&[7u8; 32])
+            .unwrap();
+        let PublicKey::Secp256k1(key) = &public_key else {
+            unreachable!()
+        };
+        let uncompressed = key.to_encoded_point(false);
+        assert!(SignatureScheme::Secp256k1
+            .public_key_from_bytes(uncompressed.as_bytes())
+            .is_err());
+
+        // An Ed25519 key is not a secp256k1 key, and vice versa
+        let ed25519_key = SignatureScheme::Ed25519
+            .public_key_from_secret(&[7u8; 32])
+            .unwrap()
+            .to_bytes();
+        assert!(SignatureScheme::Secp256k1
+            .public_key_from_bytes(&ed25519_key)
+            .is_err());
+        assert!(SignatureScheme::Ed25519
+            .public_key_from_bytes(&public_key.to_bytes())
+            .is_err());
+    }
+}
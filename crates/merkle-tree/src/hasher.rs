@@ -0,0 +1,107 @@
+use sha2::digest::consts::U32;
+use sha2::{Digest, Sha256};
+
+/// Hash function used for the nodes of a [`crate::MerkleTree`].
+/// Implementations must keep leaves and internal nodes in separate domains, so a
+/// leaf can never be passed off as an internal node or the other way round.
+pub trait MerkleHasher {
+    /// Hash a single data item into a leaf node.
+    fn hash_leaf(data: &[u8]) -> [u8; 32];
+
+    /// Hash two child nodes into their parent.
+    fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32];
+}
+
+/// Any [`Digest`] with a 32-byte output is a hasher, e.g. SHA-512/256 or BLAKE2s-256.
+///
+/// Leaves are hashed as `0x00 || data` and internal nodes as `0x01 || left || right`
+/// to prevent second-preimage attacks between leaf and internal nodes.
+impl<D: Digest<OutputSize = U32>> MerkleHasher for D {
+    fn hash_leaf(data: &[u8]) -> [u8; 32] {
+        let mut hasher = D::new();
+        hasher.update([0x00]); // Domain separation prefix for leaves
+        hasher.update(data);
+        hasher.finalize().into()
+    }
+
+    fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let mut hasher = D::new();
+        hasher.update([0x01]); // Domain separation prefix for internal nodes
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize().into()
+    }
+}
+
+/// The default hasher: SHA-256 with the 0x00/0x01 domain separation prefixes
+pub type Sha256Hasher = Sha256;
+
+/// BLAKE3 with the same domain separation prefixes as the SHA-256 hasher.
+/// Trees built with it have different roots, so both sides must agree on it.
+#[cfg(feature = "blake3")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Blake3Hasher;
+
+#[cfg(feature = "blake3")]
+impl MerkleHasher for Blake3Hasher {
+    fn hash_leaf(data: &[u8]) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&[0x00]);
+        hasher.update(data);
+        hasher.finalize().into()
+    }
+
+    fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&[0x01]);
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MerkleTree;
+
+    /// Build a tree with `H` and check every proof verifies with `H` only
+    fn assert_proofs_verify_within<H: MerkleHasher>() -> [u8; 32] {
+        let data: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i]).collect();
+        let tree = MerkleTree::<H>::from_data_with_digest(&data).unwrap();
+        for (i, item) in data.iter().enumerate() {
+            let proof = tree.generate_proof(i).unwrap();
+            assert_eq!(proof.leaf_hash, H::hash_leaf(item));
+            assert_eq!(
+                proof.compute_root_with_digest::<H>().unwrap(),
+                tree.root_hash()
+            );
+        }
+        let multiproof = tree.generate_multiproof(&[0, 3, 4]).unwrap();
+        assert_eq!(
+            multiproof.compute_root_with_digest::<H>().unwrap(),
+            tree.root_hash()
+        );
+        tree.root_hash()
+    }
+
+    #[test]
+    fn test_sha256_hasher() {
+        let root = assert_proofs_verify_within::<Sha256Hasher>();
+        let data: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i]).collect();
+        assert_eq!(root, MerkleTree::from_data(&data).unwrap().root_hash());
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn test_blake3_hasher() {
+        let root = assert_proofs_verify_within::<Blake3Hasher>();
+        assert_ne!(root, assert_proofs_verify_within::<Sha256Hasher>());
+
+        // A BLAKE3 proof does not verify as SHA-256
+        let data: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i]).collect();
+        let tree = MerkleTree::<Blake3Hasher>::from_data_with_digest(&data).unwrap();
+        let proof = tree.generate_proof(2).unwrap();
+        assert!(!proof.verify(&tree.root_hash()));
+    }
+}
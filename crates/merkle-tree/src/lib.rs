@@ -1,91 +1,117 @@
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+use std::marker::PhantomData;
 use thiserror::Error;
 
+pub mod hasher;
+pub mod multiproof;
 pub mod proof;
+pub use hasher::*;
+pub use multiproof::*;
 pub use proof::*;
 
+/// Identifier of the hash algorithm used for leaf and internal node hashes
+pub const HASH_ALGORITHM: &str = "sha256";
+
+/// Shortest node hash a truncated tree may use (128 bits)
+pub const MIN_TRUNCATION_BYTES: usize = 16;
+
 #[derive(Debug, Error)]
 pub enum MerkleTreeError {
     #[error("Empty data provided")]
     EmptyData,
     #[error("Invalid leaf index: {0}")]
     InvalidLeafIndex(usize),
+    #[error("Invalid hash truncation: {0} bytes (expected 16 to 31)")]
+    InvalidTruncation(usize),
+    #[error("Invalid hash: {0}")]
+    InvalidHash(String),
+    #[error("Invalid multiproof: {0}")]
+    InvalidMultiProof(String),
 }
 
 /// A Merkle tree that can be used to verify data integrity.
 /// The tree is built bottom-up from a collection of data items.
 /// Each leaf node is the hash of a data item, and internal nodes
 /// are hashes of their children.
+///
+/// Nodes are hashed with `H`, any [`MerkleHasher`]: SHA-256 by default, any
+/// 32-byte [`sha2::Digest`] such as SHA-512/256, or BLAKE3 with the `blake3` feature.
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct MerkleTree {
+pub struct MerkleTree<H = Sha256Hasher> {
     root: [u8; 32],
     leaves: Vec<[u8; 32]>,
     levels: Vec<Vec<[u8; 32]>>,
+    /// Width in bytes every node hash is cut to, if this is a truncated view
+    #[serde(skip)]
+    truncation: Option<usize>,
+    #[serde(skip)]
+    digest: PhantomData<fn() -> H>,
 }
 
+/// SHA-256 constructors, so `MerkleTree::from_data` needs no type annotations
 impl MerkleTree {
     /// Build a Merkle tree from a collection of data items.
     /// Each item is hashed to create a leaf node. If there's an odd number
-    /// of nodes at any level, the last node is duplicated.
+    /// of nodes at any level, the last node is promoted to the next level unchanged.
     pub fn from_data(data: &[Vec<u8>]) -> Result<Self, MerkleTreeError> {
-        if data.is_empty() {
-            return Err(MerkleTreeError::EmptyData);
-        }
-
-        // Hash each data item to create leaf nodes
-        let leaves: Vec<[u8; 32]> = data.iter().map(|item| hash_data(item)).collect();
-
-        // Build the tree level by level
-        let mut levels = Vec::new();
-        levels.push(leaves.clone());
-
-        let mut current_level = leaves.clone();
-        while current_level.len() > 1 {
-            let mut next_level = Vec::new();
-
-            // Process pairs of nodes
-            for i in (0..current_level.len()).step_by(2) {
-                if i + 1 < current_level.len() {
-                    // Two siblings: hash them together
-                    let hash = hash_pair(&current_level[i], &current_level[i + 1]);
-                    next_level.push(hash);
-                } else {
-                    // Odd number: duplicate the last node
-                    let hash = hash_pair(&current_level[i], &current_level[i]);
-                    next_level.push(hash);
-                }
-            }
+        Self::from_data_with_digest(data)
+    }
 
-            levels.push(next_level.clone());
-            current_level = next_level;
-        }
+    /// Create a Merkle tree from existing tree structure
+    /// This is used when rebuilding a tree from stored leaf hashes
+    pub fn from_leaf_hashes(leaf_hashes: &[[u8; 32]]) -> Result<Self, MerkleTreeError> {
+        Self::from_leaf_hashes_with_digest(leaf_hashes)
+    }
 
-        let root = current_level[0];
+    /// Check that exactly these leaves, in this order, commit to `root`.
+    /// Rebuilds the tree from the leaves, so unlike a single proof this also
+    /// catches missing, extra or reordered leaves.
+    pub fn verify_batch_membership(leaves: &[[u8; 32]], root: &[u8; 32]) -> bool {
+        Self::verify_batch_membership_with_digest(leaves, root)
+    }
 
-        Ok(MerkleTree {
-            root,
-            leaves,
-            levels,
-        })
+    /// Build a tree the way versions before lone-node promotion did, pairing the last
+    /// node of an odd level with itself. Only for checking trees those versions stored;
+    /// new trees are built with [`MerkleTree::from_leaf_hashes`].
+    pub fn from_leaf_hashes_legacy(leaf_hashes: &[[u8; 32]]) -> Result<Self, MerkleTreeError> {
+        Self::build(leaf_hashes, None, LoneNode::Duplicate)
     }
+}
 
-    /// Get the root hash of the Merkle tree.
-    pub fn root_hash(&self) -> [u8; 32] {
-        self.root
+/// What `build` does with the lone last node of an odd level
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LoneNode {
+    /// Carry it up unchanged
+    Promote,
+    /// Pair it with itself, as trees stored before promotion did
+    Duplicate,
+}
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    /// Build a Merkle tree from a collection of data items, hashing with `H`.
+    /// See [`MerkleTree::from_data`].
+    pub fn from_data_with_digest(data: &[Vec<u8>]) -> Result<Self, MerkleTreeError> {
+        // Hash each data item to create leaf nodes
+        let leaves: Vec<[u8; 32]> = data.iter().map(|item| hash_data::<H>(item)).collect();
+        Self::from_leaf_hashes_with_digest(&leaves)
     }
 
-    /// Get the number of leaf nodes (data items) in the tree.
-    pub fn num_leaves(&self) -> usize {
-        self.leaves.len()
+    /// Create a Merkle tree from stored leaf hashes, hashing internal nodes with `H`.
+    /// See [`MerkleTree::from_leaf_hashes`].
+    pub fn from_leaf_hashes_with_digest(leaf_hashes: &[[u8; 32]]) -> Result<Self, MerkleTreeError> {
+        Self::build(leaf_hashes, None, LoneNode::Promote)
     }
 
-    /// Create a Merkle tree from existing tree structure
-    /// This is used when rebuilding a tree from stored leaf hashes
-    pub fn from_leaf_hashes(leaf_hashes: &[[u8; 32]]) -> Result<Self, MerkleTreeError> {
+    /// Build the tree level by level, cutting every node to `truncation` bytes if set
+    fn build(
+        leaf_hashes: &[[u8; 32]],
+        truncation: Option<usize>,
+        lone_node: LoneNode,
+    ) -> Result<Self, MerkleTreeError> {
         if leaf_hashes.is_empty() {
             return Err(MerkleTreeError::EmptyData);
         }
+        let width = truncation.unwrap_or(32);
 
         // Build the tree level by level from leaf hashes
         let mut levels = Vec::new();
@@ -99,12 +125,15 @@ impl MerkleTree {
             for i in (0..current_level.len()).step_by(2) {
                 if i + 1 < current_level.len() {
                     // Two siblings: hash them together
-                    let hash = hash_pair(&current_level[i], &current_level[i + 1]);
-                    next_level.push(hash);
+                    let hash = hash_pair::<H>(&current_level[i], &current_level[i + 1]);
+                    next_level.push(truncate_hash(&hash, width));
+                } else if lone_node == LoneNode::Duplicate {
+                    let hash = hash_pair::<H>(&current_level[i], &current_level[i]);
+                    next_level.push(truncate_hash(&hash, width));
                 } else {
-                    // Odd number: duplicate the last node
-                    let hash = hash_pair(&current_level[i], &current_level[i]);
-                    next_level.push(hash);
+                    // Odd number: promote the last node unchanged. Pairing it with itself
+                    // would give [a, b, c] and [a, b, c, c] the same root
+                    next_level.push(current_level[i]);
                 }
             }
 
@@ -118,12 +147,160 @@ impl MerkleTree {
             root,
             leaves: leaf_hashes.to_vec(),
             levels,
+            truncation,
+            digest: PhantomData,
+        })
+    }
+
+    /// Append a data item as a new last leaf.
+    /// Only the new leaf's path to the root is recomputed (O(log n) hashes), and the
+    /// result is the same tree `from_data` builds from all items in order.
+    pub fn push(&mut self, data: &[u8]) {
+        self.push_leaf_hash(hash_data::<H>(data));
+    }
+
+    /// Append several data items as new last leaves, in order. See [`MerkleTree::push`].
+    pub fn extend(&mut self, data: &[Vec<u8>]) {
+        for item in data {
+            self.push(item);
+        }
+    }
+
+    /// Append a stored leaf hash as a new last leaf. See [`MerkleTree::push`].
+    /// A truncated tree cuts the leaf to its width, as [`MerkleTree::truncated`] does.
+    /// A legacy tree (see [`MerkleTree::duplicates_lone_nodes`]) is rebuilt whole with
+    /// promotion instead, so its root changes as on any other write.
+    pub fn push_leaf_hash(&mut self, leaf_hash: [u8; 32]) {
+        let width = self.truncation.unwrap_or(32);
+        let leaf_hash = truncate_hash(&leaf_hash, width);
+        if self.duplicates_lone_nodes() {
+            let mut leaves = self.leaves.clone();
+            leaves.push(leaf_hash);
+            *self = Self::build(&leaves, self.truncation, LoneNode::Promote)
+                .expect("leaves are not empty");
+            return;
+        }
+        self.leaves.push(leaf_hash);
+        self.levels[0].push(leaf_hash);
+
+        // The new leaf's ancestors are the last node of every level above it
+        let mut level = 0;
+        while self.levels[level].len() > 1 {
+            let nodes = &self.levels[level];
+            let index = nodes.len() - 1;
+            let parent = if index % 2 == 1 {
+                truncate_hash(&hash_pair::<H>(&nodes[index - 1], &nodes[index]), width)
+            } else {
+                // A lone last node is promoted unchanged, as in `build`
+                nodes[index]
+            };
+
+            if level + 1 == self.levels.len() {
+                self.levels.push(Vec::new());
+            }
+            let above = &mut self.levels[level + 1];
+            if index / 2 < above.len() {
+                above[index / 2] = parent;
+            } else {
+                above.push(parent);
+            }
+            level += 1;
+        }
+        self.root = self.levels[level][0];
+    }
+
+    /// Rebuild this tree with every node hash, leaves included, cut to its first
+    /// `bytes` bytes (the rest zeroed). Proofs get shorter, but each node only has
+    /// `bytes * 4` bits of collision resistance instead of 128; see the
+    /// [`MIN_TRUNCATION_BYTES`] floor. Verify its proofs with
+    /// [`MerkleProof::compute_root_truncated`].
+    pub fn truncated(&self, bytes: usize) -> Result<Self, MerkleTreeError> {
+        validate_truncation(bytes)?;
+        let leaves: Vec<[u8; 32]> = self
+            .leaves
+            .iter()
+            .map(|leaf| truncate_hash(leaf, bytes))
+            .collect();
+        Self::build(&leaves, Some(bytes), self.lone_node())
+    }
+
+    /// Whether this tree pairs the lone node of an odd level with itself, as trees
+    /// stored before lone-node promotion do. Its root differs from a tree rebuilt from
+    /// the same leaves, but its proofs still verify against it.
+    pub fn duplicates_lone_nodes(&self) -> bool {
+        self.levels.windows(2).any(|pair| {
+            let (below, above) = (&pair[0], &pair[1]);
+            below.len() % 2 == 1 && above.last() != below.last()
         })
     }
 
+    fn lone_node(&self) -> LoneNode {
+        if self.duplicates_lone_nodes() {
+            LoneNode::Duplicate
+        } else {
+            LoneNode::Promote
+        }
+    }
+
+    /// Width node hashes are cut to, or `None` for a full-width tree
+    pub fn hash_truncation(&self) -> Option<usize> {
+        self.truncation
+    }
+
+    /// Check batch membership for a tree hashed with `H`.
+    /// See [`MerkleTree::verify_batch_membership`].
+    pub fn verify_batch_membership_with_digest(leaves: &[[u8; 32]], root: &[u8; 32]) -> bool {
+        match Self::from_leaf_hashes_with_digest(leaves) {
+            Ok(tree) => tree.root_hash() == *root,
+            Err(_) => false,
+        }
+    }
+
+    /// Get the root hash of the Merkle tree.
+    pub fn root_hash(&self) -> [u8; 32] {
+        self.root
+    }
+
+    /// Get the number of leaf nodes (data items) in the tree.
+    pub fn num_leaves(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Get the stored hash of the leaf at the given index, if it exists.
+    pub fn leaf_hash(&self, leaf_index: usize) -> Option<[u8; 32]> {
+        self.leaves.get(leaf_index).copied()
+    }
+
+    /// Get every level of the tree, from the leaves (index 0) up to the root (last).
+    /// Each node of a level is the hash of a pair from the level below, except that the
+    /// last node of an odd level is carried up unchanged (or, in a legacy tree, paired
+    /// with itself), so auditors can recompute every node.
+    pub fn levels(&self) -> &[Vec<[u8; 32]>] {
+        &self.levels
+    }
+
+    /// Get the root and every level (see [`MerkleTree::levels`]) as hex strings.
+    pub fn root_and_levels_hex(&self) -> (String, Vec<Vec<String>>) {
+        let levels = self
+            .levels
+            .iter()
+            .map(|level| {
+                level
+                    .iter()
+                    .map(|hash| encode_hash(hash, self.truncation))
+                    .collect()
+            })
+            .collect();
+        (encode_hash(&self.root, self.truncation), levels)
+    }
+
     /// Generate a Merkle proof for the leaf at the given index.
     /// A Merkle proof consists of sibling hashes along the path from
     /// the leaf to the root, along with their positions (left or right).
+    /// A node promoted past a level has no sibling there, so the path may be
+    /// shorter than the tree is high.
+    /// Proofs from a tree hashed with a digest other than SHA-256 are checked
+    /// with [`MerkleProof::compute_root_with_digest`].
     pub fn generate_proof(&self, leaf_index: usize) -> Result<MerkleProof, MerkleTreeError> {
         if leaf_index >= self.leaves.len() {
             return Err(MerkleTreeError::InvalidLeafIndex(leaf_index));
@@ -140,7 +317,7 @@ impl MerkleTree {
                 current_index - 1
             };
 
-            // Check if sibling exists at this level
+            // Odd node at the end has no sibling: it is promoted, so nothing to hash in
             if sibling_index < self.levels[level].len() {
                 let sibling_hash = self.levels[level][sibling_index];
                 let is_left = sibling_index < current_index;
@@ -148,13 +325,12 @@ impl MerkleTree {
                     hash: sibling_hash,
                     is_left,
                 });
-            } else {
-                // Odd node at the end: sibling is itself
-                let sibling_hash = self.levels[level][current_index];
-                let is_left = false; // Convention: duplicate is always on the right
+            } else if self.levels[level + 1][current_index / 2] != self.levels[level][current_index]
+            {
+                // Unless a legacy tree paired it with itself: the duplicate is on the right
                 path.push(ProofNode {
-                    hash: sibling_hash,
-                    is_left,
+                    hash: self.levels[level][current_index],
+                    is_left: false,
                 });
             }
 
@@ -169,32 +345,57 @@ impl MerkleTree {
     }
 }
 
-/// Hash a single data item (leaf node) using SHA-256.
-///
-/// Uses domain separation prefix 0x00 for leaves to prevent
-/// second-preimage attacks between leaf and internal nodes.
-fn hash_data(data: &[u8]) -> [u8; 32] {
-    let mut hasher = Sha256::new();
-    hasher.update([0x00]); // Domain separation prefix for leaves
-    hasher.update(data);
-    hasher.finalize().into()
+/// Check that `bytes` is a supported truncation width
+pub fn validate_truncation(bytes: usize) -> Result<(), MerkleTreeError> {
+    if (MIN_TRUNCATION_BYTES..32).contains(&bytes) {
+        Ok(())
+    } else {
+        Err(MerkleTreeError::InvalidTruncation(bytes))
+    }
 }
 
-/// Hash a pair of hashes together (internal node) using SHA-256.
-///
-/// Uses domain separation prefix 0x01 for internal nodes.
-/// The hashes are concatenated (0x01 || left || right) before hashing.
-fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
-    let mut hasher = Sha256::new();
-    hasher.update([0x01]); // Domain separation prefix for internal nodes
-    hasher.update(left);
-    hasher.update(right);
-    hasher.finalize().into()
+/// Keep the first `bytes` bytes of `hash` and zero the rest
+pub fn truncate_hash(hash: &[u8; 32], bytes: usize) -> [u8; 32] {
+    let mut truncated = [0u8; 32];
+    truncated[..bytes].copy_from_slice(&hash[..bytes]);
+    truncated
+}
+
+/// Hex-encode a node hash at the width of its tree: the first `truncation` bytes, or all 32
+pub fn encode_hash(hash: &[u8; 32], truncation: Option<usize>) -> String {
+    hex::encode(&hash[..truncation.unwrap_or(32)])
+}
+
+/// Decode a node hash encoded by [`encode_hash`] with the same `truncation`
+pub fn decode_hash(hex_hash: &str, truncation: Option<usize>) -> Result<[u8; 32], MerkleTreeError> {
+    let width = truncation.unwrap_or(32);
+    let bytes = hex::decode(hex_hash).map_err(|e| MerkleTreeError::InvalidHash(e.to_string()))?;
+    if bytes.len() != width {
+        return Err(MerkleTreeError::InvalidHash(format!(
+            "expected {} bytes, got {}",
+            width,
+            bytes.len()
+        )));
+    }
+    let mut hash = [0u8; 32];
+    hash[..width].copy_from_slice(&bytes);
+    Ok(hash)
+}
+
+/// Hash a single data item (leaf node) with `H`.
+fn hash_data<H: MerkleHasher>(data: &[u8]) -> [u8; 32] {
+    H::hash_leaf(data)
+}
+
+/// Hash a pair of hashes together (internal node) with `H`.
+pub(crate) fn hash_pair<H: MerkleHasher>(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    H::hash_pair(left, right)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use sha2::{Sha256, Sha512_256};
 
     #[test]
     fn test_single_item() {
@@ -203,7 +404,7 @@ mod tests {
         let root = tree.root_hash();
 
         // Single item: root should be hash of the item
-        let expected = hash_data(b"hello");
+        let expected = hash_data::<Sha256>(b"hello");
         assert_eq!(root, expected);
     }
 
@@ -224,6 +425,65 @@ mod tests {
         assert_eq!(computed_root2, root);
     }
 
+    #[test]
+    fn test_exposed_levels_recompute_to_root() {
+        for count in 1..=9 {
+            let data: Vec<Vec<u8>> = (0..count).map(|i| vec![i as u8]).collect();
+            let tree = MerkleTree::from_data(&data).unwrap();
+            let levels = tree.levels();
+
+            assert_eq!(levels[0], tree.leaves);
+            for pair in levels.windows(2) {
+                let (below, above) = (&pair[0], &pair[1]);
+                let expected: Vec<[u8; 32]> = below
+                    .chunks(2)
+                    .map(|nodes| match nodes {
+                        [left, right] => hash_pair::<Sha256>(left, right),
+                        [lone] => *lone,
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                assert_eq!(above, &expected);
+            }
+            assert_eq!(levels.last().unwrap(), &vec![tree.root_hash()]);
+
+            let (root, hex_levels) = tree.root_and_levels_hex();
+            assert_eq!(root, hex::encode(tree.root_hash()));
+            assert_eq!(hex_levels.len(), levels.len());
+            assert_eq!(hex_levels[0][0], hex::encode(levels[0][0]));
+        }
+    }
+
+    #[test]
+    fn test_truncated_proofs_verify() {
+        let data: Vec<Vec<u8>> = (0..5).map(|i| format!("file{}", i).into_bytes()).collect();
+        let full = MerkleTree::from_data(&data).unwrap();
+        let tree = full.truncated(20).unwrap();
+        assert_eq!(tree.hash_truncation(), Some(20));
+        let root = tree.root_hash();
+        assert_ne!(root, full.root_hash());
+        assert_eq!(root[20..], [0u8; 12]);
+
+        for i in 0..data.len() {
+            let proof = tree.generate_proof(i).unwrap();
+            assert!(proof.path.iter().all(|node| node.hash[20..] == [0u8; 12]));
+            assert_eq!(proof.compute_root_truncated(20).unwrap(), root);
+            // A verifier assuming another width does not reach the root
+            assert_ne!(proof.compute_root_truncated(16).unwrap(), root);
+            assert_ne!(proof.compute_root().unwrap(), root);
+        }
+
+        let hex_root = encode_hash(&root, tree.hash_truncation());
+        assert_eq!(hex_root.len(), 40);
+        assert_eq!(decode_hash(&hex_root, Some(20)).unwrap(), root);
+        assert!(decode_hash(&hex_root, None).is_err());
+        assert_eq!(tree.root_and_levels_hex().0, hex_root);
+
+        for bytes in [0, 15, 32] {
+            assert!(full.truncated(bytes).is_err());
+        }
+    }
+
     #[test]
     fn test_three_items() {
         let data = vec![b"file1".to_vec(), b"file2".to_vec(), b"file3".to_vec()];
@@ -271,6 +531,124 @@ mod tests {
         assert_ne!(computed_root, root);
     }
 
+    #[test]
+    fn test_push_matches_from_data() {
+        let data: Vec<Vec<u8>> = (0..20).map(|i| format!("file{}", i).into_bytes()).collect();
+        let mut tree = MerkleTree::from_data(&data[..1]).unwrap();
+        for count in 2..=data.len() {
+            tree.push(&data[count - 1]);
+            let expected = MerkleTree::from_data(&data[..count]).unwrap();
+            assert_eq!(tree.root_hash(), expected.root_hash());
+            assert_eq!(tree.levels(), expected.levels());
+            assert_eq!(tree.num_leaves(), count);
+        }
+
+        // Proofs from the grown tree verify against its root
+        for i in 0..data.len() {
+            assert!(tree.generate_proof(i).unwrap().verify(&tree.root_hash()));
+        }
+
+        // Bulk appends land on the same tree
+        let mut extended = MerkleTree::from_data(&data[..3]).unwrap();
+        extended.extend(&data[3..]);
+        assert_eq!(extended.root_hash(), tree.root_hash());
+        assert_eq!(extended.levels(), tree.levels());
+    }
+
+    #[test]
+    fn test_push_to_truncated_tree() {
+        let data: Vec<Vec<u8>> = (0..6).map(|i| vec![i]).collect();
+        let mut tree = MerkleTree::from_data(&data[..2])
+            .unwrap()
+            .truncated(20)
+            .unwrap();
+        tree.extend(&data[2..]);
+        let expected = MerkleTree::from_data(&data).unwrap().truncated(20).unwrap();
+        assert_eq!(tree.root_hash(), expected.root_hash());
+        assert_eq!(tree.levels(), expected.levels());
+    }
+
+    #[test]
+    fn test_repeated_last_item_changes_root() {
+        // Duplicating the lone node of an odd level gave these the same root
+        let short: Vec<Vec<u8>> = [b"a", b"b", b"c"].iter().map(|d| d.to_vec()).collect();
+        let mut padded = short.clone();
+        padded.push(b"c".to_vec());
+        let short_tree = MerkleTree::from_data(&short).unwrap();
+        let padded_tree = MerkleTree::from_data(&padded).unwrap();
+        assert_ne!(short_tree.root_hash(), padded_tree.root_hash());
+
+        // Also one level up: six items pair into an odd level of three
+        let six: Vec<Vec<u8>> = (0..6).map(|i| vec![i]).collect();
+        let mut eight = six.clone();
+        eight.extend_from_slice(&six[4..]);
+        assert_ne!(
+            MerkleTree::from_data(&six).unwrap().root_hash(),
+            MerkleTree::from_data(&eight).unwrap().root_hash()
+        );
+
+        // Proofs stay consistent with the root, including for promoted nodes
+        for (data, tree) in [(&short, &short_tree), (&padded, &padded_tree)] {
+            for i in 0..data.len() {
+                let proof = tree.generate_proof(i).unwrap();
+                assert!(proof.verify(&tree.root_hash()));
+            }
+        }
+        assert_eq!(short_tree.generate_proof(2).unwrap().path.len(), 1);
+    }
+
+    #[test]
+    fn test_legacy_tree_keeps_its_root_and_proofs() {
+        // Three leaves built the old way: the lone third leaf is paired with itself
+        let leaves: Vec<[u8; 32]> = [b"a", b"b", b"c"]
+            .iter()
+            .map(|d| hash_data::<Sha256>(*d))
+            .collect();
+        let legacy = MerkleTree::from_leaf_hashes_legacy(&leaves).unwrap();
+        let expected_root = hash_pair::<Sha256>(
+            &hash_pair::<Sha256>(&leaves[0], &leaves[1]),
+            &hash_pair::<Sha256>(&leaves[2], &leaves[2]),
+        );
+        assert_eq!(legacy.root_hash(), expected_root);
+        assert!(legacy.duplicates_lone_nodes());
+
+        let current = MerkleTree::from_leaf_hashes(&leaves).unwrap();
+        assert!(!current.duplicates_lone_nodes());
+        assert_ne!(current.root_hash(), expected_root);
+        // Trees without odd levels are the same either way
+        let even = MerkleTree::from_leaf_hashes_legacy(&leaves[..2]).unwrap();
+        assert!(!even.duplicates_lone_nodes());
+
+        // A stored legacy tree still proves every leaf against its own root
+        let stored: MerkleTree =
+            serde_json::from_str(&serde_json::to_string(&legacy).unwrap()).unwrap();
+        assert!(stored.duplicates_lone_nodes());
+        for i in 0..3 {
+            assert!(stored.generate_proof(i).unwrap().verify(&expected_root));
+        }
+        assert_eq!(stored.generate_proof(2).unwrap().path.len(), 2);
+        let truncated = stored.truncated(20).unwrap();
+        for i in 0..3 {
+            let proof = truncated.generate_proof(i).unwrap();
+            assert_eq!(
+                proof.compute_root_truncated(20).unwrap(),
+                truncated.root_hash()
+            );
+        }
+        assert!(stored.generate_multiproof(&[0, 2]).is_err());
+
+        // Appending rebuilds it with promotion
+        let mut grown = stored.clone();
+        grown.push(b"d");
+        assert!(!grown.duplicates_lone_nodes());
+        assert_eq!(
+            grown.root_hash(),
+            MerkleTree::from_data(&[b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"d".to_vec()])
+                .unwrap()
+                .root_hash()
+        );
+    }
+
     #[test]
     fn test_empty_data() {
         let data: Vec<Vec<u8>> = vec![];
@@ -296,7 +674,7 @@ mod tests {
 
     #[test]
     fn test_from_leaf_hashes_single() {
-        let leaf_hash = hash_data(b"hello");
+        let leaf_hash = hash_data::<Sha256>(b"hello");
         let leaf_hashes = vec![leaf_hash];
         let tree = MerkleTree::from_leaf_hashes(&leaf_hashes).unwrap();
 
@@ -313,8 +691,8 @@ mod tests {
 
     #[test]
     fn test_from_leaf_hashes_two() {
-        let leaf_hash1 = hash_data(b"file1");
-        let leaf_hash2 = hash_data(b"file2");
+        let leaf_hash1 = hash_data::<Sha256>(b"file1");
+        let leaf_hash2 = hash_data::<Sha256>(b"file2");
         let leaf_hashes = vec![leaf_hash1, leaf_hash2];
         let tree = MerkleTree::from_leaf_hashes(&leaf_hashes).unwrap();
 
@@ -322,7 +700,7 @@ mod tests {
         assert_eq!(tree.leaves, leaf_hashes);
 
         // Root should be hash of the two leaves
-        let expected_root = hash_pair(&leaf_hash1, &leaf_hash2);
+        let expected_root = hash_pair::<Sha256>(&leaf_hash1, &leaf_hash2);
         assert_eq!(tree.root_hash(), expected_root);
 
         // Verify proofs for both leaves
@@ -337,19 +715,18 @@ mod tests {
 
     #[test]
     fn test_from_leaf_hashes_three() {
-        let leaf_hash1 = hash_data(b"file1");
-        let leaf_hash2 = hash_data(b"file2");
-        let leaf_hash3 = hash_data(b"file3");
+        let leaf_hash1 = hash_data::<Sha256>(b"file1");
+        let leaf_hash2 = hash_data::<Sha256>(b"file2");
+        let leaf_hash3 = hash_data::<Sha256>(b"file3");
         let leaf_hashes = vec![leaf_hash1, leaf_hash2, leaf_hash3];
         let tree = MerkleTree::from_leaf_hashes(&leaf_hashes).unwrap();
 
         assert_eq!(tree.num_leaves(), 3);
         assert_eq!(tree.leaves, leaf_hashes);
 
-        // With 3 leaves: first two hash together, third duplicates
-        let hash12 = hash_pair(&leaf_hash1, &leaf_hash2);
-        let hash33 = hash_pair(&leaf_hash3, &leaf_hash3);
-        let expected_root = hash_pair(&hash12, &hash33);
+        // With 3 leaves: first two hash together, third is promoted
+        let hash12 = hash_pair::<Sha256>(&leaf_hash1, &leaf_hash2);
+        let expected_root = hash_pair::<Sha256>(&hash12, &leaf_hash3);
         assert_eq!(tree.root_hash(), expected_root);
 
         // Verify proofs for all three leaves
@@ -396,10 +773,10 @@ mod tests {
 
     #[test]
     fn test_from_leaf_hashes_four() {
-        let leaf_hash1 = hash_data(b"file1");
-        let leaf_hash2 = hash_data(b"file2");
-        let leaf_hash3 = hash_data(b"file3");
-        let leaf_hash4 = hash_data(b"file4");
+        let leaf_hash1 = hash_data::<Sha256>(b"file1");
+        let leaf_hash2 = hash_data::<Sha256>(b"file2");
+        let leaf_hash3 = hash_data::<Sha256>(b"file3");
+        let leaf_hash4 = hash_data::<Sha256>(b"file4");
         let leaf_hashes = vec![leaf_hash1, leaf_hash2, leaf_hash3, leaf_hash4];
         let tree = MerkleTree::from_leaf_hashes(&leaf_hashes).unwrap();
 
@@ -407,9 +784,9 @@ mod tests {
         assert_eq!(tree.leaves, leaf_hashes);
 
         // With 4 leaves: perfect binary tree
-        let hash12 = hash_pair(&leaf_hash1, &leaf_hash2);
-        let hash34 = hash_pair(&leaf_hash3, &leaf_hash4);
-        let expected_root = hash_pair(&hash12, &hash34);
+        let hash12 = hash_pair::<Sha256>(&leaf_hash1, &leaf_hash2);
+        let hash34 = hash_pair::<Sha256>(&leaf_hash3, &leaf_hash4);
+        let expected_root = hash_pair::<Sha256>(&hash12, &hash34);
         assert_eq!(tree.root_hash(), expected_root);
 
         // Verify proofs for all four leaves
@@ -419,4 +796,74 @@ mod tests {
             assert_eq!(computed_root, expected_root);
         }
     }
+
+    #[test]
+    fn test_verify_batch_membership() {
+        let leaves: Vec<[u8; 32]> = (0..5u8).map(|i| hash_data::<Sha256>(&[i])).collect();
+        let root = MerkleTree::from_leaf_hashes(&leaves).unwrap().root_hash();
+        assert!(MerkleTree::verify_batch_membership(&leaves, &root));
+
+        // Extra leaf
+        let mut added = leaves.clone();
+        added.push(hash_data::<Sha256>(b"extra"));
+        assert!(!MerkleTree::verify_batch_membership(&added, &root));
+
+        // Missing leaf
+        let removed = &leaves[..4];
+        assert!(!MerkleTree::verify_batch_membership(removed, &root));
+
+        // Reordered leaves
+        let mut reordered = leaves.clone();
+        reordered.swap(1, 3);
+        assert!(!MerkleTree::verify_batch_membership(&reordered, &root));
+
+        // Modified leaf
+        let mut modified = leaves.clone();
+        modified[2] = hash_data::<Sha256>(b"tampered");
+        assert!(!MerkleTree::verify_batch_membership(&modified, &root));
+
+        // No leaves never match
+        assert!(!MerkleTree::verify_batch_membership(&[], &root));
+    }
+
+    #[test]
+    fn test_generic_over_digest() {
+        let data = vec![b"file1".to_vec(), b"file2".to_vec(), b"file3".to_vec()];
+
+        // The default digest is SHA-256
+        let default_tree = MerkleTree::from_data(&data).unwrap();
+        let sha256_tree = MerkleTree::<Sha256>::from_data_with_digest(&data).unwrap();
+        assert_eq!(default_tree.root_hash(), sha256_tree.root_hash());
+
+        let other_tree = MerkleTree::<Sha512_256>::from_data_with_digest(&data).unwrap();
+        assert_ne!(other_tree.root_hash(), sha256_tree.root_hash());
+        assert_eq!(
+            other_tree.leaf_hash(0),
+            Some(hash_data::<Sha512_256>(b"file1"))
+        );
+
+        for i in 0..data.len() {
+            let proof = other_tree.generate_proof(i).unwrap();
+            assert_eq!(
+                proof.compute_root_with_digest::<Sha512_256>().unwrap(),
+                other_tree.root_hash()
+            );
+            // Checking with the wrong digest never reaches the root
+            assert_ne!(proof.compute_root().unwrap(), other_tree.root_hash());
+        }
+
+        let leaves: Vec<[u8; 32]> = (0..data.len())
+            .map(|i| other_tree.leaf_hash(i).unwrap())
+            .collect();
+        assert!(
+            MerkleTree::<Sha512_256>::verify_batch_membership_with_digest(
+                &leaves,
+                &other_tree.root_hash()
+            )
+        );
+        assert!(!MerkleTree::verify_batch_membership(
+            &leaves,
+            &other_tree.root_hash()
+        ));
+    }
 }
@@ -0,0 +1,230 @@
+use crate::{hash_pair, MerkleHasher, MerkleTree, MerkleTreeError, Sha256Hasher};
+use serde::{Deserialize, Serialize};
+
+/// A Merkle proof for several leaves of the same tree at once.
+/// Nodes on the paths of more than one proven leaf, and nodes the verifier can
+/// compute from the proven leaves themselves, are left out, so a multiproof is
+/// smaller than the individual proofs of its leaves together.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MerkleMultiProof {
+    /// Number of leaves in the tree; fixes where levels have a lone node.
+    pub num_leaves: usize,
+    /// The proven leaves as (leaf index, leaf hash), in ascending index order.
+    pub leaves: Vec<(usize, [u8; 32])>,
+    /// The sibling hashes the verifier cannot compute, level by level from the
+    /// leaves up and left to right within a level.
+    pub hashes: Vec<[u8; 32]>,
+}
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    /// Generate one proof for the leaves at `leaf_indices`.
+    /// Indices may be given in any order; repeated indices are proven once.
+    pub fn generate_multiproof(
+        &self,
+        leaf_indices: &[usize],
+    ) -> Result<MerkleMultiProof, MerkleTreeError> {
+        let mut indices = leaf_indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+        if indices.is_empty() {
+            return Err(MerkleTreeError::EmptyData);
+        }
+        if let Some(&index) = indices.iter().find(|&&index| index >= self.leaves.len()) {
+            return Err(MerkleTreeError::InvalidLeafIndex(index));
+        }
+        // Verifiers always promote lone nodes, so a legacy tree's root is out of reach
+        if self.duplicates_lone_nodes() {
+            return Err(MerkleTreeError::InvalidMultiProof(
+                "tree pairs lone nodes with themselves; rebuild it first".to_string(),
+            ));
+        }
+
+        let leaves = indices
+            .iter()
+            .map(|&index| (index, self.leaves[index]))
+            .collect();
+
+        // Walk up like the verifier will, recording each sibling it cannot compute
+        let mut hashes = Vec::new();
+        let mut known = indices;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let mut next = Vec::new();
+            let mut i = 0;
+            while i < known.len() {
+                let index = known[i];
+                let sibling = index ^ 1;
+                if known.get(i + 1) == Some(&sibling) {
+                    // Both children are known: nothing to send
+                    i += 1;
+                } else if sibling < level.len() {
+                    hashes.push(level[sibling]);
+                }
+                next.push(index / 2);
+                i += 1;
+            }
+            known = next;
+        }
+
+        Ok(MerkleMultiProof {
+            num_leaves: self.leaves.len(),
+            leaves,
+            hashes,
+        })
+    }
+}
+
+impl MerkleMultiProof {
+    /// Compute the root hash from this multiproof.
+    /// Fails if the proof lacks a node it needs, carries nodes it does not use,
+    /// or lists leaves out of order or outside the tree.
+    pub fn compute_root(&self) -> Result<[u8; 32], MerkleTreeError> {
+        self.compute_root_with_digest::<Sha256Hasher>()
+    }
+
+    /// Compute the root hash from this multiproof, hashing with `H`.
+    /// `H` must be the hasher the tree was built with.
+    pub fn compute_root_with_digest<H: MerkleHasher>(&self) -> Result<[u8; 32], MerkleTreeError> {
+        if self.leaves.is_empty() {
+            return Err(MerkleTreeError::EmptyData);
+        }
+        if self.leaves.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(MerkleTreeError::InvalidMultiProof(
+                "leaves must be in strictly ascending index order".to_string(),
+            ));
+        }
+        if let Some(&(index, _)) = self
+            .leaves
+            .iter()
+            .find(|(index, _)| *index >= self.num_leaves)
+        {
+            return Err(MerkleTreeError::InvalidLeafIndex(index));
+        }
+
+        let mut hashes = self.hashes.iter();
+        let mut missing = || {
+            hashes.next().copied().ok_or_else(|| {
+                MerkleTreeError::InvalidMultiProof("proof is missing a node".to_string())
+            })
+        };
+
+        let mut known = self.leaves.clone();
+        let mut width = self.num_leaves;
+        while width > 1 {
+            let mut next = Vec::new();
+            let mut i = 0;
+            while i < known.len() {
+                let (index, hash) = known[i];
+                let sibling = index ^ 1;
+                let parent = match known.get(i + 1) {
+                    Some(&(next_index, next_hash)) if next_index == sibling => {
+                        i += 1;
+                        hash_pair::<H>(&hash, &next_hash)
+                    }
+                    // A lone node at the end of an odd level is promoted unchanged
+                    _ if sibling >= width => hash,
+                    _ if index % 2 == 0 => hash_pair::<H>(&hash, &missing()?),
+                    _ => hash_pair::<H>(&missing()?, &hash),
+                };
+                next.push((index / 2, parent));
+                i += 1;
+            }
+            known = next;
+            width = width.div_ceil(2);
+        }
+
+        if hashes.next().is_some() {
+            return Err(MerkleTreeError::InvalidMultiProof(
+                "proof has unused nodes".to_string(),
+            ));
+        }
+        Ok(known[0].1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree_of(count: u8) -> MerkleTree {
+        let data: Vec<Vec<u8>> = (0..count).map(|i| vec![i]).collect();
+        MerkleTree::from_data(&data).unwrap()
+    }
+
+    #[test]
+    fn test_multiproof_of_eight_leaves() {
+        let tree = tree_of(8);
+        let multiproof = tree.generate_multiproof(&[1, 4, 5]).unwrap();
+        assert_eq!(multiproof.compute_root().unwrap(), tree.root_hash());
+
+        // Shared and computable nodes are left out
+        let individual: usize = [1, 4, 5]
+            .iter()
+            .map(|&i| tree.generate_proof(i).unwrap().path.len())
+            .sum();
+        assert_eq!(individual, 9);
+        assert_eq!(multiproof.hashes.len(), 3);
+
+        // Omitting a required node fails instead of producing some other root
+        let mut short = multiproof.clone();
+        short.hashes.pop();
+        assert!(matches!(
+            short.compute_root(),
+            Err(MerkleTreeError::InvalidMultiProof(_))
+        ));
+
+        // So do a node too many and a tampered node
+        let mut long = multiproof.clone();
+        long.hashes.push([0u8; 32]);
+        assert!(long.compute_root().is_err());
+        let mut tampered = multiproof;
+        tampered.hashes[0][0] ^= 1;
+        assert_ne!(tampered.compute_root().unwrap(), tree.root_hash());
+    }
+
+    #[test]
+    fn test_multiproof_any_subset_and_odd_levels() {
+        for count in 1..=7u8 {
+            let tree = tree_of(count);
+            // Every non-empty subset of leaves
+            for mask in 1u32..(1 << count) {
+                let indices: Vec<usize> = (0..count as usize)
+                    .filter(|i| mask & (1 << i) != 0)
+                    .collect();
+                let multiproof = tree.generate_multiproof(&indices).unwrap();
+                assert_eq!(multiproof.compute_root().unwrap(), tree.root_hash());
+            }
+        }
+
+        // Order and repeats of the requested indices do not matter
+        let tree = tree_of(5);
+        assert_eq!(
+            tree.generate_multiproof(&[4, 0, 4]).unwrap(),
+            tree.generate_multiproof(&[0, 4]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invalid_multiproof_requests() {
+        let tree = tree_of(4);
+        assert!(matches!(
+            tree.generate_multiproof(&[]),
+            Err(MerkleTreeError::EmptyData)
+        ));
+        assert!(matches!(
+            tree.generate_multiproof(&[1, 4]),
+            Err(MerkleTreeError::InvalidLeafIndex(4))
+        ));
+
+        let mut multiproof = tree.generate_multiproof(&[0, 2]).unwrap();
+        multiproof.leaves.reverse();
+        assert!(multiproof.compute_root().is_err());
+    }
+
+    #[test]
+    fn test_multiproof_serialization() {
+        let multiproof = tree_of(6).generate_multiproof(&[2, 5]).unwrap();
+        let json = serde_json::to_string(&multiproof).unwrap();
+        let deserialized: MerkleMultiProof = serde_json::from_str(&json).unwrap();
+        assert_eq!(multiproof, deserialized);
+    }
+}
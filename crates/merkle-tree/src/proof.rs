@@ -1,6 +1,9 @@
-use crate::MerkleTreeError;
+use crate::{
+    decode_hash, hash_pair, truncate_hash, validate_truncation, MerkleHasher, MerkleTreeError,
+    Sha256Hasher,
+};
 use serde::{Deserialize, Serialize};
-use sha2::Digest;
+use subtle::ConstantTimeEq;
 
 /// A node in a Merkle proof path, containing a hash and its position.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -29,36 +32,59 @@ impl MerkleProof {
     /// This reconstructs the root hash by following the proof path
     /// and hashing pairs of nodes together.
     pub fn compute_root(&self) -> Result<[u8; 32], MerkleTreeError> {
-        let mut current_hash = self.leaf_hash;
+        self.compute_root_with_digest::<Sha256Hasher>()
+    }
+
+    /// Check that this proof leads to `expected_root`.
+    /// The roots are compared in constant time, so timing does not reveal where they differ.
+    pub fn verify(&self, expected_root: &[u8; 32]) -> bool {
+        let computed_root = self.fold_path::<Sha256Hasher>(32);
+        computed_root.ct_eq(expected_root).into()
+    }
+
+    /// Check that this proof leads to the hex-encoded `expected_root`.
+    /// Fails if the root is not valid hex of exactly 32 bytes.
+    pub fn verify_hex(&self, expected_root_hex: &str) -> Result<bool, MerkleTreeError> {
+        let expected_root = decode_hash(expected_root_hex, None)?;
+        Ok(self.verify(&expected_root))
+    }
+
+    /// Compute the root hash from this proof, hashing with `H`.
+    /// `H` must be the hasher the tree was built with.
+    pub fn compute_root_with_digest<H: MerkleHasher>(&self) -> Result<[u8; 32], MerkleTreeError> {
+        Ok(self.fold_path::<H>(32))
+    }
+
+    /// Compute the root of a tree truncated to `bytes` (see [`crate::MerkleTree::truncated`]).
+    /// The leaf and every computed node are cut to `bytes`, matching how the tree was built.
+    pub fn compute_root_truncated(&self, bytes: usize) -> Result<[u8; 32], MerkleTreeError> {
+        validate_truncation(bytes)?;
+        Ok(self.fold_path::<Sha256Hasher>(bytes))
+    }
+
+    fn fold_path<H: MerkleHasher>(&self, width: usize) -> [u8; 32] {
+        let mut current_hash = truncate_hash(&self.leaf_hash, width);
 
         for node in &self.path {
-            current_hash = if node.is_left {
+            let sibling = truncate_hash(&node.hash, width);
+            let hash = if node.is_left {
                 // Sibling is on the left, current is on the right
-                hash_pair(&node.hash, &current_hash)
+                hash_pair::<H>(&sibling, &current_hash)
             } else {
                 // Sibling is on the right, current is on the left
-                hash_pair(&current_hash, &node.hash)
+                hash_pair::<H>(&current_hash, &sibling)
             };
+            current_hash = truncate_hash(&hash, width);
         }
 
-        Ok(current_hash)
+        current_hash
     }
 }
 
-/// Hash a pair of hashes together (internal node) using SHA-256.
-/// Uses domain separation prefix 0x01 for internal nodes.
-/// The hashes are concatenated (0x01 || left || right) before hashing.
-fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
-    let mut hasher = sha2::Sha256::new();
-    hasher.update([0x01]); // Domain separation prefix for internal nodes
-    hasher.update(left);
-    hasher.update(right);
-    hasher.finalize().into()
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::MerkleTree;
 
     #[test]
     fn test_proof_serialization() {
@@ -75,4 +101,38 @@ mod tests {
         let deserialized: MerkleProof = serde_json::from_str(&json).unwrap();
         assert_eq!(proof, deserialized);
     }
+
+    #[test]
+    fn test_verify_against_expected_root() {
+        let data: Vec<Vec<u8>> = (0..5).map(|i| vec![i]).collect();
+        let tree = MerkleTree::from_data(&data).unwrap();
+        let root = tree.root_hash();
+        let root_hex = hex::encode(root);
+
+        for index in 0..data.len() {
+            let proof = tree.generate_proof(index).unwrap();
+            assert!(proof.verify(&root));
+            assert!(proof.verify_hex(&root_hex).unwrap());
+        }
+
+        // A tampered sibling leads elsewhere
+        let mut proof = tree.generate_proof(2).unwrap();
+        proof.path[0].hash[0] ^= 1;
+        assert!(!proof.verify(&root));
+        assert!(!proof.verify_hex(&root_hex).unwrap());
+    }
+
+    #[test]
+    fn test_verify_hex_rejects_malformed_root() {
+        let tree = MerkleTree::from_data(&[b"a".to_vec(), b"b".to_vec()]).unwrap();
+        let proof = tree.generate_proof(0).unwrap();
+        let root_hex = hex::encode(tree.root_hash());
+
+        for malformed in [&root_hex[..62], &root_hex[..63], "zz"] {
+            assert!(matches!(
+                proof.verify_hex(malformed),
+                Err(MerkleTreeError::InvalidHash(_))
+            ));
+        }
+    }
 }
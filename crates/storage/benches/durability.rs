@@ -0,0 +1,52 @@
+//! Upload throughput of the filesystem backend under each durability mode
+//!
+//! Run with `cargo bench -p storage --bench durability`. Set `DURABILITY_BENCH_DIR` to
+//! measure a specific disk; the default is the system temp directory, which may be a
+//! tmpfs where fsync is free and both modes perform alike.
+
+use std::path::PathBuf;
+use std::time::Instant;
+use storage::filesystem::FilesystemStorage;
+use storage::{Durability, Storage};
+
+/// Files uploaded per mode
+const FILES: usize = 200;
+
+/// Size of each uploaded file in bytes
+const FILE_SIZE: usize = 4096;
+
+fn main() {
+    let base = std::env::var_os("DURABILITY_BENCH_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join(format!("vs-durability-bench-{}", std::process::id()));
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to start runtime");
+    let content = vec![0xAB; FILE_SIZE];
+
+    for durability in [Durability::Strict, Durability::Relaxed] {
+        let dir = base.join(format!("{:?}", durability).to_lowercase());
+        let storage = FilesystemStorage::new(&dir).with_durability(durability);
+
+        let started = Instant::now();
+        runtime.block_on(async {
+            for i in 0..FILES {
+                storage
+                    .store_file_and_update_tree("client", "batch", &format!("{}.bin", i), &content)
+                    .await
+                    .expect("Upload failed");
+            }
+        });
+        let elapsed = started.elapsed();
+
+        println!(
+            "{:?}: {} uploads of {} bytes in {:.2?} ({:.0} uploads/s)",
+            durability,
+            FILES,
+            FILE_SIZE,
+            elapsed,
+            FILES as f64 / elapsed.as_secs_f64()
+        );
+    }
+
+    std::fs::remove_dir_all(&base).ok();
+}
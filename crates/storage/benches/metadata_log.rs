@@ -0,0 +1,57 @@
+//! Sequential uploads to one batch, rewriting metadata.json for every new file versus
+//! appending to the metadata log
+//!
+//! Run with `cargo bench -p storage --bench metadata_log`; `DURABILITY_BENCH_DIR`
+//! selects the disk as for the durability bench. Every upload also rebuilds the Merkle
+//! tree by hashing each file of the batch, which is O(n) in both modes, so the gap is
+//! the metadata rewrite alone and widens with batch size and fsync latency.
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use storage::filesystem::FilesystemStorage;
+use storage::Storage;
+
+/// Files uploaded to the batch per mode
+const FILES: usize = 2000;
+
+/// Uploads timed at the end of each run, where the batch is largest
+const TAIL: usize = 200;
+
+fn main() {
+    let base = std::env::var_os("DURABILITY_BENCH_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join(format!("vs-metadata-log-bench-{}", std::process::id()));
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to start runtime");
+
+    for (mode, limit) in [("rewrite", 1), ("log", 256)] {
+        let dir = base.join(mode);
+        let storage = FilesystemStorage::new(&dir).with_metadata_log_limit(limit);
+
+        let mut tail = Duration::ZERO;
+        let started = Instant::now();
+        runtime.block_on(async {
+            for i in 0..FILES {
+                let upload_started = Instant::now();
+                storage
+                    .store_file_and_update_tree("client", "batch", &format!("{:05}.bin", i), b"x")
+                    .await
+                    .expect("Upload failed");
+                if i >= FILES - TAIL {
+                    tail += upload_started.elapsed();
+                }
+            }
+        });
+
+        println!(
+            "{}: {} uploads in {:.2?}, last {} at {:.2?} per upload",
+            mode,
+            FILES,
+            started.elapsed(),
+            TAIL,
+            tail / TAIL as u32
+        );
+    }
+
+    std::fs::remove_dir_all(&base).ok();
+}
@@ -1,42 +1,251 @@
 use crate::{
-    database::{DatabaseRetryConfig, DatabaseStorage},
-    filesystem::FilesystemStorage,
+    compression::CompressionConfig,
+    database::{DatabasePoolConfig, DatabaseRetryConfig, DatabaseStorage},
+    encryption::EncryptionConfig,
+    filesystem::{Durability, FilesystemStorage},
+    memory::MemoryStorage,
+    read_only::ReadOnlyStorage,
+    sqlite::SqliteStorage,
+    timed::TimedStorage,
     Storage,
 };
 use anyhow::Result;
 use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
 
 /// Storage backend type
 pub enum StorageBackend {
-    /// Filesystem storage with data directory path
-    Filesystem(String),
-    /// Database storage with database URL and optional retry configuration
+    /// Filesystem storage with data directory path, optional per-batch file limit,
+    /// how uploads are flushed to disk, whether the data directory may be a symlink
+    /// and optional at-rest encryption and compression
+    Filesystem {
+        data_dir: String,
+        max_files_per_batch: Option<usize>,
+        durability: Durability,
+        follow_data_symlink: bool,
+        encryption: Option<EncryptionConfig>,
+        compression: Option<CompressionConfig>,
+    },
+    /// Database storage with database URL, optional retry and connection pool
+    /// configuration, optional directory for file content kept outside the database,
+    /// optional per-batch file limit and optional at-rest encryption and compression
     Database {
         database_url: String,
         retry_config: Option<DatabaseRetryConfig>,
+        pool_config: Option<DatabasePoolConfig>,
+        external_content_dir: Option<String>,
+        max_files_per_batch: Option<usize>,
+        encryption: Option<EncryptionConfig>,
+        compression: Option<CompressionConfig>,
+    },
+    /// SQLite database storage with database file path (created if missing) and
+    /// optional per-batch file limit
+    Sqlite {
+        path: String,
+        max_files_per_batch: Option<usize>,
+    },
+    /// In-memory storage, lost on restart, with optional per-batch file limit
+    Memory { max_files_per_batch: Option<usize> },
+    /// S3-compatible object storage with bucket, optional endpoint URL (AWS when unset),
+    /// optional region and optional per-batch file limit
+    #[cfg(feature = "s3")]
+    S3 {
+        bucket: String,
+        endpoint: Option<String>,
+        region: Option<String>,
+        max_files_per_batch: Option<usize>,
     },
 }
 
+/// Decorators stacked on top of the initialized backend
+#[derive(Debug, Clone, Default)]
+pub struct StorageLayers {
+    /// Reject every write
+    pub read_only: bool,
+    /// Warn about calls taking at least this long
+    pub slow_op_threshold: Option<Duration>,
+}
+
+impl StorageLayers {
+    /// Wrap `storage` in the configured decorators
+    /// Timing is outermost so rejected writes are timed like any other call
+    pub fn apply(&self, mut storage: Arc<dyn Storage>) -> Arc<dyn Storage> {
+        if self.read_only {
+            storage = Arc::new(ReadOnlyStorage::new(storage));
+        }
+        if let Some(threshold) = self.slow_op_threshold {
+            storage = Arc::new(TimedStorage::new(storage, threshold));
+        }
+        storage
+    }
+}
+
 impl StorageBackend {
-    /// Initialize storage backend based on type
-    pub async fn initialize(self) -> Result<Arc<dyn Storage>> {
+    /// Initialize storage backend based on type, wrapped in the configured layers
+    pub async fn initialize(self, layers: &StorageLayers) -> Result<Arc<dyn Storage>> {
+        let storage = self.initialize_backend(layers).await?;
+        Ok(layers.apply(storage))
+    }
+
+    async fn initialize_backend(self, layers: &StorageLayers) -> Result<Arc<dyn Storage>> {
         match self {
-            StorageBackend::Filesystem(data_dir) => {
-                let storage = FilesystemStorage::new(data_dir);
+            StorageBackend::Filesystem {
+                data_dir,
+                max_files_per_batch,
+                durability,
+                follow_data_symlink,
+                encryption,
+                compression,
+            } => {
+                let mut storage = FilesystemStorage::new(data_dir).with_durability(durability);
+                if let Some(max) = max_files_per_batch {
+                    storage = storage.with_max_files_per_batch(max);
+                }
+                if let Some(encryption) = encryption {
+                    storage = storage.with_encryption(encryption);
+                }
+                if let Some(compression) = compression {
+                    storage = storage.with_compression(compression);
+                }
+
+                // Fail fast on a misconfigured data directory; read-only servers never write it
+                storage
+                    .validate_data_dir(follow_data_symlink, !layers.read_only)
+                    .await?;
+
+                // Repair batches left inconsistent by a crash mid-upload
+                let report = storage.reconcile().await?;
+                if !report.is_clean() {
+                    warn!(
+                        "Reconciled filesystem storage after unclean shutdown: {:?}",
+                        report
+                    );
+                }
                 Ok(Arc::new(storage))
             }
             StorageBackend::Database {
                 database_url,
                 retry_config,
+                pool_config,
+                external_content_dir,
+                max_files_per_batch,
+                encryption,
+                compression,
+            } => {
+                let mut storage = DatabaseStorage::new_with_pool_config(
+                    &database_url,
+                    retry_config.unwrap_or_default(),
+                    pool_config.unwrap_or_default(),
+                )
+                .await?;
+                if let Some(encryption) = encryption {
+                    storage = storage.with_encryption(encryption);
+                }
+                if let Some(compression) = compression {
+                    storage = storage.with_compression(compression);
+                }
+                if let Some(dir) = external_content_dir {
+                    storage = storage.with_external_content_dir(dir);
+                }
+                if let Some(max) = max_files_per_batch {
+                    storage = storage.with_max_files_per_batch(max);
+                }
+                Ok(Arc::new(storage))
+            }
+            StorageBackend::Sqlite {
+                path,
+                max_files_per_batch,
             } => {
-                let storage = match retry_config {
-                    Some(config) => {
-                        DatabaseStorage::new_with_retry_config(&database_url, config).await?
-                    }
-                    None => DatabaseStorage::new(&database_url).await?,
-                };
+                let mut storage = SqliteStorage::new(&path).await?;
+                if let Some(max) = max_files_per_batch {
+                    storage = storage.with_max_files_per_batch(max);
+                }
+                Ok(Arc::new(storage))
+            }
+            StorageBackend::Memory {
+                max_files_per_batch,
+            } => {
+                let mut storage = MemoryStorage::new();
+                if let Some(max) = max_files_per_batch {
+                    storage = storage.with_max_files_per_batch(max);
+                }
+                Ok(Arc::new(storage))
+            }
+            #[cfg(feature = "s3")]
+            StorageBackend::S3 {
+                bucket,
+                endpoint,
+                region,
+                max_files_per_batch,
+            } => {
+                let mut storage =
+                    crate::s3::S3Storage::new(bucket, endpoint.as_deref(), region.as_deref())
+                        .await?;
+                if let Some(max) = max_files_per_batch {
+                    storage = storage.with_max_files_per_batch(max);
+                }
+
+                // Fail fast on a missing bucket or bad credentials
+                storage.validate_bucket().await?;
                 Ok(Arc::new(storage))
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_layers_are_stacked_on_initialize() {
+        let dir = std::env::temp_dir().join(format!("vs-layers-{}", std::process::id()));
+        let path = dir.to_string_lossy().to_string();
+
+        let writable = StorageBackend::Filesystem {
+            data_dir: path.clone(),
+            max_files_per_batch: None,
+            durability: Durability::Strict,
+            follow_data_symlink: true,
+            encryption: None,
+            compression: None,
+        }
+        .initialize(&StorageLayers::default())
+        .await
+        .unwrap();
+        writable
+            .store_file_and_update_tree("client", "batch", "a.txt", b"a")
+            .await
+            .unwrap();
+
+        let layers = StorageLayers {
+            read_only: true,
+            slow_op_threshold: Some(Duration::from_secs(1)),
+        };
+        let read_only = StorageBackend::Filesystem {
+            data_dir: path,
+            max_files_per_batch: None,
+            durability: Durability::Strict,
+            follow_data_symlink: true,
+            encryption: None,
+            compression: None,
+        }
+        .initialize(&layers)
+        .await
+        .unwrap();
+        assert_eq!(
+            read_only
+                .read_file("client", "batch", "a.txt")
+                .await
+                .unwrap(),
+            b"a"
+        );
+        assert!(read_only
+            .store_file_and_update_tree("client", "batch", "b.txt", b"b")
+            .await
+            .is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,185 @@
+use crate::encryption::ENCRYPTION_KEY_ENV;
+use crate::{Compression, CompressionConfig, EncryptionConfig};
+use anyhow::{Context, Result};
+use std::borrow::Cow;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+/// Opening bytes of stored content whose header records how it was transformed
+/// Content stored as uploaded has no header, unless it starts with these bytes itself:
+/// then it gets an empty header, so no stored content is ever mistaken for another form.
+const MAGIC: &[u8; 8] = b"\x89VSBLOB\n";
+
+/// Header length: the magic bytes and one byte of flags
+const HEADER_LEN: usize = MAGIC.len() + 1;
+
+/// Header flag: the body is encrypted
+const ENCRYPTED: u8 = 0b01;
+
+/// Header flag: the (decrypted) body is compressed, starting with its algorithm's tag
+const COMPRESSED: u8 = 0b10;
+
+/// Transformations applied to file content at rest, by every backend that stores it
+/// Content is compressed before it is encrypted; `None` leaves it as uploaded. Stored
+/// content records in a header what was done to it and is decoded by that record alone,
+/// so content stored before either was turned on or off stays readable (given the key,
+/// if it is encrypted).
+#[derive(Clone, Default)]
+pub(crate) struct ContentCodec {
+    pub(crate) compression: Option<CompressionConfig>,
+    pub(crate) encryption: Option<EncryptionConfig>,
+}
+
+impl ContentCodec {
+    /// Whether uploaded content is always transformed before it is stored
+    pub(crate) fn transforms_content(&self) -> bool {
+        self.compression().is_some() || self.encryption.is_some()
+    }
+
+    /// Compression applied to new content; algorithm `none` stores it as uploaded
+    fn compression(&self) -> Option<&CompressionConfig> {
+        self.compression
+            .as_ref()
+            .filter(|compression| compression.algorithm != Compression::None)
+    }
+
+    /// Whether the content of `source` would be stored byte for byte, so it can be
+    /// copied without reading it into memory
+    pub(crate) async fn stores_file_unchanged(&self, source: &Path) -> Result<bool> {
+        Ok(!self.transforms_content() && !file_has_header(source).await?)
+    }
+
+    /// Stored form of uploaded content
+    pub(crate) fn encode<'a>(&self, content: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        let mut body = Cow::Borrowed(content);
+        let mut flags = 0;
+        if let Some(compression) = self.compression() {
+            body = Cow::Owned(compression.compress(&body)?);
+            flags |= COMPRESSED;
+        }
+        if let Some(encryption) = &self.encryption {
+            body = Cow::Owned(encryption.encrypt(&body)?);
+            flags |= ENCRYPTED;
+        }
+        if flags == 0 && !has_header(&body) {
+            return Ok(body);
+        }
+        let mut stored = Vec::with_capacity(HEADER_LEN + body.len());
+        stored.extend_from_slice(MAGIC);
+        stored.push(flags);
+        stored.extend_from_slice(&body);
+        Ok(Cow::Owned(stored))
+    }
+
+    /// Uploaded bytes of stored content, decrypted and decompressed as its header says
+    pub(crate) fn decode(&self, stored: Vec<u8>) -> Result<Vec<u8>> {
+        if !has_header(&stored) {
+            return Ok(stored);
+        }
+        let flags = *stored
+            .get(MAGIC.len())
+            .context("Stored content has a truncated header")?;
+        anyhow::ensure!(
+            flags & !(ENCRYPTED | COMPRESSED) == 0,
+            "Unknown flags {:#04x} in stored content header",
+            flags
+        );
+        let body = &stored[HEADER_LEN..];
+        let content = if flags & ENCRYPTED != 0 {
+            let encryption = self.encryption.as_ref().with_context(|| {
+                format!(
+                    "Stored content is encrypted but {} is not set",
+                    ENCRYPTION_KEY_ENV
+                )
+            })?;
+            Cow::Owned(encryption.decrypt(body)?)
+        } else {
+            Cow::Borrowed(body)
+        };
+        if flags & COMPRESSED != 0 {
+            return CompressionConfig::decompress(&content);
+        }
+        Ok(content.into_owned())
+    }
+}
+
+/// Whether stored content starts with a header (and so is not the uploaded bytes)
+pub(crate) fn has_header(stored: &[u8]) -> bool {
+    stored.starts_with(MAGIC)
+}
+
+/// Whether a file starts with a header, reading only its first bytes
+pub(crate) async fn file_has_header(path: &Path) -> Result<bool> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to read file: {:?}", path))?;
+    let mut prefix = [0; MAGIC.len()];
+    match file.read_exact(&mut prefix).await {
+        Ok(_) => Ok(has_header(&prefix)),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Failed to read file: {:?}", path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_content_marked_encrypted_is_decrypted() {
+        let plain = ContentCodec::default();
+        let encrypted = ContentCodec {
+            encryption: Some(EncryptionConfig::new([9; 32])),
+            ..Default::default()
+        };
+
+        // Content stored before the key was set reads back with it
+        let stored = plain.encode(b"plaintext").unwrap();
+        assert_eq!(&stored[..], b"plaintext");
+        assert_eq!(encrypted.decode(stored.into_owned()).unwrap(), b"plaintext");
+
+        let sealed = encrypted.encode(b"plaintext").unwrap().into_owned();
+        assert!(has_header(&sealed));
+        assert_eq!(encrypted.decode(sealed.clone()).unwrap(), b"plaintext");
+        let err = plain.decode(sealed).unwrap_err();
+        assert!(err.to_string().contains(ENCRYPTION_KEY_ENV));
+
+        // Uploaded content that looks like a header is escaped rather than misread
+        let lookalike = [&MAGIC[..], &[ENCRYPTED], b"not sealed"].concat();
+        for codec in [&plain, &encrypted] {
+            let stored = codec.encode(&lookalike).unwrap().into_owned();
+            assert_eq!(codec.decode(stored).unwrap(), lookalike);
+        }
+
+        assert!(plain.decode(MAGIC.to_vec()).is_err());
+        assert!(plain.decode([&MAGIC[..], &[0x80]].concat()).is_err());
+    }
+
+    #[test]
+    fn test_compression_is_undone_by_the_header_not_the_configuration() {
+        let content = b"abc".repeat(1000);
+        let plain = ContentCodec::default();
+        let compressed = ContentCodec {
+            compression: Some(CompressionConfig::new(Compression::Zstd, 3).unwrap()),
+            ..Default::default()
+        };
+        let none = ContentCodec {
+            compression: Some(CompressionConfig::new(Compression::None, 0).unwrap()),
+            ..Default::default()
+        };
+
+        // Off, then on: content stored uncompressed reads back as it is
+        let stored = plain.encode(&content).unwrap().into_owned();
+        assert_eq!(compressed.decode(stored).unwrap(), content);
+
+        // On, then off: compressed content is still decompressed
+        let stored = compressed.encode(&content).unwrap().into_owned();
+        assert!(has_header(&stored) && stored.len() < content.len() / 10);
+        assert_eq!(plain.decode(stored.clone()).unwrap(), content);
+        assert_eq!(none.decode(stored).unwrap(), content);
+
+        // Algorithm `none` stores content as uploaded
+        assert!(!none.transforms_content());
+        assert_eq!(&none.encode(&content).unwrap()[..], &content[..]);
+    }
+}
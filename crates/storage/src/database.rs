@@ -1,17 +1,35 @@
+mod external;
 mod queries;
 mod schema;
+/// Statements shared with the SQLite backend: both schemas use the same table and column
+/// names and sqlx binds `$N` placeholders on either database, so statements in the common
+/// subset of SQL are written once. Row locks, arrays and server-side hashing stay per backend.
+pub(crate) mod sql;
+use crypto::hash_leaf;
 use merkle_tree::MerkleTree;
 
-use crate::Storage;
+use crate::codec::ContentCodec;
+use crate::{
+    ensure_canonical_client_id, BatchFull, CompactionReport, CompressionConfig, EncryptionConfig,
+    ReplaceConflict, ReplacementFile, Storage, StorageError, StorageResult,
+};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use queries::Queries;
+use external::ExternalContentStore;
+use queries::{Queries, StoredContent};
 use schema::Schema;
-use sqlx::PgPool;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use tokio::time::sleep;
 use tracing::warn;
 
+/// How long external content must have gone unused before compaction may delete it,
+/// leaving uploads time to commit the row that refers to content they just wrote
+const EXTERNAL_CONTENT_GRACE: Duration = Duration::from_secs(3600);
+
 /// Database connection retry configuration
 #[derive(Debug, Clone)]
 pub struct DatabaseRetryConfig {
@@ -51,9 +69,81 @@ impl DatabaseRetryConfig {
     }
 }
 
+/// Database connection pool configuration
+/// The defaults are sqlx's own pool defaults
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatabasePoolConfig {
+    /// Most connections the pool opens
+    pub max_connections: u32,
+    /// Connections the pool keeps open even when idle
+    pub min_connections: u32,
+    /// How long a query waits for a free connection before failing
+    pub acquire_timeout_seconds: u64,
+    /// How long an idle connection above `min_connections` stays open; `None` keeps it
+    pub idle_timeout_seconds: Option<u64>,
+}
+
+impl Default for DatabasePoolConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout_seconds: 30,
+            idle_timeout_seconds: Some(600),
+        }
+    }
+}
+
+impl DatabasePoolConfig {
+    /// Create pool config from environment variables
+    /// Reads DB_POOL_MAX_CONNECTIONS, DB_POOL_MIN_CONNECTIONS,
+    /// DB_POOL_ACQUIRE_TIMEOUT_SECONDS and DB_POOL_IDLE_TIMEOUT_SECONDS
+    /// (0 keeps idle connections open)
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            std::env::var(name).ok().and_then(|s| s.parse().ok())
+        }
+
+        let defaults = Self::default();
+        Self {
+            max_connections: var("DB_POOL_MAX_CONNECTIONS").unwrap_or(defaults.max_connections),
+            min_connections: var("DB_POOL_MIN_CONNECTIONS").unwrap_or(defaults.min_connections),
+            acquire_timeout_seconds: var("DB_POOL_ACQUIRE_TIMEOUT_SECONDS")
+                .unwrap_or(defaults.acquire_timeout_seconds),
+            idle_timeout_seconds: match var::<u64>("DB_POOL_IDLE_TIMEOUT_SECONDS") {
+                Some(0) => None,
+                Some(seconds) => Some(seconds),
+                None => defaults.idle_timeout_seconds,
+            },
+        }
+    }
+
+    fn pool_options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(Duration::from_secs(self.acquire_timeout_seconds))
+            .idle_timeout(self.idle_timeout_seconds.map(Duration::from_secs))
+    }
+}
+
 /// PostgreSQL database storage implementation
 pub struct DatabaseStorage {
     pool: PgPool,
+    /// When set, file content lives here and the files table only holds references
+    external_content: Option<ExternalContentStore>,
+    /// Most files a batch may hold; `None` means unlimited
+    max_files_per_batch: Option<usize>,
+    /// Compresses and encrypts file content at rest
+    codec: ContentCodec,
+}
+
+/// Content written to a files row
+enum NewContent<'a> {
+    /// Content as stored (compressed and encrypted if configured), with the leaf hash
+    /// of the uploaded bytes
+    Inline(&'a [u8], [u8; 32]),
+    External(&'a str),
 }
 
 impl DatabaseStorage {
@@ -64,22 +154,139 @@ impl DatabaseStorage {
     }
 
     /// Create a new PostgreSQL database storage instance with custom retry configuration
+    /// Uses default pool configuration
     pub async fn new_with_retry_config(
         database_url: &str,
         retry_config: DatabaseRetryConfig,
     ) -> Result<Self> {
-        let pool = connect_with_retry(database_url, &retry_config).await?;
+        Self::new_with_pool_config(database_url, retry_config, DatabasePoolConfig::default()).await
+    }
+
+    /// Create a new PostgreSQL database storage instance with custom retry and
+    /// connection pool configuration
+    pub async fn new_with_pool_config(
+        database_url: &str,
+        retry_config: DatabaseRetryConfig,
+        pool_config: DatabasePoolConfig,
+    ) -> Result<Self> {
+        let pool = connect_with_retry(database_url, &retry_config, &pool_config).await?;
         Schema::initialize(&pool).await?;
-        Ok(Self { pool })
+        Ok(Self {
+            pool,
+            external_content: None,
+            max_files_per_batch: None,
+            codec: ContentCodec::default(),
+        })
+    }
+
+    /// Store new file content in a directory outside the database
+    /// Metadata, keys and trees stay in PostgreSQL; existing inline rows stay readable
+    pub fn with_external_content_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.external_content = Some(ExternalContentStore::new(dir).with_codec(self.codec.clone()));
+        self
+    }
+
+    /// Encrypt file content at rest with the given key, inline and external alike
+    pub fn with_encryption(mut self, encryption: EncryptionConfig) -> Self {
+        self.codec.encryption = Some(encryption);
+        self.update_external_codec()
+    }
+
+    /// Compress file content at rest, before encrypting it, inline and external alike
+    pub fn with_compression(mut self, compression: CompressionConfig) -> Self {
+        self.codec.compression = Some(compression);
+        self.update_external_codec()
+    }
+
+    /// Give the external content store the current codec
+    fn update_external_codec(mut self) -> Self {
+        self.external_content = self
+            .external_content
+            .map(|store| store.with_codec(self.codec.clone()));
+        self
+    }
+
+    /// Reject adding new files to batches that already hold `max` files
+    pub fn with_max_files_per_batch(mut self, max: usize) -> Self {
+        self.max_files_per_batch = Some(max);
+        self
+    }
+
+    /// Write the files row, then rebuild and store the batch's Merkle tree
+    async fn store_row_and_update_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        content: NewContent<'_>,
+    ) -> Result<()> {
+        // One transaction covers the batch, the file and the tree, so a failure at any
+        // point leaves none of them changed and a stored file is always in the tree
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .context("Failed to begin transaction for atomic file and tree update")?;
+
+        self.write_row_and_tree(&mut tx, client_id, batch_id, filename, content)
+            .await?;
+
+        tx.commit()
+            .await
+            .context("Failed to commit transaction for atomic file and tree update")?;
+
+        Ok(())
+    }
+
+    /// The writes of [`Self::store_row_and_update_tree`], inside the caller's transaction
+    async fn write_row_and_tree(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        content: NewContent<'_>,
+    ) -> Result<()> {
+        Queries::ensure_batch(&mut **tx, client_id, batch_id).await?;
+        // The batch row lock serializes concurrent writers, so each rebuild sees the files
+        // committed before it and the file count stays accurate
+        Queries::lock_batch(&mut **tx, client_id, batch_id).await?;
+        if let Some(max_files) = self.max_files_per_batch {
+            let others =
+                Queries::count_other_files(&mut **tx, client_id, batch_id, filename).await?;
+            if others >= max_files as i64 {
+                return Err(BatchFull { max_files }.into());
+            }
+        }
+        match content {
+            NewContent::Inline(stored, leaf_hash) => {
+                Queries::store_file(tx, client_id, batch_id, filename, stored, &leaf_hash).await?
+            }
+            NewContent::External(content_ref) => {
+                Queries::store_file_ref(tx, client_id, batch_id, filename, content_ref).await?
+            }
+        }
+
+        // Rebuild from every file of the batch, including the one just written
+        let leaf_hashes = Queries::compute_leaf_hashes_from_files(&mut **tx, client_id, batch_id)
+            .await
+            .context("Failed to compute leaf hashes from files")?;
+        let tree = MerkleTree::from_leaf_hashes(&leaf_hashes)
+            .context("Failed to build Merkle tree from leaf hashes")?;
+        Queries::store_merkle_tree(&mut **tx, client_id, batch_id, &tree).await
     }
 }
 
 /// Connect to PostgreSQL database with retry logic and exponential backoff
-async fn connect_with_retry(database_url: &str, config: &DatabaseRetryConfig) -> Result<PgPool> {
+async fn connect_with_retry(
+    database_url: &str,
+    config: &DatabaseRetryConfig,
+    pool_config: &DatabasePoolConfig,
+) -> Result<PgPool> {
     let mut last_error = None;
 
     for attempt in 0..config.max_attempts {
-        match PgPool::connect(database_url).await {
+        match pool_config.pool_options().connect(database_url).await {
             Ok(pool) => {
                 // Test the connection
                 if let Err(e) = sqlx::query("SELECT 1").execute(&pool).await {
@@ -131,105 +338,1510 @@ fn calculate_retry_delay(attempt: u32, initial_delay_seconds: u64) -> Duration {
 
 #[async_trait]
 impl Storage for DatabaseStorage {
-    async fn read_file(&self, client_id: &str, batch_id: &str, filename: &str) -> Result<Vec<u8>> {
-        Queries::read_file(&self.pool, client_id, batch_id, filename)
+    async fn read_file(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Vec<u8>> {
+        let stored = Queries::read_file(&self.pool, client_id, batch_id, filename)
             .await?
-            .ok_or_else(|| {
-                anyhow::anyhow!(
-                    "File {} not found in batch {} for client {}",
-                    filename,
-                    batch_id,
-                    client_id
+            .ok_or_else(|| StorageError::file_not_found(batch_id, filename))?;
+
+        match stored {
+            StoredContent::Inline(content) => Ok(self.codec.decode(content)?),
+            StoredContent::External(content_ref) => match &self.external_content {
+                Some(store) => Ok(store.get(&content_ref).await?),
+                None => Err(anyhow::anyhow!(
+                    "File {} is stored externally but no external content directory is configured",
+                    filename
                 )
-            })
+                .into()),
+            },
+        }
     }
 
-    async fn load_batch_filenames(&self, client_id: &str, batch_id: &str) -> Result<Vec<String>> {
+    async fn load_batch_filenames(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Vec<String>> {
         if !Queries::batch_exists(&self.pool, client_id, batch_id).await? {
-            anyhow::bail!("Batch {} not found for client {}", batch_id, client_id);
+            return Err(StorageError::batch_not_found(batch_id));
         }
-        Queries::load_batch_filenames(&self.pool, client_id, batch_id).await
+        Ok(Queries::load_batch_filenames(&self.pool, client_id, batch_id).await?)
     }
 
-    async fn file_exists(&self, client_id: &str, batch_id: &str, filename: &str) -> Result<bool> {
-        Queries::file_exists(&self.pool, client_id, batch_id, filename).await
+    async fn batch_exists(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        Ok(Queries::batch_exists(&self.pool, client_id, batch_id).await?)
     }
 
-    async fn store_public_key(&self, client_id: &str, public_key: &[u8]) -> Result<()> {
-        Queries::store_public_key(&self.pool, client_id, public_key).await
+    async fn client_exists(&self, client_id: &str) -> StorageResult<bool> {
+        Ok(Queries::client_exists(&self.pool, client_id).await?)
     }
 
-    async fn load_public_key(&self, client_id: &str) -> Result<Option<Vec<u8>>> {
-        Queries::load_public_key(&self.pool, client_id).await
+    async fn count_clients(&self) -> StorageResult<u64> {
+        Ok(Queries::count_clients(&self.pool).await?)
     }
 
-    async fn load_merkle_tree(
+    async fn file_exists(
         &self,
         client_id: &str,
         batch_id: &str,
-    ) -> Result<Option<merkle_tree::MerkleTree>> {
-        Queries::load_merkle_tree(&self.pool, client_id, batch_id).await
+        filename: &str,
+    ) -> StorageResult<bool> {
+        Ok(Queries::file_exists(&self.pool, client_id, batch_id, filename).await?)
     }
 
-    async fn store_file_and_update_tree(
+    async fn file_stored_at(
         &self,
         client_id: &str,
         batch_id: &str,
         filename: &str,
-        content: &[u8],
-    ) -> Result<()> {
-        // Use a single transaction to ensure atomicity
-        // SELECT FOR UPDATE locks the merkle_trees row to prevent concurrent modifications
+    ) -> StorageResult<Option<u64>> {
+        Ok(Queries::file_stored_at(&self.pool, client_id, batch_id, filename).await?)
+    }
+
+    async fn store_public_key(&self, client_id: &str, public_key: &[u8]) -> StorageResult<()> {
+        ensure_canonical_client_id(client_id, public_key)?;
+        Ok(Queries::store_public_key(&self.pool, client_id, public_key).await?)
+    }
+
+    async fn load_public_key(&self, client_id: &str) -> StorageResult<Option<Vec<u8>>> {
+        Ok(Queries::load_public_key(&self.pool, client_id).await?)
+    }
+
+    async fn set_batch_public(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        public: bool,
+    ) -> StorageResult<()> {
+        if !Queries::batch_exists(&self.pool, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        Ok(Queries::set_batch_public(&self.pool, client_id, batch_id, public).await?)
+    }
+
+    async fn is_batch_public(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        Ok(Queries::is_batch_public(&self.pool, client_id, batch_id).await?)
+    }
+
+    async fn grant_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<()> {
+        if !Queries::batch_exists(&self.pool, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        Ok(Queries::grant_batch_access(&self.pool, client_id, batch_id, grantee_id).await?)
+    }
+
+    async fn revoke_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<()> {
+        if !Queries::batch_exists(&self.pool, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        Ok(Queries::revoke_batch_access(&self.pool, client_id, batch_id, grantee_id).await?)
+    }
+
+    async fn has_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<bool> {
+        Ok(Queries::has_batch_access(&self.pool, client_id, batch_id, grantee_id).await?)
+    }
+
+    async fn set_batch_annotations(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        annotations: &BTreeMap<String, String>,
+    ) -> StorageResult<()> {
+        if !Queries::batch_exists(&self.pool, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        Ok(Queries::set_batch_annotations(&self.pool, client_id, batch_id, annotations).await?)
+    }
+
+    async fn load_batch_annotations(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<BTreeMap<String, String>> {
+        Ok(Queries::load_batch_annotations(&self.pool, client_id, batch_id).await?)
+    }
+
+    async fn set_committed_root(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        root: &[u8; 32],
+    ) -> StorageResult<()> {
+        if !Queries::batch_exists(&self.pool, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        Ok(Queries::set_committed_root(&self.pool, client_id, batch_id, root).await?)
+    }
+
+    async fn load_committed_root(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Option<[u8; 32]>> {
+        Ok(Queries::load_committed_root(&self.pool, client_id, batch_id).await?)
+    }
+
+    async fn create_batch(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        Ok(Queries::create_batch(&self.pool, client_id, batch_id).await?)
+    }
+
+    async fn rename_batch(
+        &self,
+        client_id: &str,
+        old_batch_id: &str,
+        new_batch_id: &str,
+    ) -> StorageResult<()> {
         let mut tx = self
             .pool
             .begin()
             .await
-            .context("Failed to begin transaction for atomic file and tree update")?;
+            .context("Failed to begin transaction for batch rename")?;
 
-        Queries::ensure_batch(&mut *tx, client_id, batch_id).await?;
-        Queries::store_file(&mut *tx, client_id, batch_id, filename, content).await?;
+        // Lock the source batch row so concurrent uploads wait for the rename
+        let source =
+            sqlx::query("SELECT 1 FROM batches WHERE client_id = $1 AND batch_id = $2 FOR UPDATE")
+                .bind(client_id)
+                .bind(old_batch_id)
+                .fetch_optional(&mut *tx)
+                .await
+                .context("Failed to lock batch row")?;
+        if source.is_none() {
+            return Err(StorageError::batch_not_found(old_batch_id));
+        }
 
-        // Lock the merkle_trees row to prevent concurrent modifications
-        let _ = sqlx::query(
-            "SELECT 1 FROM merkle_trees 
-             WHERE client_id = $1 AND batch_id = $2 
-             FOR UPDATE",
-        )
-        .bind(client_id)
-        .bind(batch_id)
-        .fetch_optional(&mut *tx)
-        .await
-        .context("Failed to lock Merkle tree row")?;
+        let destination_exists: bool = sqlx::query_scalar(sql::BATCH_EXISTS)
+            .bind(client_id)
+            .bind(new_batch_id)
+            .fetch_one(&mut *tx)
+            .await
+            .context("Failed to check destination batch existence")?;
+        if destination_exists {
+            return Err(StorageError::batch_already_exists(new_batch_id));
+        }
+
+        Queries::rename_batch(&mut tx, client_id, old_batch_id, new_batch_id).await?;
 
-        // Commit transaction before computing hashes (read-only operation)
         tx.commit()
             .await
-            .context("Failed to commit transaction for file storage")?;
+            .context("Failed to commit transaction for batch rename")?;
+        Ok(())
+    }
+
+    async fn delete_batch(&self, client_id: &str, batch_id: &str) -> StorageResult<()> {
+        // Trees and access grants go with the batch row (ON DELETE CASCADE) and blobs no
+        // other file uses with its files; external content they referred to is left for
+        // compaction to remove
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .context("Failed to begin transaction for batch deletion")?;
+        if !Queries::delete_batch(&mut tx, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        Ok(tx
+            .commit()
+            .await
+            .context("Failed to commit batch deletion")?)
+    }
+
+    async fn delete_file(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Option<MerkleTree>> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .context("Failed to begin transaction for file deletion")?;
 
-        // Compute leaf hashes from all files in the batch
-        // This ensures correctness and handles tree updates correctly
-        let leaf_hashes = Queries::compute_leaf_hashes_from_files(&self.pool, client_id, batch_id)
+        // Removing the row and rebuilding the tree commit together, as for uploads
+        Queries::lock_batch(&mut *tx, client_id, batch_id).await?;
+        if !Queries::delete_file(&mut tx, client_id, batch_id, filename).await? {
+            return Err(StorageError::file_not_found(batch_id, filename));
+        }
+        let leaf_hashes = Queries::compute_leaf_hashes_from_files(&mut *tx, client_id, batch_id)
             .await
             .context("Failed to compute leaf hashes from files")?;
+        let tree = if leaf_hashes.is_empty() {
+            // No files left: the batch has no root, so its tree goes too
+            Queries::delete_orphaned_rows(&mut tx, client_id, batch_id).await?;
+            None
+        } else {
+            let tree = MerkleTree::from_leaf_hashes(&leaf_hashes)
+                .context("Failed to build Merkle tree from leaf hashes")?;
+            Queries::store_merkle_tree(&mut *tx, client_id, batch_id, &tree).await?;
+            Some(tree)
+        };
 
-        // Build Merkle tree from all leaf hashes
-        let tree = MerkleTree::from_leaf_hashes(&leaf_hashes)
-            .context("Failed to build Merkle tree from leaf hashes")?;
+        tx.commit()
+            .await
+            .context("Failed to commit transaction for file deletion")?;
+        Ok(tree)
+    }
 
-        // Store the rebuilt tree in a new transaction
+    async fn replace_batch(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        files: &[ReplacementFile],
+        committed_root: &[u8; 32],
+    ) -> StorageResult<()> {
+        if let Some(max_files) = self.max_files_per_batch {
+            if files.len() > max_files {
+                return Err(BatchFull { max_files }.into());
+            }
+        }
+        let mut files: Vec<&ReplacementFile> = files.iter().collect();
+        files.sort_by(|a, b| a.filename.cmp(&b.filename));
+        if let Some(pair) = files.windows(2).find(|w| w[0].filename == w[1].filename) {
+            return Err(anyhow::anyhow!("File {} is listed twice", pair[0].filename).into());
+        }
+
+        // Read new content before the transaction, so a bad source never half-applies
+        let mut uploads = Vec::new();
+        for file in &files {
+            let Some(source) = &file.source else {
+                continue;
+            };
+            let content = match &self.external_content {
+                Some(store) => StoredContent::External(store.put_file(source).await?),
+                None => {
+                    let content = tokio::fs::read(source)
+                        .await
+                        .with_context(|| format!("Failed to read source file: {:?}", source))?;
+                    // The leaf hash keys the blob, which files of other clients may share
+                    if hash_leaf(&content) != file.leaf_hash {
+                        return Err(anyhow::anyhow!(
+                            "File {} does not match its leaf hash",
+                            file.filename
+                        )
+                        .into());
+                    }
+                    StoredContent::Inline(self.codec.encode(&content)?.into_owned())
+                }
+            };
+            uploads.push((*file, content));
+        }
+
+        if !Queries::batch_exists(&self.pool, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
         let mut tx = self
             .pool
             .begin()
             .await
-            .context("Failed to begin transaction for tree storage")?;
+            .context("Failed to begin transaction for batch replacement")?;
+        Queries::lock_batch(&mut *tx, client_id, batch_id).await?;
 
-        Queries::store_merkle_tree(&mut *tx, client_id, batch_id, &tree).await?;
+        // Kept files must still be what the caller saw, or the new root would be wrong
+        let current: BTreeMap<String, [u8; 32]> =
+            Queries::leaf_hashes_by_filename(&mut *tx, client_id, batch_id)
+                .await?
+                .into_iter()
+                .collect();
+        for file in files.iter().filter(|file| file.source.is_none()) {
+            if current.get(&file.filename) != Some(&file.leaf_hash) {
+                return Err(ReplaceConflict {
+                    filename: file.filename.clone(),
+                }
+                .into());
+            }
+        }
 
-        // Commit the entire transaction
+        let filenames: Vec<String> = files.iter().map(|file| file.filename.clone()).collect();
+        Queries::delete_files_except(&mut tx, client_id, batch_id, &filenames).await?;
+        for (file, content) in &uploads {
+            let filename = &file.filename;
+            match content {
+                StoredContent::Inline(content) => {
+                    Queries::store_file(
+                        &mut tx,
+                        client_id,
+                        batch_id,
+                        filename,
+                        content,
+                        &file.leaf_hash,
+                    )
+                    .await?
+                }
+                StoredContent::External(content_ref) => {
+                    Queries::store_file_ref(&mut tx, client_id, batch_id, filename, content_ref)
+                        .await?
+                }
+            }
+        }
+
+        // A batch without files has no tree
+        if files.is_empty() {
+            Queries::delete_orphaned_rows(&mut tx, client_id, batch_id).await?;
+        } else {
+            let leaf_hashes: Vec<[u8; 32]> = files.iter().map(|file| file.leaf_hash).collect();
+            let tree = MerkleTree::from_leaf_hashes(&leaf_hashes)
+                .context("Failed to build Merkle tree from leaf hashes")?;
+            Queries::store_merkle_tree(&mut *tx, client_id, batch_id, &tree).await?;
+        }
+        Queries::set_committed_root(&mut *tx, client_id, batch_id, committed_root).await?;
+
+        Ok(tx
+            .commit()
+            .await
+            .context("Failed to commit batch replacement")?)
+    }
+
+    async fn compact_batch(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<CompactionReport> {
+        // Database metadata is normalized by the schema; only orphaned rows can pile up
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .context("Failed to begin transaction for batch compaction")?;
+        if !Queries::batch_exists(&self.pool, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        Queries::lock_batch(&mut *tx, client_id, batch_id).await?;
+        let orphaned_rows = Queries::delete_orphaned_rows(&mut tx, client_id, batch_id).await?;
         tx.commit()
             .await
-            .context("Failed to commit transaction for atomic file and tree update")?;
+            .context("Failed to commit batch compaction")?;
 
-        Ok(())
+        let mut report = CompactionReport {
+            orphaned_rows_removed: orphaned_rows as usize,
+            ..CompactionReport::default()
+        };
+
+        // Content is shared across batches, so only content no file anywhere uses is removed.
+        // An upload may reuse a candidate after the references are loaded and commit its
+        // row later, so each candidate's age is checked again as it is removed.
+        if let Some(store) = &self.external_content {
+            let idle = store.list_idle(EXTERNAL_CONTENT_GRACE).await?;
+            let referenced = Queries::referenced_content(&self.pool).await?;
+            for content_ref in idle.iter().filter(|r| !referenced.contains(*r)) {
+                if store
+                    .remove_if_idle(content_ref, EXTERNAL_CONTENT_GRACE)
+                    .await?
+                {
+                    report.orphaned_blobs_removed += 1;
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    async fn list_batches(&self) -> StorageResult<Vec<(String, String)>> {
+        Ok(Queries::list_batches(&self.pool).await?)
+    }
+
+    async fn list_client_batches(&self, client_id: &str) -> StorageResult<Vec<String>> {
+        Ok(Queries::list_client_batches(&self.pool, client_id).await?)
+    }
+
+    async fn load_merkle_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Option<merkle_tree::MerkleTree>> {
+        Ok(Queries::load_merkle_tree(&self.pool, client_id, batch_id).await?)
+    }
+
+    async fn store_file_and_update_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        content: &[u8],
+    ) -> StorageResult<()> {
+        match &self.external_content {
+            Some(store) => {
+                let content_ref = store.put(content).await?;
+                Ok(self
+                    .store_row_and_update_tree(
+                        client_id,
+                        batch_id,
+                        filename,
+                        NewContent::External(&content_ref),
+                    )
+                    .await?)
+            }
+            None => {
+                let stored = self.codec.encode(content)?;
+                Ok(self
+                    .store_row_and_update_tree(
+                        client_id,
+                        batch_id,
+                        filename,
+                        NewContent::Inline(&stored, hash_leaf(content)),
+                    )
+                    .await?)
+            }
+        }
+    }
+
+    async fn store_file_from_path_and_update_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        source: &Path,
+    ) -> StorageResult<()> {
+        // External content can be streamed straight from the source file
+        if let Some(store) = &self.external_content {
+            let content_ref = store.put_file(source).await?;
+            return Ok(self
+                .store_row_and_update_tree(
+                    client_id,
+                    batch_id,
+                    filename,
+                    NewContent::External(&content_ref),
+                )
+                .await?);
+        }
+
+        // Inline content is bound as a single BYTEA value, so it has to be buffered here
+        let content = tokio::fs::read(source)
+            .await
+            .with_context(|| format!("Failed to read source file: {:?}", source))?;
+        self.store_file_and_update_tree(client_id, batch_id, filename, &content)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Compression;
+    use crypto::{compute_client_id, generate_keypair, hash_leaf};
+
+    /// Connect to the database named by `TEST_DATABASE_URL`; these tests are skipped
+    /// when it is unset, as they need a running PostgreSQL server
+    async fn test_storage() -> Option<DatabaseStorage> {
+        let database_url = std::env::var("TEST_DATABASE_URL").ok()?;
+        Some(DatabaseStorage::new(&database_url).await.unwrap())
+    }
+
+    /// Register a fresh client, so each run works on batches of its own
+    async fn register_client(storage: &DatabaseStorage) -> String {
+        let (_, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+        storage
+            .store_public_key(&client_id, verifying_key.as_bytes())
+            .await
+            .unwrap();
+        client_id
+    }
+
+    #[tokio::test]
+    async fn test_single_connection_pool_serializes_concurrent_calls() {
+        let Ok(database_url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let pool_config = DatabasePoolConfig {
+            max_connections: 1,
+            ..DatabasePoolConfig::default()
+        };
+        let storage = std::sync::Arc::new(
+            DatabaseStorage::new_with_pool_config(
+                &database_url,
+                DatabaseRetryConfig::default(),
+                pool_config,
+            )
+            .await
+            .unwrap(),
+        );
+
+        // Calls beyond the single connection wait for it instead of failing
+        let mut tasks = tokio::task::JoinSet::new();
+        for _ in 0..8 {
+            let storage = storage.clone();
+            tasks.spawn(async move { register_client(&storage).await });
+        }
+        let mut client_ids = Vec::new();
+        while let Some(client_id) = tasks.join_next().await {
+            client_ids.push(client_id.unwrap());
+        }
+        assert_eq!(client_ids.len(), 8);
+        for client_id in &client_ids {
+            assert!(storage.client_exists(client_id).await.unwrap());
+        }
+        // The test database is shared, so other tests may have registered clients too
+        assert!(storage.count_clients().await.unwrap() >= 8);
+    }
+
+    #[tokio::test]
+    async fn test_failed_store_leaves_neither_file_nor_tree() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let client_id = register_client(&storage).await;
+
+        // Fail after the file row and tree are written: nothing of either is visible
+        let mut tx = storage.pool.begin().await.unwrap();
+        storage
+            .write_row_and_tree(
+                &mut tx,
+                &client_id,
+                "batch",
+                "a.txt",
+                NewContent::Inline(b"alpha", hash_leaf(b"alpha")),
+            )
+            .await
+            .unwrap();
+        tx.rollback().await.unwrap();
+
+        assert!(!storage.batch_exists(&client_id, "batch").await.unwrap());
+        assert!(!storage
+            .file_exists(&client_id, "batch", "a.txt")
+            .await
+            .unwrap());
+        assert!(storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .is_none());
+
+        // A committed store makes the file and its tree visible together
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "a.txt", b"alpha")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .load_batch_filenames(&client_id, "batch")
+                .await
+                .unwrap(),
+            vec!["a.txt".to_string()]
+        );
+        let tree = storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tree.root_hash(), hash_leaf(b"alpha"));
+    }
+
+    #[tokio::test]
+    async fn test_delete_batch_removes_files_and_tree() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let client_id = register_client(&storage).await;
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "a.txt", b"alpha")
+            .await
+            .unwrap();
+
+        storage.delete_batch(&client_id, "batch").await.unwrap();
+
+        assert!(!storage.batch_exists(&client_id, "batch").await.unwrap());
+        assert!(!storage
+            .file_exists(&client_id, "batch", "a.txt")
+            .await
+            .unwrap());
+        assert!(storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(storage
+            .load_batch_filenames(&client_id, "batch")
+            .await
+            .unwrap_err()
+            .is_not_found());
+        assert!(storage.delete_batch(&client_id, "batch").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rename_batch() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let client_id = register_client(&storage).await;
+        storage
+            .store_file_and_update_tree(&client_id, "tmp", "a.txt", b"a")
+            .await
+            .unwrap();
+        storage
+            .store_file_and_update_tree(&client_id, "tmp", "b.txt", b"b")
+            .await
+            .unwrap();
+        let root = storage
+            .load_merkle_tree(&client_id, "tmp")
+            .await
+            .unwrap()
+            .unwrap()
+            .root_hash();
+
+        storage
+            .rename_batch(&client_id, "tmp", "final")
+            .await
+            .unwrap();
+
+        assert!(!storage.batch_exists(&client_id, "tmp").await.unwrap());
+        assert!(storage
+            .load_batch_filenames(&client_id, "tmp")
+            .await
+            .unwrap_err()
+            .is_not_found());
+        assert_eq!(
+            storage.list_client_batches(&client_id).await.unwrap(),
+            ["final"]
+        );
+        assert_eq!(
+            storage
+                .load_batch_filenames(&client_id, "final")
+                .await
+                .unwrap(),
+            vec!["a.txt", "b.txt"]
+        );
+        assert_eq!(
+            storage
+                .read_file(&client_id, "final", "b.txt")
+                .await
+                .unwrap(),
+            b"b"
+        );
+        let renamed_tree = storage.load_merkle_tree(&client_id, "final").await.unwrap();
+        assert_eq!(renamed_tree.unwrap().root_hash(), root);
+    }
+
+    #[tokio::test]
+    async fn test_rename_batch_conflict() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let client_id = register_client(&storage).await;
+        storage
+            .store_file_and_update_tree(&client_id, "first", "a.txt", b"a")
+            .await
+            .unwrap();
+        storage
+            .store_file_and_update_tree(&client_id, "second", "b.txt", b"b")
+            .await
+            .unwrap();
+
+        let err = storage
+            .rename_batch(&client_id, "first", "second")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+
+        // Both batches untouched
+        assert_eq!(
+            storage
+                .load_batch_filenames(&client_id, "first")
+                .await
+                .unwrap(),
+            vec!["a.txt"]
+        );
+        assert_eq!(
+            storage
+                .load_batch_filenames(&client_id, "second")
+                .await
+                .unwrap(),
+            vec!["b.txt"]
+        );
+
+        // Missing source is reported as not found
+        let err = storage
+            .rename_batch(&client_id, "missing", "third")
+            .await
+            .unwrap_err();
+        assert!(err.is_not_found());
+    }
+
+    #[tokio::test]
+    async fn test_list_client_batches() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let client_id = register_client(&storage).await;
+        assert!(storage
+            .list_client_batches(&client_id)
+            .await
+            .unwrap()
+            .is_empty());
+
+        storage
+            .store_file_and_update_tree(&client_id, "b", "a.txt", b"a")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.list_client_batches(&client_id).await.unwrap(),
+            ["b"]
+        );
+
+        storage.create_batch(&client_id, "c").await.unwrap();
+        storage
+            .store_file_and_update_tree(&client_id, "a", "a.txt", b"a")
+            .await
+            .unwrap();
+        let other_id = register_client(&storage).await;
+        storage
+            .store_file_and_update_tree(&other_id, "d", "a.txt", b"a")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.list_client_batches(&client_id).await.unwrap(),
+            ["a", "b", "c"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_file_rebuilds_tree() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let client_id = register_client(&storage).await;
+        for (filename, content) in [("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")] {
+            storage
+                .store_file_and_update_tree(&client_id, "batch", filename, content)
+                .await
+                .unwrap();
+        }
+
+        let tree = storage
+            .delete_file(&client_id, "batch", "b.txt")
+            .await
+            .unwrap()
+            .unwrap();
+
+        let root = MerkleTree::from_data(&[b"a".to_vec(), b"c".to_vec()])
+            .unwrap()
+            .root_hash();
+        assert_eq!(tree.root_hash(), root);
+        let stored = storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.root_hash(), root);
+        for i in 0..stored.num_leaves() {
+            assert!(stored.generate_proof(i).unwrap().verify(&root));
+        }
+        assert!(!storage
+            .file_exists(&client_id, "batch", "b.txt")
+            .await
+            .unwrap());
+        assert!(storage
+            .delete_file(&client_id, "batch", "b.txt")
+            .await
+            .is_err());
+
+        // Deleting the last files leaves an empty batch without a tree
+        storage
+            .delete_file(&client_id, "batch", "a.txt")
+            .await
+            .unwrap();
+        assert!(storage
+            .delete_file(&client_id, "batch", "c.txt")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(storage.batch_exists(&client_id, "batch").await.unwrap());
+        assert!(storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_compaction_removes_orphans_and_keeps_referenced_content() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let external_dir =
+            std::env::temp_dir().join(format!("vs-db-compact-{}", std::process::id()));
+        let storage = storage.with_external_content_dir(&external_dir);
+        let client_id = register_client(&storage).await;
+        storage
+            .store_file_and_update_tree(&client_id, "kept", "a.txt", b"still referenced")
+            .await
+            .unwrap();
+        storage
+            .store_file_and_update_tree(&client_id, "emptied", "b.txt", b"no longer referenced")
+            .await
+            .unwrap();
+
+        // Drop the file row alone, leaving its tree and its content behind
+        sqlx::query("DELETE FROM files WHERE client_id = $1 AND batch_id = $2")
+            .bind(&client_id)
+            .bind("emptied")
+            .execute(&storage.pool)
+            .await
+            .unwrap();
+        // Age both contents past the grace period
+        let content_path = |content: &[u8]| {
+            let content_ref = hex::encode(hash_leaf(content));
+            external_dir.join(&content_ref[..2]).join(content_ref)
+        };
+        for content in [b"still referenced".as_slice(), b"no longer referenced"] {
+            std::fs::File::options()
+                .write(true)
+                .open(content_path(content))
+                .unwrap()
+                .set_modified(std::time::SystemTime::now() - 2 * EXTERNAL_CONTENT_GRACE)
+                .unwrap();
+        }
+
+        let report = storage.compact_batch(&client_id, "emptied").await.unwrap();
+        assert_eq!(report.orphaned_rows_removed, 1);
+        assert_eq!(report.orphaned_blobs_removed, 1);
+        assert!(storage
+            .load_merkle_tree(&client_id, "emptied")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(!content_path(b"no longer referenced").exists());
+
+        // Content still referenced by another batch survives, however old
+        assert_eq!(
+            storage
+                .read_file(&client_id, "kept", "a.txt")
+                .await
+                .unwrap(),
+            b"still referenced"
+        );
+        let report = storage.compact_batch(&client_id, "kept").await.unwrap();
+        assert_eq!(report.orphaned_rows_removed, 0);
+        assert_eq!(report.orphaned_blobs_removed, 0);
+        assert!(storage
+            .load_merkle_tree(&client_id, "kept")
+            .await
+            .unwrap()
+            .is_some());
+
+        tokio::fs::remove_dir_all(&external_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_batch_and_client_exist() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let (_, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+        assert!(!storage.client_exists(&client_id).await.unwrap());
+        assert!(!storage.batch_exists(&client_id, "batch").await.unwrap());
+
+        storage
+            .store_public_key(&client_id, verifying_key.as_bytes())
+            .await
+            .unwrap();
+        assert!(storage.client_exists(&client_id).await.unwrap());
+        assert!(!storage.batch_exists(&client_id, "batch").await.unwrap());
+
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "a.txt", b"a")
+            .await
+            .unwrap();
+        assert!(storage.batch_exists(&client_id, "batch").await.unwrap());
+        assert!(!storage.batch_exists(&client_id, "other").await.unwrap());
+        let other_client = register_client(&storage).await;
+        assert!(!storage.batch_exists(&other_client, "batch").await.unwrap());
+
+        // An empty batch exists until it is deleted
+        assert!(storage.create_batch(&client_id, "empty").await.unwrap());
+        assert!(storage.batch_exists(&client_id, "empty").await.unwrap());
+        storage.delete_batch(&client_id, "empty").await.unwrap();
+        assert!(!storage.batch_exists(&client_id, "empty").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_max_files_per_batch() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let storage = storage.with_max_files_per_batch(2);
+        let client_id = register_client(&storage).await;
+        for name in ["a.txt", "b.txt"] {
+            storage
+                .store_file_and_update_tree(&client_id, "batch", name, name.as_bytes())
+                .await
+                .unwrap();
+        }
+
+        let err = storage
+            .store_file_and_update_tree(&client_id, "batch", "c.txt", b"c")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            StorageError::BatchFull(BatchFull { max_files: 2 })
+        ));
+        assert!(!storage
+            .file_exists(&client_id, "batch", "c.txt")
+            .await
+            .unwrap());
+
+        // Existing files stay readable and replaceable; other batches are unaffected
+        assert_eq!(
+            storage
+                .read_file(&client_id, "batch", "a.txt")
+                .await
+                .unwrap(),
+            b"a.txt"
+        );
+        assert_eq!(
+            storage
+                .read_file(&client_id, "batch", "b.txt")
+                .await
+                .unwrap(),
+            b"b.txt"
+        );
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "a.txt", b"new")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .load_merkle_tree(&client_id, "batch")
+                .await
+                .unwrap()
+                .unwrap()
+                .num_leaves(),
+            2
+        );
+        storage
+            .store_file_and_update_tree(&client_id, "other", "c.txt", b"c")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_batch_access_grant_and_revoke() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let owner = register_client(&storage).await;
+        let reader = register_client(&storage).await;
+        let other = register_client(&storage).await;
+        storage
+            .store_file_and_update_tree(&owner, "batch", "a.txt", b"a")
+            .await
+            .unwrap();
+
+        assert!(!storage
+            .has_batch_access(&owner, "batch", &reader)
+            .await
+            .unwrap());
+
+        storage
+            .grant_batch_access(&owner, "batch", &reader)
+            .await
+            .unwrap();
+        // Granting twice is a no-op
+        storage
+            .grant_batch_access(&owner, "batch", &reader)
+            .await
+            .unwrap();
+        assert!(storage
+            .has_batch_access(&owner, "batch", &reader)
+            .await
+            .unwrap());
+        assert!(!storage
+            .has_batch_access(&owner, "batch", &other)
+            .await
+            .unwrap());
+
+        // Access follows the batch through a rename and survives further uploads
+        storage
+            .rename_batch(&owner, "batch", "renamed")
+            .await
+            .unwrap();
+        storage
+            .store_file_and_update_tree(&owner, "renamed", "b.txt", b"b")
+            .await
+            .unwrap();
+        assert!(storage
+            .has_batch_access(&owner, "renamed", &reader)
+            .await
+            .unwrap());
+
+        storage
+            .revoke_batch_access(&owner, "renamed", &reader)
+            .await
+            .unwrap();
+        assert!(!storage
+            .has_batch_access(&owner, "renamed", &reader)
+            .await
+            .unwrap());
+
+        let err = storage
+            .grant_batch_access(&owner, "missing", &reader)
+            .await
+            .unwrap_err();
+        assert!(err.is_not_found());
+    }
+
+    #[tokio::test]
+    async fn test_batch_annotations_round_trip() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let client_id = register_client(&storage).await;
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "a.txt", b"a")
+            .await
+            .unwrap();
+
+        assert!(storage
+            .load_batch_annotations(&client_id, "batch")
+            .await
+            .unwrap()
+            .is_empty());
+
+        let annotations: BTreeMap<String, String> =
+            [("commit", "1a2b3c"), ("description", "nightly")]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+        storage
+            .set_batch_annotations(&client_id, "batch", &annotations)
+            .await
+            .unwrap();
+
+        // Annotations survive further uploads and renames, and never touch the tree
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "b.txt", b"b")
+            .await
+            .unwrap();
+        storage
+            .rename_batch(&client_id, "batch", "renamed")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .load_batch_annotations(&client_id, "renamed")
+                .await
+                .unwrap(),
+            annotations
+        );
+        storage
+            .set_batch_annotations(&client_id, "renamed", &BTreeMap::new())
+            .await
+            .unwrap();
+        assert!(storage
+            .load_batch_annotations(&client_id, "renamed")
+            .await
+            .unwrap()
+            .is_empty());
+        let tree = storage
+            .load_merkle_tree(&client_id, "renamed")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::from_data(&[b"a".to_vec(), b"b".to_vec()])
+                .unwrap()
+                .root_hash()
+        );
+
+        let err = storage
+            .set_batch_annotations(&client_id, "missing", &annotations)
+            .await
+            .unwrap_err();
+        assert!(err.is_not_found());
+    }
+
+    #[tokio::test]
+    async fn test_large_batch_reads_back_in_filename_order() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let client_id = register_client(&storage).await;
+        // Stored in reverse, so insertion order cannot pass for filename order
+        for i in (0..50).rev() {
+            storage
+                .store_file_and_update_tree(
+                    &client_id,
+                    "batch",
+                    &format!("file-{:02}.txt", i),
+                    format!("content {}", i).as_bytes(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let filenames = storage
+            .load_batch_filenames(&client_id, "batch")
+            .await
+            .unwrap();
+        let expected_names: Vec<String> = (0..50).map(|i| format!("file-{:02}.txt", i)).collect();
+        assert_eq!(filenames, expected_names);
+
+        let mut contents = Vec::new();
+        for filename in &filenames {
+            contents.push(
+                storage
+                    .read_file(&client_id, "batch", filename)
+                    .await
+                    .unwrap(),
+            );
+        }
+        let expected: Vec<Vec<u8>> = (0..50)
+            .map(|i| format!("content {}", i).into_bytes())
+            .collect();
+        assert_eq!(contents, expected);
+
+        // The tree's leaves follow the same order
+        let tree = storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::from_data(&expected).unwrap().root_hash()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_content_is_stored_sealed_and_read_back_plain() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let external_dir =
+            std::env::temp_dir().join(format!("vs-db-encrypted-{}", std::process::id()));
+        let storage = storage.with_encryption(EncryptionConfig::new([9; 32]));
+        let client_id = register_client(&storage).await;
+        // Content unique to this run, so its blob is not one stored in another form before
+        let inline = format!("inline plaintext of {}", client_id).into_bytes();
+        let external = format!("external plaintext of {}", client_id).into_bytes();
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "a.txt", &inline)
+            .await
+            .unwrap();
+        let storage = storage.with_external_content_dir(&external_dir);
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "b.txt", &external)
+            .await
+            .unwrap();
+
+        let (stored_inline,): (Vec<u8>,) = sqlx::query_as(
+            "SELECT blobs.content FROM files JOIN blobs ON blobs.hash = files.blob_hash
+             WHERE client_id = $1 AND batch_id = $2 AND filename = $3",
+        )
+        .bind(&client_id)
+        .bind("batch")
+        .bind("a.txt")
+        .fetch_one(&storage.pool)
+        .await
+        .unwrap();
+        assert!(!stored_inline
+            .windows(inline.len())
+            .any(|w| w == inline.as_slice()));
+        let external_ref = hex::encode(hash_leaf(&external));
+        let stored_external =
+            tokio::fs::read(external_dir.join(&external_ref[..2]).join(&external_ref))
+                .await
+                .unwrap();
+        assert!(!stored_external
+            .windows(external.len())
+            .any(|w| w == external.as_slice()));
+
+        assert_eq!(
+            storage
+                .read_file(&client_id, "batch", "a.txt")
+                .await
+                .unwrap(),
+            inline
+        );
+        assert_eq!(
+            storage
+                .read_file(&client_id, "batch", "b.txt")
+                .await
+                .unwrap(),
+            external
+        );
+
+        // The tree is built over the plaintext, so roots and proofs are unchanged
+        let root = MerkleTree::from_data(&[inline, external])
+            .unwrap()
+            .root_hash();
+        let stored = storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.root_hash(), root);
+
+        storage.delete_batch(&client_id, "batch").await.unwrap();
+        tokio::fs::remove_dir_all(&external_dir).await.unwrap();
+    }
+
+    /// Reference count of the blob holding `content`, if there is one
+    async fn blob_refcount(storage: &DatabaseStorage, content: &[u8]) -> Option<i32> {
+        sqlx::query_scalar("SELECT refcount FROM blobs WHERE hash = $1")
+            .bind(hash_leaf(content).as_slice())
+            .fetch_optional(&storage.pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_identical_content_is_stored_once() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let client_id = register_client(&storage).await;
+        // Content unique to this run, as the blobs table is shared by every client
+        let content = format!("shared by {}", client_id).into_bytes();
+        for filename in ["a.txt", "b.txt"] {
+            storage
+                .store_file_and_update_tree(&client_id, "batch", filename, &content)
+                .await
+                .unwrap();
+        }
+        assert_eq!(blob_refcount(&storage, &content).await, Some(2));
+        for filename in ["a.txt", "b.txt"] {
+            assert_eq!(
+                storage
+                    .read_file(&client_id, "batch", filename)
+                    .await
+                    .unwrap(),
+                content
+            );
+        }
+
+        // Overwriting a file moves its reference to the new content's blob
+        let other = format!("other of {}", client_id).into_bytes();
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "b.txt", &other)
+            .await
+            .unwrap();
+        assert_eq!(blob_refcount(&storage, &content).await, Some(1));
+        assert_eq!(blob_refcount(&storage, &other).await, Some(1));
+
+        // The last file using a blob takes it along when deleted
+        storage
+            .delete_file(&client_id, "batch", "a.txt")
+            .await
+            .unwrap();
+        assert_eq!(blob_refcount(&storage, &content).await, None);
+        storage.delete_batch(&client_id, "batch").await.unwrap();
+        assert_eq!(blob_refcount(&storage, &other).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_inline_content_is_migrated_to_blobs() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let client_id = register_client(&storage).await;
+        let content = format!("legacy of {}", client_id).into_bytes();
+
+        // Rows written before the blobs table existed hold their content inline
+        Queries::create_batch(&storage.pool, &client_id, "batch")
+            .await
+            .unwrap();
+        for filename in ["a.txt", "b.txt"] {
+            sqlx::query(
+                "INSERT INTO files (client_id, batch_id, filename, content) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(&client_id)
+            .bind("batch")
+            .bind(filename)
+            .bind(&content)
+            .execute(&storage.pool)
+            .await
+            .unwrap();
+        }
+
+        Schema::initialize(&storage.pool).await.unwrap();
+
+        assert_eq!(blob_refcount(&storage, &content).await, Some(2));
+        let inline: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM files WHERE client_id = $1 AND content IS NOT NULL",
+        )
+        .bind(&client_id)
+        .fetch_one(&storage.pool)
+        .await
+        .unwrap();
+        assert_eq!(inline, 0);
+        assert_eq!(
+            storage
+                .read_file(&client_id, "batch", "b.txt")
+                .await
+                .unwrap(),
+            content
+        );
+        let leaves = Queries::compute_leaf_hashes_from_files(&storage.pool, &client_id, "batch")
+            .await
+            .unwrap();
+        assert_eq!(leaves, vec![hash_leaf(&content); 2]);
+
+        storage.delete_batch(&client_id, "batch").await.unwrap();
+        assert_eq!(blob_refcount(&storage, &content).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_stored_before_the_key_was_set_stays_readable() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let external_dir =
+            std::env::temp_dir().join(format!("vs-db-encrypted-later-{}", std::process::id()));
+        let client_id = register_client(&storage).await;
+        // Content unique to this run, so its blob is not one stored sealed before
+        let inline = format!("inline {}", client_id).into_bytes();
+        let external = format!("external {}", client_id).into_bytes();
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "a.txt", &inline)
+            .await
+            .unwrap();
+        let storage = storage.with_external_content_dir(&external_dir);
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "b.txt", &external)
+            .await
+            .unwrap();
+
+        let sealed = storage.with_encryption(EncryptionConfig::new([9; 32]));
+        assert_eq!(
+            sealed
+                .read_file(&client_id, "batch", "a.txt")
+                .await
+                .unwrap(),
+            inline
+        );
+        assert_eq!(
+            sealed
+                .read_file(&client_id, "batch", "b.txt")
+                .await
+                .unwrap(),
+            external
+        );
+
+        sealed.delete_batch(&client_id, "batch").await.unwrap();
+        let _ = tokio::fs::remove_dir_all(&external_dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_turning_compression_on_or_off_keeps_stored_content_readable() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let zstd = CompressionConfig::new(Compression::Zstd, 3).unwrap();
+        let client_id = register_client(&storage).await;
+        // Content unique to this run, so its blob is not one stored in another form before
+        let uncompressed = format!("stored uncompressed by {}\n", client_id).repeat(100);
+        let compressed = format!("stored compressed by {}\n", client_id).repeat(100);
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "a.txt", uncompressed.as_bytes())
+            .await
+            .unwrap();
+        let storage = storage.with_compression(zstd);
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "b.txt", compressed.as_bytes())
+            .await
+            .unwrap();
+        // The same content again under another name shares the blob stored compressed
+        let plain = test_storage().await.unwrap();
+        plain
+            .store_file_and_update_tree(&client_id, "batch", "c.txt", compressed.as_bytes())
+            .await
+            .unwrap();
+
+        for storage in [&plain, &storage] {
+            for (filename, content) in [
+                ("a.txt", &uncompressed),
+                ("b.txt", &compressed),
+                ("c.txt", &compressed),
+            ] {
+                assert_eq!(
+                    storage
+                        .read_file(&client_id, "batch", filename)
+                        .await
+                        .unwrap(),
+                    content.as_bytes()
+                );
+            }
+        }
+
+        plain.delete_batch(&client_id, "batch").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_compressed_content_is_stored_small_and_read_back_unchanged() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_DATABASE_URL not set; skipping");
+            return;
+        };
+        let zstd = CompressionConfig::new(Compression::Zstd, 3).unwrap();
+        let storage = storage.with_compression(zstd);
+        let client_id = register_client(&storage).await;
+        // Content unique to this run, so its blob is not one stored uncompressed before
+        let content = format!("{}\n", client_id).repeat(16_000).into_bytes();
+        assert!(content.len() > 1_000_000);
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "a.txt", &content)
+            .await
+            .unwrap();
+
+        let stored: i32 = sqlx::query_scalar("SELECT length(content) FROM blobs WHERE hash = $1")
+            .bind(hash_leaf(&content).as_slice())
+            .fetch_one(&storage.pool)
+            .await
+            .unwrap();
+        assert!((stored as usize) < content.len() / 50);
+        assert_eq!(
+            storage
+                .read_file(&client_id, "batch", "a.txt")
+                .await
+                .unwrap(),
+            content
+        );
+        let tree = storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tree.root_hash(), hash_leaf(&content));
+
+        storage.delete_batch(&client_id, "batch").await.unwrap();
     }
 }
@@ -0,0 +1,258 @@
+use crate::codec::ContentCodec;
+use crate::filesystem::{Durability, FilesystemStorage};
+use anyhow::{Context, Result};
+use crypto::hash_leaf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Content-addressed store for file bodies kept outside the database
+/// Files are keyed by the hex leaf hash of their content, so identical content
+/// uploaded under different names or batches is stored once.
+pub struct ExternalContentStore {
+    dir: PathBuf,
+    /// Compresses and encrypts stored content; references stay the leaf hash of the
+    /// uploaded bytes
+    codec: ContentCodec,
+}
+
+impl ExternalContentStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            codec: ContentCodec::default(),
+        }
+    }
+
+    /// Compress and encrypt content at rest as `codec` is configured
+    pub(super) fn with_codec(mut self, codec: ContentCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Path for a content reference, fanned out by the first two hex digits
+    fn content_path(&self, content_ref: &str) -> Result<PathBuf> {
+        // References come from the database; never let one escape the store directory
+        if content_ref.len() != 64 || !content_ref.bytes().all(|b| b.is_ascii_hexdigit()) {
+            anyhow::bail!("Invalid external content reference: {:?}", content_ref);
+        }
+        Ok(self.dir.join(&content_ref[..2]).join(content_ref))
+    }
+
+    /// Store content and return its reference (hex leaf hash)
+    pub async fn put(&self, content: &[u8]) -> Result<String> {
+        let content_ref = hex::encode(hash_leaf(content));
+        let path = self.content_path(&content_ref)?;
+        if !Self::touch(&path).await? {
+            Self::create_parent(&path).await?;
+            let content = self.codec.encode(content)?;
+            FilesystemStorage::write_file_atomic(&path, &content, Durability::Strict)
+                .await
+                .context("Failed to write external content")?;
+        }
+        Ok(content_ref)
+    }
+
+    /// Store the content of a local file without reading it into memory
+    /// (unless it is compressed or encrypted, which transforms it in one piece)
+    pub async fn put_file(&self, source: &Path) -> Result<String> {
+        if !self.codec.stores_file_unchanged(source).await? {
+            let content = tokio::fs::read(source)
+                .await
+                .with_context(|| format!("Failed to read source file: {:?}", source))?;
+            return self.put(&content).await;
+        }
+        let content_ref = hex::encode(FilesystemStorage::hash_file(source.to_path_buf()).await?);
+        let path = self.content_path(&content_ref)?;
+        if !Self::touch(&path).await? {
+            Self::create_parent(&path).await?;
+            FilesystemStorage::copy_file_atomic(source, &path, Durability::Strict)
+                .await
+                .context("Failed to copy external content")?;
+        }
+        Ok(content_ref)
+    }
+
+    /// Read content by reference
+    pub async fn get(&self, content_ref: &str) -> Result<Vec<u8>> {
+        let path = self.content_path(content_ref)?;
+        let content = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("Failed to read external content: {:?}", path))?;
+        self.codec
+            .decode(content)
+            .with_context(|| format!("Failed to read external content: {:?}", path))
+    }
+
+    /// References of stored content last written or reused at least `min_age` ago
+    /// Content is written before the row referring to it, so recent content may be
+    /// about to be referenced and must not be treated as unreferenced yet
+    pub async fn list_idle(&self, min_age: Duration) -> Result<Vec<String>> {
+        let mut idle = Vec::new();
+        if !self.dir.exists() {
+            return Ok(idle);
+        }
+        let cutoff = SystemTime::now() - min_age;
+        let mut fanout = tokio::fs::read_dir(&self.dir)
+            .await
+            .context("Failed to read external content directory")?;
+        while let Some(prefix) = fanout.next_entry().await? {
+            if !prefix.file_type().await?.is_dir() {
+                continue;
+            }
+            let mut entries = tokio::fs::read_dir(prefix.path()).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().to_string();
+                // Skips temp files of writes in progress
+                if self.content_path(&name).is_err() {
+                    continue;
+                }
+                if entry.metadata().await?.modified()? <= cutoff {
+                    idle.push(name);
+                }
+            }
+        }
+        idle.sort_unstable();
+        Ok(idle)
+    }
+
+    /// Delete content by reference unless it was written or reused within `min_age`,
+    /// returning whether it was deleted
+    /// The content is moved aside before its age is checked: a concurrent `put` either
+    /// reused it first, and it is moved back, or finds it gone and writes it again.
+    pub async fn remove_if_idle(&self, content_ref: &str, min_age: Duration) -> Result<bool> {
+        let path = self.content_path(content_ref)?;
+        let aside = path.with_extension("removing");
+        match tokio::fs::rename(&path, &aside).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            result => result
+                .with_context(|| format!("Failed to move external content aside: {:?}", path))?,
+        }
+        let modified = tokio::fs::metadata(&aside).await?.modified()?;
+        if modified > SystemTime::now() - min_age {
+            tokio::fs::rename(&aside, &path)
+                .await
+                .with_context(|| format!("Failed to restore external content: {:?}", path))?;
+            return Ok(false);
+        }
+        tokio::fs::remove_file(&aside)
+            .await
+            .with_context(|| format!("Failed to remove external content: {:?}", path))?;
+        Ok(true)
+    }
+
+    /// Mark existing content as just reused, so `list_idle` does not report it
+    /// Returns false if there is no such content
+    async fn touch(path: &Path) -> Result<bool> {
+        let file = match tokio::fs::OpenOptions::new().write(true).open(path).await {
+            Ok(file) => file.into_std().await,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e).context("Failed to open external content"),
+        };
+        tokio::task::spawn_blocking(move || file.set_modified(SystemTime::now()))
+            .await
+            .context("Failed to join touch task")?
+            .context("Failed to update external content modification time")?;
+        Ok(true)
+    }
+
+    async fn create_parent(path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create external content directory")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("vs-external-{}-{}", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_put_get_and_dedup() {
+        let dir = temp_store_dir("dedup");
+        let store = ExternalContentStore::new(&dir);
+
+        let first = store.put(b"same content").await.unwrap();
+        let second = store.put(b"same content").await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first, hex::encode(hash_leaf(b"same content")));
+        assert_eq!(store.get(&first).await.unwrap(), b"same content");
+
+        // File and in-memory puts agree on the reference
+        let source = dir.join("source.bin");
+        tokio::fs::write(&source, b"same content").await.unwrap();
+        assert_eq!(store.put_file(&source).await.unwrap(), first);
+
+        // One stored object for the shared content
+        let mut entries = tokio::fs::read_dir(dir.join(&first[..2])).await.unwrap();
+        let mut count = 0;
+        while entries.next_entry().await.unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 1);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reused_content_is_not_idle() {
+        let dir = temp_store_dir("idle");
+        let store = ExternalContentStore::new(&dir);
+        let content_ref = store.put(b"content").await.unwrap();
+        let hour = Duration::from_secs(3600);
+
+        assert_eq!(
+            store.list_idle(Duration::ZERO).await.unwrap(),
+            vec![content_ref.clone()]
+        );
+        assert!(store.list_idle(hour).await.unwrap().is_empty());
+
+        // Backdate it, then reuse it: the reuse counts as fresh
+        let path = store.content_path(&content_ref).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - 2 * hour)
+            .unwrap();
+        assert_eq!(
+            store.list_idle(hour).await.unwrap(),
+            vec![content_ref.clone()]
+        );
+        store.put(b"content").await.unwrap();
+        assert!(store.list_idle(hour).await.unwrap().is_empty());
+
+        // Reused content is kept even once listed as idle
+        assert!(!store.remove_if_idle(&content_ref, hour).await.unwrap());
+        assert_eq!(store.get(&content_ref).await.unwrap(), b"content");
+
+        assert!(store
+            .remove_if_idle(&content_ref, Duration::ZERO)
+            .await
+            .unwrap());
+        assert!(store.get(&content_ref).await.is_err());
+        assert!(!store
+            .remove_if_idle(&content_ref, Duration::ZERO)
+            .await
+            .unwrap());
+
+        // Content removed under a `put` that found it is written again
+        store.put(b"content").await.unwrap();
+        assert_eq!(store.get(&content_ref).await.unwrap(), b"content");
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_invalid_reference() {
+        let store = ExternalContentStore::new(temp_store_dir("invalid"));
+        assert!(store.get("../../etc/passwd").await.is_err());
+        assert!(store.get(&"g".repeat(64)).await.is_err());
+    }
+}
@@ -1,7 +1,16 @@
+use super::sql;
 use anyhow::{Context, Result};
-use crypto::hash_leaf;
 use merkle_tree::MerkleTree;
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
+use std::collections::BTreeMap;
+
+/// File content as recorded in a files row
+pub enum StoredContent {
+    /// Content stored in the database, in the blob the row refers to
+    Inline(Vec<u8>),
+    /// Reference (hex leaf hash) into the external content store
+    External(String),
+}
 
 /// Query operations for database storage
 pub struct Queries;
@@ -13,37 +22,149 @@ impl Queries {
         client_id: &str,
         batch_id: &str,
     ) -> Result<()> {
+        sqlx::query(sql::INSERT_BATCH)
+            .bind(client_id)
+            .bind(batch_id)
+            .execute(pool)
+            .await
+            .context("Failed to ensure batch exists")?;
+        Ok(())
+    }
+
+    /// Lock a batch row until the end of the transaction
+    pub async fn lock_batch(
+        pool: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<()> {
+        sqlx::query("SELECT 1 FROM batches WHERE client_id = $1 AND batch_id = $2 FOR UPDATE")
+            .bind(client_id)
+            .bind(batch_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to lock batch row")?;
+        Ok(())
+    }
+
+    /// Count the files in a batch other than `filename`
+    pub async fn count_other_files(
+        pool: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> Result<i64> {
+        sqlx::query_scalar(sql::COUNT_OTHER_FILES)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(filename)
+            .fetch_one(pool)
+            .await
+            .context("Failed to count batch files")
+    }
+
+    /// Store file content as the blob with the given leaf hash, shared with every other
+    /// file of the same content
+    /// `leaf_hash` is that of the plaintext, which for encrypted content the database
+    /// never sees; the caller computes it, as it also keys the blob
+    pub async fn store_file(
+        conn: &mut PgConnection,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        content: &[u8],
+        leaf_hash: &[u8; 32],
+    ) -> Result<()> {
+        let replaced = Self::file_blob(&mut *conn, client_id, batch_id, filename).await?;
+        sqlx::query(
+            "INSERT INTO blobs (hash, content, refcount) VALUES ($1, $2, 1)
+             ON CONFLICT (hash) DO UPDATE SET refcount = blobs.refcount + 1",
+        )
+        .bind(leaf_hash.as_slice())
+        .bind(content)
+        .execute(&mut *conn)
+        .await
+        .context("Failed to store blob")?;
         sqlx::query(
-            "INSERT INTO batches (client_id, batch_id) VALUES ($1, $2) 
-             ON CONFLICT (client_id, batch_id) DO NOTHING",
+            "INSERT INTO files (client_id, batch_id, filename, blob_hash) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (client_id, batch_id, filename)
+             DO UPDATE SET content = NULL, content_ref = NULL, leaf_hash = NULL,
+                           blob_hash = EXCLUDED.blob_hash, created_at = CURRENT_TIMESTAMP",
         )
         .bind(client_id)
         .bind(batch_id)
-        .execute(pool)
+        .bind(filename)
+        .bind(leaf_hash.as_slice())
+        .execute(&mut *conn)
         .await
-        .context("Failed to ensure batch exists")?;
-        Ok(())
+        .context("Failed to store file")?;
+        Self::release_blobs(conn, &replaced).await
     }
 
-    /// Store file content
-    pub async fn store_file(
-        pool: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    /// Store a reference to externally stored file content
+    pub async fn store_file_ref(
+        conn: &mut PgConnection,
         client_id: &str,
         batch_id: &str,
         filename: &str,
-        content: &[u8],
+        content_ref: &str,
     ) -> Result<()> {
+        let replaced = Self::file_blob(&mut *conn, client_id, batch_id, filename).await?;
         sqlx::query(
-            "INSERT INTO files (client_id, batch_id, filename, content) VALUES ($1, $2, $3, $4)
-             ON CONFLICT (client_id, batch_id, filename) DO UPDATE SET content = EXCLUDED.content",
+            "INSERT INTO files (client_id, batch_id, filename, content_ref) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (client_id, batch_id, filename)
+             DO UPDATE SET content = NULL, content_ref = EXCLUDED.content_ref,
+                           leaf_hash = NULL, blob_hash = NULL, created_at = CURRENT_TIMESTAMP",
         )
         .bind(client_id)
         .bind(batch_id)
         .bind(filename)
-        .bind(content)
-        .execute(pool)
+        .bind(content_ref)
+        .execute(&mut *conn)
         .await
-        .context("Failed to store file")?;
+        .context("Failed to store file reference")?;
+        Self::release_blobs(conn, &replaced).await
+    }
+
+    /// Hash of the blob a file refers to (none if the file is missing or stored externally)
+    async fn file_blob(
+        conn: &mut PgConnection,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> Result<Vec<Vec<u8>>> {
+        let blob_hash: Option<Option<Vec<u8>>> = sqlx::query_scalar(
+            "SELECT blob_hash FROM files WHERE client_id = $1 AND batch_id = $2 AND filename = $3",
+        )
+        .bind(client_id)
+        .bind(batch_id)
+        .bind(filename)
+        .fetch_optional(conn)
+        .await
+        .context("Failed to look up file blob")?;
+        Ok(blob_hash.flatten().into_iter().collect())
+    }
+
+    /// Drop one reference to each listed blob (a hash listed twice loses two), once the
+    /// files rows using them are gone or changed, and delete blobs nothing uses any more
+    pub async fn release_blobs(conn: &mut PgConnection, blob_hashes: &[Vec<u8>]) -> Result<()> {
+        if blob_hashes.is_empty() {
+            return Ok(());
+        }
+        sqlx::query(
+            "UPDATE blobs SET refcount = blobs.refcount - released.count
+             FROM (SELECT hash, COUNT(*) AS count FROM UNNEST($1::BYTEA[]) AS hash GROUP BY hash)
+                  AS released
+             WHERE blobs.hash = released.hash",
+        )
+        .bind(blob_hashes)
+        .execute(&mut *conn)
+        .await
+        .context("Failed to release blobs")?;
+        sqlx::query("DELETE FROM blobs WHERE hash = ANY($1) AND refcount = 0")
+            .bind(blob_hashes)
+            .execute(&mut *conn)
+            .await
+            .context("Failed to delete unused blobs")?;
         Ok(())
     }
 
@@ -53,9 +174,11 @@ impl Queries {
         client_id: &str,
         batch_id: &str,
         filename: &str,
-    ) -> Result<Option<Vec<u8>>> {
-        let row = sqlx::query_as::<_, (Vec<u8>,)>(
-            "SELECT content FROM files WHERE client_id = $1 AND batch_id = $2 AND filename = $3",
+    ) -> Result<Option<StoredContent>> {
+        let row = sqlx::query_as::<_, (Option<Vec<u8>>, Option<String>)>(
+            "SELECT COALESCE(blobs.content, files.content), files.content_ref
+             FROM files LEFT JOIN blobs ON blobs.hash = files.blob_hash
+             WHERE client_id = $1 AND batch_id = $2 AND filename = $3",
         )
         .bind(client_id)
         .bind(batch_id)
@@ -64,20 +187,309 @@ impl Queries {
         .await
         .context("Failed to query file")?;
 
-        Ok(row.map(|(content,)| content))
+        match row {
+            None => Ok(None),
+            Some((_, Some(content_ref))) => Ok(Some(StoredContent::External(content_ref))),
+            Some((Some(content), None)) => Ok(Some(StoredContent::Inline(content))),
+            Some((None, None)) => {
+                anyhow::bail!("File {} has neither content nor reference", filename)
+            }
+        }
+    }
+
+    /// Create a batch; returns false if it already exists
+    pub async fn create_batch(pool: &PgPool, client_id: &str, batch_id: &str) -> Result<bool> {
+        let result = sqlx::query(sql::INSERT_BATCH)
+            .bind(client_id)
+            .bind(batch_id)
+            .execute(pool)
+            .await
+            .context("Failed to create batch")?;
+        Ok(result.rows_affected() == 1)
     }
 
     /// Check if batch exists
     pub async fn batch_exists(pool: &PgPool, client_id: &str, batch_id: &str) -> Result<bool> {
-        let exists: bool = sqlx::query_scalar(
-            "SELECT EXISTS(SELECT 1 FROM batches WHERE client_id = $1 AND batch_id = $2)",
+        let exists: bool = sqlx::query_scalar(sql::BATCH_EXISTS)
+            .bind(client_id)
+            .bind(batch_id)
+            .fetch_one(pool)
+            .await
+            .context("Failed to check batch existence")?;
+        Ok(exists)
+    }
+
+    /// Set the public flag on a batch
+    pub async fn set_batch_public(
+        pool: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+        client_id: &str,
+        batch_id: &str,
+        public: bool,
+    ) -> Result<()> {
+        sqlx::query(sql::SET_BATCH_PUBLIC)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(public)
+            .execute(pool)
+            .await
+            .context("Failed to update batch visibility")?;
+        Ok(())
+    }
+
+    /// Check if batch is public (false if the batch does not exist)
+    pub async fn is_batch_public(pool: &PgPool, client_id: &str, batch_id: &str) -> Result<bool> {
+        let public: Option<bool> = sqlx::query_scalar(sql::IS_BATCH_PUBLIC)
+            .bind(client_id)
+            .bind(batch_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to check batch visibility")?;
+        Ok(public.unwrap_or(false))
+    }
+
+    /// Replace the annotations stored on a batch
+    pub async fn set_batch_annotations(
+        pool: &PgPool,
+        client_id: &str,
+        batch_id: &str,
+        annotations: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let annotations =
+            serde_json::to_value(annotations).context("Failed to serialize annotations")?;
+        sqlx::query(sql::SET_BATCH_ANNOTATIONS)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(annotations)
+            .execute(pool)
+            .await
+            .context("Failed to update batch annotations")?;
+        Ok(())
+    }
+
+    /// Load a batch's annotations (empty if the batch does not exist)
+    pub async fn load_batch_annotations(
+        pool: &PgPool,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<BTreeMap<String, String>> {
+        let annotations: Option<serde_json::Value> =
+            sqlx::query_scalar(sql::LOAD_BATCH_ANNOTATIONS)
+                .bind(client_id)
+                .bind(batch_id)
+                .fetch_optional(pool)
+                .await
+                .context("Failed to load batch annotations")?;
+        match annotations {
+            Some(annotations) => {
+                serde_json::from_value(annotations).context("Failed to deserialize annotations")
+            }
+            None => Ok(BTreeMap::new()),
+        }
+    }
+
+    /// Record the root committed to by a finalize
+    pub async fn set_committed_root(
+        pool: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+        client_id: &str,
+        batch_id: &str,
+        root: &[u8; 32],
+    ) -> Result<()> {
+        sqlx::query(sql::SET_COMMITTED_ROOT)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(root.as_slice())
+            .execute(pool)
+            .await
+            .context("Failed to update committed root")?;
+        Ok(())
+    }
+
+    /// Load the root recorded by the batch's last finalize (none if never finalized)
+    pub async fn load_committed_root(
+        pool: &PgPool,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<Option<[u8; 32]>> {
+        let root: Option<Option<Vec<u8>>> = sqlx::query_scalar(sql::LOAD_COMMITTED_ROOT)
+            .bind(client_id)
+            .bind(batch_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to load committed root")?;
+        root.flatten()
+            .map(|bytes| {
+                bytes
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("Stored committed root is not 32 bytes"))
+            })
+            .transpose()
+    }
+
+    /// Delete rows of a batch that nothing refers to any more: a Merkle tree left behind
+    /// once the batch has no files. Returns the number of rows deleted
+    pub async fn delete_orphaned_rows(
+        conn: &mut PgConnection,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<u64> {
+        let result = sqlx::query(sql::DELETE_ORPHANED_TREE)
+            .bind(client_id)
+            .bind(batch_id)
+            .execute(&mut *conn)
+            .await
+            .context("Failed to delete orphaned Merkle tree")?;
+        Ok(result.rows_affected())
+    }
+
+    /// Every external content reference still used by a file, across all batches
+    pub async fn referenced_content(pool: &PgPool) -> Result<std::collections::HashSet<String>> {
+        let refs: Vec<String> = sqlx::query_scalar(
+            "SELECT DISTINCT content_ref FROM files WHERE content_ref IS NOT NULL",
+        )
+        .fetch_all(pool)
+        .await
+        .context("Failed to load external content references")?;
+        Ok(refs.into_iter().collect())
+    }
+
+    /// Grant a client read access to a batch
+    pub async fn grant_batch_access(
+        pool: &PgPool,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> Result<()> {
+        sqlx::query(sql::GRANT_BATCH_ACCESS)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(grantee_id)
+            .execute(pool)
+            .await
+            .context("Failed to grant batch access")?;
+        Ok(())
+    }
+
+    /// Revoke a client's read access to a batch
+    pub async fn revoke_batch_access(
+        pool: &PgPool,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> Result<()> {
+        sqlx::query(sql::REVOKE_BATCH_ACCESS)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(grantee_id)
+            .execute(pool)
+            .await
+            .context("Failed to revoke batch access")?;
+        Ok(())
+    }
+
+    /// Check if a client was granted read access to a batch
+    pub async fn has_batch_access(
+        pool: &PgPool,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> Result<bool> {
+        let granted: bool = sqlx::query_scalar(sql::HAS_BATCH_ACCESS)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(grantee_id)
+            .fetch_one(pool)
+            .await
+            .context("Failed to check batch access")?;
+        Ok(granted)
+    }
+
+    /// Move a batch and everything referencing it to a new batch_id
+    /// Must run inside a transaction; the new batch row is created first so that
+    /// foreign keys from files and merkle_trees stay valid throughout
+    pub async fn rename_batch(
+        conn: &mut PgConnection,
+        client_id: &str,
+        old_batch_id: &str,
+        new_batch_id: &str,
+    ) -> Result<()> {
+        sqlx::query(sql::COPY_BATCH)
+            .bind(client_id)
+            .bind(old_batch_id)
+            .bind(new_batch_id)
+            .execute(&mut *conn)
+            .await
+            .context("Failed to create renamed batch")?;
+
+        for table in sql::BATCH_CHILD_TABLES {
+            sqlx::query(&sql::move_batch_rows(table))
+                .bind(client_id)
+                .bind(old_batch_id)
+                .bind(new_batch_id)
+                .execute(&mut *conn)
+                .await
+                .with_context(|| format!("Failed to move {} to renamed batch", table))?;
+        }
+
+        sqlx::query(sql::DELETE_BATCH)
+            .bind(client_id)
+            .bind(old_batch_id)
+            .execute(&mut *conn)
+            .await
+            .context("Failed to remove old batch")?;
+        Ok(())
+    }
+
+    /// Delete a batch; its tree and access grants are removed by cascade
+    /// Its files are deleted first, releasing their blobs. Returns false if the batch
+    /// did not exist
+    pub async fn delete_batch(
+        conn: &mut PgConnection,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<bool> {
+        let blob_hashes: Vec<Option<Vec<u8>>> = sqlx::query_scalar(
+            "DELETE FROM files WHERE client_id = $1 AND batch_id = $2 RETURNING blob_hash",
         )
         .bind(client_id)
         .bind(batch_id)
-        .fetch_one(pool)
+        .fetch_all(&mut *conn)
         .await
-        .context("Failed to check batch existence")?;
-        Ok(exists)
+        .context("Failed to delete batch files")?;
+        let result = sqlx::query(sql::DELETE_BATCH)
+            .bind(client_id)
+            .bind(batch_id)
+            .execute(&mut *conn)
+            .await
+            .context("Failed to delete batch")?;
+        let blob_hashes: Vec<Vec<u8>> = blob_hashes.into_iter().flatten().collect();
+        Self::release_blobs(conn, &blob_hashes).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Delete one file row of a batch, releasing its blob
+    /// Returns false if the batch has no such file
+    pub async fn delete_file(
+        conn: &mut PgConnection,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> Result<bool> {
+        let deleted: Option<Option<Vec<u8>>> = sqlx::query_scalar(
+            "DELETE FROM files WHERE client_id = $1 AND batch_id = $2 AND filename = $3
+             RETURNING blob_hash",
+        )
+        .bind(client_id)
+        .bind(batch_id)
+        .bind(filename)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("Failed to delete file")?;
+        let Some(blob_hash) = deleted else {
+            return Ok(false);
+        };
+        let blob_hashes: Vec<Vec<u8>> = blob_hash.into_iter().collect();
+        Self::release_blobs(conn, &blob_hashes).await?;
+        Ok(true)
     }
 
     /// Check if file exists
@@ -87,16 +499,37 @@ impl Queries {
         batch_id: &str,
         filename: &str,
     ) -> Result<bool> {
-        let exists: bool = sqlx::query_scalar(
-            "SELECT EXISTS(SELECT 1 FROM files WHERE client_id = $1 AND batch_id = $2 AND filename = $3)",
+        let exists: bool = sqlx::query_scalar(sql::FILE_EXISTS)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(filename)
+            .fetch_one(pool)
+            .await
+            .context("Failed to check file existence")?;
+        Ok(exists)
+    }
+
+    /// Load when a file's current content was stored, in milliseconds since the Unix epoch
+    pub async fn file_stored_at(
+        pool: &PgPool,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> Result<Option<u64>> {
+        // created_at has no time zone; casting interprets it in the session's zone,
+        // the same one CURRENT_TIMESTAMP was stored in
+        let stored_at: Option<i64> = sqlx::query_scalar(
+            "SELECT (EXTRACT(EPOCH FROM created_at::TIMESTAMPTZ) * 1000)::BIGINT FROM files
+             WHERE client_id = $1 AND batch_id = $2 AND filename = $3",
         )
         .bind(client_id)
         .bind(batch_id)
         .bind(filename)
-        .fetch_one(pool)
+        .fetch_optional(pool)
         .await
-        .context("Failed to check file existence")?;
-        Ok(exists)
+        .context("Failed to load file timestamp")?
+        .flatten();
+        Ok(stored_at.map(|ms| ms as u64))
     }
 
     /// Load batch filenames from files table
@@ -105,68 +538,155 @@ impl Queries {
         client_id: &str,
         batch_id: &str,
     ) -> Result<Vec<String>> {
-        let rows = sqlx::query_as::<_, (String,)>(
-            "SELECT filename FROM files 
-             WHERE client_id = $1 AND batch_id = $2 ORDER BY filename",
-        )
-        .bind(client_id)
-        .bind(batch_id)
-        .fetch_all(pool)
-        .await
-        .context("Failed to load batch filenames")?;
+        let rows = sqlx::query_as::<_, (String,)>(sql::LOAD_BATCH_FILENAMES)
+            .bind(client_id)
+            .bind(batch_id)
+            .fetch_all(pool)
+            .await
+            .context("Failed to load batch filenames")?;
 
         Ok(rows.into_iter().map(|(filename,)| filename).collect())
     }
 
+    /// List all batches as (client_id, batch_id) pairs
+    pub async fn list_batches(pool: &PgPool) -> Result<Vec<(String, String)>> {
+        sqlx::query_as::<_, (String, String)>(sql::LIST_BATCHES)
+            .fetch_all(pool)
+            .await
+            .context("Failed to list batches")
+    }
+
+    /// List one client's batch IDs, sorted
+    pub async fn list_client_batches(pool: &PgPool, client_id: &str) -> Result<Vec<String>> {
+        sqlx::query_scalar::<_, String>(sql::LIST_CLIENT_BATCHES)
+            .bind(client_id)
+            .fetch_all(pool)
+            .await
+            .context("Failed to list client batches")
+    }
+
     /// Store public key
     pub async fn store_public_key(pool: &PgPool, client_id: &str, public_key: &[u8]) -> Result<()> {
-        sqlx::query(
-            "INSERT INTO clients (client_id, public_key) VALUES ($1, $2)
-             ON CONFLICT (client_id) DO UPDATE SET public_key = EXCLUDED.public_key",
-        )
-        .bind(client_id)
-        .bind(public_key)
-        .execute(pool)
-        .await
-        .context("Failed to store public key")?;
+        sqlx::query(sql::STORE_PUBLIC_KEY)
+            .bind(client_id)
+            .bind(public_key)
+            .execute(pool)
+            .await
+            .context("Failed to store public key")?;
         Ok(())
     }
 
+    /// Check if client is registered
+    pub async fn client_exists(pool: &PgPool, client_id: &str) -> Result<bool> {
+        let exists: bool = sqlx::query_scalar(sql::CLIENT_EXISTS)
+            .bind(client_id)
+            .fetch_one(pool)
+            .await
+            .context("Failed to check client existence")?;
+        Ok(exists)
+    }
+
+    /// Count registered clients
+    pub async fn count_clients(pool: &PgPool) -> Result<u64> {
+        let count: i64 = sqlx::query_scalar(sql::COUNT_CLIENTS)
+            .fetch_one(pool)
+            .await
+            .context("Failed to count clients")?;
+        Ok(count as u64)
+    }
+
     /// Load public key
     pub async fn load_public_key(pool: &PgPool, client_id: &str) -> Result<Option<Vec<u8>>> {
-        let row =
-            sqlx::query_as::<_, (Vec<u8>,)>("SELECT public_key FROM clients WHERE client_id = $1")
-                .bind(client_id)
-                .fetch_optional(pool)
-                .await
-                .context("Failed to load public key")?;
+        let row = sqlx::query_as::<_, (Vec<u8>,)>(sql::LOAD_PUBLIC_KEY)
+            .bind(client_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to load public key")?;
 
         Ok(row.map(|(key,)| key))
     }
 
-    /// Compute leaf hashes from file contents
-    /// Reads all files in the batch and computes their leaf hashes
+    /// Compute leaf hashes for all files in the batch, in filename order
     pub async fn compute_leaf_hashes_from_files(
-        pool: &PgPool,
+        pool: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
         client_id: &str,
         batch_id: &str,
     ) -> Result<Vec<[u8; 32]>> {
-        // Load all filenames sorted
-        let filenames = Self::load_batch_filenames(pool, client_id, batch_id).await?;
-
-        // Compute leaf hash for each file
-        let mut leaf_hashes = Vec::new();
-        for filename in filenames {
-            let content = Self::read_file(pool, client_id, batch_id, &filename)
-                .await?
-                .ok_or_else(|| anyhow::anyhow!("File {} not found", filename))?;
-
-            // Use crypto::hash_leaf to compute hash (same as used during upload)
-            let leaf_hash = hash_leaf(&content);
-            leaf_hashes.push(leaf_hash);
-        }
+        let leaves = Self::leaf_hashes_by_filename(pool, client_id, batch_id).await?;
+        Ok(leaves.into_iter().map(|(_, leaf_hash)| leaf_hash).collect())
+    }
+
+    /// Every file of the batch with its leaf hash, in filename order
+    /// Inline content is hashed inside PostgreSQL (sha256 over 0x00 || content, the same
+    /// as crypto::hash_leaf), so file bodies never leave the database; encrypted inline
+    /// content has its leaf hash recorded instead, and external references already are
+    /// the leaf hash. Memory use is one hash per file.
+    pub async fn leaf_hashes_by_filename(
+        pool: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<Vec<(String, [u8; 32])>> {
+        let rows = sqlx::query_as::<_, (String, Option<Vec<u8>>, Option<String>)>(
+            "SELECT filename,
+                    CASE WHEN blob_hash IS NOT NULL THEN blob_hash
+                         WHEN leaf_hash IS NOT NULL THEN leaf_hash
+                         WHEN content IS NOT NULL THEN sha256(decode('00', 'hex') || content) END,
+                    content_ref
+             FROM files
+             WHERE client_id = $1 AND batch_id = $2 ORDER BY filename",
+        )
+        .bind(client_id)
+        .bind(batch_id)
+        .fetch_all(pool)
+        .await
+        .context("Failed to compute leaf hashes")?;
 
-        Ok(leaf_hashes)
+        rows.into_iter()
+            .map(|(filename, inline_hash, content_ref)| {
+                let mut leaf_hash = [0u8; 32];
+                match (content_ref, inline_hash) {
+                    (Some(content_ref), _) => hex::decode_to_slice(&content_ref, &mut leaf_hash)
+                        .with_context(|| {
+                            format!("Invalid content reference for file {}", filename)
+                        })?,
+                    (None, Some(hash)) => {
+                        anyhow::ensure!(
+                            hash.len() == 32,
+                            "Unexpected hash length for file {}",
+                            filename
+                        );
+                        leaf_hash.copy_from_slice(&hash);
+                    }
+                    (None, None) => {
+                        anyhow::bail!("File {} has neither content nor reference", filename)
+                    }
+                }
+                Ok((filename, leaf_hash))
+            })
+            .collect()
+    }
+
+    /// Delete every file of the batch not named in `keep`, releasing their blobs
+    pub async fn delete_files_except(
+        conn: &mut PgConnection,
+        client_id: &str,
+        batch_id: &str,
+        keep: &[String],
+    ) -> Result<u64> {
+        let blob_hashes: Vec<Option<Vec<u8>>> = sqlx::query_scalar(
+            "DELETE FROM files WHERE client_id = $1 AND batch_id = $2
+             AND NOT (filename = ANY($3)) RETURNING blob_hash",
+        )
+        .bind(client_id)
+        .bind(batch_id)
+        .bind(keep)
+        .fetch_all(&mut *conn)
+        .await
+        .context("Failed to delete replaced files")?;
+        let deleted = blob_hashes.len() as u64;
+        let blob_hashes: Vec<Vec<u8>> = blob_hashes.into_iter().flatten().collect();
+        Self::release_blobs(conn, &blob_hashes).await?;
+        Ok(deleted)
     }
 
     /// Store Merkle tree structure
@@ -178,18 +698,13 @@ impl Queries {
     ) -> Result<()> {
         let tree_json = serde_json::to_value(tree).context("Failed to serialize Merkle tree")?;
 
-        sqlx::query(
-            "INSERT INTO merkle_trees (client_id, batch_id, tree_data) 
-             VALUES ($1, $2, $3)
-             ON CONFLICT (client_id, batch_id) 
-             DO UPDATE SET tree_data = EXCLUDED.tree_data, updated_at = CURRENT_TIMESTAMP",
-        )
-        .bind(client_id)
-        .bind(batch_id)
-        .bind(tree_json)
-        .execute(pool)
-        .await
-        .context("Failed to store Merkle tree")?;
+        sqlx::query(sql::STORE_MERKLE_TREE)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(tree_json)
+            .execute(pool)
+            .await
+            .context("Failed to store Merkle tree")?;
         Ok(())
     }
 
@@ -199,14 +714,12 @@ impl Queries {
         client_id: &str,
         batch_id: &str,
     ) -> Result<Option<MerkleTree>> {
-        let row = sqlx::query_as::<_, (serde_json::Value,)>(
-            "SELECT tree_data FROM merkle_trees WHERE client_id = $1 AND batch_id = $2",
-        )
-        .bind(client_id)
-        .bind(batch_id)
-        .fetch_optional(pool)
-        .await
-        .context("Failed to load Merkle tree")?;
+        let row = sqlx::query_as::<_, (serde_json::Value,)>(sql::LOAD_MERKLE_TREE)
+            .bind(client_id)
+            .bind(batch_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to load Merkle tree")?;
 
         match row {
             Some((tree_json,)) => {
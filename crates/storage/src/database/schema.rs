@@ -10,8 +10,11 @@ impl Schema {
     pub async fn initialize(pool: &PgPool) -> Result<()> {
         Self::create_clients_table(pool).await?;
         Self::create_batches_table(pool).await?;
+        Self::create_blobs_table(pool).await?;
         Self::create_files_table(pool).await?;
+        Self::migrate_inline_content(pool).await?;
         Self::create_merkle_trees_table(pool).await?;
+        Self::create_batch_acl_table(pool).await?;
         Self::create_indexes(pool).await?;
         info!("PostgreSQL database storage initialized");
         Ok(())
@@ -41,6 +44,9 @@ impl Schema {
             CREATE TABLE IF NOT EXISTS batches (
                 client_id VARCHAR(255) NOT NULL,
                 batch_id VARCHAR(255) NOT NULL,
+                is_public BOOLEAN NOT NULL DEFAULT FALSE,
+                batch_metadata JSONB NOT NULL DEFAULT '{}',
+                committed_root BYTEA,
                 created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
                 PRIMARY KEY (client_id, batch_id),
                 FOREIGN KEY (client_id) REFERENCES clients(client_id) ON DELETE CASCADE
@@ -50,6 +56,45 @@ impl Schema {
         .execute(pool)
         .await
         .context("Failed to create batches table")?;
+
+        // Databases created before public batches existed lack the column
+        sqlx::query(
+            "ALTER TABLE batches ADD COLUMN IF NOT EXISTS is_public BOOLEAN NOT NULL DEFAULT FALSE",
+        )
+        .execute(pool)
+        .await
+        .context("Failed to add is_public column to batches table")?;
+
+        // Likewise for batch annotations
+        sqlx::query(
+            "ALTER TABLE batches ADD COLUMN IF NOT EXISTS batch_metadata JSONB NOT NULL DEFAULT '{}'",
+        )
+        .execute(pool)
+        .await
+        .context("Failed to add batch_metadata column to batches table")?;
+
+        // And for roots committed by finalize
+        sqlx::query("ALTER TABLE batches ADD COLUMN IF NOT EXISTS committed_root BYTEA")
+            .execute(pool)
+            .await
+            .context("Failed to add committed_root column to batches table")?;
+        Ok(())
+    }
+
+    /// Create blobs table holding inline file content once per leaf hash
+    async fn create_blobs_table(pool: &PgPool) -> Result<()> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS blobs (
+                hash BYTEA PRIMARY KEY,
+                content BYTEA NOT NULL,
+                refcount INT NOT NULL CHECK (refcount >= 0)
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create blobs table")?;
         Ok(())
     }
 
@@ -61,7 +106,10 @@ impl Schema {
                 client_id VARCHAR(255) NOT NULL,
                 batch_id VARCHAR(255) NOT NULL,
                 filename VARCHAR(255) NOT NULL,
-                content BYTEA NOT NULL,
+                content BYTEA,
+                content_ref VARCHAR(64),
+                leaf_hash BYTEA,
+                blob_hash BYTEA REFERENCES blobs(hash),
                 created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
                 PRIMARY KEY (client_id, batch_id, filename),
                 FOREIGN KEY (client_id, batch_id) REFERENCES batches(client_id, batch_id) ON DELETE CASCADE
@@ -71,6 +119,85 @@ impl Schema {
         .execute(pool)
         .await
         .context("Failed to create files table")?;
+
+        // Databases created before external content existed: content was mandatory
+        // and there was no reference column. Exactly one of the two is set per row.
+        sqlx::query("ALTER TABLE files ADD COLUMN IF NOT EXISTS content_ref VARCHAR(64)")
+            .execute(pool)
+            .await
+            .context("Failed to add content_ref column to files table")?;
+        sqlx::query("ALTER TABLE files ALTER COLUMN content DROP NOT NULL")
+            .execute(pool)
+            .await
+            .context("Failed to make files.content nullable")?;
+
+        // Leaf hash of encrypted inline content, which PostgreSQL cannot hash itself
+        sqlx::query("ALTER TABLE files ADD COLUMN IF NOT EXISTS leaf_hash BYTEA")
+            .execute(pool)
+            .await
+            .context("Failed to add leaf_hash column to files table")?;
+
+        // Inline content moved to the blobs table; rows refer to it by leaf hash
+        sqlx::query(
+            "ALTER TABLE files ADD COLUMN IF NOT EXISTS blob_hash BYTEA REFERENCES blobs(hash)",
+        )
+        .execute(pool)
+        .await
+        .context("Failed to add blob_hash column to files table")?;
+        Ok(())
+    }
+
+    /// Move content stored inline in files rows, by databases created before the blobs
+    /// table existed, into blobs: one per distinct leaf hash, counting the rows using it
+    async fn migrate_inline_content(pool: &PgPool) -> Result<()> {
+        let pending: bool =
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM files WHERE content IS NOT NULL)")
+                .fetch_one(pool)
+                .await
+                .context("Failed to check for inline file content")?;
+        if !pending {
+            return Ok(());
+        }
+
+        let mut tx = pool
+            .begin()
+            .await
+            .context("Failed to begin inline content migration")?;
+        // Servers starting together would otherwise count the same rows twice
+        sqlx::query("LOCK TABLE files IN EXCLUSIVE MODE")
+            .execute(&mut *tx)
+            .await
+            .context("Failed to lock files table")?;
+        // Encrypted rows carry the leaf hash of their plaintext; the others are hashed
+        // like crypto::hash_leaf
+        sqlx::query(
+            r#"
+            INSERT INTO blobs (hash, content, refcount)
+            SELECT DISTINCT ON (hash) hash, content, COUNT(*) OVER (PARTITION BY hash)
+            FROM (
+                SELECT COALESCE(leaf_hash, sha256(decode('00', 'hex') || content)) AS hash, content
+                FROM files WHERE content IS NOT NULL
+            ) AS inline
+            ON CONFLICT (hash) DO UPDATE SET refcount = blobs.refcount + EXCLUDED.refcount
+            "#,
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to copy inline file content to blobs")?;
+        let migrated = sqlx::query(
+            "UPDATE files
+             SET blob_hash = COALESCE(leaf_hash, sha256(decode('00', 'hex') || content)),
+                 content = NULL, leaf_hash = NULL
+             WHERE content IS NOT NULL",
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to point files at their blobs")?
+        .rows_affected();
+        tx.commit()
+            .await
+            .context("Failed to commit inline content migration")?;
+        info!("Moved the content of {} files to the blobs table", migrated);
         Ok(())
     }
 
@@ -94,6 +221,26 @@ impl Schema {
         Ok(())
     }
 
+    /// Create batch_acl table listing clients granted read access to a batch
+    async fn create_batch_acl_table(pool: &PgPool) -> Result<()> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS batch_acl (
+                client_id VARCHAR(255) NOT NULL,
+                batch_id VARCHAR(255) NOT NULL,
+                grantee_id VARCHAR(255) NOT NULL,
+                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (client_id, batch_id, grantee_id),
+                FOREIGN KEY (client_id, batch_id) REFERENCES batches(client_id, batch_id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create batch_acl table")?;
+        Ok(())
+    }
+
     /// Create indexes for better query performance
     async fn create_indexes(pool: &PgPool) -> Result<()> {
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_batches_client ON batches(client_id)")
@@ -105,6 +252,12 @@ impl Schema {
             .execute(pool)
             .await
             .context("Failed to create files index")?;
+
+        // Deleting a blob checks that no files row still refers to it
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_files_blob ON files(blob_hash)")
+            .execute(pool)
+            .await
+            .context("Failed to create files blob index")?;
         Ok(())
     }
 }
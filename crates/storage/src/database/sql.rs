@@ -0,0 +1,116 @@
+/// Create a batch unless it exists
+pub const INSERT_BATCH: &str = "INSERT INTO batches (client_id, batch_id) VALUES ($1, $2)
+     ON CONFLICT (client_id, batch_id) DO NOTHING";
+
+/// Check whether a batch exists
+pub const BATCH_EXISTS: &str =
+    "SELECT EXISTS(SELECT 1 FROM batches WHERE client_id = $1 AND batch_id = $2)";
+
+/// Count the files of a batch other than the named one
+pub const COUNT_OTHER_FILES: &str = "SELECT COUNT(*) FROM files
+     WHERE client_id = $1 AND batch_id = $2 AND filename <> $3";
+
+/// Set a batch's public flag
+pub const SET_BATCH_PUBLIC: &str =
+    "UPDATE batches SET is_public = $3 WHERE client_id = $1 AND batch_id = $2";
+
+/// Load a batch's public flag
+pub const IS_BATCH_PUBLIC: &str =
+    "SELECT is_public FROM batches WHERE client_id = $1 AND batch_id = $2";
+
+/// Replace a batch's annotations (JSON)
+pub const SET_BATCH_ANNOTATIONS: &str =
+    "UPDATE batches SET batch_metadata = $3 WHERE client_id = $1 AND batch_id = $2";
+
+/// Load a batch's annotations (JSON)
+pub const LOAD_BATCH_ANNOTATIONS: &str =
+    "SELECT batch_metadata FROM batches WHERE client_id = $1 AND batch_id = $2";
+
+/// Record a batch's committed root
+pub const SET_COMMITTED_ROOT: &str =
+    "UPDATE batches SET committed_root = $3 WHERE client_id = $1 AND batch_id = $2";
+
+/// Load a batch's committed root
+pub const LOAD_COMMITTED_ROOT: &str =
+    "SELECT committed_root FROM batches WHERE client_id = $1 AND batch_id = $2";
+
+/// Delete a batch's Merkle tree once the batch has no files
+pub const DELETE_ORPHANED_TREE: &str =
+    "DELETE FROM merkle_trees WHERE client_id = $1 AND batch_id = $2
+     AND NOT EXISTS (SELECT 1 FROM files WHERE client_id = $1 AND batch_id = $2)";
+
+/// Grant a client read access to a batch
+pub const GRANT_BATCH_ACCESS: &str =
+    "INSERT INTO batch_acl (client_id, batch_id, grantee_id) VALUES ($1, $2, $3)
+     ON CONFLICT (client_id, batch_id, grantee_id) DO NOTHING";
+
+/// Revoke a client's read access to a batch
+pub const REVOKE_BATCH_ACCESS: &str =
+    "DELETE FROM batch_acl WHERE client_id = $1 AND batch_id = $2 AND grantee_id = $3";
+
+/// Check whether a client was granted read access to a batch
+pub const HAS_BATCH_ACCESS: &str = "SELECT EXISTS(SELECT 1 FROM batch_acl
+     WHERE client_id = $1 AND batch_id = $2 AND grantee_id = $3)";
+
+/// Copy a batch row under a new batch_id (first step of a rename)
+pub const COPY_BATCH: &str =
+    "INSERT INTO batches (client_id, batch_id, is_public, batch_metadata, committed_root, created_at)
+     SELECT client_id, $3, is_public, batch_metadata, committed_root, created_at FROM batches
+     WHERE client_id = $1 AND batch_id = $2";
+
+/// Tables whose rows follow their batch when it is renamed
+pub const BATCH_CHILD_TABLES: [&str; 3] = ["files", "merkle_trees", "batch_acl"];
+
+/// Move the rows of one of [`BATCH_CHILD_TABLES`] to a new batch_id
+pub fn move_batch_rows(table: &str) -> String {
+    format!(
+        "UPDATE {} SET batch_id = $3 WHERE client_id = $1 AND batch_id = $2",
+        table
+    )
+}
+
+/// Delete a batch row (its files, tree and access grants cascade)
+pub const DELETE_BATCH: &str = "DELETE FROM batches WHERE client_id = $1 AND batch_id = $2";
+
+/// Delete one file of a batch
+pub const DELETE_FILE: &str =
+    "DELETE FROM files WHERE client_id = $1 AND batch_id = $2 AND filename = $3";
+
+/// Check whether a file exists
+pub const FILE_EXISTS: &str =
+    "SELECT EXISTS(SELECT 1 FROM files WHERE client_id = $1 AND batch_id = $2 AND filename = $3)";
+
+/// A batch's filenames, sorted
+pub const LOAD_BATCH_FILENAMES: &str = "SELECT filename FROM files
+     WHERE client_id = $1 AND batch_id = $2 ORDER BY filename";
+
+/// Every batch as (client_id, batch_id), sorted
+pub const LIST_BATCHES: &str =
+    "SELECT client_id, batch_id FROM batches ORDER BY client_id, batch_id";
+
+/// One client's batch IDs, sorted
+pub const LIST_CLIENT_BATCHES: &str =
+    "SELECT batch_id FROM batches WHERE client_id = $1 ORDER BY batch_id";
+
+/// Store or replace a client's public key
+pub const STORE_PUBLIC_KEY: &str = "INSERT INTO clients (client_id, public_key) VALUES ($1, $2)
+     ON CONFLICT (client_id) DO UPDATE SET public_key = EXCLUDED.public_key";
+
+/// Check whether a client is registered
+pub const CLIENT_EXISTS: &str = "SELECT EXISTS(SELECT 1 FROM clients WHERE client_id = $1)";
+
+/// Count registered clients
+pub const COUNT_CLIENTS: &str = "SELECT COUNT(*) FROM clients";
+
+/// Load a client's public key
+pub const LOAD_PUBLIC_KEY: &str = "SELECT public_key FROM clients WHERE client_id = $1";
+
+/// Store or replace a batch's Merkle tree (JSON)
+pub const STORE_MERKLE_TREE: &str = "INSERT INTO merkle_trees (client_id, batch_id, tree_data)
+     VALUES ($1, $2, $3)
+     ON CONFLICT (client_id, batch_id)
+     DO UPDATE SET tree_data = EXCLUDED.tree_data, updated_at = CURRENT_TIMESTAMP";
+
+/// Load a batch's Merkle tree (JSON)
+pub const LOAD_MERKLE_TREE: &str =
+    "SELECT tree_data FROM merkle_trees WHERE client_id = $1 AND batch_id = $2";
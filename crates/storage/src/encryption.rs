@@ -0,0 +1,59 @@
+use anyhow::{Context, Result};
+use crypto::{decrypt_at_rest, encrypt_at_rest};
+
+/// Environment variable holding the at-rest encryption key (64 hex characters)
+pub const ENCRYPTION_KEY_ENV: &str = "SERVER_ENCRYPTION_KEY";
+
+/// Server-side encryption of stored file content (XChaCha20-Poly1305)
+/// Content is encrypted on write and decrypted on read; leaf hashes, and so Merkle
+/// roots and proofs, stay those of the plaintext. Encrypted content is marked as such,
+/// so content stored before the key was set stays readable; content stored with it
+/// cannot be read back without it.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionConfig {
+    key: [u8; 32],
+}
+
+impl EncryptionConfig {
+    /// Create a configuration from a 32-byte key
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key }
+    }
+
+    /// Parse a key given as 64 hex characters
+    pub fn from_hex(key: &str) -> Result<Self> {
+        let bytes = hex::decode(key.trim()).context("Encryption key is not valid hex")?;
+        let key: [u8; 32] = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            anyhow::anyhow!("Encryption key must be 32 bytes, got {}", bytes.len())
+        })?;
+        Ok(Self::new(key))
+    }
+
+    /// Load the key from `SERVER_ENCRYPTION_KEY`; `None` if it is unset or empty
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var(ENCRYPTION_KEY_ENV) {
+            Ok(key) if !key.trim().is_empty() => Self::from_hex(&key)
+                .map(Some)
+                .with_context(|| format!("Invalid {}", ENCRYPTION_KEY_ENV)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Encrypt content before it is stored
+    pub(crate) fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        encrypt_at_rest(&self.key, plaintext).context("Failed to encrypt stored content")
+    }
+
+    /// Decrypt stored content
+    pub(crate) fn decrypt(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        decrypt_at_rest(&self.key, sealed).context("Failed to decrypt stored content")
+    }
+}
+
+impl std::fmt::Debug for EncryptionConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptionConfig")
+            .field("key", &"<redacted>")
+            .finish()
+    }
+}
@@ -0,0 +1,154 @@
+use crate::{BatchFull, ClientIdMismatch, ReplaceConflict};
+use std::fmt;
+
+/// Result of a `Storage` operation
+pub type StorageResult<T> = std::result::Result<T, StorageError>;
+
+/// Kind of stored resource an error refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    Batch,
+    File,
+    Client,
+}
+
+impl fmt::Display for ResourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Batch => "Batch",
+            Self::File => "File",
+            Self::Client => "Client",
+        })
+    }
+}
+
+/// Error returned by `Storage` operations
+/// Callers can tell a missing batch or a conflicting write from a failing backend
+/// without matching on messages. Converts to `anyhow::Error` like any error, and
+/// converting an `anyhow::Error` back recovers the typed errors it wraps, so backends
+/// can keep using `anyhow` internally.
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    /// The batch, file or client does not exist
+    #[error("{kind} {id} not found")]
+    NotFound { kind: ResourceKind, id: String },
+
+    /// The batch, file or client already exists
+    #[error("{kind} {id} already exists")]
+    AlreadyExists { kind: ResourceKind, id: String },
+
+    /// A batch replacement found other content than it expected
+    #[error(transparent)]
+    Conflict(ReplaceConflict),
+
+    /// The batch is at its file limit
+    #[error(transparent)]
+    BatchFull(BatchFull),
+
+    /// A public key was registered under an ID other than its own
+    #[error(transparent)]
+    ClientIdMismatch(ClientIdMismatch),
+
+    /// The backend failed (I/O, database, object store, corrupt data)
+    #[error(transparent)]
+    Backend(anyhow::Error),
+}
+
+impl StorageError {
+    /// A batch that does not exist
+    pub fn batch_not_found(batch_id: &str) -> Self {
+        Self::NotFound {
+            kind: ResourceKind::Batch,
+            id: batch_id.to_string(),
+        }
+    }
+
+    /// A file that does not exist in a batch; its ID is `batch_id/filename`
+    pub fn file_not_found(batch_id: &str, filename: &str) -> Self {
+        Self::NotFound {
+            kind: ResourceKind::File,
+            id: format!("{}/{}", batch_id, filename),
+        }
+    }
+
+    /// A batch that exists already
+    pub fn batch_already_exists(batch_id: &str) -> Self {
+        Self::AlreadyExists {
+            kind: ResourceKind::Batch,
+            id: batch_id.to_string(),
+        }
+    }
+
+    /// Whether this error means the resource does not exist
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Self::NotFound { .. })
+    }
+}
+
+impl From<anyhow::Error> for StorageError {
+    fn from(e: anyhow::Error) -> Self {
+        // Typed errors raised inside a backend keep their variant through `?`
+        let e = match e.downcast::<StorageError>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+        let e = match e.downcast::<ReplaceConflict>() {
+            Ok(conflict) => return Self::Conflict(conflict),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<BatchFull>() {
+            Ok(full) => return Self::BatchFull(full),
+            Err(e) => e,
+        };
+        match e.downcast::<ClientIdMismatch>() {
+            Ok(mismatch) => Self::ClientIdMismatch(mismatch),
+            Err(e) => Self::Backend(e),
+        }
+    }
+}
+
+impl From<ReplaceConflict> for StorageError {
+    fn from(conflict: ReplaceConflict) -> Self {
+        Self::Conflict(conflict)
+    }
+}
+
+impl From<BatchFull> for StorageError {
+    fn from(full: BatchFull) -> Self {
+        Self::BatchFull(full)
+    }
+}
+
+impl From<ClientIdMismatch> for StorageError {
+    fn from(mismatch: ClientIdMismatch) -> Self {
+        Self::ClientIdMismatch(mismatch)
+    }
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Backend(e.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_errors_survive_a_round_trip_through_anyhow() {
+        let err: anyhow::Error = StorageError::batch_not_found("b").into();
+        let err = StorageError::from(err.context("Failed to load batch"));
+        assert!(err.is_not_found());
+        assert_eq!(err.to_string(), "Batch b not found");
+
+        let err = StorageError::from(anyhow::Error::new(BatchFull { max_files: 2 }));
+        assert!(matches!(
+            err,
+            StorageError::BatchFull(BatchFull { max_files: 2 })
+        ));
+
+        let err = StorageError::from(anyhow::anyhow!("connection reset"));
+        assert!(matches!(err, StorageError::Backend(_)));
+    }
+}
@@ -1,19 +1,115 @@
-mod metadata;
-use crypto::hash_leaf;
+mod compact;
+pub(crate) mod metadata;
+mod reconcile;
+mod replace;
+use crypto::{hash_leaf, hash_leaf_reader};
 use merkle_tree::MerkleTree;
 
-use crate::Storage;
+use crate::codec::{file_has_header, ContentCodec};
+use crate::{
+    ensure_canonical_client_id, BatchFull, CompactionReport, CompressionConfig, EncryptionConfig,
+    ReplacementFile, Storage, StorageError, StorageResult,
+};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use fs2::FileExt;
 use metadata::Metadata;
+pub use reconcile::ReconcileReport;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 use tokio::io::AsyncWriteExt;
+use tokio::sync::OwnedMutexGuard;
+
+/// Process-wide counter making temp filenames unique within this process
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Number of temp names tried before giving up (collisions only happen across processes)
+const TEMP_FILE_MAX_ATTEMPTS: usize = 16;
+
+/// Per-batch metadata file (sorted filenames, visibility, access list and annotations)
+const METADATA_FILE: &str = "metadata.json";
+
+/// Per-batch append-only log of filenames added since metadata.json was last written
+const METADATA_LOG_FILE: &str = "metadata.log";
+
+/// Default number of logged filenames after which the log is folded into metadata.json
+const DEFAULT_METADATA_LOG_LIMIT: usize = 256;
+
+/// Per-batch Merkle tree file
+const MERKLE_TREE_FILE: &str = "merkle_tree.json";
+
+/// Per-batch lock file
+const LOCK_FILE: &str = ".lock";
+
+/// Directory under the data dir where batch replacements are staged
+const REPLACE_DIR: &str = ".replace";
+
+/// How uploads are flushed to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// fsync every file, metadata and tree write before renaming it into place, so a
+    /// completed upload survives a crash or power loss
+    #[default]
+    Strict,
+    /// Skip the per-file fsyncs and fsync the batch directory once per upload instead.
+    /// Much faster for bulk uploads, but after a power loss recently uploaded files may
+    /// be empty or missing; only suitable when clients can re-upload
+    Relaxed,
+}
+
+impl FromStr for Durability {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "relaxed" => Ok(Self::Relaxed),
+            _ => anyhow::bail!("Invalid durability: {}. Must be 'strict' or 'relaxed'", s),
+        }
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    /// fsyncs issued on this thread, so tests can tell whether writes were flushed
+    static SYNC_CALLS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// fsync a written file
+async fn sync_file(file: &tokio::fs::File) -> Result<()> {
+    #[cfg(test)]
+    SYNC_CALLS.with(|calls| calls.set(calls.get() + 1));
+    file.sync_all().await.context("Failed to sync file to disk")
+}
+
+/// fsync a directory, persisting the renames made inside it
+async fn sync_dir(dir: &Path) -> Result<()> {
+    #[cfg(test)]
+    SYNC_CALLS.with(|calls| calls.set(calls.get() + 1));
+    let dir = dir.to_path_buf();
+    tokio::task::spawn_blocking(move || File::open(&dir)?.sync_all())
+        .await
+        .context("Failed to spawn blocking task for directory sync")?
+        .context("Failed to sync directory to disk")
+}
 
 /// Filesystem-based storage implementation
 pub struct FilesystemStorage {
     data_dir: PathBuf,
+    /// Most files a batch may hold; `None` means unlimited
+    max_files_per_batch: Option<usize>,
+    /// How uploaded files, metadata and trees are flushed to disk
+    durability: Durability,
+    /// Logged filenames after which the metadata log is folded into metadata.json
+    metadata_log_limit: usize,
+    /// In-process batch locks, taken before the batch's file lock
+    batch_locks: Arc<BatchLocks>,
+    /// Compresses and encrypts file content at rest; by default it is stored as uploaded
+    codec: ContentCodec,
 }
 
 impl FilesystemStorage {
@@ -21,7 +117,95 @@ impl FilesystemStorage {
     pub fn new(data_dir: impl Into<PathBuf>) -> Self {
         Self {
             data_dir: data_dir.into(),
+            max_files_per_batch: None,
+            durability: Durability::Strict,
+            metadata_log_limit: DEFAULT_METADATA_LOG_LIMIT,
+            batch_locks: Arc::default(),
+            codec: ContentCodec::default(),
+        }
+    }
+
+    /// Flush uploads according to `durability` (strict by default)
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Fold the metadata log into metadata.json once it holds `entries` filenames
+    /// (256 by default); 1 rewrites metadata.json for every new file
+    pub fn with_metadata_log_limit(mut self, entries: usize) -> Self {
+        self.metadata_log_limit = entries.max(1);
+        self
+    }
+
+    /// Reject adding new files to batches that already hold `max` files
+    pub fn with_max_files_per_batch(mut self, max: usize) -> Self {
+        self.max_files_per_batch = Some(max);
+        self
+    }
+
+    /// Encrypt file content at rest with the given key
+    pub fn with_encryption(mut self, encryption: EncryptionConfig) -> Self {
+        self.codec.encryption = Some(encryption);
+        self
+    }
+
+    /// Compress file content at rest, before encrypting it
+    pub fn with_compression(mut self, compression: CompressionConfig) -> Self {
+        self.codec.compression = Some(compression);
+        self
+    }
+
+    /// Check the data directory at startup, creating it if absent
+    /// Fails with a clear error if it is not a directory, is a dangling symlink, is a
+    /// symlink while `follow_symlink` is false, or (when `require_writable`) cannot be
+    /// written, instead of failing on the first request
+    pub async fn validate_data_dir(
+        &self,
+        follow_symlink: bool,
+        require_writable: bool,
+    ) -> Result<()> {
+        let dir = &self.data_dir;
+        if let Ok(link) = tokio::fs::symlink_metadata(dir).await {
+            if link.file_type().is_symlink() {
+                let target = tokio::fs::read_link(dir)
+                    .await
+                    .with_context(|| format!("Failed to read data directory symlink {:?}", dir))?;
+                if !follow_symlink {
+                    anyhow::bail!(
+                        "Data directory {:?} is a symlink to {:?}, which --no-follow-data-symlink forbids",
+                        dir,
+                        target
+                    );
+                }
+                if !tokio::fs::try_exists(dir).await.unwrap_or(false) {
+                    anyhow::bail!(
+                        "Data directory {:?} is a dangling symlink to {:?}",
+                        dir,
+                        target
+                    );
+                }
+            }
+        } else {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .with_context(|| format!("Failed to create data directory {:?}", dir))?;
         }
+
+        let metadata = tokio::fs::metadata(dir)
+            .await
+            .with_context(|| format!("Failed to read data directory {:?}", dir))?;
+        if !metadata.is_dir() {
+            anyhow::bail!("Data directory {:?} exists but is not a directory", dir);
+        }
+
+        if require_writable {
+            let probe = dir.join(format!(".write-probe.{}", std::process::id()));
+            let written = tokio::fs::write(&probe, b"probe").await;
+            let _ = tokio::fs::remove_file(&probe).await;
+            written.with_context(|| format!("Data directory {:?} is not writable", dir))?;
+        }
+        Ok(())
     }
 
     /// Get batch directory path
@@ -41,26 +225,155 @@ impl FilesystemStorage {
 
     /// Get metadata file path
     fn metadata_path(&self, client_id: &str, batch_id: &str) -> PathBuf {
-        self.batch_dir(client_id, batch_id).join("metadata.json")
+        self.batch_dir(client_id, batch_id).join(METADATA_FILE)
+    }
+
+    /// Write file atomically: write to a unique temp file, fsync, then rename over the target
+    /// Readers never observe a partially written file. With relaxed durability the fsync is
+    /// skipped and the caller is responsible for syncing the directory
+    pub(crate) async fn write_file_atomic(
+        file_path: &Path,
+        content: &[u8],
+        durability: Durability,
+    ) -> Result<()> {
+        let (temp_path, mut file) = Self::create_temp_file(file_path).await?;
+
+        let result = async {
+            file.write_all(content)
+                .await
+                .context("Failed to write content to file")?;
+
+            // Sync file data to disk to ensure it's persisted
+            if durability == Durability::Strict {
+                sync_file(&file).await?;
+            }
+            drop(file);
+
+            tokio::fs::rename(&temp_path, file_path)
+                .await
+                .context("Failed to move temp file into place")
+        }
+        .await;
+
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+        }
+        result
     }
 
-    /// Write file with fsync to ensure data is persisted
-    async fn write_file_atomic(file_path: &PathBuf, content: &[u8]) -> Result<()> {
-        // Write directly to target file and sync to ensure data is persisted
-        let mut file = tokio::fs::File::create(file_path)
+    /// Copy a file atomically: stream the source into a unique temp file, fsync, then rename
+    /// The source is read in chunks, so large files are never held in memory
+    pub(crate) async fn copy_file_atomic(
+        source: &Path,
+        file_path: &Path,
+        durability: Durability,
+    ) -> Result<()> {
+        let mut source_file = tokio::fs::File::open(source)
             .await
-            .context("Failed to create file")?;
+            .with_context(|| format!("Failed to open source file: {:?}", source))?;
+        let (temp_path, mut file) = Self::create_temp_file(file_path).await?;
+
+        let result = async {
+            tokio::io::copy(&mut source_file, &mut file)
+                .await
+                .context("Failed to copy content to file")?;
+
+            // Sync file data to disk to ensure it's persisted
+            if durability == Durability::Strict {
+                sync_file(&file).await?;
+            }
+            drop(file);
 
-        file.write_all(content)
+            tokio::fs::rename(&temp_path, file_path)
+                .await
+                .context("Failed to move temp file into place")
+        }
+        .await;
+
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+        }
+        result
+    }
+
+    /// Compute the leaf hash of a stored file without reading it into memory
+    pub(crate) async fn hash_file(file_path: PathBuf) -> Result<[u8; 32]> {
+        tokio::task::spawn_blocking(move || {
+            let file = File::open(&file_path)
+                .with_context(|| format!("Failed to read file: {:?}", file_path))?;
+            hash_leaf_reader(file).with_context(|| format!("Failed to hash file: {:?}", file_path))
+        })
+        .await
+        .context("Failed to spawn blocking task for file hashing")?
+    }
+
+    /// Atomically store uploaded content, compressing and encrypting it if configured
+    async fn write_content(&self, file_path: &Path, content: &[u8]) -> Result<()> {
+        let stored = self.codec.encode(content)?;
+        Self::write_file_atomic(file_path, &stored, self.durability).await
+    }
+
+    /// Atomically store the content of `source`, compressing and encrypting it if configured
+    /// Such content is transformed in one piece, so the source is read into memory
+    pub(crate) async fn copy_content(&self, source: &Path, file_path: &Path) -> Result<()> {
+        if self.codec.stores_file_unchanged(source).await? {
+            return Self::copy_file_atomic(source, file_path, self.durability).await;
+        }
+        let content = tokio::fs::read(source)
             .await
-            .context("Failed to write content to file")?;
+            .with_context(|| format!("Failed to read source file: {:?}", source))?;
+        self.write_content(file_path, &content).await
+    }
 
-        // Sync file data to disk to ensure it's persisted
-        file.sync_all()
+    /// Read stored content, decrypting and decompressing it as its header says
+    async fn read_content(&self, file_path: &Path) -> Result<Vec<u8>> {
+        let content = tokio::fs::read(file_path)
             .await
-            .context("Failed to sync file to disk")?;
+            .with_context(|| format!("Failed to read file: {:?}", file_path))?;
+        self.codec
+            .decode(content)
+            .with_context(|| format!("Failed to read file: {:?}", file_path))
+    }
 
-        Ok(())
+    /// Compute the leaf hash of a stored file's (uploaded) content
+    pub(crate) async fn hash_content(&self, file_path: PathBuf) -> Result<[u8; 32]> {
+        if file_has_header(&file_path).await? {
+            return Ok(hash_leaf(&self.read_content(&file_path).await?));
+        }
+        Self::hash_file(file_path).await
+    }
+
+    /// Create a fresh temp file next to the target path
+    /// The name `.tmp.{pid}.{counter}` combines pid and a process-wide counter, and the
+    /// file is opened with O_EXCL semantics so a name collision is detected (and retried)
+    /// instead of silently clobbering another in-flight write. It does not include the
+    /// target's name, so it fits wherever the target does, even at the 255-byte limit.
+    async fn create_temp_file(file_path: &Path) -> Result<(PathBuf, tokio::fs::File)> {
+        let parent = file_path
+            .parent()
+            .ok_or_else(|| anyhow::anyhow!("Invalid file path: {:?}", file_path))?;
+
+        for _ in 0..TEMP_FILE_MAX_ATTEMPTS {
+            let counter = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+            let temp_path = parent.join(format!(".tmp.{}.{}", std::process::id(), counter));
+
+            match tokio::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temp_path)
+                .await
+            {
+                Ok(file) => return Ok((temp_path, file)),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e).context("Failed to create temp file"),
+            }
+        }
+
+        anyhow::bail!(
+            "Failed to create a unique temp file for {:?} after {} attempts",
+            file_path,
+            TEMP_FILE_MAX_ATTEMPTS
+        )
     }
 
     /// Get public key file path
@@ -70,40 +383,281 @@ impl FilesystemStorage {
 
     /// Get Merkle tree file path
     fn merkle_tree_path(&self, client_id: &str, batch_id: &str) -> PathBuf {
-        self.batch_dir(client_id, batch_id).join("merkle_tree.json")
+        self.batch_dir(client_id, batch_id).join(MERKLE_TREE_FILE)
     }
 
     /// Get lock file path for batch-level locking
     fn lock_file_path(&self, client_id: &str, batch_id: &str) -> PathBuf {
-        self.batch_dir(client_id, batch_id).join(".lock")
+        self.batch_dir(client_id, batch_id).join(LOCK_FILE)
+    }
+
+    /// Acquire exclusive lock on the batch
+    /// This prevents concurrent modifications from other processes/servers.
+    /// The batch directory must already exist.
+    async fn lock_batch(&self, client_id: &str, batch_id: &str) -> Result<LockGuard> {
+        // Tasks of this process queue here, so only one at a time waits on the file lock
+        let local = self.batch_locks.acquire(client_id, batch_id).await;
+
+        let lock_file = self.lock_file_path(client_id, batch_id);
+        let lock_file_handle = tokio::task::spawn_blocking(move || {
+            // Create lock file if it doesn't exist
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&lock_file)
+                .context("Failed to create lock file")?;
+
+            // Acquire exclusive lock (blocks until available)
+            file.lock_exclusive()
+                .context("Failed to acquire exclusive lock")?;
+
+            Ok::<_, anyhow::Error>(file)
+        })
+        .await
+        .context("Failed to spawn blocking task for file lock")?
+        .context("Failed to acquire file lock")?;
+
+        Ok(LockGuard {
+            file: lock_file_handle,
+            _local: local,
+        })
+    }
+
+    /// Create the batch directory if needed and take the batch lock
+    async fn prepare_batch(&self, client_id: &str, batch_id: &str) -> Result<LockGuard> {
+        let batch_dir = self.batch_dir(client_id, batch_id);
+
+        // Create batch directory if it doesn't exist
+        tokio::fs::create_dir_all(&batch_dir)
+            .await
+            .context("Failed to create batch directory")?;
+
+        self.lock_batch(client_id, batch_id).await
+    }
+
+    /// Fail with `BatchFull` if storing `filename` would add a file beyond the limit
+    /// Replacing an existing file is always allowed. Must be called while holding the batch lock
+    async fn ensure_capacity(&self, client_id: &str, batch_id: &str, filename: &str) -> Result<()> {
+        let Some(max_files) = self.max_files_per_batch else {
+            return Ok(());
+        };
+        let metadata_file = self.metadata_path(client_id, batch_id);
+        if !metadata_file.exists() {
+            return Ok(());
+        }
+        let filenames = Metadata::load_filenames(&metadata_file).await?;
+        if filenames.len() >= max_files && !filenames.iter().any(|f| f == filename) {
+            return Err(BatchFull { max_files }.into());
+        }
+        Ok(())
+    }
+
+    /// Add a freshly written file to the batch metadata and rebuild the Merkle tree
+    /// Must be called while holding the batch lock
+    async fn record_file_and_rebuild_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> Result<()> {
+        // Update metadata
+        let metadata_file = self.metadata_path(client_id, batch_id);
+        let (mut metadata, logged) = if metadata_file.exists() {
+            Metadata::load_with_log_len(&metadata_file).await?
+        } else {
+            (serde_json::Map::new(), 0)
+        };
+        let is_new = !Metadata::extract_filenames(&metadata)
+            .unwrap_or_default()
+            .iter()
+            .any(|f| f == filename);
+        Metadata::insert_filename(&mut metadata, filename);
+        if !metadata_file.exists() || (is_new && logged + 1 >= self.metadata_log_limit) {
+            Metadata::save_atomic(&metadata_file, &metadata, self.durability)
+                .await
+                .context("Failed to write metadata atomically")?;
+        } else if is_new {
+            // Appending keeps adding a file O(1) instead of rewriting every filename
+            Metadata::append_filename(&metadata_file, filename, self.durability)
+                .await
+                .context("Failed to append to metadata log")?;
+        }
+
+        // All filenames (sorted) including the newly uploaded file
+        let filenames = Metadata::extract_filenames(&metadata)?;
+        self.rebuild_tree(client_id, batch_id, &filenames, self.durability)
+            .await?;
+
+        // One directory sync persists the file, metadata and tree renames together
+        if self.durability == Durability::Relaxed {
+            sync_dir(&self.batch_dir(client_id, batch_id)).await?;
+        }
+        Ok(())
+    }
+
+    /// Rebuild and store the batch's Merkle tree from the given (sorted) filenames
+    /// Must be called while holding the batch lock
+    async fn rebuild_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filenames: &[String],
+        durability: Durability,
+    ) -> Result<MerkleTree> {
+        let leaf_hashes = self.leaf_hashes(client_id, batch_id, filenames).await?;
+
+        // Build Merkle tree from all leaf hashes
+        let tree = MerkleTree::from_leaf_hashes(&leaf_hashes)
+            .context("Failed to build Merkle tree from leaf hashes")?;
+
+        self.write_tree(client_id, batch_id, &tree, durability)
+            .await?;
+        Ok(tree)
+    }
+
+    /// Leaf hashes of the given files, streaming each one through the hasher
+    async fn leaf_hashes(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filenames: &[String],
+    ) -> Result<Vec<[u8; 32]>> {
+        let mut leaf_hashes = Vec::new();
+        for filename in filenames {
+            let file_path = self.file_path(client_id, batch_id, filename);
+            leaf_hashes.push(self.hash_content(file_path).await?);
+        }
+        Ok(leaf_hashes)
+    }
+
+    /// Store the batch's Merkle tree
+    async fn write_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        tree: &MerkleTree,
+        durability: Durability,
+    ) -> Result<()> {
+        let tree_file = self.merkle_tree_path(client_id, batch_id);
+        let tree_json =
+            serde_json::to_string_pretty(tree).context("Failed to serialize Merkle tree")?;
+        Self::write_file_atomic(&tree_file, tree_json.as_bytes(), durability)
+            .await
+            .context("Failed to write Merkle tree file")
+    }
+
+    /// Grant or revoke a client's read access in the batch metadata
+    async fn set_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+        granted: bool,
+    ) -> Result<()> {
+        let metadata_file = self.metadata_path(client_id, batch_id);
+        if !metadata_file.exists() {
+            return Err(StorageError::batch_not_found(batch_id).into());
+        }
+
+        let _guard = self.lock_batch(client_id, batch_id).await?;
+
+        let mut metadata = Metadata::load(&metadata_file).await?;
+        Metadata::set_access(&mut metadata, grantee_id, granted);
+        Metadata::save_atomic(&metadata_file, &metadata, Durability::Strict)
+            .await
+            .context("Failed to write metadata atomically")
     }
 }
 
 #[async_trait]
 impl Storage for FilesystemStorage {
-    async fn read_file(&self, client_id: &str, batch_id: &str, filename: &str) -> Result<Vec<u8>> {
+    async fn read_file(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Vec<u8>> {
         let file_path = self.file_path(client_id, batch_id, filename);
-        tokio::fs::read(&file_path)
-            .await
-            .with_context(|| format!("Failed to read file: {:?}", file_path))
+        Ok(self.read_content(&file_path).await?)
     }
 
-    async fn load_batch_filenames(&self, client_id: &str, batch_id: &str) -> Result<Vec<String>> {
+    async fn load_batch_filenames(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Vec<String>> {
         let metadata_file = self.metadata_path(client_id, batch_id);
 
         if !metadata_file.exists() {
-            anyhow::bail!("Batch {} not found for client {}", batch_id, client_id);
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+
+        Ok(Metadata::load_filenames(&metadata_file).await?)
+    }
+
+    async fn batch_exists(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        // A batch directory without metadata is not a batch (e.g. a failed first upload)
+        Ok(self.metadata_path(client_id, batch_id).exists())
+    }
+
+    async fn client_exists(&self, client_id: &str) -> StorageResult<bool> {
+        Ok(self.public_key_path(client_id).exists())
+    }
+
+    async fn count_clients(&self) -> StorageResult<u64> {
+        let mut count = 0;
+        if !self.data_dir.exists() {
+            return Ok(count);
         }
 
-        Metadata::load_filenames(&metadata_file).await
+        let mut clients = tokio::fs::read_dir(&self.data_dir)
+            .await
+            .context("Failed to read data directory")?;
+        while let Some(client) = clients.next_entry().await? {
+            let client_id = client.file_name().to_string_lossy().to_string();
+            if client.file_type().await?.is_dir() && self.public_key_path(&client_id).exists() {
+                count += 1;
+            }
+        }
+        Ok(count)
     }
 
-    async fn file_exists(&self, client_id: &str, batch_id: &str, filename: &str) -> Result<bool> {
+    async fn file_exists(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<bool> {
         let file_path = self.file_path(client_id, batch_id, filename);
         Ok(file_path.exists())
     }
 
-    async fn store_public_key(&self, client_id: &str, public_key: &[u8]) -> Result<()> {
+    async fn file_stored_at(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Option<u64>> {
+        // Files are renamed into place once fully written, so mtime is when they were stored
+        let file_path = self.file_path(client_id, batch_id, filename);
+        let metadata = match tokio::fs::metadata(&file_path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Ok(Err(e).context("Failed to read file metadata")?),
+        };
+        let modified = metadata
+            .modified()
+            .context("Failed to read file modification time")?;
+        let millis = modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .context("File modification time is before the Unix epoch")?
+            .as_millis();
+        Ok(Some(millis as u64))
+    }
+
+    async fn store_public_key(&self, client_id: &str, public_key: &[u8]) -> StorageResult<()> {
+        ensure_canonical_client_id(client_id, public_key)?;
         let client_dir = self.client_dir(client_id);
         let public_key_file = self.public_key_path(client_id);
 
@@ -116,7 +670,7 @@ impl Storage for FilesystemStorage {
         Ok(())
     }
 
-    async fn load_public_key(&self, client_id: &str) -> Result<Option<Vec<u8>>> {
+    async fn load_public_key(&self, client_id: &str) -> StorageResult<Option<Vec<u8>>> {
         let public_key_file = self.public_key_path(client_id);
 
         if !public_key_file.exists() {
@@ -131,121 +685,1486 @@ impl Storage for FilesystemStorage {
         Ok(Some(public_key_bytes))
     }
 
-    async fn load_merkle_tree(
+    async fn set_batch_public(
         &self,
         client_id: &str,
         batch_id: &str,
-    ) -> Result<Option<merkle_tree::MerkleTree>> {
-        let tree_file = self.merkle_tree_path(client_id, batch_id);
+        public: bool,
+    ) -> StorageResult<()> {
+        let metadata_file = self.metadata_path(client_id, batch_id);
+        if !metadata_file.exists() {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
 
-        if !tree_file.exists() {
-            return Ok(None);
+        let _guard = self.lock_batch(client_id, batch_id).await?;
+
+        let mut metadata = Metadata::load(&metadata_file).await?;
+        Metadata::set_public(&mut metadata, public);
+        Ok(
+            Metadata::save_atomic(&metadata_file, &metadata, Durability::Strict)
+                .await
+                .context("Failed to write metadata atomically")?,
+        )
+    }
+
+    async fn is_batch_public(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        let metadata_file = self.metadata_path(client_id, batch_id);
+        if !metadata_file.exists() {
+            return Ok(false);
         }
 
-        let tree_json = tokio::fs::read_to_string(&tree_file)
-            .await
-            .context("Failed to read Merkle tree file")?;
-        let tree: merkle_tree::MerkleTree =
-            serde_json::from_str(&tree_json).context("Failed to deserialize Merkle tree")?;
+        let metadata = Metadata::load(&metadata_file).await?;
+        Ok(Metadata::is_public(&metadata))
+    }
 
-        Ok(Some(tree))
+    async fn grant_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<()> {
+        Ok(self
+            .set_batch_access(client_id, batch_id, grantee_id, true)
+            .await?)
     }
 
-    async fn store_file_and_update_tree(
+    async fn revoke_batch_access(
         &self,
         client_id: &str,
         batch_id: &str,
-        filename: &str,
-        content: &[u8],
-    ) -> Result<()> {
-        let batch_dir = self.batch_dir(client_id, batch_id);
-        let lock_file = self.lock_file_path(client_id, batch_id);
+        grantee_id: &str,
+    ) -> StorageResult<()> {
+        Ok(self
+            .set_batch_access(client_id, batch_id, grantee_id, false)
+            .await?)
+    }
 
-        // Create batch directory if it doesn't exist
-        tokio::fs::create_dir_all(&batch_dir)
-            .await
-            .context("Failed to create batch directory")?;
+    async fn has_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<bool> {
+        let metadata_file = self.metadata_path(client_id, batch_id);
+        if !metadata_file.exists() {
+            return Ok(false);
+        }
 
-        // Acquire exclusive lock on the batch
-        // This prevents concurrent modifications from other processes/servers
-        let lock_file_handle = tokio::task::spawn_blocking({
-            let lock_file = lock_file.clone();
-            move || {
-                // Create lock file if it doesn't exist
-                let file = std::fs::OpenOptions::new()
-                    .create(true)
-                    .truncate(false)
-                    .write(true)
-                    .open(&lock_file)
-                    .context("Failed to create lock file")?;
-
-                // Acquire exclusive lock (blocks until available)
-                file.lock_exclusive()
-                    .context("Failed to acquire exclusive lock")?;
-
-                Ok::<_, anyhow::Error>(file)
-            }
-        })
-        .await
-        .context("Failed to spawn blocking task for file lock")?
-        .context("Failed to acquire file lock")?;
+        let metadata = Metadata::load(&metadata_file).await?;
+        Ok(Metadata::acl(&metadata).iter().any(|id| id == grantee_id))
+    }
 
-        // Ensure lock is released when all done
-        let _guard = LockGuard(lock_file_handle);
+    async fn set_batch_annotations(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        annotations: &BTreeMap<String, String>,
+    ) -> StorageResult<()> {
+        let metadata_file = self.metadata_path(client_id, batch_id);
+        if !metadata_file.exists() {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
 
-        // Store file
-        let file_path = self.file_path(client_id, batch_id, filename);
-        Self::write_file_atomic(&file_path, content)
+        let _guard = self.lock_batch(client_id, batch_id).await?;
+
+        let mut metadata = Metadata::load(&metadata_file).await?;
+        Metadata::set_annotations(&mut metadata, annotations);
+        Ok(
+            Metadata::save_atomic(&metadata_file, &metadata, Durability::Strict)
+                .await
+                .context("Failed to write metadata atomically")?,
+        )
+    }
+
+    async fn load_batch_annotations(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<BTreeMap<String, String>> {
+        let metadata_file = self.metadata_path(client_id, batch_id);
+        if !metadata_file.exists() {
+            return Ok(BTreeMap::new());
+        }
+
+        let metadata = Metadata::load(&metadata_file).await?;
+        Ok(Metadata::annotations(&metadata))
+    }
+
+    async fn set_committed_root(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        root: &[u8; 32],
+    ) -> StorageResult<()> {
+        let metadata_file = self.metadata_path(client_id, batch_id);
+        if !metadata_file.exists() {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+
+        let _guard = self.lock_batch(client_id, batch_id).await?;
+
+        let mut metadata = Metadata::load(&metadata_file).await?;
+        Metadata::set_committed_root(&mut metadata, root);
+        Ok(
+            Metadata::save_atomic(&metadata_file, &metadata, Durability::Strict)
+                .await
+                .context("Failed to write metadata atomically")?,
+        )
+    }
+
+    async fn load_committed_root(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Option<[u8; 32]>> {
+        let metadata_file = self.metadata_path(client_id, batch_id);
+        if !metadata_file.exists() {
+            return Ok(None);
+        }
+
+        let metadata = Metadata::load(&metadata_file).await?;
+        Ok(Metadata::committed_root(&metadata))
+    }
+
+    async fn create_batch(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        let _guard = self.prepare_batch(client_id, batch_id).await?;
+        let metadata_file = self.metadata_path(client_id, batch_id);
+        if metadata_file.exists() {
+            return Ok(false);
+        }
+
+        // Metadata listing no files is what makes the directory a batch
+        let mut metadata = serde_json::Map::new();
+        Metadata::set_filenames(&mut metadata, &[]);
+        Metadata::save_atomic(&metadata_file, &metadata, Durability::Strict)
             .await
-            .context("Failed to write file atomically")?;
+            .context("Failed to write metadata atomically")?;
+        Ok(true)
+    }
 
-        // Update metadata
+    async fn rename_batch(
+        &self,
+        client_id: &str,
+        old_batch_id: &str,
+        new_batch_id: &str,
+    ) -> StorageResult<()> {
+        if !self.metadata_path(client_id, old_batch_id).exists() {
+            return Err(StorageError::batch_not_found(old_batch_id));
+        }
+
+        // Hold the source batch lock so in-flight uploads finish before the move
+        let _guard = self.lock_batch(client_id, old_batch_id).await?;
+
+        let new_dir = self.batch_dir(client_id, new_batch_id);
+        if new_dir.exists() {
+            return Err(StorageError::batch_already_exists(new_batch_id));
+        }
+
+        // Files, metadata and tree live under the batch directory, so one rename moves all
+        Ok(
+            tokio::fs::rename(self.batch_dir(client_id, old_batch_id), &new_dir)
+                .await
+                .context("Failed to rename batch directory")?,
+        )
+    }
+
+    async fn delete_batch(&self, client_id: &str, batch_id: &str) -> StorageResult<()> {
         let metadata_file = self.metadata_path(client_id, batch_id);
-        let mut metadata = if metadata_file.exists() {
-            Metadata::load(&metadata_file).await?
-        } else {
-            serde_json::Map::new()
+        if !metadata_file.exists() {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+
+        // Hold the batch lock so in-flight uploads finish before the directory goes
+        let _guard = self.lock_batch(client_id, batch_id).await?;
+
+        // Files, metadata and tree live under the batch directory, so removing it deletes all
+        Ok(self.remove_batch_dir(client_id, batch_id).await?)
+    }
+
+    async fn delete_file(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Option<MerkleTree>> {
+        let metadata_file = self.metadata_path(client_id, batch_id);
+        if !metadata_file.exists() {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+
+        let _guard = self.lock_batch(client_id, batch_id).await?;
+        let mut metadata = Metadata::load(&metadata_file).await?;
+        let mut filenames = Metadata::extract_filenames(&metadata)?;
+        let Some(index) = filenames.iter().position(|f| f == filename) else {
+            return Err(StorageError::file_not_found(batch_id, filename));
         };
-        Metadata::insert_filename(&mut metadata, filename);
-        Metadata::save_atomic(&metadata_file, &metadata)
+        filenames.remove(index);
+
+        // The file goes first: after a crash, reconcile drops the metadata entry that has
+        // no file and rebuilds the tree, finishing the deletion
+        tokio::fs::remove_file(self.file_path(client_id, batch_id, filename))
+            .await
+            .context("Failed to remove file")?;
+        Metadata::set_filenames(&mut metadata, &filenames);
+        Metadata::save_atomic(&metadata_file, &metadata, self.durability)
             .await
             .context("Failed to write metadata atomically")?;
 
-        // Load all filenames (sorted) including the newly uploaded file
-        let filenames = Metadata::load_filenames(&metadata_file).await?;
+        let tree = if filenames.is_empty() {
+            // No files left: the batch has no root, so its tree goes too
+            tokio::fs::remove_file(self.merkle_tree_path(client_id, batch_id))
+                .await
+                .context("Failed to remove Merkle tree of empty batch")?;
+            None
+        } else {
+            Some(
+                self.rebuild_tree(client_id, batch_id, &filenames, self.durability)
+                    .await?,
+            )
+        };
 
-        // Compute leaf hashes from all files
-        let mut leaf_hashes = Vec::new();
-        for filename in &filenames {
-            let file_path = self.file_path(client_id, batch_id, filename);
-            let file_content = tokio::fs::read(&file_path)
+        if self.durability == Durability::Relaxed {
+            sync_dir(&self.batch_dir(client_id, batch_id)).await?;
+        }
+        Ok(tree)
+    }
+
+    async fn replace_batch(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        files: &[ReplacementFile],
+        committed_root: &[u8; 32],
+    ) -> StorageResult<()> {
+        Ok(self
+            .replace(client_id, batch_id, files, committed_root)
+            .await?)
+    }
+
+    async fn compact_batch(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<CompactionReport> {
+        Ok(self.compact(client_id, batch_id).await?)
+    }
+
+    async fn list_batches(&self) -> StorageResult<Vec<(String, String)>> {
+        let mut batches = Vec::new();
+        if !self.data_dir.exists() {
+            return Ok(batches);
+        }
+
+        let mut clients = tokio::fs::read_dir(&self.data_dir)
+            .await
+            .context("Failed to read data directory")?;
+        while let Some(client) = clients.next_entry().await? {
+            if !client.file_type().await?.is_dir() {
+                continue;
+            }
+            let client_id = client.file_name().to_string_lossy().to_string();
+
+            let mut entries = tokio::fs::read_dir(client.path())
                 .await
-                .with_context(|| format!("Failed to read file: {:?}", file_path))?;
-            let leaf_hash = hash_leaf(&file_content);
-            leaf_hashes.push(leaf_hash);
+                .context("Failed to read client directory")?;
+            while let Some(entry) = entries.next_entry().await? {
+                let batch_id = entry.file_name().to_string_lossy().to_string();
+                // Only directories with metadata are batches (skips public_key.hex etc.)
+                if self.metadata_path(&client_id, &batch_id).exists() {
+                    batches.push((client_id.clone(), batch_id));
+                }
+            }
         }
 
-        // Build Merkle tree from all leaf hashes
-        let tree = MerkleTree::from_leaf_hashes(&leaf_hashes)
-            .context("Failed to build Merkle tree from leaf hashes")?;
+        batches.sort();
+        Ok(batches)
+    }
+
+    async fn list_client_batches(&self, client_id: &str) -> StorageResult<Vec<String>> {
+        let client_dir = self.client_dir(client_id);
+        let mut entries = match tokio::fs::read_dir(&client_dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Ok(Err(e).context("Failed to read client directory")?),
+        };
+
+        let mut batches = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let batch_id = entry.file_name().to_string_lossy().to_string();
+            // Only directories with metadata are batches (skips public_key.hex etc.)
+            if self.metadata_path(client_id, &batch_id).exists() {
+                batches.push(batch_id);
+            }
+        }
+
+        batches.sort();
+        Ok(batches)
+    }
 
-        // Store the rebuilt tree
+    async fn load_merkle_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Option<merkle_tree::MerkleTree>> {
         let tree_file = self.merkle_tree_path(client_id, batch_id);
-        let tree_json =
-            serde_json::to_string_pretty(&tree).context("Failed to serialize Merkle tree")?;
-        Self::write_file_atomic(&tree_file, tree_json.as_bytes())
+
+        if !tree_file.exists() {
+            return Ok(None);
+        }
+
+        let tree_json = tokio::fs::read_to_string(&tree_file)
             .await
-            .context("Failed to write Merkle tree file")?;
+            .context("Failed to read Merkle tree file")?;
+        let tree: merkle_tree::MerkleTree =
+            serde_json::from_str(&tree_json).context("Failed to deserialize Merkle tree")?;
 
-        Ok(())
+        Ok(Some(tree))
+    }
+
+    async fn store_file_and_update_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        content: &[u8],
+    ) -> StorageResult<()> {
+        // Ensure lock is released when all done
+        let _guard = self.prepare_batch(client_id, batch_id).await?;
+        self.ensure_capacity(client_id, batch_id, filename).await?;
+
+        // Store file
+        let file_path = self.file_path(client_id, batch_id, filename);
+        self.write_content(&file_path, content)
+            .await
+            .context("Failed to write file atomically")?;
+
+        Ok(self
+            .record_file_and_rebuild_tree(client_id, batch_id, filename)
+            .await?)
+    }
+
+    async fn store_file_from_path_and_update_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        source: &Path,
+    ) -> StorageResult<()> {
+        // Ensure lock is released when all done
+        let _guard = self.prepare_batch(client_id, batch_id).await?;
+        self.ensure_capacity(client_id, batch_id, filename).await?;
+
+        // Stream the source into place; a rename is not possible across filesystems
+        let file_path = self.file_path(client_id, batch_id, filename);
+        self.copy_content(source, &file_path)
+            .await
+            .context("Failed to copy file atomically")?;
+
+        Ok(self
+            .record_file_and_rebuild_tree(client_id, batch_id, filename)
+            .await?)
     }
 }
 
 /// Guard to ensure file lock is released
-struct LockGuard(File);
+struct LockGuard {
+    file: File,
+    /// Released after the file lock, once the guard's fields drop
+    _local: LocalBatchLock,
+}
 
 impl Drop for LockGuard {
     fn drop(&mut self) {
-        let _ = self.0.unlock();
+        let _ = self.file.unlock();
+    }
+}
+
+/// In-process lock table with one async mutex per (client_id, batch_id)
+/// File locks alone would make every waiting task occupy a blocking thread, and whether
+/// they exclude tasks of the same process depends on the platform (some network
+/// filesystems scope them per process). The async mutex serializes this process's
+/// tasks regardless; the file lock still guards against other processes.
+#[derive(Default)]
+pub(crate) struct BatchLocks(Mutex<HashMap<BatchKey, Arc<tokio::sync::Mutex<()>>>>);
+
+/// (client_id, batch_id)
+type BatchKey = (String, String);
+
+impl BatchLocks {
+    pub(crate) async fn acquire(
+        self: &Arc<Self>,
+        client_id: &str,
+        batch_id: &str,
+    ) -> LocalBatchLock {
+        let key = (client_id.to_string(), batch_id.to_string());
+        let mutex = self
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        LocalBatchLock {
+            guard: Some(mutex.lock_owned().await),
+            locks: self.clone(),
+            key,
+        }
+    }
+}
+
+/// Holds a batch's in-process mutex; forgets the mutex when nobody else uses it
+pub(crate) struct LocalBatchLock {
+    guard: Option<OwnedMutexGuard<()>>,
+    locks: Arc<BatchLocks>,
+    key: BatchKey,
+}
+
+impl Drop for LocalBatchLock {
+    fn drop(&mut self) {
+        self.guard.take();
+        let mut locks = self.locks.0.lock().unwrap_or_else(PoisonError::into_inner);
+        // Only the table's own reference left: no task holds or awaits this mutex
+        if locks
+            .get(&self.key)
+            .is_some_and(|mutex| Arc::strong_count(mutex) == 1)
+        {
+            locks.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Compression;
+
+    fn temp_data_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("vs-fs-{}-{}", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_rename_batch() {
+        let dir = temp_data_dir("rename");
+        let storage = FilesystemStorage::new(&dir);
+        storage
+            .store_file_and_update_tree("client", "tmp", "a.txt", b"a")
+            .await
+            .unwrap();
+        storage
+            .store_file_and_update_tree("client", "tmp", "b.txt", b"b")
+            .await
+            .unwrap();
+        let root = storage
+            .load_merkle_tree("client", "tmp")
+            .await
+            .unwrap()
+            .unwrap()
+            .root_hash();
+
+        storage
+            .rename_batch("client", "tmp", "final")
+            .await
+            .unwrap();
+
+        let err = storage
+            .load_batch_filenames("client", "tmp")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            StorageError::NotFound {
+                kind: crate::ResourceKind::Batch,
+                ..
+            }
+        ));
+        assert_eq!(
+            storage.list_batches().await.unwrap(),
+            vec![("client".to_string(), "final".to_string())]
+        );
+        assert_eq!(
+            storage
+                .load_batch_filenames("client", "final")
+                .await
+                .unwrap(),
+            vec!["a.txt", "b.txt"]
+        );
+        assert_eq!(
+            storage.read_file("client", "final", "b.txt").await.unwrap(),
+            b"b"
+        );
+        let renamed_tree = storage.load_merkle_tree("client", "final").await.unwrap();
+        assert_eq!(renamed_tree.unwrap().root_hash(), root);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_batch() {
+        let dir = temp_data_dir("delete");
+        let storage = FilesystemStorage::new(&dir);
+        for batch_id in ["gone", "kept"] {
+            storage
+                .store_file_and_update_tree("client", batch_id, "a.txt", b"a")
+                .await
+                .unwrap();
+        }
+
+        storage.delete_batch("client", "gone").await.unwrap();
+
+        assert!(!storage.batch_exists("client", "gone").await.unwrap());
+        assert!(!storage.batch_dir("client", "gone").exists());
+        assert!(storage
+            .load_merkle_tree("client", "gone")
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            storage.list_batches().await.unwrap(),
+            vec![("client".to_string(), "kept".to_string())]
+        );
+        // Nothing is left for reconcile to find
+        assert!(storage.reconcile().await.unwrap().is_clean());
+
+        assert!(storage.delete_batch("client", "gone").await.is_err());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_client_batches() {
+        let dir = temp_data_dir("list-client");
+        let storage = FilesystemStorage::new(&dir);
+        assert!(storage
+            .list_client_batches("client")
+            .await
+            .unwrap()
+            .is_empty());
+
+        storage
+            .store_file_and_update_tree("client", "b", "a.txt", b"a")
+            .await
+            .unwrap();
+        assert_eq!(storage.list_client_batches("client").await.unwrap(), ["b"]);
+
+        storage.create_batch("client", "c").await.unwrap();
+        storage
+            .store_file_and_update_tree("client", "a", "a.txt", b"a")
+            .await
+            .unwrap();
+        storage
+            .store_file_and_update_tree("other", "d", "a.txt", b"a")
+            .await
+            .unwrap();
+        // Sorted, including empty batches, and only the client's own
+        assert_eq!(
+            storage.list_client_batches("client").await.unwrap(),
+            ["a", "b", "c"]
+        );
+        assert_eq!(storage.list_client_batches("other").await.unwrap(), ["d"]);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_file_rebuilds_tree() {
+        let dir = temp_data_dir("delete-file");
+        let storage = FilesystemStorage::new(&dir);
+        for (filename, content) in [("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")] {
+            storage
+                .store_file_and_update_tree("client", "batch", filename, content)
+                .await
+                .unwrap();
+        }
+
+        let tree = storage
+            .delete_file("client", "batch", "b.txt")
+            .await
+            .unwrap()
+            .unwrap();
+
+        // The returned tree is the stored one, and the remaining files verify against it
+        let root = MerkleTree::from_data(&[b"a".to_vec(), b"c".to_vec()])
+            .unwrap()
+            .root_hash();
+        assert_eq!(tree.root_hash(), root);
+        let stored = storage
+            .load_merkle_tree("client", "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.root_hash(), root);
+        for i in 0..stored.num_leaves() {
+            assert!(stored.generate_proof(i).unwrap().verify(&root));
+        }
+        assert_eq!(
+            storage
+                .load_batch_filenames("client", "batch")
+                .await
+                .unwrap(),
+            vec!["a.txt", "c.txt"]
+        );
+        assert!(!storage.file_path("client", "batch", "b.txt").exists());
+        assert!(storage.reconcile().await.unwrap().is_clean());
+
+        assert!(storage
+            .delete_file("client", "batch", "b.txt")
+            .await
+            .is_err());
+        assert!(storage
+            .delete_file("client", "missing", "a.txt")
+            .await
+            .is_err());
+
+        // Deleting the last files leaves an empty batch without a tree
+        storage
+            .delete_file("client", "batch", "a.txt")
+            .await
+            .unwrap();
+        assert!(storage
+            .delete_file("client", "batch", "c.txt")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(storage.batch_exists("client", "batch").await.unwrap());
+        assert!(storage
+            .load_merkle_tree("client", "batch")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(storage.reconcile().await.unwrap().is_clean());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_batch_and_client_exist() {
+        let dir = temp_data_dir("exists");
+        let storage = FilesystemStorage::new(&dir);
+        let client = crypto::compute_client_id_from_bytes(&[7u8; 32]);
+        let client = client.as_str();
+        assert!(!storage.client_exists(client).await.unwrap());
+        assert!(!storage.batch_exists(client, "batch").await.unwrap());
+
+        storage
+            .store_file_and_update_tree(client, "batch", "a.txt", b"a")
+            .await
+            .unwrap();
+        assert!(storage.batch_exists(client, "batch").await.unwrap());
+        assert!(!storage.batch_exists(client, "other").await.unwrap());
+        assert!(!storage.batch_exists("other", "batch").await.unwrap());
+        // Uploading files does not register the client; storing its key does
+        assert!(!storage.client_exists(client).await.unwrap());
+        assert_eq!(storage.count_clients().await.unwrap(), 0);
+        storage.store_public_key(client, &[7u8; 32]).await.unwrap();
+        assert!(storage.client_exists(client).await.unwrap());
+        assert!(!storage.client_exists("other").await.unwrap());
+        assert_eq!(storage.count_clients().await.unwrap(), 1);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_public_key_registers_under_its_canonical_id_only() {
+        let dir = temp_data_dir("canonical-id");
+        let storage = FilesystemStorage::new(&dir);
+        let key = [7u8; 32];
+        let client_id = crypto::compute_client_id_from_bytes(&key);
+        // The same key always maps to the same ID
+        assert_eq!(crypto::compute_client_id_from_bytes(&key), client_id);
+
+        storage.store_public_key(&client_id, &key).await.unwrap();
+        assert_eq!(
+            storage.load_public_key(&client_id).await.unwrap(),
+            Some(key.to_vec())
+        );
+
+        // Neither an arbitrary ID nor another key's ID can hold this key
+        let other_id = crypto::compute_client_id_from_bytes(&[8u8; 32]);
+        for wrong_id in ["alias", other_id.as_str()] {
+            let err = storage.store_public_key(wrong_id, &key).await.unwrap_err();
+            assert!(matches!(
+                err,
+                StorageError::ClientIdMismatch(crate::ClientIdMismatch { client_id: id, canonical_id })
+                    if id == wrong_id && canonical_id == client_id
+            ));
+            assert!(!storage.client_exists(wrong_id).await.unwrap());
+        }
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_create_empty_batch() {
+        let dir = temp_data_dir("create");
+        let storage = FilesystemStorage::new(&dir);
+        assert!(storage.create_batch("client", "batch").await.unwrap());
+        assert!(!storage.create_batch("client", "batch").await.unwrap());
+        assert!(storage
+            .load_batch_filenames("client", "batch")
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(storage
+            .load_merkle_tree("client", "batch")
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            storage.list_batches().await.unwrap(),
+            vec![("client".to_string(), "batch".to_string())]
+        );
+
+        storage
+            .store_file_and_update_tree("client", "batch", "a.txt", b"a")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .load_batch_filenames("client", "batch")
+                .await
+                .unwrap(),
+            vec!["a.txt"]
+        );
+        // An existing batch is left alone
+        assert!(!storage.create_batch("client", "batch").await.unwrap());
+        assert_eq!(
+            storage
+                .load_merkle_tree("client", "batch")
+                .await
+                .unwrap()
+                .unwrap()
+                .num_leaves(),
+            1
+        );
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_files_per_batch() {
+        let dir = temp_data_dir("max-files");
+        let storage = FilesystemStorage::new(&dir).with_max_files_per_batch(2);
+        for name in ["a.txt", "b.txt"] {
+            storage
+                .store_file_and_update_tree("client", "batch", name, name.as_bytes())
+                .await
+                .unwrap();
+        }
+
+        let err = storage
+            .store_file_and_update_tree("client", "batch", "c.txt", b"c")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            StorageError::BatchFull(BatchFull { max_files: 2 })
+        ));
+        assert!(!storage
+            .file_exists("client", "batch", "c.txt")
+            .await
+            .unwrap());
+
+        // Existing files stay readable and replaceable; other batches are unaffected
+        assert_eq!(
+            storage.read_file("client", "batch", "a.txt").await.unwrap(),
+            b"a.txt"
+        );
+        storage
+            .store_file_and_update_tree("client", "batch", "a.txt", b"new")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .load_merkle_tree("client", "batch")
+                .await
+                .unwrap()
+                .unwrap()
+                .num_leaves(),
+            2
+        );
+        storage
+            .store_file_and_update_tree("client", "other", "c.txt", b"c")
+            .await
+            .unwrap();
+
+        tokio::fs::remove_dir_all(&dir).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_durability_controls_fsyncs() {
+        fn sync_calls() -> usize {
+            SYNC_CALLS.with(|calls| calls.get())
+        }
+
+        let dir = temp_data_dir("durability");
+        let strict = FilesystemStorage::new(&dir);
+        let before = sync_calls();
+        strict
+            .store_file_and_update_tree("client", "strict", "a.txt", b"a")
+            .await
+            .unwrap();
+        // File, metadata and tree are each flushed
+        assert_eq!(sync_calls() - before, 3);
+
+        let relaxed = FilesystemStorage::new(&dir).with_durability(Durability::Relaxed);
+        let before = sync_calls();
+        relaxed
+            .store_file_and_update_tree("client", "relaxed", "a.txt", b"a")
+            .await
+            .unwrap();
+        // Only the batch directory is flushed
+        assert_eq!(sync_calls() - before, 1);
+        assert_eq!(
+            relaxed
+                .read_file("client", "relaxed", "a.txt")
+                .await
+                .unwrap(),
+            b"a"
+        );
+        assert_eq!(
+            relaxed
+                .load_merkle_tree("client", "relaxed")
+                .await
+                .unwrap()
+                .unwrap()
+                .root_hash(),
+            strict
+                .load_merkle_tree("client", "strict")
+                .await
+                .unwrap()
+                .unwrap()
+                .root_hash()
+        );
+
+        assert_eq!("strict".parse::<Durability>().unwrap(), Durability::Strict);
+        assert!("fast".parse::<Durability>().is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_rename_batch_conflict() {
+        let dir = temp_data_dir("rename-conflict");
+        let storage = FilesystemStorage::new(&dir);
+        storage
+            .store_file_and_update_tree("client", "first", "a.txt", b"a")
+            .await
+            .unwrap();
+        storage
+            .store_file_and_update_tree("client", "second", "b.txt", b"b")
+            .await
+            .unwrap();
+
+        let err = storage
+            .rename_batch("client", "first", "second")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+
+        // Both batches untouched
+        assert_eq!(
+            storage
+                .load_batch_filenames("client", "first")
+                .await
+                .unwrap(),
+            vec!["a.txt"]
+        );
+        assert_eq!(
+            storage
+                .load_batch_filenames("client", "second")
+                .await
+                .unwrap(),
+            vec!["b.txt"]
+        );
+
+        // Missing source is reported as not found
+        let err = storage
+            .rename_batch("client", "missing", "third")
+            .await
+            .unwrap_err();
+        assert!(err.is_not_found());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_batch_access_grant_and_revoke() {
+        let dir = temp_data_dir("acl");
+        let storage = FilesystemStorage::new(&dir);
+        storage
+            .store_file_and_update_tree("owner", "batch", "a.txt", b"a")
+            .await
+            .unwrap();
+
+        assert!(!storage
+            .has_batch_access("owner", "batch", "reader")
+            .await
+            .unwrap());
+
+        storage
+            .grant_batch_access("owner", "batch", "reader")
+            .await
+            .unwrap();
+        // Granting twice is a no-op
+        storage
+            .grant_batch_access("owner", "batch", "reader")
+            .await
+            .unwrap();
+        assert!(storage
+            .has_batch_access("owner", "batch", "reader")
+            .await
+            .unwrap());
+        assert!(!storage
+            .has_batch_access("owner", "batch", "other")
+            .await
+            .unwrap());
+
+        // Access follows the batch through a rename and survives further uploads
+        storage
+            .rename_batch("owner", "batch", "renamed")
+            .await
+            .unwrap();
+        storage
+            .store_file_and_update_tree("owner", "renamed", "b.txt", b"b")
+            .await
+            .unwrap();
+        assert!(storage
+            .has_batch_access("owner", "renamed", "reader")
+            .await
+            .unwrap());
+
+        storage
+            .revoke_batch_access("owner", "renamed", "reader")
+            .await
+            .unwrap();
+        assert!(!storage
+            .has_batch_access("owner", "renamed", "reader")
+            .await
+            .unwrap());
+
+        let err = storage
+            .grant_batch_access("owner", "missing", "reader")
+            .await
+            .unwrap_err();
+        assert!(err.is_not_found());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_batch_annotations_round_trip() {
+        let dir = temp_data_dir("annotations");
+        let storage = FilesystemStorage::new(&dir);
+        storage
+            .store_file_and_update_tree("owner", "batch", "a.txt", b"a")
+            .await
+            .unwrap();
+
+        assert!(storage
+            .load_batch_annotations("owner", "batch")
+            .await
+            .unwrap()
+            .is_empty());
+
+        let annotations: BTreeMap<String, String> =
+            [("commit", "1a2b3c"), ("description", "nightly")]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+        storage
+            .set_batch_annotations("owner", "batch", &annotations)
+            .await
+            .unwrap();
+
+        // Annotations survive further uploads and renames, and never touch the tree
+        storage
+            .store_file_and_update_tree("owner", "batch", "b.txt", b"b")
+            .await
+            .unwrap();
+        storage
+            .rename_batch("owner", "batch", "renamed")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .load_batch_annotations("owner", "renamed")
+                .await
+                .unwrap(),
+            annotations
+        );
+        storage
+            .set_batch_annotations("owner", "renamed", &BTreeMap::new())
+            .await
+            .unwrap();
+        assert!(storage
+            .load_batch_annotations("owner", "renamed")
+            .await
+            .unwrap()
+            .is_empty());
+        let tree = storage
+            .load_merkle_tree("owner", "renamed")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            tree.root_hash(),
+            MerkleTree::from_data(&[b"a".to_vec(), b"b".to_vec()])
+                .unwrap()
+                .root_hash()
+        );
+
+        let err = storage
+            .set_batch_annotations("owner", "missing", &annotations)
+            .await
+            .unwrap_err();
+        assert!(err.is_not_found());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_store_file_from_path_matches_in_memory_store() {
+        let dir = temp_data_dir("from-path");
+        let storage = FilesystemStorage::new(&dir);
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        let content: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let source = dir.join("upload.bin");
+        tokio::fs::write(&source, &content).await.unwrap();
+
+        storage
+            .store_file_from_path_and_update_tree("client", "streamed", "a.bin", &source)
+            .await
+            .unwrap();
+        storage
+            .store_file_and_update_tree("client", "buffered", "a.bin", &content)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            storage
+                .read_file("client", "streamed", "a.bin")
+                .await
+                .unwrap(),
+            content
+        );
+        let streamed = storage
+            .load_merkle_tree("client", "streamed")
+            .await
+            .unwrap();
+        let buffered = storage
+            .load_merkle_tree("client", "buffered")
+            .await
+            .unwrap();
+        assert_eq!(streamed.unwrap().root_hash(), buffered.unwrap().root_hash());
+
+        // The source is copied, not moved
+        assert!(source.exists());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_longest_valid_filename_is_stored() {
+        let dir = temp_data_dir("long-name");
+        let storage = FilesystemStorage::new(&dir);
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        // The longest name filename validation accepts
+        let filename = "a".repeat(255);
+        let source = dir.join("upload.bin");
+        tokio::fs::write(&source, b"streamed").await.unwrap();
+
+        storage
+            .store_file_and_update_tree("client", "buffered", &filename, b"buffered")
+            .await
+            .unwrap();
+        storage
+            .store_file_from_path_and_update_tree("client", "streamed", &filename, &source)
+            .await
+            .unwrap();
+
+        for (batch_id, content) in [("buffered", &b"buffered"[..]), ("streamed", b"streamed")] {
+            assert_eq!(
+                storage
+                    .read_file("client", batch_id, &filename)
+                    .await
+                    .unwrap(),
+                content
+            );
+        }
+        tokio::fs::remove_file(&source).await.unwrap();
+        assert!(storage.reconcile().await.unwrap().is_clean());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_content_is_stored_sealed_and_read_back_plain() {
+        let dir = temp_data_dir("encrypted");
+        let storage = FilesystemStorage::new(&dir).with_encryption(EncryptionConfig::new([9; 32]));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        let source = dir.join("upload.bin");
+        tokio::fs::write(&source, b"second plaintext")
+            .await
+            .unwrap();
+        storage
+            .store_file_and_update_tree("client", "batch", "a.txt", b"first plaintext")
+            .await
+            .unwrap();
+        storage
+            .store_file_from_path_and_update_tree("client", "batch", "b.txt", &source)
+            .await
+            .unwrap();
+
+        for (filename, plaintext) in [
+            ("a.txt", &b"first plaintext"[..]),
+            ("b.txt", b"second plaintext"),
+        ] {
+            let on_disk = tokio::fs::read(storage.file_path("client", "batch", filename))
+                .await
+                .unwrap();
+            assert!(!on_disk.windows(plaintext.len()).any(|w| w == plaintext));
+            assert_eq!(
+                storage
+                    .read_file("client", "batch", filename)
+                    .await
+                    .unwrap(),
+                plaintext
+            );
+        }
+
+        // The tree is built over the plaintext, so roots and proofs are unchanged
+        let expected = MerkleTree::from_leaf_hashes(&[
+            hash_leaf(b"first plaintext"),
+            hash_leaf(b"second plaintext"),
+        ])
+        .unwrap();
+        let tree = storage.load_merkle_tree("client", "batch").await.unwrap();
+        assert_eq!(tree.unwrap().root_hash(), expected.root_hash());
+
+        // Without the key the content cannot be read
+        let other = FilesystemStorage::new(&dir).with_encryption(EncryptionConfig::new([1; 32]));
+        assert!(other.read_file("client", "batch", "a.txt").await.is_err());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_stored_before_the_key_was_set_stays_readable() {
+        let dir = temp_data_dir("encrypted-later");
+        let plain = FilesystemStorage::new(&dir);
+        plain
+            .store_file_and_update_tree("client", "batch", "a.txt", b"stored in the clear")
+            .await
+            .unwrap();
+
+        let storage = FilesystemStorage::new(&dir).with_encryption(EncryptionConfig::new([9; 32]));
+        storage
+            .store_file_and_update_tree("client", "batch", "b.txt", b"stored sealed")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.read_file("client", "batch", "a.txt").await.unwrap(),
+            b"stored in the clear"
+        );
+        assert_eq!(
+            storage.read_file("client", "batch", "b.txt").await.unwrap(),
+            b"stored sealed"
+        );
+        let expected = MerkleTree::from_leaf_hashes(&[
+            hash_leaf(b"stored in the clear"),
+            hash_leaf(b"stored sealed"),
+        ])
+        .unwrap();
+        let tree = storage.load_merkle_tree("client", "batch").await.unwrap();
+        assert_eq!(tree.unwrap().root_hash(), expected.root_hash());
+
+        // Without the key, plaintext still reads back and sealed content fails clearly
+        assert_eq!(
+            plain.read_file("client", "batch", "a.txt").await.unwrap(),
+            b"stored in the clear"
+        );
+        let err = plain
+            .read_file("client", "batch", "b.txt")
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("SERVER_ENCRYPTION_KEY"));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_compressed_content_is_stored_small_and_read_back_unchanged() {
+        let dir = temp_data_dir("compressed");
+        let content = b"compressible line of text\n".repeat(40_000);
+        assert!(content.len() > 1_000_000);
+        let gzip = CompressionConfig::new(Compression::Gzip, 6).unwrap();
+        let storage = FilesystemStorage::new(&dir).with_compression(gzip);
+        storage
+            .store_file_and_update_tree("client", "batch", "a.txt", &content)
+            .await
+            .unwrap();
+
+        // Switching algorithms leaves earlier files readable: each carries its own tag
+        let zstd = CompressionConfig::new(Compression::Zstd, 3).unwrap();
+        let storage = FilesystemStorage::new(&dir).with_compression(zstd);
+        storage
+            .store_file_and_update_tree("client", "batch", "b.txt", &content)
+            .await
+            .unwrap();
+
+        for filename in ["a.txt", "b.txt"] {
+            let on_disk = tokio::fs::metadata(storage.file_path("client", "batch", filename))
+                .await
+                .unwrap()
+                .len();
+            assert!(on_disk < content.len() as u64 / 50);
+            assert_eq!(
+                storage
+                    .read_file("client", "batch", filename)
+                    .await
+                    .unwrap(),
+                content
+            );
+        }
+
+        // The tree is built over the uploaded bytes, so roots and proofs are unchanged
+        let leaf = hash_leaf(&content);
+        let expected = MerkleTree::from_leaf_hashes(&[leaf, leaf]).unwrap();
+        let tree = storage.load_merkle_tree("client", "batch").await.unwrap();
+        assert_eq!(tree.unwrap().root_hash(), expected.root_hash());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_turning_compression_on_or_off_keeps_stored_files_readable() {
+        let dir = temp_data_dir("compression-toggled");
+        let content = b"compressible line of text\n".repeat(1_000);
+        let zstd = CompressionConfig::new(Compression::Zstd, 3).unwrap();
+        let plain = FilesystemStorage::new(&dir);
+        let compressed = FilesystemStorage::new(&dir).with_compression(zstd);
+
+        // Off, then on
+        plain
+            .store_file_and_update_tree("client", "batch", "a.txt", &content)
+            .await
+            .unwrap();
+        // On, then off
+        compressed
+            .store_file_and_update_tree("client", "batch", "b.txt", &content)
+            .await
+            .unwrap();
+
+        let expected = MerkleTree::from_leaf_hashes(&[hash_leaf(&content); 2]).unwrap();
+        for storage in [&plain, &compressed] {
+            for filename in ["a.txt", "b.txt"] {
+                assert_eq!(
+                    storage
+                        .read_file("client", "batch", filename)
+                        .await
+                        .unwrap(),
+                    content
+                );
+            }
+            assert_eq!(
+                storage
+                    .leaf_hashes("client", "batch", &["a.txt".into(), "b.txt".into()])
+                    .await
+                    .unwrap(),
+                vec![hash_leaf(&content); 2]
+            );
+        }
+        let tree = plain.load_merkle_tree("client", "batch").await.unwrap();
+        assert_eq!(tree.unwrap().root_hash(), expected.root_hash());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stored_tree_serves_proofs_after_reopen() {
+        let dir = temp_data_dir("stored-tree");
+        let contents: Vec<Vec<u8>> = [b"a", b"b", b"c"].iter().map(|c| c.to_vec()).collect();
+        {
+            let storage = FilesystemStorage::new(&dir);
+            for (i, content) in contents.iter().enumerate() {
+                storage
+                    .store_file_and_update_tree("client", "batch", &format!("{}.txt", i), content)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        // A fresh instance reads the tree written on upload instead of rebuilding it
+        let storage = FilesystemStorage::new(&dir);
+        let tree = storage
+            .load_merkle_tree("client", "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        let root = MerkleTree::from_data(&contents).unwrap().root_hash();
+        assert_eq!(tree.root_hash(), root);
+        for i in 0..contents.len() {
+            assert!(tree.generate_proof(i).unwrap().verify(&root));
+        }
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_data_dir_validation() {
+        let dir = temp_data_dir("data-dir");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        // Created when absent
+        let missing = dir.join("missing");
+        FilesystemStorage::new(&missing)
+            .validate_data_dir(true, true)
+            .await
+            .unwrap();
+        assert!(missing.is_dir());
+
+        let file = dir.join("file");
+        tokio::fs::write(&file, b"not a directory").await.unwrap();
+        let err = FilesystemStorage::new(&file)
+            .validate_data_dir(true, true)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("is not a directory"), "{:#}", err);
+
+        #[cfg(unix)]
+        {
+            let link = dir.join("link");
+            std::os::unix::fs::symlink(&missing, &link).unwrap();
+            FilesystemStorage::new(&link)
+                .validate_data_dir(true, true)
+                .await
+                .unwrap();
+            let err = FilesystemStorage::new(&link)
+                .validate_data_dir(false, true)
+                .await
+                .unwrap_err();
+            assert!(
+                err.to_string().contains("--no-follow-data-symlink"),
+                "{:#}",
+                err
+            );
+
+            let dangling = dir.join("dangling");
+            std::os::unix::fs::symlink(dir.join("nowhere"), &dangling).unwrap();
+            let err = FilesystemStorage::new(&dangling)
+                .validate_data_dir(true, true)
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("dangling symlink"), "{:#}", err);
+        }
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_read_only_data_dir_fails_fast() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_data_dir("read-only-dir");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o555)).unwrap();
+
+        // Privileged users (e.g. root in containers) bypass permission bits
+        let probe = dir.join("probe");
+        let privileged = std::fs::write(&probe, b"").is_ok();
+        let _ = std::fs::remove_file(&probe);
+
+        let storage = FilesystemStorage::new(&dir);
+        if !privileged {
+            let err = storage.validate_data_dir(true, true).await.unwrap_err();
+            assert!(err.to_string().contains("is not writable"), "{:#}", err);
+        }
+        // Read-only servers only need to read it
+        storage.validate_data_dir(true, false).await.unwrap();
+
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_uploads_to_one_batch_are_all_recorded() {
+        let dir = temp_data_dir("concurrent-batch");
+        let storage = Arc::new(FilesystemStorage::new(&dir));
+
+        let tasks: Vec<_> = (0..32)
+            .map(|i| {
+                let storage = storage.clone();
+                tokio::spawn(async move {
+                    storage
+                        .store_file_and_update_tree(
+                            "client",
+                            "batch",
+                            &format!("file{:02}.txt", i),
+                            format!("content {}", i).as_bytes(),
+                        )
+                        .await
+                        .unwrap();
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // No metadata update was lost, and the tree covers every file
+        let filenames = storage
+            .load_batch_filenames("client", "batch")
+            .await
+            .unwrap();
+        let expected: Vec<String> = (0..32).map(|i| format!("file{:02}.txt", i)).collect();
+        assert_eq!(filenames, expected);
+        let tree = storage
+            .load_merkle_tree("client", "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tree.num_leaves(), 32);
+
+        // Locks are forgotten once released
+        assert!(storage.batch_locks.0.lock().unwrap().is_empty());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_atomic_writes_do_not_interfere() {
+        let dir = temp_data_dir("atomic");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        // Many writes to distinct targets in the same directory, all in flight at once
+        let tasks: Vec<_> = (0..64)
+            .map(|i| {
+                let path = dir.join(format!("file{}.bin", i));
+                tokio::spawn(async move {
+                    let content = vec![i as u8; 64 * 1024];
+                    FilesystemStorage::write_file_atomic(&path, &content, Durability::Strict)
+                        .await
+                        .unwrap();
+                    (path, content)
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            let (path, content) = task.await.unwrap();
+            assert_eq!(tokio::fs::read(&path).await.unwrap(), content);
+        }
+
+        // No temp files left behind
+        let mut entries = tokio::fs::read_dir(&dir).await.unwrap();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            assert!(!name.starts_with(".tmp."), "leftover temp file: {}", name);
+        }
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
     }
 }
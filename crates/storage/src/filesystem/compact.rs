@@ -0,0 +1,171 @@
+use super::reconcile::is_temp_file;
+use super::{Durability, FilesystemStorage, Metadata};
+use crate::{CompactionReport, StorageError};
+use anyhow::{Context, Result};
+use serde_json::Map;
+use std::collections::BTreeSet;
+use tracing::info;
+
+impl FilesystemStorage {
+    /// Rewrite a batch's metadata with only the entries it needs and delete leftover temp files
+    /// Filenames and access-list entries are sorted and deduplicated, defaults (private,
+    /// no access list, no annotations) and unknown keys are dropped. Unlike `reconcile`,
+    /// the file set is never changed: a listed file that is missing is an error.
+    pub(super) async fn compact(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<CompactionReport> {
+        let metadata_file = self.metadata_path(client_id, batch_id);
+        if !metadata_file.exists() {
+            return Err(StorageError::batch_not_found(batch_id).into());
+        }
+
+        let _guard = self.lock_batch(client_id, batch_id).await?;
+        let mut report = CompactionReport::default();
+
+        let metadata = Metadata::load(&metadata_file).await?;
+        let listed = Metadata::extract_filenames(&metadata)?;
+        let filenames: BTreeSet<String> = listed.iter().cloned().collect();
+        report.duplicate_entries_removed += listed.len() - filenames.len();
+        for filename in &filenames {
+            anyhow::ensure!(
+                self.file_path(client_id, batch_id, filename).exists(),
+                "Metadata lists {} but the file is missing; restart the server to reconcile the batch before compacting",
+                filename
+            );
+        }
+
+        let acl = Metadata::acl(&metadata);
+        let grantees: BTreeSet<String> = acl.iter().cloned().collect();
+        report.duplicate_entries_removed += acl.len() - grantees.len();
+
+        let mut compacted = Map::new();
+        Metadata::set_filenames(
+            &mut compacted,
+            &filenames.iter().cloned().collect::<Vec<_>>(),
+        );
+        if Metadata::is_public(&metadata) {
+            Metadata::set_public(&mut compacted, true);
+        }
+        for grantee in &grantees {
+            Metadata::set_access(&mut compacted, grantee, true);
+        }
+        let annotations = Metadata::annotations(&metadata);
+        if !annotations.is_empty() {
+            Metadata::set_annotations(&mut compacted, &annotations);
+        }
+        if let Some(root) = Metadata::committed_root(&metadata) {
+            Metadata::set_committed_root(&mut compacted, &root);
+        }
+        Metadata::save_atomic(&metadata_file, &compacted, Durability::Strict)
+            .await
+            .context("Failed to write metadata atomically")?;
+
+        // Every write to the batch holds its lock, so no temp file here is in use
+        let mut entries = tokio::fs::read_dir(self.batch_dir(client_id, batch_id))
+            .await
+            .context("Failed to read batch directory")?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type().await?.is_file()
+                && is_temp_file(&name)
+                && !filenames.contains(&name)
+            {
+                tokio::fs::remove_file(entry.path())
+                    .await
+                    .context("Failed to remove leftover temp file")?;
+                report.temp_files_removed += 1;
+            }
+        }
+
+        info!(
+            client_id = ?client_id,
+            batch_id = ?batch_id,
+            "Compacted batch: {:?}",
+            report
+        );
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Storage;
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn test_compaction_keeps_root_and_minimizes_metadata() {
+        let dir = std::env::temp_dir().join(format!("vs-compact-{}", std::process::id()));
+        // Every filename lands in metadata.json, so it can be hand-edited below
+        let storage = FilesystemStorage::new(&dir).with_metadata_log_limit(1);
+        for (name, content) in [("a.txt", b"a"), ("b.txt", b"b")] {
+            storage
+                .store_file_and_update_tree("client", "batch", name, content)
+                .await
+                .unwrap();
+        }
+        let root = storage
+            .load_merkle_tree("client", "batch")
+            .await
+            .unwrap()
+            .unwrap()
+            .root_hash();
+
+        // Cruft from hand edits, older versions and interrupted writes
+        let batch_dir = dir.join("client").join("batch");
+        let metadata_file = batch_dir.join("metadata.json");
+        std::fs::write(
+            &metadata_file,
+            json!({
+                "filenames": ["b.txt", "a.txt", "a.txt"],
+                "public": false,
+                "acl": ["reader", "reader"],
+                "annotations": {},
+                "legacy": true
+            })
+            .to_string(),
+        )
+        .unwrap();
+        std::fs::write(batch_dir.join(".tmp.1234.7"), b"partial").unwrap();
+
+        let report = storage.compact_batch("client", "batch").await.unwrap();
+        assert_eq!(report.duplicate_entries_removed, 2);
+        assert_eq!(report.temp_files_removed, 1);
+        assert!(!batch_dir.join(".tmp.1234.7").exists());
+
+        let metadata: Value =
+            serde_json::from_str(&std::fs::read_to_string(&metadata_file).unwrap()).unwrap();
+        assert_eq!(
+            metadata,
+            json!({"filenames": ["a.txt", "b.txt"], "acl": ["reader"]})
+        );
+        assert!(storage
+            .has_batch_access("client", "batch", "reader")
+            .await
+            .unwrap());
+        assert_eq!(
+            storage
+                .load_merkle_tree("client", "batch")
+                .await
+                .unwrap()
+                .unwrap()
+                .root_hash(),
+            root
+        );
+
+        // Already compact: nothing left to do
+        assert_eq!(
+            storage.compact_batch("client", "batch").await.unwrap(),
+            CompactionReport::default()
+        );
+
+        // A listed file that vanished is not silently dropped
+        std::fs::remove_file(batch_dir.join("b.txt")).unwrap();
+        assert!(storage.compact_batch("client", "batch").await.is_err());
+        assert!(storage.compact_batch("client", "missing").await.is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,9 +1,13 @@
+use super::{sync_file, Durability, FilesystemStorage, METADATA_LOG_FILE};
 use anyhow::{Context, Result};
 use serde_json::{Map, Value};
-use std::path::Path;
-use tokio::io::AsyncWriteExt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
 /// Filesystem metadata manager
+/// The S3 backend stores the same JSON object and edits it with the same helpers
 pub struct Metadata;
 
 impl Metadata {
@@ -14,11 +18,99 @@ impl Metadata {
     }
 
     /// Load metadata from file (public for use in atomic operations)
+    /// Filenames appended to the log since the last compaction are merged in
     pub async fn load(metadata_file: &Path) -> Result<Map<String, Value>> {
+        Ok(Self::load_with_log_len(metadata_file).await?.0)
+    }
+
+    /// Load metadata along with the number of filenames replayed from the log
+    pub async fn load_with_log_len(metadata_file: &Path) -> Result<(Map<String, Value>, usize)> {
         let content = tokio::fs::read_to_string(metadata_file)
             .await
             .context("Failed to read metadata")?;
-        serde_json::from_str(&content).context("Failed to parse metadata")
+        let mut metadata: Map<String, Value> =
+            serde_json::from_str(&content).context("Failed to parse metadata")?;
+
+        let logged = Self::load_log(metadata_file).await?;
+        if !logged.is_empty() {
+            let mut filenames: BTreeSet<String> = Self::extract_filenames(&metadata)
+                .unwrap_or_default()
+                .into_iter()
+                .collect();
+            filenames.extend(logged.iter().cloned());
+            Self::set_filenames(&mut metadata, &filenames.into_iter().collect::<Vec<_>>());
+        }
+        Ok((metadata, logged.len()))
+    }
+
+    /// Path of the append-only filename log beside a metadata file
+    fn log_path(metadata_file: &Path) -> PathBuf {
+        metadata_file.with_file_name(METADATA_LOG_FILE)
+    }
+
+    /// Filenames appended to the log since the last compaction
+    /// A last line without its newline is a torn append from a crash and is ignored
+    async fn load_log(metadata_file: &Path) -> Result<Vec<String>> {
+        let content = match tokio::fs::read(Self::log_path(metadata_file)).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context("Failed to read metadata log"),
+        };
+        let complete = content
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(&content[..0], |end| &content[..end]);
+        Ok(std::str::from_utf8(complete)
+            .context("Failed to parse metadata log")?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.to_string())
+            .collect())
+    }
+
+    /// Append a filename to the metadata log instead of rewriting the metadata file
+    /// A torn last line left by a crash is truncated first, so the entry starts on its own line
+    pub async fn append_filename(
+        metadata_file: &Path,
+        filename: &str,
+        durability: Durability,
+    ) -> Result<()> {
+        let mut log = tokio::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(Self::log_path(metadata_file))
+            .await
+            .context("Failed to open metadata log")?;
+
+        let len = log.metadata().await?.len();
+        if len > 0 {
+            let mut last = [0u8; 1];
+            log.seek(SeekFrom::End(-1)).await?;
+            log.read_exact(&mut last).await?;
+            if last[0] != b'\n' {
+                let mut content = Vec::new();
+                log.seek(SeekFrom::Start(0)).await?;
+                log.read_to_end(&mut content).await?;
+                let keep = content
+                    .iter()
+                    .rposition(|b| *b == b'\n')
+                    .map_or(0, |i| i + 1);
+                log.set_len(keep as u64)
+                    .await
+                    .context("Failed to truncate torn metadata log entry")?;
+            }
+        }
+
+        log.seek(SeekFrom::End(0)).await?;
+        log.write_all(format!("{}\n", filename).as_bytes())
+            .await
+            .context("Failed to append to metadata log")?;
+        if durability == Durability::Strict {
+            sync_file(&log).await?;
+        }
+        Ok(())
     }
 
     /// Insert filename into metadata (public for use in atomic operations)
@@ -37,8 +129,96 @@ impl Metadata {
         }
     }
 
+    /// Replace the filename list in metadata (filenames must already be sorted)
+    pub fn set_filenames(metadata: &mut Map<String, Value>, filenames: &[String]) {
+        let filenames = filenames.iter().cloned().map(Value::String).collect();
+        metadata.insert("filenames".to_string(), Value::Array(filenames));
+    }
+
+    /// Set the public flag in metadata
+    pub fn set_public(metadata: &mut Map<String, Value>, public: bool) {
+        metadata.insert("public".to_string(), Value::Bool(public));
+    }
+
+    /// Check whether metadata marks the batch as public (absent means private)
+    pub fn is_public(metadata: &Map<String, Value>) -> bool {
+        metadata
+            .get("public")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+    }
+
+    /// Client IDs granted read access to the batch (absent means none)
+    pub fn acl(metadata: &Map<String, Value>) -> Vec<String> {
+        metadata
+            .get("acl")
+            .and_then(|v| v.as_array())
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Add or remove a client ID from the batch's access list (kept sorted)
+    pub fn set_access(metadata: &mut Map<String, Value>, grantee_id: &str, granted: bool) {
+        let mut acl = Self::acl(metadata);
+        acl.retain(|id| id != grantee_id);
+        if granted {
+            acl.push(grantee_id.to_string());
+            acl.sort_unstable();
+        }
+        metadata.insert(
+            "acl".to_string(),
+            Value::Array(acl.into_iter().map(Value::String).collect()),
+        );
+    }
+
+    /// Key-value annotations of the batch (absent means none)
+    pub fn annotations(metadata: &Map<String, Value>) -> BTreeMap<String, String> {
+        metadata
+            .get("annotations")
+            .and_then(|v| v.as_object())
+            .map(|obj| {
+                obj.iter()
+                    .filter_map(|(k, v)| v.as_str().map(|s| (k.clone(), s.to_string())))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Replace the batch's annotations
+    pub fn set_annotations(
+        metadata: &mut Map<String, Value>,
+        annotations: &BTreeMap<String, String>,
+    ) {
+        let annotations = annotations
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+            .collect();
+        metadata.insert("annotations".to_string(), Value::Object(annotations));
+    }
+
+    /// Root recorded by the batch's last finalize (absent means never finalized)
+    pub fn committed_root(metadata: &Map<String, Value>) -> Option<[u8; 32]> {
+        metadata
+            .get("committed_root")
+            .and_then(|v| v.as_str())
+            .and_then(|s| hex::decode(s).ok())
+            .and_then(|bytes| bytes.try_into().ok())
+    }
+
+    /// Record the root committed to by a finalize
+    pub fn set_committed_root(metadata: &mut Map<String, Value>, root: &[u8; 32]) {
+        metadata.insert(
+            "committed_root".to_string(),
+            Value::String(hex::encode(root)),
+        );
+    }
+
     /// Extract filenames from metadata
-    fn extract_filenames(metadata: &Map<String, Value>) -> Result<Vec<String>> {
+    pub fn extract_filenames(metadata: &Map<String, Value>) -> Result<Vec<String>> {
         metadata
             .get("filenames")
             .and_then(|v| v.as_array())
@@ -50,26 +230,110 @@ impl Metadata {
             })
     }
 
-    /// Save metadata to file with fsync to ensure data is persisted
-    pub async fn save_atomic(metadata_file: &Path, metadata: &Map<String, Value>) -> Result<()> {
+    /// Save metadata to file atomically (temp file + fsync + rename)
+    /// The metadata must have been loaded with the log merged in; the log is then removed.
+    /// Replaying a log that outlives a crash only re-adds filenames already saved.
+    pub async fn save_atomic(
+        metadata_file: &Path,
+        metadata: &Map<String, Value>,
+        durability: Durability,
+    ) -> Result<()> {
         // Serialize metadata to JSON
         let metadata_json =
             serde_json::to_string_pretty(metadata).context("Failed to serialize metadata")?;
 
-        // Write directly to metadata file
-        let mut file = tokio::fs::File::create(metadata_file)
+        FilesystemStorage::write_file_atomic(metadata_file, metadata_json.as_bytes(), durability)
             .await
-            .context("Failed to create metadata file")?;
+            .context("Failed to write metadata file")?;
+
+        match tokio::fs::remove_file(Self::log_path(metadata_file)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).context("Failed to remove compacted metadata log"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Storage;
 
-        file.write_all(metadata_json.as_bytes())
+    #[tokio::test]
+    async fn test_log_and_compaction_yield_filename_set() {
+        let dir = std::env::temp_dir().join(format!("vs-metadata-log-{}", std::process::id()));
+        let storage = FilesystemStorage::new(&dir).with_metadata_log_limit(4);
+        let batch_dir = dir.join("client").join("batch");
+        let metadata_file = batch_dir.join("metadata.json");
+        let log_file = batch_dir.join(METADATA_LOG_FILE);
+        let upload = |name: &'static str| {
+            storage.store_file_and_update_tree("client", "batch", name, name.as_bytes())
+        };
+        let filenames = || storage.load_batch_filenames("client", "batch");
+
+        // The first file creates metadata.json, later ones are appended to the log
+        for name in ["c.txt", "a.txt", "d.txt", "a.txt", "b.txt"] {
+            upload(name).await.unwrap();
+        }
+        assert_eq!(
+            std::fs::read_to_string(&log_file).unwrap(),
+            "a.txt\nd.txt\nb.txt\n"
+        );
+        assert_eq!(
+            Metadata::extract_filenames(
+                &serde_json::from_str(&std::fs::read_to_string(&metadata_file).unwrap()).unwrap()
+            )
+            .unwrap(),
+            ["c.txt"]
+        );
+        assert_eq!(
+            filenames().await.unwrap(),
+            ["a.txt", "b.txt", "c.txt", "d.txt"]
+        );
+
+        // A torn append from a crash is ignored, then truncated by the next append
+        std::fs::write(&log_file, "a.txt\nd.txt\nb.txt\nz.t").unwrap();
+        assert_eq!(
+            filenames().await.unwrap(),
+            ["a.txt", "b.txt", "c.txt", "d.txt"]
+        );
+        Metadata::append_filename(&metadata_file, "b.txt", Durability::Strict)
             .await
-            .context("Failed to write metadata to file")?;
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&log_file).unwrap(),
+            "a.txt\nd.txt\nb.txt\nb.txt\n"
+        );
+
+        // Reaching the limit folds the log into metadata.json
+        upload("e.txt").await.unwrap();
+        assert!(!log_file.exists());
+        let expected = ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"];
+        assert_eq!(
+            Metadata::load_filenames(&metadata_file).await.unwrap(),
+            expected
+        );
 
-        // Sync file data to disk to ensure it's persisted
-        file.sync_all()
+        // The tree covers exactly the same files, and reconcile finds nothing to repair
+        let tree = storage
+            .load_merkle_tree("client", "batch")
             .await
-            .context("Failed to sync metadata file to disk")?;
+            .unwrap()
+            .unwrap();
+        let leaves: Vec<_> = expected
+            .iter()
+            .map(|name| crypto::hash_leaf(name.as_bytes()))
+            .collect();
+        assert_eq!(
+            tree.root_hash(),
+            merkle_tree::MerkleTree::from_leaf_hashes(&leaves)
+                .unwrap()
+                .root_hash()
+        );
+        upload("f.txt").await.unwrap();
+        assert!(storage.reconcile().await.unwrap().is_clean());
+        assert_eq!(filenames().await.unwrap().len(), 6);
 
-        Ok(())
+        std::fs::remove_dir_all(&dir).ok();
     }
 }
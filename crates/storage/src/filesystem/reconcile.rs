@@ -0,0 +1,429 @@
+use super::{
+    Durability, FilesystemStorage, Metadata, LOCK_FILE, MERKLE_TREE_FILE, METADATA_FILE,
+    METADATA_LOG_FILE, REPLACE_DIR,
+};
+use crate::Storage;
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::time::SystemTime;
+use tracing::warn;
+
+/// Outcome of reconciling batch metadata with the files on disk
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReconcileReport {
+    /// Number of batch directories inspected
+    pub batches_checked: usize,
+    /// Files found on disk but missing from metadata (added back)
+    pub files_added: usize,
+    /// Metadata entries with no backing file (removed)
+    pub entries_removed: usize,
+    /// Merkle trees rebuilt because they were missing or stale
+    pub trees_rebuilt: usize,
+    /// Leftover temp files from interrupted atomic writes (deleted)
+    pub temp_files_removed: usize,
+    /// Leftovers of interrupted batch replacements (swaps finished, staging deleted)
+    pub replacements_recovered: usize,
+}
+
+impl ReconcileReport {
+    /// True if nothing needed fixing
+    pub fn is_clean(&self) -> bool {
+        self.files_added == 0
+            && self.entries_removed == 0
+            && self.trees_rebuilt == 0
+            && self.temp_files_removed == 0
+            && self.replacements_recovered == 0
+    }
+}
+
+impl FilesystemStorage {
+    /// Reconcile every batch's metadata and Merkle tree with the files actually on disk
+    /// A store writes the file, then metadata, then the tree, each atomically but not
+    /// together; a crash in between leaves them out of sync. Files on disk are treated
+    /// as the source of truth: unlisted files are added, entries without a file are
+    /// removed, and trees that are missing or older than a file are rebuilt. Interrupted
+    /// batch replacements are settled first, so their batches are then checked as usual.
+    pub async fn reconcile(&self) -> Result<ReconcileReport> {
+        let mut report = ReconcileReport::default();
+        if !self.data_dir.exists() {
+            return Ok(report);
+        }
+        report.replacements_recovered = self.recover_replacements().await?;
+
+        let mut clients = tokio::fs::read_dir(&self.data_dir)
+            .await
+            .context("Failed to read data directory")?;
+        while let Some(client) = clients.next_entry().await? {
+            if !client.file_type().await?.is_dir() || client.file_name() == REPLACE_DIR {
+                continue;
+            }
+            let client_id = client.file_name().to_string_lossy().to_string();
+
+            let mut batches = tokio::fs::read_dir(client.path())
+                .await
+                .context("Failed to read client directory")?;
+            while let Some(batch) = batches.next_entry().await? {
+                if !batch.file_type().await?.is_dir() {
+                    continue;
+                }
+                let batch_id = batch.file_name().to_string_lossy().to_string();
+                self.reconcile_batch(&client_id, &batch_id, &mut report)
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "Failed to reconcile batch {} for client {}",
+                            batch_id, client_id
+                        )
+                    })?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    async fn reconcile_batch(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        report: &mut ReconcileReport,
+    ) -> Result<()> {
+        let _guard = self.lock_batch(client_id, batch_id).await?;
+
+        let metadata_file = self.metadata_path(client_id, batch_id);
+        let has_metadata = metadata_file.exists();
+        let (mut metadata, listed) = if has_metadata {
+            let metadata = Metadata::load(&metadata_file).await?;
+            let listed = Metadata::load_filenames(&metadata_file).await?;
+            (metadata, listed.into_iter().collect::<BTreeSet<_>>())
+        } else {
+            (serde_json::Map::new(), BTreeSet::new())
+        };
+
+        // Scan the batch directory, remembering the newest data file
+        let mut on_disk = BTreeSet::new();
+        let mut newest_file: Option<SystemTime> = None;
+        let mut entries = tokio::fs::read_dir(self.batch_dir(client_id, batch_id))
+            .await
+            .context("Failed to read batch directory")?;
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            if [
+                METADATA_FILE,
+                METADATA_LOG_FILE,
+                MERKLE_TREE_FILE,
+                LOCK_FILE,
+            ]
+            .contains(&name.as_str())
+            {
+                continue;
+            }
+            if is_temp_file(&name) && !listed.contains(&name) {
+                warn!(
+                    client_id = ?client_id,
+                    batch_id = ?batch_id,
+                    "Removing leftover temp file {:?}",
+                    name
+                );
+                tokio::fs::remove_file(entry.path())
+                    .await
+                    .context("Failed to remove leftover temp file")?;
+                report.temp_files_removed += 1;
+                continue;
+            }
+
+            let modified = entry.metadata().await?.modified()?;
+            newest_file = newest_file.max(Some(modified));
+            on_disk.insert(name);
+        }
+
+        // A directory with neither metadata nor files is not a batch
+        if !has_metadata && on_disk.is_empty() {
+            return Ok(());
+        }
+        report.batches_checked += 1;
+
+        for filename in on_disk.difference(&listed) {
+            warn!(
+                client_id = ?client_id,
+                batch_id = ?batch_id,
+                "File {:?} missing from metadata, adding it",
+                filename
+            );
+            report.files_added += 1;
+        }
+        for filename in listed.difference(&on_disk) {
+            warn!(
+                client_id = ?client_id,
+                batch_id = ?batch_id,
+                "Metadata lists {:?} but the file is missing, removing entry",
+                filename
+            );
+            report.entries_removed += 1;
+        }
+
+        // BTreeSet iteration is sorted, matching metadata and leaf order
+        let filenames: Vec<String> = on_disk.into_iter().collect();
+        let metadata_changed = !has_metadata || filenames.iter().ne(listed.iter());
+        if metadata_changed {
+            Metadata::set_filenames(&mut metadata, &filenames);
+            // Repairs are always flushed, whatever the upload durability
+            Metadata::save_atomic(&metadata_file, &metadata, Durability::Strict)
+                .await
+                .context("Failed to write metadata atomically")?;
+        }
+
+        let tree_file = self.merkle_tree_path(client_id, batch_id);
+        if filenames.is_empty() {
+            if tree_file.exists() {
+                tokio::fs::remove_file(&tree_file)
+                    .await
+                    .context("Failed to remove Merkle tree of empty batch")?;
+            }
+            return Ok(());
+        }
+
+        // The tree is written last, so it is stale if any file is newer than it
+        let stored = self
+            .load_merkle_tree(client_id, batch_id)
+            .await
+            .ok()
+            .flatten();
+        let tree_stale = match (&stored, tree_file.metadata()) {
+            (Some(tree), Ok(tree_meta)) => {
+                tree.num_leaves() != filenames.len() || newest_file > Some(tree_meta.modified()?)
+            }
+            _ => true,
+        };
+        if metadata_changed || tree_stale {
+            if let Some(tree) = stored.filter(|tree| tree.duplicates_lone_nodes()) {
+                // A tree from before lone-node promotion gets a new root when rebuilt, and
+                // clients hold the old one: keep it, only refreshed, if the files still match
+                if !metadata_changed
+                    && tree.levels()[0] == self.leaf_hashes(client_id, batch_id, &filenames).await?
+                {
+                    self.write_tree(client_id, batch_id, &tree, Durability::Strict)
+                        .await?;
+                    return Ok(());
+                }
+                warn!(
+                    client_id = ?client_id,
+                    batch_id = ?batch_id,
+                    "Files changed under a legacy Merkle tree; its root {} is replaced",
+                    hex::encode(tree.root_hash())
+                );
+            }
+            warn!(
+                client_id = ?client_id,
+                batch_id = ?batch_id,
+                "Rebuilding Merkle tree"
+            );
+            self.rebuild_tree(client_id, batch_id, &filenames, Durability::Strict)
+                .await?;
+            report.trees_rebuilt += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether a name looks like a temp file from `create_temp_file`: `.tmp.{pid}.{counter}`,
+/// or `.{name}.{pid}.{counter}.tmp` as left behind by earlier versions
+pub(super) fn is_temp_file(name: &str) -> bool {
+    let is_number = |part: Option<&str>| {
+        part.is_some_and(|p| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit()))
+    };
+    if let Some(inner) = name.strip_prefix(".tmp.") {
+        let mut parts = inner.split('.');
+        return is_number(parts.next()) && is_number(parts.next()) && parts.next().is_none();
+    }
+    let Some(inner) = name.strip_prefix('.').and_then(|n| n.strip_suffix(".tmp")) else {
+        return false;
+    };
+    let mut parts = inner.rsplitn(3, '.');
+    is_number(parts.next())
+        && is_number(parts.next())
+        && parts.next().is_some_and(|n| !n.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use merkle_tree::MerkleTree;
+    use std::path::PathBuf;
+
+    fn temp_data_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("vs-reconcile-{}-{}", name, std::process::id()))
+    }
+
+    async fn root_of(storage: &FilesystemStorage, batch_id: &str) -> [u8; 32] {
+        storage
+            .load_merkle_tree("client", batch_id)
+            .await
+            .unwrap()
+            .unwrap()
+            .root_hash()
+    }
+
+    fn expected_root(contents: &[&[u8]]) -> [u8; 32] {
+        let data: Vec<Vec<u8>> = contents.iter().map(|c| c.to_vec()).collect();
+        MerkleTree::from_data(&data).unwrap().root_hash()
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_adds_file_written_before_crash() {
+        let dir = temp_data_dir("orphan");
+        let storage = FilesystemStorage::new(&dir);
+        storage
+            .store_file_and_update_tree("client", "batch", "a.txt", b"a")
+            .await
+            .unwrap();
+
+        // Crash after the file rename, before metadata and tree were updated
+        let batch_dir = dir.join("client").join("batch");
+        std::fs::write(batch_dir.join("b.txt"), b"b").unwrap();
+        // ... and an interrupted write left its temp file behind
+        std::fs::write(batch_dir.join(".tmp.1234.7"), b"partial").unwrap();
+
+        let report = storage.reconcile().await.unwrap();
+        assert_eq!(report.files_added, 1);
+        assert_eq!(report.temp_files_removed, 1);
+        assert_eq!(report.trees_rebuilt, 1);
+        assert_eq!(
+            storage
+                .load_batch_filenames("client", "batch")
+                .await
+                .unwrap(),
+            vec!["a.txt", "b.txt"]
+        );
+        assert_eq!(
+            root_of(&storage, "batch").await,
+            expected_root(&[b"a", b"b"])
+        );
+        assert!(!batch_dir.join(".tmp.1234.7").exists());
+
+        // A second pass finds nothing to fix
+        assert!(storage.reconcile().await.unwrap().is_clean());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_drops_entry_without_file() {
+        let dir = temp_data_dir("missing");
+        let storage = FilesystemStorage::new(&dir);
+        for (name, content) in [("a.txt", b"a"), ("b.txt", b"b")] {
+            storage
+                .store_file_and_update_tree("client", "batch", name, content)
+                .await
+                .unwrap();
+        }
+
+        // Metadata lists a file that never made it to disk
+        std::fs::remove_file(dir.join("client").join("batch").join("b.txt")).unwrap();
+
+        let report = storage.reconcile().await.unwrap();
+        assert_eq!(report.entries_removed, 1);
+        assert_eq!(report.trees_rebuilt, 1);
+        assert_eq!(
+            storage
+                .load_batch_filenames("client", "batch")
+                .await
+                .unwrap(),
+            vec!["a.txt"]
+        );
+        assert_eq!(root_of(&storage, "batch").await, expected_root(&[b"a"]));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_rebuilds_tree_older_than_files() {
+        let dir = temp_data_dir("stale-tree");
+        let storage = FilesystemStorage::new(&dir);
+        for (name, content) in [("a.txt", b"a"), ("b.txt", b"b")] {
+            storage
+                .store_file_and_update_tree("client", "batch", name, content)
+                .await
+                .unwrap();
+        }
+
+        // Crash after an overwrite was renamed into place, before the tree was rewritten
+        let file = dir.join("client").join("batch").join("b.txt");
+        std::fs::write(&file, b"new").unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(5);
+        std::fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+
+        let report = storage.reconcile().await.unwrap();
+        assert_eq!(report.files_added, 0);
+        assert_eq!(report.entries_removed, 0);
+        assert_eq!(report.trees_rebuilt, 1);
+        assert_eq!(
+            root_of(&storage, "batch").await,
+            expected_root(&[b"a", b"new"])
+        );
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_keeps_legacy_tree_matching_files() {
+        let dir = temp_data_dir("legacy-tree");
+        let storage = FilesystemStorage::new(&dir);
+        let contents: [&[u8]; 3] = [b"a", b"b", b"c"];
+        for (name, content) in ["a.txt", "b.txt", "c.txt"].into_iter().zip(contents) {
+            storage
+                .store_file_and_update_tree("client", "batch", name, content)
+                .await
+                .unwrap();
+        }
+
+        // A tree stored before lone-node promotion, older than the files (e.g. restored
+        // from a backup that did not keep modification times)
+        let leaves: Vec<[u8; 32]> = contents.iter().map(|c| crypto::hash_leaf(c)).collect();
+        let legacy = MerkleTree::from_leaf_hashes_legacy(&leaves).unwrap();
+        let tree_file = dir.join("client").join("batch").join(MERKLE_TREE_FILE);
+        std::fs::write(&tree_file, serde_json::to_vec(&legacy).unwrap()).unwrap();
+        let earlier = SystemTime::now() - std::time::Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&tree_file)
+            .unwrap()
+            .set_modified(earlier)
+            .unwrap();
+
+        let report = storage.reconcile().await.unwrap();
+        assert!(report.is_clean());
+        assert_eq!(root_of(&storage, "batch").await, legacy.root_hash());
+        assert!(tree_file.metadata().unwrap().modified().unwrap() > earlier);
+
+        // Once a file really changes, the tree is rebuilt the current way
+        std::fs::write(dir.join("client").join("batch").join("d.txt"), b"d").unwrap();
+        let report = storage.reconcile().await.unwrap();
+        assert_eq!(report.trees_rebuilt, 1);
+        assert_eq!(
+            root_of(&storage, "batch").await,
+            expected_root(&[b"a", b"b", b"c", b"d"])
+        );
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn test_is_temp_file() {
+        assert!(is_temp_file(".tmp.42.0"));
+        assert!(is_temp_file(".a.txt.42.0.tmp"));
+        assert!(!is_temp_file(".tmp.42"));
+        assert!(!is_temp_file(".tmp.42.0.txt"));
+        assert!(!is_temp_file("a.txt"));
+        assert!(!is_temp_file(".hidden.tmp"));
+        assert!(!is_temp_file(".a.txt.x.0.tmp"));
+    }
+}
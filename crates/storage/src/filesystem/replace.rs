@@ -0,0 +1,497 @@
+use super::{sync_dir, FilesystemStorage, Metadata, MERKLE_TREE_FILE, METADATA_FILE, REPLACE_DIR};
+use crate::{BatchFull, ReplaceConflict, ReplacementFile, Storage, StorageError};
+use anyhow::{Context, Result};
+use merkle_tree::MerkleTree;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Stage of a batch replacement: the complete new batch directory, built beside the batch
+const STAGED: &str = "new";
+
+/// Stage of a batch replacement: the old batch directory, moved aside during the swap
+const RETIRED: &str = "old";
+
+impl FilesystemStorage {
+    /// Where a replacement of one of `client_id`'s batches keeps a directory in `stage`
+    /// Kept outside the client directory, so a staged batch is never listed as a batch
+    fn replacement_dir(&self, client_id: &str, stage: &str, batch_id: &str) -> PathBuf {
+        self.data_dir
+            .join(REPLACE_DIR)
+            .join(client_id)
+            .join(stage)
+            .join(batch_id)
+    }
+
+    /// Replace a batch's file set: build the complete new batch directory beside it, then
+    /// swap the two with renames
+    /// A failure while staging leaves the batch untouched. A crash between the two renames
+    /// leaves no batch directory, only the staged and the old one; `reconcile` then
+    /// finishes the swap.
+    pub(super) async fn replace(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        files: &[ReplacementFile],
+        committed_root: &[u8; 32],
+    ) -> Result<()> {
+        let metadata_file = self.metadata_path(client_id, batch_id);
+        if !metadata_file.exists() {
+            return Err(StorageError::batch_not_found(batch_id).into());
+        }
+        if let Some(max_files) = self.max_files_per_batch {
+            if files.len() > max_files {
+                return Err(BatchFull { max_files }.into());
+            }
+        }
+
+        let _guard = self.lock_batch(client_id, batch_id).await?;
+
+        // Leaves are in filename order, like the batch's own tree
+        let mut files: Vec<&ReplacementFile> = files.iter().collect();
+        files.sort_by(|a, b| a.filename.cmp(&b.filename));
+        if let Some(pair) = files.windows(2).find(|w| w[0].filename == w[1].filename) {
+            anyhow::bail!("File {} is listed twice", pair[0].filename);
+        }
+
+        // Kept files must still be what the caller saw, or the new root would be wrong
+        let metadata = Metadata::load(&metadata_file).await?;
+        let current = Metadata::extract_filenames(&metadata)?;
+        let tree = self.load_merkle_tree(client_id, batch_id).await?;
+        for file in files.iter().filter(|file| file.source.is_none()) {
+            let leaf_hash = current
+                .binary_search(&file.filename)
+                .ok()
+                .and_then(|index| tree.as_ref()?.leaf_hash(index));
+            if leaf_hash != Some(file.leaf_hash) {
+                return Err(ReplaceConflict {
+                    filename: file.filename.clone(),
+                }
+                .into());
+            }
+        }
+
+        let staged = self.replacement_dir(client_id, STAGED, batch_id);
+        let result = self
+            .stage_replacement(
+                client_id,
+                batch_id,
+                &staged,
+                &files,
+                metadata,
+                committed_root,
+            )
+            .await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_dir_all(&staged).await;
+        }
+        result?;
+
+        self.swap_in_replacement(client_id, batch_id, &staged).await
+    }
+
+    /// Write the complete new batch directory: every file, the metadata and the tree
+    async fn stage_replacement(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        staged: &Path,
+        files: &[&ReplacementFile],
+        mut metadata: serde_json::Map<String, serde_json::Value>,
+        committed_root: &[u8; 32],
+    ) -> Result<()> {
+        // Left behind by a replacement that failed before it could clean up
+        if staged.exists() {
+            tokio::fs::remove_dir_all(staged)
+                .await
+                .context("Failed to remove stale staged batch")?;
+        }
+        tokio::fs::create_dir_all(staged)
+            .await
+            .context("Failed to create staged batch directory")?;
+
+        for file in files {
+            let target = staged.join(&file.filename);
+            match &file.source {
+                Some(source) => self
+                    .copy_content(source, &target)
+                    .await
+                    .with_context(|| format!("Failed to stage file {}", file.filename))?,
+                None => {
+                    // Kept files are never rewritten in place, so a hard link is a safe copy
+                    let current = self.file_path(client_id, batch_id, &file.filename);
+                    if tokio::fs::hard_link(&current, &target).await.is_err() {
+                        Self::copy_file_atomic(&current, &target, self.durability)
+                            .await
+                            .with_context(|| format!("Failed to stage file {}", file.filename))?;
+                    }
+                }
+            }
+        }
+
+        // Visibility, access list and annotations carry over; the file set and root do not
+        let filenames: Vec<String> = files.iter().map(|file| file.filename.clone()).collect();
+        Metadata::set_filenames(&mut metadata, &filenames);
+        Metadata::set_committed_root(&mut metadata, committed_root);
+        Metadata::save_atomic(&staged.join(METADATA_FILE), &metadata, self.durability)
+            .await
+            .context("Failed to write staged metadata")?;
+
+        // A batch without files has no tree
+        if !files.is_empty() {
+            let leaf_hashes: Vec<[u8; 32]> = files.iter().map(|file| file.leaf_hash).collect();
+            let tree = MerkleTree::from_leaf_hashes(&leaf_hashes)
+                .context("Failed to build Merkle tree from leaf hashes")?;
+            let tree_json =
+                serde_json::to_string_pretty(&tree).context("Failed to serialize Merkle tree")?;
+            Self::write_file_atomic(
+                &staged.join(MERKLE_TREE_FILE),
+                tree_json.as_bytes(),
+                self.durability,
+            )
+            .await
+            .context("Failed to write staged Merkle tree")?;
+        }
+
+        // The swap must never expose a staged batch whose entries are not on disk yet
+        sync_dir(staged).await
+    }
+
+    /// Move the batch directory aside and the staged one into its place
+    async fn swap_in_replacement(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        staged: &Path,
+    ) -> Result<()> {
+        let batch_dir = self.batch_dir(client_id, batch_id);
+        let retired = self.prepare_retired_dir(client_id, batch_id).await?;
+
+        if let Err(e) = tokio::fs::rename(&batch_dir, &retired).await {
+            let _ = tokio::fs::remove_dir_all(staged).await;
+            return Err(e).context("Failed to move batch directory aside");
+        }
+        if let Err(e) = tokio::fs::rename(staged, &batch_dir).await {
+            // Put the old batch back rather than leave none
+            tokio::fs::rename(&retired, &batch_dir)
+                .await
+                .context("Failed to restore batch directory after a failed replacement")?;
+            let _ = tokio::fs::remove_dir_all(staged).await;
+            return Err(e).context("Failed to move staged batch into place");
+        }
+        sync_dir(&self.client_dir(client_id)).await?;
+
+        // The replacement is complete; a leftover old directory is removed by `reconcile`
+        if let Err(e) = tokio::fs::remove_dir_all(&retired).await {
+            warn!(
+                client_id = ?client_id,
+                batch_id = ?batch_id,
+                "Failed to remove replaced batch directory: {}",
+                e
+            );
+        }
+        Ok(())
+    }
+
+    /// Delete a batch directory by moving it aside as a retired batch, then removing it
+    /// The move is one rename, so a crash never leaves part of the batch in place; a
+    /// retired directory left behind is removed by `reconcile`
+    pub(super) async fn remove_batch_dir(&self, client_id: &str, batch_id: &str) -> Result<()> {
+        let retired = self.prepare_retired_dir(client_id, batch_id).await?;
+        tokio::fs::rename(self.batch_dir(client_id, batch_id), &retired)
+            .await
+            .context("Failed to move batch directory aside")?;
+        sync_dir(&self.client_dir(client_id)).await?;
+        tokio::fs::remove_dir_all(&retired)
+            .await
+            .context("Failed to remove deleted batch directory")
+    }
+
+    /// Where to move a batch directory aside to, with any stale leftover there removed
+    async fn prepare_retired_dir(&self, client_id: &str, batch_id: &str) -> Result<PathBuf> {
+        let retired = self.replacement_dir(client_id, RETIRED, batch_id);
+        if retired.exists() {
+            tokio::fs::remove_dir_all(&retired)
+                .await
+                .context("Failed to remove stale retired batch")?;
+        }
+        if let Some(parent) = retired.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create retired batch directory")?;
+        }
+        Ok(retired)
+    }
+
+    /// Finish or discard batch replacements interrupted by a crash
+    /// A staged batch whose batch directory is gone while the old one was moved aside was
+    /// mid-swap and is moved into place; any other staged batch never replaced anything
+    /// and is deleted, as is every old batch directory. Returns the number of leftover
+    /// directories handled.
+    pub(super) async fn recover_replacements(&self) -> Result<usize> {
+        let root = self.data_dir.join(REPLACE_DIR);
+        if !root.exists() {
+            return Ok(0);
+        }
+
+        let mut recovered = 0;
+        let mut clients = tokio::fs::read_dir(&root)
+            .await
+            .context("Failed to read replacement directory")?;
+        while let Some(client) = clients.next_entry().await? {
+            let client_id = client.file_name().to_string_lossy().to_string();
+            for stage in [STAGED, RETIRED] {
+                let stage_dir = client.path().join(stage);
+                if !stage_dir.exists() {
+                    continue;
+                }
+                let mut batches = tokio::fs::read_dir(&stage_dir)
+                    .await
+                    .context("Failed to read replacement directory")?;
+                while let Some(batch) = batches.next_entry().await? {
+                    let batch_id = batch.file_name().to_string_lossy().to_string();
+                    let batch_dir = self.batch_dir(&client_id, &batch_id);
+                    let retired = self.replacement_dir(&client_id, RETIRED, &batch_id);
+                    if stage == STAGED && !batch_dir.exists() && retired.exists() {
+                        warn!(
+                            client_id = ?client_id,
+                            batch_id = ?batch_id,
+                            "Finishing batch replacement interrupted mid-swap"
+                        );
+                        tokio::fs::rename(batch.path(), &batch_dir)
+                            .await
+                            .context("Failed to move staged batch into place")?;
+                    } else {
+                        warn!(
+                            client_id = ?client_id,
+                            batch_id = ?batch_id,
+                            "Removing leftover {} batch of an interrupted replacement",
+                            stage
+                        );
+                        tokio::fs::remove_dir_all(batch.path())
+                            .await
+                            .context("Failed to remove leftover replacement directory")?;
+                    }
+                    recovered += 1;
+                }
+            }
+        }
+        Ok(recovered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::hash_leaf;
+
+    fn temp_data_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("vs-replace-{}-{}", name, std::process::id()))
+    }
+
+    fn kept(filename: &str, content: &[u8]) -> ReplacementFile {
+        ReplacementFile {
+            filename: filename.to_string(),
+            leaf_hash: hash_leaf(content),
+            source: None,
+        }
+    }
+
+    fn uploaded(dir: &Path, filename: &str, content: &[u8]) -> ReplacementFile {
+        let source = dir.join(format!("upload-{}", filename));
+        std::fs::write(&source, content).unwrap();
+        ReplacementFile {
+            filename: filename.to_string(),
+            leaf_hash: hash_leaf(content),
+            source: Some(source),
+        }
+    }
+
+    async fn batch_with_files(storage: &FilesystemStorage) {
+        for (name, content) in [("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")] {
+            storage
+                .store_file_and_update_tree("client", "batch", name, content)
+                .await
+                .unwrap();
+        }
+        storage
+            .set_batch_annotations(
+                "client",
+                "batch",
+                &[("k".to_string(), "v".to_string())].into(),
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replace_swaps_file_set_and_root() {
+        let dir = temp_data_dir("swap");
+        let storage = FilesystemStorage::new(&dir);
+        batch_with_files(&storage).await;
+
+        let files = [
+            kept("a.txt", b"a"),
+            uploaded(&dir, "b.txt", b"b2"),
+            uploaded(&dir, "d.txt", b"d"),
+        ];
+        let root =
+            MerkleTree::from_leaf_hashes(&[hash_leaf(b"a"), hash_leaf(b"b2"), hash_leaf(b"d")])
+                .unwrap()
+                .root_hash();
+        storage
+            .replace_batch("client", "batch", &files, &root)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            storage
+                .load_batch_filenames("client", "batch")
+                .await
+                .unwrap(),
+            vec!["a.txt", "b.txt", "d.txt"]
+        );
+        assert_eq!(
+            storage.read_file("client", "batch", "b.txt").await.unwrap(),
+            b"b2"
+        );
+        assert!(!storage
+            .file_exists("client", "batch", "c.txt")
+            .await
+            .unwrap());
+        let tree = storage.load_merkle_tree("client", "batch").await.unwrap();
+        assert_eq!(tree.unwrap().root_hash(), root);
+        assert_eq!(
+            storage
+                .load_committed_root("client", "batch")
+                .await
+                .unwrap(),
+            Some(root)
+        );
+        // Metadata outside the file set carries over
+        assert_eq!(
+            storage
+                .load_batch_annotations("client", "batch")
+                .await
+                .unwrap()
+                .get("k")
+                .map(String::as_str),
+            Some("v")
+        );
+        assert_eq!(storage.list_batches().await.unwrap().len(), 1);
+        assert_eq!(storage.recover_replacements().await.unwrap(), 0);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_replace_leaves_batch_unchanged() {
+        let dir = temp_data_dir("partial");
+        let storage = FilesystemStorage::new(&dir);
+        batch_with_files(&storage).await;
+        let root = storage
+            .load_merkle_tree("client", "batch")
+            .await
+            .unwrap()
+            .unwrap()
+            .root_hash();
+        let unchanged = |storage: FilesystemStorage| async move {
+            assert_eq!(
+                storage
+                    .load_batch_filenames("client", "batch")
+                    .await
+                    .unwrap(),
+                vec!["a.txt", "b.txt", "c.txt"]
+            );
+            assert_eq!(
+                storage.read_file("client", "batch", "a.txt").await.unwrap(),
+                b"a"
+            );
+            let tree = storage.load_merkle_tree("client", "batch").await.unwrap();
+            assert_eq!(tree.unwrap().root_hash(), root);
+            assert_eq!(
+                storage
+                    .load_committed_root("client", "batch")
+                    .await
+                    .unwrap(),
+                None
+            );
+            storage
+        };
+
+        // The second new file cannot be read after the first was staged
+        let mut missing = uploaded(&dir, "e.txt", b"e");
+        missing.source = Some(dir.join("gone"));
+        let files = [uploaded(&dir, "a.txt", b"a2"), kept("b.txt", b"b"), missing];
+        assert!(storage
+            .replace_batch("client", "batch", &files, &[0u8; 32])
+            .await
+            .is_err());
+        let storage = unchanged(storage).await;
+
+        // A kept file whose content changed since the caller listed the batch
+        let files = [kept("a.txt", b"a"), kept("c.txt", b"stale")];
+        let err = storage
+            .replace_batch("client", "batch", &files, &[0u8; 32])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            StorageError::Conflict(ReplaceConflict { filename }) if filename == "c.txt"
+        ));
+        let storage = unchanged(storage).await;
+
+        // Nothing staged is left behind
+        assert!(!storage.replacement_dir("client", STAGED, "batch").exists());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_finishes_interrupted_swap() {
+        let dir = temp_data_dir("recover");
+        let storage = FilesystemStorage::new(&dir);
+        for batch_id in ["swapping", "staging"] {
+            storage
+                .store_file_and_update_tree("client", batch_id, "a.txt", b"a")
+                .await
+                .unwrap();
+        }
+        // "swapping" crashed between the two renames, "staging" before the first one
+        for batch_id in ["swapping", "staging"] {
+            let staged = storage.replacement_dir("client", STAGED, batch_id);
+            std::fs::create_dir_all(staged.parent().unwrap()).unwrap();
+            copy_dir(&storage.batch_dir("client", batch_id), &staged);
+            std::fs::write(staged.join("b.txt"), b"b").unwrap();
+        }
+        let retired = storage.replacement_dir("client", RETIRED, "swapping");
+        std::fs::create_dir_all(retired.parent().unwrap()).unwrap();
+        std::fs::rename(storage.batch_dir("client", "swapping"), &retired).unwrap();
+
+        let report = storage.reconcile().await.unwrap();
+        assert_eq!(report.replacements_recovered, 3);
+        for (batch_id, filenames) in [
+            ("swapping", vec!["a.txt", "b.txt"]),
+            ("staging", vec!["a.txt"]),
+        ] {
+            assert_eq!(
+                storage
+                    .load_batch_filenames("client", batch_id)
+                    .await
+                    .unwrap(),
+                filenames
+            );
+        }
+        assert!(!retired.exists());
+        assert!(storage.reconcile().await.unwrap().is_clean());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    fn copy_dir(from: &Path, to: &Path) {
+        std::fs::create_dir_all(to).unwrap();
+        for entry in std::fs::read_dir(from).unwrap() {
+            let entry = entry.unwrap();
+            std::fs::copy(entry.path(), to.join(entry.file_name())).unwrap();
+        }
+    }
+}
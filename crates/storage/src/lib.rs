@@ -1,37 +1,322 @@
 pub mod backend;
+mod codec;
+pub mod compression;
 pub mod database;
+pub mod encryption;
+pub mod error;
 pub mod filesystem;
+pub mod memory;
+pub mod read_only;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod sqlite;
+pub mod timed;
 
-use anyhow::Result;
 use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
-pub use backend::StorageBackend;
-pub use database::DatabaseRetryConfig;
+pub use backend::{StorageBackend, StorageLayers};
+pub use compression::{Compression, CompressionConfig};
+pub use database::{DatabasePoolConfig, DatabaseRetryConfig};
+pub use encryption::EncryptionConfig;
+pub use error::{ResourceKind, StorageError, StorageResult};
+pub use filesystem::Durability;
+pub use memory::MemoryStorage;
+pub use read_only::ReadOnlyStorage;
+#[cfg(feature = "s3")]
+pub use s3::S3Storage;
+pub use sqlite::SqliteStorage;
+pub use timed::TimedStorage;
+
+/// Error returned when adding a file would take a batch past its file limit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchFull {
+    pub max_files: usize,
+}
+
+impl std::fmt::Display for BatchFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Batch already holds the maximum of {} files",
+            self.max_files
+        )
+    }
+}
+
+impl std::error::Error for BatchFull {}
+
+/// Error returned when a public key is registered under an ID other than its own
+/// Every key has exactly one client ID, `SHA256(public_key)`, so the key lookup by ID
+/// used to verify requests is never ambiguous. Any future alias (e.g. after key
+/// rotation) has to be a separate, explicit mapping rather than a second registration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdMismatch {
+    pub client_id: String,
+    pub canonical_id: String,
+}
+
+impl std::fmt::Display for ClientIdMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Public key belongs to client {}, not {}",
+            self.canonical_id, self.client_id
+        )
+    }
+}
+
+impl std::error::Error for ClientIdMismatch {}
+
+/// Ensure `client_id` is the canonical ID of `public_key` before registering it
+pub fn ensure_canonical_client_id(
+    client_id: &str,
+    public_key: &[u8],
+) -> std::result::Result<(), ClientIdMismatch> {
+    let canonical_id = crypto::compute_client_id_from_bytes(public_key);
+    if canonical_id != client_id {
+        return Err(ClientIdMismatch {
+            client_id: client_id.to_string(),
+            canonical_id,
+        });
+    }
+    Ok(())
+}
+
+/// Error returned when a batch replacement keeps a file the batch does not hold with the
+/// expected content, e.g. because it changed after the client listed the batch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplaceConflict {
+    pub filename: String,
+}
+
+impl std::fmt::Display for ReplaceConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Batch does not hold {} with the expected content; list the batch and retry",
+            self.filename
+        )
+    }
+}
+
+impl std::error::Error for ReplaceConflict {}
+
+/// One file of the set a batch is replaced with (see `Storage::replace_batch`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplacementFile {
+    pub filename: String,
+    /// Leaf hash of the file's content
+    pub leaf_hash: [u8; 32],
+    /// New content, which the caller has checked against `leaf_hash`; `None` keeps the
+    /// file the batch already holds, which must have `leaf_hash`
+    pub source: Option<PathBuf>,
+}
+
+/// Outcome of compacting a batch
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CompactionReport {
+    /// Duplicate filename or access-list entries dropped from metadata
+    pub duplicate_entries_removed: usize,
+    /// Leftover temp files from interrupted atomic writes (deleted)
+    pub temp_files_removed: usize,
+    /// Database rows nothing refers to any more (deleted)
+    pub orphaned_rows_removed: usize,
+    /// Externally stored file bodies no file refers to any more (deleted)
+    pub orphaned_blobs_removed: usize,
+}
 
 /// Storage backend trait for file and metadata operations
 #[async_trait]
 pub trait Storage: Send + Sync {
     /// Read a file from a batch
-    async fn read_file(&self, client_id: &str, batch_id: &str, filename: &str) -> Result<Vec<u8>>;
+    async fn read_file(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Vec<u8>>;
 
     /// Load all filenames from batch metadata
-    async fn load_batch_filenames(&self, client_id: &str, batch_id: &str) -> Result<Vec<String>>;
+    async fn load_batch_filenames(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Vec<String>>;
+
+    /// Check if a batch exists (it may hold no files)
+    async fn batch_exists(&self, client_id: &str, batch_id: &str) -> StorageResult<bool>;
+
+    /// Check if a client is registered, i.e. its public key is stored
+    async fn client_exists(&self, client_id: &str) -> StorageResult<bool>;
+
+    /// Number of registered clients
+    async fn count_clients(&self) -> StorageResult<u64>;
 
     /// Check if a file exists in a batch
-    async fn file_exists(&self, client_id: &str, batch_id: &str, filename: &str) -> Result<bool>;
+    async fn file_exists(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<bool>;
+
+    /// When a file's current content was stored, in milliseconds since the Unix epoch
+    /// Returns `None` if the file does not exist
+    async fn file_stored_at(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Option<u64>>;
 
     /// Store or update a client's public key
-    async fn store_public_key(&self, client_id: &str, public_key: &[u8]) -> Result<()>;
+    /// Fails with `ClientIdMismatch` unless `client_id` is `SHA256(public_key)`
+    async fn store_public_key(&self, client_id: &str, public_key: &[u8]) -> StorageResult<()>;
 
     /// Load a client's public key
-    async fn load_public_key(&self, client_id: &str) -> Result<Option<Vec<u8>>>;
+    async fn load_public_key(&self, client_id: &str) -> StorageResult<Option<Vec<u8>>>;
+
+    /// Mark a batch as publicly readable (or private again)
+    async fn set_batch_public(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        public: bool,
+    ) -> StorageResult<()>;
+
+    /// Check whether a batch is publicly readable
+    /// Returns false for batches that do not exist
+    async fn is_batch_public(&self, client_id: &str, batch_id: &str) -> StorageResult<bool>;
+
+    /// Grant another client read access to a batch (no-op if already granted)
+    /// Fails if the batch does not exist
+    async fn grant_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<()>;
+
+    /// Revoke a previously granted read access (no-op if never granted)
+    /// Fails if the batch does not exist
+    async fn revoke_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<()>;
+
+    /// Check whether a client was granted read access to another client's batch
+    /// Returns false for batches that do not exist
+    async fn has_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<bool>;
+
+    /// Replace a batch's key-value annotations (an empty map clears them)
+    /// Annotations are plain metadata and never enter the Merkle tree
+    /// Fails if the batch does not exist
+    async fn set_batch_annotations(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        annotations: &BTreeMap<String, String>,
+    ) -> StorageResult<()>;
+
+    /// Load a batch's annotations
+    /// Returns an empty map for batches without annotations or that do not exist
+    async fn load_batch_annotations(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<BTreeMap<String, String>>;
+
+    /// Record the root the owner committed to when finalizing a batch (replaces any earlier one)
+    /// Fails if the batch does not exist
+    async fn set_committed_root(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        root: &[u8; 32],
+    ) -> StorageResult<()>;
+
+    /// Load the root recorded by the batch's last finalize
+    /// Returns `None` for batches that were never finalized or do not exist
+    async fn load_committed_root(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Option<[u8; 32]>>;
+
+    /// Create an empty batch that files can be uploaded into later
+    /// Returns false, changing nothing, if the batch already exists
+    async fn create_batch(&self, client_id: &str, batch_id: &str) -> StorageResult<bool>;
+
+    /// Rename a batch, keeping its files, visibility, access list, annotations, committed
+    /// root and Merkle tree
+    /// Fails if the source batch does not exist or the destination already exists
+    async fn rename_batch(
+        &self,
+        client_id: &str,
+        old_batch_id: &str,
+        new_batch_id: &str,
+    ) -> StorageResult<()>;
+
+    /// Delete a batch with its files, visibility, access list, annotations, committed root
+    /// and Merkle tree
+    /// Fails if the batch does not exist
+    async fn delete_batch(&self, client_id: &str, batch_id: &str) -> StorageResult<()>;
+
+    /// Delete one file from a batch and rebuild the batch's Merkle tree without it
+    /// Returns the rebuilt tree, or `None` if no files are left (the tree is removed too)
+    /// Fails if the batch or the file does not exist
+    async fn delete_file(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Option<merkle_tree::MerkleTree>>;
+
+    /// Replace a batch's whole file set with `files` and record `committed_root` as its
+    /// committed root, keeping its visibility, access list and annotations
+    /// Files not in `files` are deleted. Either the new file set, its Merkle tree and the
+    /// root are all stored, or the batch is left unchanged.
+    /// Fails with `ReplaceConflict` if a kept file is missing or has other content,
+    /// `BatchFull` if `files` exceeds the file limit, and if the batch does not exist
+    async fn replace_batch(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        files: &[ReplacementFile],
+        committed_root: &[u8; 32],
+    ) -> StorageResult<()>;
+
+    /// Rewrite a batch's metadata canonically and remove leftovers nothing refers to
+    /// Never changes the batch's file set, so its root stays the same
+    /// Fails if the batch does not exist or its metadata lists a file that is missing
+    async fn compact_batch(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<CompactionReport>;
+
+    /// List every batch in storage as (client_id, batch_id) pairs, sorted
+    async fn list_batches(&self) -> StorageResult<Vec<(String, String)>>;
+
+    /// List the IDs of one client's batches, sorted
+    /// A client with no batches (or that never uploaded) has an empty list
+    async fn list_client_batches(&self, client_id: &str) -> StorageResult<Vec<String>>;
 
     /// Load Merkle tree structure for a batch
     async fn load_merkle_tree(
         &self,
         client_id: &str,
         batch_id: &str,
-    ) -> Result<Option<merkle_tree::MerkleTree>>;
+    ) -> StorageResult<Option<merkle_tree::MerkleTree>>;
 
     /// Atomically store file and update Merkle tree
     /// This method ensures that concurrent uploads to the same batch_id are handled correctly
@@ -44,5 +329,16 @@ pub trait Storage: Send + Sync {
         batch_id: &str,
         filename: &str,
         content: &[u8],
-    ) -> Result<()>;
+    ) -> StorageResult<()>;
+
+    /// Atomically store a file read from a local path and update Merkle tree
+    /// Same guarantees as `store_file_and_update_tree`, but lets backends stream
+    /// the content (e.g. from an upload temp file) instead of buffering it
+    async fn store_file_from_path_and_update_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        source: &Path,
+    ) -> StorageResult<()>;
 }
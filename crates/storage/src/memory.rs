@@ -0,0 +1,848 @@
+use crate::{
+    ensure_canonical_client_id, BatchFull, CompactionReport, ReplaceConflict, ReplacementFile,
+    Storage, StorageError, StorageResult,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use crypto::hash_leaf;
+use merkle_tree::MerkleTree;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+/// In-memory storage implementation
+/// Keeps everything in process memory and loses it on restart, so it is meant for tests
+/// and trying the server out without a data directory or database. Each write holds the
+/// write lock for its whole duration, which makes every operation atomic.
+#[derive(Default)]
+pub struct MemoryStorage {
+    /// Batches keyed by (client_id, batch_id)
+    batches: RwLock<HashMap<(String, String), Batch>>,
+    /// Registered public keys keyed by client_id
+    public_keys: RwLock<HashMap<String, Vec<u8>>>,
+    max_files_per_batch: Option<usize>,
+    /// Time every file read takes, to exercise callers that watch for slow storage
+    #[cfg(test)]
+    read_delay: std::time::Duration,
+}
+
+#[derive(Default)]
+struct Batch {
+    /// Files by filename; the map's order is the leaf order of the tree
+    files: BTreeMap<String, StoredFile>,
+    public: bool,
+    acl: BTreeSet<String>,
+    annotations: BTreeMap<String, String>,
+    committed_root: Option<[u8; 32]>,
+    /// Absent while the batch holds no files
+    tree: Option<MerkleTree>,
+}
+
+struct StoredFile {
+    content: Vec<u8>,
+    leaf_hash: [u8; 32],
+    /// Milliseconds since the Unix epoch
+    stored_at: u64,
+}
+
+impl StoredFile {
+    fn new(content: Vec<u8>) -> Self {
+        Self {
+            leaf_hash: hash_leaf(&content),
+            content,
+            stored_at: now_ms(),
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn key(client_id: &str, batch_id: &str) -> (String, String) {
+    (client_id.to_string(), batch_id.to_string())
+}
+
+impl Batch {
+    /// Rebuild the Merkle tree from the leaf hashes of the current files
+    fn rebuild_tree(&mut self) -> Result<Option<MerkleTree>> {
+        self.tree = if self.files.is_empty() {
+            None
+        } else {
+            let leaf_hashes: Vec<[u8; 32]> =
+                self.files.values().map(|file| file.leaf_hash).collect();
+            Some(
+                MerkleTree::from_leaf_hashes(&leaf_hashes)
+                    .context("Failed to build Merkle tree from leaf hashes")?,
+            )
+        };
+        Ok(self.tree.clone())
+    }
+}
+
+impl MemoryStorage {
+    /// Create empty in-memory storage
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit how many files a batch may hold; uploads adding a file beyond it fail with `BatchFull`
+    pub fn with_max_files_per_batch(mut self, max: usize) -> Self {
+        self.max_files_per_batch = Some(max);
+        self
+    }
+
+    /// Make every file read take `delay`
+    #[cfg(test)]
+    pub(crate) fn with_read_delay(mut self, delay: std::time::Duration) -> Self {
+        self.read_delay = delay;
+        self
+    }
+
+    /// Store `content` as `filename` and rebuild the batch's tree, creating the batch if needed
+    async fn store(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        content: Vec<u8>,
+    ) -> Result<()> {
+        let mut batches = self.batches.write().await;
+        let batch = batches.entry(key(client_id, batch_id)).or_default();
+        if let Some(max_files) = self.max_files_per_batch {
+            if batch.files.len() >= max_files && !batch.files.contains_key(filename) {
+                return Err(BatchFull { max_files }.into());
+            }
+        }
+
+        batch
+            .files
+            .insert(filename.to_string(), StoredFile::new(content));
+        batch.rebuild_tree()?;
+        Ok(())
+    }
+
+    /// Apply `update` to an existing batch
+    async fn update_batch<T>(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        update: impl FnOnce(&mut Batch) -> Result<T>,
+    ) -> Result<T> {
+        let mut batches = self.batches.write().await;
+        let Some(batch) = batches.get_mut(&key(client_id, batch_id)) else {
+            return Err(StorageError::batch_not_found(batch_id).into());
+        };
+        update(batch)
+    }
+
+    /// Read from a batch, or return `default` if it does not exist
+    async fn read_batch<T>(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        default: T,
+        read: impl FnOnce(&Batch) -> T,
+    ) -> T {
+        let batches = self.batches.read().await;
+        match batches.get(&key(client_id, batch_id)) {
+            Some(batch) => read(batch),
+            None => default,
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn read_file(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Vec<u8>> {
+        #[cfg(test)]
+        tokio::time::sleep(self.read_delay).await;
+        Ok(self
+            .read_batch(client_id, batch_id, None, |batch| {
+                batch.files.get(filename).map(|file| file.content.clone())
+            })
+            .await
+            .ok_or_else(|| StorageError::file_not_found(batch_id, filename))?)
+    }
+
+    async fn load_batch_filenames(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Vec<String>> {
+        Ok(self
+            .read_batch(client_id, batch_id, None, |batch| {
+                Some(batch.files.keys().cloned().collect())
+            })
+            .await
+            .ok_or_else(|| StorageError::batch_not_found(batch_id))?)
+    }
+
+    async fn batch_exists(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        Ok(self.read_batch(client_id, batch_id, false, |_| true).await)
+    }
+
+    async fn client_exists(&self, client_id: &str) -> StorageResult<bool> {
+        Ok(self.public_keys.read().await.contains_key(client_id))
+    }
+
+    async fn count_clients(&self) -> StorageResult<u64> {
+        Ok(self.public_keys.read().await.len() as u64)
+    }
+
+    async fn file_exists(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<bool> {
+        Ok(self
+            .read_batch(client_id, batch_id, false, |batch| {
+                batch.files.contains_key(filename)
+            })
+            .await)
+    }
+
+    async fn file_stored_at(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Option<u64>> {
+        Ok(self
+            .read_batch(client_id, batch_id, None, |batch| {
+                batch.files.get(filename).map(|file| file.stored_at)
+            })
+            .await)
+    }
+
+    async fn store_public_key(&self, client_id: &str, public_key: &[u8]) -> StorageResult<()> {
+        ensure_canonical_client_id(client_id, public_key)?;
+        self.public_keys
+            .write()
+            .await
+            .insert(client_id.to_string(), public_key.to_vec());
+        Ok(())
+    }
+
+    async fn load_public_key(&self, client_id: &str) -> StorageResult<Option<Vec<u8>>> {
+        Ok(self.public_keys.read().await.get(client_id).cloned())
+    }
+
+    async fn set_batch_public(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        public: bool,
+    ) -> StorageResult<()> {
+        Ok(self
+            .update_batch(client_id, batch_id, |batch| {
+                batch.public = public;
+                Ok(())
+            })
+            .await?)
+    }
+
+    async fn is_batch_public(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        Ok(self
+            .read_batch(client_id, batch_id, false, |batch| batch.public)
+            .await)
+    }
+
+    async fn grant_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<()> {
+        Ok(self
+            .update_batch(client_id, batch_id, |batch| {
+                batch.acl.insert(grantee_id.to_string());
+                Ok(())
+            })
+            .await?)
+    }
+
+    async fn revoke_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<()> {
+        Ok(self
+            .update_batch(client_id, batch_id, |batch| {
+                batch.acl.remove(grantee_id);
+                Ok(())
+            })
+            .await?)
+    }
+
+    async fn has_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<bool> {
+        Ok(self
+            .read_batch(client_id, batch_id, false, |batch| {
+                batch.acl.contains(grantee_id)
+            })
+            .await)
+    }
+
+    async fn set_batch_annotations(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        annotations: &BTreeMap<String, String>,
+    ) -> StorageResult<()> {
+        Ok(self
+            .update_batch(client_id, batch_id, |batch| {
+                batch.annotations = annotations.clone();
+                Ok(())
+            })
+            .await?)
+    }
+
+    async fn load_batch_annotations(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<BTreeMap<String, String>> {
+        Ok(self
+            .read_batch(client_id, batch_id, BTreeMap::new(), |batch| {
+                batch.annotations.clone()
+            })
+            .await)
+    }
+
+    async fn set_committed_root(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        root: &[u8; 32],
+    ) -> StorageResult<()> {
+        Ok(self
+            .update_batch(client_id, batch_id, |batch| {
+                batch.committed_root = Some(*root);
+                Ok(())
+            })
+            .await?)
+    }
+
+    async fn load_committed_root(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Option<[u8; 32]>> {
+        Ok(self
+            .read_batch(client_id, batch_id, None, |batch| batch.committed_root)
+            .await)
+    }
+
+    async fn create_batch(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        let mut batches = self.batches.write().await;
+        let batch_key = key(client_id, batch_id);
+        if batches.contains_key(&batch_key) {
+            return Ok(false);
+        }
+        batches.insert(batch_key, Batch::default());
+        Ok(true)
+    }
+
+    async fn rename_batch(
+        &self,
+        client_id: &str,
+        old_batch_id: &str,
+        new_batch_id: &str,
+    ) -> StorageResult<()> {
+        let mut batches = self.batches.write().await;
+        let new_key = key(client_id, new_batch_id);
+        if !batches.contains_key(&key(client_id, old_batch_id)) {
+            return Err(StorageError::batch_not_found(old_batch_id));
+        }
+        if batches.contains_key(&new_key) {
+            return Err(StorageError::batch_already_exists(new_batch_id));
+        }
+
+        if let Some(batch) = batches.remove(&key(client_id, old_batch_id)) {
+            batches.insert(new_key, batch);
+        }
+        Ok(())
+    }
+
+    async fn delete_batch(&self, client_id: &str, batch_id: &str) -> StorageResult<()> {
+        if self
+            .batches
+            .write()
+            .await
+            .remove(&key(client_id, batch_id))
+            .is_none()
+        {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        Ok(())
+    }
+
+    async fn delete_file(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Option<MerkleTree>> {
+        Ok(self
+            .update_batch(client_id, batch_id, |batch| {
+                if batch.files.remove(filename).is_none() {
+                    return Err(StorageError::file_not_found(batch_id, filename).into());
+                }
+                batch.rebuild_tree()
+            })
+            .await?)
+    }
+
+    async fn replace_batch(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        files: &[ReplacementFile],
+        committed_root: &[u8; 32],
+    ) -> StorageResult<()> {
+        if let Some(max_files) = self.max_files_per_batch {
+            if files.len() > max_files {
+                return Err(BatchFull { max_files }.into());
+            }
+        }
+        let mut files: Vec<&ReplacementFile> = files.iter().collect();
+        files.sort_by(|a, b| a.filename.cmp(&b.filename));
+        if let Some(pair) = files.windows(2).find(|w| w[0].filename == w[1].filename) {
+            return Err(anyhow::anyhow!("File {} is listed twice", pair[0].filename).into());
+        }
+
+        // Read new content before taking the lock, so a bad source never half-applies
+        let mut uploads = BTreeMap::new();
+        for file in &files {
+            if let Some(source) = &file.source {
+                let content = tokio::fs::read(source)
+                    .await
+                    .with_context(|| format!("Failed to read source file: {:?}", source))?;
+                uploads.insert(file.filename.clone(), content);
+            }
+        }
+
+        Ok(self
+            .update_batch(client_id, batch_id, |batch| {
+                // Kept files must still be what the caller saw, or the new root would be wrong
+                for file in files.iter().filter(|file| file.source.is_none()) {
+                    let leaf_hash = batch.files.get(&file.filename).map(|f| f.leaf_hash);
+                    if leaf_hash != Some(file.leaf_hash) {
+                        return Err(ReplaceConflict {
+                            filename: file.filename.clone(),
+                        }
+                        .into());
+                    }
+                }
+
+                let mut current = std::mem::take(&mut batch.files);
+                for file in &files {
+                    let stored = match uploads.remove(&file.filename) {
+                        Some(content) => StoredFile::new(content),
+                        None => current
+                            .remove(&file.filename)
+                            .context("Kept file vanished during replacement")?,
+                    };
+                    batch.files.insert(file.filename.clone(), stored);
+                }
+                batch.rebuild_tree()?;
+                batch.committed_root = Some(*committed_root);
+                Ok(())
+            })
+            .await?)
+    }
+
+    async fn compact_batch(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<CompactionReport> {
+        // Maps and sets keep no duplicates or leftovers, so there is never anything to remove
+        Ok(self
+            .update_batch(client_id, batch_id, |_| Ok(CompactionReport::default()))
+            .await?)
+    }
+
+    async fn list_batches(&self) -> StorageResult<Vec<(String, String)>> {
+        let mut batches: Vec<(String, String)> =
+            self.batches.read().await.keys().cloned().collect();
+        batches.sort();
+        Ok(batches)
+    }
+
+    async fn list_client_batches(&self, client_id: &str) -> StorageResult<Vec<String>> {
+        let mut batches: Vec<String> = self
+            .batches
+            .read()
+            .await
+            .keys()
+            .filter(|(owner, _)| owner == client_id)
+            .map(|(_, batch_id)| batch_id.clone())
+            .collect();
+        batches.sort();
+        Ok(batches)
+    }
+
+    async fn load_merkle_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Option<MerkleTree>> {
+        Ok(self
+            .read_batch(client_id, batch_id, None, |batch| batch.tree.clone())
+            .await)
+    }
+
+    async fn store_file_and_update_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        content: &[u8],
+    ) -> StorageResult<()> {
+        Ok(self
+            .store(client_id, batch_id, filename, content.to_vec())
+            .await?)
+    }
+
+    async fn store_file_from_path_and_update_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        source: &Path,
+    ) -> StorageResult<()> {
+        let content = tokio::fs::read(source)
+            .await
+            .with_context(|| format!("Failed to read source file: {:?}", source))?;
+        Ok(self.store(client_id, batch_id, filename, content).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::{compute_client_id, generate_keypair};
+
+    /// Register a fresh client
+    async fn register_client(storage: &MemoryStorage) -> String {
+        let (_, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+        storage
+            .store_public_key(&client_id, verifying_key.as_bytes())
+            .await
+            .unwrap();
+        client_id
+    }
+
+    #[tokio::test]
+    async fn test_store_read_and_exists() {
+        let storage = MemoryStorage::new();
+        let client_id = register_client(&storage).await;
+        assert!(storage.client_exists(&client_id).await.unwrap());
+        assert_eq!(storage.count_clients().await.unwrap(), 1);
+        assert!(!storage.batch_exists(&client_id, "batch").await.unwrap());
+        assert!(storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .is_none());
+
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "b.txt", b"beta")
+            .await
+            .unwrap();
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "a.txt", b"alpha")
+            .await
+            .unwrap();
+
+        assert!(storage.batch_exists(&client_id, "batch").await.unwrap());
+        assert!(storage
+            .file_exists(&client_id, "batch", "a.txt")
+            .await
+            .unwrap());
+        assert!(!storage
+            .file_exists(&client_id, "batch", "c.txt")
+            .await
+            .unwrap());
+        assert_eq!(
+            storage
+                .read_file(&client_id, "batch", "a.txt")
+                .await
+                .unwrap(),
+            b"alpha"
+        );
+        assert!(storage
+            .read_file(&client_id, "batch", "c.txt")
+            .await
+            .is_err());
+        assert_eq!(
+            storage
+                .load_batch_filenames(&client_id, "batch")
+                .await
+                .unwrap(),
+            ["a.txt", "b.txt"]
+        );
+
+        // The tree's leaves are in filename order, like the other backends
+        let tree = storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        let root = MerkleTree::from_data(&[b"alpha".to_vec(), b"beta".to_vec()])
+            .unwrap()
+            .root_hash();
+        assert_eq!(tree.root_hash(), root);
+    }
+
+    #[tokio::test]
+    async fn test_missing_batch_is_not_found() {
+        let storage = MemoryStorage::new();
+        let client_id = register_client(&storage).await;
+        let err = storage
+            .load_batch_filenames(&client_id, "missing")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            StorageError::NotFound {
+                kind: crate::ResourceKind::Batch,
+                ref id,
+            } if id == "missing"
+        ));
+        assert!(storage
+            .read_file(&client_id, "missing", "a.txt")
+            .await
+            .unwrap_err()
+            .is_not_found());
+    }
+
+    #[tokio::test]
+    async fn test_delete_batch_removes_files_and_tree() {
+        let storage = MemoryStorage::new();
+        let client_id = register_client(&storage).await;
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "a.txt", b"alpha")
+            .await
+            .unwrap();
+
+        storage.delete_batch(&client_id, "batch").await.unwrap();
+
+        assert!(!storage.batch_exists(&client_id, "batch").await.unwrap());
+        assert!(!storage
+            .file_exists(&client_id, "batch", "a.txt")
+            .await
+            .unwrap());
+        assert!(storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(storage.delete_batch(&client_id, "batch").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_client_batches() {
+        let storage = MemoryStorage::new();
+        let client_id = register_client(&storage).await;
+        assert!(storage
+            .list_client_batches(&client_id)
+            .await
+            .unwrap()
+            .is_empty());
+
+        storage
+            .store_file_and_update_tree(&client_id, "b", "a.txt", b"a")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.list_client_batches(&client_id).await.unwrap(),
+            ["b"]
+        );
+
+        storage.create_batch(&client_id, "c").await.unwrap();
+        storage
+            .store_file_and_update_tree(&client_id, "a", "a.txt", b"a")
+            .await
+            .unwrap();
+        let other_id = register_client(&storage).await;
+        storage
+            .store_file_and_update_tree(&other_id, "d", "a.txt", b"a")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.list_client_batches(&client_id).await.unwrap(),
+            ["a", "b", "c"]
+        );
+        assert_eq!(storage.list_batches().await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_delete_file_rebuilds_tree() {
+        let storage = MemoryStorage::new();
+        let client_id = register_client(&storage).await;
+        for (filename, content) in [("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")] {
+            storage
+                .store_file_and_update_tree(&client_id, "batch", filename, content)
+                .await
+                .unwrap();
+        }
+
+        let tree = storage
+            .delete_file(&client_id, "batch", "b.txt")
+            .await
+            .unwrap()
+            .unwrap();
+
+        let root = MerkleTree::from_data(&[b"a".to_vec(), b"c".to_vec()])
+            .unwrap()
+            .root_hash();
+        assert_eq!(tree.root_hash(), root);
+        assert!(storage
+            .delete_file(&client_id, "batch", "b.txt")
+            .await
+            .is_err());
+
+        // Deleting the last files leaves an empty batch without a tree
+        storage
+            .delete_file(&client_id, "batch", "a.txt")
+            .await
+            .unwrap();
+        assert!(storage
+            .delete_file(&client_id, "batch", "c.txt")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(storage.batch_exists(&client_id, "batch").await.unwrap());
+        assert!(storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_max_files_per_batch() {
+        let storage = MemoryStorage::new().with_max_files_per_batch(1);
+        storage
+            .store_file_and_update_tree("client", "batch", "a.txt", b"a")
+            .await
+            .unwrap();
+
+        // Replacing the one file is allowed, adding a second is not
+        storage
+            .store_file_and_update_tree("client", "batch", "a.txt", b"A")
+            .await
+            .unwrap();
+        let err = storage
+            .store_file_and_update_tree("client", "batch", "b.txt", b"b")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StorageError::BatchFull(_)));
+    }
+
+    #[tokio::test]
+    async fn test_replace_batch_checks_kept_files() {
+        let storage = MemoryStorage::new();
+        for (filename, content) in [("a.txt", b"a"), ("b.txt", b"b")] {
+            storage
+                .store_file_and_update_tree("client", "batch", filename, content)
+                .await
+                .unwrap();
+        }
+        storage
+            .set_batch_annotations(
+                "client",
+                "batch",
+                &BTreeMap::from([("k".to_string(), "v".to_string())]),
+            )
+            .await
+            .unwrap();
+
+        let dir = std::env::temp_dir().join(format!("vs-memory-replace-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("c.txt");
+        std::fs::write(&source, b"c").unwrap();
+
+        // A kept file with other content than the batch holds is a conflict
+        let stale = [ReplacementFile {
+            filename: "a.txt".to_string(),
+            leaf_hash: hash_leaf(b"x"),
+            source: None,
+        }];
+        let err = storage
+            .replace_batch("client", "batch", &stale, &[0; 32])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StorageError::Conflict(_)));
+
+        let files = [
+            ReplacementFile {
+                filename: "a.txt".to_string(),
+                leaf_hash: hash_leaf(b"a"),
+                source: None,
+            },
+            ReplacementFile {
+                filename: "c.txt".to_string(),
+                leaf_hash: hash_leaf(b"c"),
+                source: Some(source),
+            },
+        ];
+        let root = MerkleTree::from_data(&[b"a".to_vec(), b"c".to_vec()])
+            .unwrap()
+            .root_hash();
+        storage
+            .replace_batch("client", "batch", &files, &root)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            storage
+                .load_batch_filenames("client", "batch")
+                .await
+                .unwrap(),
+            ["a.txt", "c.txt"]
+        );
+        let tree = storage
+            .load_merkle_tree("client", "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tree.root_hash(), root);
+        assert_eq!(
+            storage
+                .load_committed_root("client", "batch")
+                .await
+                .unwrap(),
+            Some(root)
+        );
+        assert_eq!(
+            storage
+                .load_batch_annotations("client", "batch")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
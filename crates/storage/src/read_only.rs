@@ -0,0 +1,293 @@
+use crate::{CompactionReport, ReplacementFile, Storage, StorageResult};
+use anyhow::Result;
+use async_trait::async_trait;
+use merkle_tree::MerkleTree;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Storage decorator that serves reads from the wrapped storage and rejects every write,
+/// for running a server as a read-only mirror or during maintenance
+pub struct ReadOnlyStorage {
+    inner: Arc<dyn Storage>,
+}
+
+impl ReadOnlyStorage {
+    pub fn new(inner: Arc<dyn Storage>) -> Self {
+        Self { inner }
+    }
+}
+
+/// Error returned for every write
+fn rejected<T>(operation: &str) -> Result<T> {
+    anyhow::bail!("Storage is read-only: {} rejected", operation)
+}
+
+#[async_trait]
+impl Storage for ReadOnlyStorage {
+    async fn read_file(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Vec<u8>> {
+        self.inner.read_file(client_id, batch_id, filename).await
+    }
+
+    async fn load_batch_filenames(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Vec<String>> {
+        self.inner.load_batch_filenames(client_id, batch_id).await
+    }
+
+    async fn batch_exists(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        self.inner.batch_exists(client_id, batch_id).await
+    }
+
+    async fn client_exists(&self, client_id: &str) -> StorageResult<bool> {
+        self.inner.client_exists(client_id).await
+    }
+
+    async fn count_clients(&self) -> StorageResult<u64> {
+        self.inner.count_clients().await
+    }
+
+    async fn file_exists(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<bool> {
+        self.inner.file_exists(client_id, batch_id, filename).await
+    }
+
+    async fn file_stored_at(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Option<u64>> {
+        self.inner
+            .file_stored_at(client_id, batch_id, filename)
+            .await
+    }
+
+    async fn store_public_key(&self, _client_id: &str, _public_key: &[u8]) -> StorageResult<()> {
+        Ok(rejected("store_public_key")?)
+    }
+
+    async fn load_public_key(&self, client_id: &str) -> StorageResult<Option<Vec<u8>>> {
+        self.inner.load_public_key(client_id).await
+    }
+
+    async fn set_batch_public(
+        &self,
+        _client_id: &str,
+        _batch_id: &str,
+        _public: bool,
+    ) -> StorageResult<()> {
+        Ok(rejected("set_batch_public")?)
+    }
+
+    async fn is_batch_public(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        self.inner.is_batch_public(client_id, batch_id).await
+    }
+
+    async fn grant_batch_access(
+        &self,
+        _client_id: &str,
+        _batch_id: &str,
+        _grantee_id: &str,
+    ) -> StorageResult<()> {
+        Ok(rejected("grant_batch_access")?)
+    }
+
+    async fn revoke_batch_access(
+        &self,
+        _client_id: &str,
+        _batch_id: &str,
+        _grantee_id: &str,
+    ) -> StorageResult<()> {
+        Ok(rejected("revoke_batch_access")?)
+    }
+
+    async fn has_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<bool> {
+        self.inner
+            .has_batch_access(client_id, batch_id, grantee_id)
+            .await
+    }
+
+    async fn set_batch_annotations(
+        &self,
+        _client_id: &str,
+        _batch_id: &str,
+        _annotations: &BTreeMap<String, String>,
+    ) -> StorageResult<()> {
+        Ok(rejected("set_batch_annotations")?)
+    }
+
+    async fn load_batch_annotations(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<BTreeMap<String, String>> {
+        self.inner.load_batch_annotations(client_id, batch_id).await
+    }
+
+    async fn set_committed_root(
+        &self,
+        _client_id: &str,
+        _batch_id: &str,
+        _root: &[u8; 32],
+    ) -> StorageResult<()> {
+        Ok(rejected("set_committed_root")?)
+    }
+
+    async fn load_committed_root(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Option<[u8; 32]>> {
+        self.inner.load_committed_root(client_id, batch_id).await
+    }
+
+    async fn create_batch(&self, _client_id: &str, _batch_id: &str) -> StorageResult<bool> {
+        Ok(rejected("create_batch")?)
+    }
+
+    async fn rename_batch(
+        &self,
+        _client_id: &str,
+        _old_batch_id: &str,
+        _new_batch_id: &str,
+    ) -> StorageResult<()> {
+        Ok(rejected("rename_batch")?)
+    }
+
+    async fn delete_batch(&self, _client_id: &str, _batch_id: &str) -> StorageResult<()> {
+        Ok(rejected("delete_batch")?)
+    }
+
+    async fn delete_file(
+        &self,
+        _client_id: &str,
+        _batch_id: &str,
+        _filename: &str,
+    ) -> StorageResult<Option<MerkleTree>> {
+        Ok(rejected("delete_file")?)
+    }
+
+    async fn replace_batch(
+        &self,
+        _client_id: &str,
+        _batch_id: &str,
+        _files: &[ReplacementFile],
+        _committed_root: &[u8; 32],
+    ) -> StorageResult<()> {
+        Ok(rejected("replace_batch")?)
+    }
+
+    async fn compact_batch(
+        &self,
+        _client_id: &str,
+        _batch_id: &str,
+    ) -> StorageResult<CompactionReport> {
+        Ok(rejected("compact_batch")?)
+    }
+
+    async fn list_batches(&self) -> StorageResult<Vec<(String, String)>> {
+        self.inner.list_batches().await
+    }
+
+    async fn list_client_batches(&self, client_id: &str) -> StorageResult<Vec<String>> {
+        self.inner.list_client_batches(client_id).await
+    }
+
+    async fn load_merkle_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Option<MerkleTree>> {
+        self.inner.load_merkle_tree(client_id, batch_id).await
+    }
+
+    async fn store_file_and_update_tree(
+        &self,
+        _client_id: &str,
+        _batch_id: &str,
+        _filename: &str,
+        _content: &[u8],
+    ) -> StorageResult<()> {
+        Ok(rejected("store_file_and_update_tree")?)
+    }
+
+    async fn store_file_from_path_and_update_tree(
+        &self,
+        _client_id: &str,
+        _batch_id: &str,
+        _filename: &str,
+        _source: &Path,
+    ) -> StorageResult<()> {
+        Ok(rejected("store_file_from_path_and_update_tree")?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::FilesystemStorage;
+
+    #[tokio::test]
+    async fn test_reads_delegate_and_writes_are_rejected() {
+        let dir = std::env::temp_dir().join(format!("vs-ro-{}", std::process::id()));
+        let inner = Arc::new(FilesystemStorage::new(&dir));
+        inner
+            .store_file_and_update_tree("client", "batch", "a.txt", b"a")
+            .await
+            .unwrap();
+        let storage = ReadOnlyStorage::new(inner.clone());
+
+        assert_eq!(
+            storage.read_file("client", "batch", "a.txt").await.unwrap(),
+            b"a"
+        );
+        assert_eq!(
+            storage
+                .load_batch_filenames("client", "batch")
+                .await
+                .unwrap(),
+            vec!["a.txt".to_string()]
+        );
+
+        let err = storage
+            .store_file_and_update_tree("client", "batch", "b.txt", b"b")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("read-only"));
+        assert!(storage
+            .rename_batch("client", "batch", "other")
+            .await
+            .is_err());
+        assert!(storage
+            .set_batch_public("client", "batch", true)
+            .await
+            .is_err());
+
+        // Nothing reached the wrapped storage
+        assert!(!inner.file_exists("client", "batch", "b.txt").await.unwrap());
+        assert!(!inner.is_batch_public("client", "batch").await.unwrap());
+        assert_eq!(
+            inner.read_file("client", "batch", "a.txt").await.unwrap(),
+            b"a"
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,1107 @@
+use crate::filesystem::metadata::Metadata;
+use crate::filesystem::{BatchLocks, LocalBatchLock};
+use crate::{
+    ensure_canonical_client_id, BatchFull, CompactionReport, ReplaceConflict, ReplacementFile,
+    Storage, StorageError, StorageResult,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{Delete, ObjectIdentifier};
+use aws_sdk_s3::Client;
+use crypto::hash_leaf;
+use merkle_tree::MerkleTree;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// Per-batch metadata object (sorted filenames, visibility, access list and annotations)
+const METADATA_OBJECT: &str = "metadata.json";
+
+/// Per-batch Merkle tree object
+const MERKLE_TREE_OBJECT: &str = "merkle_tree.json";
+
+/// Per-client public key object
+const PUBLIC_KEY_OBJECT: &str = "public_key.hex";
+
+/// Most objects fetched at once when reading a whole batch
+const READ_CONCURRENCY: usize = 16;
+
+/// Most keys one DeleteObjects request may name
+const DELETE_BATCH_SIZE: usize = 1000;
+
+/// S3-compatible object storage implementation
+/// Lays objects out like the filesystem backend lays out files: `{client_id}/{batch_id}/{filename}`
+/// for content, `{client_id}/{batch_id}/metadata.json` and `merkle_tree.json` beside it,
+/// and `{client_id}/public_key.hex`. Object stores have no locks or multi-object
+/// transactions, so batch writes are serialized within this process only: run a single
+/// server instance per bucket, and a crash in the middle of a multi-object write (delete,
+/// rename, replace) can leave the batch partly changed.
+pub struct S3Storage {
+    client: Client,
+    bucket: String,
+    /// Most files a batch may hold; `None` means unlimited
+    max_files_per_batch: Option<usize>,
+    /// In-process batch locks
+    batch_locks: Arc<BatchLocks>,
+}
+
+impl S3Storage {
+    /// Create S3 storage for `bucket`
+    /// Credentials and, unless `region` is given, the region come from the usual AWS
+    /// sources (environment, profile, instance metadata). With `endpoint`, requests go to
+    /// that URL with path-style addressing, as MinIO and most S3-compatible stores expect.
+    pub async fn new(
+        bucket: impl Into<String>,
+        endpoint: Option<&str>,
+        region: Option<&str>,
+    ) -> Result<Self> {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest());
+        if let Some(region) = region {
+            loader = loader.region(Region::new(region.to_string()));
+        }
+        let sdk_config = loader.load().await;
+
+        let mut config = aws_sdk_s3::config::Builder::from(&sdk_config);
+        if let Some(endpoint) = endpoint {
+            config = config.endpoint_url(endpoint).force_path_style(true);
+        }
+
+        Ok(Self {
+            client: Client::from_conf(config.build()),
+            bucket: bucket.into(),
+            max_files_per_batch: None,
+            batch_locks: Arc::default(),
+        })
+    }
+
+    /// Limit how many files a batch may hold; uploads adding a file beyond it fail with `BatchFull`
+    pub fn with_max_files_per_batch(mut self, max: usize) -> Self {
+        self.max_files_per_batch = Some(max);
+        self
+    }
+
+    /// Check the bucket exists and the credentials can reach it
+    /// Called at startup so a misconfigured bucket fails fast instead of on the first upload
+    pub async fn validate_bucket(&self) -> Result<()> {
+        self.client
+            .head_bucket()
+            .bucket(&self.bucket)
+            .send()
+            .await
+            .with_context(|| format!("Cannot access S3 bucket {}", self.bucket))?;
+        Ok(())
+    }
+
+    fn batch_prefix(client_id: &str, batch_id: &str) -> String {
+        format!("{}/{}/", client_id, batch_id)
+    }
+
+    fn file_key(client_id: &str, batch_id: &str, filename: &str) -> String {
+        format!("{}{}", Self::batch_prefix(client_id, batch_id), filename)
+    }
+
+    fn metadata_key(client_id: &str, batch_id: &str) -> String {
+        Self::file_key(client_id, batch_id, METADATA_OBJECT)
+    }
+
+    fn merkle_tree_key(client_id: &str, batch_id: &str) -> String {
+        Self::file_key(client_id, batch_id, MERKLE_TREE_OBJECT)
+    }
+
+    fn public_key_key(client_id: &str) -> String {
+        format!("{}/{}", client_id, PUBLIC_KEY_OBJECT)
+    }
+
+    /// Fetch an object's content; `None` if there is no such key
+    async fn get_object(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        get_object(&self.client, &self.bucket, key).await
+    }
+
+    async fn put_object(&self, key: &str, body: ByteStream) -> Result<()> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("Failed to write object {}", key))?;
+        Ok(())
+    }
+
+    async fn object_exists(&self, key: &str) -> Result<bool> {
+        match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(false),
+            Err(e) => Err(e).with_context(|| format!("Failed to check object {}", key)),
+        }
+    }
+
+    /// Every key under `prefix`, following continuation tokens
+    async fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let page = page.with_context(|| format!("Failed to list objects under {}", prefix))?;
+            keys.extend(
+                page.contents()
+                    .iter()
+                    .filter_map(|object| object.key().map(str::to_string)),
+            );
+        }
+        Ok(keys)
+    }
+
+    /// Delete `keys`, up to a thousand per request
+    async fn delete_objects(&self, keys: &[String]) -> Result<()> {
+        for chunk in keys.chunks(DELETE_BATCH_SIZE) {
+            let objects = chunk
+                .iter()
+                .map(|key| ObjectIdentifier::builder().key(key).build())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .context("Failed to build object identifier")?;
+            let delete = Delete::builder()
+                .set_objects(Some(objects))
+                .quiet(true)
+                .build()
+                .context("Failed to build delete request")?;
+            let output = self
+                .client
+                .delete_objects()
+                .bucket(&self.bucket)
+                .delete(delete)
+                .send()
+                .await
+                .context("Failed to delete objects")?;
+            if let Some(error) = output.errors().first() {
+                anyhow::bail!(
+                    "Failed to delete object {}: {}",
+                    error.key().unwrap_or_default(),
+                    error.message().unwrap_or_default()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Load a batch's metadata; `None` if the batch does not exist
+    async fn load_metadata(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<Option<Map<String, Value>>> {
+        let Some(content) = self
+            .get_object(&Self::metadata_key(client_id, batch_id))
+            .await?
+        else {
+            return Ok(None);
+        };
+        serde_json::from_slice(&content)
+            .context("Failed to parse metadata")
+            .map(Some)
+    }
+
+    /// Load a batch's metadata, failing if the batch does not exist
+    async fn require_metadata(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<Map<String, Value>> {
+        self.load_metadata(client_id, batch_id)
+            .await?
+            .ok_or_else(|| StorageError::batch_not_found(batch_id).into())
+    }
+
+    async fn save_metadata(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        metadata: &Map<String, Value>,
+    ) -> Result<()> {
+        let metadata_json =
+            serde_json::to_vec_pretty(metadata).context("Failed to serialize metadata")?;
+        self.put_object(
+            &Self::metadata_key(client_id, batch_id),
+            ByteStream::from(metadata_json),
+        )
+        .await
+    }
+
+    /// Change an existing batch's metadata under the batch lock
+    async fn update_metadata(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        update: impl FnOnce(&mut Map<String, Value>),
+    ) -> Result<()> {
+        let _guard = self.lock_batch(client_id, batch_id).await;
+        let mut metadata = self.require_metadata(client_id, batch_id).await?;
+        update(&mut metadata);
+        self.save_metadata(client_id, batch_id, &metadata).await
+    }
+
+    async fn lock_batch(&self, client_id: &str, batch_id: &str) -> LocalBatchLock {
+        self.batch_locks.acquire(client_id, batch_id).await
+    }
+
+    /// Fetch the content of `filenames` from a batch concurrently, in the given order
+    async fn read_batch_files(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filenames: &[String],
+    ) -> Result<Vec<Vec<u8>>> {
+        let permits = Arc::new(Semaphore::new(READ_CONCURRENCY));
+        let mut reads = JoinSet::new();
+        for (index, filename) in filenames.iter().enumerate() {
+            let client = self.client.clone();
+            let bucket = self.bucket.clone();
+            let key = Self::file_key(client_id, batch_id, filename);
+            let permits = permits.clone();
+            reads.spawn(async move {
+                let _permit = permits.acquire_owned().await?;
+                let content = get_object(&client, &bucket, &key)
+                    .await?
+                    .with_context(|| format!("Object {} not found", key))?;
+                Ok::<_, anyhow::Error>((index, content))
+            });
+        }
+
+        let mut contents = vec![Vec::new(); filenames.len()];
+        while let Some(read) = reads.join_next().await {
+            let (index, content) = read.context("Failed to join object read")??;
+            contents[index] = content;
+        }
+        Ok(contents)
+    }
+
+    /// Rebuild and store the batch's Merkle tree from the given (sorted) filenames
+    /// Must be called while holding the batch lock
+    async fn rebuild_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filenames: &[String],
+    ) -> Result<MerkleTree> {
+        let leaf_hashes: Vec<[u8; 32]> = self
+            .read_batch_files(client_id, batch_id, filenames)
+            .await?
+            .iter()
+            .map(|content| hash_leaf(content))
+            .collect();
+        let tree = MerkleTree::from_leaf_hashes(&leaf_hashes)
+            .context("Failed to build Merkle tree from leaf hashes")?;
+        self.store_tree(client_id, batch_id, &tree).await?;
+        Ok(tree)
+    }
+
+    async fn store_tree(&self, client_id: &str, batch_id: &str, tree: &MerkleTree) -> Result<()> {
+        let tree_json =
+            serde_json::to_vec_pretty(tree).context("Failed to serialize Merkle tree")?;
+        self.put_object(
+            &Self::merkle_tree_key(client_id, batch_id),
+            ByteStream::from(tree_json),
+        )
+        .await
+    }
+
+    /// Store one file and rebuild the batch's tree, creating the batch if needed
+    /// The content object is written first and the metadata last, so a failure in between
+    /// leaves an object the metadata does not list rather than a listed file that is missing
+    async fn store(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        body: ByteStream,
+    ) -> Result<()> {
+        let _guard = self.lock_batch(client_id, batch_id).await;
+        let mut metadata = self
+            .load_metadata(client_id, batch_id)
+            .await?
+            .unwrap_or_default();
+        let mut filenames = Metadata::extract_filenames(&metadata).unwrap_or_default();
+        let is_new = !filenames.iter().any(|f| f == filename);
+        if let Some(max_files) = self.max_files_per_batch {
+            if is_new && filenames.len() >= max_files {
+                return Err(BatchFull { max_files }.into());
+            }
+        }
+
+        self.put_object(&Self::file_key(client_id, batch_id, filename), body)
+            .await?;
+        Metadata::insert_filename(&mut metadata, filename);
+        filenames = Metadata::extract_filenames(&metadata)?;
+        self.rebuild_tree(client_id, batch_id, &filenames).await?;
+        self.save_metadata(client_id, batch_id, &metadata).await
+    }
+
+    /// Batch IDs among `keys` (relative to a client's prefix) that have metadata
+    fn batch_ids<'a>(keys: impl Iterator<Item = &'a str>) -> Vec<String> {
+        keys.filter_map(|key| key.strip_suffix(&format!("/{}", METADATA_OBJECT)))
+            .filter(|batch_id| !batch_id.contains('/'))
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+/// Fetch an object's content; `None` if there is no such key
+/// Free-standing so concurrent reads can run it on spawned tasks
+async fn get_object(client: &Client, bucket: &str, key: &str) -> Result<Option<Vec<u8>>> {
+    let output = match client.get_object().bucket(bucket).key(key).send().await {
+        Ok(output) => output,
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read object {}", key)),
+    };
+    let content = output
+        .body
+        .collect()
+        .await
+        .with_context(|| format!("Failed to read object {}", key))?;
+    Ok(Some(content.into_bytes().to_vec()))
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn read_file(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Vec<u8>> {
+        Ok(self
+            .get_object(&Self::file_key(client_id, batch_id, filename))
+            .await?
+            .ok_or_else(|| StorageError::file_not_found(batch_id, filename))?)
+    }
+
+    async fn load_batch_filenames(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Vec<String>> {
+        let metadata = self.require_metadata(client_id, batch_id).await?;
+        Ok(Metadata::extract_filenames(&metadata)?)
+    }
+
+    async fn batch_exists(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        Ok(self
+            .object_exists(&Self::metadata_key(client_id, batch_id))
+            .await?)
+    }
+
+    async fn client_exists(&self, client_id: &str) -> StorageResult<bool> {
+        Ok(self.object_exists(&Self::public_key_key(client_id)).await?)
+    }
+
+    async fn count_clients(&self) -> StorageResult<u64> {
+        let count = self
+            .list_keys("")
+            .await?
+            .iter()
+            .filter(|key| {
+                key.split_once('/')
+                    .is_some_and(|(_, rest)| rest == PUBLIC_KEY_OBJECT)
+            })
+            .count();
+        Ok(count as u64)
+    }
+
+    async fn file_exists(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<bool> {
+        Ok(self
+            .object_exists(&Self::file_key(client_id, batch_id, filename))
+            .await?)
+    }
+
+    async fn file_stored_at(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Option<u64>> {
+        // Objects are replaced whole, so their last-modified time is when they were stored
+        let key = Self::file_key(client_id, batch_id, filename);
+        let output = match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+        {
+            Ok(output) => output,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => return Ok(None),
+            Err(e) => return Ok(Err(e).with_context(|| format!("Failed to check object {}", key))?),
+        };
+        let modified = output
+            .last_modified()
+            .context("Object has no last-modified time")?
+            .to_millis()
+            .context("Object last-modified time is out of range")?;
+        Ok(Some(modified.max(0) as u64))
+    }
+
+    async fn store_public_key(&self, client_id: &str, public_key: &[u8]) -> StorageResult<()> {
+        ensure_canonical_client_id(client_id, public_key)?;
+        Ok(self
+            .put_object(
+                &Self::public_key_key(client_id),
+                ByteStream::from(hex::encode(public_key).into_bytes()),
+            )
+            .await?)
+    }
+
+    async fn load_public_key(&self, client_id: &str) -> StorageResult<Option<Vec<u8>>> {
+        let Some(content) = self.get_object(&Self::public_key_key(client_id)).await? else {
+            return Ok(None);
+        };
+        let public_key_hex = String::from_utf8(content).context("Failed to read public key")?;
+        let public_key_bytes =
+            hex::decode(public_key_hex.trim()).context("Failed to decode public key")?;
+        Ok(Some(public_key_bytes))
+    }
+
+    async fn set_batch_public(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        public: bool,
+    ) -> StorageResult<()> {
+        Ok(self
+            .update_metadata(client_id, batch_id, |metadata| {
+                Metadata::set_public(metadata, public)
+            })
+            .await?)
+    }
+
+    async fn is_batch_public(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        Ok(self
+            .load_metadata(client_id, batch_id)
+            .await?
+            .is_some_and(|metadata| Metadata::is_public(&metadata)))
+    }
+
+    async fn grant_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<()> {
+        Ok(self
+            .update_metadata(client_id, batch_id, |metadata| {
+                Metadata::set_access(metadata, grantee_id, true)
+            })
+            .await?)
+    }
+
+    async fn revoke_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<()> {
+        Ok(self
+            .update_metadata(client_id, batch_id, |metadata| {
+                Metadata::set_access(metadata, grantee_id, false)
+            })
+            .await?)
+    }
+
+    async fn has_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<bool> {
+        Ok(self
+            .load_metadata(client_id, batch_id)
+            .await?
+            .is_some_and(|metadata| Metadata::acl(&metadata).iter().any(|id| id == grantee_id)))
+    }
+
+    async fn set_batch_annotations(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        annotations: &BTreeMap<String, String>,
+    ) -> StorageResult<()> {
+        Ok(self
+            .update_metadata(client_id, batch_id, |metadata| {
+                Metadata::set_annotations(metadata, annotations)
+            })
+            .await?)
+    }
+
+    async fn load_batch_annotations(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<BTreeMap<String, String>> {
+        Ok(self
+            .load_metadata(client_id, batch_id)
+            .await?
+            .map(|metadata| Metadata::annotations(&metadata))
+            .unwrap_or_default())
+    }
+
+    async fn set_committed_root(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        root: &[u8; 32],
+    ) -> StorageResult<()> {
+        Ok(self
+            .update_metadata(client_id, batch_id, |metadata| {
+                Metadata::set_committed_root(metadata, root)
+            })
+            .await?)
+    }
+
+    async fn load_committed_root(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Option<[u8; 32]>> {
+        Ok(self
+            .load_metadata(client_id, batch_id)
+            .await?
+            .and_then(|metadata| Metadata::committed_root(&metadata)))
+    }
+
+    async fn create_batch(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        let _guard = self.lock_batch(client_id, batch_id).await;
+        if self.batch_exists(client_id, batch_id).await? {
+            return Ok(false);
+        }
+
+        // Metadata listing no files is what makes the prefix a batch
+        let mut metadata = Map::new();
+        Metadata::set_filenames(&mut metadata, &[]);
+        self.save_metadata(client_id, batch_id, &metadata).await?;
+        Ok(true)
+    }
+
+    async fn rename_batch(
+        &self,
+        client_id: &str,
+        old_batch_id: &str,
+        new_batch_id: &str,
+    ) -> StorageResult<()> {
+        let _guard = self.lock_batch(client_id, old_batch_id).await;
+        if !self.batch_exists(client_id, old_batch_id).await? {
+            return Err(StorageError::batch_not_found(old_batch_id));
+        }
+        let _new_guard = self.lock_batch(client_id, new_batch_id).await;
+        if self.batch_exists(client_id, new_batch_id).await? {
+            return Err(StorageError::batch_already_exists(new_batch_id));
+        }
+
+        // Objects cannot be renamed: copy them, metadata last so the new batch only
+        // appears once complete, then delete the old ones, metadata first
+        let old_prefix = Self::batch_prefix(client_id, old_batch_id);
+        let old_metadata = Self::metadata_key(client_id, old_batch_id);
+        let mut keys = self.list_keys(&old_prefix).await?;
+        keys.sort_by_key(|key| *key == old_metadata);
+        for key in &keys {
+            let name = &key[old_prefix.len()..];
+            self.client
+                .copy_object()
+                .bucket(&self.bucket)
+                .copy_source(format!("{}/{}", self.bucket, key))
+                .key(Self::file_key(client_id, new_batch_id, name))
+                .send()
+                .await
+                .with_context(|| format!("Failed to copy object {}", key))?;
+        }
+        keys.reverse();
+        Ok(self.delete_objects(&keys).await?)
+    }
+
+    async fn delete_batch(&self, client_id: &str, batch_id: &str) -> StorageResult<()> {
+        let _guard = self.lock_batch(client_id, batch_id).await;
+        if !self.batch_exists(client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+
+        // The metadata goes first, so the batch is gone even if a later delete fails
+        let metadata_key = Self::metadata_key(client_id, batch_id);
+        self.delete_objects(std::slice::from_ref(&metadata_key))
+            .await?;
+        let keys = self
+            .list_keys(&Self::batch_prefix(client_id, batch_id))
+            .await?;
+        Ok(self.delete_objects(&keys).await?)
+    }
+
+    async fn delete_file(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Option<MerkleTree>> {
+        let _guard = self.lock_batch(client_id, batch_id).await;
+        let mut metadata = self.require_metadata(client_id, batch_id).await?;
+        let mut filenames = Metadata::extract_filenames(&metadata)?;
+        let Some(index) = filenames.iter().position(|f| f == filename) else {
+            return Err(StorageError::file_not_found(batch_id, filename));
+        };
+        filenames.remove(index);
+
+        Metadata::set_filenames(&mut metadata, &filenames);
+        self.save_metadata(client_id, batch_id, &metadata).await?;
+        let tree = if filenames.is_empty() {
+            // No files left: the batch has no root, so its tree goes too
+            self.delete_objects(&[Self::merkle_tree_key(client_id, batch_id)])
+                .await?;
+            None
+        } else {
+            Some(self.rebuild_tree(client_id, batch_id, &filenames).await?)
+        };
+        self.delete_objects(&[Self::file_key(client_id, batch_id, filename)])
+            .await?;
+        Ok(tree)
+    }
+
+    async fn replace_batch(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        files: &[ReplacementFile],
+        committed_root: &[u8; 32],
+    ) -> StorageResult<()> {
+        if let Some(max_files) = self.max_files_per_batch {
+            if files.len() > max_files {
+                return Err(BatchFull { max_files }.into());
+            }
+        }
+        let mut files: Vec<&ReplacementFile> = files.iter().collect();
+        files.sort_by(|a, b| a.filename.cmp(&b.filename));
+        if let Some(pair) = files.windows(2).find(|w| w[0].filename == w[1].filename) {
+            return Err(anyhow::anyhow!("File {} is listed twice", pair[0].filename).into());
+        }
+
+        let _guard = self.lock_batch(client_id, batch_id).await;
+        let mut metadata = self.require_metadata(client_id, batch_id).await?;
+
+        // Kept files must still be what the caller saw, or the new root would be wrong
+        let current = Metadata::extract_filenames(&metadata)?;
+        let tree = self.load_merkle_tree(client_id, batch_id).await?;
+        for file in files.iter().filter(|file| file.source.is_none()) {
+            let leaf_hash = current
+                .binary_search(&file.filename)
+                .ok()
+                .and_then(|index| tree.as_ref()?.leaf_hash(index));
+            if leaf_hash != Some(file.leaf_hash) {
+                return Err(ReplaceConflict {
+                    filename: file.filename.clone(),
+                }
+                .into());
+            }
+        }
+
+        for file in &files {
+            if let Some(source) = &file.source {
+                let body = ByteStream::from_path(source)
+                    .await
+                    .with_context(|| format!("Failed to read source file: {:?}", source))?;
+                self.put_object(&Self::file_key(client_id, batch_id, &file.filename), body)
+                    .await?;
+            }
+        }
+
+        // Visibility, access list and annotations carry over; the file set and root do not
+        let filenames: Vec<String> = files.iter().map(|file| file.filename.clone()).collect();
+        if files.is_empty() {
+            self.delete_objects(&[Self::merkle_tree_key(client_id, batch_id)])
+                .await?;
+        } else {
+            let leaf_hashes: Vec<[u8; 32]> = files.iter().map(|file| file.leaf_hash).collect();
+            let tree = MerkleTree::from_leaf_hashes(&leaf_hashes)
+                .context("Failed to build Merkle tree from leaf hashes")?;
+            self.store_tree(client_id, batch_id, &tree).await?;
+        }
+        Metadata::set_filenames(&mut metadata, &filenames);
+        Metadata::set_committed_root(&mut metadata, committed_root);
+        self.save_metadata(client_id, batch_id, &metadata).await?;
+
+        let removed: Vec<String> = current
+            .iter()
+            .filter(|filename| filenames.binary_search(filename).is_err())
+            .map(|filename| Self::file_key(client_id, batch_id, filename))
+            .collect();
+        Ok(self.delete_objects(&removed).await?)
+    }
+
+    async fn compact_batch(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<CompactionReport> {
+        let _guard = self.lock_batch(client_id, batch_id).await;
+        let metadata = self.require_metadata(client_id, batch_id).await?;
+        let mut report = CompactionReport::default();
+
+        let listed = Metadata::extract_filenames(&metadata)?;
+        let filenames: BTreeSet<String> = listed.iter().cloned().collect();
+        report.duplicate_entries_removed += listed.len() - filenames.len();
+        let prefix = Self::batch_prefix(client_id, batch_id);
+        let stored: BTreeSet<String> = self
+            .list_keys(&prefix)
+            .await?
+            .into_iter()
+            .map(|key| key[prefix.len()..].to_string())
+            .collect();
+        if let Some(missing) = filenames.difference(&stored).next() {
+            return Err(anyhow::anyhow!(
+                "Metadata lists {} but the object is missing; re-upload it before compacting",
+                missing
+            )
+            .into());
+        }
+
+        let acl = Metadata::acl(&metadata);
+        let grantees: BTreeSet<String> = acl.iter().cloned().collect();
+        report.duplicate_entries_removed += acl.len() - grantees.len();
+
+        let mut compacted = Map::new();
+        Metadata::set_filenames(
+            &mut compacted,
+            &filenames.iter().cloned().collect::<Vec<_>>(),
+        );
+        if Metadata::is_public(&metadata) {
+            Metadata::set_public(&mut compacted, true);
+        }
+        for grantee in &grantees {
+            Metadata::set_access(&mut compacted, grantee, true);
+        }
+        let annotations = Metadata::annotations(&metadata);
+        if !annotations.is_empty() {
+            Metadata::set_annotations(&mut compacted, &annotations);
+        }
+        if let Some(root) = Metadata::committed_root(&metadata) {
+            Metadata::set_committed_root(&mut compacted, &root);
+        }
+        self.save_metadata(client_id, batch_id, &compacted).await?;
+
+        // Objects left by uploads that failed before their metadata was written
+        let orphaned: Vec<String> = stored
+            .iter()
+            .filter(|name| {
+                !filenames.contains(*name)
+                    && *name != METADATA_OBJECT
+                    && *name != MERKLE_TREE_OBJECT
+            })
+            .map(|name| format!("{}{}", prefix, name))
+            .collect();
+        self.delete_objects(&orphaned).await?;
+        report.orphaned_blobs_removed = orphaned.len();
+        Ok(report)
+    }
+
+    async fn list_batches(&self) -> StorageResult<Vec<(String, String)>> {
+        let mut batches: Vec<(String, String)> = self
+            .list_keys("")
+            .await?
+            .iter()
+            .filter_map(|key| {
+                let (client_id, rest) = key.split_once('/')?;
+                Self::batch_ids(std::iter::once(rest))
+                    .pop()
+                    .map(|batch_id| (client_id.to_string(), batch_id))
+            })
+            .collect();
+        batches.sort();
+        Ok(batches)
+    }
+
+    async fn list_client_batches(&self, client_id: &str) -> StorageResult<Vec<String>> {
+        let prefix = format!("{}/", client_id);
+        let keys = self.list_keys(&prefix).await?;
+        let mut batches = Self::batch_ids(keys.iter().map(|key| &key[prefix.len()..]));
+        batches.sort();
+        Ok(batches)
+    }
+
+    async fn load_merkle_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Option<MerkleTree>> {
+        let Some(tree_json) = self
+            .get_object(&Self::merkle_tree_key(client_id, batch_id))
+            .await?
+        else {
+            return Ok(None);
+        };
+        let tree =
+            serde_json::from_slice(&tree_json).context("Failed to deserialize Merkle tree")?;
+        Ok(Some(tree))
+    }
+
+    async fn store_file_and_update_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        content: &[u8],
+    ) -> StorageResult<()> {
+        Ok(self
+            .store(
+                client_id,
+                batch_id,
+                filename,
+                ByteStream::from(content.to_vec()),
+            )
+            .await?)
+    }
+
+    async fn store_file_from_path_and_update_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        source: &Path,
+    ) -> StorageResult<()> {
+        // Streamed from the file rather than buffered
+        let body = ByteStream::from_path(source)
+            .await
+            .with_context(|| format!("Failed to read source file: {:?}", source))?;
+        Ok(self.store(client_id, batch_id, filename, body).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::{compute_client_id, generate_keypair};
+
+    /// Connect to the bucket named by `TEST_S3_BUCKET` at `TEST_S3_ENDPOINT` (e.g. a local
+    /// MinIO); these tests are skipped unless both are set. Credentials come from the
+    /// usual `AWS_ACCESS_KEY_ID` / `AWS_SECRET_ACCESS_KEY` variables.
+    async fn test_storage() -> Option<S3Storage> {
+        let endpoint = std::env::var("TEST_S3_ENDPOINT").ok()?;
+        let bucket = std::env::var("TEST_S3_BUCKET").ok()?;
+        let storage = S3Storage::new(bucket, Some(&endpoint), Some("us-east-1"))
+            .await
+            .unwrap();
+        storage.validate_bucket().await.unwrap();
+        Some(storage)
+    }
+
+    /// Register a fresh client, so each run works on batches of its own
+    async fn register_client(storage: &S3Storage) -> String {
+        let (_, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+        storage
+            .store_public_key(&client_id, verifying_key.as_bytes())
+            .await
+            .unwrap();
+        client_id
+    }
+
+    #[tokio::test]
+    async fn test_store_read_and_exists() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_S3_ENDPOINT/TEST_S3_BUCKET not set; skipping");
+            return;
+        };
+        let client_id = register_client(&storage).await;
+        assert!(storage.client_exists(&client_id).await.unwrap());
+        assert!(!storage.batch_exists(&client_id, "batch").await.unwrap());
+
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "b.txt", b"beta")
+            .await
+            .unwrap();
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "a.txt", b"alpha")
+            .await
+            .unwrap();
+
+        assert!(storage.batch_exists(&client_id, "batch").await.unwrap());
+        assert!(storage
+            .file_exists(&client_id, "batch", "a.txt")
+            .await
+            .unwrap());
+        assert!(!storage
+            .file_exists(&client_id, "batch", "c.txt")
+            .await
+            .unwrap());
+        assert_eq!(
+            storage
+                .read_file(&client_id, "batch", "a.txt")
+                .await
+                .unwrap(),
+            b"alpha"
+        );
+        assert_eq!(
+            storage
+                .load_batch_filenames(&client_id, "batch")
+                .await
+                .unwrap(),
+            ["a.txt", "b.txt"]
+        );
+        assert!(storage
+            .file_stored_at(&client_id, "batch", "a.txt")
+            .await
+            .unwrap()
+            .is_some());
+
+        let tree = storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        let root = MerkleTree::from_data(&[b"alpha".to_vec(), b"beta".to_vec()])
+            .unwrap()
+            .root_hash();
+        assert_eq!(tree.root_hash(), root);
+        assert_eq!(
+            storage.list_client_batches(&client_id).await.unwrap(),
+            ["batch"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rename_and_delete_batch() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_S3_ENDPOINT/TEST_S3_BUCKET not set; skipping");
+            return;
+        };
+        let client_id = register_client(&storage).await;
+        storage
+            .store_file_and_update_tree(&client_id, "old", "a.txt", b"alpha")
+            .await
+            .unwrap();
+        storage.create_batch(&client_id, "taken").await.unwrap();
+        assert!(storage
+            .rename_batch(&client_id, "old", "taken")
+            .await
+            .is_err());
+
+        storage
+            .rename_batch(&client_id, "old", "new")
+            .await
+            .unwrap();
+        assert!(!storage.batch_exists(&client_id, "old").await.unwrap());
+        assert_eq!(
+            storage.read_file(&client_id, "new", "a.txt").await.unwrap(),
+            b"alpha"
+        );
+        assert_eq!(
+            storage.list_client_batches(&client_id).await.unwrap(),
+            ["new", "taken"]
+        );
+
+        storage.delete_batch(&client_id, "new").await.unwrap();
+        assert!(!storage.batch_exists(&client_id, "new").await.unwrap());
+        assert!(storage
+            .list_keys(&S3Storage::batch_prefix(&client_id, "new"))
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(storage.delete_batch(&client_id, "new").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_delete_file_rebuilds_tree() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_S3_ENDPOINT/TEST_S3_BUCKET not set; skipping");
+            return;
+        };
+        let client_id = register_client(&storage).await;
+        for (filename, content) in [("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")] {
+            storage
+                .store_file_and_update_tree(&client_id, "batch", filename, content)
+                .await
+                .unwrap();
+        }
+
+        let tree = storage
+            .delete_file(&client_id, "batch", "b.txt")
+            .await
+            .unwrap()
+            .unwrap();
+        let root = MerkleTree::from_data(&[b"a".to_vec(), b"c".to_vec()])
+            .unwrap()
+            .root_hash();
+        assert_eq!(tree.root_hash(), root);
+        assert!(!storage
+            .file_exists(&client_id, "batch", "b.txt")
+            .await
+            .unwrap());
+
+        storage
+            .delete_file(&client_id, "batch", "a.txt")
+            .await
+            .unwrap();
+        assert!(storage
+            .delete_file(&client_id, "batch", "c.txt")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(storage.batch_exists(&client_id, "batch").await.unwrap());
+        assert!(storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_reads_keep_filename_order() {
+        let Some(storage) = test_storage().await else {
+            eprintln!("TEST_S3_ENDPOINT/TEST_S3_BUCKET not set; skipping");
+            return;
+        };
+        let client_id = register_client(&storage).await;
+        for i in (0..50).rev() {
+            storage
+                .store_file_and_update_tree(
+                    &client_id,
+                    "batch",
+                    &format!("file-{:02}.txt", i),
+                    format!("content {}", i).as_bytes(),
+                )
+                .await
+                .unwrap();
+        }
+
+        // Reads finish in any order, but come back in the order asked for
+        let filenames = storage
+            .load_batch_filenames(&client_id, "batch")
+            .await
+            .unwrap();
+        let contents = storage
+            .read_batch_files(&client_id, "batch", &filenames)
+            .await
+            .unwrap();
+        let expected: Vec<Vec<u8>> = (0..50)
+            .map(|i| format!("content {}", i).into_bytes())
+            .collect();
+        assert_eq!(contents, expected);
+    }
+}
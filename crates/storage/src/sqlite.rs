@@ -0,0 +1,853 @@
+mod queries;
+mod schema;
+
+use crate::{
+    ensure_canonical_client_id, BatchFull, CompactionReport, ReplaceConflict, ReplacementFile,
+    Storage, StorageError, StorageResult,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use crypto::hash_leaf;
+use merkle_tree::MerkleTree;
+use queries::Queries;
+use schema::Schema;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Statement starting write transactions
+/// SQLite has no row locks; taking the database write lock up front serializes writers
+/// the way `SELECT ... FOR UPDATE` on the batch row does in PostgreSQL, and avoids the
+/// busy error a deferred transaction gets when it upgrades from reading to writing.
+const BEGIN_WRITE: &str = "BEGIN IMMEDIATE";
+
+/// SQLite database storage implementation, for single-node deployments without PostgreSQL
+/// Uses the same tables as `DatabaseStorage` in one database file, in WAL mode so
+/// readers are not blocked by a writer. File content is always stored inline.
+pub struct SqliteStorage {
+    pool: SqlitePool,
+    /// Most files a batch may hold; `None` means unlimited
+    max_files_per_batch: Option<usize>,
+}
+
+impl SqliteStorage {
+    /// Open (creating if missing) the SQLite database at `path` and initialize its schema
+    pub async fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .foreign_keys(true);
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options)
+            .await
+            .with_context(|| format!("Failed to open SQLite database: {:?}", path))?;
+        Schema::initialize(&pool).await?;
+        Ok(Self {
+            pool,
+            max_files_per_batch: None,
+        })
+    }
+
+    /// Reject adding new files to batches that already hold `max` files
+    pub fn with_max_files_per_batch(mut self, max: usize) -> Self {
+        self.max_files_per_batch = Some(max);
+        self
+    }
+
+    /// Write the files row, then rebuild and store the batch's Merkle tree
+    async fn store_row_and_update_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        content: &[u8],
+    ) -> Result<()> {
+        // One transaction covers the batch, the file and the tree, so a failure at any
+        // point leaves none of them changed and a stored file is always in the tree
+        let mut tx = self
+            .pool
+            .begin_with(BEGIN_WRITE)
+            .await
+            .context("Failed to begin transaction for atomic file and tree update")?;
+
+        self.write_row_and_tree(&mut tx, client_id, batch_id, filename, content)
+            .await?;
+
+        tx.commit()
+            .await
+            .context("Failed to commit transaction for atomic file and tree update")?;
+
+        Ok(())
+    }
+
+    /// The writes of [`Self::store_row_and_update_tree`], inside the caller's transaction
+    async fn write_row_and_tree(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        content: &[u8],
+    ) -> Result<()> {
+        Queries::ensure_batch(&mut **tx, client_id, batch_id).await?;
+        if let Some(max_files) = self.max_files_per_batch {
+            let others =
+                Queries::count_other_files(&mut **tx, client_id, batch_id, filename).await?;
+            if others >= max_files as i64 {
+                return Err(BatchFull { max_files }.into());
+            }
+        }
+        Queries::store_file(
+            &mut **tx,
+            client_id,
+            batch_id,
+            filename,
+            content,
+            &hash_leaf(content),
+        )
+        .await?;
+
+        // Rebuild from every file of the batch, including the one just written
+        let leaf_hashes: Vec<[u8; 32]> =
+            Queries::leaf_hashes_by_filename(&mut **tx, client_id, batch_id)
+                .await?
+                .into_iter()
+                .map(|(_, leaf_hash)| leaf_hash)
+                .collect();
+        let tree = MerkleTree::from_leaf_hashes(&leaf_hashes)
+            .context("Failed to build Merkle tree from leaf hashes")?;
+        Queries::store_merkle_tree(&mut **tx, client_id, batch_id, &tree).await
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn read_file(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Vec<u8>> {
+        Ok(
+            Queries::read_file(&self.pool, client_id, batch_id, filename)
+                .await?
+                .ok_or_else(|| StorageError::file_not_found(batch_id, filename))?,
+        )
+    }
+
+    async fn load_batch_filenames(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Vec<String>> {
+        if !Queries::batch_exists(&self.pool, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        Ok(Queries::load_batch_filenames(&self.pool, client_id, batch_id).await?)
+    }
+
+    async fn batch_exists(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        Ok(Queries::batch_exists(&self.pool, client_id, batch_id).await?)
+    }
+
+    async fn client_exists(&self, client_id: &str) -> StorageResult<bool> {
+        Ok(Queries::client_exists(&self.pool, client_id).await?)
+    }
+
+    async fn count_clients(&self) -> StorageResult<u64> {
+        Ok(Queries::count_clients(&self.pool).await?)
+    }
+
+    async fn file_exists(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<bool> {
+        Ok(Queries::file_exists(&self.pool, client_id, batch_id, filename).await?)
+    }
+
+    async fn file_stored_at(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Option<u64>> {
+        Ok(Queries::file_stored_at(&self.pool, client_id, batch_id, filename).await?)
+    }
+
+    async fn store_public_key(&self, client_id: &str, public_key: &[u8]) -> StorageResult<()> {
+        ensure_canonical_client_id(client_id, public_key)?;
+        Ok(Queries::store_public_key(&self.pool, client_id, public_key).await?)
+    }
+
+    async fn load_public_key(&self, client_id: &str) -> StorageResult<Option<Vec<u8>>> {
+        Ok(Queries::load_public_key(&self.pool, client_id).await?)
+    }
+
+    async fn set_batch_public(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        public: bool,
+    ) -> StorageResult<()> {
+        if !Queries::batch_exists(&self.pool, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        Ok(Queries::set_batch_public(&self.pool, client_id, batch_id, public).await?)
+    }
+
+    async fn is_batch_public(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        Ok(Queries::is_batch_public(&self.pool, client_id, batch_id).await?)
+    }
+
+    async fn grant_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<()> {
+        if !Queries::batch_exists(&self.pool, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        Ok(Queries::grant_batch_access(&self.pool, client_id, batch_id, grantee_id).await?)
+    }
+
+    async fn revoke_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<()> {
+        if !Queries::batch_exists(&self.pool, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        Ok(Queries::revoke_batch_access(&self.pool, client_id, batch_id, grantee_id).await?)
+    }
+
+    async fn has_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<bool> {
+        Ok(Queries::has_batch_access(&self.pool, client_id, batch_id, grantee_id).await?)
+    }
+
+    async fn set_batch_annotations(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        annotations: &BTreeMap<String, String>,
+    ) -> StorageResult<()> {
+        if !Queries::batch_exists(&self.pool, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        Ok(Queries::set_batch_annotations(&self.pool, client_id, batch_id, annotations).await?)
+    }
+
+    async fn load_batch_annotations(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<BTreeMap<String, String>> {
+        Ok(Queries::load_batch_annotations(&self.pool, client_id, batch_id).await?)
+    }
+
+    async fn set_committed_root(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        root: &[u8; 32],
+    ) -> StorageResult<()> {
+        if !Queries::batch_exists(&self.pool, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        Ok(Queries::set_committed_root(&self.pool, client_id, batch_id, root).await?)
+    }
+
+    async fn load_committed_root(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Option<[u8; 32]>> {
+        Ok(Queries::load_committed_root(&self.pool, client_id, batch_id).await?)
+    }
+
+    async fn create_batch(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        Ok(Queries::create_batch(&self.pool, client_id, batch_id).await?)
+    }
+
+    async fn rename_batch(
+        &self,
+        client_id: &str,
+        old_batch_id: &str,
+        new_batch_id: &str,
+    ) -> StorageResult<()> {
+        let mut tx = self
+            .pool
+            .begin_with(BEGIN_WRITE)
+            .await
+            .context("Failed to begin transaction for batch rename")?;
+
+        if !Queries::batch_exists(&mut *tx, client_id, old_batch_id).await? {
+            return Err(StorageError::batch_not_found(old_batch_id));
+        }
+        if Queries::batch_exists(&mut *tx, client_id, new_batch_id).await? {
+            return Err(StorageError::batch_already_exists(new_batch_id));
+        }
+
+        Queries::rename_batch(&mut tx, client_id, old_batch_id, new_batch_id).await?;
+
+        tx.commit()
+            .await
+            .context("Failed to commit transaction for batch rename")?;
+        Ok(())
+    }
+
+    async fn delete_batch(&self, client_id: &str, batch_id: &str) -> StorageResult<()> {
+        // Files, trees and access grants go with the batch row (ON DELETE CASCADE)
+        if !Queries::delete_batch(&self.pool, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        Ok(())
+    }
+
+    async fn delete_file(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Option<MerkleTree>> {
+        let mut tx = self
+            .pool
+            .begin_with(BEGIN_WRITE)
+            .await
+            .context("Failed to begin transaction for file deletion")?;
+
+        // Removing the row and rebuilding the tree commit together, as for uploads
+        if !Queries::delete_file(&mut *tx, client_id, batch_id, filename).await? {
+            return Err(StorageError::file_not_found(batch_id, filename));
+        }
+        let leaf_hashes: Vec<[u8; 32]> =
+            Queries::leaf_hashes_by_filename(&mut *tx, client_id, batch_id)
+                .await?
+                .into_iter()
+                .map(|(_, leaf_hash)| leaf_hash)
+                .collect();
+        let tree = if leaf_hashes.is_empty() {
+            // No files left: the batch has no root, so its tree goes too
+            Queries::delete_orphaned_rows(&mut tx, client_id, batch_id).await?;
+            None
+        } else {
+            let tree = MerkleTree::from_leaf_hashes(&leaf_hashes)
+                .context("Failed to build Merkle tree from leaf hashes")?;
+            Queries::store_merkle_tree(&mut *tx, client_id, batch_id, &tree).await?;
+            Some(tree)
+        };
+
+        tx.commit()
+            .await
+            .context("Failed to commit transaction for file deletion")?;
+        Ok(tree)
+    }
+
+    async fn replace_batch(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        files: &[ReplacementFile],
+        committed_root: &[u8; 32],
+    ) -> StorageResult<()> {
+        if let Some(max_files) = self.max_files_per_batch {
+            if files.len() > max_files {
+                return Err(BatchFull { max_files }.into());
+            }
+        }
+        let mut files: Vec<&ReplacementFile> = files.iter().collect();
+        files.sort_by(|a, b| a.filename.cmp(&b.filename));
+        if let Some(pair) = files.windows(2).find(|w| w[0].filename == w[1].filename) {
+            return Err(anyhow::anyhow!("File {} is listed twice", pair[0].filename).into());
+        }
+
+        // Read new content before the transaction, so a bad source never half-applies
+        let mut uploads = Vec::new();
+        for file in &files {
+            let Some(source) = &file.source else {
+                continue;
+            };
+            let content = tokio::fs::read(source)
+                .await
+                .with_context(|| format!("Failed to read source file: {:?}", source))?;
+            uploads.push((file.filename.as_str(), file.leaf_hash, content));
+        }
+
+        let mut tx = self
+            .pool
+            .begin_with(BEGIN_WRITE)
+            .await
+            .context("Failed to begin transaction for batch replacement")?;
+        if !Queries::batch_exists(&mut *tx, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+
+        // Kept files must still be what the caller saw, or the new root would be wrong
+        let current: BTreeMap<String, [u8; 32]> =
+            Queries::leaf_hashes_by_filename(&mut *tx, client_id, batch_id)
+                .await?
+                .into_iter()
+                .collect();
+        for file in files.iter().filter(|file| file.source.is_none()) {
+            if current.get(&file.filename) != Some(&file.leaf_hash) {
+                return Err(ReplaceConflict {
+                    filename: file.filename.clone(),
+                }
+                .into());
+            }
+        }
+
+        let filenames: Vec<String> = files.iter().map(|file| file.filename.clone()).collect();
+        Queries::delete_files_except(&mut *tx, client_id, batch_id, &filenames).await?;
+        for (filename, leaf_hash, content) in &uploads {
+            Queries::store_file(&mut *tx, client_id, batch_id, filename, content, leaf_hash)
+                .await?;
+        }
+
+        // A batch without files has no tree
+        if files.is_empty() {
+            Queries::delete_orphaned_rows(&mut tx, client_id, batch_id).await?;
+        } else {
+            let leaf_hashes: Vec<[u8; 32]> = files.iter().map(|file| file.leaf_hash).collect();
+            let tree = MerkleTree::from_leaf_hashes(&leaf_hashes)
+                .context("Failed to build Merkle tree from leaf hashes")?;
+            Queries::store_merkle_tree(&mut *tx, client_id, batch_id, &tree).await?;
+        }
+        Queries::set_committed_root(&mut *tx, client_id, batch_id, committed_root).await?;
+
+        Ok(tx
+            .commit()
+            .await
+            .context("Failed to commit batch replacement")?)
+    }
+
+    async fn compact_batch(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<CompactionReport> {
+        // Metadata is normalized by the schema; only orphaned rows can pile up
+        let mut tx = self
+            .pool
+            .begin_with(BEGIN_WRITE)
+            .await
+            .context("Failed to begin transaction for batch compaction")?;
+        if !Queries::batch_exists(&mut *tx, client_id, batch_id).await? {
+            return Err(StorageError::batch_not_found(batch_id));
+        }
+        let orphaned_rows = Queries::delete_orphaned_rows(&mut tx, client_id, batch_id).await?;
+        tx.commit()
+            .await
+            .context("Failed to commit batch compaction")?;
+
+        Ok(CompactionReport {
+            orphaned_rows_removed: orphaned_rows as usize,
+            ..CompactionReport::default()
+        })
+    }
+
+    async fn list_batches(&self) -> StorageResult<Vec<(String, String)>> {
+        Ok(Queries::list_batches(&self.pool).await?)
+    }
+
+    async fn list_client_batches(&self, client_id: &str) -> StorageResult<Vec<String>> {
+        Ok(Queries::list_client_batches(&self.pool, client_id).await?)
+    }
+
+    async fn load_merkle_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Option<merkle_tree::MerkleTree>> {
+        Ok(Queries::load_merkle_tree(&self.pool, client_id, batch_id).await?)
+    }
+
+    async fn store_file_and_update_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        content: &[u8],
+    ) -> StorageResult<()> {
+        Ok(self
+            .store_row_and_update_tree(client_id, batch_id, filename, content)
+            .await?)
+    }
+
+    async fn store_file_from_path_and_update_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        source: &Path,
+    ) -> StorageResult<()> {
+        // Content is bound as a single BLOB value, so it has to be buffered here
+        let content = tokio::fs::read(source)
+            .await
+            .with_context(|| format!("Failed to read source file: {:?}", source))?;
+        Ok(self
+            .store_row_and_update_tree(client_id, batch_id, filename, &content)
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::{compute_client_id, generate_keypair};
+    use std::path::PathBuf;
+
+    /// SQLite database file in a temp directory removed when dropped
+    struct TempDatabase(PathBuf);
+
+    impl TempDatabase {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("vs-sqlite-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        async fn open(&self) -> SqliteStorage {
+            SqliteStorage::new(self.0.join("data.db")).await.unwrap()
+        }
+    }
+
+    impl Drop for TempDatabase {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    async fn register_client(storage: &SqliteStorage) -> String {
+        let (_, verifying_key) = generate_keypair();
+        let client_id = compute_client_id(&verifying_key);
+        storage
+            .store_public_key(&client_id, verifying_key.as_bytes())
+            .await
+            .unwrap();
+        client_id
+    }
+
+    #[tokio::test]
+    async fn test_store_read_and_verify_proofs() {
+        let db = TempDatabase::new("proofs");
+        let storage = db.open().await;
+        let client_id = register_client(&storage).await;
+        let files: [(&str, &[u8]); 3] = [("a.txt", b"alpha"), ("b.txt", b"beta"), ("c.txt", b"")];
+        for (filename, content) in files {
+            storage
+                .store_file_and_update_tree(&client_id, "batch", filename, content)
+                .await
+                .unwrap();
+        }
+
+        assert!(storage.client_exists(&client_id).await.unwrap());
+        assert_eq!(storage.count_clients().await.unwrap(), 1);
+        assert!(storage.batch_exists(&client_id, "batch").await.unwrap());
+        assert_eq!(
+            storage
+                .load_batch_filenames(&client_id, "batch")
+                .await
+                .unwrap(),
+            ["a.txt", "b.txt", "c.txt"]
+        );
+        assert!(storage
+            .file_stored_at(&client_id, "batch", "a.txt")
+            .await
+            .unwrap()
+            .is_some());
+
+        // The stored tree proves each file, as read back, against the root the client
+        // computes from its own copies
+        let root = MerkleTree::from_data(&files.map(|(_, content)| content.to_vec()))
+            .unwrap()
+            .root_hash();
+        let tree = storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tree.root_hash(), root);
+        for (index, (filename, content)) in files.into_iter().enumerate() {
+            let read = storage
+                .read_file(&client_id, "batch", filename)
+                .await
+                .unwrap();
+            assert_eq!(read, content);
+            let proof = tree.generate_proof(index).unwrap();
+            assert_eq!(proof.leaf_hash, hash_leaf(&read));
+            assert!(proof.verify(&root));
+        }
+
+        // Replacing a file's content changes the root
+        storage
+            .store_file_and_update_tree(&client_id, "batch", "b.txt", b"BETA")
+            .await
+            .unwrap();
+        let tree = storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(tree.root_hash(), root);
+        assert_eq!(
+            storage
+                .read_file(&client_id, "batch", "b.txt")
+                .await
+                .unwrap(),
+            b"BETA"
+        );
+        assert!(storage
+            .read_file(&client_id, "batch", "missing.txt")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_missing_batch_is_not_found() {
+        let db = TempDatabase::new("not-found");
+        let storage = db.open().await;
+        let client_id = register_client(&storage).await;
+        let err = storage
+            .load_batch_filenames(&client_id, "missing")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            StorageError::NotFound {
+                kind: crate::ResourceKind::Batch,
+                ref id,
+            } if id == "missing"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_failed_store_leaves_neither_file_nor_tree() {
+        let db = TempDatabase::new("rollback");
+        let storage = db.open().await;
+        let client_id = register_client(&storage).await;
+
+        let mut tx = storage.pool.begin_with(BEGIN_WRITE).await.unwrap();
+        storage
+            .write_row_and_tree(&mut tx, &client_id, "batch", "a.txt", b"alpha")
+            .await
+            .unwrap();
+        tx.rollback().await.unwrap();
+
+        assert!(!storage.batch_exists(&client_id, "batch").await.unwrap());
+        assert!(storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_data_survives_reopening() {
+        let db = TempDatabase::new("reopen");
+        let client_id = {
+            let storage = db.open().await;
+            let client_id = register_client(&storage).await;
+            storage
+                .store_file_and_update_tree(&client_id, "batch", "a.txt", b"alpha")
+                .await
+                .unwrap();
+            let mut annotations = BTreeMap::new();
+            annotations.insert("commit".to_string(), "1a2b3c".to_string());
+            storage
+                .set_batch_annotations(&client_id, "batch", &annotations)
+                .await
+                .unwrap();
+            storage
+                .set_committed_root(&client_id, "batch", &hash_leaf(b"alpha"))
+                .await
+                .unwrap();
+            storage
+                .set_batch_public(&client_id, "batch", true)
+                .await
+                .unwrap();
+            storage.pool.close().await;
+            client_id
+        };
+
+        let storage = db.open().await;
+        assert_eq!(
+            storage
+                .read_file(&client_id, "batch", "a.txt")
+                .await
+                .unwrap(),
+            b"alpha"
+        );
+        assert_eq!(
+            storage
+                .load_batch_annotations(&client_id, "batch")
+                .await
+                .unwrap()
+                .get("commit")
+                .map(String::as_str),
+            Some("1a2b3c")
+        );
+        assert_eq!(
+            storage
+                .load_committed_root(&client_id, "batch")
+                .await
+                .unwrap(),
+            Some(hash_leaf(b"alpha"))
+        );
+        assert!(storage.is_batch_public(&client_id, "batch").await.unwrap());
+        assert_eq!(
+            storage.list_batches().await.unwrap(),
+            [(client_id.clone(), "batch".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rename_and_delete_batch() {
+        let db = TempDatabase::new("rename");
+        let storage = db.open().await;
+        let client_id = register_client(&storage).await;
+        let grantee_id = register_client(&storage).await;
+        storage
+            .store_file_and_update_tree(&client_id, "old", "a.txt", b"alpha")
+            .await
+            .unwrap();
+        storage
+            .grant_batch_access(&client_id, "old", &grantee_id)
+            .await
+            .unwrap();
+        storage.create_batch(&client_id, "taken").await.unwrap();
+
+        assert!(storage
+            .rename_batch(&client_id, "old", "taken")
+            .await
+            .is_err());
+        storage
+            .rename_batch(&client_id, "old", "new")
+            .await
+            .unwrap();
+        assert!(!storage.batch_exists(&client_id, "old").await.unwrap());
+        assert_eq!(
+            storage.read_file(&client_id, "new", "a.txt").await.unwrap(),
+            b"alpha"
+        );
+        assert!(storage
+            .has_batch_access(&client_id, "new", &grantee_id)
+            .await
+            .unwrap());
+        assert!(storage
+            .load_merkle_tree(&client_id, "new")
+            .await
+            .unwrap()
+            .is_some());
+
+        storage.delete_batch(&client_id, "new").await.unwrap();
+        assert!(!storage
+            .file_exists(&client_id, "new", "a.txt")
+            .await
+            .unwrap());
+        assert_eq!(
+            storage.list_client_batches(&client_id).await.unwrap(),
+            ["taken"]
+        );
+        assert!(storage.delete_batch(&client_id, "new").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_delete_file_and_replace_batch() {
+        let db = TempDatabase::new("replace");
+        let storage = db.open().await.with_max_files_per_batch(2);
+        let client_id = register_client(&storage).await;
+        for (filename, content) in [("a.txt", b"a"), ("b.txt", b"b")] {
+            storage
+                .store_file_and_update_tree(&client_id, "batch", filename, content)
+                .await
+                .unwrap();
+        }
+        let err = storage
+            .store_file_and_update_tree(&client_id, "batch", "c.txt", b"c")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StorageError::BatchFull(_)));
+
+        let tree = storage
+            .delete_file(&client_id, "batch", "a.txt")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tree.root_hash(), hash_leaf(b"b"));
+
+        // Keep b.txt, add c.txt; a stale kept hash is a conflict
+        let source = db.0.join("c.txt");
+        std::fs::write(&source, b"c").unwrap();
+        let stale = [ReplacementFile {
+            filename: "b.txt".to_string(),
+            leaf_hash: hash_leaf(b"stale"),
+            source: None,
+        }];
+        let err = storage
+            .replace_batch(&client_id, "batch", &stale, &[0; 32])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StorageError::Conflict(_)));
+
+        let files = [
+            ReplacementFile {
+                filename: "c.txt".to_string(),
+                leaf_hash: hash_leaf(b"c"),
+                source: Some(source),
+            },
+            ReplacementFile {
+                filename: "b.txt".to_string(),
+                leaf_hash: hash_leaf(b"b"),
+                source: None,
+            },
+        ];
+        let root = MerkleTree::from_data(&[b"b".to_vec(), b"c".to_vec()])
+            .unwrap()
+            .root_hash();
+        storage
+            .replace_batch(&client_id, "batch", &files, &root)
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .load_batch_filenames(&client_id, "batch")
+                .await
+                .unwrap(),
+            ["b.txt", "c.txt"]
+        );
+        assert_eq!(
+            storage
+                .load_merkle_tree(&client_id, "batch")
+                .await
+                .unwrap()
+                .unwrap()
+                .root_hash(),
+            root
+        );
+
+        // Emptying the batch removes its tree
+        storage
+            .replace_batch(&client_id, "batch", &[], &[0; 32])
+            .await
+            .unwrap();
+        assert!(storage
+            .load_merkle_tree(&client_id, "batch")
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
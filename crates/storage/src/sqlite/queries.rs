@@ -0,0 +1,548 @@
+use crate::database::sql;
+use anyhow::{Context, Result};
+use merkle_tree::MerkleTree;
+use sqlx::{SqliteConnection, SqlitePool};
+use std::collections::BTreeMap;
+
+/// Query operations for SQLite storage
+/// Statements valid on both databases come from `database::sql`; the rest are the
+/// SQLite counterparts of the PostgreSQL queries
+pub struct Queries;
+
+impl Queries {
+    /// Ensure batch exists (create if not exists)
+    pub async fn ensure_batch(
+        pool: impl sqlx::Executor<'_, Database = sqlx::Sqlite>,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<()> {
+        sqlx::query(sql::INSERT_BATCH)
+            .bind(client_id)
+            .bind(batch_id)
+            .execute(pool)
+            .await
+            .context("Failed to ensure batch exists")?;
+        Ok(())
+    }
+
+    /// Count the files in a batch other than `filename`
+    pub async fn count_other_files(
+        pool: impl sqlx::Executor<'_, Database = sqlx::Sqlite>,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> Result<i64> {
+        sqlx::query_scalar(sql::COUNT_OTHER_FILES)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(filename)
+            .fetch_one(pool)
+            .await
+            .context("Failed to count batch files")
+    }
+
+    /// Store file content with its leaf hash
+    pub async fn store_file(
+        pool: impl sqlx::Executor<'_, Database = sqlx::Sqlite>,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        content: &[u8],
+        leaf_hash: &[u8; 32],
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO files (client_id, batch_id, filename, content, leaf_hash)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (client_id, batch_id, filename)
+             DO UPDATE SET content = EXCLUDED.content, leaf_hash = EXCLUDED.leaf_hash,
+                 created_at = strftime('%Y-%m-%d %H:%M:%f', 'now')",
+        )
+        .bind(client_id)
+        .bind(batch_id)
+        .bind(filename)
+        .bind(content)
+        .bind(leaf_hash.as_slice())
+        .execute(pool)
+        .await
+        .context("Failed to store file")?;
+        Ok(())
+    }
+
+    /// Read file content
+    pub async fn read_file(
+        pool: &SqlitePool,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        sqlx::query_scalar(
+            "SELECT content FROM files
+             WHERE client_id = $1 AND batch_id = $2 AND filename = $3",
+        )
+        .bind(client_id)
+        .bind(batch_id)
+        .bind(filename)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to query file")
+    }
+
+    /// Create a batch; returns false if it already exists
+    pub async fn create_batch(pool: &SqlitePool, client_id: &str, batch_id: &str) -> Result<bool> {
+        let result = sqlx::query(sql::INSERT_BATCH)
+            .bind(client_id)
+            .bind(batch_id)
+            .execute(pool)
+            .await
+            .context("Failed to create batch")?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Check if batch exists
+    pub async fn batch_exists(
+        pool: impl sqlx::Executor<'_, Database = sqlx::Sqlite>,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<bool> {
+        sqlx::query_scalar(sql::BATCH_EXISTS)
+            .bind(client_id)
+            .bind(batch_id)
+            .fetch_one(pool)
+            .await
+            .context("Failed to check batch existence")
+    }
+
+    /// Set the public flag on a batch
+    pub async fn set_batch_public(
+        pool: &SqlitePool,
+        client_id: &str,
+        batch_id: &str,
+        public: bool,
+    ) -> Result<()> {
+        sqlx::query(sql::SET_BATCH_PUBLIC)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(public)
+            .execute(pool)
+            .await
+            .context("Failed to update batch visibility")?;
+        Ok(())
+    }
+
+    /// Check if batch is public (false if the batch does not exist)
+    pub async fn is_batch_public(
+        pool: &SqlitePool,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<bool> {
+        let public: Option<bool> = sqlx::query_scalar(sql::IS_BATCH_PUBLIC)
+            .bind(client_id)
+            .bind(batch_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to check batch visibility")?;
+        Ok(public.unwrap_or(false))
+    }
+
+    /// Replace the annotations stored on a batch
+    pub async fn set_batch_annotations(
+        pool: &SqlitePool,
+        client_id: &str,
+        batch_id: &str,
+        annotations: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let annotations =
+            serde_json::to_string(annotations).context("Failed to serialize annotations")?;
+        sqlx::query(sql::SET_BATCH_ANNOTATIONS)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(annotations)
+            .execute(pool)
+            .await
+            .context("Failed to update batch annotations")?;
+        Ok(())
+    }
+
+    /// Load a batch's annotations (empty if the batch does not exist)
+    pub async fn load_batch_annotations(
+        pool: &SqlitePool,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<BTreeMap<String, String>> {
+        let annotations: Option<String> = sqlx::query_scalar(sql::LOAD_BATCH_ANNOTATIONS)
+            .bind(client_id)
+            .bind(batch_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to load batch annotations")?;
+        match annotations {
+            Some(annotations) => {
+                serde_json::from_str(&annotations).context("Failed to deserialize annotations")
+            }
+            None => Ok(BTreeMap::new()),
+        }
+    }
+
+    /// Record the root committed to by a finalize
+    pub async fn set_committed_root(
+        pool: impl sqlx::Executor<'_, Database = sqlx::Sqlite>,
+        client_id: &str,
+        batch_id: &str,
+        root: &[u8; 32],
+    ) -> Result<()> {
+        sqlx::query(sql::SET_COMMITTED_ROOT)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(root.as_slice())
+            .execute(pool)
+            .await
+            .context("Failed to update committed root")?;
+        Ok(())
+    }
+
+    /// Load the root recorded by the batch's last finalize (none if never finalized)
+    pub async fn load_committed_root(
+        pool: &SqlitePool,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<Option<[u8; 32]>> {
+        let root: Option<Option<Vec<u8>>> = sqlx::query_scalar(sql::LOAD_COMMITTED_ROOT)
+            .bind(client_id)
+            .bind(batch_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to load committed root")?;
+        root.flatten()
+            .map(|bytes| {
+                bytes
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("Stored committed root is not 32 bytes"))
+            })
+            .transpose()
+    }
+
+    /// Delete a Merkle tree left behind once the batch has no files
+    /// Returns the number of rows deleted
+    pub async fn delete_orphaned_rows(
+        conn: &mut SqliteConnection,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<u64> {
+        let result = sqlx::query(sql::DELETE_ORPHANED_TREE)
+            .bind(client_id)
+            .bind(batch_id)
+            .execute(&mut *conn)
+            .await
+            .context("Failed to delete orphaned Merkle tree")?;
+        Ok(result.rows_affected())
+    }
+
+    /// Grant a client read access to a batch
+    pub async fn grant_batch_access(
+        pool: &SqlitePool,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> Result<()> {
+        sqlx::query(sql::GRANT_BATCH_ACCESS)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(grantee_id)
+            .execute(pool)
+            .await
+            .context("Failed to grant batch access")?;
+        Ok(())
+    }
+
+    /// Revoke a client's read access to a batch
+    pub async fn revoke_batch_access(
+        pool: &SqlitePool,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> Result<()> {
+        sqlx::query(sql::REVOKE_BATCH_ACCESS)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(grantee_id)
+            .execute(pool)
+            .await
+            .context("Failed to revoke batch access")?;
+        Ok(())
+    }
+
+    /// Check if a client was granted read access to a batch
+    pub async fn has_batch_access(
+        pool: &SqlitePool,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> Result<bool> {
+        sqlx::query_scalar(sql::HAS_BATCH_ACCESS)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(grantee_id)
+            .fetch_one(pool)
+            .await
+            .context("Failed to check batch access")
+    }
+
+    /// Move a batch and everything referencing it to a new batch_id
+    /// Must run inside a transaction; the new batch row is created first so that
+    /// foreign keys from files and merkle_trees stay valid throughout
+    pub async fn rename_batch(
+        conn: &mut SqliteConnection,
+        client_id: &str,
+        old_batch_id: &str,
+        new_batch_id: &str,
+    ) -> Result<()> {
+        sqlx::query(sql::COPY_BATCH)
+            .bind(client_id)
+            .bind(old_batch_id)
+            .bind(new_batch_id)
+            .execute(&mut *conn)
+            .await
+            .context("Failed to create renamed batch")?;
+
+        for table in sql::BATCH_CHILD_TABLES {
+            sqlx::query(&sql::move_batch_rows(table))
+                .bind(client_id)
+                .bind(old_batch_id)
+                .bind(new_batch_id)
+                .execute(&mut *conn)
+                .await
+                .with_context(|| format!("Failed to move {} to renamed batch", table))?;
+        }
+
+        sqlx::query(sql::DELETE_BATCH)
+            .bind(client_id)
+            .bind(old_batch_id)
+            .execute(&mut *conn)
+            .await
+            .context("Failed to remove old batch")?;
+        Ok(())
+    }
+
+    /// Delete a batch; its files, tree and access grants are removed by cascade
+    /// Returns false if the batch did not exist
+    pub async fn delete_batch(pool: &SqlitePool, client_id: &str, batch_id: &str) -> Result<bool> {
+        let result = sqlx::query(sql::DELETE_BATCH)
+            .bind(client_id)
+            .bind(batch_id)
+            .execute(pool)
+            .await
+            .context("Failed to delete batch")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Delete one file row of a batch
+    /// Returns false if the batch has no such file
+    pub async fn delete_file(
+        pool: impl sqlx::Executor<'_, Database = sqlx::Sqlite>,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> Result<bool> {
+        let result = sqlx::query(sql::DELETE_FILE)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(filename)
+            .execute(pool)
+            .await
+            .context("Failed to delete file")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Check if file exists
+    pub async fn file_exists(
+        pool: &SqlitePool,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> Result<bool> {
+        sqlx::query_scalar(sql::FILE_EXISTS)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(filename)
+            .fetch_one(pool)
+            .await
+            .context("Failed to check file existence")
+    }
+
+    /// Load when a file's current content was stored, in milliseconds since the Unix epoch
+    pub async fn file_stored_at(
+        pool: &SqlitePool,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> Result<Option<u64>> {
+        // created_at is UTC text with fractional seconds
+        let stored_at: Option<i64> = sqlx::query_scalar(
+            "SELECT CAST(unixepoch(created_at, 'subsec') * 1000 AS INTEGER) FROM files
+             WHERE client_id = $1 AND batch_id = $2 AND filename = $3",
+        )
+        .bind(client_id)
+        .bind(batch_id)
+        .bind(filename)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to load file timestamp")?;
+        Ok(stored_at.map(|ms| ms as u64))
+    }
+
+    /// Load batch filenames from files table
+    pub async fn load_batch_filenames(
+        pool: &SqlitePool,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<Vec<String>> {
+        sqlx::query_scalar(sql::LOAD_BATCH_FILENAMES)
+            .bind(client_id)
+            .bind(batch_id)
+            .fetch_all(pool)
+            .await
+            .context("Failed to load batch filenames")
+    }
+
+    /// List all batches as (client_id, batch_id) pairs
+    pub async fn list_batches(pool: &SqlitePool) -> Result<Vec<(String, String)>> {
+        sqlx::query_as::<_, (String, String)>(sql::LIST_BATCHES)
+            .fetch_all(pool)
+            .await
+            .context("Failed to list batches")
+    }
+
+    /// List one client's batch IDs, sorted
+    pub async fn list_client_batches(pool: &SqlitePool, client_id: &str) -> Result<Vec<String>> {
+        sqlx::query_scalar::<_, String>(sql::LIST_CLIENT_BATCHES)
+            .bind(client_id)
+            .fetch_all(pool)
+            .await
+            .context("Failed to list client batches")
+    }
+
+    /// Store public key
+    pub async fn store_public_key(
+        pool: &SqlitePool,
+        client_id: &str,
+        public_key: &[u8],
+    ) -> Result<()> {
+        sqlx::query(sql::STORE_PUBLIC_KEY)
+            .bind(client_id)
+            .bind(public_key)
+            .execute(pool)
+            .await
+            .context("Failed to store public key")?;
+        Ok(())
+    }
+
+    /// Check if client is registered
+    pub async fn client_exists(pool: &SqlitePool, client_id: &str) -> Result<bool> {
+        sqlx::query_scalar(sql::CLIENT_EXISTS)
+            .bind(client_id)
+            .fetch_one(pool)
+            .await
+            .context("Failed to check client existence")
+    }
+
+    /// Count registered clients
+    pub async fn count_clients(pool: &SqlitePool) -> Result<u64> {
+        let count: i64 = sqlx::query_scalar(sql::COUNT_CLIENTS)
+            .fetch_one(pool)
+            .await
+            .context("Failed to count clients")?;
+        Ok(count as u64)
+    }
+
+    /// Load public key
+    pub async fn load_public_key(pool: &SqlitePool, client_id: &str) -> Result<Option<Vec<u8>>> {
+        sqlx::query_scalar(sql::LOAD_PUBLIC_KEY)
+            .bind(client_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to load public key")
+    }
+
+    /// Every file of the batch with its stored leaf hash, in filename order
+    pub async fn leaf_hashes_by_filename(
+        pool: impl sqlx::Executor<'_, Database = sqlx::Sqlite>,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<Vec<(String, [u8; 32])>> {
+        let rows = sqlx::query_as::<_, (String, Vec<u8>)>(
+            "SELECT filename, leaf_hash FROM files
+             WHERE client_id = $1 AND batch_id = $2 ORDER BY filename",
+        )
+        .bind(client_id)
+        .bind(batch_id)
+        .fetch_all(pool)
+        .await
+        .context("Failed to load leaf hashes")?;
+
+        rows.into_iter()
+            .map(|(filename, hash)| {
+                let leaf_hash = hash
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("Unexpected hash length for file {}", filename))?;
+                Ok((filename, leaf_hash))
+            })
+            .collect()
+    }
+
+    /// Delete every file of the batch not named in `keep`
+    pub async fn delete_files_except(
+        pool: impl sqlx::Executor<'_, Database = sqlx::Sqlite>,
+        client_id: &str,
+        batch_id: &str,
+        keep: &[String],
+    ) -> Result<u64> {
+        // SQLite has no array parameters; the names are passed as one JSON array
+        let keep = serde_json::to_string(keep).context("Failed to serialize filenames")?;
+        let result = sqlx::query(
+            "DELETE FROM files WHERE client_id = $1 AND batch_id = $2
+             AND filename NOT IN (SELECT value FROM json_each($3))",
+        )
+        .bind(client_id)
+        .bind(batch_id)
+        .bind(keep)
+        .execute(pool)
+        .await
+        .context("Failed to delete replaced files")?;
+        Ok(result.rows_affected())
+    }
+
+    /// Store Merkle tree structure
+    pub async fn store_merkle_tree(
+        pool: impl sqlx::Executor<'_, Database = sqlx::Sqlite>,
+        client_id: &str,
+        batch_id: &str,
+        tree: &MerkleTree,
+    ) -> Result<()> {
+        let tree_json = serde_json::to_string(tree).context("Failed to serialize Merkle tree")?;
+        sqlx::query(sql::STORE_MERKLE_TREE)
+            .bind(client_id)
+            .bind(batch_id)
+            .bind(tree_json)
+            .execute(pool)
+            .await
+            .context("Failed to store Merkle tree")?;
+        Ok(())
+    }
+
+    /// Load Merkle tree structure
+    pub async fn load_merkle_tree(
+        pool: &SqlitePool,
+        client_id: &str,
+        batch_id: &str,
+    ) -> Result<Option<MerkleTree>> {
+        let tree_json: Option<String> = sqlx::query_scalar(sql::LOAD_MERKLE_TREE)
+            .bind(client_id)
+            .bind(batch_id)
+            .fetch_optional(pool)
+            .await
+            .context("Failed to load Merkle tree")?;
+        tree_json
+            .map(|json| serde_json::from_str(&json).context("Failed to deserialize Merkle tree"))
+            .transpose()
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::{Context, Result};
+use sqlx::SqlitePool;
+use tracing::info;
+
+/// SQLite schema manager
+/// Mirrors the PostgreSQL schema (same tables and columns, so statements in
+/// `database::sql` run on both), with SQLite types: `TEXT` keys, `BLOB` bytes and
+/// JSON kept as `TEXT`. Files also record their leaf hash, which PostgreSQL computes
+/// on the fly but SQLite has no function for.
+pub struct Schema;
+
+impl Schema {
+    /// Initialize all tables and indexes
+    pub async fn initialize(pool: &SqlitePool) -> Result<()> {
+        Self::create_clients_table(pool).await?;
+        Self::create_batches_table(pool).await?;
+        Self::create_files_table(pool).await?;
+        Self::create_merkle_trees_table(pool).await?;
+        Self::create_batch_acl_table(pool).await?;
+        Self::create_indexes(pool).await?;
+        info!("SQLite database storage initialized");
+        Ok(())
+    }
+
+    /// Create clients table
+    async fn create_clients_table(pool: &SqlitePool) -> Result<()> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS clients (
+                client_id TEXT PRIMARY KEY,
+                public_key BLOB NOT NULL,
+                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create clients table")?;
+        Ok(())
+    }
+
+    /// Create batches table
+    async fn create_batches_table(pool: &SqlitePool) -> Result<()> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS batches (
+                client_id TEXT NOT NULL,
+                batch_id TEXT NOT NULL,
+                is_public BOOLEAN NOT NULL DEFAULT FALSE,
+                batch_metadata TEXT NOT NULL DEFAULT '{}',
+                committed_root BLOB,
+                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (client_id, batch_id),
+                FOREIGN KEY (client_id) REFERENCES clients(client_id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create batches table")?;
+        Ok(())
+    }
+
+    /// Create files table
+    /// `created_at` keeps milliseconds, which `CURRENT_TIMESTAMP` would drop
+    async fn create_files_table(pool: &SqlitePool) -> Result<()> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS files (
+                client_id TEXT NOT NULL,
+                batch_id TEXT NOT NULL,
+                filename TEXT NOT NULL,
+                content BLOB NOT NULL,
+                leaf_hash BLOB NOT NULL,
+                created_at TIMESTAMP NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+                PRIMARY KEY (client_id, batch_id, filename),
+                FOREIGN KEY (client_id, batch_id) REFERENCES batches(client_id, batch_id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create files table")?;
+        Ok(())
+    }
+
+    /// Create merkle_trees table for storing Merkle tree structures
+    async fn create_merkle_trees_table(pool: &SqlitePool) -> Result<()> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS merkle_trees (
+                client_id TEXT NOT NULL,
+                batch_id TEXT NOT NULL,
+                tree_data TEXT NOT NULL,
+                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (client_id, batch_id),
+                FOREIGN KEY (client_id, batch_id) REFERENCES batches(client_id, batch_id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create merkle_trees table")?;
+        Ok(())
+    }
+
+    /// Create batch_acl table listing clients granted read access to a batch
+    async fn create_batch_acl_table(pool: &SqlitePool) -> Result<()> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS batch_acl (
+                client_id TEXT NOT NULL,
+                batch_id TEXT NOT NULL,
+                grantee_id TEXT NOT NULL,
+                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (client_id, batch_id, grantee_id),
+                FOREIGN KEY (client_id, batch_id) REFERENCES batches(client_id, batch_id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create batch_acl table")?;
+        Ok(())
+    }
+
+    /// Create indexes for better query performance
+    async fn create_indexes(pool: &SqlitePool) -> Result<()> {
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_batches_client ON batches(client_id)")
+            .execute(pool)
+            .await
+            .context("Failed to create batches index")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,471 @@
+use crate::{CompactionReport, ReplacementFile, Storage, StorageResult};
+use async_trait::async_trait;
+use merkle_tree::MerkleTree;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Storage decorator that times every call to the wrapped storage and warns about
+/// calls taking at least `threshold`, for triaging slow disks or database contention
+pub struct TimedStorage {
+    inner: Arc<dyn Storage>,
+    threshold: Duration,
+    slow_ops: AtomicU64,
+}
+
+impl TimedStorage {
+    pub fn new(inner: Arc<dyn Storage>, threshold: Duration) -> Self {
+        Self {
+            inner,
+            threshold,
+            slow_ops: AtomicU64::new(0),
+        }
+    }
+
+    /// Number of calls that took at least the threshold
+    pub fn slow_ops(&self) -> u64 {
+        self.slow_ops.load(Ordering::Relaxed)
+    }
+
+    /// Run one storage call, warning if it was slow
+    async fn time<T>(
+        &self,
+        operation: &'static str,
+        client_id: Option<&str>,
+        batch_id: Option<&str>,
+        call: impl Future<Output = T>,
+    ) -> T {
+        let started = Instant::now();
+        let result = call.await;
+        let elapsed = started.elapsed();
+
+        if elapsed >= self.threshold {
+            self.slow_ops.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                operation,
+                client_id = ?client_id,
+                batch_id = ?batch_id,
+                elapsed_ms = elapsed.as_millis() as u64,
+                "Slow storage operation"
+            );
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl Storage for TimedStorage {
+    async fn read_file(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Vec<u8>> {
+        self.time(
+            "read_file",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.read_file(client_id, batch_id, filename),
+        )
+        .await
+    }
+
+    async fn load_batch_filenames(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Vec<String>> {
+        self.time(
+            "load_batch_filenames",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.load_batch_filenames(client_id, batch_id),
+        )
+        .await
+    }
+
+    async fn batch_exists(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        self.time(
+            "batch_exists",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.batch_exists(client_id, batch_id),
+        )
+        .await
+    }
+
+    async fn client_exists(&self, client_id: &str) -> StorageResult<bool> {
+        self.time(
+            "client_exists",
+            Some(client_id),
+            None,
+            self.inner.client_exists(client_id),
+        )
+        .await
+    }
+
+    async fn count_clients(&self) -> StorageResult<u64> {
+        self.time("count_clients", None, None, self.inner.count_clients())
+            .await
+    }
+
+    async fn file_exists(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<bool> {
+        self.time(
+            "file_exists",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.file_exists(client_id, batch_id, filename),
+        )
+        .await
+    }
+
+    async fn file_stored_at(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Option<u64>> {
+        self.time(
+            "file_stored_at",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.file_stored_at(client_id, batch_id, filename),
+        )
+        .await
+    }
+
+    async fn store_public_key(&self, client_id: &str, public_key: &[u8]) -> StorageResult<()> {
+        self.time(
+            "store_public_key",
+            Some(client_id),
+            None,
+            self.inner.store_public_key(client_id, public_key),
+        )
+        .await
+    }
+
+    async fn load_public_key(&self, client_id: &str) -> StorageResult<Option<Vec<u8>>> {
+        self.time(
+            "load_public_key",
+            Some(client_id),
+            None,
+            self.inner.load_public_key(client_id),
+        )
+        .await
+    }
+
+    async fn set_batch_public(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        public: bool,
+    ) -> StorageResult<()> {
+        self.time(
+            "set_batch_public",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.set_batch_public(client_id, batch_id, public),
+        )
+        .await
+    }
+
+    async fn is_batch_public(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        self.time(
+            "is_batch_public",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.is_batch_public(client_id, batch_id),
+        )
+        .await
+    }
+
+    async fn grant_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<()> {
+        self.time(
+            "grant_batch_access",
+            Some(client_id),
+            Some(batch_id),
+            self.inner
+                .grant_batch_access(client_id, batch_id, grantee_id),
+        )
+        .await
+    }
+
+    async fn revoke_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<()> {
+        self.time(
+            "revoke_batch_access",
+            Some(client_id),
+            Some(batch_id),
+            self.inner
+                .revoke_batch_access(client_id, batch_id, grantee_id),
+        )
+        .await
+    }
+
+    async fn has_batch_access(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        grantee_id: &str,
+    ) -> StorageResult<bool> {
+        self.time(
+            "has_batch_access",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.has_batch_access(client_id, batch_id, grantee_id),
+        )
+        .await
+    }
+
+    async fn set_batch_annotations(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        annotations: &BTreeMap<String, String>,
+    ) -> StorageResult<()> {
+        self.time(
+            "set_batch_annotations",
+            Some(client_id),
+            Some(batch_id),
+            self.inner
+                .set_batch_annotations(client_id, batch_id, annotations),
+        )
+        .await
+    }
+
+    async fn load_batch_annotations(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<BTreeMap<String, String>> {
+        self.time(
+            "load_batch_annotations",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.load_batch_annotations(client_id, batch_id),
+        )
+        .await
+    }
+
+    async fn set_committed_root(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        root: &[u8; 32],
+    ) -> StorageResult<()> {
+        self.time(
+            "set_committed_root",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.set_committed_root(client_id, batch_id, root),
+        )
+        .await
+    }
+
+    async fn load_committed_root(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Option<[u8; 32]>> {
+        self.time(
+            "load_committed_root",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.load_committed_root(client_id, batch_id),
+        )
+        .await
+    }
+
+    async fn create_batch(&self, client_id: &str, batch_id: &str) -> StorageResult<bool> {
+        self.time(
+            "create_batch",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.create_batch(client_id, batch_id),
+        )
+        .await
+    }
+
+    async fn rename_batch(
+        &self,
+        client_id: &str,
+        old_batch_id: &str,
+        new_batch_id: &str,
+    ) -> StorageResult<()> {
+        self.time(
+            "rename_batch",
+            Some(client_id),
+            Some(old_batch_id),
+            self.inner
+                .rename_batch(client_id, old_batch_id, new_batch_id),
+        )
+        .await
+    }
+
+    async fn delete_batch(&self, client_id: &str, batch_id: &str) -> StorageResult<()> {
+        self.time(
+            "delete_batch",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.delete_batch(client_id, batch_id),
+        )
+        .await
+    }
+
+    async fn delete_file(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+    ) -> StorageResult<Option<MerkleTree>> {
+        self.time(
+            "delete_file",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.delete_file(client_id, batch_id, filename),
+        )
+        .await
+    }
+
+    async fn replace_batch(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        files: &[ReplacementFile],
+        committed_root: &[u8; 32],
+    ) -> StorageResult<()> {
+        self.time(
+            "replace_batch",
+            Some(client_id),
+            Some(batch_id),
+            self.inner
+                .replace_batch(client_id, batch_id, files, committed_root),
+        )
+        .await
+    }
+
+    async fn compact_batch(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<CompactionReport> {
+        self.time(
+            "compact_batch",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.compact_batch(client_id, batch_id),
+        )
+        .await
+    }
+
+    async fn list_batches(&self) -> StorageResult<Vec<(String, String)>> {
+        self.time("list_batches", None, None, self.inner.list_batches())
+            .await
+    }
+
+    async fn list_client_batches(&self, client_id: &str) -> StorageResult<Vec<String>> {
+        self.time(
+            "list_client_batches",
+            Some(client_id),
+            None,
+            self.inner.list_client_batches(client_id),
+        )
+        .await
+    }
+
+    async fn load_merkle_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+    ) -> StorageResult<Option<MerkleTree>> {
+        self.time(
+            "load_merkle_tree",
+            Some(client_id),
+            Some(batch_id),
+            self.inner.load_merkle_tree(client_id, batch_id),
+        )
+        .await
+    }
+
+    async fn store_file_and_update_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        content: &[u8],
+    ) -> StorageResult<()> {
+        self.time(
+            "store_file_and_update_tree",
+            Some(client_id),
+            Some(batch_id),
+            self.inner
+                .store_file_and_update_tree(client_id, batch_id, filename, content),
+        )
+        .await
+    }
+
+    async fn store_file_from_path_and_update_tree(
+        &self,
+        client_id: &str,
+        batch_id: &str,
+        filename: &str,
+        source: &Path,
+    ) -> StorageResult<()> {
+        self.time(
+            "store_file_from_path_and_update_tree",
+            Some(client_id),
+            Some(batch_id),
+            self.inner
+                .store_file_from_path_and_update_tree(client_id, batch_id, filename, source),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryStorage;
+
+    #[tokio::test]
+    async fn test_slow_operation_is_reported() {
+        let inner = MemoryStorage::new().with_read_delay(Duration::from_millis(50));
+        inner
+            .store_file_and_update_tree("client", "batch", "a.txt", b"content")
+            .await
+            .unwrap();
+        let storage = TimedStorage::new(Arc::new(inner), Duration::from_millis(20));
+
+        // Fast calls pass through unreported
+        assert!(storage
+            .file_exists("client", "batch", "a.txt")
+            .await
+            .unwrap());
+        assert_eq!(storage.slow_ops(), 0);
+
+        // The result of a slow call is returned unchanged, and the call is reported
+        assert_eq!(
+            storage.read_file("client", "batch", "a.txt").await.unwrap(),
+            b"content"
+        );
+        assert_eq!(storage.slow_ops(), 1);
+    }
+}
@@ -1,11 +1,16 @@
 use anyhow::{Context, Result};
+use crypto::hash_leaf;
 use sqlx::PgPool;
+use storage::database::DatabaseStorage;
+use storage::Storage;
 
+/// Validate the stored batch; `expected_files` are the bytes each file should be stored as
 pub async fn validate_upload(
     database_url: &str,
     client_id: &str,
     batch_id: &str,
     expected_file_count: usize,
+    expected_files: &[(String, Vec<u8>)],
 ) -> Result<()> {
     let pool = PgPool::connect(database_url)
         .await
@@ -41,6 +46,32 @@ pub async fn validate_upload(
 
     println!("  ✓ Batch {} exists for client {}", batch_id, client_id);
 
+    // The storage backend's own existence checks must agree
+    let storage = DatabaseStorage::new(database_url)
+        .await
+        .context("Failed to open database storage")?;
+    let checks = [
+        (storage.client_exists(client_id).await?, true),
+        (storage.client_exists("unknown-client").await?, false),
+        (storage.batch_exists(client_id, batch_id).await?, true),
+        (
+            storage.batch_exists(client_id, "unknown-batch").await?,
+            false,
+        ),
+        (
+            storage.batch_exists("unknown-client", batch_id).await?,
+            false,
+        ),
+    ];
+    if checks.iter().any(|(found, expected)| found != expected) {
+        anyhow::bail!(
+            "Storage existence checks disagree with the database: {:?}",
+            checks
+        );
+    }
+
+    println!("  ✓ Storage existence checks match the database");
+
     // Validate files count
     let file_count: i64 =
         sqlx::query_scalar("SELECT COUNT(*) FROM files WHERE client_id = $1 AND batch_id = $2")
@@ -63,32 +94,26 @@ pub async fn validate_upload(
         file_count, expected_file_count
     );
 
-    // Validate batch_filenames count
-    let metadata_count: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM batch_filenames WHERE client_id = $1 AND batch_id = $2",
+    // Validate the batch has a stored Merkle tree
+    let has_tree = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM merkle_trees WHERE client_id = $1 AND batch_id = $2)",
     )
     .bind(client_id)
     .bind(batch_id)
     .fetch_one(&pool)
     .await
-    .context("Failed to count batch_filenames")?;
+    .context("Failed to check merkle_trees")?;
 
-    if metadata_count != expected_file_count as i64 {
-        anyhow::bail!(
-            "Expected {} filenames in metadata, found {}",
-            expected_file_count,
-            metadata_count
-        );
+    if !has_tree {
+        anyhow::bail!("No Merkle tree stored for batch {}", batch_id);
     }
 
-    println!(
-        "  ✓ Found {} filenames in metadata (expected {})",
-        metadata_count, expected_file_count
-    );
+    println!("  ✓ Merkle tree stored for batch");
 
     // Validate that all files have content
     let files_with_content: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM files WHERE client_id = $1 AND batch_id = $2 AND LENGTH(content) > 0",
+        "SELECT COUNT(*) FROM files WHERE client_id = $1 AND batch_id = $2 \
+         AND (LENGTH(content) > 0 OR content_ref IS NOT NULL)",
     )
     .bind(client_id)
     .bind(batch_id)
@@ -126,6 +151,59 @@ pub async fn validate_upload(
 
     println!("  ✓ All expected filenames exist in database");
 
+    // Validate stored content is exactly what the client sent, not just present
+    for (filename, expected) in expected_files {
+        let (content, content_ref) = sqlx::query_as::<_, (Option<Vec<u8>>, Option<String>)>(
+            "SELECT content, content_ref FROM files WHERE client_id = $1 AND batch_id = $2 AND filename = $3",
+        )
+        .bind(client_id)
+        .bind(batch_id)
+        .bind(filename)
+        .fetch_one(&pool)
+        .await
+        .with_context(|| format!("Failed to read content of file {}", filename))?;
+
+        let expected_hash = hex::encode(hash_leaf(expected));
+        match (content, content_ref) {
+            (Some(content), _) if !content.is_empty() => {
+                if content.len() != expected.len() {
+                    anyhow::bail!(
+                        "File {} has {} bytes in database, expected {}",
+                        filename,
+                        content.len(),
+                        expected.len()
+                    );
+                }
+                let actual_hash = hex::encode(hash_leaf(&content));
+                if actual_hash != expected_hash {
+                    anyhow::bail!(
+                        "File {} content in database hashes to {}, expected {}",
+                        filename,
+                        actual_hash,
+                        expected_hash
+                    );
+                }
+            }
+            // External content is addressed by its leaf hash; its bytes live outside the database
+            (_, Some(content_ref)) => {
+                if content_ref != expected_hash {
+                    anyhow::bail!(
+                        "File {} references content {}, expected {}",
+                        filename,
+                        content_ref,
+                        expected_hash
+                    );
+                }
+            }
+            _ => anyhow::bail!("File {} has no content in database", filename),
+        }
+    }
+
+    println!(
+        "  ✓ All {} files hash to their expected leaf hashes",
+        expected_files.len()
+    );
+
     Ok(())
 }
 
@@ -143,7 +221,7 @@ pub async fn cleanup_test_data(database_url: &str, client_id: &str, batch_id: &s
 
     println!("🧹 Cleaning up database test data...");
 
-    // Delete batch (cascade will delete files and batch_filenames)
+    // Delete batch (cascade will delete files and merkle_trees)
     sqlx::query("DELETE FROM batches WHERE client_id = $1 AND batch_id = $2")
         .bind(client_id)
         .bind(batch_id)
@@ -1,12 +1,15 @@
 use anyhow::{Context, Result};
+use crypto::hash_leaf;
 use std::fs;
 use std::path::Path;
 
+/// Validate the stored batch; `expected_files` are the bytes each file should be stored as
 pub fn validate_upload(
     server_data_dir: &Path,
     client_id: &str,
     batch_id: &str,
     expected_file_count: usize,
+    expected_files: &[(String, Vec<u8>)],
 ) -> Result<()> {
     let batch_dir = server_data_dir.join(client_id).join(batch_id);
 
@@ -80,6 +83,35 @@ pub fn validate_upload(
     );
     println!("  ✓ All filenames are in metadata");
 
+    // Validate stored content is exactly what the client sent, not just present
+    for (filename, expected) in expected_files {
+        let file_path = batch_dir.join(filename);
+        let content = fs::read(&file_path)
+            .with_context(|| format!("Failed to read file: {:?}", file_path))?;
+        if content.len() != expected.len() {
+            anyhow::bail!(
+                "File {} has {} bytes on disk, expected {}",
+                filename,
+                content.len(),
+                expected.len()
+            );
+        }
+        let (actual_hash, expected_hash) = (hash_leaf(&content), hash_leaf(expected));
+        if actual_hash != expected_hash {
+            anyhow::bail!(
+                "File {} content on disk hashes to {}, expected {}",
+                filename,
+                hex::encode(actual_hash),
+                hex::encode(expected_hash)
+            );
+        }
+    }
+
+    println!(
+        "  ✓ All {} files hash to their expected leaf hashes",
+        expected_files.len()
+    );
+
     // Validate public key exists
     let public_key_file = server_data_dir.join(client_id).join("public_key.hex");
     if public_key_file.exists() {
@@ -2,7 +2,10 @@ use anyhow::{Context, Result};
 use std::fs;
 use std::path::Path;
 use std::process::Command;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use tokio::time::sleep;
 
 pub fn create_test_files(dir: &Path, count: usize) -> Result<()> {
@@ -16,28 +19,122 @@ pub fn create_test_files(dir: &Path, count: usize) -> Result<()> {
     Ok(())
 }
 
+/// Bytes the server should hold for each test file of a private batch: the ciphertext the
+/// client produced, reproduced with its key since encryption is deterministic
+pub fn expected_stored_files(
+    client_data_dir: &Path,
+    test_files_dir: &Path,
+    batch_id: &str,
+    count: usize,
+) -> Result<Vec<(String, Vec<u8>)>> {
+    let key_file = client_data_dir.join("keypair.txt");
+    let signing_key = crypto::decode_keypair(
+        &fs::read_to_string(&key_file)
+            .with_context(|| format!("Failed to read keypair from {:?}", key_file))?,
+    )?;
+    (0..count)
+        .map(|i| {
+            let filename = format!("file{}.txt", i);
+            let plaintext = fs::read(test_files_dir.join(&filename))
+                .with_context(|| format!("Failed to read test file {}", filename))?;
+            let ciphertext = crypto::encrypt_file(&signing_key, &filename, batch_id, &plaintext)?;
+            Ok((filename, ciphertext))
+        })
+        .collect()
+}
+
+/// Default number of health checks before giving up (override with E2E_HEALTH_RETRIES)
+const DEFAULT_HEALTH_RETRIES: u32 = 30;
+
+/// Default delay between health checks (override with E2E_HEALTH_INTERVAL_MS)
+const DEFAULT_HEALTH_INTERVAL_MS: u64 = 1000;
+
+/// Per-request timeout so a server that accepts connections but never answers
+/// cannot stall a single health check
+const HEALTH_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> Result<T> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid value for {}: {}", name, value)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Default number of per-file client operations run at once (override with E2E_CONCURRENCY)
+const DEFAULT_CONCURRENCY: usize = 1;
+
+/// How many per-file client operations may run at once
+pub fn concurrency_limit() -> Result<usize> {
+    Ok(env_or("E2E_CONCURRENCY", DEFAULT_CONCURRENCY)?.max(1))
+}
+
+/// Run the blocking `op` for every item, at most `limit` at a time
+/// Every item is attempted; the first failure is returned once all have finished
+pub async fn for_each_concurrently<F>(items: Vec<String>, limit: usize, op: F) -> Result<()>
+where
+    F: Fn(&str) -> Result<()> + Send + Sync + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(limit));
+    let op = Arc::new(op);
+    let mut tasks = JoinSet::new();
+    for item in items {
+        let permit = semaphore.clone().acquire_owned().await?;
+        let op = op.clone();
+        tasks.spawn_blocking(move || {
+            let _permit = permit;
+            op(&item)
+        });
+    }
+
+    let mut first_error = None;
+    while let Some(result) = tasks.join_next().await {
+        if let Err(e) = result.context("Client task panicked").and_then(|r| r) {
+            first_error.get_or_insert(e);
+        }
+    }
+    first_error.map_or(Ok(()), Err)
+}
+
 pub async fn wait_for_server(url: &str) -> Result<()> {
-    let client = reqwest::Client::new();
+    let retries = env_or("E2E_HEALTH_RETRIES", DEFAULT_HEALTH_RETRIES)?.max(1);
+    let interval = Duration::from_millis(env_or(
+        "E2E_HEALTH_INTERVAL_MS",
+        DEFAULT_HEALTH_INTERVAL_MS,
+    )?);
+
+    let client = reqwest::Client::builder()
+        .connect_timeout(HEALTH_REQUEST_TIMEOUT)
+        .timeout(HEALTH_REQUEST_TIMEOUT)
+        .build()
+        .context("Failed to build HTTP client")?;
     let health_url = format!("{}/health", url);
 
     println!("Waiting for server to be ready...");
-    for i in 0..30 {
+    let mut last_error = String::from("no response");
+    for attempt in 1..=retries {
         match client.get(&health_url).send().await {
-            Ok(response) => {
-                if response.status().is_success() {
-                    println!("Server is ready!");
-                    return Ok(());
-                }
-            }
-            Err(_) => {
-                if i < 29 {
-                    sleep(Duration::from_secs(1)).await;
-                }
+            Ok(response) if response.status().is_success() => {
+                println!("Server is ready!");
+                return Ok(());
             }
+            Ok(response) => last_error = format!("status {}", response.status()),
+            Err(e) if e.is_timeout() => last_error = "health check timed out".to_string(),
+            Err(e) => last_error = e.to_string(),
+        }
+
+        if attempt < retries {
+            sleep(interval).await;
         }
     }
 
-    anyhow::bail!("Server did not become ready within 30 seconds");
+    anyhow::bail!(
+        "Server did not become ready after {} health checks ({:?} apart): {}",
+        retries,
+        interval,
+        last_error
+    );
 }
 
 pub fn generate_keypair(client_binary: &Path, client_data_dir: &Path) -> Result<String> {
@@ -89,6 +186,77 @@ pub fn upload_files(
     Ok(())
 }
 
+/// Re-upload with `--skip-unchanged` and return the filenames the client actually sent
+pub fn upload_changed_files(
+    client_binary: &Path,
+    client_data_dir: &Path,
+    test_files_dir: &Path,
+    server_url: &str,
+    batch_id: &str,
+) -> Result<Vec<String>> {
+    let output = Command::new(client_binary)
+        .arg("upload")
+        .arg("--dir")
+        .arg(test_files_dir)
+        .arg("--server")
+        .arg(server_url)
+        .arg("--batch-id")
+        .arg(batch_id)
+        .arg("--skip-unchanged")
+        .env("CLIENT_DATA_DIR", client_data_dir)
+        .output()
+        .with_context(|| "Failed to run upload command")?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("Upload failed:\nSTDOUT: {}\nSTDERR: {}", stdout, stderr);
+    }
+
+    let sent = stdout
+        .lines()
+        .filter_map(|line| line.strip_prefix("Uploaded file: "))
+        .map(|filename| filename.trim().to_string())
+        .collect();
+    println!("Upload completed successfully");
+    Ok(sent)
+}
+
+/// Upload without `--batch-id` and return the batch ID the client generated
+pub fn upload_files_with_generated_batch_id(
+    client_binary: &Path,
+    client_data_dir: &Path,
+    test_files_dir: &Path,
+    server_url: &str,
+) -> Result<String> {
+    let output = Command::new(client_binary)
+        .arg("upload")
+        .arg("--dir")
+        .arg(test_files_dir)
+        .arg("--server")
+        .arg(server_url)
+        .env("CLIENT_DATA_DIR", client_data_dir)
+        .output()
+        .with_context(|| "Failed to run upload command")?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("Upload failed:\nSTDOUT: {}\nSTDERR: {}", stdout, stderr);
+    }
+
+    let batch_id = stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("Generated batch ID: "))
+        .map(|id| id.trim().to_string())
+        .ok_or_else(|| anyhow::anyhow!("Upload did not report a generated batch ID"))?;
+    println!(
+        "Upload completed successfully (generated batch ID {})",
+        batch_id
+    );
+    Ok(batch_id)
+}
+
 pub fn download_file(
     client_binary: &Path,
     client_data_dir: &Path,
@@ -103,6 +271,8 @@ pub fn download_file(
         .arg(batch_id)
         .arg("--server")
         .arg(server_url)
+        // Steps download the same files again
+        .arg("--overwrite")
         .env("CLIENT_DATA_DIR", client_data_dir)
         .output()
         .with_context(|| "Failed to run download command")?;
@@ -117,6 +287,99 @@ pub fn download_file(
     Ok(())
 }
 
+/// Download and verify every file of a batch with `download-batch`
+pub fn download_batch(
+    client_binary: &Path,
+    client_data_dir: &Path,
+    server_url: &str,
+    batch_id: &str,
+) -> Result<()> {
+    let output = Command::new(client_binary)
+        .arg("download-batch")
+        .arg("--batch-id")
+        .arg(batch_id)
+        .arg("--server")
+        .arg(server_url)
+        .env("CLIENT_DATA_DIR", client_data_dir)
+        .output()
+        .with_context(|| "Failed to run download-batch command")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        anyhow::bail!(
+            "Batch download failed:\nSTDOUT: {}\nSTDERR: {}",
+            stdout,
+            stderr
+        );
+    }
+
+    println!("Batch download completed successfully");
+    Ok(())
+}
+
+/// Download a file verifying it against a root taken from `root_source` (path or URL)
+pub fn download_file_with_root_source(
+    client_binary: &Path,
+    client_data_dir: &Path,
+    server_url: &str,
+    batch_id: &str,
+    filename: &str,
+    root_source: &Path,
+) -> Result<()> {
+    let output = Command::new(client_binary)
+        .arg("download")
+        .arg(filename)
+        .arg("--batch-id")
+        .arg(batch_id)
+        .arg("--server")
+        .arg(server_url)
+        .arg("--root-source")
+        .arg(root_source)
+        .arg("--overwrite")
+        .env("CLIENT_DATA_DIR", client_data_dir)
+        .output()
+        .with_context(|| "Failed to run download command")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        anyhow::bail!("Download failed:\nSTDOUT: {}\nSTDERR: {}", stdout, stderr);
+    }
+
+    println!("Download against pinned root completed successfully");
+    Ok(())
+}
+
+pub fn rename_batch(
+    client_binary: &Path,
+    client_data_dir: &Path,
+    server_url: &str,
+    batch_id: &str,
+    new_batch_id: &str,
+) -> Result<()> {
+    let output = Command::new(client_binary)
+        .arg("rename-batch")
+        .arg("--batch-id")
+        .arg(batch_id)
+        .arg("--new-batch-id")
+        .arg(new_batch_id)
+        .arg("--server")
+        .arg(server_url)
+        .env("CLIENT_DATA_DIR", client_data_dir)
+        .output()
+        .with_context(|| "Failed to run rename-batch command")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        anyhow::bail!("Rename failed:\nSTDOUT: {}\nSTDERR: {}", stdout, stderr);
+    }
+
+    println!("Rename completed successfully");
+    Ok(())
+}
+
 pub fn validate_merkle_proof(client_data_dir: &Path, batch_id: &str, filename: &str) -> Result<()> {
     // Read root hash
     let root_hash_file = client_data_dir.join(batch_id).join("root_hash.txt");